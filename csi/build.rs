@@ -5,4 +5,10 @@ fn main() {
         .build_server(true)
         .compile(&["proto/csi.proto"], &["proto"])
         .unwrap_or_else(|e| panic!("csi protobuf compilation failed: {}", e));
+    tonic_build::configure()
+        .build_server(true)
+        .compile(&["proto/mayastornodeplugin.proto"], &["proto"])
+        .unwrap_or_else(|e| {
+            panic!("node plugin protobuf compilation failed: {}", e)
+        });
 }
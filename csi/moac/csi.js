@@ -16,6 +16,17 @@ const PROTO_PATH = path.join(__dirname, '/proto/csi.proto');
 // TODO: can we generate version with commit SHA dynamically?
 const VERSION = '0.1';
 const PVC_RE = /pvc-([0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12})/;
+// parameter of CreateVolume with the namespace of the PVC of the volume
+const PVC_NAMESPACE_PARAM = 'csi.storage.k8s.io/pvc/namespace';
+// volume context key with the capacity of the volume, which the node plugin
+// limits a filesystem with quota to
+const CAPACITY_PARAM = 'capacityBytes';
+// topology key of a node as reported by the node plugin
+const NODE_TOPOLOGY_KEY = 'mayastor.openebs.io/node';
+// topology keys with the name of a node which we understand (the hostname is
+// what the requirements had been expressed in before the node plugin started
+// to report its own key)
+const NODE_TOPOLOGY_KEYS = [NODE_TOPOLOGY_KEY, 'kubernetes.io/hostname'];
 
 // Load csi proto file with controller and identity services
 const packageDefinition = protoLoader.loadSync(PROTO_PATH, {
@@ -82,7 +93,7 @@ function createK8sVolumeObject (volume) {
     capacityBytes: volume.getSize(),
     accessibleTopology: [
       {
-        segments: { 'kubernetes.io/hostname': volume.getNodeName() }
+        segments: { [NODE_TOPOLOGY_KEY]: volume.getNodeName() }
       }
     ]
   };
@@ -325,15 +336,15 @@ class CsiServer {
         const reqs = args.accessibilityRequirements.requisite[i];
         for (const key in reqs.segments) {
           // We are not able to evaluate any other topology requirements than
-          // the hostname req. Reject all others.
-          if (key !== 'kubernetes.io/hostname') {
+          // the node name req. Reject all others.
+          if (!NODE_TOPOLOGY_KEYS.includes(key)) {
             return cb(
               new GrpcError(
                 grpc.status.INVALID_ARGUMENT,
-                'Volume topology other than hostname not supported'
+                'Volume topology other than node name not supported'
               )
             );
-          } else {
+          } else if (!mustNodes.includes(reqs.segments[key])) {
             mustNodes.push(reqs.segments[key]);
           }
         }
@@ -345,8 +356,11 @@ class CsiServer {
       ) {
         const reqs = args.accessibilityRequirements.preferred[i];
         for (const key in reqs.segments) {
-          // ignore others than hostname (it's only preferred)
-          if (key === 'kubernetes.io/hostname') {
+          // ignore others than node name (it's only preferred)
+          if (
+            NODE_TOPOLOGY_KEYS.includes(key) &&
+            !shouldNodes.includes(reqs.segments[key])
+          ) {
             shouldNodes.push(reqs.segments[key]);
           }
         }
@@ -365,16 +379,23 @@ class CsiServer {
       count = 1;
     }
 
+    const spec = {
+      replicaCount: count,
+      preferredNodes: shouldNodes,
+      requiredNodes: mustNodes,
+      requiredBytes: args.capacityRange.requiredBytes,
+      limitBytes: args.capacityRange.limitBytes
+    };
+    // passed by csi-provisioner if run with --extra-create-metadata
+    const tenant = args.parameters[PVC_NAMESPACE_PARAM];
+    if (tenant) {
+      spec.tenant = tenant;
+    }
+
     // create the volume
     var volume;
     try {
-      volume = await this.volumes.createVolume(uuid, {
-        replicaCount: count,
-        preferredNodes: shouldNodes,
-        requiredNodes: mustNodes,
-        requiredBytes: args.capacityRange.requiredBytes,
-        limitBytes: args.capacityRange.limitBytes
-      });
+      volume = await this.volumes.createVolume(uuid, spec);
     } catch (err) {
       return cb(err);
     }
@@ -383,7 +404,7 @@ class CsiServer {
     const accessibleTopology = [];
     if (protocol.toLowerCase() === 'nbd') {
       accessibleTopology.push({
-        segments: { 'kubernetes.io/hostname': volume.getNodeName() }
+        segments: { [NODE_TOPOLOGY_KEY]: volume.getNodeName() }
       });
     }
     cb(null, {
@@ -395,7 +416,9 @@ class CsiServer {
         // to the CSI driver createVolume method.
         // Propagate them to other CSI driver methods involved in
         // standing up a volume, using the volume context.
-        volumeContext: args.parameters
+        volumeContext: Object.assign({}, args.parameters, {
+          [CAPACITY_PARAM]: volume.getSize().toString()
+        })
       }
     });
   }
@@ -592,7 +615,7 @@ class CsiServer {
     cb(null, resp);
   }
 
-  // We understand just one topology segment type and that is hostname.
+  // We understand just one topology segment type and that is node name.
   // So if it is specified we return capacity of storage pools on the node
   // or capacity of all pools in the cluster.
   //
@@ -611,7 +634,7 @@ class CsiServer {
     }
     if (args.accessibleTopology) {
       for (const key in args.accessibleTopology.segments) {
-        if (key === 'kubernetes.io/hostname') {
+        if (NODE_TOPOLOGY_KEYS.includes(key)) {
           nodeName = args.accessibleTopology.segments[key];
           break;
         }
@@ -175,7 +175,7 @@ async function main () {
     await volumeOper.start();
   }
 
-  apiServer = new ApiServer(registry);
+  apiServer = new ApiServer(registry, volumes);
   await apiServer.start(opts.port);
 
   csiServer.makeReady(registry, volumes);
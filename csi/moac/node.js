@@ -373,6 +373,15 @@ class Node extends EventEmitter {
     const reply = await this.call('statReplicas', {});
     return reply.replicas;
   }
+
+  // Get IO statistics for all nexus on the node.
+  //
+  // @returns {object[]} Array of stats where each object is for a different nexus.
+  async getNexusStats () {
+    log.debug(`Retrieving nexus stats from node "${this}"`);
+    const reply = await this.call('statNexus', {});
+    return reply.nexusList || [];
+  }
 }
 
 module.exports = Node;
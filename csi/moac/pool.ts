@@ -24,6 +24,7 @@ export class Pool {
   disks: [string];
   // TODO: define an enum
   state: string;
+  stateReason: string;
   capacity: number;
   used: number;
   replicas: Replica[];
@@ -34,6 +35,7 @@ export class Pool {
   // @param {string}   props.name     Pool name.
   // @param {string[]} props.disks    List of disks comprising the pool.
   // @param {string}   props.state    State of the pool.
+  // @param {string}   [props.stateReason] Why the pool is in its state.
   // @param {number}   props.capacity Capacity of the pool in bytes.
   // @param {number}   props.used     How many bytes are used in the pool.
   constructor(props: any) {
@@ -41,6 +43,7 @@ export class Pool {
     this.name = props.name;
     this.disks = props.disks.sort();
     this.state = props.state;
+    this.stateReason = props.stateReason || '';
     this.capacity = props.capacity;
     this.used = props.used;
     this.replicas = [];
@@ -56,6 +59,7 @@ export class Pool {
   // @param {string}   props.name     Pool name.
   // @param {string[]} props.disks    List of disks comprising the pool.
   // @param {string}   props.state    State of the pool.
+  // @param {string}   [props.stateReason] Why the pool is in its state.
   // @param {number}   props.capacity Capacity of the pool in bytes.
   // @param {number}   props.used     How many bytes are used in the pool.
   // @param {object[]} replicas       Replicas on the pool.
@@ -81,6 +85,10 @@ export class Pool {
       this.state = props.state;
       changed = true;
     }
+    if (this.stateReason !== (props.stateReason || '')) {
+      this.stateReason = props.stateReason || '';
+      changed = true;
+    }
     if (this.capacity !== props.capacity) {
       this.capacity = props.capacity;
       changed = true;
@@ -295,6 +295,9 @@ class PoolOperator {
     var reason = '';
     if (state === 'offline') {
       reason = `mayastor does not run on the node "${pool.node}"`;
+    } else if (pool.stateReason) {
+      // i.e. why the pool is read-only
+      reason = pool.stateReason;
     }
 
     await this._updateResourceProps(
@@ -5,6 +5,11 @@
 // the way of storing and presenting the stats from the mayastor
 // implementation.
 //
+// The stats are available in two formats: as JSON at /stats and in
+// OpenMetrics text format at /metrics. The samples of the latter are labeled
+// by uuid of the volume (not by the name of the bdev in mayastor), so that
+// they can be joined with the persistent volumes by the UUID directly.
+//
 // TODO: However in future it will be used for obtaining detailed (more
 // detailed than k8s api server or kubectl tools allow) information about
 // internal state of moac and its objects.
@@ -14,10 +19,59 @@
 const express = require('express');
 const log = require('./logger').Logger('api');
 
+const OPENMETRICS_CONTENT_TYPE =
+  'application/openmetrics-text; version=1.0.0; charset=utf-8';
+
+// Counters of replicas and nexus exported as metrics. The name is the name of
+// the metric family without the "mayastor_replica_" or "mayastor_nexus_"
+// prefix, the field is the name of the counter in the stats of mayastor.
+const COUNTERS = [
+  { name: 'read_ops', field: 'numReadOps', help: 'Number of read operations' },
+  {
+    name: 'write_ops',
+    field: 'numWriteOps',
+    help: 'Number of write operations'
+  },
+  {
+    name: 'read_bytes',
+    field: 'bytesRead',
+    unit: 'bytes',
+    help: 'Number of bytes read'
+  },
+  {
+    name: 'written_bytes',
+    field: 'bytesWritten',
+    unit: 'bytes',
+    help: 'Number of bytes written'
+  }
+];
+
+// Escape value of a label as required by the OpenMetrics text format.
+function escapeLabel (value) {
+  return String(value)
+    .replace(/\\/g, '\\\\')
+    .replace(/"/g, '\\"')
+    .replace(/\n/g, '\\n');
+}
+
+// Format set of labels including the curly braces.
+function formatLabels (labels) {
+  const pairs = Object.keys(labels).map(
+    (name) => `${name}="${escapeLabel(labels[name])}"`
+  );
+  return `{${pairs.join(',')}}`;
+}
+
 class ApiServer {
-  constructor (registry) {
+  // Create the API server.
+  //
+  // @param {object} registry   Registry of mayastor nodes.
+  // @param {object} [volumes]  Volume manager used to look up the tenant of
+  //                            the volumes.
+  constructor (registry, volumes) {
     var self = this;
     this.registry = registry;
+    this.volumes = volumes;
     this.app = express();
     this.app.get('/stats', (req, res) => {
       self.getStats().then(
@@ -25,6 +79,12 @@ class ApiServer {
         (err) => res.status(500).send(err.toString())
       );
     });
+    this.app.get('/metrics', (req, res) => {
+      self.getMetrics().then(
+        (metrics) => res.type(OPENMETRICS_CONTENT_TYPE).send(metrics),
+        (err) => res.status(500).send(err.toString())
+      );
+    });
   }
 
   async start (port) {
@@ -40,16 +100,33 @@ class ApiServer {
     if (this.server) this.server.close();
   }
 
-  // TODO: should return stats for nexus rather than for replica
-  async getStats () {
+  // Get the tenant of the volume, which is the namespace of its PVC if known.
+  //
+  // @param {string} uuid   UUID of the volume.
+  // @returns {string} Tenant of the volume or empty string if not known.
+  //
+  _getTenant (uuid) {
+    const volume = this.volumes && this.volumes.get(uuid);
+    return (volume && volume.tenant) || '';
+  }
+
+  // Collect stats of the replicas and nexus from all nodes. Every record is
+  // labeled by uuid of the volume, name of the node, tenant and in case of
+  // replica also by the name of the pool.
+  //
+  // @param {boolean} withNexus   Collect also stats of the nexus.
+  // @returns {object} Lists of replica and nexus stats records.
+  //
+  async _collect (withNexus) {
     var self = this;
-    var vols = [];
+    var replicas = [];
+    var nexus = [];
     var nodes = self.registry.getNode();
 
     // TODO: stats can be retrieved in parallel
     for (let i = 0; i < nodes.length; i++) {
       const node = nodes[i];
-      const timestamp = new Date().toISOString();
+      const timestamp = new Date();
 
       // Lint does not like using for-loop variable in a function defined
       // in the loop. But we know it's ok in this case.
@@ -61,27 +138,130 @@ class ApiServer {
         log.error(`Failed to retrieve stats from "${node}": ${err}`);
         continue;
       }
-
-      vols = vols.concat(
+      replicas = replicas.concat(
         replicaStats.map((r) => {
           return {
             timestamp,
-            // tags
-            uuid: r.uuid,
-            node: node.name,
-            pool: r.pool,
-            // counters
-            num_read_ops: r.stats.numReadOps,
-            num_write_ops: r.stats.numWriteOps,
-            bytes_read: r.stats.bytesRead,
-            bytes_written: r.stats.bytesWritten
+            labels: {
+              volume: r.uuid,
+              node: node.name,
+              pool: r.pool,
+              tenant: self._getTenant(r.uuid)
+            },
+            stats: r.stats,
+            // counters which survive restarts of mayastor if available
+            lifetimeStats: r.lifetimeStats || r.stats
+          };
+        })
+      );
+
+      if (!withNexus) continue;
+      let nexusStats;
+      try {
+        nexusStats = await node.getNexusStats();
+      } catch (err) {
+        log.error(`Failed to retrieve nexus stats from "${node}": ${err}`);
+        continue;
+      }
+      nexus = nexus.concat(
+        nexusStats.map((n) => {
+          return {
+            timestamp,
+            labels: {
+              volume: n.uuid,
+              node: node.name,
+              tenant: self._getTenant(n.uuid)
+            },
+            stats: n.stats,
+            queueDepth: n.queueDepth,
+            ioErrors: n.ioErrors
           };
         })
       );
       // jshint ignore:end
     }
 
-    return vols;
+    return { replicas, nexus };
+  }
+
+  // TODO: should return stats for nexus rather than for replica
+  async getStats () {
+    const { replicas } = await this._collect(false);
+
+    return replicas.map((r) => {
+      return {
+        timestamp: r.timestamp.toISOString(),
+        // tags
+        uuid: r.labels.volume,
+        node: r.labels.node,
+        pool: r.labels.pool,
+        tenant: r.labels.tenant,
+        // counters
+        num_read_ops: r.stats.numReadOps,
+        num_write_ops: r.stats.numWriteOps,
+        bytes_read: r.stats.bytesRead,
+        bytes_written: r.stats.bytesWritten
+      };
+    });
+  }
+
+  // Return stats of replicas and nexus in OpenMetrics text format. Counters
+  // carry an exemplar with uuid of the volume and time of the sample.
+  async getMetrics () {
+    const { replicas, nexus } = await this._collect(true);
+    const lines = [];
+
+    const counter = (family, help, unit, records, value) => {
+      lines.push(`# TYPE ${family} counter`);
+      if (unit) lines.push(`# UNIT ${family} ${unit}`);
+      lines.push(`# HELP ${family} ${help}`);
+      records.forEach((r) => {
+        const val = value(r) || 0;
+        const exemplar = formatLabels({ volume: r.labels.volume });
+        const ts = (r.timestamp.getTime() / 1000).toFixed(3);
+        lines.push(
+          `${family}_total${formatLabels(r.labels)} ${val} # ${exemplar} ${val} ${ts}`
+        );
+      });
+    };
+
+    COUNTERS.forEach((c) => {
+      counter(
+        `mayastor_replica_${c.name}`,
+        `${c.help} of the replica`,
+        c.unit,
+        replicas,
+        (r) => r.lifetimeStats[c.field]
+      );
+    });
+    COUNTERS.forEach((c) => {
+      counter(
+        `mayastor_nexus_${c.name}`,
+        `${c.help} of the nexus`,
+        c.unit,
+        nexus,
+        (n) => n.stats && n.stats[c.field]
+      );
+    });
+    counter(
+      'mayastor_nexus_io_errors',
+      'Number of IOs failed by the nexus',
+      null,
+      nexus,
+      (n) => n.ioErrors
+    );
+
+    lines.push('# TYPE mayastor_nexus_queue_depth gauge');
+    lines.push('# HELP mayastor_nexus_queue_depth Number of IOs in flight');
+    nexus.forEach((n) => {
+      lines.push(
+        `mayastor_nexus_queue_depth${formatLabels(n.labels)} ${n.queueDepth ||
+          0}`
+      );
+    });
+
+    lines.push('# EOF');
+    return lines.join('\n') + '\n';
   }
 }
 
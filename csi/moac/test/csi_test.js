@@ -238,6 +238,7 @@ module.exports = function () {
         for (const key in parameters) {
           expected[key] = parameters[key].toString();
         }
+        expected.capacityBytes = '20';
         expect(result.volume.volumeId).to.equal(UUID);
         expect(result.volume.capacityBytes).to.equal(20);
         expect(result.volume.volumeContext).to.eql(expected);
@@ -264,12 +265,12 @@ module.exports = function () {
         expect(result.volume.accessibleTopology).to.have.lengthOf(1);
         expect(result.volume.accessibleTopology[0]).to.eql({
           segments: {
-            'kubernetes.io/hostname': 'some-node'
+            'mayastor.openebs.io/node': 'some-node'
           }
         });
       });
 
-      it('should fail if topology requirement other than hostname', async () => {
+      it('should fail if topology requirement other than node name', async () => {
         createVolumeStub.resolves(returnedVolume);
         await shouldFailWith(GrpcCode.INVALID_ARGUMENT, () =>
           client.createVolume().sendMessage({
@@ -404,6 +405,41 @@ module.exports = function () {
         });
       });
 
+      it('should create volume on node specified by node topology key', async () => {
+        createVolumeStub.resolves(returnedVolume);
+        await client.createVolume().sendMessage({
+          name: 'pvc-' + UUID,
+          capacityRange: {
+            requiredBytes: 50,
+            limitBytes: 0
+          },
+          volumeCapabilities: [
+            {
+              accessMode: { mode: 'SINGLE_NODE_WRITER' },
+              filesystem: {}
+            }
+          ],
+          accessibilityRequirements: {
+            requisite: [
+              {
+                segments: {
+                  'mayastor.openebs.io/node': 'node',
+                  'kubernetes.io/hostname': 'node'
+                }
+              }
+            ]
+          },
+          parameters: { protocol: 'nbd' }
+        });
+        sinon.assert.calledWith(createVolumeStub, UUID, {
+          replicaCount: 1,
+          preferredNodes: [],
+          requiredNodes: ['node'],
+          requiredBytes: 50,
+          limitBytes: 0
+        });
+      });
+
       it('should create volume on preferred node', async () => {
         createVolumeStub.resolves(returnedVolume);
         await client.createVolume().sendMessage({
@@ -986,6 +1022,20 @@ module.exports = function () {
         sinon.assert.calledWith(getCapacityStub, 'node1');
       });
 
+      it('should get capacity of a node given by node topology key', async () => {
+        getCapacityStub.returns(75);
+        var resp = await client.getCapacity().sendMessage({
+          accessibleTopology: {
+            segments: {
+              'mayastor.openebs.io/node': 'node1'
+            }
+          }
+        });
+        expect(resp.availableCapacity).to.equal(75);
+        sinon.assert.calledOnce(getCapacityStub);
+        sinon.assert.calledWith(getCapacityStub, 'node1');
+      });
+
       it('should get capacity of all pools on all nodes', async () => {
         getCapacityStub.returns(80);
         var resp = await client.getCapacity().sendMessage({});
@@ -828,6 +828,55 @@ module.exports = function () {
       expect(oper.watcher.objects.pool.status.reason).to.equal(offlineReason);
     });
 
+    it('should set reason of read-only pool upon pool mod event', async () => {
+      const readOnlyReason = 'block device /dev/sdb is read-only';
+      const pool = new Pool({
+        name: 'pool',
+        disks: ['aio:///dev/sdb'],
+        state: 'POOL_ONLINE',
+        capacity: 100,
+        used: 4
+      });
+      const node = new Node('node', {}, [pool]);
+      oper = await MockedPoolOperator(
+        [
+          createPoolResource(
+            'pool',
+            'node',
+            ['/dev/sdb'],
+            'online',
+            '',
+            100,
+            4
+          )
+        ],
+        [node]
+      );
+
+      pool.state = 'POOL_READ_ONLY';
+      pool.stateReason = readOnlyReason;
+      // simulate pool mod event
+      oper.registry.emit('pool', {
+        eventType: 'mod',
+        object: pool
+      });
+
+      // Give event time to propagate
+      await sleep(10);
+
+      sinon.assert.calledOnce(putStub);
+      sinon.assert.calledWithMatch(putStub, {
+        body: {
+          status: {
+            state: 'read_only',
+            reason: readOnlyReason
+          }
+        }
+      });
+      expect(oper.watcher.objects.pool.status.state).to.equal('read_only');
+      expect(oper.watcher.objects.pool.status.reason).to.equal(readOnlyReason);
+    });
+
     it('should ignore pool mod event if pool resource does not exist', async () => {
       const node = new Node('node', {}, []);
       oper = await MockedPoolOperator([], [node]);
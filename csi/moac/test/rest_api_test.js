@@ -78,8 +78,27 @@ module.exports = function () {
     call4.resolves({
       replicas: []
     });
+    call1.withArgs('statNexus').resolves({
+      nexusList: [
+        {
+          uuid: UUID1,
+          stats: {
+            numReadOps: STAT_COUNTER,
+            numWriteOps: STAT_COUNTER,
+            bytesRead: STAT_COUNTER,
+            bytesWritten: STAT_COUNTER
+          },
+          queueDepth: 3,
+          ioErrors: 2
+        }
+      ]
+    });
+    // the volume manager knows only the first volume
+    const volumes = {
+      get: (uuid) => (uuid === UUID1 ? { tenant: 'tenant1' } : null)
+    };
 
-    apiServer = new ApiServer(registry);
+    apiServer = new ApiServer(registry, volumes);
     apiServer.start(PORT);
   });
 
@@ -148,4 +167,57 @@ module.exports = function () {
       })
       .on('error', done);
   });
+
+  it('should get volume metrics in OpenMetrics format', (done) => {
+    http
+      .get('http://127.0.0.1:' + PORT + '/metrics', (resp) => {
+        expect(resp.statusCode).to.equal(200);
+        expect(resp.headers['content-type']).to.have.string(
+          'application/openmetrics-text'
+        );
+
+        let data = '';
+        resp.on('data', (chunk) => {
+          data += chunk;
+        });
+        resp.on('end', () => {
+          sinon.assert.calledWith(call1, 'statNexus', {});
+          sinon.assert.calledWith(call3, 'statNexus', {});
+          sinon.assert.neverCalledWith(call2, 'statNexus', {});
+
+          const lines = data.split('\n');
+          expect(lines[lines.length - 2]).to.equal('# EOF');
+          expect(lines).to.include('# TYPE mayastor_replica_read_ops counter');
+          expect(lines).to.include('# UNIT mayastor_replica_read_bytes bytes');
+
+          const samples = lines.filter((l) =>
+            l.startsWith('mayastor_replica_read_ops_total')
+          );
+          expect(samples).to.have.lengthOf(3);
+          expect(samples[0]).to.match(
+            new RegExp(
+              `^mayastor_replica_read_ops_total{volume="${UUID1}",node="node1",pool="pool1",tenant="tenant1"} ${STAT_COUNTER} # {volume="${UUID1}"} ${STAT_COUNTER} \\d+\\.\\d{3}$`
+            )
+          );
+          expect(samples[1]).to.have.string(
+            `{volume="${UUID2}",node="node1",pool="pool2",tenant=""}`
+          );
+          expect(samples[2]).to.have.string(
+            `{volume="${UUID3}",node="node3",pool="pool3",tenant=""}`
+          );
+
+          const nexusLabels = `{volume="${UUID1}",node="node1",tenant="tenant1"}`;
+          expect(lines).to.include(
+            `mayastor_nexus_queue_depth${nexusLabels} 3`
+          );
+          const errors = lines.filter((l) =>
+            l.startsWith('mayastor_nexus_io_errors_total')
+          );
+          expect(errors).to.have.lengthOf(1);
+          expect(errors[0]).to.have.string(`${nexusLabels} 2 # `);
+          done();
+        });
+      })
+      .on('error', done);
+  });
 };
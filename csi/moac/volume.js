@@ -34,6 +34,8 @@ class Volume {
     this.requiredNodes = _.clone(spec.requiredNodes || []).sort();
     this.requiredBytes = spec.requiredBytes;
     this.limitBytes = spec.limitBytes;
+    // namespace of the PVC if known, used only for labeling of the stats
+    this.tenant = spec.tenant || '';
     this.size = 0;
     // state variables of the volume
     this.nexus = null;
@@ -545,6 +547,7 @@ class Volume {
   // @params {string[]} spec.requiredNodes   Replicas must be on these nodes.
   // @params {number}   spec.requiredBytes   The volume must have at least this size.
   // @params {number}   spec.limitBytes      The volume should not be bigger than this.
+  // @params {string}   [spec.tenant]        Namespace of the PVC of the volume.
   // @returns {boolean} True if the volume spec has changed, false otherwise.
   //
   update (spec) {
@@ -585,6 +588,10 @@ class Volume {
       this.limitBytes = spec.limitBytes;
       changed = true;
     }
+    if (spec.tenant && this.tenant !== spec.tenant) {
+      this.tenant = spec.tenant;
+      changed = true;
+    }
     return changed;
   }
 
@@ -134,6 +134,9 @@ class VolumeOperator {
         limitBytes: msv.spec.limitBytes || 0
       }
     };
+    if (msv.spec.tenant) {
+      props.spec.tenant = msv.spec.tenant;
+    }
     // volatile part
     const st = msv.status;
     if (st) {
@@ -221,13 +224,17 @@ class VolumeOperator {
   // @returns {object} Spec properties.
   //
   _volumeToSpec (volume) {
-    return {
+    const spec = {
       replicaCount: volume.replicaCount,
       preferredNodes: _.clone(volume.preferredNodes),
       requiredNodes: _.clone(volume.requiredNodes),
       requiredBytes: volume.requiredBytes,
       limitBytes: volume.limitBytes
     };
+    if (volume.tenant) {
+      spec.tenant = volume.tenant;
+    }
+    return spec;
   }
 
   // Transform volume to status properties used in k8s volume resource.
@@ -1,6 +1,13 @@
 //! Functions for CSI publish and unpublish block mode volumes.
 
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom},
+};
+
+use nix::sys::stat::stat;
 use tonic::{Code, Status};
+use uuid::Uuid;
 
 macro_rules! failure {
     (Code::$code:ident, $msg:literal) => {{ error!($msg); Status::new(Code::$code, $msg) }};
@@ -9,10 +16,22 @@ macro_rules! failure {
 
 use crate::{
     csi::*,
-    dev::Device,
+    dev::{Device, DeviceError},
     mount::{self},
 };
 
+/// Check that the block device at the path, the device itself or a block
+/// special file bound to it, is that of the volume.
+fn verify_device(path: &str, volume_id: &str) -> Result<(), DeviceError> {
+    let uuid = Uuid::parse_str(volume_id)?;
+    let devnum = stat(path)
+        .map_err(|error| {
+            DeviceError::from(format!("failed to stat {}: {}", path, error))
+        })?
+        .st_rdev;
+    Device::verify(devnum, &uuid)
+}
+
 pub async fn publish_block_volume(
     msg: &NodePublishVolumeRequest,
 ) -> Result<(), Status> {
@@ -49,6 +68,27 @@ pub async fn publish_block_volume(
             error
         )
     })? {
+        // Publishing over a subset of the paths is not fatal, the remaining
+        // paths are reconnected by the next stage request.
+        if let Ok(false) = device.ready().await {
+            warn!(
+                "Publishing volume {} while not all paths to {} are connected",
+                volume_id, device_path
+            );
+        }
+
+        // The device name may have been reused by udev for another device
+        // since the volume was staged.
+        if let Err(error) = verify_device(&device_path, volume_id) {
+            return Err(failure!(
+                Code::FailedPrecondition,
+                "Failed to publish volume {}: device {} does not match: {}",
+                volume_id,
+                device_path,
+                error
+            ));
+        }
+
         let devt = unsafe { libc::makedev(259, 254) };
 
         let cstr_dst = std::ffi::CString::new(target_path.as_str()).unwrap();
@@ -95,6 +135,15 @@ pub fn unpublish_block_volume(
     // block volumes are mounted on block special file, which is not
     // a regular file.
     if mount::find_mount(None, Some(target_path)).is_some() {
+        // The device is unmounted regardless, as it is only bound to the
+        // target path of this volume.
+        if let Err(error) = verify_device(target_path, volume_id) {
+            error!(
+                "Volume {} was published at {} as another device: {}",
+                volume_id, target_path, error
+            );
+        }
+
         match mount::blockdevice_unmount(&target_path) {
             Ok(_) => {}
             Err(err) => {
@@ -118,3 +167,38 @@ pub fn unpublish_block_volume(
     info!("Volume {} unpublished from {}", volume_id, target_path);
     Ok(())
 }
+
+/// Return the capacity of a block volume published at the given path.
+/// Only the total size is known for a raw block device.
+pub fn block_volume_stats(
+    volume_id: &str,
+    volume_path: &str,
+) -> Result<Vec<VolumeUsage>, Status> {
+    let mut file = File::open(volume_path).map_err(|error| {
+        failure!(
+            Code::NotFound,
+            "Failed to get stats for volume {}: failed to open {}: {}",
+            volume_id,
+            volume_path,
+            error
+        )
+    })?;
+
+    // seeking to the end of a block special file yields the device size
+    let total = file.seek(SeekFrom::End(0)).map_err(|error| {
+        failure!(
+            Code::Internal,
+            "Failed to get stats for volume {}: failed to determine size of {}: {}",
+            volume_id,
+            volume_path,
+            error
+        )
+    })?;
+
+    Ok(vec![VolumeUsage {
+        total: total as i64,
+        unit: volume_usage::Unit::Bytes as i32,
+        available: 0,
+        used: 0,
+    }])
+}
@@ -15,10 +15,26 @@
 //!         // attach the device
 //!         device.attach().await?;
 //!         // wait for it to show up in udev and obtain the path
-//!         let path = Device::wait_for_device(device, timeout, 10).await?;
+//!         let path = Device::wait_for_device(device, timeout).await?;
 //!     }
 //! ```
 //!
+//! A device which is reachable over more than one path (i.e. an nvmf URI
+//! specifying additional portals) is only considered to be attached once
+//! all of its paths are connected, which is what `ready()` reports. The same
+//! goes for an iscsi URI specifying a second portal, of which the sessions
+//! are combined by dm-multipath. The wait for an attached device returns
+//! earlier, once it is `usable()`: all paths which `attach()` connected are
+//! live. The paths which could not be connected are not waited for, and are
+//! connected in the background or by the next attach.
+//!
+//! Rather than scanning udev at intervals, the wait for an attached device
+//! listens to the udev events of block devices and nvme controllers, and
+//! looks for the device again whenever one is added or changed. The device
+//! is thus found as soon as the kernel announces it, under whichever name
+//! it gets, which for an nvmf namespace depends on the controller number
+//! it is given on (re)connect.
+//!
 //! Detaching a device is performed via:
 //! ```ignore
 //!     let uuid = Uuid::parse_str(&volume_id)?;
@@ -27,10 +43,18 @@
 //!     }
 //! ```
 
-use std::{convert::TryFrom, time::Duration};
+use std::{
+    convert::TryFrom,
+    os::unix::io::AsRawFd,
+    time::{Duration, Instant},
+};
 
-use tokio::time::delay_for;
-use udev::Enumerator;
+use nix::poll::{poll, PollFd, PollFlags};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::delay_for,
+};
+use udev::{Enumerator, EventType, MonitorBuilder, MonitorSocket};
 use url::Url;
 use uuid::Uuid;
 
@@ -41,6 +65,10 @@ mod util;
 
 const NVME_NQN_PREFIX: &str = "nqn.2019-05.io.openebs";
 
+// How long to wait for a udev event before looking for the device anyway,
+// i.e. for a path to become live, which is not announced by udev.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub use crate::error::DeviceError;
 use crate::match_dev;
 
@@ -50,6 +78,16 @@ pub type DeviceName = String;
 pub trait Attach: Sync + Send {
     async fn attach(&self) -> Result<(), DeviceError>;
     async fn find(&self) -> Result<Option<DeviceName>, DeviceError>;
+    /// Check if the device can be used, i.e. the paths connected by attach()
+    /// are live. Single path devices are usable as soon as they are found.
+    async fn usable(&self) -> Result<bool, DeviceError> {
+        Ok(true)
+    }
+    /// Check if all paths to the device are connected.
+    /// Single path devices are ready as soon as they are found.
+    async fn ready(&self) -> Result<bool, DeviceError> {
+        Ok(true)
+    }
 }
 
 #[tonic::async_trait]
@@ -77,6 +115,11 @@ impl Device {
         }
     }
 
+    /// Start keeping up the iSCSI sessions of the staged volumes.
+    pub fn supervise_sessions() {
+        iscsi::supervisor::start();
+    }
+
     /// Lookup an existing device in udev matching the given UUID
     /// to obtain a device implementing the Detach trait.
     pub async fn lookup(
@@ -116,19 +159,152 @@ impl Device {
         Ok(None)
     }
 
+    /// Check that the block device with the given device number belongs to
+    /// the volume with the given UUID, going by the WWN of an nvmf device or
+    /// the IQN of an iSCSI device as recorded by udev. This guards against
+    /// a device name which has been given to another device by udev since
+    /// it was looked up. Devices which carry no identity, i.e. nbd devices,
+    /// pass unchecked.
+    pub fn verify(devnum: u64, uuid: &Uuid) -> Result<(), DeviceError> {
+        let mut enumerator = Enumerator::new()?;
+
+        enumerator.match_subsystem("block")?;
+        enumerator.match_property("DEVTYPE", "disk")?;
+
+        let device = enumerator
+            .scan_devices()?
+            .find(|device| device.devnum() == Some(devnum))
+            .ok_or_else(|| {
+                DeviceError::from(format!(
+                    "no block device {}:{} found",
+                    unsafe { libc::major(devnum) },
+                    unsafe { libc::minor(devnum) }
+                ))
+            })?;
+
+        let (devname, found) = if let Some((devname, path)) =
+            match_dev::match_iscsi_device(&device)
+        {
+            let value =
+                iscsi::IscsiDetach::from_path(devname.to_string(), path)?;
+            (devname, *value.uuid())
+        } else if let Some((devname, wwn)) = match_dev::match_nvmf_wwn(&device)
+        {
+            (devname, Uuid::parse_str(wwn.trim_start_matches("uuid."))?)
+        } else {
+            debug!(
+                "Identity of block device {}:{} cannot be verified",
+                unsafe { libc::major(devnum) },
+                unsafe { libc::minor(devnum) }
+            );
+            return Ok(());
+        };
+
+        if found != *uuid {
+            return Err(DeviceError::from(format!(
+                "device {} belongs to volume {}",
+                devname, found
+            )));
+        }
+        Ok(())
+    }
+
     /// Wait for a device to show up in udev
-    /// once attach() has been called.
+    /// once attach() has been called, with the paths it connected live. The
+    /// remaining paths of a multipath device come up on their own.
+    /// The device is looked for whenever udev announces a block device or
+    /// an nvme controller, which is listened to before the first look so
+    /// that no event is missed.
     pub async fn wait_for_device(
         device: Box<dyn Attach>,
         timeout: Duration,
-        retries: u32,
     ) -> Result<DeviceName, DeviceError> {
-        for _ in 0 ..= retries {
+        let deadline = Instant::now() + timeout;
+        let mut events = listen(timeout).await?;
+
+        loop {
             if let Some(devname) = device.find().await? {
-                return Ok(devname);
+                if device.usable().await? {
+                    return Ok(devname);
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            let wait = std::cmp::min(deadline - now, RECHECK_INTERVAL);
+            // a closed channel means that the monitor has stopped, in which
+            // case the device is looked for at intervals
+            if let Ok(None) = tokio::time::timeout(wait, events.recv()).await {
+                delay_for(wait).await;
             }
-            delay_for(timeout).await;
         }
+
         Err(DeviceError::new("device attach timeout"))
     }
 }
+
+/// Start listening to udev for block devices and nvme controllers being
+/// added or changed. The monitor runs on a blocking thread, as the udev
+/// socket cannot be moved between threads, and passes on an event until
+/// the timeout has passed or the receiver has gone.
+async fn listen(
+    timeout: Duration,
+) -> Result<mpsc::UnboundedReceiver<()>, DeviceError> {
+    let (started, listening) = oneshot::channel();
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    tokio::task::spawn_blocking(move || {
+        let socket = match MonitorBuilder::new()
+            .and_then(|builder| builder.match_subsystem("block"))
+            .and_then(|builder| builder.match_subsystem("nvme"))
+            .and_then(|builder| builder.listen())
+        {
+            Ok(socket) => socket,
+            Err(error) => {
+                let _ = started.send(Err(error));
+                return;
+            }
+        };
+        let _ = started.send(Ok(()));
+        forward_events(socket, timeout, sender);
+    });
+
+    match listening.await {
+        Ok(result) => result.map(|_| receiver).map_err(DeviceError::from),
+        Err(_) => Err(DeviceError::new("udev monitor failed to start")),
+    }
+}
+
+/// Pass on the events of the socket which add or change a device.
+fn forward_events(
+    mut socket: MonitorSocket,
+    timeout: Duration,
+    sender: mpsc::UnboundedSender<()>,
+) {
+    let deadline = Instant::now() + timeout;
+    let mut fds = [PollFd::new(socket.as_raw_fd(), PollFlags::POLLIN)];
+
+    while Instant::now() < deadline {
+        match poll(&mut fds, RECHECK_INTERVAL.as_millis() as i32) {
+            Ok(0) => continue,
+            Ok(_) => {}
+            Err(error) => {
+                warn!("Failed to poll for udev events: {}", error);
+                return;
+            }
+        }
+
+        for event in &mut socket {
+            match event.event_type() {
+                EventType::Add | EventType::Change => {
+                    if sender.send(()).is_err() {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -10,8 +10,14 @@ use crate::{dev::util::extract_uuid, match_dev::match_iscsi_device};
 use super::{Attach, Detach, DeviceError, DeviceName};
 
 mod iscsiadm;
+mod multipath;
+pub(super) mod supervisor;
+
 use iscsiadm::IscsiAdmin;
 
+// Number of portals a target can be reached over.
+const MAX_PORTALS: usize = 2;
+
 pub(super) struct IscsiDevice {
     portal: String,
     iqn: String,
@@ -29,8 +35,8 @@ impl IscsiDevice {
         }
     }
 
-    fn to_path(&self) -> String {
-        format!("ip-{}-iscsi-{}-lun-{}", self.portal, self.iqn, self.lun)
+    fn to_path(&self, portal: &str) -> String {
+        format!("ip-{}-iscsi-{}-lun-{}", portal, self.iqn, self.lun)
     }
 
     fn from_path(path: &str) -> Result<IscsiDevice, DeviceError> {
@@ -66,7 +72,117 @@ impl IscsiDevice {
     }
 }
 
-pub(super) type IscsiAttach = IscsiDevice;
+/// An iSCSI volume which may be reachable over a second portal, which is
+/// passed as a "portal" query parameter:
+/// iscsi://host1:3260/iqn/0?portal=host2:3260
+/// The sessions over both portals are combined by dm-multipath.
+pub(super) struct IscsiAttach {
+    device: IscsiDevice,
+    portals: Vec<String>,
+}
+
+impl IscsiAttach {
+    // Log in to the target via a single portal,
+    // an existing session is not considered an error.
+    fn login(&self, portal: &str) -> Result<(), DeviceError> {
+        let iqn = &self.device.iqn;
+
+        match IscsiAdmin::find_session(portal, iqn) {
+            Ok(found) => {
+                if found {
+                    // session already exists - nothing to do
+                    supervisor::register(portal, iqn);
+                    return Ok(());
+                }
+            }
+            Err(error) => {
+                return Err(DeviceError::from(format!(
+                    "iscsiadm command (session) failed: {}",
+                    error
+                )));
+            }
+        }
+
+        if let Err(error) = IscsiAdmin::discover(portal, iqn) {
+            return Err(DeviceError::from(format!(
+                "iscsiadm command (discovery) failed: {}",
+                error
+            )));
+        }
+
+        if let Err(error) = IscsiAdmin::login(portal, iqn) {
+            let _ = IscsiAdmin::delete(portal, iqn);
+            return Err(DeviceError::from(format!(
+                "iscsiadm command (login) failed: {}",
+                error
+            )));
+        }
+
+        supervisor::register(portal, iqn);
+        Ok(())
+    }
+
+    // Return the SCSI disk of the session via each portal which has one.
+    fn disks(&self) -> Result<Vec<DeviceName>, DeviceError> {
+        let keys: Vec<String> = self
+            .portals
+            .iter()
+            .map(|portal| self.device.to_path(portal))
+            .collect();
+
+        let mut enumerator = Enumerator::new()?;
+
+        enumerator.match_subsystem("block")?;
+        enumerator.match_property("DEVTYPE", "disk")?;
+
+        let mut disks = Vec::new();
+
+        for device in enumerator.scan_devices()? {
+            if let Some((devname, path)) = match_iscsi_device(&device) {
+                if keys.iter().any(|key| key == path) {
+                    disks.push(devname.to_string());
+                }
+            }
+        }
+
+        Ok(disks)
+    }
+}
+
+impl TryFrom<&Url> for IscsiAttach {
+    type Error = DeviceError;
+
+    fn try_from(url: &Url) -> Result<Self, Self::Error> {
+        let device = IscsiDevice::try_from(url)?;
+        let mut portals = vec![device.portal.clone()];
+
+        for (key, value) in url.query_pairs() {
+            if key != "portal" {
+                continue;
+            }
+            let portal = if value.contains(':') {
+                value.to_string()
+            } else {
+                format!("{}:3260", value)
+            };
+            if !portals.contains(&portal) {
+                portals.push(portal);
+            }
+        }
+
+        if portals.len() > MAX_PORTALS {
+            return Err(DeviceError::from(format!(
+                "at most {} portals are supported",
+                MAX_PORTALS
+            )));
+        }
+
+        Ok(IscsiAttach {
+            device,
+            portals,
+        })
+    }
+}
 
 impl TryFrom<&Url> for IscsiDevice {
     type Error = DeviceError;
@@ -117,33 +233,36 @@ impl TryFrom<&Url> for IscsiDevice {
 #[tonic::async_trait]
 impl Attach for IscsiAttach {
     async fn attach(&self) -> Result<(), DeviceError> {
-        match IscsiAdmin::find_session(&self.portal, &self.iqn) {
-            Ok(found) => {
-                if found {
-                    // session already exists - nothing to do
-                    return Ok(());
-                }
-            }
-            Err(error) => {
-                return Err(DeviceError::from(format!(
-                    "iscsiadm command (session) failed: {}",
-                    error
-                )));
-            }
+        if self.portals.len() == 1 {
+            return self.login(&self.portals[0]);
         }
 
-        if let Err(error) = IscsiAdmin::discover(&self.portal, &self.iqn) {
-            return Err(DeviceError::from(format!(
-                "iscsiadm command (discovery) failed: {}",
-                error
-            )));
+        if let Err(error) = multipath::configure() {
+            warn!(
+                "Failed to configure multipath for {}: {}",
+                self.device.iqn, error
+            );
         }
 
-        if let Err(error) = IscsiAdmin::login(&self.portal, &self.iqn) {
-            let _ = IscsiAdmin::delete(&self.portal, &self.iqn);
+        // Log in via all portals. The attach only fails if no session can
+        // be established, missing sessions are detected by ready().
+        let mut connected = 0;
+
+        for portal in &self.portals {
+            match self.login(portal) {
+                Ok(_) => connected += 1,
+                Err(error) => warn!(
+                    "Failed to log in to {} via {}: {}",
+                    self.device.iqn, portal, error
+                ),
+            }
+        }
+
+        if connected == 0 {
             return Err(DeviceError::from(format!(
-                "iscsiadm command (login) failed: {}",
-                error
+                "failed to log in to {} via any of {} portal(s)",
+                self.device.iqn,
+                self.portals.len()
             )));
         }
 
@@ -151,22 +270,31 @@ impl Attach for IscsiAttach {
     }
 
     async fn find(&self) -> Result<Option<DeviceName>, DeviceError> {
-        let key: String = self.to_path();
+        let disks = self.disks()?;
 
-        let mut enumerator = Enumerator::new()?;
+        if self.portals.len() == 1 {
+            return Ok(disks.into_iter().next());
+        }
 
-        enumerator.match_subsystem("block")?;
-        enumerator.match_property("DEVTYPE", "disk")?;
+        // The volume is used through the multipath device, which shows up
+        // once multipathd has picked up a disk.
+        Ok(disks.iter().find_map(|disk| multipath::holder(disk)))
+    }
 
-        for device in enumerator.scan_devices()? {
-            if let Some((devname, path)) = match_iscsi_device(&device) {
-                if path == key {
-                    return Ok(Some(devname.to_string()));
-                }
-            }
+    async fn ready(&self) -> Result<bool, DeviceError> {
+        let disks = self.disks()?;
+
+        if disks.len() < self.portals.len() {
+            debug!(
+                "{} out of {} sessions to {} are logged in",
+                disks.len(),
+                self.portals.len(),
+                self.device.iqn
+            );
+            return Ok(false);
         }
 
-        Ok(None)
+        Ok(true)
     }
 }
 
@@ -199,22 +327,42 @@ impl Detach for IscsiDetach {
     }
 
     async fn detach(&self) -> Result<(), DeviceError> {
-        if let Err(error) =
-            IscsiAdmin::logout(&self.device.portal, &self.device.iqn)
-        {
-            return Err(DeviceError::from(format!(
-                "iscsiadm command (logout) failed: {}",
-                error
-            )));
+        let iqn = &self.device.iqn;
+        supervisor::unregister(iqn);
+
+        if let Some(holder) = multipath::holder(&self.name) {
+            if let Err(error) = multipath::flush(&holder) {
+                return Err(DeviceError::from(format!(
+                    "failed to flush multipath device {}: {}",
+                    holder, error
+                )));
+            }
         }
 
-        if let Err(error) =
-            IscsiAdmin::delete(&self.device.portal, &self.device.iqn)
-        {
-            return Err(DeviceError::from(format!(
-                "iscsiadm command (delete) failed: {}",
-                error
-            )));
+        // The volume may have been attached via more than one portal.
+        let mut portals: Vec<String> = IscsiAdmin::sessions()?
+            .into_iter()
+            .filter(|(_, target)| target == iqn)
+            .map(|(portal, _)| portal)
+            .collect();
+        if !portals.contains(&self.device.portal) {
+            portals.push(self.device.portal.clone());
+        }
+
+        for portal in &portals {
+            if let Err(error) = IscsiAdmin::logout(portal, iqn) {
+                return Err(DeviceError::from(format!(
+                    "iscsiadm command (logout) failed: {}",
+                    error
+                )));
+            }
+
+            if let Err(error) = IscsiAdmin::delete(portal, iqn) {
+                return Err(DeviceError::from(format!(
+                    "iscsiadm command (delete) failed: {}",
+                    error
+                )));
+            }
         }
 
         Ok(())
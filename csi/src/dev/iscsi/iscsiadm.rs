@@ -15,6 +15,13 @@ impl IscsiAdmin {
         portal: &str,
         iqn: &str,
     ) -> Result<bool, DeviceError> {
+        Ok(IscsiAdmin::sessions()?
+            .iter()
+            .any(|(p, t)| p == portal && t == iqn))
+    }
+
+    /// Return the portal and target of every iSCSI session.
+    pub(super) fn sessions() -> Result<Vec<(String, String)>, DeviceError> {
         const ARGS: [&str; 2] = ["--mode", "session"];
 
         let iscsiadm = IscsiAdmin::get_binary()?;
@@ -24,14 +31,14 @@ impl IscsiAdmin {
         let output = Command::new(iscsiadm).args(&ARGS).output()?;
 
         if output.status.success() {
-            return Ok(IscsiAdmin::find_target(portal, iqn, output.stdout));
+            return Ok(IscsiAdmin::targets(output.stdout));
         }
 
         if output.status.code() == Some(21) {
             // An exit code of 21 corresponds to ISCSI_ERR_NO_OBJS_FOUND.
             // In this case that means that no iSCSI sessions were found,
             // and this SHOULD NOT be considered an error.
-            return Ok(false);
+            return Ok(Vec::new());
         }
 
         Err(DeviceError::from(String::from_utf8(output.stderr).unwrap()))
@@ -56,7 +63,10 @@ impl IscsiAdmin {
         let output = Command::new(iscsiadm).args(&args).output()?;
 
         if output.status.success() {
-            if IscsiAdmin::find_target(portal, iqn, output.stdout) {
+            if IscsiAdmin::targets(output.stdout)
+                .iter()
+                .any(|(p, t)| p == portal && t == iqn)
+            {
                 return Ok(());
             }
 
@@ -127,22 +137,22 @@ impl IscsiAdmin {
         Err(DeviceError::from(String::from_utf8(output.stderr).unwrap()))
     }
 
-    fn find_target(portal: &str, iqn: &str, data: Vec<u8>) -> bool {
+    fn targets(data: Vec<u8>) -> Vec<(String, String)> {
         lazy_static! {
             static ref PATTERN: Regex = Regex::new(r"(?P<portal>[[:digit:]]+(\.[[:digit:]]+){3}:[[:digit:]]+),[[:digit:]]+ +(?P<target>iqn\.[^ ]+)").unwrap();
         }
 
-        for line in String::from_utf8(data).unwrap().split('\n') {
-            if let Some(captures) = PATTERN.captures(line) {
-                if captures.name("portal").unwrap().as_str() == portal
-                    && captures.name("target").unwrap().as_str() == iqn
-                {
-                    return true;
-                }
-            }
-        }
-
-        false
+        String::from_utf8(data)
+            .unwrap()
+            .split('\n')
+            .filter_map(|line| PATTERN.captures(line))
+            .map(|captures| {
+                (
+                    captures.name("portal").unwrap().as_str().to_string(),
+                    captures.name("target").unwrap().as_str().to_string(),
+                )
+            })
+            .collect()
     }
 
     fn get_binary() -> Result<&'static str, DeviceError> {
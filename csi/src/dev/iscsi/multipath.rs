@@ -0,0 +1,71 @@
+//! dm-multipath for iSCSI volumes which are reachable over two portals.
+//! Each session shows up as a SCSI disk of its own, which multipathd
+//! combines into a single device mapper device once it is configured for
+//! the disks of mayastor. The configuration is generated into the config
+//! directory of multipathd, leaving the main config file alone.
+
+use std::{fs, path::Path, process::Command};
+
+use super::DeviceError;
+
+const MULTIPATH_CONF: &str = "/etc/multipath/conf.d/mayastor.conf";
+
+const CONFIG: &str = r#"# Generated by the mayastor CSI plugin, do not edit.
+devices {
+    device {
+        vendor ".*"
+        product "Nexus_CAS_Driver"
+        path_grouping_policy "failover"
+        path_checker "tur"
+        failback "immediate"
+        no_path_retry "queue"
+    }
+}
+"#;
+
+/// Write the multipath configuration for the disks of mayastor, and have
+/// multipathd reload it, unless it is in place already.
+pub(super) fn configure() -> Result<(), DeviceError> {
+    if let Ok(current) = fs::read_to_string(MULTIPATH_CONF) {
+        if current == CONFIG {
+            return Ok(());
+        }
+    }
+
+    if let Some(dir) = Path::new(MULTIPATH_CONF).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(MULTIPATH_CONF, CONFIG)?;
+    info!("Generated multipath configuration {}", MULTIPATH_CONF);
+
+    execute("multipathd", &["reconfigure"])
+}
+
+/// Return the device mapper device which holds the disk, if any.
+pub(super) fn holder(devname: &str) -> Option<String> {
+    let name = Path::new(devname).file_name()?.to_str()?;
+    let holders = fs::read_dir(format!("/sys/block/{}/holders", name)).ok()?;
+
+    holders
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .find(|holder| holder.starts_with("dm-"))
+        .map(|holder| format!("/dev/{}", holder))
+}
+
+/// Flush the multipath device, so that its paths can be logged out of.
+pub(super) fn flush(devname: &str) -> Result<(), DeviceError> {
+    execute("multipath", &["-f", devname])
+}
+
+fn execute(command: &str, args: &[&str]) -> Result<(), DeviceError> {
+    trace!("{} {:?}", command, args);
+
+    let output = Command::new(command).args(args).output()?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    Err(DeviceError::from(String::from_utf8(output.stderr).unwrap()))
+}
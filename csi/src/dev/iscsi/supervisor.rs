@@ -0,0 +1,94 @@
+//! Supervisor of the iSCSI sessions of the staged volumes. A session which
+//! has been lost for good, i.e. as the target was restarted after the
+//! session had timed out, is not recovered by iscsid, and a volume would
+//! then stay without a path until it is staged again. The supervisor
+//! checks the sessions at intervals and logs in again to every target of
+//! a staged volume which has no session.
+//!
+//! The targets are registered when a volume is attached and unregistered
+//! when it is detached. As the registry does not survive a restart of the
+//! plugin, it is seeded with the sessions of mayastor which exist at start.
+
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use once_cell::sync::Lazy;
+use tokio::time::delay_for;
+
+use super::iscsiadm::IscsiAdmin;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const IQN_PREFIX: &str = "iqn.2019-05.io.openebs:";
+
+/// the portal and target of every session to keep up
+static TARGETS: Lazy<Mutex<HashSet<(String, String)>>> =
+    Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Keep up the session to the target via the portal.
+pub(super) fn register(portal: &str, iqn: &str) {
+    TARGETS
+        .lock()
+        .unwrap()
+        .insert((portal.to_string(), iqn.to_string()));
+}
+
+/// Stop supervising all sessions to the target.
+pub(super) fn unregister(iqn: &str) {
+    TARGETS.lock().unwrap().retain(|(_, target)| target != iqn);
+}
+
+/// Log in again to the targets which have no session.
+fn check() {
+    let sessions = match IscsiAdmin::sessions() {
+        Ok(sessions) => sessions,
+        Err(error) => {
+            warn!("Failed to list iSCSI sessions: {}", error);
+            return;
+        }
+    };
+
+    let lost: Vec<(String, String)> = TARGETS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|target| !sessions.contains(target))
+        .cloned()
+        .collect();
+
+    for (portal, iqn) in lost {
+        warn!("iSCSI session to {} via {} lost, logging in", iqn, portal);
+        let result = IscsiAdmin::discover(&portal, &iqn)
+            .and_then(|_| IscsiAdmin::login(&portal, &iqn));
+        match result {
+            Ok(_) => info!("iSCSI session to {} via {} restored", iqn, portal),
+            Err(error) => warn!(
+                "Failed to log in to {} via {}: {}",
+                iqn,
+                portal,
+                error.to_string().trim()
+            ),
+        }
+    }
+}
+
+/// Start supervising the iSCSI sessions.
+pub(crate) fn start() {
+    match IscsiAdmin::sessions() {
+        Ok(sessions) => {
+            for (portal, iqn) in sessions {
+                if iqn.starts_with(IQN_PREFIX) {
+                    register(&portal, &iqn);
+                }
+            }
+        }
+        Err(error) => debug!("Not seeding iSCSI sessions: {}", error),
+    }
+
+    tokio::spawn(async {
+        loop {
+            delay_for(CHECK_INTERVAL).await;
+            if let Err(error) = tokio::task::spawn_blocking(check).await {
+                error!("iSCSI session check failed: {}", error);
+            }
+        }
+    });
+}
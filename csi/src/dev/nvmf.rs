@@ -1,38 +1,250 @@
-use std::convert::TryFrom;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs,
+    net::Ipv6Addr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
 
+use nvmeadm::nvmf_subsystem::NvmeSubsystems;
+use once_cell::sync::Lazy;
+use tokio::{sync::oneshot, time::delay_for};
 use udev::Enumerator;
-use url::Url;
+use url::{Host, Url};
 use uuid::Uuid;
 
 use crate::{dev::util::extract_uuid, match_dev::match_nvmf_device};
 
 use super::{Attach, Detach, DeviceError, DeviceName};
 
-pub(super) struct NvmfAttach {
+const NVMF_DEFAULT_PORT: u16 = 4420;
+
+// How often and for how long to retry connecting the portals which failed
+// to connect when the volume was attached.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+const RECONNECT_ATTEMPTS: u32 = 30;
+
+// Kernel module parameter which tells us if native NVMe multipath is enabled.
+const NVME_MULTIPATH_PARAM: &str = "/sys/module/nvme_core/parameters/multipath";
+
+/// The reconnect running in the background for each NQN, along with the
+/// number it was started under. Dropping the sender stops the reconnect.
+static RECONNECTS: Lazy<Mutex<HashMap<String, (u64, oneshot::Sender<()>)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_RECONNECT: AtomicU64 = AtomicU64::new(0);
+
+/// A network path (transport address and service id) to an nvmf target.
+#[derive(Debug, Clone, PartialEq)]
+struct Portal {
     host: String,
     port: u16,
+}
+
+impl Portal {
+    /// Parse a portal of the form "host[:port]", where an IPv6 address has
+    /// to be enclosed in brackets, i.e. "[fd00::1]:4420", as it contains
+    /// colons itself.
+    fn parse(value: &str) -> Result<Portal, DeviceError> {
+        let invalid =
+            || DeviceError::from(format!("invalid portal: {}", value));
+
+        let (host, port) = if let Some(rest) = value.strip_prefix('[') {
+            let end = rest.find(']').ok_or_else(invalid)?;
+            let address = rest[.. end].parse::<Ipv6Addr>().map_err(|_| {
+                DeviceError::from(format!("invalid IPv6 address: {}", value))
+            })?;
+            let port = match &rest[end + 1 ..] {
+                "" => None,
+                port => Some(port.strip_prefix(':').ok_or_else(invalid)?),
+            };
+            (address.to_string(), port)
+        } else {
+            let mut parts = value.splitn(2, ':');
+            let host = parts.next().unwrap_or_default();
+            let port = parts.next();
+            if port.map_or(false, |port| port.contains(':')) {
+                return Err(DeviceError::from(format!(
+                    "invalid portal: {}: IPv6 addresses must be enclosed in brackets",
+                    value
+                )));
+            }
+            (host.to_string(), port)
+        };
+
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Portal {
+            host,
+            port: match port {
+                Some(port) => port.parse::<u16>()?,
+                None => NVMF_DEFAULT_PORT,
+            },
+        })
+    }
+}
+
+// Connect to the target over a single portal,
+// an existing connection is not considered an error.
+fn connect(nqn: &str, portal: &Portal) -> Result<(), DeviceError> {
+    if let Err(failure) =
+        nvmeadm::nvmf_discovery::connect(&portal.host, portal.port as u32, nqn)
+    {
+        if let Ok(error) = failure.downcast::<std::io::Error>() {
+            if let Some(errno) = error.raw_os_error() {
+                if errno == 114 {
+                    return Ok(());
+                }
+            }
+            return Err(DeviceError::from(error));
+        }
+        return Err(DeviceError::new("connect failed"));
+    }
+
+    Ok(())
+}
+
+/// Keep trying to connect to the target over the portals which could not
+/// be connected when the volume was attached, so that the volume does not
+/// have to wait for all of its paths, while the missing ones still come up
+/// once their portals are reachable. The kernel reconnects the paths that
+/// have been connected once by itself. A reconnect to the same target which
+/// is still running, from an earlier attach, is stopped.
+fn reconnect(nqn: String, portals: Vec<Portal>) {
+    let id = NEXT_RECONNECT.fetch_add(1, Ordering::Relaxed);
+    let (cancel, cancelled) = oneshot::channel();
+    RECONNECTS.lock().unwrap().insert(nqn.clone(), (id, cancel));
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = cancelled => {
+                debug!("Stopped reconnecting to {}", nqn);
+                return;
+            }
+            _ = retry_connect(&nqn, portals) => {}
+        }
+
+        let mut reconnects = RECONNECTS.lock().unwrap();
+        if reconnects.get(&nqn).map_or(false, |(current, _)| *current == id) {
+            reconnects.remove(&nqn);
+        }
+    });
+}
+
+/// Stop reconnecting to the target, if that is still going on.
+fn stop_reconnect(nqn: &str) {
+    if RECONNECTS.lock().unwrap().remove(nqn).is_some() {
+        debug!("Stopping the reconnect to {}", nqn);
+    }
+}
+
+/// Try to connect over the portals at intervals, until all of them are
+/// connected or the attempts are used up.
+async fn retry_connect(nqn: &str, mut portals: Vec<Portal>) {
+    for _ in 0 .. RECONNECT_ATTEMPTS {
+        delay_for(RECONNECT_INTERVAL).await;
+
+        let (target, pending) = (nqn.to_string(), portals.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            pending
+                .into_iter()
+                .filter(|portal| connect(&target, portal).is_err())
+                .collect::<Vec<Portal>>()
+        })
+        .await;
+
+        match result {
+            Ok(failed) => portals = failed,
+            Err(error) => {
+                error!("Reconnecting to {} failed: {}", nqn, error);
+                return;
+            }
+        }
+
+        if portals.is_empty() {
+            info!("All paths to {} are connected", nqn);
+            return;
+        }
+    }
+
+    warn!(
+        "Giving up connecting to {} via {} portal(s)",
+        nqn,
+        portals.len()
+    );
+}
+
+/// An nvmf volume which may be reachable over more than one portal.
+/// Additional portals are passed as repeated "portal" query parameters:
+/// nvmf://host1:4420/nqn?portal=host2:4420&portal=host3:4420
+pub(super) struct NvmfAttach {
+    portals: Vec<Portal>,
     uuid: Uuid,
     nqn: String,
+    // the number of portals connected by attach()
+    connected: AtomicUsize,
 }
 
 impl NvmfAttach {
-    fn new(host: String, port: u16, uuid: Uuid, nqn: String) -> NvmfAttach {
+    fn new(portals: Vec<Portal>, uuid: Uuid, nqn: String) -> NvmfAttach {
         NvmfAttach {
-            host,
-            port,
+            portals,
             uuid,
             nqn,
+            connected: AtomicUsize::new(0),
         }
     }
+
+    // Return the number of live controllers connected to our subsystem.
+    fn live_paths(&self) -> Result<usize, DeviceError> {
+        Ok(NvmeSubsystems::new()?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.nqn == self.nqn && entry.state == "live")
+            .count())
+    }
+}
+
+/// Check if native NVMe multipath is enabled, in which case all paths
+/// to a namespace are represented by a single block device.
+fn multipath_enabled() -> bool {
+    match fs::read_to_string(NVME_MULTIPATH_PARAM) {
+        Ok(value) => value.trim() == "Y",
+        Err(_) => false,
+    }
+}
+
+/// Check if the controller of a namespace is live. With native NVMe
+/// multipath the namespace belongs to the subsystem rather than to one of
+/// its controllers, and the subsystem has no state.
+fn controller_live(device: &udev::Device) -> bool {
+    let state = device.parent().and_then(|parent| {
+        parent
+            .attribute_value("state")
+            .and_then(|state| state.to_str())
+            .map(|state| state.trim().to_string())
+    });
+
+    match state {
+        Some(state) => state == "live",
+        None => true,
+    }
 }
 
 impl TryFrom<&Url> for NvmfAttach {
     type Error = DeviceError;
 
     fn try_from(url: &Url) -> Result<Self, Self::Error> {
-        let host = url
-            .host_str()
-            .ok_or_else(|| DeviceError::new("missing host"))?;
+        // the brackets of an IPv6 address are not part of the address
+        let host = match url.host() {
+            Some(Host::Ipv6(address)) => address.to_string(),
+            Some(host) => host.to_string(),
+            None => return Err(DeviceError::new("missing host")),
+        };
 
         let segments: Vec<&str> = url
             .path_segments()
@@ -59,34 +271,67 @@ impl TryFrom<&Url> for NvmfAttach {
             DeviceError::from(format!("invalid UUID: {}", error))
         })?;
 
-        let port = url.port().unwrap_or(4420);
+        let mut portals = vec![Portal {
+            host,
+            port: url.port().unwrap_or(NVMF_DEFAULT_PORT),
+        }];
 
-        Ok(NvmfAttach::new(
-            host.to_string(),
-            port,
-            uuid,
-            segments[0].to_string(),
-        ))
+        for (key, value) in url.query_pairs() {
+            if key != "portal" {
+                continue;
+            }
+            let portal = Portal::parse(&value)?;
+            if !portals.contains(&portal) {
+                portals.push(portal);
+            }
+        }
+
+        Ok(NvmfAttach::new(portals, uuid, segments[0].to_string()))
     }
 }
 
 #[tonic::async_trait]
 impl Attach for NvmfAttach {
     async fn attach(&self) -> Result<(), DeviceError> {
-        if let Err(failure) = nvmeadm::nvmf_discovery::connect(
-            &self.host,
-            self.port as u32,
-            &self.nqn,
-        ) {
-            if let Ok(error) = failure.downcast::<std::io::Error>() {
-                if let Some(errno) = error.raw_os_error() {
-                    if errno == 114 {
-                        return Ok(());
-                    }
-                }
-                return Err(DeviceError::from(error));
+        if self.portals.len() > 1 && !multipath_enabled() {
+            warn!(
+                "Native NVMe multipath is disabled, {} will be attached as {} separate devices",
+                self.nqn,
+                self.portals.len()
+            );
+        }
+
+        // Connect over all portals so that the initiator can fail over
+        // transparently. The attach only fails if no path can be connected,
+        // the portals which cannot be connected yet are retried in the
+        // background.
+        let mut failed = Vec::new();
+
+        for portal in &self.portals {
+            if let Err(error) = connect(&self.nqn, portal) {
+                warn!(
+                    "Failed to connect to {} via {}:{}: {}",
+                    self.nqn, portal.host, portal.port, error
+                );
+                failed.push(portal.clone());
             }
-            return Err(DeviceError::new("connect failed"));
+        }
+
+        self.connected
+            .store(self.portals.len() - failed.len(), Ordering::SeqCst);
+
+        if failed.len() == self.portals.len() {
+            return Err(DeviceError::from(format!(
+                "failed to connect to {} via any of {} portal(s)",
+                self.nqn,
+                self.portals.len()
+            )));
+        }
+
+        if failed.is_empty() {
+            stop_reconnect(&self.nqn);
+        } else {
+            reconnect(self.nqn.clone(), failed);
         }
 
         Ok(())
@@ -100,13 +345,45 @@ impl Attach for NvmfAttach {
         enumerator.match_subsystem("block")?;
         enumerator.match_property("DEVTYPE", "disk")?;
 
+        // A namespace of a controller which has been lost lingers until the
+        // controller is deleted, while the namespace of the controller which
+        // replaces it shows up under another name.
+        let mut found = None;
+
         for device in enumerator.scan_devices()? {
             if let Some(devname) = match_nvmf_device(&device, &key) {
-                return Ok(Some(devname.to_string()));
+                if controller_live(&device) {
+                    return Ok(Some(devname.to_string()));
+                }
+                found.get_or_insert_with(|| devname.to_string());
             }
         }
 
-        Ok(None)
+        Ok(found)
+    }
+
+    /// The device is usable once the paths over all the portals which
+    /// attach() connected are live. Those which it could not connect are
+    /// not waited for, they come up once the reconnect gets them.
+    async fn usable(&self) -> Result<bool, DeviceError> {
+        let connected = self.connected.load(Ordering::SeqCst).max(1);
+        Ok(self.live_paths()? >= connected)
+    }
+
+    async fn ready(&self) -> Result<bool, DeviceError> {
+        let paths = self.live_paths()?;
+
+        if paths < self.portals.len() {
+            debug!(
+                "{} out of {} paths to {} are live",
+                paths,
+                self.portals.len(),
+                self.nqn
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
     }
 }
 
@@ -131,6 +408,9 @@ impl Detach for NvmfDetach {
     }
 
     async fn detach(&self) -> Result<(), DeviceError> {
+        // the reconnect would otherwise connect the volume again
+        stop_reconnect(&self.nqn);
+
         if nvmeadm::nvmf_discovery::disconnect(&self.nqn)? == 0 {
             return Err(DeviceError::from(format!(
                 "nvmf disconnect {} failed: no device found",
@@ -141,3 +421,68 @@ impl Detach for NvmfDetach {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portal(host: &str, port: u16) -> Portal {
+        Portal {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parse_portal() {
+        assert_eq!(
+            Portal::parse("10.0.0.1:4421").unwrap(),
+            portal("10.0.0.1", 4421)
+        );
+        assert_eq!(
+            Portal::parse("node-1").unwrap(),
+            portal("node-1", NVMF_DEFAULT_PORT)
+        );
+        assert_eq!(
+            Portal::parse("[fd00::1]:4421").unwrap(),
+            portal("fd00::1", 4421)
+        );
+        assert_eq!(
+            Portal::parse("[fd00:0::1]").unwrap(),
+            portal("fd00::1", NVMF_DEFAULT_PORT)
+        );
+    }
+
+    #[test]
+    fn parse_invalid_portal() {
+        for value in &[
+            "",
+            ":4420",
+            "fd00::1",
+            "fd00::1:4420",
+            "[fd00::1",
+            "[fd00::1]4420",
+            "[node-1]:4420",
+            "[fd00::1]:",
+            "10.0.0.1:port",
+            "10.0.0.1:65536",
+        ] {
+            assert!(Portal::parse(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parse_portals_of_url() {
+        let url = Url::parse(concat!(
+            "nvmf://[fd00::1]:4421/",
+            "nqn.2019-05.io.openebs:00000000-76b6-4fcf-864d-1027d4038756",
+            "?portal=10.0.0.2&portal=[fd00::1]:4421",
+        ))
+        .unwrap();
+        let attach = NvmfAttach::try_from(&url).unwrap();
+        assert_eq!(
+            attach.portals,
+            vec![portal("fd00::1", 4421), portal("10.0.0.2", 4420)]
+        );
+    }
+}
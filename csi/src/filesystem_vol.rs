@@ -2,6 +2,7 @@
 
 use std::{fs, io::ErrorKind, path::PathBuf};
 
+use nix::sys::statvfs::statvfs;
 use tonic::{Code, Status};
 
 macro_rules! failure {
@@ -11,8 +12,10 @@ macro_rules! failure {
 
 use crate::{
     csi::{volume_capability::MountVolume, *},
-    format::prepare_device,
-    mount::{self, subset, ReadOnly},
+    format::{prepare_device, MkfsOptions},
+    mount::{self, ReadOnly},
+    quota,
+    repair,
 };
 
 pub async fn stage_fs_volume(
@@ -55,6 +58,84 @@ pub async fn stage_fs_volume(
         }
     };
 
+    let enforce_quota = match msg.volume_context.get(quota::QUOTA_PARAM) {
+        None => false,
+        Some(value) => value.parse::<bool>().map_err(|_| {
+            failure!(
+                Code::InvalidArgument,
+                "Failed to stage volume {}: invalid value for {}: {}",
+                volume_id,
+                quota::QUOTA_PARAM,
+                value
+            )
+        })?,
+    };
+
+    if enforce_quota && !quota::supported(&fstype) {
+        return Err(failure!(
+            Code::InvalidArgument,
+            "Failed to stage volume {}: quota is not supported on {}",
+            volume_id,
+            fstype
+        ));
+    }
+
+    let quota_limit = if enforce_quota {
+        let limit = quota::limit(&msg.volume_context).map_err(|error| {
+            failure!(
+                Code::InvalidArgument,
+                "Failed to stage volume {}: {}",
+                volume_id,
+                error
+            )
+        })?;
+        Some(limit)
+    } else {
+        None
+    };
+
+    let enable_repair = match msg.volume_context.get(repair::REPAIR_PARAM) {
+        None => false,
+        Some(value) => value.parse::<bool>().map_err(|_| {
+            failure!(
+                Code::InvalidArgument,
+                "Failed to stage volume {}: invalid value for {}: {}",
+                volume_id,
+                repair::REPAIR_PARAM,
+                value
+            )
+        })?,
+    };
+
+    if enable_repair && !repair::supported(&fstype) {
+        return Err(failure!(
+            Code::InvalidArgument,
+            "Failed to stage volume {}: repair is not supported on {}",
+            volume_id,
+            fstype
+        ));
+    }
+
+    let mkfs_options = MkfsOptions::parse(&fstype, &msg.volume_context)
+        .map_err(|error| {
+            failure!(
+                Code::InvalidArgument,
+                "Failed to stage volume {}: {}",
+                volume_id,
+                error
+            )
+        })?;
+
+    let context_options = mount::context_options(&fstype, &msg.volume_context)
+        .map_err(|error| {
+            failure!(
+                Code::InvalidArgument,
+                "Failed to stage volume {}: {}",
+                volume_id,
+                error
+            )
+        })?;
+
     if mount::find_mount(Some(&device_path), Some(&fs_staging_path)).is_some() {
         debug!(
             "Device {} is already mounted onto {}",
@@ -87,7 +168,9 @@ pub async fn stage_fs_volume(
                 ));
     }
 
-    if let Err(error) = prepare_device(&device_path, &fstype).await {
+    if let Err(error) =
+        prepare_device(&device_path, &fstype, &mkfs_options).await
+    {
         return Err(failure!(
             Code::Internal,
             "Failed to stage volume {}: error preparing device {}: {}",
@@ -97,22 +180,96 @@ pub async fn stage_fs_volume(
         ));
     }
 
+    let mut mount_flags = mnt.mount_flags.clone();
+
+    for option in mount::default_options(&fstype)
+        .into_iter()
+        .chain(context_options)
+    {
+        if !mount_flags.contains(&option) {
+            mount_flags.push(option);
+        }
+    }
+
+    if enforce_quota {
+        if let Err(error) = quota::prepare(&device_path, &fstype) {
+            return Err(failure!(
+                Code::Internal,
+                "Failed to stage volume {}: error enabling quota on device {}: {}",
+                volume_id,
+                device_path,
+                error
+            ));
+        }
+        mount_flags.push(quota::mount_option());
+    }
+
     debug!("Mounting device {} onto {}", device_path, fs_staging_path);
 
     if let Err(error) = mount::filesystem_mount(
         &device_path,
         &fs_staging_path,
         &fstype,
-        &mnt.mount_flags,
+        &mount_flags,
     ) {
-        return Err(failure!(
-            Code::Internal,
-            "Failed to stage volume {}: failed to mount device {} onto {}: {}",
-            volume_id,
-            device_path,
-            fs_staging_path,
-            error
-        ));
+        if !enable_repair || !repair::needed(&error) {
+            return Err(failure!(
+                Code::Internal,
+                "Failed to stage volume {}: failed to mount device {} onto {}: {}",
+                volume_id,
+                device_path,
+                fs_staging_path,
+                error
+            ));
+        }
+
+        warn!(
+            "Failed to mount device {} onto {}: {}, repairing filesystem",
+            device_path, fs_staging_path, error
+        );
+
+        let mut steps = vec![format!("mount failed: {}", error)];
+
+        let result = repair::repair(&device_path, &fstype).and_then(|done| {
+            info!("Repaired filesystem on device {}: {}", device_path, done);
+            steps.push(done);
+            mount::filesystem_mount(
+                &device_path,
+                &fs_staging_path,
+                &fstype,
+                &mount_flags,
+            )
+            .map_err(|error| format!("mount failed after repair: {}", error))
+        });
+
+        if let Err(error) = result {
+            steps.push(error.clone());
+            let message = format!(
+                "Failed to stage volume {}: failed to mount device {} onto {}: {}",
+                volume_id, device_path, fs_staging_path, error
+            );
+            error!("{}", message);
+            return Err(repair::status(Code::Internal, message, &steps));
+        }
+    }
+
+    if let Some(limit) = quota_limit {
+        if let Err(error) = quota::apply(
+            &fs_staging_path,
+            &fstype,
+            quota::project_id(volume_id),
+            limit,
+        ) {
+            let _ = mount::filesystem_unmount(&fs_staging_path);
+            return Err(failure!(
+                Code::Internal,
+                "Failed to stage volume {}: error setting quota on {}: {}",
+                volume_id,
+                fs_staging_path,
+                error
+            ));
+        }
+        info!("Quota enabled for volume {}", volume_id);
     }
 
     info!("Volume {} staged to {}", volume_id, fs_staging_path);
@@ -218,20 +375,93 @@ pub fn publish_fs_volume(
             ));
         }
 
-        if !subset(&mnt.mount_flags, &mount.options)
-            || msg.readonly != mount.options.readonly()
-        {
+        let current = mount::mount_options(target_path).unwrap_or_default();
+
+        if msg.readonly != current.mount.readonly() {
+            return Err(failure!(
+                    Code::AlreadyExists,
+                    "Failed to publish volume {}: directory {} is already mounted as \"{}\" but \"{}\" is requested",
+                    volume_id,
+                    target_path,
+                    if current.mount.readonly() { "ro" } else { "rw" },
+                    if msg.readonly { "ro" } else { "rw" }
+                ));
+        }
+
+        let missing: Vec<String> = mnt
+            .mount_flags
+            .iter()
+            .filter(|&entry| {
+                entry != "ro" && entry != "rw" && !current.contains(entry)
+            })
+            .cloned()
+            .collect();
+
+        if missing.is_empty() {
+            info!(
+                "Volume {} is already published to {}",
+                volume_id, target_path
+            );
+            return Ok(());
+        }
+
+        // only per mount flags can be changed on an existing bind mount,
+        // filesystem options are fixed when the volume is staged
+        let fixed: Vec<String> = missing
+            .iter()
+            .filter(|&entry| !mount::is_mount_flag(entry))
+            .cloned()
+            .collect();
+
+        if !fixed.is_empty() {
             return Err(failure!(
                     Code::AlreadyExists,
-                    "Failed to publish volume {}: directory {} is already mounted but with incompatible flags",
+                    "Failed to publish volume {}: directory {} is already mounted with options {} (filesystem: {}), cannot add options {}",
                     volume_id,
-                    target_path
+                    target_path,
+                    current.mount.join(","),
+                    current.superblock.join(","),
+                    fixed.join(",")
                 ));
         }
 
+        // remount with the union of the current and requested flags, where
+        // a requested atime flag replaces the current one
+        let replace_atime =
+            missing.iter().any(|entry| mount::is_atime_flag(entry));
+        let mut options: Vec<String> = current
+            .mount
+            .iter()
+            .filter(|&entry| {
+                mount::is_mount_flag(entry)
+                    && !(replace_atime && mount::is_atime_flag(entry))
+            })
+            .cloned()
+            .collect();
+        options.extend(missing.iter().cloned());
+
+        debug!(
+            "Remounting {} to add options {}",
+            target_path,
+            missing.join(",")
+        );
+
+        if let Err(error) = mount::bind_remount(&target_path, &options) {
+            return Err(failure!(
+                Code::Internal,
+                "Failed to publish volume {}: failed to remount {} with options {}: {}",
+                volume_id,
+                target_path,
+                options.join(","),
+                error
+            ));
+        }
+
         info!(
-            "Volume {} is already published to {}",
-            volume_id, target_path
+            "Volume {} is already published to {}, added options {}",
+            volume_id,
+            target_path,
+            missing.join(",")
         );
 
         return Ok(());
@@ -265,18 +495,33 @@ pub fn publish_fs_volume(
         ));
     }
 
-    if msg.readonly && !readonly {
-        let mut options = mnt.mount_flags.clone();
+    // flags applying to the mount itself must be set by remounting, a bind
+    // mount ignores them
+    let mut options: Vec<String> = mnt
+        .mount_flags
+        .iter()
+        .filter(|&entry| mount::is_mount_flag(entry) && entry != "rw")
+        .cloned()
+        .collect();
+
+    if msg.readonly && !options.readonly() {
         options.push(String::from("ro"));
+    }
 
-        debug!("Remounting {} as readonly", target_path);
+    if !options.is_empty() {
+        debug!(
+            "Remounting {} with options {}",
+            target_path,
+            options.join(",")
+        );
 
         if let Err(error) = mount::bind_remount(&target_path, &options) {
             let message = format!(
-                    "Failed to publish volume {}: failed to mount {} to {} as readonly: {}",
+                    "Failed to publish volume {}: failed to remount {} to {} with options {}: {}",
                     volume_id,
                     fs_staging_path,
                     target_path,
+                    options.join(","),
                     error
                 );
 
@@ -346,3 +591,52 @@ pub fn unpublish_fs_volume(
     info!("Volume {} unpublished from {}", volume_id, target_path);
     Ok(())
 }
+
+/// Return usage statistics (bytes and inodes) for a filesystem volume
+/// mounted at the given path.
+pub fn fs_volume_stats(
+    volume_id: &str,
+    volume_path: &str,
+) -> Result<Vec<VolumeUsage>, Status> {
+    if mount::find_mount(None, Some(volume_path)).is_none() {
+        return Err(failure!(
+            Code::NotFound,
+            "Failed to get stats for volume {}: no mount found at {}",
+            volume_id,
+            volume_path
+        ));
+    }
+
+    let stats = statvfs(volume_path).map_err(|error| {
+        failure!(
+            Code::Internal,
+            "Failed to get stats for volume {}: statfs on {} failed: {}",
+            volume_id,
+            volume_path,
+            error
+        )
+    })?;
+
+    let block_size = stats.fragment_size() as i64;
+    let total = stats.blocks() as i64 * block_size;
+    let available = stats.blocks_available() as i64 * block_size;
+    let used = (stats.blocks() - stats.blocks_free()) as i64 * block_size;
+
+    let inodes = stats.files() as i64;
+    let inodes_free = stats.files_free() as i64;
+
+    Ok(vec![
+        VolumeUsage {
+            total,
+            available,
+            used,
+            unit: volume_usage::Unit::Bytes as i32,
+        },
+        VolumeUsage {
+            total: inodes,
+            available: inodes_free,
+            used: inodes - inodes_free,
+            unit: volume_usage::Unit::Inodes as i32,
+        },
+    ])
+}
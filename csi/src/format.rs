@@ -1,12 +1,352 @@
 //! Utility function for formatting a device with filesystem
 
-use std::process::Command;
+use std::{collections::HashMap, process::Command};
 
 use blkid::probe::Probe;
 
+use crate::quota;
+
+/// Volume context (storage class) parameters passed on to mkfs.
+const INODE_SIZE_PARAM: &str = "inodeSize";
+const RESERVED_BLOCKS_PARAM: &str = "reservedBlocks";
+const AGCOUNT_PARAM: &str = "agCount";
+const LAZY_ITABLE_INIT_PARAM: &str = "lazyItableInit";
+const MKFS_OPTIONS_PARAM: &str = "mkfsOptions";
+
+/// Volumes up to this size have their inode tables initialized by mkfs,
+/// instead of by the kernel in the background after the first mount.
+const SMALL_VOLUME: u64 = 1024 * 1024 * 1024;
+
+/// Volumes of at least this size reserve less blocks for root by default,
+/// as 5% of a large volume is a lot of space nobody can use.
+const LARGE_VOLUME: u64 = 64 * 1024 * 1024 * 1024;
+const LARGE_VOLUME_RESERVED_BLOCKS: u8 = 1;
+
+/// Options for creating a filesystem, taken from the volume context.
+/// Options which are not set are left to mkfs, or to the per-size defaults.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct MkfsOptions {
+    /// size of an inode in bytes
+    inode_size: Option<u32>,
+    /// percentage of blocks reserved for root (ext only)
+    reserved_blocks: Option<u8>,
+    /// number of allocation groups (xfs only)
+    agcount: Option<u32>,
+    /// defer the initialization of inode tables to the kernel (ext only)
+    lazy_itable_init: Option<bool>,
+    /// further arguments for mkfs, which have been checked against the
+    /// options allowed for the filesystem
+    extra: Vec<String>,
+}
+
+/// The value an option of mkfs takes.
+enum OptionValue {
+    Flag,
+    Number,
+    /// one of the words
+    Word(&'static [&'static str]),
+    /// comma separated suboptions, each with a value or not, of which the
+    /// names are one of the words
+    SubOptions(&'static [&'static str]),
+}
+
+/// Return the value taken by an option of mkfs for the filesystem, if the
+/// option can be given. Options which name another device or change the
+/// identity of the filesystem, i.e. its label or uuid, are not allowed.
+fn mkfs_option(fstype: &str, option: &str) -> Option<OptionValue> {
+    use OptionValue::*;
+
+    let value = match (fstype, option) {
+        (fs, "-b") | (fs, "-i") | (fs, "-N") | (fs, "-m") if is_ext(fs) => {
+            Number
+        }
+        (fs, "-j") if is_ext(fs) => Flag,
+        (fs, "-E") if is_ext(fs) => SubOptions(&[
+            "lazy_itable_init",
+            "lazy_journal_init",
+            "discard",
+            "nodiscard",
+            "stride",
+            "stripe_width",
+            "stripe-width",
+            "packed_meta_blocks",
+            "num_backup_sb",
+        ]),
+        (fs, "-O") if is_ext(fs) => SubOptions(&[
+            "64bit",
+            "dir_index",
+            "dir_nlink",
+            "extent",
+            "extra_isize",
+            "filetype",
+            "flex_bg",
+            "has_journal",
+            "huge_file",
+            "inline_data",
+            "large_file",
+            "metadata_csum",
+            "project",
+            "quota",
+            "sparse_super",
+            "sparse_super2",
+            "uninit_bg",
+        ]),
+        (fs, "-T") if is_ext(fs) => Word(&[
+            "default",
+            "small",
+            "floppy",
+            "big",
+            "huge",
+            "news",
+            "largefile",
+            "largefile4",
+        ]),
+        ("xfs", "-b") => SubOptions(&["size"]),
+        ("xfs", "-d") => {
+            SubOptions(&["agcount", "agsize", "su", "sw", "sunit", "swidth"])
+        }
+        ("xfs", "-i") => SubOptions(&["size", "maxpct", "align", "sparse"]),
+        ("xfs", "-l") => {
+            SubOptions(&["size", "lazy-count", "su", "sunit", "version"])
+        }
+        ("xfs", "-m") => SubOptions(&[
+            "crc",
+            "finobt",
+            "reflink",
+            "rmapbt",
+            "bigtime",
+            "inobtcount",
+        ]),
+        ("xfs", "-n") => SubOptions(&["size", "ftype"]),
+        ("xfs", "-K") => Flag,
+        ("btrfs", "-n") | ("btrfs", "-s") => Number,
+        ("btrfs", "-O") => {
+            SubOptions(&["mixed-bg", "extref", "raid56", "skinny-metadata"])
+        }
+        ("btrfs", "-R") => SubOptions(&["quota", "free-space-tree"]),
+        ("btrfs", "-K") => Flag,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Check the value of an option of mkfs.
+fn check_value(kind: &OptionValue, value: &str) -> bool {
+    let safe = |value: &str| {
+        !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+    };
+
+    match kind {
+        OptionValue::Flag => false,
+        OptionValue::Number => value.parse::<u64>().is_ok(),
+        OptionValue::Word(words) => words.contains(&value),
+        OptionValue::SubOptions(names) => value.split(',').all(|option| {
+            let mut parts = option.splitn(2, '=');
+            // a feature is disabled by a leading ^
+            let name = parts.next().unwrap_or_default().trim_start_matches('^');
+            names.contains(&name) && parts.next().map_or(true, safe)
+        }),
+    }
+}
+
+/// Parse the further options for mkfs, separated by white space. The value
+/// of an option can follow it either directly or as the next argument.
+fn parse_extra(fstype: &str, options: &str) -> Result<Vec<String>, String> {
+    let invalid = |option: &str| {
+        Err(format!(
+            "invalid value for {}: {} is not allowed on {}",
+            MKFS_OPTIONS_PARAM, option, fstype
+        ))
+    };
+
+    let mut args = Vec::new();
+    let mut words = options.split_whitespace();
+
+    while let Some(word) = words.next() {
+        if !word.starts_with('-') || !word.is_char_boundary(2) {
+            return invalid(word);
+        }
+        let (option, attached) = word.split_at(2);
+        let kind = match mkfs_option(fstype, option) {
+            Some(kind) => kind,
+            None => return invalid(option),
+        };
+
+        args.push(option.to_string());
+        if let OptionValue::Flag = kind {
+            if !attached.is_empty() {
+                return invalid(word);
+            }
+            continue;
+        }
+
+        let value = if attached.is_empty() {
+            words.next().unwrap_or_default()
+        } else {
+            attached
+        };
+        if !check_value(&kind, value) {
+            return invalid(&format!("{} {}", option, value));
+        }
+        args.push(value.to_string());
+    }
+
+    Ok(args)
+}
+
+fn is_ext(fstype: &str) -> bool {
+    fstype == "ext3" || fstype == "ext4"
+}
+
+fn parse_param<T: std::str::FromStr>(
+    context: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<T>, String> {
+    match context.get(name) {
+        None => Ok(None),
+        Some(value) => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| format!("invalid value for {}: {}", name, value)),
+    }
+}
+
+impl MkfsOptions {
+    /// Parse and validate the mkfs options in the volume context for the
+    /// given filesystem type.
+    pub(crate) fn parse(
+        fstype: &str,
+        context: &HashMap<String, String>,
+    ) -> Result<Self, String> {
+        let options = Self {
+            inode_size: parse_param(context, INODE_SIZE_PARAM)?,
+            reserved_blocks: parse_param(context, RESERVED_BLOCKS_PARAM)?,
+            agcount: parse_param(context, AGCOUNT_PARAM)?,
+            lazy_itable_init: parse_param(context, LAZY_ITABLE_INIT_PARAM)?,
+            extra: match context.get(MKFS_OPTIONS_PARAM) {
+                Some(options) => parse_extra(fstype, options)?,
+                None => Vec::new(),
+            },
+        };
+
+        let unsupported = |name: &str| {
+            Err(format!("{} is not supported on {}", name, fstype))
+        };
+
+        if let Some(size) = options.inode_size {
+            let range = match fstype {
+                "xfs" => 256 ..= 2048,
+                fs if is_ext(fs) => 128 ..= 4096,
+                _ => return unsupported(INODE_SIZE_PARAM),
+            };
+            if !size.is_power_of_two() || !range.contains(&size) {
+                return Err(format!(
+                    "invalid value for {}: {} is not a power of two between \
+                     {} and {}",
+                    INODE_SIZE_PARAM,
+                    size,
+                    range.start(),
+                    range.end()
+                ));
+            }
+        }
+
+        if let Some(percent) = options.reserved_blocks {
+            if !is_ext(fstype) {
+                return unsupported(RESERVED_BLOCKS_PARAM);
+            }
+            if percent > 50 {
+                return Err(format!(
+                    "invalid value for {}: {} is more than 50 percent",
+                    RESERVED_BLOCKS_PARAM, percent
+                ));
+            }
+        }
+
+        if let Some(count) = options.agcount {
+            if fstype != "xfs" {
+                return unsupported(AGCOUNT_PARAM);
+            }
+            if count == 0 {
+                return Err(format!(
+                    "invalid value for {}: must be at least 1",
+                    AGCOUNT_PARAM
+                ));
+            }
+        }
+
+        if options.lazy_itable_init.is_some() && !is_ext(fstype) {
+            return unsupported(LAZY_ITABLE_INIT_PARAM);
+        }
+
+        Ok(options)
+    }
+
+    /// Return the arguments for mkfs of a device with the given size,
+    /// filling in the defaults for options which have not been set.
+    /// The further options come last, so that they take precedence.
+    fn args(&self, fstype: &str, size: u64) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if is_ext(fstype) {
+            if let Some(size) = self.inode_size {
+                args.push(format!("-I{}", size));
+            }
+
+            let reserved = self.reserved_blocks.or_else(|| {
+                if size >= LARGE_VOLUME {
+                    Some(LARGE_VOLUME_RESERVED_BLOCKS)
+                } else {
+                    None
+                }
+            });
+            if let Some(percent) = reserved {
+                args.push(format!("-m{}", percent));
+            }
+
+            let lazy = self.lazy_itable_init.or_else(|| {
+                if size <= SMALL_VOLUME {
+                    Some(false)
+                } else {
+                    None
+                }
+            });
+            if let Some(lazy) = lazy {
+                args.push(format!("-Elazy_itable_init={}", lazy as u8));
+            }
+        }
+
+        if fstype == "xfs" {
+            if let Some(size) = self.inode_size {
+                args.push(format!("-isize={}", size));
+            }
+            if let Some(count) = self.agcount {
+                args.push(format!("-dagcount={}", count));
+            }
+        }
+
+        args.extend(self.extra.iter().cloned());
+        args
+    }
+}
+
+// Filesystem specific arguments for creating a filesystem
+// non-interactively on the whole device.
+fn mkfs_args(fstype: &str) -> &'static [&'static str] {
+    match fstype {
+        "ext3" | "ext4" => &["-F"],
+        "btrfs" => &["-f"],
+        _ => &[],
+    }
+}
+
 pub(crate) async fn prepare_device(
     device: &str,
     fstype: &str,
+    options: &MkfsOptions,
 ) -> Result<(), String> {
     debug!("Probing device {}", device);
 
@@ -24,8 +364,11 @@ pub(crate) async fn prepare_device(
 
     debug!("Creating new filesystem ({}) on device {}", fstype, device);
 
+    let size = quota::device_size(device)?;
     let binary = format!("mkfs.{}", fstype);
     let output = Command::new(&binary)
+        .args(mkfs_args(fstype))
+        .args(options.args(fstype, size))
         .arg(device)
         .output()
         .map_err(|error| format!("failed to execute {}: {}", binary, error))?;
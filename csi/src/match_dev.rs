@@ -73,3 +73,12 @@ pub(super) fn match_nvmf_device<'a>(
 
     Some(devname)
 }
+
+pub(super) fn match_nvmf_wwn(device: &Device) -> Option<(&str, &str)> {
+    require!("Mayastor NVMe controller" == device.property_value("ID_MODEL"));
+
+    require!(let devname = device.property_value("DEVNAME"));
+    require!(let wwn = device.property_value("ID_WWN"));
+
+    Some((devname, wwn))
+}
@@ -1,6 +1,6 @@
 //! Utility functions for mounting and unmounting filesystems.
 
-use std::{collections::HashSet, io::Error};
+use std::{collections::HashMap, env, fs, io::Error};
 
 use proc_mounts::MountIter;
 use sys_mount::{unmount, FilesystemType, Mount, MountFlags, UnmountFlags};
@@ -76,49 +76,291 @@ pub fn find_mount(
     found.map(MountInfo::from)
 }
 
-/// Check if options in "first" are also present in "second",
-/// but exclude values "ro" and "rw" from the comparison.
-pub(super) fn subset(first: &[String], second: &[String]) -> bool {
-    let set: HashSet<&String> = second.iter().collect();
-    for entry in first {
-        if entry == "ro" {
+/// Mount options of a mounted filesystem as found in /proc/self/mountinfo,
+/// which unlike /proc/mounts keeps the options of the mount separate from
+/// the options of the filesystem (superblock) that is mounted.
+#[derive(Debug, Default)]
+pub struct MountOptions {
+    pub mount: Vec<String>,
+    pub superblock: Vec<String>,
+}
+
+impl MountOptions {
+    /// Check if an option is set either on the mount or the filesystem.
+    pub fn contains(&self, option: &str) -> bool {
+        self.mount.iter().any(|entry| entry == option)
+            || self.superblock.iter().any(|entry| entry == option)
+    }
+}
+
+// Undo the octal escaping of spaces, tabs, newlines and backslashes
+// in paths found in /proc/self/mountinfo.
+fn unescape(path: &str) -> String {
+    path.replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+fn split_options(options: &str) -> Vec<String> {
+    options.split(',').map(String::from).collect()
+}
+
+/// Return the options of the (topmost) mount on target.
+pub fn mount_options(target: &str) -> Option<MountOptions> {
+    let content = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    let mut found: Option<MountOptions> = None;
+
+    // Format of each line (see proc(5)), the number of optional fields
+    // preceding the "-" separator is variable:
+    // 36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 6 || unescape(fields[4]) != target {
             continue;
         }
-        if entry == "rw" {
-            continue;
+
+        let superblock = fields
+            .iter()
+            .position(|&field| field == "-")
+            .and_then(|index| fields.get(index + 3))
+            .map_or_else(Vec::new, |options| split_options(options));
+
+        found = Some(MountOptions {
+            mount: split_options(fields[5]),
+            superblock,
+        });
+    }
+
+    found
+}
+
+// Filesystems we know how to create and mount, in order of preference.
+// The first one that is available is the default filesystem.
+const FILESYSTEMS: [&str; 4] = ["xfs", "ext4", "ext3", "btrfs"];
+
+// Check if the mkfs binary for a filesystem can be found in PATH.
+fn mkfs_available(fstype: &str) -> bool {
+    let binary = format!("mkfs.{}", fstype);
+    env::var_os("PATH").map_or(false, |paths| {
+        env::split_paths(&paths).any(|dir| dir.join(&binary).is_file())
+    })
+}
+
+/// Return supported filesystems, i.e. the known filesystems that we are
+/// able to create on this node.
+pub fn probe_filesystems() -> Vec<String> {
+    let found: Vec<String> = FILESYSTEMS
+        .iter()
+        .filter(|fstype| mkfs_available(fstype))
+        .map(|fstype| String::from(*fstype))
+        .collect();
+
+    if found.is_empty() {
+        warn!("No mkfs binaries found, assuming xfs and ext4 are available");
+        return vec![String::from("xfs"), String::from("ext4")];
+    }
+
+    info!("Supported filesystems: {}", found.join(","));
+    found
+}
+
+/// Return the mount options that are applied by default to a filesystem,
+/// in addition to those requested.
+pub fn default_options(fstype: &str) -> Vec<String> {
+    let options: &[&str] = match fstype {
+        // ext3 is mounted by the ext4 driver on recent kernels, make sure
+        // we keep the ext3 journalling semantics
+        "ext3" => &["data=ordered"],
+        // avoid metadata copy-on-write for every read access
+        "btrfs" => &["noatime"],
+        _ => &[],
+    };
+    options.iter().map(|option| String::from(*option)).collect()
+}
+
+/// Volume context (storage class) parameter with further mount options.
+pub const MOUNT_OPTIONS_PARAM: &str = "mountOptions";
+
+/// The value a mount option takes.
+enum OptionValue {
+    Flag,
+    Number,
+    /// a number of bytes, optionally with a unit, i.e. 64k
+    Size,
+    /// one of the words
+    Word(&'static [&'static str]),
+}
+
+/// Return the value taken by a mount option of the filesystem, if the
+/// option can be given in the volume context. Options which could be used
+/// to get around the restrictions of the node, i.e. the selinux context or
+/// the owner of the files, and the options which are set by the plugin
+/// itself, i.e. ro and the quota options, are not allowed.
+fn context_option(fstype: &str, name: &str) -> Option<OptionValue> {
+    use OptionValue::*;
+
+    let ext = fstype == "ext3" || fstype == "ext4";
+    let value = match (fstype, name) {
+        (_, "noatime")
+        | (_, "relatime")
+        | (_, "nodiratime")
+        | (_, "strictatime")
+        | (_, "lazytime")
+        | (_, "nolazytime")
+        | (_, "sync")
+        | (_, "dirsync")
+        | (_, "nosuid")
+        | (_, "nodev")
+        | (_, "noexec")
+        | (_, "discard")
+        | (_, "nodiscard") => Flag,
+        (_, "data") if ext => Word(&["ordered", "writeback", "journal"]),
+        (_, "errors") if ext => Word(&["remount-ro", "continue"]),
+        (_, "barrier") if ext => Word(&["0", "1"]),
+        (_, "commit")
+        | (_, "init_itable")
+        | (_, "max_batch_time")
+        | (_, "min_batch_time")
+        | (_, "stripe")
+            if ext =>
+        {
+            Number
+        }
+        (_, "nobarrier")
+        | (_, "journal_checksum")
+        | (_, "journal_async_commit")
+        | (_, "auto_da_alloc")
+        | (_, "noauto_da_alloc")
+        | (_, "delalloc")
+        | (_, "nodelalloc")
+        | (_, "noinit_itable")
+        | (_, "dioread_lock")
+        | (_, "dioread_nolock")
+            if ext =>
+        {
+            Flag
         }
-        if set.get(entry).is_none() {
-            return false;
+        ("xfs", "logbufs") => Number,
+        ("xfs", "logbsize") | ("xfs", "allocsize") => Size,
+        ("xfs", "inode32")
+        | ("xfs", "inode64")
+        | ("xfs", "largeio")
+        | ("xfs", "nolargeio")
+        | ("xfs", "noalign")
+        | ("xfs", "swalloc")
+        | ("xfs", "wsync")
+        | ("xfs", "nouuid")
+        | ("xfs", "filestreams") => Flag,
+        ("btrfs", "compress") | ("btrfs", "compress-force") => {
+            Word(&["zlib", "lzo", "zstd", "no"])
         }
+        ("btrfs", "space_cache") => Word(&["v1", "v2"]),
+        ("btrfs", "commit") => Number,
+        ("btrfs", "ssd")
+        | ("btrfs", "nossd")
+        | ("btrfs", "ssd_spread")
+        | ("btrfs", "autodefrag")
+        | ("btrfs", "noautodefrag")
+        | ("btrfs", "flushoncommit")
+        | ("btrfs", "noflushoncommit") => Flag,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Parse and validate the comma separated mount options in the volume
+/// context for the given filesystem type.
+pub fn context_options(
+    fstype: &str,
+    context: &HashMap<String, String>,
+) -> Result<Vec<String>, String> {
+    let options = match context.get(MOUNT_OPTIONS_PARAM) {
+        Some(options) => options,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut list = Vec::new();
+
+    for option in options.split(',').map(str::trim) {
+        let mut parts = option.splitn(2, '=');
+        let name = parts.next().unwrap_or_default();
+        let value = parts.next();
+
+        let valid = match (context_option(fstype, name), value) {
+            (Some(OptionValue::Flag), None) => true,
+            (Some(OptionValue::Number), Some(value)) => {
+                value.parse::<u64>().is_ok()
+            }
+            (Some(OptionValue::Size), Some(value)) => {
+                let digits = value.trim_end_matches(|c| "kKmMgG".contains(c));
+                value.len() - digits.len() <= 1 && digits.parse::<u64>().is_ok()
+            }
+            (Some(OptionValue::Word(words)), Some(value)) => {
+                words.contains(&value)
+            }
+            _ => false,
+        };
+
+        if !valid {
+            return Err(format!(
+                "invalid value for {}: {} is not allowed on {}",
+                MOUNT_OPTIONS_PARAM, option, fstype
+            ));
+        }
+        list.push(option.to_string());
+    }
+
+    Ok(list)
+}
+
+// Return the mount flag for options that are implemented by the VFS
+// rather than being passed on to the filesystem.
+fn mount_flag(option: &str) -> Option<MountFlags> {
+    match option {
+        "ro" => Some(MountFlags::RDONLY),
+        "nosuid" => Some(MountFlags::NOSUID),
+        "nodev" => Some(MountFlags::NODEV),
+        "noexec" => Some(MountFlags::NOEXEC),
+        "noatime" => Some(MountFlags::NOATIME),
+        "nodiratime" => Some(MountFlags::NODIRATIME),
+        "relatime" => Some(MountFlags::RELATIME),
+        "strictatime" => Some(MountFlags::STRICTATIME),
+        "sync" => Some(MountFlags::SYNCHRONOUS),
+        "dirsync" => Some(MountFlags::DIRSYNC),
+        _ => None,
     }
-    true
 }
 
-/// Return supported filesystems.
-pub fn probe_filesystems() -> Vec<String> {
-    vec![String::from("xfs"), String::from("ext4")]
+/// Check if an option is a per mount option, which (unlike filesystem
+/// options) can be changed by remounting a bind mount.
+pub(super) fn is_mount_flag(option: &str) -> bool {
+    option == "rw" || mount_flag(option).is_some()
+}
+
+/// Check if an option controls access time updates.
+pub(super) fn is_atime_flag(option: &str) -> bool {
+    matches!(option, "noatime" | "relatime" | "strictatime")
 }
 
 // Utility function to transform a vector of options
 // to the format required by sys_mount::Mount::new()
-fn parse(options: &[String]) -> (bool, String) {
+fn parse(options: &[String]) -> (MountFlags, String) {
     let mut list: Vec<&str> = Vec::new();
-    let mut readonly: bool = false;
+    let mut flags = MountFlags::empty();
 
     for entry in options {
-        if entry == "ro" {
-            readonly = true;
-            continue;
-        }
-
         if entry == "rw" {
             continue;
         }
 
-        list.push(entry);
+        match mount_flag(entry) {
+            Some(flag) => flags.insert(flag),
+            None => list.push(entry),
+        }
     }
 
-    (readonly, list.join(","))
+    (flags, list.join(","))
 }
 
 // Utility function to wrap a string in an Option.
@@ -153,13 +395,7 @@ pub fn filesystem_mount(
     fstype: &str,
     options: &[String],
 ) -> Result<Mount, Error> {
-    let mut flags = MountFlags::empty();
-
-    let (readonly, value) = parse(options);
-
-    if readonly {
-        flags.insert(MountFlags::RDONLY);
-    }
+    let (flags, value) = parse(options);
 
     let mount = Mount::new(
         device,
@@ -225,16 +461,9 @@ pub fn bind_mount(
 /// Bind remount a path to modify mount options.
 /// Assumes that target has already been bind mounted.
 pub fn bind_remount(target: &str, options: &[String]) -> Result<Mount, Error> {
-    let mut flags = MountFlags::empty();
-
-    let (readonly, value) = parse(options);
+    let (mut flags, value) = parse(options);
 
     flags.insert(MountFlags::BIND);
-
-    if readonly {
-        flags.insert(MountFlags::RDONLY);
-    }
-
     flags.insert(MountFlags::REMOUNT);
 
     let mount = Mount::new(
@@ -1,4 +1,10 @@
-use std::{boxed::Box, path::Path, time::Duration, vec::Vec};
+use std::{
+    boxed::Box,
+    collections::HashMap,
+    path::Path,
+    time::Duration,
+    vec::Vec,
+};
 
 use tonic::{Code, Request, Response, Status};
 
@@ -11,13 +17,18 @@ use glob::glob;
 use uuid::Uuid;
 
 use crate::{
-    block_vol::{publish_block_volume, unpublish_block_volume},
+    block_vol::{
+        block_volume_stats,
+        publish_block_volume,
+        unpublish_block_volume,
+    },
     csi::{
         volume_capability::{access_mode::Mode, AccessType},
         *,
     },
     dev::Device,
     filesystem_vol::{
+        fs_volume_stats,
         publish_fs_volume,
         stage_fs_volume,
         unpublish_fs_volume,
@@ -31,8 +42,11 @@ pub struct Node {
     pub filesystems: Vec<String>,
 }
 
-const ATTACH_TIMEOUT_INTERVAL: Duration = Duration::from_millis(100);
-const ATTACH_RETRIES: u32 = 100;
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Topology key of the node, which the control plane uses to constrain the
+/// volumes with local replicas to the node.
+const TOPOLOGY_NODE_KEY: &str = "mayastor.openebs.io/node";
 
 // Determine if given access mode in conjunction with ro mount flag makes
 // sense or not. If access mode is not supported or the combination does
@@ -46,7 +60,7 @@ const ATTACH_RETRIES: u32 = 100;
 // publish or stage request makes sense.
 
 /// Check that the access_mode from VolumeCapability is consistent with
-/// the readonly status
+/// the readonly status and access type
 fn check_access_mode(
     volume_capability: &Option<VolumeCapability>,
     readonly: bool,
@@ -64,13 +78,18 @@ fn check_access_mode(
                         }
                         Err(format!("volume capability: invalid combination of access mode ({:?}) and mount flag (rw)", mode))
                     }
+                    // the nexus is published to all nodes for a raw block
+                    // volume, whose users coordinate the writes themselves,
+                    // no filesystem can be mounted on more than one node
+                    Mode::MultiNodeMultiWriter => {
+                        match &capability.access_type {
+                            Some(AccessType::Block(_)) => Ok(()),
+                            _ => Err(format!("volume capability: access mode {:?} is only supported for raw block volumes", mode)),
+                        }
+                    }
                     Mode::Unknown => Err(String::from(
                         "volume capability: unknown access mode",
                     )),
-                    _ => Err(format!(
-                        "volume capability: unsupported access mode: {:?}",
-                        mode
-                    )),
                 },
                 None => Err(format!(
                     "volume capability: invalid access mode: {}",
@@ -107,15 +126,20 @@ impl node_server::Node for Node {
         let max_volumes_per_node =
             glob("/dev/nbd*").expect("Invalid glob pattern").count() as i64;
 
+        let mut segments = HashMap::new();
+        segments.insert(TOPOLOGY_NODE_KEY.to_string(), self.node_name.clone());
+
         debug!(
-            "NodeGetInfo request: ID={}, max volumes={}",
-            node_id, max_volumes_per_node,
+            "NodeGetInfo request: ID={}, max volumes={}, topology={:?}",
+            node_id, max_volumes_per_node, segments,
         );
 
         Ok(Response::new(NodeGetInfoResponse {
             node_id,
             max_volumes_per_node,
-            accessible_topology: None,
+            accessible_topology: Some(Topology {
+                segments,
+            }),
         }))
     }
 
@@ -123,11 +147,14 @@ impl node_server::Node for Node {
         &self,
         _request: Request<NodeGetCapabilitiesRequest>,
     ) -> Result<Response<NodeGetCapabilitiesResponse>, Status> {
-        let caps = vec![node_service_capability::rpc::Type::StageUnstageVolume];
+        let caps = vec![
+            node_service_capability::rpc::Type::StageUnstageVolume,
+            node_service_capability::rpc::Type::GetVolumeStats,
+        ];
 
         debug!("NodeGetCapabilities request: {:?}", caps);
 
-        // We don't support stage/unstage and expand volume rpcs
+        // We don't support expand volume rpcs
         Ok(Response::new(NodeGetCapabilitiesResponse {
             capabilities: caps
                 .into_iter()
@@ -293,30 +320,54 @@ impl node_server::Node for Node {
         Ok(Response::new(NodeUnpublishVolumeResponse {}))
     }
 
-    /// Get volume stats method is currently not implemented,
-    /// although it's simple to do.
-    ///
-    /// TODO: Just read the data about capacity/used space
-    /// inodes/bytes from the system using the mountpoint.
+    /// Return usage statistics for a published or staged volume.
+    /// For filesystem volumes this is the capacity, used and available
+    /// bytes and inodes as reported by statfs on the mountpoint, for block
+    /// volumes only the total size of the device is reported.
     async fn node_get_volume_stats(
         &self,
         request: Request<NodeGetVolumeStatsRequest>,
     ) -> Result<Response<NodeGetVolumeStatsResponse>, Status> {
         let msg = request.into_inner();
+
         trace!("node_get_volume_stats {:?}", msg);
 
-        /*
+        if msg.volume_id.is_empty() {
+            return Err(failure!(
+                Code::InvalidArgument,
+                "Failed to get volume stats: missing volume id"
+            ));
+        }
+
+        if msg.volume_path.is_empty() {
+            return Err(failure!(
+                Code::InvalidArgument,
+                "Failed to get stats for volume {}: missing volume path",
+                &msg.volume_id
+            ));
+        }
+
+        let volume_path = Path::new(&msg.volume_path);
+        if !volume_path.exists() {
+            return Err(failure!(
+                Code::NotFound,
+                "Failed to get stats for volume {}: path {} does not exist",
+                &msg.volume_id,
+                &msg.volume_path
+            ));
+        }
+
+        let usage = if volume_path.is_dir() {
+            fs_volume_stats(&msg.volume_id, &msg.volume_path)?
+        } else {
+            block_volume_stats(&msg.volume_id, &msg.volume_path)?
+        };
+
+        debug!("Volume {} usage: {:?}", &msg.volume_id, usage);
+
         Ok(Response::new(NodeGetVolumeStatsResponse {
-            usage: vec![VolumeUsage {
-                total: 0 as i64,
-                unit: volume_usage::Unit::Bytes as i32,
-                available: 0,
-                used: 0,
-            }],
+            usage,
         }))
-        */
-        error!("Unimplemented {:?}", msg);
-        Err(Status::new(Code::Unimplemented, "Method not implemented"))
     }
 
     async fn node_expand_volume(
@@ -388,7 +439,7 @@ impl node_server::Node for Node {
             )
         })?;
 
-        let device_path = match device.find().await.map_err(|error| {
+        let mut found = device.find().await.map_err(|error| {
             failure!(
             Code::Internal,
             "Failed to stage volume {}: error locating device for URI {}: {}",
@@ -396,7 +447,26 @@ impl node_server::Node for Node {
             uri,
             error
         )
-        })? {
+        })?;
+
+        // A device with missing paths is attached again,
+        // which reconnects only the paths that are missing.
+        if found.is_some()
+            && !device.ready().await.map_err(|error| {
+                failure!(
+                    Code::Internal,
+                    "Failed to stage volume {}: error checking paths of device for URI {}: {}",
+                    &msg.volume_id,
+                    uri,
+                    error
+                )
+            })?
+        {
+            debug!("Not all paths of volume {} are connected", &msg.volume_id);
+            found = None;
+        }
+
+        let device_path = match found {
             Some(devpath) => devpath,
             None => {
                 debug!("Attaching volume {}", &msg.volume_id);
@@ -411,20 +481,16 @@ impl node_server::Node for Node {
                     ));
                 }
 
-                Device::wait_for_device(
-                    device,
-                    ATTACH_TIMEOUT_INTERVAL,
-                    ATTACH_RETRIES,
-                )
-                .await
-                .map_err(|error| {
-                    failure!(
-                        Code::Unavailable,
-                        "Failed to stage volume {}: {}",
-                        &msg.volume_id,
-                        error
-                    )
-                })?
+                Device::wait_for_device(device, ATTACH_TIMEOUT)
+                    .await
+                    .map_err(|error| {
+                        failure!(
+                            Code::Unavailable,
+                            "Failed to stage volume {}: {}",
+                            &msg.volume_id,
+                            error
+                        )
+                    })?
             }
         };
 
@@ -442,7 +508,12 @@ impl node_server::Node for Node {
                     .await?;
             }
             AccessType::Block(_) => {
-                // block volumes are not staged
+                // block volumes are not staged, the device is bind mounted
+                // directly on publish
+                info!(
+                    "Volume {} attached as block device {}",
+                    &msg.volume_id, device_path
+                );
             }
         }
         Ok(Response::new(NodeStageVolumeResponse {}))
@@ -0,0 +1,154 @@
+//! Implementation of the private gRPC service of the node plugin, which is
+//! used by the mayastor control plane.
+//!
+//! The control plane freezes the filesystem of a volume right before it asks
+//! the nexus to create a snapshot and thaws it right after. While frozen,
+//! the filesystem has all its dirty data written out and new writes block,
+//! which makes the snapshot filesystem consistent.
+
+use std::{fs::File, os::unix::io::AsRawFd};
+
+use nix::errno::Errno;
+use tonic::{Code, Request, Response, Status};
+use uuid::Uuid;
+
+macro_rules! failure {
+    (Code::$code:ident, $msg:literal) => {{ error!($msg); Status::new(Code::$code, $msg) }};
+    (Code::$code:ident, $fmt:literal $(,$args:expr)+) => {{ let message = format!($fmt $(,$args)+); error!("{}", message); Status::new(Code::$code, message) }};
+}
+
+use crate::{
+    dev::Device,
+    mayastornodeplugin::{
+        mayastor_node_plugin_server::MayastorNodePlugin,
+        FreezeVolumeReply,
+        FreezeVolumeRequest,
+        ThawVolumeReply,
+        ThawVolumeRequest,
+    },
+    mount,
+};
+
+nix::ioctl_readwrite!(fifreeze, b'X', 119, libc::c_int);
+nix::ioctl_readwrite!(fithaw, b'X', 120, libc::c_int);
+
+#[derive(Clone, Debug)]
+pub struct NodePlugin {}
+
+/// Return a mount point of the filesystem on the device of the volume.
+async fn find_mountpoint(volume_id: &str) -> Result<String, Status> {
+    let uuid = Uuid::parse_str(volume_id).map_err(|error| {
+        failure!(
+            Code::InvalidArgument,
+            "Volume {} is not a valid UUID: {}",
+            volume_id,
+            error
+        )
+    })?;
+
+    let device = Device::lookup(&uuid)
+        .await
+        .map_err(|error| {
+            failure!(
+                Code::Internal,
+                "Failed to locate device of volume {}: {}",
+                volume_id,
+                error
+            )
+        })?
+        .ok_or_else(|| {
+            failure!(
+                Code::NotFound,
+                "Volume {} is not attached to this node",
+                volume_id
+            )
+        })?;
+
+    // any mount of the filesystem will do, freezing applies to the
+    // filesystem rather than to a single mount of it
+    match mount::find_mount(Some(&device.devname()), None) {
+        Some(mount) => Ok(mount.dest),
+        None => Err(failure!(
+            Code::FailedPrecondition,
+            "Volume {} has no mounted filesystem",
+            volume_id
+        )),
+    }
+}
+
+#[tonic::async_trait]
+impl MayastorNodePlugin for NodePlugin {
+    async fn freeze_volume(
+        &self,
+        request: Request<FreezeVolumeRequest>,
+    ) -> Result<Response<FreezeVolumeReply>, Status> {
+        let volume_id = request.into_inner().volume_id;
+        let mountpoint = find_mountpoint(&volume_id).await?;
+
+        debug!("Freezing volume {} mounted on {}", volume_id, mountpoint);
+
+        let file = File::open(&mountpoint).map_err(|error| {
+            failure!(
+                Code::Internal,
+                "Failed to freeze volume {}: failed to open {}: {}",
+                volume_id,
+                mountpoint,
+                error
+            )
+        })?;
+
+        match unsafe { fifreeze(file.as_raw_fd(), &mut 0) } {
+            Ok(_) => info!("Volume {} frozen", volume_id),
+            Err(nix::Error::Sys(Errno::EBUSY)) => {
+                debug!("Volume {} is frozen already", volume_id)
+            }
+            Err(error) => {
+                return Err(failure!(
+                    Code::Internal,
+                    "Failed to freeze volume {}: {}",
+                    volume_id,
+                    error
+                ))
+            }
+        }
+
+        Ok(Response::new(FreezeVolumeReply {}))
+    }
+
+    async fn thaw_volume(
+        &self,
+        request: Request<ThawVolumeRequest>,
+    ) -> Result<Response<ThawVolumeReply>, Status> {
+        let volume_id = request.into_inner().volume_id;
+        let mountpoint = find_mountpoint(&volume_id).await?;
+
+        debug!("Thawing volume {} mounted on {}", volume_id, mountpoint);
+
+        let file = File::open(&mountpoint).map_err(|error| {
+            failure!(
+                Code::Internal,
+                "Failed to thaw volume {}: failed to open {}: {}",
+                volume_id,
+                mountpoint,
+                error
+            )
+        })?;
+
+        match unsafe { fithaw(file.as_raw_fd(), &mut 0) } {
+            Ok(_) => info!("Volume {} thawed", volume_id),
+            Err(nix::Error::Sys(Errno::EINVAL)) => {
+                debug!("Volume {} is not frozen", volume_id)
+            }
+            Err(error) => {
+                return Err(failure!(
+                    Code::Internal,
+                    "Failed to thaw volume {}: {}",
+                    volume_id,
+                    error
+                ))
+            }
+        }
+
+        Ok(Response::new(ThawVolumeReply {}))
+    }
+}
@@ -0,0 +1,336 @@
+//! Utility functions for limiting a staged filesystem with project quota.
+//!
+//! Thin provisioned replicas only allocate space from the pool as it is
+//! written to. Enabling a project quota on the root of the filesystem makes
+//! the filesystem refuse writes beyond the capacity of the volume, instead of
+//! the pool running out of space underneath it. The capacity is passed in
+//! the volume context by the controller, as the device may be larger than
+//! the volume.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Seek, SeekFrom},
+    process::Command,
+};
+
+/// Volume context (storage class) parameter that enables quota.
+pub(crate) const QUOTA_PARAM: &str = "quota";
+
+/// Volume context parameter with the capacity of the volume in bytes, set
+/// by the controller when the volume is created.
+pub(crate) const CAPACITY_PARAM: &str = "capacityBytes";
+
+/// Return the filesystems we are able to enforce project quota on.
+pub(crate) fn supported(fstype: &str) -> bool {
+    fstype == "xfs" || fstype == "ext4"
+}
+
+/// Return the mount option which enables project quota accounting.
+pub(crate) fn mount_option() -> String {
+    String::from("prjquota")
+}
+
+/// Derive a project ID from the volume ID. Project ID 0 is reserved.
+pub(crate) fn project_id(volume_id: &str) -> u32 {
+    let hex: String = volume_id
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .take(8)
+        .collect();
+    match u32::from_str_radix(&hex, 16) {
+        Ok(0) | Err(_) => 1,
+        Ok(id) => id,
+    }
+}
+
+/// Return the number of bytes the volume is limited to, which is its
+/// capacity from the volume context.
+pub(crate) fn limit(context: &HashMap<String, String>) -> Result<u64, String> {
+    let value = context.get(CAPACITY_PARAM).ok_or_else(|| {
+        format!("the capacity of the volume ({}) is missing", CAPACITY_PARAM)
+    })?;
+    match value.parse::<u64>() {
+        Ok(limit) if limit > 0 => Ok(limit),
+        _ => Err(format!(
+            "invalid capacity of the volume ({}): {}",
+            CAPACITY_PARAM, value
+        )),
+    }
+}
+
+/// Return the size of the device in bytes.
+pub(crate) fn device_size(device: &str) -> Result<u64, String> {
+    File::open(device)
+        .and_then(|mut file| file.seek(SeekFrom::End(0)))
+        .map_err(|error| format!("failed to get size of {}: {}", device, error))
+}
+
+/// A command line of one of the quota tools.
+type Cmd = (&'static str, Vec<String>);
+
+fn cmd(binary: &'static str, args: &[&str]) -> Cmd {
+    (binary, args.iter().map(|arg| String::from(*arg)).collect())
+}
+
+/// Run the command and return its output.
+fn run((binary, args): &Cmd) -> Result<String, String> {
+    let output = Command::new(binary)
+        .args(args)
+        .output()
+        .map_err(|error| format!("failed to execute {}: {}", binary, error))?;
+
+    trace!(
+        "Output from {} command: {}",
+        binary,
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+
+    Err(format!(
+        "{} command failed: {}",
+        binary,
+        String::from_utf8_lossy(&output.stderr)
+    ))
+}
+
+/// Command listing the superblock of an ext4 filesystem.
+fn ext4_features_cmd(device: &str) -> Cmd {
+    cmd("tune2fs", &["-l", device])
+}
+
+/// Command turning on project quota of an ext4 filesystem.
+fn ext4_enable_cmd(device: &str) -> Cmd {
+    cmd("tune2fs", &["-O", "quota,project", "-Q", "prjquota", device])
+}
+
+/// Return true if the superblock listed by tune2fs has the features needed
+/// for project quota.
+fn ext4_has_quota(output: &str) -> bool {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("Filesystem features:"))
+        .map_or(false, |features| {
+            let features: Vec<&str> = features.split_whitespace().collect();
+            features.contains(&"quota") && features.contains(&"project")
+        })
+}
+
+/// Prepare an unmounted filesystem for project quota. Xfs needs nothing
+/// beyond the mount option, ext4 needs the quota feature to be turned on,
+/// unless it has been on a previous stage of the volume.
+pub(crate) fn prepare(device: &str, fstype: &str) -> Result<(), String> {
+    match fstype {
+        "ext4" => {
+            if !ext4_has_quota(&run(&ext4_features_cmd(device))?) {
+                run(&ext4_enable_cmd(device))?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Return the commands which assign the mounted filesystem root to the
+/// project and limit the project to the given number of bytes.
+fn apply_cmds(
+    mountpoint: &str,
+    fstype: &str,
+    project: u32,
+    limit: u64,
+) -> Result<Vec<Cmd>, String> {
+    let project = project.to_string();
+
+    match fstype {
+        "xfs" => {
+            let setup = format!("project -s -p {} {}", mountpoint, project);
+            let limit = format!("limit -p bhard={} {}", limit, project);
+            Ok(vec![cmd(
+                "xfs_quota",
+                &["-x", "-c", &setup, "-c", &limit, mountpoint],
+            )])
+        }
+        "ext4" => {
+            // setquota takes the block limits in units of 1KiB
+            let blocks = ((limit + 1023) / 1024).to_string();
+            Ok(vec![
+                cmd("chattr", &["-p", &project, "+P", mountpoint]),
+                cmd(
+                    "setquota",
+                    &["-P", &project, "0", &blocks, "0", "0", mountpoint],
+                ),
+            ])
+        }
+        _ => Err(format!("quota is not supported on {}", fstype)),
+    }
+}
+
+/// Return the command reporting the project quota of the mounted
+/// filesystem, and the column of the hard block limit in its report.
+fn report_cmd(mountpoint: &str, fstype: &str) -> (Cmd, usize) {
+    match fstype {
+        "xfs" => (
+            cmd("xfs_quota", &["-x", "-c", "report -p -b -n -N", mountpoint]),
+            3,
+        ),
+        _ => (cmd("repquota", &["-P", "-n", mountpoint]), 4),
+    }
+}
+
+/// Return the hard block limit of the project in 1KiB units from the quota
+/// report, in which a project is listed by its number as #<id>.
+fn hard_limit(report: &str, column: usize, project: u32) -> Option<u64> {
+    let id = format!("#{}", project);
+    report
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .find(|fields| fields.first() == Some(&id.as_str()))
+        .and_then(|fields| fields.get(column)?.parse().ok())
+}
+
+/// Assign the mounted filesystem root to the project and limit the project
+/// to the given number of bytes. The limit is read back, as xfs_quota does
+/// not fail when its commands do.
+pub(crate) fn apply(
+    mountpoint: &str,
+    fstype: &str,
+    project: u32,
+    limit: u64,
+) -> Result<(), String> {
+    for command in apply_cmds(mountpoint, fstype, project, limit)? {
+        run(&command)?;
+    }
+
+    let (report, column) = report_cmd(mountpoint, fstype);
+    match hard_limit(&run(&report)?, column, project) {
+        Some(blocks) if blocks > 0 => Ok(()),
+        _ => Err(format!(
+            "no limit is set on project {} of {}",
+            project, mountpoint
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Cmd) -> Vec<&str> {
+        cmd.1.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn limit_of_context() {
+        let mut context = HashMap::new();
+        assert!(limit(&context).is_err());
+        context.insert(CAPACITY_PARAM.to_string(), "0".to_string());
+        assert!(limit(&context).is_err());
+        context.insert(CAPACITY_PARAM.to_string(), "1g".to_string());
+        assert!(limit(&context).is_err());
+        context.insert(CAPACITY_PARAM.to_string(), "10485760".to_string());
+        assert_eq!(limit(&context), Ok(10_485_760));
+    }
+
+    #[test]
+    fn tune2fs_cmds() {
+        let cmd = ext4_features_cmd("/dev/nvme0n1");
+        assert_eq!(cmd.0, "tune2fs");
+        assert_eq!(args(&cmd), ["-l", "/dev/nvme0n1"]);
+
+        let cmd = ext4_enable_cmd("/dev/nvme0n1");
+        assert_eq!(cmd.0, "tune2fs");
+        assert_eq!(
+            args(&cmd),
+            ["-O", "quota,project", "-Q", "prjquota", "/dev/nvme0n1"]
+        );
+    }
+
+    #[test]
+    fn tune2fs_features() {
+        let listing = "tune2fs 1.45.5 (07-Jan-2020)\n\
+            Filesystem volume name:   <none>\n\
+            Filesystem features:      has_journal ext_attr resize_inode \
+            dir_index filetype extent 64bit flex_bg sparse_super \
+            large_file huge_file dir_nlink extra_isize metadata_csum\n\
+            Filesystem flags:         signed_directory_hash\n";
+        assert!(!ext4_has_quota(listing));
+
+        let listing = listing.replace("metadata_csum", "metadata_csum quota");
+        assert!(!ext4_has_quota(&listing));
+
+        let listing = listing.replace("quota", "quota project");
+        assert!(ext4_has_quota(&listing));
+
+        assert!(!ext4_has_quota(""));
+    }
+
+    #[test]
+    fn xfs_quota_cmds() {
+        let cmds = apply_cmds("/staging", "xfs", 1234, 10_485_760).unwrap();
+        assert_eq!(cmds.len(), 1);
+        assert_eq!(cmds[0].0, "xfs_quota");
+        assert_eq!(
+            args(&cmds[0]),
+            [
+                "-x",
+                "-c",
+                "project -s -p /staging 1234",
+                "-c",
+                "limit -p bhard=10485760 1234",
+                "/staging"
+            ]
+        );
+
+        let (cmd, column) = report_cmd("/staging", "xfs");
+        assert_eq!(cmd.0, "xfs_quota");
+        assert_eq!(args(&cmd), ["-x", "-c", "report -p -b -n -N", "/staging"]);
+        assert_eq!(column, 3);
+    }
+
+    #[test]
+    fn xfs_quota_report() {
+        let report = "#0      2048   0      0 00 [--------]\n\
+            #1234      0   0  10240 00 [--------]\n";
+        assert_eq!(hard_limit(report, 3, 1234), Some(10240));
+        assert_eq!(hard_limit(report, 3, 0), Some(0));
+        assert_eq!(hard_limit(report, 3, 123), None);
+        assert_eq!(hard_limit("", 3, 1234), None);
+    }
+
+    #[test]
+    fn chattr_setquota_cmds() {
+        let cmds = apply_cmds("/staging", "ext4", 1234, 10_485_761).unwrap();
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(cmds[0].0, "chattr");
+        assert_eq!(args(&cmds[0]), ["-p", "1234", "+P", "/staging"]);
+        // the limit is rounded up to whole KiB
+        assert_eq!(cmds[1].0, "setquota");
+        assert_eq!(
+            args(&cmds[1]),
+            ["-P", "1234", "0", "10241", "0", "0", "/staging"]
+        );
+
+        let (cmd, column) = report_cmd("/staging", "ext4");
+        assert_eq!(cmd.0, "repquota");
+        assert_eq!(args(&cmd), ["-P", "-n", "/staging"]);
+        assert_eq!(column, 4);
+
+        assert!(apply_cmds("/staging", "btrfs", 1234, 1024).is_err());
+    }
+
+    #[test]
+    fn repquota_report() {
+        let report = "*** Report for project quotas on device /dev/sda\n\
+            Block grace time: 7days; Inode grace time: 7days\n\
+            Project   used soft  hard grace used soft hard grace\n\
+            ---------------------------------------------------\n\
+            #0     --   20    0     0          2    0    0\n\
+            #1234  --    4    0 10241          1    0    0\n";
+        assert_eq!(hard_limit(report, 4, 1234), Some(10241));
+        assert_eq!(hard_limit(report, 4, 0), Some(0));
+        assert_eq!(hard_limit(report, 4, 5678), None);
+    }
+}
@@ -0,0 +1,139 @@
+//! Utility functions for repairing a filesystem which fails to mount.
+//!
+//! A filesystem which has not been unmounted cleanly, i.e. as the node
+//! crashed or the nexus went away underneath it, may be refused by the
+//! kernel until it has been checked. When enabled in the volume context,
+//! the filesystem is repaired and mounted once more, instead of the volume
+//! staying unusable until somebody repairs it by hand. Repairing is opt-in,
+//! as fixing up a filesystem can lose data which an administrator may have
+//! been able to save.
+
+use std::{io::Error, process::Command};
+
+use prost::Message;
+use tonic::{Code, Status};
+
+/// Volume context (storage class) parameter that enables the repair.
+pub(crate) const REPAIR_PARAM: &str = "fsRepair";
+
+/// Return the filesystems we are able to repair. The repair of btrfs is
+/// considered too dangerous to be run unattended.
+pub(crate) fn supported(fstype: &str) -> bool {
+    fstype == "xfs" || fstype == "ext3" || fstype == "ext4"
+}
+
+/// Return true if a mount which failed with the error could succeed once
+/// the filesystem has been repaired. The kernel refuses a corrupted
+/// filesystem with EUCLEAN, and one which it cannot make sense of at all
+/// with EINVAL or EIO.
+pub(crate) fn needed(error: &Error) -> bool {
+    match error.raw_os_error() {
+        Some(libc::EUCLEAN) | Some(libc::EINVAL) | Some(libc::EIO) => true,
+        _ => false,
+    }
+}
+
+/// Repair the filesystem on the device, and return what has been done.
+/// The dirty log of xfs is not zeroed, as that throws away the metadata
+/// changes in it, so a filesystem which needs that is left to an
+/// administrator.
+pub(crate) fn repair(device: &str, fstype: &str) -> Result<String, String> {
+    let (binary, args): (String, &[&str]) = match fstype {
+        "xfs" => (String::from("xfs_repair"), &[]),
+        fs => (format!("fsck.{}", fs), &["-y"]),
+    };
+
+    debug!("Repairing filesystem ({}) on device {}", fstype, device);
+
+    let output = Command::new(&binary)
+        .args(args)
+        .arg(device)
+        .output()
+        .map_err(|error| format!("failed to execute {}: {}", binary, error))?;
+
+    trace!(
+        "Output from {} command: {}",
+        binary,
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    let mut words = vec![binary.as_str()];
+    words.extend(args);
+    words.push(device);
+    let command = words.join(" ");
+
+    // fsck exits with 1 if errors were corrected, and with 2 if the system
+    // should be rebooted, which does not apply to an unmounted filesystem
+    let code = output.status.code();
+    let corrected = match code {
+        Some(0) => false,
+        Some(1) | Some(2) if fstype != "xfs" => true,
+        _ => {
+            return Err(format!(
+                "{} failed ({}): {}",
+                command,
+                code.map_or_else(
+                    || String::from("killed"),
+                    |code| format!("exit status {}", code)
+                ),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+    };
+
+    if corrected {
+        Ok(format!("{} corrected errors on the filesystem", command))
+    } else {
+        Ok(format!("{} completed", command))
+    }
+}
+
+/// google.rpc.Status, as carried in the details of a gRPC error
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+/// google.rpc.DebugInfo
+#[derive(Clone, PartialEq, Message)]
+struct DebugInfo {
+    #[prost(string, repeated, tag = "1")]
+    stack_entries: Vec<String>,
+    #[prost(string, tag = "2")]
+    detail: String,
+}
+
+/// Return an error status which carries the steps taken to repair the
+/// filesystem as google.rpc.DebugInfo in its details.
+pub(crate) fn status(code: Code, message: String, steps: &[String]) -> Status {
+    let mut info = Vec::new();
+    let mut details = Vec::new();
+
+    let debug_info = DebugInfo {
+        stack_entries: steps.to_vec(),
+        detail: String::from("filesystem repair"),
+    };
+
+    if debug_info.encode(&mut info).is_ok() {
+        let status = RpcStatus {
+            code: code as i32,
+            message: message.clone(),
+            details: vec![prost_types::Any {
+                type_url: String::from(
+                    "type.googleapis.com/google.rpc.DebugInfo",
+                ),
+                value: info,
+            }],
+        };
+        if status.encode(&mut details).is_err() {
+            details.clear();
+        }
+    }
+
+    Status::with_details(code, message, details.into())
+}
@@ -7,27 +7,33 @@ extern crate clap;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
-extern crate log;
+extern crate tracing;
 
 use std::{
     fs,
-    io::{ErrorKind, Write},
+    io::ErrorKind,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
-use chrono::Local;
 use clap::{App, Arg};
 use csi::{identity_server::IdentityServer, node_server::NodeServer};
-use env_logger::{Builder, Env};
 use futures::stream::TryStreamExt;
 use std::{
-    path::Path,
     pin::Pin,
     task::{Context, Poll},
 };
 use tokio::{net::UnixListener, prelude::*};
 use tonic::transport::{server::Connected, Server};
-
-use crate::{identity::Identity, mount::probe_filesystems, node::Node};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    dev::Device,
+    identity::Identity,
+    mayastornodeplugin::mayastor_node_plugin_server::MayastorNodePluginServer,
+    mount::probe_filesystems,
+    node::Node,
+    nodeplugin::NodePlugin,
+};
 
 #[allow(dead_code)]
 #[allow(clippy::type_complexity)]
@@ -38,6 +44,12 @@ pub mod csi {
     tonic::include_proto!("csi.v1");
 }
 
+#[allow(clippy::type_complexity)]
+#[allow(clippy::unit_arg)]
+pub mod mayastornodeplugin {
+    tonic::include_proto!("mayastornodeplugin");
+}
+
 mod dev;
 mod error;
 
@@ -48,6 +60,9 @@ mod identity;
 mod match_dev;
 mod mount;
 mod node;
+mod nodeplugin;
+mod quota;
+mod repair;
 
 use snafu::Snafu;
 
@@ -124,7 +139,15 @@ async fn main() -> Result<(), String> {
         .arg(
             Arg::with_name("log-debug")
                 .short("l")
-                .help("Log extra info - file name and line number"),
+                .help("Log extra info - thread name and id"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .possible_values(&["default", "json"])
+                .default_value("default")
+                .help("Format of the log messages"),
         )
         .arg(
             Arg::with_name("node-name")
@@ -155,28 +178,20 @@ async fn main() -> Result<(), String> {
 
     // configure logger: env var takes precedence over cmd line options
     let filter_expr = format!("{}={}", module_path!(), level);
-    let mut builder =
-        Builder::from_env(Env::default().default_filter_or(filter_expr));
-    if matches.is_present("log-debug") {
-        builder.format(|buf, record| {
-            let mut level_style = buf.default_level_style(record.level());
-            level_style.set_intense(true);
-            writeln!(
-                buf,
-                "[{} {} {}:{}] {}",
-                Local::now().format("%Y-%m-%dT%H:%M:%SZ"),
-                level_style.value(record.level()),
-                Path::new(record.file().unwrap())
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                record.line().unwrap(),
-                record.args()
-            )
-        });
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(filter_expr));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_thread_names(matches.is_present("log-debug"))
+        .with_thread_ids(matches.is_present("log-debug"));
+    match matches.value_of("log-format") {
+        Some("json") => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+        _ => builder.init(),
     }
-    builder.init();
 
     // Remove stale CSI socket from previous instance if there is any
     match fs::remove_file(csi_socket) {
@@ -191,15 +206,25 @@ async fn main() -> Result<(), String> {
         }
     }
 
+    Device::supervise_sessions();
+
     let mut uds_sock = UnixListener::bind(csi_socket).unwrap();
     info!("CSI plugin bound to {}", csi_socket);
 
+    // every request is handled in a span with its own id, such that the
+    // messages logged for it can be told apart from those of other requests
+    let request_id = AtomicU64::new(1);
     let uds = Server::builder()
+        .trace_fn(move |_| {
+            let id = request_id.fetch_add(1, Ordering::Relaxed);
+            info_span!("csi", request_id = id)
+        })
         .add_service(NodeServer::new(Node {
             node_name: node_name.into(),
             filesystems: probe_filesystems(),
         }))
         .add_service(IdentityServer::new(Identity {}))
+        .add_service(MayastorNodePluginServer::new(NodePlugin {}))
         .serve_with_incoming(uds_sock.incoming().map_ok(UnixStream));
     let _ = uds.await;
     Ok(())
@@ -392,6 +392,28 @@ function createGrpcClient () {
   return client;
 }
 
+// Create grpc client of the health service of mayastor. Must be closed by the
+// user when not used anymore.
+function createHealthClient () {
+  var client = createClient(
+    {
+      protoPath: path.join(__dirname, '..', 'rpc', 'proto', 'health.proto'),
+      packageName: 'grpc.health.v1',
+      serviceName: 'Health',
+      options: {
+        keepCase: true,
+        enums: String,
+        defaults: true
+      }
+    },
+    grpcEndpoint
+  );
+  if (!client) {
+    throw new Error('Failed to initialize grpc health client');
+  }
+  return client;
+}
+
 // Create mayastor grpc client, call a method and return the result of it.
 function callGrpcMethod (method, args, done) {
   var client;
@@ -479,5 +501,6 @@ module.exports = {
   getMyIp,
   getCmdPath,
   createGrpcClient,
+  createHealthClient,
   callGrpcMethod
 };
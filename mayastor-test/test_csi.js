@@ -62,6 +62,27 @@ function createCsiClient (service) {
   return new proto[service](csiSock, grpc.credentials.createInsecure());
 }
 
+// Client of the private service of the node plugin.
+function createNodePluginClient () {
+  const pkgDef = grpc.loadPackageDefinition(
+    protoLoader.loadSync(
+      path.join(__dirname, '..', 'csi', 'proto', 'mayastornodeplugin.proto'),
+      {
+        keepCase: true,
+        longs: String,
+        enums: String,
+        defaults: true,
+        oneofs: true
+      }
+    )
+  );
+  const proto = pkgDef.mayastornodeplugin;
+  return new proto.MayastorNodePlugin(
+    csiSock,
+    grpc.credentials.createInsecure()
+  );
+}
+
 function cleanPublishDir (mountTarget, done) {
   const proc = common.runAsRoot('umount', ['-f', mountTarget]);
   proc.once('close', (code, signal) => {
@@ -90,6 +111,19 @@ function shouldFailWith (code, done) {
 }
 
 // Get filesystem type for given mount point.
+function getMountOptions (mp) {
+  const lines = execSync('mount')
+    .toString()
+    .trim()
+    .split('\n');
+  for (let i = 0; i < lines.length; i++) {
+    const cols = lines[i].split(' ');
+    if (mp === cols[2]) {
+      return cols[5].replace(/[()]/g, '').split(',');
+    }
+  }
+}
+
 function getFsType (mp) {
   const lines = execSync('mount')
     .toString()
@@ -294,6 +328,9 @@ describe('csi', function () {
           1,
           'number of nbd devices should be above 1'
         );
+        assert.deepEqual(res.accessible_topology.segments, {
+          'mayastor.openebs.io/node': common.CSI_ID
+        });
         done();
       });
     });
@@ -301,9 +338,11 @@ describe('csi', function () {
     it('get capabilities', (done) => {
       client.nodeGetCapabilities({}, (err, res) => {
         if (err) return done(err);
-        assert.lengthOf(res.capabilities, 1);
+        assert.lengthOf(res.capabilities, 2);
         assert.equal(res.capabilities[0].type, 'rpc');
         assert.equal(res.capabilities[0].rpc.type, 'STAGE_UNSTAGE_VOLUME');
+        assert.equal(res.capabilities[1].type, 'rpc');
+        assert.equal(res.capabilities[1].rpc.type, 'GET_VOLUME_STATS');
         done();
       });
     });
@@ -423,7 +462,15 @@ function csiProtocolTest (protoname, shareType, timeoutMillis) {
             volume_id: UUID1,
             volume_path: mountTarget
           },
-          shouldFailWith(grpc.status.UNIMPLEMENTED, done)
+          (err, res) => {
+            if (err) return done(err);
+            assert.lengthOf(res.usage, 2);
+            assert.equal(res.usage[0].unit, 'BYTES');
+            assert.isAbove(parseInt(res.usage[0].total), 0);
+            assert.equal(res.usage[1].unit, 'INODES');
+            assert.isAbove(parseInt(res.usage[1].total), 0);
+            done();
+          }
         );
       });
 
@@ -431,6 +478,38 @@ function csiProtocolTest (protoname, shareType, timeoutMillis) {
         client.nodeStageVolume(getDefaultArgs(), done);
       });
 
+      it('should freeze and thaw the staged volume', function (done) {
+        if (shareType === enums.NEXUS_NBD) {
+          // nbd devices can not be looked up by the uuid of the volume
+          this.skip();
+        }
+        const pluginClient = createNodePluginClient();
+        const args = { volume_id: UUID1 };
+        async.series(
+          [
+            (next) => pluginClient.freezeVolume(args, next),
+            // freezing a frozen volume is ok
+            (next) => pluginClient.freezeVolume(args, next),
+            (next) => pluginClient.thawVolume(args, next),
+            // thawing a volume that is not frozen is ok
+            (next) => pluginClient.thawVolume(args, next)
+          ],
+          (err) => {
+            pluginClient.close();
+            done(err);
+          }
+        );
+      });
+
+      it('should fail to freeze a volume which is not attached', (done) => {
+        const pluginClient = createNodePluginClient();
+        pluginClient.freezeVolume({ volume_id: UUID5 }, (err) => {
+          pluginClient.close();
+          assert.equal(err.code, grpc.status.NOT_FOUND);
+          done();
+        });
+      });
+
       it('staging a volume with the same staging path but with a different bdev should fail', (done) => {
         const args = getDefaultArgs();
         args.volume_id = UUID2;
@@ -609,6 +688,131 @@ function csiProtocolTest (protoname, shareType, timeoutMillis) {
           shouldFailWith(grpc.status.INVALID_ARGUMENT, done)
         );
       });
+
+      it('should fail to stage with mkfs options of another fs', (done) => {
+        const args = {
+          volume_id: UUID3,
+          publish_context: publishedUris[UUID3],
+          staging_target_path: mountTarget,
+          volume_capability: {
+            access_mode: {
+              mode: 'MULTI_NODE_READER_ONLY'
+            },
+            mount: {
+              fs_type: 'ext4'
+            }
+          },
+          volume_context: { agCount: '4' }
+        };
+        client.nodeStageVolume(
+          args,
+          shouldFailWith(grpc.status.INVALID_ARGUMENT, done)
+        );
+      });
+
+      it('should fail to stage with an invalid inode size', (done) => {
+        const args = {
+          volume_id: UUID3,
+          publish_context: publishedUris[UUID3],
+          staging_target_path: mountTarget,
+          volume_capability: {
+            access_mode: {
+              mode: 'MULTI_NODE_READER_ONLY'
+            },
+            mount: {
+              fs_type: 'xfs'
+            }
+          },
+          volume_context: { inodeSize: '300' }
+        };
+        client.nodeStageVolume(
+          args,
+          shouldFailWith(grpc.status.INVALID_ARGUMENT, done)
+        );
+      });
+
+      it('should fail to stage with mkfs options which are not allowed', (done) => {
+        const args = {
+          volume_id: UUID3,
+          publish_context: publishedUris[UUID3],
+          staging_target_path: mountTarget,
+          volume_capability: {
+            access_mode: {
+              mode: 'MULTI_NODE_READER_ONLY'
+            },
+            mount: {
+              fs_type: 'ext4'
+            }
+          },
+          volume_context: { mkfsOptions: '-L foo' }
+        };
+        client.nodeStageVolume(
+          args,
+          shouldFailWith(grpc.status.INVALID_ARGUMENT, done)
+        );
+      });
+
+      it('should fail to stage with mount options which are not allowed', (done) => {
+        const args = {
+          volume_id: UUID3,
+          publish_context: publishedUris[UUID3],
+          staging_target_path: mountTarget,
+          volume_capability: {
+            access_mode: {
+              mode: 'MULTI_NODE_READER_ONLY'
+            },
+            mount: {
+              fs_type: 'xfs'
+            }
+          },
+          volume_context: { mountOptions: 'noatime,context=foo' }
+        };
+        client.nodeStageVolume(
+          args,
+          shouldFailWith(grpc.status.INVALID_ARGUMENT, done)
+        );
+      });
+
+      it('should fail to stage with an invalid repair setting', (done) => {
+        const args = {
+          volume_id: UUID3,
+          publish_context: publishedUris[UUID3],
+          staging_target_path: mountTarget,
+          volume_capability: {
+            access_mode: {
+              mode: 'MULTI_NODE_READER_ONLY'
+            },
+            mount: {
+              fs_type: 'xfs'
+            }
+          },
+          volume_context: { fsRepair: 'yes' }
+        };
+        client.nodeStageVolume(
+          args,
+          shouldFailWith(grpc.status.INVALID_ARGUMENT, done)
+        );
+      });
+
+      it('should fail to stage a filesystem with multi node multi writer access', (done) => {
+        const args = {
+          volume_id: UUID3,
+          publish_context: publishedUris[UUID3],
+          staging_target_path: mountTarget,
+          volume_capability: {
+            access_mode: {
+              mode: 'MULTI_NODE_MULTI_WRITER'
+            },
+            mount: {
+              fs_type: 'xfs'
+            }
+          }
+        };
+        client.nodeStageVolume(
+          args,
+          shouldFailWith(grpc.status.INVALID_ARGUMENT, done)
+        );
+      });
     });
 
     // The combinations of ro/rw and access mode flags are quite confusing.
@@ -910,6 +1114,53 @@ function csiProtocolTest (protoname, shareType, timeoutMillis) {
           });
         });
 
+        it('should remount when re-publishing with additional mount flags', (done) => {
+          const args = {
+            volume_id: UUID5,
+            publish_context: publishedUris[UUID5],
+            staging_target_path: mountTarget,
+            target_path: bindTarget2,
+            volume_capability: {
+              access_mode: {
+                mode: 'MULTI_NODE_SINGLE_WRITER'
+              },
+              mount: {
+                fs_type: 'ext4',
+                mount_flags: ['noexec']
+              }
+            }
+          };
+
+          client.nodePublishVolume(args, (err) => {
+            if (err) return done(err);
+            assert.include(getMountOptions(bindTarget2), 'noexec');
+            done();
+          });
+        });
+
+        it('should fail to re-publish with different filesystem options', (done) => {
+          const args = {
+            volume_id: UUID5,
+            publish_context: publishedUris[UUID5],
+            staging_target_path: mountTarget,
+            target_path: bindTarget2,
+            volume_capability: {
+              access_mode: {
+                mode: 'MULTI_NODE_SINGLE_WRITER'
+              },
+              mount: {
+                fs_type: 'ext4',
+                mount_flags: ['data=journal']
+              }
+            }
+          };
+
+          client.nodePublishVolume(
+            args,
+            shouldFailWith(grpc.status.ALREADY_EXISTS, done)
+          );
+        });
+
         it('should be able to unpublish ro volume', (done) => {
           client.nodeUnpublishVolume(
             {
@@ -0,0 +1,144 @@
+// Test the gRPC endpoints of mayastor given in its config file besides the
+// one on the command line, and their auth policies.
+
+'use strict';
+
+const assert = require('chai').assert;
+const fs = require('fs');
+const path = require('path');
+const protoLoader = require('@grpc/proto-loader');
+// grpc-kit can't connect to UDS nor pass metadata
+const grpc = require('grpc-uds');
+const common = require('./test_common');
+
+const YAML_PATH = '/tmp/mayastor_grpc_test.yaml';
+const UDS_PATH = '/tmp/mayastor_grpc_test.sock';
+const TOKEN_PATH = '/tmp/mayastor_grpc_test.token';
+const TOKEN = 'not-so-secret';
+const TCP_ENDPOINT = common.getMyIp() + ':10125';
+
+const YAML = `
+grpc:
+  endpoints:
+    - address: "unix:${UDS_PATH}"
+    - address: "${TCP_ENDPOINT}"
+      token_file: "${TOKEN_PATH}"
+      read_only: true
+`;
+
+function createClient (endpoint) {
+  const pkgDef = grpc.loadPackageDefinition(
+    protoLoader.loadSync(
+      path.join(__dirname, '..', 'rpc', 'proto', 'mayastor.proto'),
+      {
+        keepCase: true,
+        longs: String,
+        enums: String,
+        defaults: true,
+        oneofs: true
+      }
+    )
+  );
+  return new pkgDef.mayastor.Mayastor(
+    endpoint,
+    grpc.credentials.createInsecure()
+  );
+}
+
+function withToken (token) {
+  const meta = new grpc.Metadata();
+  meta.add('authorization', 'Bearer ' + token);
+  return meta;
+}
+
+describe('grpc endpoints', function () {
+  var udsClient;
+  var tcpClient;
+
+  this.timeout(10000);
+
+  before((done) => {
+    fs.writeFileSync(YAML_PATH, YAML);
+    fs.writeFileSync(TOKEN_PATH, TOKEN + '\n');
+    common.startMayastor(null, [
+      '-r', common.SOCK,
+      '-g', common.grpcEndpoint,
+      '-y', YAML_PATH
+    ]);
+    common.waitFor((pingDone) => {
+      common.callGrpcMethod('listPools', {}, pingDone);
+    }, (err) => {
+      if (err) return done(err);
+      const child = common.runAsRoot('chmod', ['a+rw', UDS_PATH]);
+      child.on('close', (code) => {
+        if (code !== 0) return done(new Error('Failed to chmod the socket'));
+        udsClient = createClient('unix://' + UDS_PATH);
+        tcpClient = createClient(TCP_ENDPOINT);
+        done();
+      });
+    });
+  });
+
+  after((done) => {
+    if (udsClient) udsClient.close();
+    if (tcpClient) tcpClient.close();
+    common.stopAll(() => {
+      [YAML_PATH, TOKEN_PATH].forEach((p) => {
+        try {
+          fs.unlinkSync(p);
+        } catch (err) {}
+      });
+      done();
+    });
+  });
+
+  it('should serve the endpoint given on the command line', (done) => {
+    common.callGrpcMethod('listPools', {}, done);
+  });
+
+  it('should serve the unix domain socket without a token', (done) => {
+    udsClient.listPools({}, (err, res) => {
+      if (err) return done(err);
+      assert.isArray(res.pools);
+      done();
+    });
+  });
+
+  it('should refuse a call without a token on the tcp endpoint', (done) => {
+    tcpClient.listPools({}, (err) => {
+      assert.isNotNull(err);
+      assert.equal(err.code, grpc.status.UNAUTHENTICATED);
+      done();
+    });
+  });
+
+  it('should refuse a call with a wrong token on the tcp endpoint', (done) => {
+    tcpClient.listPools({}, withToken('guess'), (err) => {
+      assert.isNotNull(err);
+      assert.equal(err.code, grpc.status.UNAUTHENTICATED);
+      done();
+    });
+  });
+
+  it('should serve a call with the token on the tcp endpoint', (done) => {
+    tcpClient.listPools({}, withToken(TOKEN), (err, res) => {
+      if (err) return done(err);
+      assert.isArray(res.pools);
+      done();
+    });
+  });
+
+  it('should refuse a call changing the state on the read-only endpoint', (done) => {
+    const args = { name: 'tpool', disks: ['malloc:///malloc0?size_mb=64'] };
+    tcpClient.createPool(args, withToken(TOKEN), (err) => {
+      assert.isNotNull(err);
+      assert.equal(err.code, grpc.status.PERMISSION_DENIED);
+      // which never reached mayastor
+      udsClient.listPools({}, (err, res) => {
+        if (err) return done(err);
+        assert.isUndefined(res.pools.find((p) => p.name === 'tpool'));
+        done();
+      });
+    });
+  });
+});
@@ -160,6 +160,66 @@ function controlPlaneTest (thisProtocol) {
     });
   });
 
+  const hosts = (thisProtocol === enums.NEXUS_ISCSI)
+    ? ['iqn.1993-08.org.debian:01:host1', 'iqn.1993-08.org.debian:01:host2']
+    : ['nqn.2019-05.io.openebs:host1', 'nqn.2019-05.io.openebs:host2'];
+
+  it('should fail to publish the nexus to more than one host without shared access', (done) => {
+    client.publishNexus(
+      {
+        uuid: UUID,
+        share: thisProtocol,
+        allowed_hosts: hosts
+      },
+      (err, res) => {
+        if (!err) return done(new Error('Expected error'));
+        assert.equal(err.code, grpc.status.INVALID_ARGUMENT);
+        done();
+      }
+    );
+  });
+
+  if (thisProtocol !== enums.NEXUS_NBD) {
+    it('should publish the nexus to more than one host with shared access', (done) => {
+      client.publishNexus(
+        {
+          uuid: UUID,
+          share: thisProtocol,
+          shared_access: true,
+          allowed_hosts: hosts
+        },
+        (err, res) => {
+          if (err) return done(err);
+          assert(res.device_uri);
+          done();
+        }
+      );
+    });
+
+    it('should fail another publish request to other hosts', (done) => {
+      client.publishNexus(
+        {
+          uuid: UUID,
+          share: thisProtocol,
+          shared_access: true,
+          allowed_hosts: hosts.slice(1)
+        },
+        (err, res) => {
+          if (!err) return done(new Error('Expected error'));
+          assert.equal(err.code, grpc.status.INVALID_ARGUMENT);
+          done();
+        }
+      );
+    });
+
+    it('should un-publish the shared nexus device', (done) => {
+      client.unpublishNexus({ uuid: UUID }, (err, res) => {
+        if (err) done(err);
+        done();
+      });
+    });
+  }
+
   it('should re-publish the nexus using a crypto-key', (done) => {
     client.publishNexus(
       {
@@ -447,6 +507,48 @@ describe('nexus', function () {
     });
   });
 
+  it('should account for the memory of the nvmf child', (done) => {
+    client.getResourceUsage({}, (err, res) => {
+      if (err) return done(err);
+      assert.isAbove(parseInt(res.usage.max_rss), 0);
+
+      const child = res.children.find(
+        (c) => c.name === '127.0.0.1:8420/nqn.2019-05.io.openebs:disk2'
+      );
+      assert.isDefined(child);
+      assert.equal(child.transport, 'tcp');
+      assert.isAbove(parseInt(child.qpairs), 1);
+      assert.isAbove(parseInt(child.bytes), 0);
+      assert.isAtLeast(
+        parseInt(res.children_bytes),
+        parseInt(child.bytes)
+      );
+      assert.equal(res.children_bytes_limit, '0');
+      done();
+    });
+  });
+
+  it('should set and remove QoS limits of the nexus', (done) => {
+    client.setBdevQos(
+      { uuid: UUID, limits: { rw_mbytes_per_sec: 50 } },
+      (err) => {
+        if (err) return done(err);
+        client.listNexus({}, (err, res) => {
+          if (err) return done(err);
+          assert.equal(res.nexus_list[0].qos.rw_mbytes_per_sec, '50');
+          client.setBdevQos({ uuid: UUID }, (err) => {
+            if (err) return done(err);
+            client.listNexus({}, (err, res) => {
+              if (err) return done(err);
+              assert.equal(res.nexus_list[0].qos.rw_mbytes_per_sec, '0');
+              done();
+            });
+          });
+        });
+      }
+    );
+  });
+
   it('should be able to remove one of its children', (done) => {
     const args = {
       uuid: UUID,
@@ -566,6 +668,78 @@ describe('nexus', function () {
       });
     });
 
+    it('should report stats of the nexus with no IO in flight', (done) => {
+      client.statNexus({}, (err, res) => {
+        if (err) return done(err);
+        const stats = res.nexus_list.find((n) => n.uuid === UUID);
+        assert(stats);
+        assert.isAbove(parseInt(stats.stats.num_write_ops), 0);
+        assert.isAbove(parseInt(stats.stats.num_read_ops), 0);
+        assert.equal(parseInt(stats.queue_depth), 0);
+        assert.equal(parseInt(stats.in_flight_bytes), 0);
+        done();
+      });
+    });
+
+    it('should report the bytes written to the children of the nexus', (done) => {
+      client.statNexus({}, (err, res) => {
+        if (err) return done(err);
+        const stats = res.nexus_list.find((n) => n.uuid === UUID);
+        assert(stats);
+        // each write is dispatched to all the children
+        assert.isAtLeast(
+          parseInt(stats.child_bytes_written),
+          parseInt(stats.stats.bytes_written)
+        );
+        assert.equal(parseInt(stats.rebuild_bytes_written), 0);
+        assert.isAtLeast(stats.write_amplification, 1);
+        done();
+      });
+    });
+
+    it('should report the lifetime counters of the nexus', (done) => {
+      client.statNexus({}, (err, res) => {
+        if (err) return done(err);
+        const stats = res.nexus_list.find((n) => n.uuid === UUID);
+        assert(stats);
+        assert.isAtLeast(
+          parseInt(stats.lifetime_stats.num_write_ops),
+          parseInt(stats.stats.num_write_ops)
+        );
+        assert.isAtLeast(
+          parseInt(stats.lifetime_stats.bytes_read),
+          parseInt(stats.stats.bytes_read)
+        );
+        assert.equal(parseInt(stats.io_errors), 0);
+        done();
+      });
+    });
+
+    it('should reset the lifetime counters of the nexus', (done) => {
+      client.resetStats({ uuid: UUID }, (err) => {
+        if (err) return done(err);
+        client.statNexus({}, (err, res) => {
+          if (err) return done(err);
+          const stats = res.nexus_list.find((n) => n.uuid === UUID);
+          assert(stats);
+          assert.isAbove(parseInt(stats.stats.num_write_ops), 0);
+          assert.equal(parseInt(stats.lifetime_stats.num_write_ops), 0);
+          assert.equal(parseInt(stats.lifetime_stats.bytes_written), 0);
+          done();
+        });
+      });
+    });
+
+    it('should fail to reset the counters of an unknown volume', (done) => {
+      client.resetStats(
+        { uuid: 'f0a6e7d5-3c2b-4a19-8e7f-6d5c4b3a2918' },
+        (err) => {
+          assert.equal(err.code, grpc.status.NOT_FOUND);
+          done();
+        }
+      );
+    });
+
     it('should un-publish the NBD nexus device', (done) => {
       client.unpublishNexus({ uuid: UUID }, (err, res) => {
         if (err) done(err);
@@ -607,6 +781,16 @@ describe('nexus', function () {
       });
     });
 
+    it('should fail to set the ANA state of the iscsi nexus', (done) => {
+      client.setNexusAnaState(
+        { uuid: UUID, ana_state: enums.NVME_ANA_INACCESSIBLE_STATE },
+        (err) => {
+          assert.equal(err.code, grpc.status.FAILED_PRECONDITION);
+          done();
+        }
+      );
+    });
+
     it('should un-publish the iscsi nexus device', (done) => {
       client.unpublishNexus({ uuid: UUID }, (err, res) => {
         if (err) done(err);
@@ -677,6 +861,33 @@ describe('nexus', function () {
       );
     });
 
+    it('should report the nexus as optimized', (done) => {
+      client.listNexus({}, (err, res) => {
+        if (err) return done(err);
+        const nexus = res.nexus_list.find((n) => n.uuid === UUID);
+        assert.equal(nexus.ana_state, 'NVME_ANA_OPTIMIZED_STATE');
+        done();
+      });
+    });
+
+    it('should set the ANA state of the nexus', (done) => {
+      client.setNexusAnaState(
+        { uuid: UUID, ana_state: enums.NVME_ANA_INACCESSIBLE_STATE },
+        (err) => {
+          if (err) return done(err);
+          client.listNexus({}, (err, res) => {
+            if (err) return done(err);
+            const nexus = res.nexus_list.find((n) => n.uuid === UUID);
+            assert.equal(nexus.ana_state, 'NVME_ANA_INACCESSIBLE_STATE');
+            client.setNexusAnaState(
+              { uuid: UUID, ana_state: enums.NVME_ANA_OPTIMIZED_STATE },
+              done
+            );
+          });
+        }
+      );
+    });
+
     it('should un-publish the nvmf nexus device', (done) => {
       client.unpublishNexus({ uuid: UUID }, (err, res) => {
         if (err) done(err);
@@ -19,6 +19,7 @@ const POOL = 'tpool';
 const DISK_FILE = '/tmp/mayastor_test_disk';
 // arbitrary uuid used for creating a replica
 const UUID = 'dbe4d7eb-118a-4d15-b789-a18d9af6ff21';
+const CLONE_UUID = 'dbe4d7eb-118a-4d15-b789-a18d9af6ff22';
 // uuid without the last digit for generating a set of uuids
 const BASE_UUID = 'c35fa4dd-d527-4b7b-9cf0-436b8bb0ba7';
 // regexps for testing nvmf and iscsi URIs
@@ -150,6 +151,31 @@ describe('replica', function () {
     );
   });
 
+  it('should report mayastor as serving', (done) => {
+    const health = common.createHealthClient();
+    async.eachSeries(
+      ['', 'mayastor.Mayastor'],
+      (service, next) => {
+        health.check({ service }, (err, res) => {
+          if (err) return next(err);
+          assert.equal(res.status, 'SERVING');
+          next();
+        });
+      },
+      (err) => {
+        if (err) {
+          health.close();
+          return done(err);
+        }
+        health.check({ service: 'unknown' }, (err) => {
+          health.close();
+          assert.equal(err.code, grpc.status.NOT_FOUND);
+          done();
+        });
+      }
+    );
+  });
+
   it('should not support multiple disks for a pool', (done) => {
     client.createPool(
       {
@@ -393,6 +419,70 @@ describe('replica', function () {
     );
   });
 
+  it('should reshare the replica under a different protocol', (done) => {
+    client.reshareReplica(
+      {
+        uuid: UUID,
+        share: 'REPLICA_NVMF',
+        gracePeriod: 1
+      },
+      (err, res) => {
+        if (err) return done(err);
+        assert.match(res.uri, NVMF_URI);
+        assert.match(res.previousUri, ISCSI_URI);
+
+        client.listReplicas({}, (err, res) => {
+          if (err) return done(err);
+          res = res.replicas.filter((ent) => {
+            return ent.uuid === UUID;
+          });
+          assert.lengthOf(res, 1);
+          res = res[0];
+          assert.equal(res.share, 'REPLICA_NVMF');
+          assert.match(res.uri, NVMF_URI);
+          // the replica is not backed by a PCIe device
+          assert.equal(res.placement.numaNode, -1);
+          assert.isFalse(res.placement.crossNuma);
+          assert.lengthOf(res.placement.listeners, 1);
+          assert.match(res.placement.listeners[0], /^nvmf:\/\//);
+          done();
+        });
+      }
+    );
+  });
+
+  it('should fail to reshare the replica during the grace period', (done) => {
+    client.reshareReplica(
+      {
+        uuid: UUID,
+        share: 'REPLICA_ISCSI',
+        gracePeriod: 1
+      },
+      (err) => {
+        assert.equal(err.code, grpc.status.FAILED_PRECONDITION);
+        done();
+      }
+    );
+  });
+
+  it('should reshare the replica after the grace period', (done) => {
+    setTimeout(() => {
+      client.reshareReplica(
+        {
+          uuid: UUID,
+          share: 'REPLICA_ISCSI',
+          gracePeriod: 1
+        },
+        (err, res) => {
+          if (err) return done(err);
+          assert.match(res.uri, ISCSI_URI);
+          assert.match(res.previousUri, NVMF_URI);
+          done();
+        }
+      );
+    }, 1500);
+  });
+
   it('should unshare the replica', (done) => {
     client.shareReplica(
       {
@@ -437,6 +527,79 @@ describe('replica', function () {
     });
   });
 
+  it('should set QoS limits of the replica', (done) => {
+    client.setBdevQos(
+      {
+        uuid: UUID,
+        limits: { rw_ios_per_sec: 10000, w_mbytes_per_sec: 100 }
+      },
+      (err) => {
+        if (err) return done(err);
+        client.listReplicas({}, (err, res) => {
+          if (err) return done(err);
+          res = res.replicas.filter((ent) => ent.uuid === UUID);
+          assert.lengthOf(res, 1);
+          assert.equal(res[0].qos.rw_ios_per_sec, '10000');
+          assert.equal(res[0].qos.rw_mbytes_per_sec, '0');
+          assert.equal(res[0].qos.r_mbytes_per_sec, '0');
+          assert.equal(res[0].qos.w_mbytes_per_sec, '100');
+          done();
+        });
+      }
+    );
+  });
+
+  it('should fail to set an IOPS limit which is not a multiple of 1000', (done) => {
+    client.setBdevQos(
+      {
+        uuid: UUID,
+        limits: { rw_ios_per_sec: 1500 }
+      },
+      (err) => {
+        assert.equal(err.code, grpc.status.INVALID_ARGUMENT);
+        done();
+      }
+    );
+  });
+
+  it('should record the QoS calls in the audit log', (done) => {
+    client.getAuditLog({}, (err, res) => {
+      if (err) return done(err);
+      // listing the replicas is not recorded
+      const records = res.records.slice(-2);
+      assert.lengthOf(records, 2);
+      records.forEach((r) => {
+        assert.equal(r.method, '/mayastor.Mayastor/SetBdevQos');
+        assert.include(r.arguments, UUID);
+      });
+      assert.equal(records[0].code, grpc.status.OK);
+      assert.equal(records[1].code, grpc.status.INVALID_ARGUMENT);
+      assert.isNotEmpty(records[1].message);
+      done();
+    });
+  });
+
+  it('should fail to reload the config without a config file', (done) => {
+    client.reloadConfig({}, (err) => {
+      assert.equal(err.code, grpc.status.FAILED_PRECONDITION);
+      done();
+    });
+  });
+
+  it('should remove QoS limits of the replica', (done) => {
+    client.setBdevQos({ uuid: UUID }, (err) => {
+      if (err) return done(err);
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        res = res.replicas.filter((ent) => ent.uuid === UUID);
+        assert.lengthOf(res, 1);
+        assert.equal(res[0].qos.rw_ios_per_sec, '0');
+        assert.equal(res[0].qos.w_mbytes_per_sec, '0');
+        done();
+      });
+    });
+  });
+
   it('should succeed when destroying replica that does not exist', (done) => {
     const unknownUuid = 'c35fa4dd-d527-4b7b-9cf0-436b8bb0ba77';
     client.destroyReplica({ uuid: unknownUuid }, done);
@@ -578,6 +741,26 @@ describe('replica', function () {
     let uri; // URI of the created nvmf replica
     const blockFile = '/tmp/test_block'; // file with contents of a data block
 
+    // collect the ranges streamed by diffSnapshots
+    function diffSnapshots (args, done) {
+      const call = client.diffSnapshots(args);
+      const ranges = [];
+      let finished = false;
+      call.on('data', (res) => ranges.push(...res.ranges));
+      call.on('error', (err) => {
+        if (!finished) {
+          finished = true;
+          done(err);
+        }
+      });
+      call.on('end', () => {
+        if (!finished) {
+          finished = true;
+          done(null, ranges);
+        }
+      });
+    }
+
     // run unlink as root because the file was created by root
     function rmBlockFile (done) {
       common.execAsRoot('rm', ['-f', blockFile], (err) => {
@@ -716,6 +899,229 @@ describe('replica', function () {
       });
     });
 
+    it('should create a replica from the snapshot', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const snapshot = res.replicas.find((ent) => {
+          return ent.uuid.startsWith(UUID + '-snap-');
+        });
+        client.createReplicaFromSnapshot(
+          {
+            uuid: CLONE_UUID,
+            snapshot: snapshot.uuid,
+            share: 'REPLICA_NVMF'
+          },
+          (err, res) => {
+            if (err) return done(err);
+            assert.equal(res.uuid, CLONE_UUID);
+            assert.equal(res.pool, POOL);
+            assert.equal(res.thin, true);
+            assert.equal(res.size, 96 * 1024 * 1024);
+            assert.equal(res.share, 'REPLICA_NVMF');
+            assert.match(res.uri, NVMF_URI);
+            done();
+          }
+        );
+      });
+    });
+
+    it('should read the snapshot data from the clone', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const clone = res.replicas.find((ent) => ent.uuid === CLONE_UUID);
+        common.execAsRoot(
+          common.getCmdPath('initiator'),
+          ['--offset=4096', clone.uri, 'read', blockFile],
+          (err) => {
+            if (err) return done(err);
+            fs.readFile(blockFile, (err, data) => {
+              if (err) return done(err);
+              data = data.toString();
+              assert.lengthOf(data, 512);
+              assert.equal(data, 'm'.repeat(512));
+              done();
+            });
+          }
+        );
+      });
+    });
+
+    it('should list the ranges of the snapshot holding data', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const snapshot = res.replicas.find((ent) => {
+          return ent.uuid.startsWith(UUID + '-snap-');
+        });
+        diffSnapshots({ target: snapshot.uuid }, (err, ranges) => {
+          if (err) return done(err);
+          // the only write went to the first cluster
+          assert.lengthOf(ranges, 1);
+          assert.equal(ranges[0].offset, '0');
+          assert.equal(ranges[0].length, (4 * 1024 * 1024).toString());
+          done();
+        });
+      });
+    });
+
+    it('should list the ranges of the replica written since the snapshot', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const snapshot = res.replicas.find((ent) => {
+          return ent.uuid.startsWith(UUID + '-snap-');
+        });
+        async.series(
+          [
+            (next) => {
+              diffSnapshots(
+                { base: snapshot.uuid, target: UUID },
+                (err, ranges) => {
+                  if (err) return next(err);
+                  assert.lengthOf(ranges, 0);
+                  next();
+                }
+              );
+            },
+            (next) => {
+              common.execAsRoot(
+                common.getCmdPath('initiator'),
+                ['--offset=' + 8 * 1024 * 1024, uri, 'write', blockFile],
+                next
+              );
+            },
+            (next) => {
+              diffSnapshots(
+                { base: snapshot.uuid, target: UUID },
+                (err, ranges) => {
+                  if (err) return next(err);
+                  assert.lengthOf(ranges, 1);
+                  assert.equal(ranges[0].offset, (8 * 1024 * 1024).toString());
+                  assert.equal(ranges[0].length, (4 * 1024 * 1024).toString());
+                  next();
+                }
+              );
+            }
+          ],
+          done
+        );
+      });
+    });
+
+    it('should fail to diff against a replica which is not a snapshot', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const snapshot = res.replicas.find((ent) => {
+          return ent.uuid.startsWith(UUID + '-snap-');
+        });
+        diffSnapshots({ base: UUID, target: snapshot.uuid }, (err) => {
+          assert(err);
+          assert.equal(err.code, grpc.status.FAILED_PRECONDITION);
+          done();
+        });
+      });
+    });
+
+    it('should fail to create a replica from a replica which is not a snapshot', (done) => {
+      client.createReplicaFromSnapshot(
+        {
+          uuid: BASE_UUID + '9',
+          snapshot: UUID,
+          share: 'REPLICA_NONE'
+        },
+        (err) => {
+          assert(err);
+          assert.equal(err.code, grpc.status.FAILED_PRECONDITION);
+          done();
+        }
+      );
+    });
+
+    it('should fail to create a replica from a snapshot which does not exist', (done) => {
+      client.createReplicaFromSnapshot(
+        {
+          uuid: BASE_UUID + '9',
+          snapshot: UUID + '-snap-0',
+          share: 'REPLICA_NONE'
+        },
+        (err) => {
+          assert(err);
+          assert.equal(err.code, grpc.status.NOT_FOUND);
+          done();
+        }
+      );
+    });
+
+    it('should refuse to destroy a snapshot with clones', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const snapshot = res.replicas.find((ent) => {
+          return ent.uuid.startsWith(UUID + '-snap-');
+        });
+        client.destroyReplica({ uuid: snapshot.uuid }, (err) => {
+          assert(err);
+          assert.equal(err.code, grpc.status.FAILED_PRECONDITION);
+          done();
+        });
+      });
+    });
+
+    it('should destroy a snapshot after flattening its clones', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const snapshot = res.replicas.find((ent) => {
+          return ent.uuid.startsWith(UUID + '-snap-');
+        });
+        client.destroyReplica(
+          { uuid: snapshot.uuid, dependents: 'DEPENDENTS_FLATTEN' },
+          (err) => {
+            if (err) return done(err);
+            client.listReplicas({}, (err, res) => {
+              if (err) return done(err);
+              const names = res.replicas.map((ent) => ent.uuid);
+              assert.notInclude(names, snapshot.uuid);
+              assert.include(names, UUID);
+              assert.include(names, CLONE_UUID);
+              done();
+            });
+          }
+        );
+      });
+    });
+
+    it('should take snapshot on the replica created from the snapshot', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const clone = res.replicas.find((ent) => ent.uuid === CLONE_UUID);
+        common.execAsRoot(
+          common.getCmdPath('initiator'),
+          [clone.uri, 'create-snapshot'],
+          done
+        );
+      });
+    });
+
+    it('should destroy a snapshot together with its clones', (done) => {
+      client.listReplicas({}, (err, res) => {
+        if (err) return done(err);
+        const snapshot = res.replicas.find((ent) => {
+          return ent.uuid.startsWith(CLONE_UUID + '-snap-');
+        });
+        client.destroyReplica(
+          { uuid: snapshot.uuid, dependents: 'DEPENDENTS_CASCADE' },
+          (err) => {
+            if (err) return done(err);
+            client.listReplicas({}, (err, res) => {
+              if (err) return done(err);
+              const names = res.replicas.map((ent) => ent.uuid);
+              assert.notInclude(names, snapshot.uuid);
+              assert.notInclude(names, CLONE_UUID);
+              assert.include(names, UUID);
+              done();
+            });
+          }
+        );
+      });
+    });
+
     it('should destroy nvmf replica', (done) => {
       client.destroyReplica({ uuid: UUID }, (err, res) => {
         if (err) return done(err);
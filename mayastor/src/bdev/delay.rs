@@ -0,0 +1,168 @@
+//! Latency injection into the IO of the bdevs created with the delay:// URI
+//! scheme, for testing the read policies and latency objectives of the
+//! nexus against children which are slower than others.
+//!
+//! The delay module of SPDK passes the IO on to the bdev underneath. It is
+//! created without any latency of its own, as the latency of every IO is
+//! drawn from the range given by the fixed latency and the jitter of its
+//! kind, which is not something the delay module can do. The IO of our
+//! bdevs is intercepted instead, see bdev::interpose, to hold back every IO
+//! for the latency drawn before passing it on.
+//!
+//! The latencies can be changed at any time with [`set`], and only apply to
+//! the IOs submitted from then on.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use rand::Rng;
+
+use spdk_sys::{
+    spdk_bdev_fn_table,
+    spdk_bdev_io,
+    spdk_bdev_io_type,
+    spdk_io_channel,
+    SPDK_BDEV_IO_TYPE_READ,
+    SPDK_BDEV_IO_TYPE_UNMAP,
+    SPDK_BDEV_IO_TYPE_WRITE,
+    SPDK_BDEV_IO_TYPE_WRITE_ZEROES,
+};
+
+use crate::{
+    bdev::interpose::{self, Interpose},
+    core::Bdev,
+};
+
+/// the latency of the IOs of one kind
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IoDelay {
+    /// the latency every IO is held back for at least
+    pub latency: Duration,
+    /// the most an IO is held back for in addition, drawn uniformly
+    pub jitter: Duration,
+}
+
+impl IoDelay {
+    /// draw the latency of an IO
+    fn draw(&self) -> Duration {
+        let jitter = self.jitter.as_micros() as u64;
+        if jitter == 0 {
+            self.latency
+        } else {
+            self.latency
+                + Duration::from_micros(
+                    rand::thread_rng().gen_range(0, jitter + 1),
+                )
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.latency == Duration::default()
+            && self.jitter == Duration::default()
+    }
+}
+
+/// the latencies of a delay bdev
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Delays {
+    pub read: IoDelay,
+    /// writes, write zeroes and unmaps
+    pub write: IoDelay,
+}
+
+/// the latency of the IOs of one kind of a delay bdev, in microseconds
+#[derive(Default)]
+struct Latency {
+    latency: AtomicU64,
+    jitter: AtomicU64,
+}
+
+impl Latency {
+    fn load(&self) -> IoDelay {
+        IoDelay {
+            latency: Duration::from_micros(
+                self.latency.load(Ordering::Relaxed),
+            ),
+            jitter: Duration::from_micros(self.jitter.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn store(&self, delay: IoDelay) {
+        self.latency.store(delay.latency.as_micros() as u64, Ordering::Relaxed);
+        self.jitter.store(delay.jitter.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// a bdev into which latency can be injected
+#[derive(Default)]
+struct Delayed {
+    read: Latency,
+    write: Latency,
+}
+
+impl Delayed {
+    fn load(&self) -> Delays {
+        Delays {
+            read: self.read.load(),
+            write: self.write.load(),
+        }
+    }
+
+    fn store(&self, delays: Delays) {
+        self.read.store(delays.read);
+        self.write.store(delays.write);
+    }
+}
+
+/// allow latency to be injected into the bdev by intercepting its IO
+pub(crate) fn install(bdev: &Bdev, delays: Delays) {
+    let delayed = Delayed::default();
+    delayed.store(delays);
+    interpose::install(bdev, delayed, |_| {});
+}
+
+/// stop intercepting the IO of the bdev, as it is about to be destroyed,
+/// it is passed on from now on
+pub(crate) fn forget(bdev: &Bdev) {
+    interpose::forget(bdev);
+}
+
+/// set the latencies of the delay bdev, returns false if there is no delay
+/// bdev with the given name
+pub fn set(name: &str, delays: Delays) -> bool {
+    interpose::with_name(name, |bdev: &Delayed| {
+        info!("{}: setting latencies {:?}", name, delays);
+        bdev.store(delays);
+    })
+    .is_some()
+}
+
+/// returns the latencies of the delay bdev
+pub fn get(name: &str) -> Option<Delays> {
+    interpose::with_name(name, Delayed::load)
+}
+
+impl Interpose for Delayed {
+    fn submit_request(
+        &self,
+        module: &'static spdk_bdev_fn_table,
+        ch: *mut spdk_io_channel,
+        io: *mut spdk_bdev_io,
+    ) {
+        let delay = match unsafe { (*io).type_ } as spdk_bdev_io_type {
+            SPDK_BDEV_IO_TYPE_READ => self.read.load(),
+            SPDK_BDEV_IO_TYPE_WRITE
+            | SPDK_BDEV_IO_TYPE_WRITE_ZEROES
+            | SPDK_BDEV_IO_TYPE_UNMAP => self.write.load(),
+            _ => IoDelay::default(),
+        };
+
+        if delay.is_zero() {
+            interpose::pass_on(module, ch, io)
+        } else {
+            interpose::pass_on_after(module, ch, io, delay.draw())
+        }
+    }
+}
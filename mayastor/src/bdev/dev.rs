@@ -31,12 +31,20 @@ use crate::{
 };
 
 mod aio;
+mod delay;
+mod error;
 mod iscsi;
+pub(crate) mod link;
 mod loopback;
 mod malloc;
+pub(crate) mod memory;
+#[cfg(feature = "mock-children")]
+mod mock;
+mod null;
 mod nvme;
 mod nvmf;
 mod uring;
+mod write_protect;
 
 impl Uri {
     pub fn parse(
@@ -53,6 +61,19 @@ impl Uri {
             // really should not be used other than for testing
             "aio" => Ok(Box::new(aio::Aio::try_from(&url)?)),
             "malloc" => Ok(Box::new(malloc::Malloc::try_from(&url)?)),
+            "null" => Ok(Box::new(null::Null::try_from(&url)?)),
+
+            // pass-through devices with programmable faults and latencies
+            // for testing
+            "error" => Ok(Box::new(error::ErrorBdev::try_from(&url)?)),
+            "delay" => Ok(Box::new(delay::Delay::try_from(&url)?)),
+
+            // in memory devices with programmable behaviour for IO path tests
+            #[cfg(feature = "mock-children")]
+            "mock" => Ok(Box::new(mock::Mock::try_from(&url)?)),
+
+            // disks by the links udev maintains for them, opened through aio
+            "partuuid" | "devlink" => Ok(Box::new(link::resolve(&url)?)),
 
             // retain this for the time being for backwards compatibility
             "bdev" => Ok(Box::new(loopback::Loopback::try_from(&url)?)),
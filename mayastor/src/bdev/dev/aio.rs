@@ -8,7 +8,7 @@ use url::Url;
 use spdk_sys::{bdev_aio_delete, create_aio_bdev};
 
 use crate::{
-    bdev::{util::uri, CreateDestroy, GetName},
+    bdev::{dev::write_protect, util::uri, CreateDestroy, GetName},
     core::Bdev,
     ffihelper::{cb_arg, done_errno_cb, errno_result_from_i32, ErrnoResult},
     nexus_uri::{self, NexusBdevError},
@@ -19,6 +19,7 @@ pub(super) struct Aio {
     name: String,
     alias: String,
     blk_size: u32,
+    readonly: bool,
     uuid: Option<uuid::Uuid>,
 }
 
@@ -49,6 +50,23 @@ impl TryFrom<&Url> for Aio {
             None => 512,
         };
 
+        if blk_size < 512 || !blk_size.is_power_of_two() {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: format!("invalid blk_size {}", blk_size),
+            });
+        }
+
+        let readonly = match parameters.remove("readonly") {
+            Some(value) => uri::boolean(&value, true).context(
+                nexus_uri::BoolParamParseError {
+                    uri: url.to_string(),
+                    parameter: String::from("readonly"),
+                },
+            )?,
+            None => false,
+        };
+
         let uuid = uri::uuid(parameters.remove("uuid")).context(
             nexus_uri::UuidParamParseError {
                 uri: url.to_string(),
@@ -63,11 +81,20 @@ impl TryFrom<&Url> for Aio {
             name: url.path().into(),
             alias: url.to_string(),
             blk_size,
+            readonly,
             uuid,
         })
     }
 }
 
+impl Aio {
+    /// the device is known by another URI, which it has been resolved from
+    pub(super) fn with_alias(mut self, url: &Url) -> Self {
+        self.alias = url.to_string();
+        self
+    }
+}
+
 impl GetName for Aio {
     fn get_name(&self) -> String {
         self.name.clone()
@@ -109,6 +136,9 @@ impl CreateDestroy for Aio {
                                 self.get_name()
                             );
                         }
+                        if self.readonly {
+                            write_protect::protect(&bdev);
+                        }
                     };
                     name
                 })
@@ -120,6 +150,7 @@ impl CreateDestroy for Aio {
     async fn destroy(self: Box<Self>) -> Result<(), Self::Error> {
         match Bdev::lookup_by_name(&self.name) {
             Some(bdev) => {
+                write_protect::forget(&bdev);
                 let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
                 unsafe {
                     bdev_aio_delete(
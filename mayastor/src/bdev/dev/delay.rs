@@ -0,0 +1,200 @@
+//! The delay bdev is a pass-through bdev on top of an existing bdev, which
+//! holds back its IO for a fixed latency plus a random jitter, given per
+//! kind of IO. The latencies can be changed with the functions in
+//! bdev::delay, or over gRPC. It is meant for testing the read policies
+//! and latency objectives of the nexus without having to slow down real
+//! devices.
+//!
+//! A delay bdev is created on the bdev named by the URI path, i.e.
+//! delay:///malloc0?read_us=500&read_jitter_us=100, and is named after it
+//! with a prefix, i.e. delay_malloc0. Destroying it leaves the bdev
+//! underneath alone.
+use std::{collections::HashMap, convert::TryFrom, time::Duration};
+
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use nix::errno::Errno;
+use snafu::ResultExt;
+use url::Url;
+
+use spdk_sys::{create_delay_disk, delete_delay_disk};
+
+use crate::{
+    bdev::{
+        delay::{self, Delays, IoDelay},
+        util::uri,
+        CreateDestroy,
+        GetName,
+    },
+    core::Bdev,
+    ffihelper::{cb_arg, done_errno_cb, ErrnoResult, IntoCString},
+    nexus_uri::{self, NexusBdevError},
+};
+
+/// the prefix of the name of a delay bdev
+const DELAY_BDEV_PREFIX: &str = "delay_";
+
+#[derive(Debug)]
+pub(super) struct Delay {
+    /// the name of the bdev the delay bdev is created on
+    base: String,
+    /// alias which can be used to open the bdev
+    alias: String,
+    /// the latencies the bdev is created with
+    delays: Delays,
+    /// uuid of the spdk bdev
+    uuid: Option<uuid::Uuid>,
+}
+
+/// remove the parameter given in microseconds from the parameters
+fn micros(
+    url: &Url,
+    parameters: &mut HashMap<String, String>,
+    parameter: &str,
+) -> Result<Duration, NexusBdevError> {
+    match parameters.remove(parameter) {
+        Some(value) => value.parse().map(Duration::from_micros).context(
+            nexus_uri::IntParamParseError {
+                uri: url.to_string(),
+                parameter: String::from(parameter),
+            },
+        ),
+        None => Ok(Duration::default()),
+    }
+}
+
+impl TryFrom<&Url> for Delay {
+    type Error = NexusBdevError;
+
+    fn try_from(url: &Url) -> Result<Self, Self::Error> {
+        let segments = uri::segments(url);
+
+        if segments.is_empty() {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: String::from("no path segments"),
+            });
+        }
+
+        let mut parameters: HashMap<String, String> =
+            url.query_pairs().into_owned().collect();
+
+        let delays = Delays {
+            read: IoDelay {
+                latency: micros(url, &mut parameters, "read_us")?,
+                jitter: micros(url, &mut parameters, "read_jitter_us")?,
+            },
+            write: IoDelay {
+                latency: micros(url, &mut parameters, "write_us")?,
+                jitter: micros(url, &mut parameters, "write_jitter_us")?,
+            },
+        };
+
+        let uuid = uri::uuid(parameters.remove("uuid")).context(
+            nexus_uri::UuidParamParseError {
+                uri: url.to_string(),
+            },
+        )?;
+
+        if let Some(keys) = uri::keys(parameters) {
+            warn!("ignored parameters: {}", keys);
+        }
+
+        Ok(Self {
+            base: segments.join("/"),
+            alias: url.to_string(),
+            delays,
+            uuid,
+        })
+    }
+}
+
+impl GetName for Delay {
+    fn get_name(&self) -> String {
+        format!("{}{}", DELAY_BDEV_PREFIX, self.base)
+    }
+}
+
+#[async_trait(?Send)]
+impl CreateDestroy for Delay {
+    type Error = NexusBdevError;
+
+    async fn create(&self) -> Result<String, Self::Error> {
+        if Bdev::lookup_by_name(&self.get_name()).is_some() {
+            return Err(NexusBdevError::BdevExists {
+                name: self.get_name(),
+            });
+        }
+
+        // the delay module would otherwise wait for the bdev to show up
+        if Bdev::lookup_by_name(&self.base).is_none() {
+            return Err(NexusBdevError::BdevNotFound {
+                name: self.base.clone(),
+            });
+        }
+
+        // the latencies are injected by us, see bdev::delay
+        let cbase = self.base.clone().into_cstring();
+        let cname = self.get_name().into_cstring();
+        let errno = unsafe {
+            create_delay_disk(cbase.as_ptr(), cname.as_ptr(), 0, 0, 0, 0)
+        };
+        if errno != 0 {
+            return Err(NexusBdevError::CreateBdev {
+                source: Errno::from_i32(errno.abs()),
+                name: self.get_name(),
+            });
+        }
+
+        let mut bdev =
+            Bdev::lookup_by_name(&self.get_name()).ok_or_else(|| {
+                NexusBdevError::BdevNotFound {
+                    name: self.get_name(),
+                }
+            })?;
+
+        delay::install(&bdev, self.delays);
+
+        if let Some(uuid) = self.uuid {
+            bdev.set_uuid(Some(uuid.to_string()));
+        }
+        if !bdev.add_alias(&self.alias) {
+            error!(
+                "Failed to add alias {} to device {}",
+                self.alias,
+                self.get_name()
+            );
+        }
+
+        Ok(self.get_name())
+    }
+
+    async fn destroy(self: Box<Self>) -> Result<(), Self::Error> {
+        let name = self.get_name();
+
+        if let Some(bdev) = Bdev::lookup_by_name(&name) {
+            delay::forget(&bdev);
+
+            let (s, r) = oneshot::channel::<ErrnoResult<()>>();
+            unsafe {
+                delete_delay_disk(
+                    bdev.as_ptr(),
+                    Some(done_errno_cb),
+                    cb_arg(s),
+                )
+            };
+
+            r.await
+                .context(nexus_uri::CancelBdev {
+                    name: name.clone(),
+                })?
+                .context(nexus_uri::DestroyBdev {
+                    name,
+                })
+        } else {
+            Err(NexusBdevError::BdevNotFound {
+                name,
+            })
+        }
+    }
+}
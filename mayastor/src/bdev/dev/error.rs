@@ -0,0 +1,158 @@
+//! The error bdev is a pass-through bdev on top of an existing bdev, into
+//! the IO of which faults can be injected with the functions in
+//! bdev::fault, or over gRPC. It is meant for testing the fault handling
+//! and rebuild of the nexus without having to break real devices.
+//!
+//! An error bdev is created on the bdev named by the URI path, i.e.
+//! error:///malloc0, and is named after it with the prefix of the error
+//! module of SPDK, i.e. EE_malloc0. Destroying it leaves the bdev
+//! underneath alone.
+use std::{collections::HashMap, convert::TryFrom};
+
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use nix::errno::Errno;
+use snafu::ResultExt;
+use url::Url;
+
+use spdk_sys::{vbdev_error_create, vbdev_error_delete};
+
+use crate::{
+    bdev::{fault, util::uri, CreateDestroy, GetName},
+    core::Bdev,
+    ffihelper::{cb_arg, done_errno_cb, ErrnoResult, IntoCString},
+    nexus_uri::{self, NexusBdevError},
+};
+
+/// the prefix the error module of SPDK names its bdevs with
+const ERROR_BDEV_PREFIX: &str = "EE_";
+
+#[derive(Debug)]
+pub(super) struct ErrorBdev {
+    /// the name of the bdev the error bdev is created on
+    base: String,
+    /// alias which can be used to open the bdev
+    alias: String,
+    /// uuid of the spdk bdev
+    uuid: Option<uuid::Uuid>,
+}
+
+impl TryFrom<&Url> for ErrorBdev {
+    type Error = NexusBdevError;
+
+    fn try_from(url: &Url) -> Result<Self, Self::Error> {
+        let segments = uri::segments(url);
+
+        if segments.is_empty() {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: String::from("no path segments"),
+            });
+        }
+
+        let mut parameters: HashMap<String, String> =
+            url.query_pairs().into_owned().collect();
+
+        let uuid = uri::uuid(parameters.remove("uuid")).context(
+            nexus_uri::UuidParamParseError {
+                uri: url.to_string(),
+            },
+        )?;
+
+        if let Some(keys) = uri::keys(parameters) {
+            warn!("ignored parameters: {}", keys);
+        }
+
+        Ok(Self {
+            base: segments.join("/"),
+            alias: url.to_string(),
+            uuid,
+        })
+    }
+}
+
+impl GetName for ErrorBdev {
+    fn get_name(&self) -> String {
+        format!("{}{}", ERROR_BDEV_PREFIX, self.base)
+    }
+}
+
+#[async_trait(?Send)]
+impl CreateDestroy for ErrorBdev {
+    type Error = NexusBdevError;
+
+    async fn create(&self) -> Result<String, Self::Error> {
+        if Bdev::lookup_by_name(&self.get_name()).is_some() {
+            return Err(NexusBdevError::BdevExists {
+                name: self.get_name(),
+            });
+        }
+
+        // the error module would otherwise wait for the bdev to show up
+        if Bdev::lookup_by_name(&self.base).is_none() {
+            return Err(NexusBdevError::BdevNotFound {
+                name: self.base.clone(),
+            });
+        }
+
+        let cname = self.base.clone().into_cstring();
+        let errno = unsafe { vbdev_error_create(cname.as_ptr()) };
+        if errno != 0 {
+            return Err(NexusBdevError::CreateBdev {
+                source: Errno::from_i32(errno.abs()),
+                name: self.get_name(),
+            });
+        }
+
+        let mut bdev =
+            Bdev::lookup_by_name(&self.get_name()).ok_or_else(|| {
+                NexusBdevError::BdevNotFound {
+                    name: self.get_name(),
+                }
+            })?;
+
+        fault::install(&bdev);
+
+        if let Some(uuid) = self.uuid {
+            bdev.set_uuid(Some(uuid.to_string()));
+        }
+        if !bdev.add_alias(&self.alias) {
+            error!(
+                "Failed to add alias {} to device {}",
+                self.alias,
+                self.get_name()
+            );
+        }
+
+        Ok(self.get_name())
+    }
+
+    async fn destroy(self: Box<Self>) -> Result<(), Self::Error> {
+        let name = self.get_name();
+
+        if let Some(bdev) = Bdev::lookup_by_name(&name) {
+            fault::forget(&bdev);
+
+            let (s, r) = oneshot::channel::<ErrnoResult<()>>();
+            unsafe {
+                vbdev_error_delete(
+                    bdev.as_ptr(),
+                    Some(done_errno_cb),
+                    cb_arg(s),
+                )
+            };
+
+            r.await
+                .context(nexus_uri::CancelBdev {
+                    name: name.clone(),
+                })?
+                .context(nexus_uri::DestroyBdev {
+                    name,
+                })
+        } else {
+            Err(NexusBdevError::BdevNotFound {
+                name,
+            })
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Devices referenced by one of the links udev maintains for them, rather
+//! than by their kernel name which may change from one boot to the next:
+//!
+//! - `partuuid://<uuid>` for a GPT partition by its unique partition GUID
+//! - `devlink://<kind>/<name>` for any link in /dev/disk/<kind>, i.e.
+//!   `devlink://by-id/nvme-INTEL_SSDPE2KX010T8_BTLJ0000000001P0FGN-part1`
+//!
+//! The link is resolved every time the URI is parsed, so when a pool is
+//! imported, and the device is then opened through aio as if its current
+//! kernel name had been given. The URI is kept as the alias of the bdev, so
+//! the pool is reported and saved in the config by the link it was created
+//! with.
+
+use std::{
+    convert::TryFrom,
+    path::{Path, PathBuf},
+};
+
+use url::Url;
+
+use crate::{
+    bdev::{dev::aio::Aio, util::uri},
+    core::Bdev,
+    nexus_uri::NexusBdevError,
+};
+
+/// where udev maintains the links to the disks
+const DISK_LINKS: &str = "/dev/disk";
+
+/// the schemes of the URIs which are resolved here
+const SCHEMES: [&str; 2] = ["partuuid", "devlink"];
+
+/// the path of the link the URI refers to
+fn link_path(url: &Url) -> Result<PathBuf, NexusBdevError> {
+    let invalid = |message: &str| NexusBdevError::UriInvalid {
+        uri: url.to_string(),
+        message: message.to_string(),
+    };
+
+    let host = url.host_str().ok_or_else(|| invalid("no host"))?;
+    let segments = uri::segments(url);
+
+    match url.scheme() {
+        "partuuid" => {
+            if !segments.is_empty() {
+                return Err(invalid("unexpected path segments"));
+            }
+            // udev creates the links with the partition GUID in lower case
+            Ok(Path::new(DISK_LINKS)
+                .join("by-partuuid")
+                .join(host.to_lowercase()))
+        }
+        "devlink" => {
+            if !host.starts_with("by-") {
+                return Err(invalid("unknown kind of device link"));
+            }
+            if segments.is_empty() || segments.iter().any(|s| *s == "..") {
+                return Err(invalid("invalid device link name"));
+            }
+            Ok(Path::new(DISK_LINKS).join(host).join(segments.join("/")))
+        }
+        scheme => Err(NexusBdevError::UriSchemeUnsupported {
+            scheme: scheme.to_string(),
+        }),
+    }
+}
+
+/// Resolve the link the URI refers to and parse it as an aio device on the
+/// path the link currently points to. Any parameters of the URI are passed
+/// on as is.
+pub(super) fn resolve(url: &Url) -> Result<Aio, NexusBdevError> {
+    let link = link_path(url)?;
+    let device =
+        link.canonicalize()
+            .map_err(|e| NexusBdevError::ResolveLink {
+                uri: url.to_string(),
+                link: link.display().to_string(),
+                message: e.to_string(),
+            })?;
+
+    let mut target = Url::parse("aio:///").unwrap();
+    target.set_path(&device.to_string_lossy());
+    target.set_query(url.query());
+    debug!("{} resolves to {}", url, device.display());
+
+    Ok(Aio::try_from(&target)?.with_alias(url))
+}
+
+/// The URI of a device link which the bdev was created from, if any.
+pub(crate) fn link_uri(bdev: &Bdev) -> Option<String> {
+    bdev.aliases().into_iter().find(|alias| {
+        Url::parse(alias).map_or(false, |url| SCHEMES.contains(&url.scheme()))
+    })
+}
@@ -0,0 +1,158 @@
+//! Accounting of the memory used by the nvme controllers of nvmf and pcie
+//! children.
+//!
+//! Every attached controller has an admin queue pair, and an IO queue pair
+//! for every core doing IO to it, each with its own set of requests and, for
+//! NVMe over TCP, the PDUs to go with them. With hundreds of replicas
+//! connected to a node this adds up. The memory is estimated when a
+//! controller is attached and the total can be capped in the nexus options,
+//! such that attaching more children fails instead of the node running out
+//! of memory.
+
+use std::{collections::HashMap, fmt::Display, sync::Mutex};
+
+use once_cell::sync::Lazy;
+
+use crate::{core::Cores, nexus_uri::NexusBdevError, subsys::Config};
+
+/// requests per IO queue pair when not configured (DEFAULT_IO_QUEUE_REQUESTS
+/// of the nvme driver)
+const DEFAULT_IO_QUEUE_REQUESTS: u64 = 512;
+
+/// requests of the admin queue pair (DEFAULT_ADMIN_QUEUE_REQUESTS)
+const ADMIN_QUEUE_REQUESTS: u64 = 32;
+
+/// approximate size of a request, including the PDU of the TCP transport
+const TCP_REQUEST_BYTES: u64 = 1536;
+
+/// approximate size of a request, including the registered send and receive
+/// buffers of the RDMA transport
+const RDMA_REQUEST_BYTES: u64 = 1024;
+
+/// approximate size of a request, including the command slot of the PCIe
+/// transport
+const PCIE_REQUEST_BYTES: u64 = 512;
+
+/// transport of an nvme controller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Tcp,
+    Rdma,
+    Pcie,
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::Rdma => write!(f, "rdma"),
+            Transport::Pcie => write!(f, "pcie"),
+        }
+    }
+}
+
+/// memory used by the controller of a child
+#[derive(Debug, Clone)]
+pub struct ChildMemory {
+    /// name of the nvme controller
+    pub name: String,
+    pub transport: Transport,
+    /// queue pairs, including the admin queue pair
+    pub qpairs: u64,
+    /// requests allocated over all queue pairs
+    pub requests: u64,
+    /// estimate of the memory in bytes
+    pub bytes: u64,
+}
+
+static CHILDREN: Lazy<Mutex<HashMap<String, ChildMemory>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// reservation of memory for a controller which is being attached, the
+/// memory is released again when dropped unless the attach succeeded
+pub(crate) struct Reservation(Option<String>);
+
+impl Reservation {
+    /// keep the memory accounted for, as the controller is attached now
+    pub(crate) fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(name) = self.0.take() {
+            release(&name);
+        }
+    }
+}
+
+fn estimate(name: &str, transport: Transport) -> ChildMemory {
+    let io_queue_requests =
+        match Config::get().nvme_bdev_opts.io_queue_requests as u64 {
+            0 => DEFAULT_IO_QUEUE_REQUESTS,
+            n => n,
+        };
+    // the nexus opens an IO channel, and thereby a queue pair, on every core
+    let io_qpairs = Cores::count().into_iter().count() as u64;
+    let requests = ADMIN_QUEUE_REQUESTS + io_qpairs * io_queue_requests;
+
+    ChildMemory {
+        name: name.to_string(),
+        transport,
+        qpairs: io_qpairs + 1,
+        requests,
+        bytes: requests
+            * match transport {
+                Transport::Tcp => TCP_REQUEST_BYTES,
+                Transport::Rdma => RDMA_REQUEST_BYTES,
+                Transport::Pcie => PCIE_REQUEST_BYTES,
+            },
+    }
+}
+
+/// account for the memory of a controller which is about to be attached,
+/// failing if that would exceed the limit
+pub(crate) fn reserve(
+    name: &str,
+    transport: Transport,
+) -> Result<Reservation, NexusBdevError> {
+    let child = estimate(name, transport);
+    let limit = limit();
+    let mut children = CHILDREN.lock().unwrap();
+    let used = children.values().map(|c| c.bytes).sum::<u64>();
+
+    if limit > 0 && used + child.bytes > limit {
+        return Err(NexusBdevError::MemoryLimit {
+            name: name.to_string(),
+            used,
+            limit,
+        });
+    }
+
+    children.insert(name.to_string(), child);
+    Ok(Reservation(Some(name.to_string())))
+}
+
+/// stop accounting for the memory of a detached controller
+pub(crate) fn release(name: &str) {
+    CHILDREN.lock().unwrap().remove(name);
+}
+
+/// limit of the memory of all controllers together in bytes, 0 if there is
+/// none
+pub fn limit() -> u64 {
+    Config::get().nexus_opts.nvme_children_mem_limit_mb * 1024 * 1024
+}
+
+/// list the memory used by the controllers of all attached children
+pub fn children() -> Vec<ChildMemory> {
+    let mut list = CHILDREN
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    list
+}
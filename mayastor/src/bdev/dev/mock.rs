@@ -0,0 +1,159 @@
+//! The mock bdev stores its data in ordinary memory and its behaviour can be
+//! programmed with the functions in bdev::mock. It is meant for testing the
+//! nexus IO path only and is not available unless mayastor is built with the
+//! mock-children feature.
+use std::{collections::HashMap, convert::TryFrom};
+
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use nix::errno::Errno;
+use snafu::ResultExt;
+use url::Url;
+use uuid::Uuid;
+
+use spdk_sys::spdk_bdev_unregister;
+
+use crate::{
+    bdev::{mock, util::uri, CreateDestroy, GetName},
+    core::Bdev,
+    ffihelper::{cb_arg, done_errno_cb, ErrnoResult},
+    nexus_uri::{self, NexusBdevError},
+};
+
+#[derive(Debug)]
+pub struct Mock {
+    /// the name of the bdev we created, this is equal to the URI path minus
+    /// the leading '/'
+    name: String,
+    /// alias which can be used to open the bdev
+    alias: String,
+    /// the number of blocks the device should have
+    num_blocks: u64,
+    /// the size of a single block, defaults to 512
+    blk_size: u32,
+    /// uuid of the spdk bdev
+    uuid: Uuid,
+}
+
+impl TryFrom<&Url> for Mock {
+    type Error = NexusBdevError;
+
+    fn try_from(uri: &Url) -> Result<Self, Self::Error> {
+        let segments = uri::segments(uri);
+        if segments.is_empty() {
+            return Err(NexusBdevError::UriInvalid {
+                uri: uri.to_string(),
+                message: "no path segments".to_string(),
+            });
+        }
+
+        let mut parameters: HashMap<String, String> =
+            uri.query_pairs().into_owned().collect();
+
+        let blk_size: u32 = if let Some(value) = parameters.remove("blk_size") {
+            value.parse().context(nexus_uri::IntParamParseError {
+                uri: uri.to_string(),
+                parameter: String::from("blk_size"),
+            })?
+        } else {
+            512
+        };
+
+        if blk_size != 512 && blk_size != 4096 {
+            return Err(NexusBdevError::UriInvalid {
+                uri: uri.to_string(),
+                message:
+                    "invalid blk_size specified must be one of 512 or 4096"
+                        .to_string(),
+            });
+        }
+
+        let size: u64 = if let Some(value) = parameters.remove("size_mb") {
+            value.parse().context(nexus_uri::IntParamParseError {
+                uri: uri.to_string(),
+                parameter: String::from("size_mb"),
+            })?
+        } else {
+            64
+        };
+
+        let uuid = uri::uuid(parameters.remove("uuid")).context(
+            nexus_uri::UuidParamParseError {
+                uri: uri.to_string(),
+            },
+        )?;
+
+        if let Some(keys) = uri::keys(parameters) {
+            warn!("ignored parameters: {}", keys);
+        }
+
+        Ok(Self {
+            name: uri.path()[1 ..].into(),
+            alias: uri.to_string(),
+            num_blocks: (size << 20) / u64::from(blk_size),
+            blk_size,
+            uuid: uuid.unwrap_or_else(Uuid::new_v4),
+        })
+    }
+}
+
+impl GetName for Mock {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl CreateDestroy for Mock {
+    type Error = NexusBdevError;
+
+    async fn create(&self) -> Result<String, Self::Error> {
+        if Bdev::lookup_by_name(&self.name).is_some() {
+            return Err(NexusBdevError::BdevExists {
+                name: self.name.clone(),
+            });
+        }
+
+        let mut bdev = mock::create(&self.name, self.num_blocks, self.blk_size)
+            .map_err(|errno| NexusBdevError::CreateBdev {
+                source: Errno::from_i32(errno),
+                name: self.name.clone(),
+            })?;
+
+        bdev.set_uuid(Some(self.uuid.to_string()));
+        if !bdev.add_alias(&self.alias) {
+            error!(
+                "Failed to add alias {} to device {}",
+                self.alias,
+                self.get_name()
+            );
+        }
+
+        Ok(self.name.clone())
+    }
+
+    async fn destroy(self: Box<Self>) -> Result<(), Self::Error> {
+        if let Some(bdev) = Bdev::lookup_by_name(&self.name) {
+            let (s, r) = oneshot::channel::<ErrnoResult<()>>();
+            unsafe {
+                spdk_bdev_unregister(
+                    bdev.as_ptr(),
+                    Some(done_errno_cb),
+                    cb_arg(s),
+                )
+            };
+
+            r.await
+                .context(nexus_uri::CancelBdev {
+                    name: self.name.clone(),
+                })?
+                .context(nexus_uri::DestroyBdev {
+                    name: self.name,
+                })
+        } else {
+            Err(NexusBdevError::BdevNotFound {
+                name: self.name,
+            })
+        }
+    }
+}
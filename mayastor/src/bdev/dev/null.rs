@@ -0,0 +1,217 @@
+//! The null bdev throws away what is written to it and reads back whatever
+//! is in the buffer, which makes it suitable for benchmarking the nexus and
+//! the targets without real media. With the verify parameter, every block
+//! read is stamped with a pattern and every block written is checked
+//! against it, see bdev::pattern, to catch the corruption of data on its
+//! way through them.
+//!
+//! null:///null0?size_mb=1024&blk_size=4096&verify=true
+use std::{collections::HashMap, convert::TryFrom};
+
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use nix::errno::Errno;
+use snafu::ResultExt;
+use url::Url;
+
+use spdk_sys::{bdev_null_create, bdev_null_delete, spdk_null_bdev_opts};
+
+use crate::{
+    bdev::{pattern, util::uri, CreateDestroy, GetName},
+    core::Bdev,
+    ffihelper::{cb_arg, done_errno_cb, ErrnoResult, IntoCString},
+    nexus_uri::{self, NexusBdevError},
+};
+
+#[derive(Debug)]
+pub(super) struct Null {
+    /// the name of the bdev we created, this is equal to the URI path minus
+    /// the leading '/'
+    name: String,
+    /// alias which can be used to open the bdev
+    alias: String,
+    /// the number of blocks the device should have
+    num_blocks: u64,
+    /// the size of a single block, 512 if no blk_size is given
+    blk_size: u32,
+    /// stamp the blocks read and verify the blocks written
+    verify: bool,
+    /// uuid of the spdk bdev
+    uuid: Option<uuid::Uuid>,
+}
+
+impl TryFrom<&Url> for Null {
+    type Error = NexusBdevError;
+
+    fn try_from(url: &Url) -> Result<Self, Self::Error> {
+        let segments = uri::segments(url);
+
+        if segments.is_empty() {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: String::from("no path segments"),
+            });
+        }
+
+        let mut parameters: HashMap<String, String> =
+            url.query_pairs().into_owned().collect();
+
+        let mut number = |parameter: &str| -> Result<u64, NexusBdevError> {
+            match parameters.remove(parameter) {
+                Some(value) => {
+                    value.parse().context(nexus_uri::IntParamParseError {
+                        uri: url.to_string(),
+                        parameter: String::from(parameter),
+                    })
+                }
+                None => Ok(0),
+            }
+        };
+
+        let blk_size = match number("blk_size")? {
+            0 => 512,
+            value => value,
+        };
+        let size_mb = number("size_mb")?;
+        let num_blocks = number("num_blocks")?;
+
+        if blk_size % 512 != 0 || blk_size > 65536 {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: format!("invalid blk_size {}", blk_size),
+            });
+        }
+
+        let num_blocks = match (size_mb, num_blocks) {
+            (0, 0) => {
+                return Err(NexusBdevError::UriInvalid {
+                    uri: url.to_string(),
+                    message: String::from("size_mb or num_blocks is required"),
+                })
+            }
+            (size_mb, 0) => (size_mb << 20) / blk_size,
+            (0, num_blocks) => num_blocks,
+            _ => {
+                return Err(NexusBdevError::UriInvalid {
+                    uri: url.to_string(),
+                    message: String::from(
+                        "conflicting parameters num_blocks and size_mb are mutually exclusive",
+                    ),
+                })
+            }
+        };
+
+        let verify = match parameters.remove("verify") {
+            Some(value) => uri::boolean(&value, true).context(
+                nexus_uri::BoolParamParseError {
+                    uri: url.to_string(),
+                    parameter: String::from("verify"),
+                },
+            )?,
+            None => false,
+        };
+
+        let uuid = uri::uuid(parameters.remove("uuid")).context(
+            nexus_uri::UuidParamParseError {
+                uri: url.to_string(),
+            },
+        )?;
+
+        if let Some(keys) = uri::keys(parameters) {
+            warn!("ignored parameters: {}", keys);
+        }
+
+        Ok(Self {
+            name: segments.join("/"),
+            alias: url.to_string(),
+            num_blocks,
+            blk_size: blk_size as u32,
+            verify,
+            uuid,
+        })
+    }
+}
+
+impl GetName for Null {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl CreateDestroy for Null {
+    type Error = NexusBdevError;
+
+    async fn create(&self) -> Result<String, Self::Error> {
+        if Bdev::lookup_by_name(&self.name).is_some() {
+            return Err(NexusBdevError::BdevExists {
+                name: self.name.clone(),
+            });
+        }
+
+        let cname = self.name.clone().into_cstring();
+        let opts = spdk_null_bdev_opts {
+            name: cname.as_ptr(),
+            num_blocks: self.num_blocks,
+            block_size: self.blk_size,
+            ..Default::default()
+        };
+
+        let errno = unsafe {
+            let mut bdev: *mut spdk_sys::spdk_bdev = std::ptr::null_mut();
+            bdev_null_create(&mut bdev, &opts)
+        };
+        if errno != 0 {
+            return Err(NexusBdevError::CreateBdev {
+                source: Errno::from_i32(errno.abs()),
+                name: self.name.clone(),
+            });
+        }
+
+        let mut bdev = Bdev::lookup_by_name(&self.name).ok_or_else(|| {
+            NexusBdevError::BdevNotFound {
+                name: self.name.clone(),
+            }
+        })?;
+
+        if self.verify {
+            pattern::install(&bdev);
+        }
+
+        if let Some(uuid) = self.uuid {
+            bdev.set_uuid(Some(uuid.to_string()));
+        }
+        if !bdev.add_alias(&self.alias) {
+            error!(
+                "Failed to add alias {} to device {}",
+                self.alias,
+                self.get_name()
+            );
+        }
+
+        Ok(self.name.clone())
+    }
+
+    async fn destroy(self: Box<Self>) -> Result<(), Self::Error> {
+        if let Some(bdev) = Bdev::lookup_by_name(&self.name) {
+            pattern::forget(&bdev);
+
+            let (s, r) = oneshot::channel::<ErrnoResult<()>>();
+            unsafe {
+                bdev_null_delete(bdev.as_ptr(), Some(done_errno_cb), cb_arg(s))
+            };
+
+            r.await
+                .context(nexus_uri::CancelBdev {
+                    name: self.name.clone(),
+                })?
+                .context(nexus_uri::DestroyBdev {
+                    name: self.name,
+                })
+        } else {
+            Err(NexusBdevError::BdevNotFound {
+                name: self.name,
+            })
+        }
+    }
+}
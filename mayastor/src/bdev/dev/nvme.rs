@@ -19,7 +19,11 @@ use spdk_sys::{
 };
 
 use crate::{
-    bdev::{CreateDestroy, GetName},
+    bdev::{
+        dev::memory::{self, Transport},
+        CreateDestroy,
+        GetName,
+    },
     core::Bdev,
     ffihelper::{cb_arg, errno_result_from_i32, ErrnoResult, IntoCString},
     nexus_uri::{self, NexusBdevError},
@@ -78,6 +82,7 @@ impl CreateDestroy for NVMe {
         let cname = self.name.clone().into_cstring();
         let mut context = NvmeCreateContext::new(self);
 
+        let reservation = memory::reserve(&self.name, Transport::Pcie)?;
         let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
 
         let errno = unsafe {
@@ -115,6 +120,8 @@ impl CreateDestroy for NVMe {
             error!("failed to added alias too created bdev")
         }
 
+        reservation.keep();
+
         Ok(unsafe { CStr::from_ptr(context.names[0]) }
             .to_str()
             .unwrap()
@@ -126,6 +133,9 @@ impl CreateDestroy for NVMe {
             let errno = unsafe {
                 bdev_nvme_delete(self.name.clone().into_cstring().as_ptr())
             };
+            if errno == 0 {
+                memory::release(&self.name);
+            }
             async {
                 errno_result_from_i32((), errno).context(
                     nexus_uri::DestroyBdev {
@@ -2,6 +2,7 @@ use std::{
     collections::HashMap,
     convert::TryFrom,
     ffi::{CStr, CString},
+    net::IpAddr,
     os::raw::{c_char, c_int, c_ulong, c_void},
     ptr::copy_nonoverlapping,
 };
@@ -20,7 +21,12 @@ use spdk_sys::{
 };
 
 use crate::{
-    bdev::{util::uri, CreateDestroy, GetName},
+    bdev::{
+        dev::memory::{self, Transport},
+        util::{resolve, uri},
+        CreateDestroy,
+        GetName,
+    },
     core::Bdev,
     ffihelper::{cb_arg, errno_result_from_i32, ErrnoResult},
     nexus_uri::{self, NexusBdevError},
@@ -35,7 +41,8 @@ pub(super) struct Nvmf {
     name: String,
     /// alias which can be used to open the bdev
     alias: String,
-    /// the remote target host (address)
+    /// the remote target host, an address or a name which is resolved
+    /// when connecting
     host: String,
     /// the transport service id (ie. port)
     port: u16,
@@ -45,6 +52,8 @@ pub(super) struct Nvmf {
     prchk_flags: u32,
     /// uuid of the spdk bdev
     uuid: Option<uuid::Uuid>,
+    /// transport to connect over, TCP unless given as trtype=rdma
+    transport: Transport,
 }
 
 /// Convert a URI to an Nvmf "object"
@@ -107,6 +116,20 @@ impl TryFrom<&Url> for Nvmf {
             },
         )?;
 
+        let transport = match parameters.remove("trtype") {
+            None => Transport::Tcp,
+            Some(value) => match value.to_lowercase().as_str() {
+                "tcp" => Transport::Tcp,
+                "rdma" => Transport::Rdma,
+                _ => {
+                    return Err(NexusBdevError::UriInvalid {
+                        uri: url.to_string(),
+                        message: format!("invalid trtype {}", value),
+                    })
+                }
+            },
+        };
+
         if let Some(keys) = uri::keys(parameters) {
             warn!("ignored parameters: {}", keys);
         }
@@ -120,6 +143,7 @@ impl TryFrom<&Url> for Nvmf {
             subnqn: segments[0].to_string(),
             prchk_flags,
             uuid,
+            transport,
         })
     }
 }
@@ -158,9 +182,17 @@ impl CreateDestroy for Nvmf {
                 .expect("done callback receiver side disappeared");
         }
 
+        let addr = resolve::resolve(&self.host).await.map_err(|message| {
+            NexusBdevError::ResolveHost {
+                host: self.host.clone(),
+                message,
+            }
+        })?;
+
         let cname = CString::new(self.name.clone()).unwrap();
-        let mut context = NvmeCreateContext::new(self);
+        let mut context = NvmeCreateContext::new(self, addr);
 
+        let reservation = memory::reserve(&self.name, self.transport)?;
         let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
 
         let errno = unsafe {
@@ -188,6 +220,11 @@ impl CreateDestroy for Nvmf {
             })?
             .context(nexus_uri::CreateBdev {
                 name: self.name.clone(),
+            })
+            .map_err(|e| {
+                // the host may have moved to another address
+                resolve::forget(&self.host);
+                e
             })?;
 
         if let Some(bdev) = Bdev::lookup_by_name(&self.get_name()) {
@@ -205,6 +242,8 @@ impl CreateDestroy for Nvmf {
             }
         };
 
+        reservation.keep();
+
         Ok(unsafe { CStr::from_ptr(context.names[0]) }
             .to_str()
             .unwrap()
@@ -218,6 +257,9 @@ impl CreateDestroy for Nvmf {
                 let cname = CString::new(self.name.clone()).unwrap();
 
                 let errno = unsafe { bdev_nvme_delete(cname.as_ptr()) };
+                if errno == 0 {
+                    memory::release(&self.name);
+                }
 
                 async {
                     errno_result_from_i32((), errno).context(
@@ -249,9 +291,13 @@ struct NvmeCreateContext {
 unsafe impl Send for NvmeCreateContext {}
 
 impl NvmeCreateContext {
-    pub fn new(nvmf: &Nvmf) -> NvmeCreateContext {
+    pub fn new(nvmf: &Nvmf, addr: IpAddr) -> NvmeCreateContext {
         let port = format!("{}", nvmf.port);
-        let protocol = "TCP";
+        let traddr = addr.to_string();
+        let protocol = match nvmf.transport {
+            Transport::Rdma => "RDMA",
+            _ => "TCP",
+        };
 
         let mut trid = spdk_nvme_transport_id::default();
 
@@ -262,9 +308,9 @@ impl NvmeCreateContext {
                 protocol.len(),
             );
             copy_nonoverlapping(
-                nvmf.host.as_ptr() as *const c_void,
+                traddr.as_ptr() as *const c_void,
                 &mut trid.traddr[0] as *const _ as *mut c_void,
-                nvmf.host.len(),
+                traddr.len(),
             );
             copy_nonoverlapping(
                 port.as_ptr() as *const c_void,
@@ -278,8 +324,15 @@ impl NvmeCreateContext {
             );
         }
 
-        trid.trtype = spdk_sys::SPDK_NVME_TRANSPORT_TCP;
-        trid.adrfam = spdk_sys::SPDK_NVMF_ADRFAM_IPV4;
+        trid.trtype = match nvmf.transport {
+            Transport::Rdma => spdk_sys::SPDK_NVME_TRANSPORT_RDMA,
+            _ => spdk_sys::SPDK_NVME_TRANSPORT_TCP,
+        };
+        trid.adrfam = if addr.is_ipv6() {
+            spdk_sys::SPDK_NVMF_ADRFAM_IPV6
+        } else {
+            spdk_sys::SPDK_NVMF_ADRFAM_IPV4
+        };
 
         let hostid = spdk_nvme_host_id::default();
 
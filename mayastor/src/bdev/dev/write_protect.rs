@@ -0,0 +1,83 @@
+//! File backed bdevs which have been created read-only.
+//!
+//! Neither the aio nor the uring module of SPDK can open a device read-only,
+//! so a bdev created with `readonly=true` in its URI is write protected by
+//! intercepting its IO, see bdev::interpose, which fails the writes with the
+//! NVMe status "Namespace is Write Protected", the same status a read-only
+//! pool fails writes with. All other IO types are passed on to the module.
+
+use std::os::raw::c_void;
+
+use spdk_sys::{
+    spdk_bdev_fn_table,
+    spdk_bdev_io,
+    spdk_bdev_io_type,
+    spdk_io_channel,
+    SPDK_BDEV_IO_TYPE_UNMAP,
+    SPDK_BDEV_IO_TYPE_WRITE_ZEROES,
+};
+
+use crate::{
+    bdev::interpose::{self, Interpose},
+    core::Bdev,
+};
+
+/// a write protected bdev
+struct Protected;
+
+/// write protect the bdev by intercepting its IO
+pub(super) fn protect(bdev: &Bdev) {
+    interpose::install(bdev, Protected, |table| {
+        table.io_type_supported = Some(io_type_supported)
+    });
+    interpose::write_protect(bdev.as_ptr(), true);
+    info!("{} is write protected", bdev.name());
+}
+
+/// stop intercepting the IO of the bdev, as it is about to be destroyed
+pub(super) fn forget(bdev: &Bdev) {
+    interpose::forget(bdev);
+}
+
+/// the function table of the module of the bdev with the given context,
+/// which is looked up as it is not in the IO path
+fn module_table(ctx: *mut c_void) -> &'static spdk_bdev_fn_table {
+    Bdev::bdev_first()
+        .into_iter()
+        .flatten()
+        .find_map(|bdev| {
+            if unsafe { (*bdev.as_ptr()).ctxt } == ctx {
+                interpose::module(bdev.as_ptr())
+            } else {
+                None
+            }
+        })
+        .expect("bdev is not write protected")
+}
+
+/// WRITE_ZEROES and UNMAP are not supported, so they are not emulated by
+/// the bdev layer either
+extern "C" fn io_type_supported(
+    ctx: *mut c_void,
+    io_type: spdk_bdev_io_type,
+) -> bool {
+    if io_type == SPDK_BDEV_IO_TYPE_WRITE_ZEROES
+        || io_type == SPDK_BDEV_IO_TYPE_UNMAP
+    {
+        return false;
+    }
+
+    unsafe { module_table(ctx).io_type_supported.unwrap()(ctx, io_type) }
+}
+
+impl Interpose for Protected {
+    fn submit_request(
+        &self,
+        module: &'static spdk_bdev_fn_table,
+        ch: *mut spdk_io_channel,
+        io: *mut spdk_bdev_io,
+    ) {
+        // the writes have been failed by the interposer already
+        interpose::pass_on(module, ch, io);
+    }
+}
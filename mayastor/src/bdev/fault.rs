@@ -0,0 +1,211 @@
+//! Fault injection into the IO of the bdevs created with the error:// URI
+//! scheme, for testing how the nexus handles failing and slow children.
+//!
+//! The error module of SPDK passes the IO on to the bdev underneath. The IO
+//! of our bdevs is intercepted, see bdev::interpose, to check every IO
+//! against the faults injected into the bdev first. A fault applies to the
+//! IOs of its kind which overlap its range of blocks, and of those to every
+//! nth one, until it has been injected the given number of times:
+//!
+//! - the IO fails, unless a delay is given
+//! - the IO is passed on once the delay has expired
+//!
+//! Faults can be injected, listed and cleared at any time with [`inject`],
+//! [`list`] and [`clear`].
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use spdk_sys::{
+    spdk_bdev_fn_table,
+    spdk_bdev_io,
+    spdk_bdev_io_complete,
+    spdk_bdev_io_type,
+    spdk_io_channel,
+    SPDK_BDEV_IO_STATUS_FAILED,
+    SPDK_BDEV_IO_TYPE_READ,
+    SPDK_BDEV_IO_TYPE_UNMAP,
+    SPDK_BDEV_IO_TYPE_WRITE,
+    SPDK_BDEV_IO_TYPE_WRITE_ZEROES,
+};
+
+use crate::{
+    bdev::interpose::{self, Interpose},
+    core::Bdev,
+};
+
+/// the IOs a fault applies to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultIo {
+    /// reads and writes
+    All,
+    Read,
+    /// writes, write zeroes and unmaps
+    Write,
+}
+
+impl Default for FaultIo {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
+/// a fault injected into the IO of an error bdev
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fault {
+    /// the IOs the fault applies to
+    pub io: FaultIo,
+    /// first block of the range of the fault
+    pub offset: u64,
+    /// number of blocks of the range, up to the end of the bdev if 0
+    pub num_blocks: u64,
+    /// inject the fault into every nth IO of the range, every IO if 0 or 1
+    pub frequency: u32,
+    /// number of times to inject the fault, without a limit if 0
+    pub count: u32,
+    /// delay the IOs by this rather than fail them
+    pub delay: Option<Duration>,
+    /// number of IOs which fell into the range of the fault
+    pub matched: u64,
+    /// number of IOs the fault has been injected into
+    pub injected: u64,
+}
+
+impl Fault {
+    fn applies(
+        &self,
+        io_type: spdk_bdev_io_type,
+        offset: u64,
+        len: u64,
+    ) -> bool {
+        let io = match io_type {
+            SPDK_BDEV_IO_TYPE_READ => FaultIo::Read,
+            SPDK_BDEV_IO_TYPE_WRITE
+            | SPDK_BDEV_IO_TYPE_WRITE_ZEROES
+            | SPDK_BDEV_IO_TYPE_UNMAP => FaultIo::Write,
+            _ => return false,
+        };
+
+        (self.io == FaultIo::All || self.io == io)
+            && offset + len > self.offset
+            && (self.num_blocks == 0 || offset < self.offset + self.num_blocks)
+            && (self.count == 0 || self.injected < u64::from(self.count))
+    }
+
+    /// count the IO, and return true if the fault is to be injected into it
+    fn hit(&mut self) -> bool {
+        self.matched += 1;
+        if self.frequency > 1 && self.matched % u64::from(self.frequency) != 0 {
+            return false;
+        }
+        self.injected += 1;
+        true
+    }
+}
+
+/// the faults of a bdev into which faults can be injected
+#[derive(Default)]
+struct Faulty {
+    /// whether any faults have been injected, so that the IO of the bdev
+    /// is passed on without taking the lock when there are none
+    active: AtomicBool,
+    faults: Mutex<Vec<Fault>>,
+}
+
+/// allow faults to be injected into the bdev by intercepting its IO
+pub(crate) fn install(bdev: &Bdev) {
+    interpose::install(bdev, Faulty::default(), |_| {});
+}
+
+/// stop intercepting the IO of the bdev, as it is about to be destroyed,
+/// it is passed on from now on
+pub(crate) fn forget(bdev: &Bdev) {
+    interpose::forget(bdev);
+}
+
+/// call f with the faults of the bdev of the given name, returns None if
+/// there is no such bdev into which faults can be injected
+fn with_faults<R>(
+    name: &str,
+    f: impl FnOnce(&mut Vec<Fault>) -> R,
+) -> Option<R> {
+    interpose::with_name(name, |bdev: &Faulty| {
+        let mut faults = bdev.faults.lock().unwrap();
+        let result = f(&mut faults);
+        bdev.active.store(!faults.is_empty(), Ordering::Release);
+        result
+    })
+}
+
+/// inject a fault into the IO of the error bdev, returns false if there is
+/// no error bdev with the given name
+pub fn inject(name: &str, fault: Fault) -> bool {
+    with_faults(name, |faults| {
+        info!("{}: injecting fault {:?}", name, fault);
+        faults.push(Fault {
+            matched: 0,
+            injected: 0,
+            ..fault
+        });
+    })
+    .is_some()
+}
+
+/// remove all faults from the error bdev, returns false if there is no error
+/// bdev with the given name
+pub fn clear(name: &str) -> bool {
+    with_faults(name, |faults| {
+        info!("{}: clearing {} faults", name, faults.len());
+        faults.clear();
+    })
+    .is_some()
+}
+
+/// returns the faults injected into the error bdev with their counters
+pub fn list(name: &str) -> Option<Vec<Fault>> {
+    with_faults(name, |faults| faults.clone())
+}
+
+impl Interpose for Faulty {
+    fn submit_request(
+        &self,
+        module: &'static spdk_bdev_fn_table,
+        ch: *mut spdk_io_channel,
+        io: *mut spdk_bdev_io,
+    ) {
+        if !self.active.load(Ordering::Acquire) {
+            return interpose::pass_on(module, ch, io);
+        }
+
+        let (io_type, offset, len) = unsafe {
+            (
+                (*io).type_ as spdk_bdev_io_type,
+                (*io).u.bdev.offset_blocks,
+                (*io).u.bdev.num_blocks,
+            )
+        };
+
+        let fault = self
+            .faults
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|f| f.applies(io_type, offset, len))
+            .find_map(|f| if f.hit() { Some(f.delay) } else { None });
+
+        match fault {
+            None => interpose::pass_on(module, ch, io),
+            Some(None) => unsafe {
+                spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_FAILED)
+            },
+            Some(Some(delay)) => {
+                interpose::pass_on_after(module, ch, io, delay)
+            }
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Interception of the IO of a bdev by replacing its function table with a
+//! copy in which submit_request is ours, i.e. to apply the flush policy of
+//! an lvol, to inject faults, to delay the IO or to verify the data written.
+//!
+//! Every bdev gets a copy of its own, which is followed by the state of the
+//! bdev. The IO path gets from the bdev of an IO to its state through the
+//! function table, without any lookup or lock. The state is shared between
+//! the cores, so what can be changed while the bdev is in use is kept in
+//! atomics, or behind a lock of the bdev itself.
+//!
+//! Writes to a bdev whose IO is intercepted can be failed with the NVMe
+//! status "Namespace is Write Protected" before they reach its state, see
+//! [`write_protect`].
+//!
+//! The copy is removed with [`forget`] before the bdev is destroyed, after
+//! which the IO of the bdev goes to its module directly.
+
+use std::{
+    any::TypeId,
+    os::raw::c_void,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use spdk_sys::{
+    spdk_bdev,
+    spdk_bdev_fn_table,
+    spdk_bdev_io,
+    spdk_bdev_io_complete_nvme_status,
+    spdk_bdev_io_type,
+    spdk_for_each_thread,
+    spdk_io_channel,
+    spdk_poller,
+    spdk_poller_register,
+    spdk_poller_unregister,
+    SPDK_BDEV_IO_TYPE_UNMAP,
+    SPDK_BDEV_IO_TYPE_WRITE,
+    SPDK_BDEV_IO_TYPE_WRITE_ZEROES,
+};
+
+use crate::{
+    core::Bdev,
+    lvs::read_only::NVME_SC_NAMESPACE_IS_WRITE_PROTECTED,
+};
+
+/// The state of a bdev whose IO is intercepted.
+pub(crate) trait Interpose: Send + Sync + 'static {
+    /// called for every IO submitted to the bdev, the IO is passed on to the
+    /// function table of the module of the bdev with [`pass_on`]
+    fn submit_request(
+        &self,
+        module: &'static spdk_bdev_fn_table,
+        ch: *mut spdk_io_channel,
+        io: *mut spdk_bdev_io,
+    );
+}
+
+/// the part of the copy of the function table which does not depend on the
+/// kind of state
+#[repr(C)]
+struct Header {
+    /// must be the first field, as the bdev points to it
+    table: spdk_bdev_fn_table,
+    /// the function table of the module of the bdev
+    module: &'static spdk_bdev_fn_table,
+    /// the kind of state which follows
+    kind: TypeId,
+    /// fail the writes rather than submitting them with the state
+    write_protected: AtomicBool,
+    /// submit an IO with the state which follows
+    submit: unsafe fn(*const Header, *mut spdk_io_channel, *mut spdk_bdev_io),
+    /// free the copy along with the state
+    free: unsafe fn(*mut Header),
+}
+
+#[repr(C)]
+struct Interposed<T> {
+    header: Header,
+    state: T,
+}
+
+unsafe fn submit<T: Interpose>(
+    header: *const Header,
+    ch: *mut spdk_io_channel,
+    io: *mut spdk_bdev_io,
+) {
+    let interposed = &*(header as *const Interposed<T>);
+    interposed
+        .state
+        .submit_request(interposed.header.module, ch, io);
+}
+
+unsafe fn free<T>(header: *mut Header) {
+    drop(Box::from_raw(header as *mut Interposed<T>));
+}
+
+fn is_write(io: *mut spdk_bdev_io) -> bool {
+    matches!(
+        unsafe { (*io).type_ } as spdk_bdev_io_type,
+        SPDK_BDEV_IO_TYPE_WRITE
+            | SPDK_BDEV_IO_TYPE_WRITE_ZEROES
+            | SPDK_BDEV_IO_TYPE_UNMAP
+    )
+}
+
+extern "C" fn submit_request(ch: *mut spdk_io_channel, io: *mut spdk_bdev_io) {
+    unsafe {
+        let header = (*(*io).bdev).fn_table as *const Header;
+        if (*header).write_protected.load(Ordering::Relaxed) && is_write(io) {
+            return spdk_bdev_io_complete_nvme_status(
+                io,
+                0,
+                0,
+                NVME_SC_NAMESPACE_IS_WRITE_PROTECTED,
+            );
+        }
+        ((*header).submit)(header, ch, io)
+    }
+}
+
+/// the copy of the function table installed on the bdev, if any
+fn header(bdev: *mut spdk_bdev) -> Option<*mut Header> {
+    let table = unsafe { (*bdev).fn_table };
+    let ours: unsafe extern "C" fn(_, _) = submit_request;
+    if unsafe { (*table).submit_request } == Some(ours) {
+        Some(table as *mut Header)
+    } else {
+        None
+    }
+}
+
+/// Intercept the IO of the bdev with the given state. Other functions of
+/// the table than submit_request can be replaced by f, which is given the
+/// copy of the table. The IO of a bdev is intercepted with one state at a
+/// time, it is a bug to install another one before the first is forgotten.
+pub(crate) fn install<T: Interpose>(
+    bdev: &Bdev,
+    state: T,
+    f: impl FnOnce(&mut spdk_bdev_fn_table),
+) {
+    let ptr = bdev.as_ptr();
+    assert!(
+        header(ptr).is_none(),
+        "the IO of bdev {} is intercepted already",
+        bdev.name()
+    );
+
+    let module = unsafe { &*(*ptr).fn_table };
+    let mut table = *module;
+    f(&mut table);
+    table.submit_request = Some(submit_request);
+
+    let interposed = Box::into_raw(Box::new(Interposed {
+        header: Header {
+            table,
+            module,
+            kind: TypeId::of::<T>(),
+            write_protected: AtomicBool::new(false),
+            submit: submit::<T>,
+            free: free::<T>,
+        },
+        state,
+    }));
+
+    unsafe { (*ptr).fn_table = &(*interposed).header.table };
+}
+
+/// the function table of the module of the bdev, if its IO is intercepted
+pub(crate) fn module(
+    bdev: *mut spdk_bdev,
+) -> Option<&'static spdk_bdev_fn_table> {
+    header(bdev).map(|h| unsafe { (*h).module })
+}
+
+/// call f with the state of the bdev, returns None if the IO of the bdev is
+/// not intercepted with a state of this kind
+pub(crate) fn with<T: Interpose, R>(
+    bdev: *mut spdk_bdev,
+    f: impl FnOnce(&T) -> R,
+) -> Option<R> {
+    let header = header(bdev)?;
+    unsafe {
+        if (*header).kind != TypeId::of::<T>() {
+            return None;
+        }
+        Some(f(&(*(header as *const Interposed<T>)).state))
+    }
+}
+
+/// call f with the state of the bdev with the given name, as [`with`]
+pub(crate) fn with_name<T: Interpose, R>(
+    name: &str,
+    f: impl FnOnce(&T) -> R,
+) -> Option<R> {
+    Bdev::lookup_by_name(name).and_then(|bdev| with(bdev.as_ptr(), f))
+}
+
+/// Fail the writes, write zeroes and unmaps of the bdev with the write
+/// protected status from now on, or submit them again. Returns false if the
+/// IO of the bdev is not intercepted.
+pub(crate) fn write_protect(bdev: *mut spdk_bdev, protect: bool) -> bool {
+    match header(bdev) {
+        Some(header) => {
+            let header = unsafe { &*header };
+            header.write_protected.store(protect, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+extern "C" fn quiesced(_ctx: *mut c_void) {}
+
+extern "C" fn free_header(ctx: *mut c_void) {
+    let header = ctx as *mut Header;
+    unsafe { ((*header).free)(header) };
+}
+
+/// Stop intercepting the IO of the bdev, which goes to its module from now
+/// on. The copy of the function table and the state are freed once every
+/// thread has been polled, as they may still be in use by an IO which has
+/// been submitted on another core in the mean time.
+pub(crate) fn forget(bdev: &Bdev) {
+    let ptr = bdev.as_ptr();
+    if let Some(header) = header(ptr) {
+        unsafe {
+            (*ptr).fn_table = (*header).module;
+            spdk_for_each_thread(
+                Some(quiesced),
+                header as *mut c_void,
+                Some(free_header),
+            );
+        }
+    }
+}
+
+/// pass the IO on to the function table of the module
+pub(crate) fn pass_on(
+    module: &'static spdk_bdev_fn_table,
+    ch: *mut spdk_io_channel,
+    io: *mut spdk_bdev_io,
+) {
+    unsafe { module.submit_request.unwrap()(ch, io) }
+}
+
+/// an IO which is passed on once its delay has expired
+struct Delayed {
+    module: &'static spdk_bdev_fn_table,
+    ch: *mut spdk_io_channel,
+    io: *mut spdk_bdev_io,
+    poller: *mut spdk_poller,
+}
+
+extern "C" fn submit_delayed(ctx: *mut c_void) -> i32 {
+    let mut delayed = unsafe { Box::from_raw(ctx as *mut Delayed) };
+    unsafe { spdk_poller_unregister(&mut delayed.poller) };
+    pass_on(delayed.module, delayed.ch, delayed.io);
+    1
+}
+
+/// pass the IO on to the function table of the module once the delay has
+/// expired
+pub(crate) fn pass_on_after(
+    module: &'static spdk_bdev_fn_table,
+    ch: *mut spdk_io_channel,
+    io: *mut spdk_bdev_io,
+    delay: Duration,
+) {
+    let delayed = Box::into_raw(Box::new(Delayed {
+        module,
+        ch,
+        io,
+        poller: std::ptr::null_mut(),
+    }));
+    unsafe {
+        (*delayed).poller = spdk_poller_register(
+            Some(submit_delayed),
+            delayed as *mut c_void,
+            delay.as_micros() as u64,
+        );
+    }
+}
@@ -0,0 +1,568 @@
+//! In memory bdevs with programmable behaviour, used to test the IO path of
+//! the nexus without real devices.
+//!
+//! Unlike the malloc bdev, the data of a mock bdev is stored on the heap, so
+//! together with the --no-huge option of the environment, a nexus can be
+//! created and exercised on machines without hugepages. The behaviour of a
+//! mock bdev can be changed at any time with [`set_behavior`]:
+//!
+//! - delay: every IO is completed after the given duration
+//! - fail_reads and fail_writes: IOs of the given kind fail
+//! - torn_writes: writes only store the first half of their blocks and fail
+//! - path_down_ios: the next reads and writes fail with the NVMe status of a
+//!   path which is down
+//!
+//! The IOs which are delayed can be aborted.
+//!
+//! Mock bdevs are created by means of the mock:/// URI scheme and are only
+//! available when mayastor is built with the mock-children feature.
+
+use std::{
+    collections::VecDeque,
+    ffi::CString,
+    os::raw::c_void,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+
+use spdk_sys::{
+    iovec,
+    spdk_bdev,
+    spdk_bdev_fn_table,
+    spdk_bdev_io,
+    spdk_bdev_io_complete,
+    spdk_bdev_io_complete_nvme_status,
+    spdk_bdev_io_get_buf,
+    spdk_bdev_io_status,
+    spdk_bdev_io_type,
+    spdk_bdev_module,
+    spdk_bdev_module_list_add,
+    spdk_bdev_register,
+    spdk_get_io_channel,
+    spdk_io_channel,
+    spdk_io_device_register,
+    spdk_io_device_unregister,
+    spdk_poller,
+    spdk_poller_register,
+    spdk_poller_unregister,
+    SPDK_BDEV_IO_STATUS_ABORTED,
+    SPDK_BDEV_IO_STATUS_FAILED,
+    SPDK_BDEV_IO_STATUS_SUCCESS,
+    SPDK_BDEV_IO_TYPE_ABORT,
+    SPDK_BDEV_IO_TYPE_FLUSH,
+    SPDK_BDEV_IO_TYPE_READ,
+    SPDK_BDEV_IO_TYPE_RESET,
+    SPDK_BDEV_IO_TYPE_UNMAP,
+    SPDK_BDEV_IO_TYPE_WRITE,
+    SPDK_BDEV_IO_TYPE_WRITE_ZEROES,
+};
+
+use crate::core::Bdev;
+
+const MOCK_MODULE_NAME: &str = "MOCK_MODULE";
+const MOCK_PRODUCT_ID: &str = "Mock disk";
+
+/// how a mock bdev responds to IO
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Behavior {
+    /// complete every IO after this duration
+    pub delay: Option<Duration>,
+    /// fail all reads
+    pub fail_reads: bool,
+    /// fail all writes, write zeroes and unmaps
+    pub fail_writes: bool,
+    /// store only the first half of the blocks of a write and fail it
+    pub torn_writes: bool,
+    /// fail this many of the next reads and writes as if the path to the
+    /// disk was down
+    pub path_down_ios: u32,
+}
+
+/// IO counters of a mock bdev
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MockStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub failed: u64,
+    pub aborts: u64,
+    pub resets: u64,
+}
+
+struct MockModule(*mut spdk_bdev_module);
+
+unsafe impl Sync for MockModule {}
+unsafe impl Send for MockModule {}
+
+static MOCK_MODULE: Lazy<MockModule> = Lazy::new(|| {
+    let mut module = Box::new(spdk_bdev_module::default());
+    module.name = CString::new(MOCK_MODULE_NAME).unwrap().into_raw();
+    module.module_init = Some(mock_mod_init);
+    module.get_ctx_size = Some(mock_ctx_size);
+    MockModule(Box::into_raw(module))
+});
+
+struct MockFnTable(spdk_bdev_fn_table);
+
+unsafe impl Sync for MockFnTable {}
+unsafe impl Send for MockFnTable {}
+
+static MOCK_FN_TABLE: Lazy<MockFnTable> = Lazy::new(|| {
+    let mut table = spdk_bdev_fn_table::default();
+    table.destruct = Some(destruct);
+    table.submit_request = Some(submit_request);
+    table.io_type_supported = Some(io_type_supported);
+    table.get_io_channel = Some(get_io_channel);
+    MockFnTable(table)
+});
+
+extern "C" fn mock_mod_init() -> i32 {
+    info!("Initializing mock bdev module");
+    0
+}
+
+extern "C" fn mock_ctx_size() -> i32 {
+    0
+}
+
+/// register the mock bdev module, this must be done before the bdev
+/// subsystem is initialized
+pub fn register_module() {
+    unsafe { spdk_bdev_module_list_add(MOCK_MODULE.0) };
+}
+
+/// a mock bdev, the spdk bdev is embedded and its context points back to us
+struct MockDisk {
+    bdev: spdk_bdev,
+    data: Mutex<Vec<u8>>,
+    behavior: Mutex<Behavior>,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    failed: AtomicU64,
+    aborts: AtomicU64,
+    resets: AtomicU64,
+}
+
+impl MockDisk {
+    fn as_ptr(&self) -> *mut c_void {
+        self as *const _ as *mut c_void
+    }
+
+    unsafe fn from_raw<'a>(ctx: *mut c_void) -> &'a MockDisk {
+        &*(ctx as *const MockDisk)
+    }
+
+    /// returns the mock disk of the named bdev, None if the bdev does not
+    /// exist or is not a mock bdev
+    fn lookup<'a>(name: &str) -> Option<&'a MockDisk> {
+        let bdev = Bdev::lookup_by_name(name)?;
+        unsafe {
+            if (*bdev.as_ptr()).module != MOCK_MODULE.0 {
+                return None;
+            }
+            Some(Self::from_raw((*bdev.as_ptr()).ctxt))
+        }
+    }
+
+    fn block_len(&self) -> u64 {
+        u64::from(self.bdev.blocklen)
+    }
+
+    /// copy the blocks of the IO from the disk into its buffers
+    fn read(&self, io: *mut spdk_bdev_io) {
+        let (iovs, offset, len) = io_range(io, self.block_len());
+        let data = self.data.lock().unwrap();
+        let mut pos = offset;
+        for iov in iovs {
+            let remaining = offset + len - pos;
+            if remaining == 0 {
+                break;
+            }
+            let len = std::cmp::min(iov.iov_len as usize, remaining);
+            let buf = unsafe {
+                std::slice::from_raw_parts_mut(iov.iov_base as *mut u8, len)
+            };
+            buf.copy_from_slice(&data[pos .. pos + len]);
+            pos += len;
+        }
+    }
+
+    /// copy up to len bytes of the IO buffers to the disk
+    fn write(&self, io: *mut spdk_bdev_io, len: usize) {
+        let (iovs, offset, _) = io_range(io, self.block_len());
+        let mut data = self.data.lock().unwrap();
+        let mut pos = offset;
+        for iov in iovs {
+            let remaining = offset + len - pos;
+            if remaining == 0 {
+                break;
+            }
+            let len = std::cmp::min(iov.iov_len as usize, remaining);
+            let buf = unsafe {
+                std::slice::from_raw_parts(iov.iov_base as *const u8, len)
+            };
+            data[pos .. pos + len].copy_from_slice(buf);
+            pos += len;
+        }
+    }
+
+    /// zero the blocks of the IO
+    fn zero(&self, io: *mut spdk_bdev_io) {
+        let (_, offset, len) = io_range(io, self.block_len());
+        let mut data = self.data.lock().unwrap();
+        data[offset .. offset + len].iter_mut().for_each(|b| *b = 0);
+    }
+
+    /// execute the IO and return the status it is to be completed with
+    fn execute(&self, io: *mut spdk_bdev_io) -> Status {
+        let io_type = unsafe { (*io).type_ } as spdk_bdev_io_type;
+        let behavior = {
+            let mut behavior = self.behavior.lock().unwrap();
+            if behavior.path_down_ios > 0
+                && matches!(
+                    io_type,
+                    SPDK_BDEV_IO_TYPE_READ | SPDK_BDEV_IO_TYPE_WRITE
+                )
+            {
+                behavior.path_down_ios -= 1;
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                return Status::PathDown;
+            }
+            behavior.clone()
+        };
+
+        let ok = match io_type {
+            SPDK_BDEV_IO_TYPE_READ => {
+                self.reads.fetch_add(1, Ordering::Relaxed);
+                if !behavior.fail_reads {
+                    self.read(io);
+                }
+                !behavior.fail_reads
+            }
+            SPDK_BDEV_IO_TYPE_WRITE => {
+                self.writes.fetch_add(1, Ordering::Relaxed);
+                let (_, _, len) = io_range(io, self.block_len());
+                if behavior.fail_writes {
+                    false
+                } else if behavior.torn_writes {
+                    let blocks = unsafe { (*io).u.bdev.num_blocks } / 2;
+                    self.write(io, (blocks * self.block_len()) as usize);
+                    false
+                } else {
+                    self.write(io, len);
+                    true
+                }
+            }
+            SPDK_BDEV_IO_TYPE_WRITE_ZEROES | SPDK_BDEV_IO_TYPE_UNMAP => {
+                self.writes.fetch_add(1, Ordering::Relaxed);
+                if !behavior.fail_writes {
+                    self.zero(io);
+                }
+                !behavior.fail_writes
+            }
+            SPDK_BDEV_IO_TYPE_FLUSH => true,
+            SPDK_BDEV_IO_TYPE_RESET => {
+                self.resets.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        };
+
+        if ok {
+            Status::Bdev(SPDK_BDEV_IO_STATUS_SUCCESS)
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            Status::Bdev(SPDK_BDEV_IO_STATUS_FAILED)
+        }
+    }
+}
+
+/// NVMe status code type and internal path error status, from nvme_spec.h
+const NVME_SCT_PATH: i32 = 0x3;
+const NVME_SC_INTERNAL_PATH_ERROR: i32 = 0x0;
+
+/// the status an IO of a mock bdev is completed with
+#[derive(Debug, Clone, Copy)]
+enum Status {
+    Bdev(spdk_bdev_io_status),
+    /// the NVMe status of an IO of which the path was down
+    PathDown,
+}
+
+impl Status {
+    fn complete(self, io: *mut spdk_bdev_io) {
+        match self {
+            Status::Bdev(status) => unsafe {
+                spdk_bdev_io_complete(io, status)
+            },
+            Status::PathDown => unsafe {
+                spdk_bdev_io_complete_nvme_status(
+                    io,
+                    0,
+                    NVME_SCT_PATH,
+                    NVME_SC_INTERNAL_PATH_ERROR,
+                )
+            },
+        }
+    }
+}
+
+/// returns the buffers of the IO, together with the offset and length in
+/// bytes of the range it refers to
+fn io_range<'a>(
+    io: *mut spdk_bdev_io,
+    block_len: u64,
+) -> (&'a [iovec], usize, usize) {
+    unsafe {
+        let bdev = &(*io).u.bdev;
+        let iovs: &[iovec] = if bdev.iovs.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts(bdev.iovs, bdev.iovcnt as usize)
+        };
+        (
+            iovs,
+            (bdev.offset_blocks * block_len) as usize,
+            (bdev.num_blocks * block_len) as usize,
+        )
+    }
+}
+
+/// IO channel of a mock bdev, holds the IOs which are delayed
+struct MockChannel {
+    pending: VecDeque<(Instant, *mut spdk_bdev_io, Status)>,
+    poller: *mut spdk_poller,
+}
+
+extern "C" fn channel_create(_device: *mut c_void, ctx: *mut c_void) -> i32 {
+    let ch = ctx as *mut MockChannel;
+    unsafe {
+        std::ptr::write(
+            ch,
+            MockChannel {
+                pending: VecDeque::new(),
+                poller: std::ptr::null_mut(),
+            },
+        );
+        (*ch).poller = spdk_poller_register(Some(complete_delayed), ctx, 100);
+    }
+    0
+}
+
+extern "C" fn channel_destroy(_device: *mut c_void, ctx: *mut c_void) {
+    let ch = ctx as *mut MockChannel;
+    unsafe {
+        (*ch)
+            .pending
+            .drain(..)
+            .for_each(|(_, io, status)| status.complete(io));
+        spdk_poller_unregister(&mut (*ch).poller);
+        std::ptr::drop_in_place(ch);
+    }
+}
+
+/// complete the delayed IOs of which the delay has expired
+extern "C" fn complete_delayed(ctx: *mut c_void) -> i32 {
+    let ch = unsafe { &mut *(ctx as *mut MockChannel) };
+    let now = Instant::now();
+    let mut completed = 0;
+
+    while let Some((deadline, _, _)) = ch.pending.front() {
+        if *deadline > now {
+            break;
+        }
+        let (_, io, status) = ch.pending.pop_front().unwrap();
+        status.complete(io);
+        completed += 1;
+    }
+
+    completed
+}
+
+fn channel_ctx(ch: *mut spdk_io_channel) -> *mut MockChannel {
+    unsafe {
+        (ch as *mut u8).add(std::mem::size_of::<spdk_io_channel>())
+            as *mut MockChannel
+    }
+}
+
+extern "C" fn io_type_supported(
+    _ctx: *mut c_void,
+    io_type: spdk_bdev_io_type,
+) -> bool {
+    matches!(
+        io_type,
+        SPDK_BDEV_IO_TYPE_READ
+            | SPDK_BDEV_IO_TYPE_WRITE
+            | SPDK_BDEV_IO_TYPE_WRITE_ZEROES
+            | SPDK_BDEV_IO_TYPE_UNMAP
+            | SPDK_BDEV_IO_TYPE_FLUSH
+            | SPDK_BDEV_IO_TYPE_RESET
+            | SPDK_BDEV_IO_TYPE_ABORT
+    )
+}
+
+extern "C" fn get_io_channel(ctx: *mut c_void) -> *mut spdk_io_channel {
+    unsafe { spdk_get_io_channel(ctx) }
+}
+
+extern "C" fn submit_request(ch: *mut spdk_io_channel, io: *mut spdk_bdev_io) {
+    let needs_buf = unsafe {
+        (*io).type_ as spdk_bdev_io_type == SPDK_BDEV_IO_TYPE_READ
+            && (*(*io).u.bdev.iovs).iov_base.is_null()
+    };
+
+    if needs_buf {
+        let len = unsafe {
+            (*io).u.bdev.num_blocks * u64::from((*(*io).bdev).blocklen)
+        };
+        unsafe { spdk_bdev_io_get_buf(io, Some(get_buf_cb), len) };
+    } else {
+        submit(ch, io);
+    }
+}
+
+extern "C" fn get_buf_cb(
+    ch: *mut spdk_io_channel,
+    io: *mut spdk_bdev_io,
+    success: bool,
+) {
+    if success {
+        submit(ch, io);
+    } else {
+        unsafe { spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_FAILED) };
+    }
+}
+
+fn submit(ch: *mut spdk_io_channel, io: *mut spdk_bdev_io) {
+    if unsafe { (*io).type_ } as spdk_bdev_io_type == SPDK_BDEV_IO_TYPE_ABORT {
+        abort(ch, io);
+        return;
+    }
+
+    let disk = unsafe { MockDisk::from_raw((*(*io).bdev).ctxt) };
+    let status = disk.execute(io);
+
+    match disk.behavior.lock().unwrap().delay {
+        Some(delay) => {
+            let ch = unsafe { &mut *channel_ctx(ch) };
+            ch.pending.push_back((Instant::now() + delay, io, status));
+        }
+        None => status.complete(io),
+    }
+}
+
+/// complete the delayed IO to abort as aborted, the abort fails if it is
+/// not delayed on the channel
+fn abort(ch: *mut spdk_io_channel, io: *mut spdk_bdev_io) {
+    let ch = unsafe { &mut *channel_ctx(ch) };
+    let target = unsafe { (*io).u.abort.bio_to_abort };
+
+    let status = match ch.pending.iter().position(|(_, p, _)| *p == target) {
+        Some(idx) => {
+            let (_, aborted, _) = ch.pending.remove(idx).unwrap();
+            let disk = unsafe { MockDisk::from_raw((*(*io).bdev).ctxt) };
+            disk.aborts.fetch_add(1, Ordering::Relaxed);
+            unsafe {
+                spdk_bdev_io_complete(aborted, SPDK_BDEV_IO_STATUS_ABORTED)
+            };
+            SPDK_BDEV_IO_STATUS_SUCCESS
+        }
+        None => SPDK_BDEV_IO_STATUS_FAILED,
+    };
+
+    unsafe { spdk_bdev_io_complete(io, status) };
+}
+
+/// called when the bdev is unregistered, the disk is freed once all of its
+/// channels are gone
+extern "C" fn destruct(ctx: *mut c_void) -> i32 {
+    extern "C" fn unregistered(ctx: *mut c_void) {
+        let disk = unsafe { Box::from_raw(ctx as *mut MockDisk) };
+        unsafe {
+            let _ = CString::from_raw(disk.bdev.name);
+            let _ = CString::from_raw(disk.bdev.product_name);
+        }
+    }
+
+    unsafe { spdk_io_device_unregister(ctx, Some(unregistered)) };
+    0
+}
+
+/// create and register a mock bdev, returns the errno on failure
+pub(crate) fn create(
+    name: &str,
+    num_blocks: u64,
+    block_len: u32,
+) -> Result<Bdev, i32> {
+    let mut disk = Box::new(MockDisk {
+        bdev: spdk_bdev::default(),
+        data: Mutex::new(vec![0; (num_blocks * u64::from(block_len)) as usize]),
+        behavior: Mutex::new(Behavior::default()),
+        reads: AtomicU64::new(0),
+        writes: AtomicU64::new(0),
+        failed: AtomicU64::new(0),
+        aborts: AtomicU64::new(0),
+        resets: AtomicU64::new(0),
+    });
+
+    disk.bdev.name = CString::new(name).unwrap().into_raw();
+    disk.bdev.product_name = CString::new(MOCK_PRODUCT_ID).unwrap().into_raw();
+    disk.bdev.fn_table = &MOCK_FN_TABLE.0;
+    disk.bdev.module = MOCK_MODULE.0;
+    disk.bdev.blocklen = block_len;
+    disk.bdev.blockcnt = num_blocks;
+
+    let disk = Box::into_raw(disk);
+
+    unsafe {
+        (*disk).bdev.ctxt = (*disk).as_ptr();
+        spdk_io_device_register(
+            (*disk).as_ptr(),
+            Some(channel_create),
+            Some(channel_destroy),
+            std::mem::size_of::<MockChannel>() as u32,
+            (*disk).bdev.name,
+        );
+
+        let errno = spdk_bdev_register(&mut (*disk).bdev);
+        if errno != 0 {
+            destruct((*disk).as_ptr());
+            return Err(errno);
+        }
+
+        Ok(Bdev::from(&mut (*disk).bdev as *mut spdk_bdev))
+    }
+}
+
+/// change the behaviour of a mock bdev, returns false if there is no mock
+/// bdev with the given name
+pub fn set_behavior(name: &str, behavior: Behavior) -> bool {
+    if let Some(disk) = MockDisk::lookup(name) {
+        info!("{}: mock behaviour set to {:?}", name, behavior);
+        *disk.behavior.lock().unwrap() = behavior;
+        true
+    } else {
+        false
+    }
+}
+
+/// returns the current behaviour of a mock bdev
+pub fn behavior(name: &str) -> Option<Behavior> {
+    MockDisk::lookup(name).map(|d| d.behavior.lock().unwrap().clone())
+}
+
+/// returns the IO counters of a mock bdev
+pub fn stats(name: &str) -> Option<MockStats> {
+    MockDisk::lookup(name).map(|d| MockStats {
+        reads: d.reads.load(Ordering::Relaxed),
+        writes: d.writes.load(Ordering::Relaxed),
+        failed: d.failed.load(Ordering::Relaxed),
+        aborts: d.aborts.load(Ordering::Relaxed),
+        resets: d.resets.load(Ordering::Relaxed),
+    })
+}
@@ -4,12 +4,15 @@ pub use nexus::{
     nexus_bdev::{
         nexus_create,
         nexus_lookup,
+        ChildFaultPolicy,
+        FaultPolicy,
         Nexus,
         NexusState,
         NexusStatus,
+        ReadPolicy,
         VerboseError,
     },
-    nexus_child::ChildStatus,
+    nexus_child::{ChildIoStats, ChildStatus},
     nexus_child_error_store::{ActionType, NexusErrStore, QueryType},
     nexus_label::{GPTHeader, GptEntry},
     nexus_metadata_content::{
@@ -18,6 +21,8 @@ pub use nexus::{
         NexusConfigVersion2,
         NexusConfigVersion3,
     },
+    nexus_share::HostAccess,
+    nexus_topology::{node_count, ChildTopology, NvmfController},
 };
 
 pub trait BdevCreateDestroy: CreateDestroy + GetName + std::fmt::Debug {}
@@ -41,6 +46,12 @@ pub trait GetName {
 
 pub struct Uri;
 
+pub mod delay;
 pub(crate) mod dev;
+pub mod fault;
+pub(crate) mod interpose;
+#[cfg(feature = "mock-children")]
+pub mod mock;
 pub(crate) mod nexus;
+pub mod pattern;
 pub mod util;
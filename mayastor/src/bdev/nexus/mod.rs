@@ -20,15 +20,26 @@ pub(crate) mod nexus_child;
 pub(crate) mod nexus_child_error_store;
 mod nexus_config;
 pub mod nexus_fn_table;
+pub(crate) mod nexus_hooks;
+pub(crate) mod nexus_intent_log;
 pub mod nexus_io;
 pub mod nexus_iscsi;
+pub(crate) mod nexus_key_rotation;
 pub mod nexus_label;
+pub mod nexus_label_v2;
+mod nexus_latency;
+pub(crate) mod nexus_local;
 pub mod nexus_metadata;
 pub mod nexus_metadata_content;
 pub mod nexus_module;
 pub mod nexus_nbd;
 pub mod nexus_nvmf;
+mod nexus_read_cache;
+pub mod nexus_resolver;
 pub mod nexus_share;
+mod nexus_snapshot;
+pub mod nexus_topology;
+mod nexus_write_cache;
 
 /// public function which simply calls register module
 pub fn register_module() {
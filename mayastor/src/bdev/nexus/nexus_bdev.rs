@@ -5,9 +5,12 @@
 //! application needs synchronous mirroring may be required.
 
 use std::{
+    collections::HashMap,
     fmt,
     fmt::{Display, Formatter},
     os::raw::c_void,
+    sync::Arc,
+    time::Duration,
 };
 
 use futures::channel::oneshot;
@@ -15,14 +18,18 @@ use nix::errno::Errno;
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use tonic::{Code, Status};
+use tracing::instrument;
 
 use spdk_sys::{
     spdk_bdev,
+    spdk_bdev_abort,
     spdk_bdev_desc,
     spdk_bdev_flush_blocks,
     spdk_bdev_io,
     spdk_bdev_io_get_buf,
+    spdk_bdev_io_get_io_channel,
     spdk_bdev_nvme_admin_passthru,
+    spdk_bdev_nvme_io_passthru,
     spdk_bdev_readv_blocks,
     spdk_bdev_register,
     spdk_bdev_reset,
@@ -42,17 +49,37 @@ use crate::{
             instances,
             nexus_channel::{DREvent, NexusChannel, NexusChannelInner},
             nexus_child::{ChildError, ChildState, ChildStatus, NexusChild},
-            nexus_io::{io_status, nvme_admin_opc, Bio},
+            nexus_child_error_store::{ActionType, ChildIoFailure},
+            nexus_intent_log::IntentLog,
+            nexus_io::{
+                io_status,
+                io_type,
+                nvme_admin_opc,
+                nvme_nvm_opc,
+                Bio,
+                ChildIoError,
+                NexusIoStats,
+                MAX_RETRIED_CHILDREN,
+            },
             nexus_iscsi::{NexusIscsiError, NexusIscsiTarget},
+            nexus_key_rotation::KeyRotation,
             nexus_label::LabelError,
+            nexus_label_v2::LabelV2,
+            nexus_local,
             nexus_nbd::{NbdDisk, NbdError},
             nexus_nvmf::{NexusNvmfError, NexusNvmfTarget},
+            nexus_read_cache::ReadCache,
+            nexus_share::HostAccess,
+            nexus_write_cache::WriteCache,
         },
     },
-    core::{Bdev, DmaError, Share},
+    core::{sleep, Bdev, CoreError, DmaError, QosLimits, Reactors, Share},
     ffihelper::errno_result_from_i32,
+    iscsi_portal,
+    limits,
     nexus_uri::{bdev_destroy, NexusBdevError},
     rebuild::RebuildError,
+    subsys::{events, Config, LiveOpts},
 };
 
 /// Obtain the full error chain
@@ -97,6 +124,12 @@ pub enum Error {
         name
     ))]
     AlreadyShared { name: String },
+    #[snafu(display(
+        "Invalid hosts to share nexus {} with: {}",
+        name,
+        reason
+    ))]
+    InvalidHosts { name: String, reason: String },
     #[snafu(display("The nexus {} has not been shared", name))]
     NotShared { name: String },
     #[snafu(display("Failed to share nexus over NBD {}", name))]
@@ -111,6 +144,21 @@ pub enum Error {
         source: NexusNvmfError,
         name: String,
     },
+    #[snafu(display("Cannot rotate the key of nexus {}: {}", name, reason))]
+    CannotRotateKey { name: String, reason: String },
+    #[snafu(display(
+        "Failed to rotate the key of nexus {}: {}",
+        name,
+        reason
+    ))]
+    RotateKey { name: String, reason: String },
+    #[snafu(display("The nexus {} has not been shared over nvmf", name))]
+    NotSharedNvmf { name: String },
+    #[snafu(display("Failed to set ANA state of nexus {}", name))]
+    SetAnaState {
+        source: NexusNvmfError,
+        name: String,
+    },
     #[snafu(display("Failed to allocate label of nexus {}", name))]
     AllocLabel { source: DmaError, name: String },
     #[snafu(display("Failed to write label of nexus {}", name))]
@@ -170,6 +218,17 @@ pub enum Error {
     ChildNotFound { child: String, name: String },
     #[snafu(display("Suitable rebuild source for nexus {} not found", name))]
     NoRebuildSource { name: String },
+    #[snafu(display(
+        "Child {} of nexus {} cannot be a rebuild source: {}",
+        child,
+        name,
+        reason
+    ))]
+    InvalidRebuildSource {
+        child: String,
+        name: String,
+        reason: String,
+    },
     #[snafu(display(
         "Failed to create rebuild job for child {} of nexus {}",
         child,
@@ -212,8 +271,14 @@ pub enum Error {
     },
     #[snafu(display("Invalid ShareProtocol value {}", sp_value))]
     InvalidShareProtocol { sp_value: i32 },
+    #[snafu(display("Invalid FaultPolicy value {}", value))]
+    InvalidFaultPolicy { value: i32 },
+    #[snafu(display("Invalid ReadPolicy value {}", value))]
+    InvalidReadPolicy { value: i32 },
     #[snafu(display("Failed to create nexus {}", name))]
     NexusCreate { name: String },
+    #[snafu(display("Nexus {} exceeds the limits of the node", name))]
+    NodeLimit { source: limits::Error, name: String },
     #[snafu(display("Failed to destroy nexus {}", name))]
     NexusDestroy { name: String },
     #[snafu(display(
@@ -227,6 +292,74 @@ pub enum Error {
         name: String,
         state: String,
     },
+    #[snafu(display("Failed to set QoS limits of nexus {}", name))]
+    SetQos { source: CoreError, name: String },
+    #[snafu(display(
+        "Failed to write back the cache of nexus {}: {}",
+        name,
+        reason
+    ))]
+    WriteBackCache { name: String, reason: String },
+    #[snafu(display(
+        "Invalid read cache {} for nexus {}: {}",
+        uri,
+        name,
+        reason
+    ))]
+    InvalidReadCache {
+        name: String,
+        uri: String,
+        reason: String,
+    },
+    #[snafu(display("Failed to create read cache {} of nexus {}", uri, name))]
+    CreateReadCache {
+        source: NexusBdevError,
+        name: String,
+        uri: String,
+    },
+    #[snafu(display("Failed to create a snapshot of nexus {}", name))]
+    CreateSnapshot { source: CoreError, name: String },
+    #[snafu(display(
+        "Cannot restore a snapshot of nexus {}: {}",
+        name,
+        reason
+    ))]
+    CannotRestoreSnapshot { name: String, reason: String },
+    #[snafu(display(
+        "Failed to restore snapshot {} of nexus {}: {}",
+        snapshot,
+        name,
+        reason
+    ))]
+    RestoreSnapshot {
+        name: String,
+        snapshot: u64,
+        reason: String,
+    },
+    #[snafu(display(
+        "Cannot switch child {} of nexus {}: {}",
+        child,
+        name,
+        reason
+    ))]
+    CannotSwitchChild {
+        name: String,
+        child: String,
+        reason: String,
+    },
+    #[snafu(display(
+        "Failed to switch child {} of nexus {} to {}: {}",
+        child,
+        name,
+        uri,
+        reason
+    ))]
+    SwitchChild {
+        name: String,
+        child: String,
+        uri: String,
+        reason: String,
+    },
 }
 
 impl From<Error> for tonic::Status {
@@ -244,9 +377,51 @@ impl From<Error> for tonic::Status {
             Error::AlreadyShared {
                 ..
             } => Status::invalid_argument(e.to_string()),
+            Error::InvalidHosts {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::InvalidReadCache {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::CreateReadCache {
+                ..
+            } => Status::invalid_argument(e.to_string()),
             Error::NotShared {
                 ..
             } => Status::invalid_argument(e.to_string()),
+            Error::CannotRotateKey {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::NotSharedNvmf {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::SetAnaState {
+                source:
+                    NexusNvmfError::InvalidAnaState {
+                        ..
+                    },
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::ShareIscsiNexus {
+                source:
+                    NexusIscsiError::PortalFailed {
+                        source: iscsi_portal::Error::InvalidAddress {
+                            ..
+                        },
+                        ..
+                    },
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::ShareIscsiNexus {
+                source:
+                    NexusIscsiError::PortalFailed {
+                        source: iscsi_portal::Error::InterfaceNotFound {
+                            ..
+                        },
+                        ..
+                    },
+                ..
+            } => Status::invalid_argument(e.to_string()),
             Error::CreateChild {
                 ..
             } => Status::invalid_argument(e.to_string()),
@@ -265,6 +440,27 @@ impl From<Error> for tonic::Status {
             Error::ChildNotFound {
                 ..
             } => Status::not_found(e.to_string()),
+            Error::InvalidRebuildSource {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::SetQos {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::InvalidFaultPolicy {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::InvalidReadPolicy {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::NodeLimit {
+                source, ..
+            } => Status::from(source),
+            Error::CannotRestoreSnapshot {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::CannotSwitchChild {
+                ..
+            } => Status::failed_precondition(e.to_string()),
             e => Status::new(Code::Internal, e.to_string()),
         }
     }
@@ -314,6 +510,39 @@ pub struct Nexus {
     pub(crate) share_handle: Option<String>,
     /// enum containing the protocol-specific target used to publish the nexus
     pub nexus_target: Option<NexusTarget>,
+    /// the nexus is published read-only, writes are failed rather than
+    /// dispatched to the children
+    pub(crate) read_only: bool,
+    /// the rotation of the key since the nexus was published encrypted
+    pub(crate) key_rotation: Option<Arc<KeyRotation>>,
+    /// the hosts the nexus is published to
+    pub(crate) host_access: HostAccess,
+    /// what to do with IO when no healthy child is left
+    pub(crate) fault_policy: FaultPolicy,
+    /// which child a read is sent to
+    pub(crate) read_policy: ReadPolicy,
+    /// when to fault a child due to its IO errors, that of the config if
+    /// none
+    pub(crate) child_fault_policy: Option<ChildFaultPolicy>,
+    /// URIs of the replicas which replace the children faulted due to their
+    /// IO errors, in order
+    pub(crate) spares: Vec<String>,
+    /// the child which was taken offline last, leaving no healthy child
+    pub(crate) last_online_child: Option<String>,
+    /// the write-back cache, if enabled
+    pub(crate) write_cache: Option<Arc<WriteCache>>,
+    /// the read cache on a local device, if enabled
+    pub(crate) read_cache: Option<Arc<ReadCache>>,
+    /// the write-intent log, if enabled
+    pub(crate) intent_log: Option<Arc<IntentLog>>,
+    /// the record of the v2 label last written to the children
+    pub(crate) label_v2: Option<LabelV2>,
+    /// bytes written to the children when writing back the cache
+    pub(crate) write_back_bytes: u64,
+    /// bytes written to the children by the rebuild jobs which are done
+    pub(crate) rebuild_bytes_written: u64,
+    /// the children chosen to rebuild a child from, by the child rebuilt
+    pub(crate) rebuild_sources: HashMap<String, String>,
 }
 
 unsafe impl core::marker::Sync for Nexus {}
@@ -329,6 +558,54 @@ pub enum NexusStatus {
     Online,
 }
 
+/// max time IO is held by a frozen nexus unless specified otherwise
+pub const DEFAULT_FREEZE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the nexus does with IO when its last healthy child has failed
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum FaultPolicy {
+    /// fail the IO right away
+    Fail,
+    /// hold the IO for at most the given time, awaiting a child to come back
+    /// online, and fail it after that
+    Freeze(Duration),
+}
+
+impl Default for FaultPolicy {
+    fn default() -> Self {
+        FaultPolicy::Fail
+    }
+}
+
+/// Which of the healthy children a read is sent to, the children being
+/// rebuilt are never read from
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum ReadPolicy {
+    /// each child in turn
+    RoundRobin,
+    /// the child with the fewest reads in flight on the core, the next one
+    /// in turn among those with as few
+    QueueDepth,
+    /// the children served by this node in turn, the others in turn if
+    /// there is none
+    PreferLocal,
+}
+
+impl Default for ReadPolicy {
+    fn default() -> Self {
+        ReadPolicy::RoundRobin
+    }
+}
+
+/// When a child is faulted due to its IO errors
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct ChildFaultPolicy {
+    /// the number of failed reads and writes a child may have
+    pub max_errors: u32,
+    /// within this time
+    pub window: Duration,
+}
+
 #[derive(Debug, Serialize, Clone, Copy, PartialEq, PartialOrd)]
 pub enum NexusState {
     /// nexus created but no children attached
@@ -403,6 +680,21 @@ impl Nexus {
             share_handle: None,
             size,
             nexus_target: None,
+            read_only: false,
+            key_rotation: None,
+            host_access: HostAccess::default(),
+            fault_policy: FaultPolicy::default(),
+            read_policy: ReadPolicy::default(),
+            child_fault_policy: None,
+            spares: Vec::new(),
+            last_online_child: None,
+            write_cache: None,
+            read_cache: None,
+            intent_log: None,
+            label_v2: None,
+            write_back_bytes: 0,
+            rebuild_bytes_written: 0,
+            rebuild_sources: HashMap::new(),
         });
 
         n.bdev.set_uuid(match uuid {
@@ -462,7 +754,10 @@ impl Nexus {
 
         self.try_open_children()?;
         self.sync_labels().await?;
-        self.register()
+        self.register()?;
+        self.rebuild_removed_children().await;
+        self.resync_intent_log().await;
+        Ok(())
     }
 
     pub async fn sync_labels(&mut self) -> Result<(), Error> {
@@ -482,6 +777,10 @@ impl Nexus {
             label.get_block_count(),
         ));
 
+        self.sync_label_v2(&label).await.context(WriteLabel {
+            name: self.name.clone(),
+        })?;
+        self.open_intent_log(&label).await;
         Ok(())
     }
 
@@ -510,6 +809,7 @@ impl Nexus {
     }
 
     /// Destroy the nexus
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
     pub async fn destroy(&mut self) -> Result<(), Error> {
         // used to synchronize the destroy call
         extern "C" fn nexus_destroy_cb(arg: *mut c_void, rc: i32) {
@@ -526,6 +826,11 @@ impl Nexus {
         let _ = self.unshare_nexus().await;
         assert_eq!(self.share_handle, None);
 
+        if let Err(e) = self.set_write_cache(0).await {
+            error!("{}", e);
+        }
+        self.close_intent_log().await;
+
         // no-op when not shared and will be removed once the old share bits are
         // gone
         self.bdev.unshare().await.unwrap();
@@ -545,7 +850,10 @@ impl Nexus {
         }
 
         info!("Destroying nexus {}", self.name);
+        let name = self.name.clone();
 
+        // the nexus is freed once unregistered
+        let read_cache = self.read_cache.take();
         let (s, r) = oneshot::channel::<bool>();
 
         unsafe {
@@ -557,11 +865,17 @@ impl Nexus {
             );
         }
 
-        if r.await.unwrap() {
+        let destroyed = r.await.unwrap();
+        if let Some(cache) = read_cache {
+            cache.destroy().await;
+        }
+
+        if destroyed {
+            events::nexus_destroyed(&name);
             Ok(())
         } else {
             Err(Error::NexusDestroy {
-                name: self.name.clone(),
+                name,
             })
         }
     }
@@ -614,6 +928,98 @@ impl Nexus {
         &mut *(n as *mut Nexus)
     }
 
+    /// IO stats aggregated over the IO channels of all cores, including the
+    /// bytes written to the children outside of the IO path, None if the
+    /// nexus is not open for IO
+    pub async fn io_stats(&self) -> Option<NexusIoStats> {
+        if self.state != NexusState::Open {
+            return None;
+        }
+        let mut stats = NexusChannel::io_stats(self.as_ptr()).await;
+        stats.child_bytes_written += self.write_back_bytes;
+        stats.rebuild_bytes_written = self.rebuild_bytes_written();
+        if let Some(cache) = self.read_cache.as_ref() {
+            let (hits, misses) = cache.hits_and_misses();
+            stats.read_cache_hits = hits;
+            stats.read_cache_misses = misses;
+        }
+        Some(stats)
+    }
+
+    /// set the QoS rate limits of the nexus, throttling the front-end IO
+    pub async fn set_qos(&self, limits: QosLimits) -> Result<(), Error> {
+        self.bdev.set_qos_limits(limits).await.context(SetQos {
+            name: self.name.clone(),
+        })
+    }
+
+    /// QoS rate limits in effect for the nexus
+    pub fn qos(&self) -> QosLimits {
+        self.bdev.qos_limits()
+    }
+
+    /// set what to do with IO when no healthy child is left, IO which is
+    /// frozen already keeps its deadline
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        info!("{}: Setting fault policy {:?}", self.name, policy);
+        self.fault_policy = policy;
+    }
+
+    /// what is done with IO when no healthy child is left
+    pub fn fault_policy(&self) -> FaultPolicy {
+        self.fault_policy
+    }
+
+    /// set which child a read is sent to, which applies to the reads
+    /// submitted from now on
+    pub fn set_read_policy(&mut self, policy: ReadPolicy) {
+        info!("{}: Setting read policy {:?}", self.name, policy);
+        self.read_policy = policy;
+    }
+
+    /// which child a read is sent to
+    pub fn read_policy(&self) -> ReadPolicy {
+        self.read_policy
+    }
+
+    /// set when a child is faulted due to its IO errors, none for the policy
+    /// of the config, and the spares which replace the faulted children
+    pub fn set_child_fault_policy(
+        &mut self,
+        policy: Option<ChildFaultPolicy>,
+        spares: Vec<String>,
+    ) {
+        info!(
+            "{}: Setting child fault policy {:?} with spares {:?}",
+            self.name, policy, spares
+        );
+        self.child_fault_policy = policy;
+        self.spares = spares;
+    }
+
+    /// when a child is faulted due to its IO errors, none if it is not
+    pub fn child_fault_policy(&self) -> Option<ChildFaultPolicy> {
+        if self.child_fault_policy.is_some() {
+            return self.child_fault_policy;
+        }
+
+        let cfg = Config::get();
+        let opts = &cfg.err_store_opts;
+        if opts.action == ActionType::Fault {
+            Some(ChildFaultPolicy {
+                max_errors: opts.max_errors,
+                window: Duration::from_nanos(opts.retention_ns),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// the spares which replace the children faulted due to their IO errors
+    pub fn spares(&self) -> &[String] {
+        &self.spares
+    }
+
     /// determine if any of the children do not support the requested
     /// io type. Break the loop on first occurrence.
     /// TODO: optionally add this check during nexus creation
@@ -624,6 +1030,17 @@ impl Nexus {
             .any(|b| b.io_type_supported(io_type))
     }
 
+    /// determine if all of the children support the requested io type, as
+    /// needed for the commands which are mirrored to all of them
+    pub fn io_is_supported_by_all(&self, io_type: u32) -> bool {
+        !self.children.is_empty()
+            && self.children.iter().all(|c| {
+                c.bdev
+                    .as_ref()
+                    .map_or(false, |b| b.io_type_supported(io_type))
+            })
+    }
+
     /// main IO completion routine
     unsafe extern "C" fn io_completion(
         child_io: *mut spdk_bdev_io,
@@ -632,6 +1049,25 @@ impl Nexus {
     ) {
         let mut pio = Bio(parent_io as *mut _);
 
+        if Bio::io_type(child_io) == Some(io_type::READ) {
+            NexusChannel::inner_from_channel(spdk_bdev_io_get_io_channel(
+                pio.0,
+            ))
+            .read_completed((*child_io).bdev);
+        }
+
+        if !success
+            && (Self::failover_read(&mut pio, child_io)
+                || Self::resubmit_later(&mut pio, child_io))
+        {
+            Bio::io_free(child_io);
+            return;
+        }
+
+        if success {
+            Self::child_io_done(&pio, child_io);
+        }
+
         // if any child IO has failed record this within the io context
         if !success {
             trace!(
@@ -641,13 +1077,200 @@ impl Nexus {
                 pio
             );
 
-            pio.ctx_as_mut_ref().status = io_status::FAILED;
+            if Bio::is_aborted(child_io)
+                && pio.ctx_as_mut_ref().status != io_status::FAILED
+            {
+                pio.ctx_as_mut_ref().status = io_status::ABORTED;
+            } else {
+                pio.ctx_as_mut_ref().status = io_status::FAILED;
+            }
         }
         pio.assess(child_io, success);
         // always free the child IO
         Bio::io_free(child_io);
     }
 
+    /// count a read or write of the nexus which succeeded against the child
+    /// it was routed to, and have the child degraded if its writes are too
+    /// slow
+    fn child_io_done(pio: &Bio, child_io: *mut spdk_bdev_io) {
+        let read = match Bio::io_type(child_io) {
+            Some(io_type::READ) => true,
+            Some(io_type::WRITE) => false,
+            _ => return,
+        };
+        let bdev = unsafe { (*child_io).bdev };
+        let nexus = pio.nexus_as_ref();
+        if let Some(child) = nexus
+            .children
+            .iter()
+            .find(|c| c.bdev.as_ref().map_or(false, |b| b.as_ptr() == bdev))
+        {
+            child.io_done(read, pio.data_bytes());
+            let submitted = unsafe { (*child_io).internal.submit_tsc };
+            if !read && child.slow_writes.record(submitted) {
+                nexus.child_too_slow(&child.name);
+            }
+        }
+    }
+
+    /// Fail a read which failed on a child over to another child of the
+    /// channel, if the retry options of the nexus enable read failover. The
+    /// children a read has failed on are kept in its context, so that it is
+    /// tried on every child which is read from at most once. Returns true if
+    /// the read was resubmitted, in which case the failure is recorded
+    /// against the child as retried.
+    fn failover_read(pio: &mut Bio, child_io: *mut spdk_bdev_io) -> bool {
+        if Bio::io_type(pio.0) != Some(io_type::READ)
+            || Bio::is_aborted(child_io)
+            || !Config::get().nexus_opts.child_io_retry.read_failover
+        {
+            return false;
+        }
+
+        let channels = NexusChannel::inner_from_channel(unsafe {
+            spdk_bdev_io_get_io_channel(pio.0)
+        });
+        let failed = unsafe { (*child_io).bdev };
+        let ctx = pio.ctx_as_mut_ref();
+        let count = ctx.failovers as usize;
+        if !ctx.failed_on[.. count].contains(&failed) {
+            if count == MAX_RETRIED_CHILDREN {
+                return false;
+            }
+            ctx.failed_on[count] = failed;
+            ctx.failovers += 1;
+        }
+        let failed_on = &ctx.failed_on[.. ctx.failovers as usize];
+
+        let readable = channels.ch.len() - channels.write_only;
+        let child = match (0 .. readable)
+            .map(|_| channels.child_select())
+            .find(|&c| !failed_on.contains(&channels.ch[c].get_bdev().as_ptr()))
+        {
+            Some(child) => child,
+            None => return false,
+        };
+
+        let (desc, ch) = channels.ch[child].io_tuple();
+        if Self::readv_impl(pio.0, desc, ch) != 0 {
+            return false;
+        }
+        channels.read_dispatched(child);
+
+        trace!(
+            "{}: read {:?} failed by {:?} failed over to {:?}",
+            pio.nexus_as_ref().name,
+            pio,
+            failed,
+            channels.ch[child].get_bdev().name(),
+        );
+        pio.nexus_as_ref().error_record_add(
+            failed,
+            io_type::READ,
+            ChildIoFailure {
+                timed_out: Bio::is_timed_out(child_io),
+                retried: true,
+                resubmitted: false,
+            },
+            pio.offset(),
+            pio.num_blocks(),
+        );
+        true
+    }
+
+    /// Resubmit a read or write which failed on a child because its path
+    /// was down to the same child after a backoff, unless the IO has no
+    /// retries left under the retry options of the nexus. Media and other
+    /// errors are not retried on the same child. Returns true if the IO is
+    /// resubmitted, in which case the failure is recorded against the child
+    /// as retried, without counting towards faulting it.
+    fn resubmit_later(pio: &mut Bio, child_io: *mut spdk_bdev_io) -> bool {
+        let op = match Bio::io_type(pio.0) {
+            Some(op @ io_type::READ) | Some(op @ io_type::WRITE) => op,
+            _ => return false,
+        };
+        if Bio::error_kind(child_io) != ChildIoError::PathDown {
+            return false;
+        }
+
+        let opts = Config::get().nexus_opts.child_io_retry;
+        let failed = unsafe { (*child_io).bdev };
+        let retry = match pio.ctx_as_mut_ref().resubmits_to(failed) {
+            Some(resubmits) if *resubmits < opts.max_retries => {
+                *resubmits += 1;
+                *resubmits - 1
+            }
+            _ => return false,
+        };
+
+        trace!(
+            "{}: resubmitting IO {:?} failed by {:?}, retry {}",
+            pio.nexus_as_ref().name,
+            pio,
+            failed,
+            retry + 1,
+        );
+        pio.nexus_as_ref().error_record_add(
+            failed,
+            op,
+            ChildIoFailure {
+                timed_out: Bio::is_timed_out(child_io),
+                retried: true,
+                resubmitted: true,
+            },
+            pio.offset(),
+            pio.num_blocks(),
+        );
+
+        let parent = pio.0;
+        Reactors::current().spawn_local(async move {
+            sleep(opts.backoff(retry)).await;
+            Self::resubmit(parent, failed, op);
+        });
+        true
+    }
+
+    /// resubmit an IO to the child with the given bdev, on the channel the IO
+    /// was submitted on, failing the IO if the child is no longer there
+    fn resubmit(parent: *mut spdk_bdev_io, bdev: *mut spdk_bdev, op: u32) {
+        let mut pio = Bio(parent);
+        let channels = NexusChannel::inner_from_channel(unsafe {
+            spdk_bdev_io_get_io_channel(parent)
+        });
+
+        let rc = match channels
+            .ch
+            .iter()
+            .position(|c| c.get_bdev().as_ptr() == bdev)
+        {
+            Some(child) => {
+                let (desc, ch) = channels.ch[child].io_tuple();
+                if op == io_type::READ {
+                    let rc = Self::readv_impl(parent, desc, ch);
+                    if rc == 0 {
+                        channels.read_dispatched(child);
+                    }
+                    rc
+                } else {
+                    Self::writev_impl(parent, desc, ch)
+                }
+            }
+            None => -libc::ENODEV,
+        };
+
+        if rc != 0 {
+            error!(
+                "{}: Failed to resubmit IO {:?}: {}",
+                pio.nexus_as_ref().name,
+                pio,
+                rc
+            );
+            pio.ctx_as_mut_ref().status = io_status::FAILED;
+            pio.assess(std::ptr::null(), false);
+        }
+    }
+
     /// callback when the IO has buffer associated with itself
     extern "C" fn nexus_get_buf_cb(
         ch: *mut spdk_io_channel,
@@ -660,10 +1283,13 @@ impl Nexus {
             warn!("{}: Failed to get io buffer for io {:?}", nexus.name, bio);
         }
 
-        let ch = NexusChannel::inner_from_channel(ch);
-        let (desc, ch) = ch.ch[ch.previous].io_tuple();
+        let channels = NexusChannel::inner_from_channel(ch);
+        let child = channels.previous;
+        let (desc, ch) = channels.ch[child].io_tuple();
         let ret = Self::readv_impl(io, desc, ch);
-        if ret != 0 {
+        if ret == 0 {
+            channels.read_dispatched(child);
+        } else {
             let bio = Bio(io);
             let nexus = bio.nexus_as_ref();
             error!("{}: Failed to submit IO {:?}", nexus.name, bio);
@@ -678,9 +1304,10 @@ impl Nexus {
     ) {
         let mut io = Bio::new(pio, 1); // only 1 in flight
 
-        // we use RR to read from the children also, set that we only need
-        // to read from one child before we complete the IO to the callee.
-        let child = channels.child_select();
+        // we read from one child selected by the read policy, set that we
+        // only need to read from one child before we complete the IO to the
+        // callee.
+        let child = channels.read_select();
 
         // if there is no buffer space for us allocated within the request
         // allocate it now, taking care of proper alignment
@@ -699,7 +1326,9 @@ impl Nexus {
 
         let ret = Self::readv_impl(pio, desc, ch);
 
-        if ret != 0 {
+        if ret == 0 {
+            channels.read_dispatched(child);
+        } else {
             error!(
                 "{}: Failed to submit dispatched IO {:p}",
                 io.nexus_as_ref().name,
@@ -765,6 +1394,84 @@ impl Nexus {
         }
     }
 
+    /// completion of the abort of the child IOs of a front-end IO, the
+    /// abort succeeds if any child succeeded to abort its IO
+    unsafe extern "C" fn abort_completion(
+        child_io: *mut spdk_bdev_io,
+        success: bool,
+        parent_io: *mut c_void,
+    ) {
+        let mut pio = Bio(parent_io as *mut _);
+        if success {
+            pio.ctx_as_mut_ref().status = io_status::SUCCESS;
+        }
+        Self::abort_assess(&mut pio);
+        Bio::io_free(child_io);
+    }
+
+    /// drop a reference to an abort IO, completing it when it was the last
+    fn abort_assess(pio: &mut Bio) {
+        pio.ctx_as_mut_ref().in_flight -= 1;
+        if pio.ctx_as_mut_ref().in_flight == 0 {
+            if pio.ctx_as_mut_ref().status == io_status::SUCCESS {
+                pio.ok();
+            } else {
+                pio.fail();
+            }
+        }
+    }
+
+    /// Abort a front-end IO, as requested by an initiator, by aborting its
+    /// child IOs. These are found by their callback argument, which is the
+    /// front-end IO. Only reads are aborted: a write that is aborted on some
+    /// children but not on others would leave the children inconsistent, so
+    /// the abort of any other IO fails and the IO completes as usual.
+    pub(crate) fn abort(
+        &self,
+        pio: *mut spdk_bdev_io,
+        channels: &NexusChannelInner,
+    ) {
+        // hold a reference of our own while dispatching, such that the abort
+        // is not completed before all children have been dispatched
+        let mut io = Bio::new(pio, channels.ch.len() as i8 + 1);
+        io.ctx_as_mut_ref().status = io_status::FAILED;
+
+        let bio_to_abort = io.bio_to_abort();
+        if Bio::io_type(bio_to_abort) == Some(io_type::READ) {
+            for c in channels.ch.iter() {
+                let (desc, chan) = c.io_tuple();
+                let rc = unsafe {
+                    spdk_bdev_abort(
+                        desc,
+                        chan,
+                        bio_to_abort as *mut _,
+                        Some(Self::abort_completion),
+                        pio as *mut _,
+                    )
+                };
+                // children that do not support abort are skipped
+                if rc != 0 {
+                    trace!(
+                        "{}: Failed to dispatch abort of {:?} rc={}",
+                        self.name,
+                        Bio(bio_to_abort),
+                        rc
+                    );
+                    Self::abort_assess(&mut io);
+                }
+            }
+        } else {
+            trace!(
+                "{}: Not aborting {:?} which is not a read",
+                self.name,
+                Bio(bio_to_abort)
+            );
+            io.ctx_as_mut_ref().in_flight = 1;
+        }
+
+        Self::abort_assess(&mut io);
+    }
+
     /// write vectored IO to the underlying children.
     pub(crate) fn writev(
         &self,
@@ -776,18 +1483,9 @@ impl Nexus {
         let results = channels
             .ch
             .iter()
-            .map(|c| unsafe {
+            .map(|c| {
                 let (desc, chan) = c.io_tuple();
-                spdk_bdev_writev_blocks(
-                    desc,
-                    chan,
-                    io.iovs(),
-                    io.iov_count(),
-                    io.offset() + io.nexus_as_ref().data_ent_offset,
-                    io.num_blocks(),
-                    Some(Self::io_completion),
-                    pio as *mut _,
-                )
+                Self::writev_impl(pio, desc, chan)
             })
             .collect::<Vec<_>>();
 
@@ -801,6 +1499,28 @@ impl Nexus {
         }
     }
 
+    /// do the actual write to a child
+    fn writev_impl(
+        pio: *mut spdk_bdev_io,
+        desc: *mut spdk_bdev_desc,
+        ch: *mut spdk_io_channel,
+    ) -> i32 {
+        let io = Bio(pio);
+        let nexus = io.nexus_as_ref();
+        unsafe {
+            spdk_bdev_writev_blocks(
+                desc,
+                ch,
+                io.iovs(),
+                io.iov_count(),
+                io.offset() + nexus.data_ent_offset,
+                io.num_blocks(),
+                Some(Self::io_completion),
+                pio as *mut _,
+            )
+        }
+    }
+
     pub(crate) fn unmap(
         &self,
         pio: *mut spdk_bdev_io,
@@ -932,6 +1652,60 @@ impl Nexus {
         }
     }
 
+    /// NVMe IO passthru, of which only the reservation commands are
+    /// supported. The reservations are registered, acquired and released on
+    /// all children alike, so any child reports the same reservation state
+    /// and it survives the loss of a child. As the children are replicas
+    /// shared over nvmf, the reservations are kept by their targets.
+    pub(crate) fn nvme_io(
+        &self,
+        pio: *mut spdk_bdev_io,
+        channels: &NexusChannelInner,
+    ) {
+        let mut io = Bio(pio);
+        let cmd = io.nvme_cmd();
+        let targets = match cmd.opc() as u8 {
+            nvme_nvm_opc::RESERVATION_REGISTER
+            | nvme_nvm_opc::RESERVATION_ACQUIRE
+            | nvme_nvm_opc::RESERVATION_RELEASE => &channels.ch[..],
+            nvme_nvm_opc::RESERVATION_REPORT => &channels.ch[.. 1],
+            opc => {
+                trace!(
+                    "{}: Rejecting NVMe IO passthru with opcode {:#x}",
+                    self.name,
+                    opc
+                );
+                io.fail();
+                return;
+            }
+        };
+
+        let io = Bio::new(pio, targets.len() as i8);
+        let results = targets
+            .iter()
+            .map(|c| unsafe {
+                let (desc, chan) = c.io_tuple();
+                spdk_bdev_nvme_io_passthru(
+                    desc,
+                    chan,
+                    &cmd,
+                    io.nvme_buf(),
+                    io.nvme_nbytes(),
+                    Some(Self::io_completion),
+                    pio as *mut _,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if results.iter().any(|r| *r != 0) {
+            error!(
+                "{}: Failed to submit dispatched IO {:?}",
+                io.nexus_as_ref().name,
+                pio
+            );
+        }
+    }
+
     /// Status of the nexus
     /// Online
     /// All children must also be online
@@ -989,6 +1763,9 @@ pub async fn nexus_create(
         // children are the same, which seems wrong
         return Ok(());
     }
+    limits::check_nexus().context(NodeLimit {
+        name: String::from(name),
+    })?;
 
     let mut ni = Nexus::new(name, size, uuid, None);
 
@@ -1010,6 +1787,9 @@ pub async fn nexus_create(
         }) => {
             info!("deleting nexus due to missing children");
             for child in children {
+                if nexus_local::lookup(child).is_some() {
+                    continue;
+                }
                 if let Err(e) = bdev_destroy(child).await {
                     error!("failed to destroy child during cleanup {}", e);
                 }
@@ -1025,7 +1805,16 @@ pub async fn nexus_create(
             return Err(e);
         }
 
-        Ok(_) => nexus_list.push(ni),
+        Ok(_) => {
+            let qos = QosLimits::from(LiveOpts::get().qos_defaults);
+            if qos != QosLimits::default() {
+                if let Err(e) = ni.set_qos(qos).await {
+                    error!("{}: {}", name, e.verbose());
+                }
+            }
+            nexus_list.push(ni);
+            events::nexus_created(name);
+        }
     }
     Ok(())
 }
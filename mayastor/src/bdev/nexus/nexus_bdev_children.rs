@@ -15,21 +15,36 @@
 //! 'fault_child` will do the same as `offline_child` except, it will not close
 //! the child.
 //!
+//! `read_only_child` faults a child whose writes are refused as the pool of
+//! its replica has become read-only.
+//!
 //! `add_child` will construct a new `NexusChild` and add the bdev given by the
 //! uri to the nexus. The nexus will transition to degraded mode as the new
 //! child requires rebuild first. If the rebuild flag is set then the rebuild
 //! is also started otherwise it has to be started through `start_rebuild`.
+//! `add_child_from` does the same, rebuilding the child from the given one.
+//!
+//! `replace_child` will add a child for the new URI of a faulted child, as
+//! given by the URI resolver, and remove the faulted one.
+//!
+//! `switch_child` will move an online child to another URI of its replica,
+//! holding off the writes to the nexus meanwhile, so that it does not need to
+//! be rebuilt.
 //!
 //! When reconfiguring the nexus, we traverse all our children, create new IO
 //! channels for all children that are in the open state.
 
 use futures::future::join_all;
 use snafu::ResultExt;
+use tracing::instrument;
+
+use spdk_sys::spdk_bdev;
 
 use crate::{
     bdev::{
         nexus::{
             nexus_bdev::{
+                nexus_lookup,
                 CreateChild,
                 DestroyChild,
                 Error,
@@ -40,19 +55,34 @@ use crate::{
             },
             nexus_channel::DREvent,
             nexus_child::{ChildState, ChildStatus, NexusChild},
+            nexus_hooks,
             nexus_label::{
                 LabelError,
                 NexusChildLabel,
                 NexusLabel,
                 NexusLabelStatus,
             },
+            nexus_local,
+            nexus_resolver,
         },
         VerboseError,
     },
-    core::Bdev,
+    core::{Bdev, BdevHandle, RangeContext, Reactors},
     nexus_uri::{bdev_create, bdev_destroy, NexusBdevError},
+    rebuild::RebuildJob,
+    subsys::events,
 };
 
+/// The UUID of the replica the child URI is of, which ends the NQN or IQN
+/// of a shared replica and is the name of a local one.
+fn replica_uuid(uri: &str) -> Option<String> {
+    url::Url::parse(uri)
+        .ok()?
+        .path_segments()?
+        .find_map(|s| uuid::Uuid::parse_str(s.rsplit(':').next()?).ok())
+        .map(|u| u.to_string())
+}
+
 impl Nexus {
     /// register children with the nexus, only allowed during the nexus init
     /// phase
@@ -79,12 +109,22 @@ impl Nexus {
         uri: &str,
     ) -> Result<(), NexusBdevError> {
         assert_eq!(self.state, NexusState::Init);
-        let name = bdev_create(&uri).await?;
-        self.children.push(NexusChild::new(
-            uri.to_string(),
-            self.name.clone(),
-            Bdev::lookup_by_name(&name),
-        ));
+        let child = match nexus_local::lookup(uri) {
+            Some(bdev) => NexusChild::local(
+                uri.to_string(),
+                self.name.clone(),
+                bdev,
+            ),
+            None => {
+                let name = bdev_create(&uri).await?;
+                NexusChild::new(
+                    uri.to_string(),
+                    self.name.clone(),
+                    Bdev::lookup_by_name(&name),
+                )
+            }
+        };
+        self.children.push(child);
 
         self.child_count += 1;
         Ok(())
@@ -99,12 +139,37 @@ impl Nexus {
     /// The rebuild flag dictates wether we attempt to start the rebuild or not
     /// If the rebuild fails to start the child remains degraded until such
     /// time the rebuild is retried and complete
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
     pub async fn add_child(
         &mut self,
         uri: &str,
         norebuild: bool,
     ) -> Result<NexusStatus, Error> {
-        let status = self.add_child_only(uri).await?;
+        self.add_child_from(uri, norebuild, None).await
+    }
+
+    /// Add a new child as [`Nexus::add_child`] does, choosing the child it
+    /// is rebuilt from, now or when its rebuild is started later on.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
+    pub async fn add_child_from(
+        &mut self,
+        uri: &str,
+        norebuild: bool,
+        source: Option<&str>,
+    ) -> Result<NexusStatus, Error> {
+        // the source is checked before the child is added
+        if let Some(source) = source {
+            self.set_rebuild_source(uri, source)?;
+        }
+
+        let status = match self.add_child_only(uri).await {
+            Ok(status) => status,
+            Err(e) => {
+                self.rebuild_sources.remove(uri);
+                return Err(e);
+            }
+        };
+        events::child_added(&self.name, uri);
 
         if !norebuild {
             if let Err(e) = self.start_rebuild(&uri).await {
@@ -132,20 +197,51 @@ impl Nexus {
         &mut self,
         uri: &str,
     ) -> Result<NexusStatus, Error> {
-        let name = bdev_create(&uri).await.context(CreateChild {
-            name: self.name.clone(),
-        })?;
+        let mut child = self.open_new_child(uri).await?;
+
+        // we have created the bdev, and created a nexusChild struct. To
+        // make use of the device itself the data and metadata must be
+        // validated. The child will be added and marked as faulted, once the
+        // rebuild has completed the device can transition to online
+
+        // it can never take part in the IO path
+        // of the nexus until it's rebuilt from a healthy child.
+        child.out_of_sync(true);
+
+        self.children.push(child);
+        self.child_count += 1;
+
+        if let Err(e) = self.sync_labels().await {
+            error!("Failed to sync labels {:?}", e);
+            // todo: how to signal this?
+        }
+
+        Ok(self.status())
+    }
+
+    /// Create the bdev of the URI and open it as a child of the nexus, which
+    /// is not added to the children yet.
+    async fn open_new_child(&self, uri: &str) -> Result<NexusChild, Error> {
+        let local = nexus_local::lookup(uri);
+        let name = match local.as_ref() {
+            Some(bdev) => bdev.name(),
+            None => bdev_create(&uri).await.context(CreateChild {
+                name: self.name.clone(),
+            })?,
+        };
 
         let child_bdev = match Bdev::lookup_by_name(&name) {
             Some(child) => {
                 if child.block_len() != self.bdev.block_len()
                     || self.min_num_blocks() > child.num_blocks()
                 {
-                    if let Err(err) = bdev_destroy(uri).await {
-                        error!(
-                            "Failed to destroy child bdev with wrong geometry: {}",
-                            err
-                        );
+                    if local.is_none() {
+                        if let Err(err) = bdev_destroy(uri).await {
+                            error!(
+                                "Failed to destroy child bdev with wrong geometry: {}",
+                                err
+                            );
+                        }
                     }
 
                     return Err(Error::ChildGeometry {
@@ -164,40 +260,24 @@ impl Nexus {
             }
         };
 
-        let mut child = NexusChild::new(
-            uri.to_owned(),
-            self.name.clone(),
-            Some(child_bdev),
-        );
+        let mut child = if local.is_some() {
+            NexusChild::local(uri.to_owned(), self.name.clone(), child_bdev)
+        } else {
+            NexusChild::new(uri.to_owned(), self.name.clone(), Some(child_bdev))
+        };
         match child.open(self.size) {
             Ok(name) => {
-                // we have created the bdev, and created a nexusChild struct. To
-                // make use of the device itself the
-                // data and metadata must be validated. The child
-                // will be added and marked as faulted, once the rebuild has
-                // completed the device can transition to online
                 info!("{}: child opened successfully {}", self.name, name);
-
-                // it can never take part in the IO path
-                // of the nexus until it's rebuilt from a healthy child.
-                child.out_of_sync(true);
-
-                self.children.push(child);
-                self.child_count += 1;
-
-                if let Err(e) = self.sync_labels().await {
-                    error!("Failed to sync labels {:?}", e);
-                    // todo: how to signal this?
-                }
-
-                Ok(self.status())
+                Ok(child)
             }
             Err(e) => {
-                if let Err(err) = bdev_destroy(uri).await {
-                    error!(
-                        "Failed to destroy child which failed to open: {}",
-                        err
-                    );
+                if local.is_none() {
+                    if let Err(err) = bdev_destroy(uri).await {
+                        error!(
+                            "Failed to destroy child which failed to open: {}",
+                            err
+                        );
+                    }
                 }
                 Err(e).context(OpenChild {
                     child: uri.to_owned(),
@@ -209,6 +289,7 @@ impl Nexus {
 
     /// Destroy child with given uri.
     /// If the child does not exist the method returns success.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
     pub async fn remove_child(&mut self, uri: &str) -> Result<(), Error> {
         if self.child_count == 1 {
             return Err(Error::DestroyLastChild {
@@ -229,7 +310,10 @@ impl Nexus {
 
         let mut child = self.children.remove(idx);
         self.child_count -= 1;
+        self.rebuild_sources.remove(uri);
         self.reconfigure(DREvent::ChildRemove).await;
+        self.update_label_v2().await;
+        events::child_removed(&self.name, uri);
 
         child.destroy().await.context(DestroyChild {
             name: self.name.clone(),
@@ -238,6 +322,7 @@ impl Nexus {
     }
 
     /// offline a child device and reconfigure the IO channels
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
     pub async fn offline_child(
         &mut self,
         name: &str,
@@ -247,7 +332,11 @@ impl Nexus {
         self.cancel_child_rebuild_jobs(name).await;
 
         if let Some(child) = self.children.iter_mut().find(|c| c.name == name) {
+            let was_online = child.status() == ChildStatus::Online;
             child.offline();
+            if was_online && self.status() == NexusStatus::Faulted {
+                self.last_online_child = Some(name.to_owned());
+            }
         } else {
             return Err(Error::ChildNotFound {
                 name: self.name.clone(),
@@ -261,6 +350,7 @@ impl Nexus {
     }
 
     /// fault a child device and reconfigure the IO channels
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
     pub async fn fault_child(&mut self, name: &str) -> Result<(), Error> {
         trace!("{}: fault child request for {}", self.name, name);
 
@@ -277,6 +367,12 @@ impl Nexus {
             if child.status() != ChildStatus::Faulted {
                 child.fault();
                 self.reconfigure(DREvent::ChildFault).await;
+                nexus_resolver::child_faulted(&self.name, name);
+                nexus_hooks::child_faulted(&self.name, name, "io errors");
+                events::child_faulted(&self.name, name, "io errors");
+                if self.status() == NexusStatus::Degraded {
+                    events::nexus_degraded(&self.name);
+                }
             }
             Ok(())
         } else {
@@ -287,9 +383,249 @@ impl Nexus {
         }
     }
 
+    /// Fault a child as the pool of its replica has become read-only, which
+    /// unlike an IO error is reported as such. As the writes to the child
+    /// can never succeed again, it is not kept around as the last child.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
+    pub async fn read_only_child(&mut self, name: &str) -> Result<(), Error> {
+        if self.child_count < 2 {
+            return Err(Error::RemoveLastChild {
+                name: self.name.clone(),
+                child: name.to_owned(),
+            });
+        }
+
+        self.cancel_child_rebuild_jobs(name).await;
+
+        if let Some(child) = self.children.iter_mut().find(|c| c.name == name) {
+            if !child.is_read_only() {
+                warn!(
+                    "{}: the pool of child {} is read-only, faulting it",
+                    self.name, name
+                );
+                child.read_only();
+                self.reconfigure(DREvent::ChildFault).await;
+                nexus_resolver::child_faulted(&self.name, name);
+                nexus_hooks::child_faulted(&self.name, name, "read-only pool");
+                events::child_faulted(&self.name, name, "read-only pool");
+                if self.status() == NexusStatus::Degraded {
+                    events::nexus_degraded(&self.name);
+                }
+            }
+            Ok(())
+        } else {
+            Err(Error::ChildNotFound {
+                name: self.name.clone(),
+                child: name.to_owned(),
+            })
+        }
+    }
+
+    /// Called from the IO path when a write to the child of the given bdev
+    /// was refused as the pool of its replica is read-only. The child is
+    /// faulted from the master core.
+    pub(crate) fn child_write_protected(&self, bdev: *const spdk_bdev) {
+        let nexus_name = self.name.clone();
+        Reactors::master().send_future(async move {
+            let nexus = match nexus_lookup(&nexus_name) {
+                Some(nexus) => nexus,
+                None => return,
+            };
+            let name = match nexus.children.iter().find(|c| {
+                c.bdev.as_ref().map(|b| b.as_ptr() as *const _) == Some(bdev)
+            }) {
+                Some(child) if !child.is_read_only() => child.name.clone(),
+                _ => return,
+            };
+            if let Err(e) = nexus.read_only_child(&name).await {
+                error!(
+                    "{}: failed to fault read-only child {}: {}",
+                    nexus_name, name, e
+                );
+            }
+        });
+    }
+
+    /// Replace a faulted child with one of another URI, under which its
+    /// replica is reachable again. Unless no other child is online, the new
+    /// child is rebuilt as any child added. Otherwise no IO has reached any
+    /// child since this one faulted, so it is up to date and the nexus
+    /// resumes on it.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
+    pub async fn replace_child(
+        &mut self,
+        uri: &str,
+        new_uri: &str,
+    ) -> Result<NexusStatus, Error> {
+        if !self.children.iter().any(|c| c.name == uri) {
+            return Err(Error::ChildNotFound {
+                name: self.name.clone(),
+                child: uri.to_owned(),
+            });
+        }
+
+        let last = !self
+            .children
+            .iter()
+            .any(|c| c.name != uri && c.status() == ChildStatus::Online);
+
+        if last {
+            self.add_child_only(new_uri).await?;
+            if let Some(child) =
+                self.children.iter_mut().find(|c| c.name == new_uri)
+            {
+                child.out_of_sync(false);
+            }
+            self.reconfigure(DREvent::ChildOnline).await;
+        } else {
+            self.add_child(new_uri, false).await?;
+        }
+
+        // the bdev of the old child may well be gone already
+        if let Err(e) = self.remove_child(uri).await {
+            warn!("{}: {}", self.name, e.verbose());
+        }
+        Ok(self.status())
+    }
+
+    /// Switch an online child to another URI of its replica, as the replica
+    /// is now shared over another protocol. The writes to the nexus are held
+    /// off while the child is opened over the new URI, which then takes the
+    /// place of the old one without a rebuild, as no write can have reached
+    /// the one but not the other.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
+    pub async fn switch_child(
+        &mut self,
+        uri: &str,
+        new_uri: &str,
+    ) -> Result<NexusStatus, Error> {
+        let nexus_name = self.name.clone();
+        let cannot = |reason: &str| Error::CannotSwitchChild {
+            name: nexus_name.clone(),
+            child: uri.to_owned(),
+            reason: reason.to_owned(),
+        };
+        let failed = |reason: String| Error::SwitchChild {
+            name: nexus_name.clone(),
+            child: uri.to_owned(),
+            uri: new_uri.to_owned(),
+            reason,
+        };
+
+        let child = match self.children.iter().find(|c| c.name == uri) {
+            Some(child) => child,
+            None => {
+                return Err(Error::ChildNotFound {
+                    name: self.name.clone(),
+                    child: uri.to_owned(),
+                })
+            }
+        };
+        if child.status() != ChildStatus::Online {
+            return Err(cannot("the child is not online"));
+        }
+        if !RebuildJob::lookup_src(uri).is_empty() {
+            return Err(cannot("the child is the source of a rebuild"));
+        }
+        if self.children.iter().any(|c| c.name == new_uri) {
+            return Err(cannot("the new URI is a child already"));
+        }
+        if let (Some(old), Some(new)) =
+            (replica_uuid(uri), replica_uuid(new_uri))
+        {
+            if old != new {
+                return Err(cannot("the new URI is of another replica"));
+            }
+        }
+        let label = child.probe_label().await.ok();
+
+        // hold off the writes to the nexus until the new child is in place
+        let nexus = BdevHandle::open(&self.name, false, false)
+            .map_err(|e| failed(e.to_string()))?;
+        let mut ctx = RangeContext::new(0, self.bdev.num_blocks());
+        nexus
+            .desc
+            .lock_lba_range(&mut ctx, &nexus.channel)
+            .await
+            .map_err(|e| failed(format!("failed to quiesce: {}", e)))?;
+
+        let result = match self.open_new_child(new_uri).await {
+            Ok(mut child) => {
+                let same = match (&label, child.probe_label().await) {
+                    (Some(old), Ok(new)) => {
+                        old.primary.guid == new.primary.guid
+                    }
+                    (Some(_), Err(_)) => false,
+                    (None, _) => true,
+                };
+                if same {
+                    let idx = self
+                        .children
+                        .iter()
+                        .position(|c| c.name == uri)
+                        .unwrap();
+                    self.children.insert(idx + 1, child);
+                    self.child_count += 1;
+                    Ok(())
+                } else {
+                    child.close();
+                    if let Err(e) = child.destroy().await {
+                        error!("{}: {}", self.name, e.verbose());
+                    }
+                    Err(failed(
+                        "the new child does not carry the label of the nexus"
+                            .into(),
+                    ))
+                }
+            }
+            Err(e) => Err(e),
+        };
+
+        if result.is_ok() {
+            info!("{}: switching child {} to {}", self.name, uri, new_uri);
+            // the channels of the new child are set up as the old one is
+            // removed
+            if let Err(e) = self.remove_child(uri).await {
+                warn!("{}: {}", self.name, e.verbose());
+            }
+        }
+
+        nexus
+            .desc
+            .unlock_lba_range(&mut ctx, &nexus.channel)
+            .await
+            .map_err(|e| failed(format!("failed to resume: {}", e)))?;
+
+        result.map(|_| self.status())
+    }
+
+    /// Replace a child faulted due to its IO errors with the first of the
+    /// spares of the nexus, which is rebuilt. A spare which fails to be added
+    /// is dropped in favour of the next one.
+    pub(crate) async fn replace_with_spare(&mut self, uri: &str) {
+        while !self.spares.is_empty() {
+            let spare = self.spares.remove(0);
+            info!(
+                "{}: replacing child {} with spare {}",
+                self.name, uri, spare
+            );
+            match self.replace_child(uri, &spare).await {
+                Ok(_) => return,
+                Err(e) => error!(
+                    "{}: failed to replace child {} with spare {}: {}",
+                    self.name,
+                    uri,
+                    spare,
+                    e.verbose()
+                ),
+            }
+        }
+    }
+
     /// online a child and reconfigure the IO channels. The child is already
     /// registered, but simply not opened. This can be required in case where
     /// a child is misbehaving.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
     pub async fn online_child(
         &mut self,
         name: &str,
@@ -301,6 +637,16 @@ impl Nexus {
                 child: name.to_owned(),
                 name: self.name.clone(),
             })?;
+
+            // No IO has reached any child since the last healthy child went
+            // offline, so that child is up to date and needs no rebuild. IO
+            // which is frozen meanwhile resumes on it.
+            if self.last_online_child.as_deref() == Some(name) {
+                self.last_online_child = None;
+                self.reconfigure(DREvent::ChildOnline).await;
+                return Ok(self.status());
+            }
+
             child.out_of_sync(true);
             self.start_rebuild(name).await.map(|_| {})?;
             Ok(self.status())
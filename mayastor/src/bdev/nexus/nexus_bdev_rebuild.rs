@@ -1,5 +1,6 @@
 use futures::channel::oneshot::Receiver;
 use snafu::ResultExt;
+use tracing::instrument;
 
 use rpc::mayastor::{RebuildProgressReply, RebuildStateReply};
 
@@ -11,32 +12,94 @@ use crate::{
                 CreateRebuildError,
                 Error,
                 Nexus,
+                NexusStatus,
                 RebuildJobNotFound,
                 RebuildOperationError,
                 RemoveRebuildJob,
             },
             nexus_channel::DREvent,
-            nexus_child::{ChildState, ChildStatus},
+            nexus_child::ChildStatus,
+            nexus_hooks,
         },
         VerboseError,
     },
     core::Reactors,
     rebuild::{ClientOperations, RebuildError, RebuildJob, RebuildState},
+    subsys::events,
 };
 
 impl Nexus {
     /// Starts a rebuild job and returns a receiver channel
     /// which can be used to await the rebuild completion
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
     pub async fn start_rebuild(
         &mut self,
         name: &str,
+    ) -> Result<Receiver<RebuildState>, Error> {
+        self.start_rebuild_of(name, None).await
+    }
+
+    /// Starts a rebuild job as [`Nexus::start_rebuild`] does, copying from
+    /// the given child. The child stays the source of rebuilds of the child
+    /// started later on, for as long as it is healthy.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
+    pub async fn start_rebuild_from(
+        &mut self,
+        name: &str,
+        source: &str,
+    ) -> Result<Receiver<RebuildState>, Error> {
+        self.set_rebuild_source(name, source)?;
+        self.start_rebuild(name).await
+    }
+
+    /// Choose the child to rebuild the given child from, which must be
+    /// healthy.
+    pub(crate) fn set_rebuild_source(
+        &mut self,
+        name: &str,
+        source: &str,
+    ) -> Result<(), Error> {
+        let invalid = |reason: &str| Error::InvalidRebuildSource {
+            child: source.to_owned(),
+            name: self.name.clone(),
+            reason: reason.to_owned(),
+        };
+
+        if source == name {
+            return Err(invalid("it is the child to rebuild"));
+        }
+        match self.children.iter().find(|c| c.name == source) {
+            Some(c) if c.status() == ChildStatus::Online => {}
+            Some(_) => return Err(invalid("it is not online")),
+            None => {
+                return Err(Error::ChildNotFound {
+                    child: source.to_owned(),
+                    name: self.name.clone(),
+                })
+            }
+        }
+
+        self.rebuild_sources.insert(name.to_owned(), source.to_owned());
+        Ok(())
+    }
+
+    /// Starts a rebuild job which copies the given ranges of blocks of the
+    /// nexus only, or all of them if none are given
+    pub(crate) async fn start_rebuild_of(
+        &mut self,
+        name: &str,
+        regions: Option<Vec<std::ops::Range<u64>>>,
     ) -> Result<Receiver<RebuildState>, Error> {
         trace!("{}: start rebuild request for {}", self.name, name);
 
+        // a child which is being rebuilt itself is no source, the one chosen
+        // for the child is used while it is healthy
+        let chosen = self.rebuild_sources.get(name);
         let src_child_name = match self
             .children
             .iter()
-            .find(|c| c.state == ChildState::Open && c.name != name)
+            .filter(|c| c.status() == ChildStatus::Online && c.name != name)
+            .min_by_key(|c| Some(&c.name) != chosen)
         {
             Some(child) => Ok(child.name.clone()),
             None => Err(Error::NoRebuildSource {
@@ -79,6 +142,22 @@ impl Nexus {
             name: self.name.clone(),
         })?;
 
+        if let Some(regions) = regions {
+            let offset = self.data_ent_offset;
+            let regions = regions
+                .into_iter()
+                .map(|r| r.start + offset .. r.end + offset)
+                .collect();
+            if let Err(source) = job.restrict(regions) {
+                let _ = RebuildJob::remove(name);
+                return Err(Error::CreateRebuildError {
+                    source,
+                    child: name.to_owned(),
+                    name: self.name.clone(),
+                });
+            }
+        }
+
         // We're now rebuilding the `dst_child` which means it HAS to become an
         // active participant in the frontend nexus bdev for Writes.
         // This is because the rebuild job copies from src to target child
@@ -89,10 +168,13 @@ impl Nexus {
         // rebuilt ranges in sync with the other children.
         self.reconfigure(DREvent::ChildRebuild).await;
 
-        job.as_client().start().context(RebuildOperationError {
-            job: name.to_owned(),
-            name: self.name.clone(),
-        })
+        let receiver =
+            job.as_client().start().context(RebuildOperationError {
+                job: name.to_owned(),
+                name: self.name.clone(),
+            })?;
+        events::rebuild_started(&self.name, &dst_child_name, &src_child_name);
+        Ok(receiver)
     }
 
     /// Terminates a rebuild in the background
@@ -161,6 +243,18 @@ impl Nexus {
         })
     }
 
+    /// Returns the number of bytes written by all the rebuild jobs of the
+    /// nexus, the running ones included
+    pub fn rebuild_bytes_written(&self) -> u64 {
+        self.children
+            .iter()
+            .filter_map(|c| RebuildJob::lookup(&c.name).ok())
+            .map(|j| j.as_client().stats())
+            .fold(self.rebuild_bytes_written, |bytes, s| {
+                bytes + s.blocks_recovered * s.block_size
+            })
+    }
+
     /// Cancels all rebuilds jobs associated with the child
     /// If any job is found with the child as a destination then the job is
     /// stopped. If any job is found with the child as a source then
@@ -168,6 +262,8 @@ impl Nexus {
     /// as src, if found
     /// todo: how to proceed if no healthy child is found?
     pub async fn cancel_child_rebuild_jobs(&mut self, name: &str) {
+        // the child is no longer the source of any rebuild
+        self.rebuild_sources.retain(|_, source| source != name);
         let mut src_jobs = self.get_rebuild_job_src(name);
 
         let mut replace_jobs = Vec::new();
@@ -274,6 +370,33 @@ impl Nexus {
         }
 
         self.reconfigure(DREvent::ChildRebuild).await;
+
+        let state = job.state();
+        nexus_hooks::rebuild_complete(
+            &self.name,
+            &job.destination,
+            &state.to_string(),
+        );
+        events::rebuild_completed(
+            &self.name,
+            &job.destination,
+            &state.to_string(),
+        );
+        if state != RebuildState::Completed && state != RebuildState::Stopped {
+            nexus_hooks::child_faulted(
+                &self.name,
+                &job.destination,
+                "rebuild failed",
+            );
+            events::child_faulted(
+                &self.name,
+                &job.destination,
+                "rebuild failed",
+            );
+            if self.status() == NexusStatus::Degraded {
+                events::nexus_degraded(&self.name);
+            }
+        }
         Ok(())
     }
 
@@ -288,6 +411,15 @@ impl Nexus {
             return Ok(());
         }
 
+        let stats = j.as_client().stats();
+        let bytes = stats.blocks_recovered * stats.block_size;
+        self.rebuild_bytes_written += bytes;
+        for child in self.children.iter() {
+            if child.name == j.source || child.name == j.destination {
+                child.rebuild_done(child.name == j.source, bytes);
+            }
+        }
+
         let complete_err = self.on_rebuild_complete_job(&j).await;
         let remove_err = RebuildJob::remove(&job)
             .context(RemoveRebuildJob {
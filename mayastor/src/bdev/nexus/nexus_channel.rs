@@ -1,21 +1,46 @@
 //!
 //! IO is driven by means of so called channels.
-use std::{convert::TryFrom, ffi::c_void};
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    ffi::c_void,
+    ptr,
+    time::{Duration, Instant},
+};
+
+use futures::channel::oneshot;
 
 use spdk_sys::{
+    spdk_bdev,
+    spdk_bdev_io,
     spdk_for_each_channel,
     spdk_for_each_channel_continue,
     spdk_io_channel,
     spdk_io_channel_iter,
     spdk_io_channel_iter_get_channel,
+    spdk_io_channel_iter_get_ctx,
     spdk_io_channel_iter_get_io_device,
+    spdk_poller,
+    spdk_poller_register,
+    spdk_poller_unregister,
 };
 
 use crate::{
-    bdev::{nexus::nexus_child::ChildStatus, Nexus},
+    bdev::{
+        nexus::{
+            nexus_bdev::ReadPolicy,
+            nexus_child::{ChildStatus, NexusChild},
+            nexus_fn_table::NexusFnTable,
+            nexus_io::{Bio, NexusIoStats},
+        },
+        Nexus,
+    },
     core::BdevHandle,
 };
 
+/// interval of the poller failing expired frozen IO in microseconds
+const FREEZE_POLL_PERIOD_US: u64 = 100_000;
+
 /// io channel, per core
 #[repr(C)]
 #[derive(Debug)]
@@ -29,9 +54,29 @@ pub(crate) struct NexusChannelInner {
     pub(crate) ch: Vec<BdevHandle>,
     pub(crate) write_only: usize,
     pub(crate) previous: usize,
+    /// whether the child of the handle of the same index is served by this
+    /// node
+    local: Vec<bool>,
+    /// number of reads in flight on the handle of the same index
+    reads: Vec<u32>,
+    /// front-end IO submitted on this channel
+    pub(crate) io_stats: NexusIoStats,
+    /// handle of the read cache device, opened on the first read from it
+    pub(crate) read_cache: Option<BdevHandle>,
+    /// IO held while there is no healthy child, with its deadline
+    frozen: VecDeque<(*mut spdk_bdev_io, Instant)>,
+    /// poller failing the frozen IO past its deadline, only registered
+    /// while IO is frozen
+    freeze_poller: *mut spdk_poller,
     device: *mut c_void,
 }
 
+/// context used to sum up the IO stats of all channels
+struct IoStatsCtx {
+    stats: NexusIoStats,
+    sender: oneshot::Sender<NexusIoStats>,
+}
+
 #[derive(Debug)]
 /// Dynamic Reconfiguration Events occur when a child is added or removed
 pub enum DREvent {
@@ -45,6 +90,8 @@ pub enum DREvent {
     ChildRemove,
     /// Child rebuild event
     ChildRebuild,
+    /// the child lags behind as its writes are too slow
+    ChildLagging,
 }
 
 impl NexusChannelInner {
@@ -58,6 +105,80 @@ impl NexusChannelInner {
         self.previous
     }
 
+    /// select the child to read from by the read policy of the nexus
+    pub(crate) fn read_select(&mut self) -> usize {
+        let nexus = unsafe { Nexus::from_raw(self.device) };
+        let readable = self.ch.len() - self.write_only;
+        // the children in turn, starting after the previous one
+        let next = self.previous + 1;
+        let mut turn = (0 .. readable).map(|i| (next + i) % readable);
+
+        let child = match nexus.read_policy {
+            ReadPolicy::RoundRobin => None,
+            ReadPolicy::QueueDepth => turn.min_by_key(|&c| self.reads[c]),
+            ReadPolicy::PreferLocal => turn.find(|&c| self.local[c]),
+        };
+        match child {
+            Some(child) => {
+                self.previous = child;
+                child
+            }
+            None => self.child_select(),
+        }
+    }
+
+    /// account for a read dispatched to the child of the handle
+    #[inline]
+    pub(crate) fn read_dispatched(&mut self, child: usize) {
+        self.reads[child] += 1;
+    }
+
+    /// account for a read completed by the child of the bdev
+    #[inline]
+    pub(crate) fn read_completed(&mut self, bdev: *mut spdk_bdev) {
+        if let Some(c) =
+            self.ch.iter().position(|h| h.get_bdev().as_ptr() == bdev)
+        {
+            self.reads[c] = self.reads[c].saturating_sub(1);
+        }
+    }
+
+    /// add the handle of the child to the channel
+    fn add(&mut self, child: &NexusChild) {
+        self.ch.push(
+            BdevHandle::try_from(child.get_descriptor().unwrap()).unwrap(),
+        );
+        self.local.push(child.is_local());
+        self.reads.push(0);
+    }
+
+    /// account for an IO submitted to the nexus on this channel
+    #[inline]
+    pub(crate) fn io_submitted(&mut self, bytes: u64) {
+        self.io_stats.queue_depth += 1;
+        self.io_stats.in_flight_bytes += bytes;
+    }
+
+    /// account for a write dispatched to the children of this channel
+    #[inline]
+    pub(crate) fn io_fanned_out(&mut self, bytes: u64) {
+        self.io_stats.child_bytes_written += bytes * self.ch.len() as u64;
+    }
+
+    /// account for an IO completed by the nexus on this channel
+    #[inline]
+    pub(crate) fn io_completed(&mut self, bytes: u64) {
+        self.io_stats.queue_depth = self.io_stats.queue_depth.saturating_sub(1);
+        self.io_stats.in_flight_bytes =
+            self.io_stats.in_flight_bytes.saturating_sub(bytes);
+    }
+
+    /// account for an IO failed by the nexus on this channel
+    #[inline]
+    pub(crate) fn io_failed(&mut self) {
+        self.io_stats.io_errors += 1;
+    }
+
     /// refreshing our channels simply means that we either have a child going
     /// online or offline. We don't know which child has gone, or was added, so
     /// we simply put back all the channels, and reopen the bdevs that are in
@@ -81,6 +202,8 @@ impl NexusChannelInner {
         // clearing the values will drop any existing handles in the
         // channel
         self.ch.clear();
+        self.local.clear();
+        self.reads.clear();
         self.previous = 0;
         self.write_only = 0;
 
@@ -89,11 +212,7 @@ impl NexusChannelInner {
             .children
             .iter_mut()
             .filter(|c| c.status() == ChildStatus::Online)
-            .for_each(|c| {
-                self.ch.push(
-                    BdevHandle::try_from(c.get_descriptor().unwrap()).unwrap(),
-                )
-            });
+            .for_each(|c| self.add(c));
 
         if !self.ch.is_empty() {
             nexus
@@ -102,10 +221,7 @@ impl NexusChannelInner {
                 .filter(|c| c.rebuilding())
                 .map(|c| {
                     self.write_only += 1;
-                    self.ch.push(
-                        BdevHandle::try_from(c.get_descriptor().unwrap())
-                            .unwrap(),
-                    )
+                    self.add(c)
                 })
                 .for_each(drop);
         }
@@ -118,6 +234,92 @@ impl NexusChannelInner {
         );
 
         //trace!("{:?}", nexus.children);
+
+        if !self.ch.is_empty() {
+            self.thaw();
+        }
+    }
+
+    /// hold an IO until a child comes back online or the timeout expires
+    pub(crate) fn freeze(&mut self, io: *mut spdk_bdev_io, timeout: Duration) {
+        if self.frozen.is_empty() {
+            let nexus = unsafe { Nexus::from_raw(self.device) };
+            warn!(
+                "{}: No healthy child left, freezing IO for up to {:?}",
+                nexus.name, timeout
+            );
+            self.freeze_poller = unsafe {
+                spdk_poller_register(
+                    Some(Self::freeze_poll),
+                    self as *mut _ as *mut c_void,
+                    FREEZE_POLL_PERIOD_US,
+                )
+            };
+        }
+        self.frozen.push_back((io, Instant::now() + timeout));
+    }
+
+    /// complete a frozen IO as aborted on request of the initiator, returns
+    /// false if the IO is not frozen
+    pub(crate) fn abort_frozen(&mut self, io: *mut spdk_bdev_io) -> bool {
+        match self.frozen.iter().position(|(f, _)| *f == io) {
+            Some(pos) => {
+                self.frozen.remove(pos);
+                self.unregister_freeze_poller();
+                Bio(io).aborted();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// dispatch the frozen IO to the children which are back online
+    fn thaw(&mut self) {
+        if self.frozen.is_empty() {
+            return;
+        }
+
+        let nexus = unsafe { Nexus::from_raw(self.device) };
+        info!("{}: Resuming {} frozen IOs", nexus.name, self.frozen.len());
+
+        let frozen = std::mem::take(&mut self.frozen);
+        self.unregister_freeze_poller();
+        for (io, _) in frozen {
+            NexusFnTable::io_dispatch(io, self);
+        }
+    }
+
+    /// fail the frozen IO which is past its deadline, or all of it
+    fn expire(&mut self, all: bool) {
+        let now = Instant::now();
+        let mut expired = 0;
+
+        while let Some((io, deadline)) = self.frozen.front().copied() {
+            if !all && deadline > now {
+                break;
+            }
+            self.frozen.pop_front();
+            Bio(io).fail();
+            expired += 1;
+        }
+
+        if expired > 0 {
+            let nexus = unsafe { Nexus::from_raw(self.device) };
+            warn!("{}: Failed {} frozen IOs", nexus.name, expired);
+        }
+        self.unregister_freeze_poller();
+    }
+
+    fn unregister_freeze_poller(&mut self) {
+        if self.frozen.is_empty() && !self.freeze_poller.is_null() {
+            unsafe { spdk_poller_unregister(&mut self.freeze_poller) };
+        }
+    }
+
+    extern "C" fn freeze_poll(ctx: *mut c_void) -> i32 {
+        let inner = unsafe { &mut *(ctx as *mut NexusChannelInner) };
+        inner.expire(false);
+        0
     }
 }
 
@@ -135,6 +337,12 @@ impl NexusChannel {
             ch: Vec::new(),
             previous: 0,
             write_only: 0,
+            local: Vec::new(),
+            reads: Vec::new(),
+            io_stats: NexusIoStats::default(),
+            read_cache: None,
+            frozen: VecDeque::new(),
+            freeze_poller: ptr::null_mut(),
             device,
         });
 
@@ -142,12 +350,7 @@ impl NexusChannel {
             .children
             .iter_mut()
             .filter(|c| c.status() == ChildStatus::Online)
-            .map(|c| {
-                channels.ch.push(
-                    BdevHandle::try_from(c.get_descriptor().unwrap()).unwrap(),
-                )
-            })
-            .for_each(drop);
+            .for_each(|c| channels.add(c));
         ch.inner = Box::into_raw(channels);
         0
     }
@@ -157,7 +360,11 @@ impl NexusChannel {
         let nexus = unsafe { Nexus::from_raw(device) };
         debug!("{} Destroying IO channels", nexus.bdev.name());
         let inner = NexusChannel::from_raw(ctx).inner_mut();
+        inner.expire(true);
         inner.ch.clear();
+        inner.local.clear();
+        inner.reads.clear();
+        inner.read_cache = None;
     }
 
     /// function called when we receive a Dynamic Reconfigure event (DR)
@@ -167,7 +374,8 @@ impl NexusChannel {
             | DREvent::ChildOnline
             | DREvent::ChildRemove
             | DREvent::ChildFault
-            | DREvent::ChildRebuild => unsafe {
+            | DREvent::ChildRebuild
+            | DREvent::ChildLagging => unsafe {
                 spdk_for_each_channel(
                     device,
                     Some(NexusChannel::refresh_io_channels),
@@ -206,6 +414,56 @@ impl NexusChannel {
         unsafe { spdk_for_each_channel_continue(ch_iter, 0) };
     }
 
+    /// Sum up the front-end IO stats of the channels of all cores. The stats
+    /// of each channel are read on the core owning the channel.
+    pub(crate) async fn io_stats(device: *mut c_void) -> NexusIoStats {
+        let (sender, receiver) = oneshot::channel::<NexusIoStats>();
+        let ctx = Box::new(IoStatsCtx {
+            stats: NexusIoStats::default(),
+            sender,
+        });
+
+        unsafe {
+            spdk_for_each_channel(
+                device,
+                Some(Self::collect_io_stats),
+                Box::into_raw(ctx) as *mut c_void,
+                Some(Self::collect_io_stats_done),
+            );
+        }
+
+        receiver.await.expect("io stats sender is gone")
+    }
+
+    /// add the IO stats of the current channel to the context
+    extern "C" fn collect_io_stats(ch_iter: *mut spdk_io_channel_iter) {
+        let ctx = unsafe {
+            &mut *(spdk_io_channel_iter_get_ctx(ch_iter) as *mut IoStatsCtx)
+        };
+        let channel = unsafe { spdk_io_channel_iter_get_channel(ch_iter) };
+        let inner = Self::inner_from_channel(channel);
+
+        ctx.stats.queue_depth += inner.io_stats.queue_depth;
+        ctx.stats.in_flight_bytes += inner.io_stats.in_flight_bytes;
+        ctx.stats.child_bytes_written += inner.io_stats.child_bytes_written;
+        ctx.stats.io_errors += inner.io_stats.io_errors;
+
+        unsafe { spdk_for_each_channel_continue(ch_iter, 0) };
+    }
+
+    /// all channels have been visited, send the aggregated stats
+    extern "C" fn collect_io_stats_done(
+        ch_iter: *mut spdk_io_channel_iter,
+        _status: i32,
+    ) {
+        let ctx = unsafe {
+            *Box::from_raw(
+                spdk_io_channel_iter_get_ctx(ch_iter) as *mut IoStatsCtx
+            )
+        };
+        let _ = ctx.sender.send(ctx.stats);
+    }
+
     /// Converts a raw pointer to a nexusChannel. Note that the memory is not
     /// allocated by us.
     pub(crate) fn from_raw<'a>(n: *mut c_void) -> &'a mut Self {
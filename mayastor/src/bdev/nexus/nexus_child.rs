@@ -1,4 +1,12 @@
-use std::{convert::TryFrom, fmt::Display, sync::Arc};
+use std::{
+    convert::TryFrom,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use nix::errno::Errno;
 use serde::{export::Formatter, Serialize};
@@ -7,7 +15,14 @@ use snafu::{ResultExt, Snafu};
 use spdk_sys::{spdk_bdev_module_release_bdev, spdk_io_channel};
 
 use crate::{
-    bdev::NexusErrStore,
+    bdev::{
+        nexus::{
+            nexus_latency::SlowWrites,
+            nexus_local,
+            nexus_module::NEXUS_MODULE,
+        },
+        NexusErrStore,
+    },
     core::{Bdev, BdevHandle, CoreError, Descriptor, DmaBuf},
     nexus_uri::{bdev_destroy, NexusBdevError},
     rebuild::{ClientOperations, RebuildJob},
@@ -74,6 +89,8 @@ struct StatusReasons {
     /// Faulted
     /// fatal error, cannot be recovered
     fatal_error: bool,
+    /// the pool of the replica has become read-only
+    read_only: bool,
 }
 
 impl StatusReasons {
@@ -82,6 +99,12 @@ impl StatusReasons {
         self.fatal_error = true;
     }
 
+    /// writes fail as the pool of the replica is read-only
+    fn read_only(&mut self) {
+        self.read_only = true;
+        self.fatal_error = true;
+    }
+
     /// set offline
     fn offline(&mut self, offline: bool) {
         self.offline = offline;
@@ -130,6 +153,48 @@ impl ToString for ChildStatus {
     }
 }
 
+/// IO counters of a child since it was added to the nexus. The reads and
+/// writes are those of the nexus routed to the child which succeeded, the
+/// IO of rebuilds is counted apart from them. A timed out or retried IO is
+/// counted as an error as well.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct ChildIoStats {
+    /// reads of the nexus served by the child
+    pub num_read_ops: u64,
+    /// writes of the nexus written to the child
+    pub num_write_ops: u64,
+    /// bytes read from the child by the reads of the nexus
+    pub bytes_read: u64,
+    /// bytes written to the child by the writes of the nexus
+    pub bytes_written: u64,
+    /// bytes read from the child as the source of rebuilds
+    pub rebuild_bytes_read: u64,
+    /// bytes written to the child as the destination of rebuilds
+    pub rebuild_bytes_written: u64,
+    /// IOs which failed
+    pub errors: u64,
+    /// IOs which failed as they timed out
+    pub timeouts: u64,
+    /// IOs which failed and were retried, on another child or on the same
+    /// child once its path was back
+    pub retries: u64,
+}
+
+/// the counters of ['ChildIoStats'], updated from any core, the rebuild
+/// counters only by the rebuilds which are done
+#[derive(Debug, Default)]
+struct IoCounters {
+    num_read_ops: AtomicU64,
+    num_write_ops: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    rebuild_bytes_read: AtomicU64,
+    rebuild_bytes_written: AtomicU64,
+    errors: AtomicU64,
+    timeouts: AtomicU64,
+    retries: AtomicU64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct NexusChild {
     /// name of the parent this child belongs too
@@ -154,6 +219,14 @@ pub struct NexusChild {
     /// record of most-recent IO errors
     #[serde(skip_serializing)]
     pub(crate) err_store: Option<NexusErrStore>,
+    #[serde(skip_serializing)]
+    io_counters: IoCounters,
+    /// the writes which exceeded the latency threshold
+    #[serde(skip_serializing)]
+    pub(crate) slow_writes: SlowWrites,
+    /// the bdev is shared by our own nvmf target at the URI of the child,
+    /// which is used directly rather than over the network
+    local: bool,
 }
 
 impl Display for NexusChild {
@@ -220,14 +293,22 @@ impl NexusChild {
         }
 
         self.desc = Some(Arc::new(
-            Bdev::open_by_name(&bdev.name(), true).context(OpenChild {})?,
+            nexus_local::open(bdev, true).context(OpenChild {})?,
         ));
 
-        self.bdev_handle = Some(
-            BdevHandle::try_from(self.desc.as_ref().unwrap().clone()).unwrap(),
-        );
+        let mut handle =
+            BdevHandle::try_from(self.desc.as_ref().unwrap().clone()).unwrap();
 
+        // a stalled remote child must not hold up the open of the nexus
+        // while its label is read
         let cfg = Config::get();
+        if cfg.nexus_opts.child_io_timeout_secs > 0 {
+            handle.set_timeout(Some(Duration::from_secs(
+                cfg.nexus_opts.child_io_timeout_secs,
+            )));
+        }
+        self.bdev_handle = Some(handle);
+
         if cfg.err_store_opts.enable_err_store {
             self.err_store =
                 Some(NexusErrStore::new(cfg.err_store_opts.err_store_size));
@@ -245,6 +326,16 @@ impl NexusChild {
         self.close();
         self.status_reasons.fatal_error();
     }
+    /// Fault the child as the pool of its replica has become read-only
+    pub(crate) fn read_only(&mut self) {
+        self.close();
+        self.status_reasons.read_only();
+    }
+    /// Faulted as the pool of its replica is read-only, rather than by an
+    /// IO error
+    pub fn is_read_only(&self) -> bool {
+        self.status_reasons.read_only
+    }
     /// Set the child as out of sync with the nexus
     /// It requires a full rebuild before it can service IO
     /// and remains degraded until such time
@@ -318,6 +409,15 @@ impl NexusChild {
         }
     }
 
+    /// Whether the child is open but out of sync with the nexus without
+    /// being rebuilt, the writes it misses are to be kept marked in the
+    /// intent log until it is.
+    pub(crate) fn lagging(&self) -> bool {
+        self.state == ChildState::Open
+            && self.status_reasons.out_of_sync
+            && !self.rebuilding()
+    }
+
     /// return a descriptor to this child
     pub fn get_descriptor(&self) -> Result<Arc<Descriptor>, CoreError> {
         if let Some(ref d) = self.desc {
@@ -332,9 +432,13 @@ impl NexusChild {
     /// close the bdev -- we have no means of determining if this succeeds
     pub(crate) fn close(&mut self) -> ChildState {
         trace!("{}: Closing child {}", self.parent, self.name);
-        if let Some(bdev) = self.bdev.as_ref() {
+        // the claim of a local child is held by the nvmf target, as is the
+        // claim of a replica which has been shared since it became a child
+        if let Some(bdev) = self.bdev.as_ref().filter(|_| !self.local) {
             unsafe {
-                if !(*bdev.as_ptr()).internal.claim_module.is_null() {
+                if (*bdev.as_ptr()).internal.claim_module
+                    == NEXUS_MODULE.as_ptr()
+                {
                     spdk_bdev_module_release_bdev(bdev.as_ptr());
                 }
             }
@@ -363,6 +467,112 @@ impl NexusChild {
             status_reasons: Default::default(),
             bdev_handle: None,
             err_store: None,
+            io_counters: IoCounters::default(),
+            slow_writes: SlowWrites::default(),
+            local: false,
+        }
+    }
+
+    /// create a new nexus child of a bdev shared by our own nvmf target
+    pub(crate) fn local(name: String, parent: String, bdev: Bdev) -> Self {
+        info!("{}: using the local bdev {} for {}", parent, bdev.name(), name);
+        NexusChild {
+            local: true,
+            ..Self::new(name, parent, Some(bdev))
+        }
+    }
+
+    /// the child is served by this node, by a bdev of its own or by the bdev
+    /// our nvmf target shares at the URI of the child
+    pub fn is_local(&self) -> bool {
+        self.local
+            || !matches!(
+                self.name.split("://").next(),
+                Some("nvmf") | Some("iscsi")
+            )
+    }
+
+    /// the local bdev which is used for the nvmf URI of the child, if any
+    pub fn local_bdev(&self) -> Option<String> {
+        self.bdev
+            .as_ref()
+            .filter(|_| self.local)
+            .map(|bdev| bdev.name())
+    }
+
+    /// the IO counters of the child, including the IO of the rebuilds which
+    /// are running, those which are done are counted by the counters already
+    pub fn io_stats(&self) -> ChildIoStats {
+        let counters = &self.io_counters;
+        let running = |j: &&mut RebuildJob| !j.state().done();
+        let recovered = |j: &mut RebuildJob| {
+            let stats = j.as_client().stats();
+            stats.blocks_recovered * stats.block_size
+        };
+        let rebuild_read = RebuildJob::lookup_src(&self.name)
+            .into_iter()
+            .filter(running)
+            .map(recovered)
+            .sum::<u64>();
+        let rebuild_written = RebuildJob::lookup(&self.name)
+            .ok()
+            .filter(running)
+            .map(recovered)
+            .unwrap_or_default();
+
+        ChildIoStats {
+            num_read_ops: counters.num_read_ops.load(Ordering::Relaxed),
+            num_write_ops: counters.num_write_ops.load(Ordering::Relaxed),
+            bytes_read: counters.bytes_read.load(Ordering::Relaxed),
+            bytes_written: counters.bytes_written.load(Ordering::Relaxed),
+            rebuild_bytes_read: counters
+                .rebuild_bytes_read
+                .load(Ordering::Relaxed)
+                + rebuild_read,
+            rebuild_bytes_written: counters
+                .rebuild_bytes_written
+                .load(Ordering::Relaxed)
+                + rebuild_written,
+            errors: self.io_counters.errors.load(Ordering::Relaxed),
+            timeouts: self.io_counters.timeouts.load(Ordering::Relaxed),
+            retries: self.io_counters.retries.load(Ordering::Relaxed),
+        }
+    }
+
+    /// count a read or write of the nexus which succeeded on the child
+    pub(crate) fn io_done(&self, read: bool, bytes: u64) {
+        let counters = &self.io_counters;
+        let (ops, total) = if read {
+            (&counters.num_read_ops, &counters.bytes_read)
+        } else {
+            (&counters.num_write_ops, &counters.bytes_written)
+        };
+        ops.fetch_add(1, Ordering::Relaxed);
+        total.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// count the bytes of a rebuild which is done, read from the child as
+    /// its source or written to it as its destination
+    pub(crate) fn rebuild_done(&self, source: bool, bytes: u64) {
+        if source {
+            self.io_counters
+                .rebuild_bytes_read
+                .fetch_add(bytes, Ordering::Relaxed);
+        } else {
+            self.io_counters
+                .rebuild_bytes_written
+                .fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// count an IO of the child which failed
+    pub(crate) fn io_failed(&self, timed_out: bool, retried: bool) {
+        self.io_counters.errors.fetch_add(1, Ordering::Relaxed);
+        if timed_out {
+            self.io_counters.timeouts.fetch_add(1, Ordering::Relaxed);
+        }
+        if retried {
+            self.io_counters.retries.fetch_add(1, Ordering::Relaxed);
         }
     }
 
@@ -370,7 +580,10 @@ impl NexusChild {
     pub(crate) async fn destroy(&mut self) -> Result<(), NexusBdevError> {
         trace!("destroying child {:?}", self);
         assert_eq!(self.state, ChildState::Closed);
-        if let Some(_bdev) = &self.bdev {
+        if self.local {
+            // the bdev belongs to the replica, which outlives the child
+            Ok(())
+        } else if let Some(_bdev) = &self.bdev {
             bdev_destroy(&self.name).await
         } else {
             warn!("Destroy child without bdev");
@@ -408,7 +621,14 @@ impl NexusChild {
     ) -> Result<usize, ChildIoError> {
         match self.bdev_handle.as_ref() {
             Some(desc) => {
-                Ok(desc.write_at(offset, buf).await.context(WriteError {
+                let result = desc.write_at(offset, buf).await;
+                if let Err(error) = &result {
+                    self.io_failed(
+                        matches!(error, CoreError::IoTimeout { .. }),
+                        false,
+                    );
+                }
+                Ok(result.context(WriteError {
                     name: self.name.clone(),
                 })?)
             }
@@ -426,7 +646,14 @@ impl NexusChild {
     ) -> Result<usize, ChildIoError> {
         match self.bdev_handle.as_ref() {
             Some(desc) => {
-                Ok(desc.read_at(offset, buf).await.context(ReadError {
+                let result = desc.read_at(offset, buf).await;
+                if let Err(error) = &result {
+                    self.io_failed(
+                        matches!(error, CoreError::IoTimeout { .. }),
+                        false,
+                    );
+                }
+                Ok(result.context(ReadError {
                     name: self.name.clone(),
                 })?)
             }
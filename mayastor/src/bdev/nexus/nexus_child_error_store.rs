@@ -56,6 +56,19 @@ pub enum QueryType {
             * revisions */
 }
 
+/// how an IO of a child failed, besides failing
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ChildIoFailure {
+    /// the IO timed out
+    pub(crate) timed_out: bool,
+    /// the IO was retried, a read on another child or any IO on the same
+    /// child
+    pub(crate) retried: bool,
+    /// the IO is resubmitted to the same child, such that its failure is
+    /// not held against the child unless the resubmitted IO fails as well
+    pub(crate) resubmitted: bool,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ActionType {
@@ -236,42 +249,37 @@ impl Display for NexusErrStore {
 }
 
 impl Nexus {
-    pub fn error_record_add(
+    pub(crate) fn error_record_add(
         &self,
         bdev: *const spdk_bdev,
         io_op_type: spdk_bdev_io_type,
-        io_error_type: i32,
+        failure: ChildIoFailure,
         io_offset: u64,
         io_num_blocks: u64,
     ) {
         let now = Instant::now();
-        let cfg = Config::get();
-        if cfg.err_store_opts.enable_err_store
-            && (io_op_type == io_type::READ || io_op_type == io_type::WRITE)
-        {
-            let nexus_name = self.name.clone();
-            // dispatch message to management core to do this
-            let mgmt_reactor = Reactors::get_by_core(Cores::first()).unwrap();
-            mgmt_reactor.send_future(async move {
-                Nexus::future_error_record_add(
-                    nexus_name,
-                    bdev,
-                    io_op_type,
-                    io_error_type,
-                    io_offset,
-                    io_num_blocks,
-                    now,
-                )
-                .await;
-            });
-        }
+        let nexus_name = self.name.clone();
+        // dispatch message to management core to do this
+        let mgmt_reactor = Reactors::get_by_core(Cores::first()).unwrap();
+        mgmt_reactor.send_future(async move {
+            Nexus::future_error_record_add(
+                nexus_name,
+                bdev,
+                io_op_type,
+                failure,
+                io_offset,
+                io_num_blocks,
+                now,
+            )
+            .await;
+        });
     }
 
     async fn future_error_record_add(
         name: String,
         bdev: *const spdk_bdev,
         io_op_type: spdk_bdev_io_type,
-        io_error_type: i32,
+        failure: ChildIoFailure,
         io_offset: u64,
         io_num_blocks: u64,
         now: Instant,
@@ -284,33 +292,45 @@ impl Nexus {
             }
         };
         trace!("Adding error record {} bdev {:?}", io_op_type, bdev);
+        let cfg = Config::get();
+        let policy = nexus.child_fault_policy();
         for child in nexus.children.iter_mut() {
             if child.bdev.as_ref().unwrap().as_ptr() as *const _ == bdev {
                 if child.state == ChildState::Open {
+                    child.io_failed(failure.timed_out, failure.retried);
+                    if failure.resubmitted
+                        || !cfg.err_store_opts.enable_err_store
+                        || (io_op_type != io_type::READ
+                            && io_op_type != io_type::WRITE)
+                    {
+                        return;
+                    }
                     if child.err_store.is_some() {
                         child.err_store.as_mut().unwrap().add_record(
                             io_op_type,
-                            io_error_type,
+                            io_status::FAILED,
                             io_offset,
                             io_num_blocks,
                             now,
                         );
-                        let cfg = Config::get();
-                        if cfg.err_store_opts.action == ActionType::Fault
-                            && !Self::assess_child(
+                        if let Some(policy) = policy {
+                            if !Self::assess_child(
                                 &child,
-                                cfg.err_store_opts.max_errors,
-                                cfg.err_store_opts.retention_ns,
+                                policy.max_errors,
+                                policy.window.as_nanos() as u64,
                                 QueryType::Total,
-                            )
-                        {
-                            let child_name = child.name.clone();
-                            info!("Faulting child {}", child_name);
-                            if nexus.fault_child(&child_name).await.is_err() {
-                                error!(
-                                    "Failed to fault the child {}",
-                                    child_name,
-                                );
+                            ) {
+                                let child_name = child.name.clone();
+                                info!("Faulting child {}", child_name);
+                                if nexus.fault_child(&child_name).await.is_err()
+                                {
+                                    error!(
+                                        "Failed to fault the child {}",
+                                        child_name,
+                                    );
+                                    return;
+                                }
+                                nexus.replace_with_spare(&child_name).await;
                             }
                         }
                     } else {
@@ -16,8 +16,8 @@ use spdk_sys::{
 
 use crate::bdev::nexus::{
     instances,
-    nexus_bdev::Nexus,
-    nexus_channel::NexusChannel,
+    nexus_bdev::{FaultPolicy, Nexus},
+    nexus_channel::{NexusChannel, NexusChannelInner},
     nexus_io::{io_type, Bio},
 };
 
@@ -62,11 +62,14 @@ impl NexusFnTable {
         match io_type {
             // we always assume the device supports read/write commands
             io_type::READ | io_type::WRITE => true,
+            // the cache is written back when the nexus is flushed
+            io_type::FLUSH if nexus.write_cache.is_some() => true,
             io_type::FLUSH
             | io_type::RESET
             | io_type::UNMAP
             | io_type::NVME_ADMIN
-            | io_type::WRITE_ZEROES => {
+            | io_type::WRITE_ZEROES
+            | io_type::ABORT => {
                 let supported = nexus.io_is_supported(io_type);
                 if !supported {
                     trace!(
@@ -77,6 +80,18 @@ impl NexusFnTable {
                 }
                 supported
             }
+            // reservations are mirrored to all children
+            io_type::NVME_IO => {
+                let supported = nexus.io_is_supported_by_all(io_type);
+                if !supported {
+                    trace!(
+                        "IO type {:?} not supported for {}",
+                        io_type,
+                        nexus.bdev.name()
+                    );
+                }
+                supported
+            }
             _ => {
                 trace!(
                     "un matched IO type {} not supported for {}",
@@ -93,19 +108,114 @@ impl NexusFnTable {
     extern "C" fn io_submit(
         channel: *mut spdk_io_channel,
         io: *mut spdk_bdev_io,
+    ) {
+        if Bio::io_type(io).is_some() {
+            let ch = NexusChannel::inner_from_channel(channel);
+            ch.io_submitted(Bio(io).data_bytes());
+            Bio(io).ctx_as_mut_ref().logged = false;
+            Self::io_dispatch(io, ch);
+        } else {
+            // something is very wrong ...
+            error!("Received unknown IO type {}", unsafe { (*io).type_ });
+        }
+    }
+
+    /// Dispatch an IO, which has been accounted for already, to the write
+    /// cache or the children of the channel. Without any child the IO is
+    /// failed or frozen, according to the fault policy of the nexus.
+    pub(crate) fn io_dispatch(
+        io: *mut spdk_bdev_io,
+        ch: &mut NexusChannelInner,
+    ) {
+        Self::dispatch(io, ch, true)
+    }
+
+    /// Dispatch an IO past the write cache, once the cache has been written
+    /// back for it.
+    pub(crate) fn io_dispatch_uncached(
+        io: *mut spdk_bdev_io,
+        ch: &mut NexusChannelInner,
+    ) {
+        Self::dispatch(io, ch, false)
+    }
+
+    fn dispatch(
+        io: *mut spdk_bdev_io,
+        mut ch: &mut NexusChannelInner,
+        cached: bool,
     ) {
         if let Some(io_type) = Bio::io_type(io) {
             let mut nio = Bio(io);
-            let mut ch = NexusChannel::inner_from_channel(channel);
             let nexus = nio.nexus_as_ref();
 
+            if ch.ch.is_empty() {
+                match (io_type, nexus.fault_policy) {
+                    (io_type::ABORT, _) => {
+                        if ch.abort_frozen(nio.bio_to_abort()) {
+                            Bio::new(io, 0).ok();
+                        } else {
+                            nio.fail();
+                        }
+                    }
+                    (_, FaultPolicy::Freeze(timeout)) => {
+                        trace!("{}: Freezing {:p}", nexus.name, io);
+                        ch.freeze(io, timeout);
+                    }
+                    (_, FaultPolicy::Fail) => {
+                        trace!("{}: No child left for {:p}", nexus.name, io);
+                        nio.fail();
+                    }
+                }
+                return;
+            }
+
+            if let Some(cache) = nexus.read_cache.as_ref() {
+                match io_type {
+                    io_type::WRITE | io_type::WRITE_ZEROES | io_type::UNMAP => {
+                        cache.invalidate(nio.offset(), nio.num_blocks())
+                    }
+                    _ => {}
+                }
+            }
+
+            if cached {
+                if let Some(cache) = nexus.write_cache.as_ref() {
+                    if cache.intercept(nexus, io, io_type) {
+                        return;
+                    }
+                }
+                if let Some(cache) = nexus.read_cache.as_ref() {
+                    if cache.intercept(nexus, io, io_type, &mut ch) {
+                        return;
+                    }
+                }
+            }
+
+            if let Some(log) = nexus.intent_log.as_ref() {
+                if !nexus.read_only && log.intercept(io, io_type) {
+                    return;
+                }
+            }
+
             match io_type {
+                io_type::WRITE | io_type::WRITE_ZEROES | io_type::UNMAP
+                    if nexus.read_only =>
+                {
+                    trace!(
+                        "{}: Rejecting {} {:p} of read-only nexus",
+                        nexus.name,
+                        io_type,
+                        io
+                    );
+                    nio.fail()
+                }
                 io_type::READ => {
                     //trace!("{}: Dispatching READ {:p}", nexus.name(), io);
                     nexus.readv(io, &mut ch)
                 }
                 io_type::WRITE => {
                     //trace!("{}: Dispatching WRITE {:p}", nexus.name(), io);
+                    ch.io_fanned_out(nio.data_bytes());
                     nexus.writev(io, &ch)
                 }
                 io_type::RESET => {
@@ -122,6 +232,9 @@ impl NexusFnTable {
                 io_type::FLUSH => {
                     if nexus.io_is_supported(io_type) {
                         nexus.flush(io, &ch)
+                    } else if nexus.write_cache.is_some() {
+                        // the cache has been written back, that is all
+                        Bio::new(io, 0).ok();
                     } else {
                         nio.fail()
                     }
@@ -140,6 +253,17 @@ impl NexusFnTable {
                         nio.fail()
                     }
                 }
+                io_type::NVME_IO => {
+                    if nexus.io_is_supported_by_all(io_type) {
+                        nexus.nvme_io(io, &ch)
+                    } else {
+                        nio.fail()
+                    }
+                }
+                io_type::ABORT => {
+                    trace!("{}: Dispatching ABORT {:p}", nexus.name, io);
+                    nexus.abort(io, &ch)
+                }
 
                 _ => panic!(
                     "{} Received unsupported IO! type {}",
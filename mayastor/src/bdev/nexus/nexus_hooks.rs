@@ -0,0 +1,142 @@
+//! Commands run on events of a nexus.
+//!
+//! For operators who hook mayastor into a ticketing system or their own
+//! automation rather than the full control plane, a command can be given in
+//! the event_hooks section of the config for each of these events:
+//!
+//! - `child_faulted`: a child of a nexus has been faulted
+//! - `rebuild_complete`: the rebuild of a child has finished
+//! - `nexus_published`: a nexus has been published
+//!
+//! The command is run by the shell in a thread of its own, so that it never
+//! holds up a reactor, and is killed if it runs for longer than the timeout.
+//! The details of the event are passed in environment variables:
+//!
+//! - `MAYASTOR_EVENT`: the name of the event as above
+//! - `MAYASTOR_NEXUS`: the name of the nexus
+//! - `MAYASTOR_NEXUS_UUID`: the uuid of the nexus
+//! - `MAYASTOR_CHILD`: the uri of the child, for child events
+//! - `MAYASTOR_REASON`: why the child has been faulted
+//! - `MAYASTOR_REBUILD_STATE`: how the rebuild ended, i.e. completed
+//! - `MAYASTOR_DEVICE_URI`: the uri the nexus has been published at
+
+use std::{
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{core::Mthread, grpc::name_to_uuid, subsys::Config};
+
+/// how often to check whether a hook has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A child of the nexus has been faulted, the reason being i.e. "io error",
+/// "read-only pool" or "hot removed".
+pub(crate) fn child_faulted(nexus: &str, child: &str, reason: &str) {
+    let hooks = &Config::get().event_hooks;
+    run(
+        "child_faulted",
+        hooks.child_faulted.as_ref(),
+        nexus,
+        vec![
+            ("MAYASTOR_CHILD", child.to_string()),
+            ("MAYASTOR_REASON", reason.to_string()),
+        ],
+    );
+}
+
+/// The rebuild of a child of the nexus has finished in the given state.
+pub(crate) fn rebuild_complete(nexus: &str, child: &str, state: &str) {
+    let hooks = &Config::get().event_hooks;
+    run(
+        "rebuild_complete",
+        hooks.rebuild_complete.as_ref(),
+        nexus,
+        vec![
+            ("MAYASTOR_CHILD", child.to_string()),
+            ("MAYASTOR_REBUILD_STATE", state.to_string()),
+        ],
+    );
+}
+
+/// The nexus has been published at the device uri.
+pub(crate) fn nexus_published(nexus: &str, device_uri: &str) {
+    let hooks = &Config::get().event_hooks;
+    run(
+        "nexus_published",
+        hooks.nexus_published.as_ref(),
+        nexus,
+        vec![("MAYASTOR_DEVICE_URI", device_uri.to_string())],
+    );
+}
+
+/// run the command of the event, if any, in the background
+fn run(
+    event: &'static str,
+    command: Option<&String>,
+    nexus: &str,
+    env: Vec<(&'static str, String)>,
+) {
+    let command = match command {
+        Some(command) if !command.is_empty() => command.clone(),
+        _ => return,
+    };
+    let timeout =
+        Duration::from_secs(Config::get().event_hooks.timeout_secs.max(1));
+
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c")
+        .arg(&command)
+        .env("MAYASTOR_EVENT", event)
+        .env("MAYASTOR_NEXUS", nexus)
+        .env("MAYASTOR_NEXUS_UUID", name_to_uuid(nexus))
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    let nexus = nexus.to_string();
+    thread::spawn(move || {
+        Mthread::unaffinitize();
+
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                error!("{}: failed to run the {} hook: {}", nexus, event, e);
+                return;
+            }
+        };
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => {
+                    debug!("{}: the {} hook has run", nexus, event);
+                    return;
+                }
+                Ok(Some(status)) => {
+                    warn!("{}: the {} hook failed: {}", nexus, event, status);
+                    return;
+                }
+                Ok(None) if Instant::now() >= deadline => {
+                    warn!(
+                        "{}: the {} hook timed out after {:?}, killing it",
+                        nexus, event, timeout
+                    );
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return;
+                }
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    error!(
+                        "{}: failed to wait for the {} hook: {}",
+                        nexus, event, e
+                    );
+                    return;
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,616 @@
+//! Write-intent log of a nexus.
+//!
+//! The nexus is divided into regions of at least a MiB, each of which has a
+//! bit in a bitmap that is kept at the end of the metadata partition of
+//! every child. Before a write, write zeroes or unmap is dispatched to the
+//! children, the bits of the regions it touches are set on the children
+//! that can take IO. The bits are cleared again, in memory and then on the
+//! children, once the regions have had no write in flight for a while.
+//!
+//! A write which was in flight when mayastor stopped may have reached some
+//! of the children only, but its regions are marked in the log of at least
+//! one of them. When the nexus is created again, the union of the logs of
+//! its children is taken and only the regions marked there are copied from
+//! one healthy child to the others, rather than rebuilding them as a whole.
+//! When the nexus is destroyed, the log is cleared unless a rebuild is
+//! pending.
+
+use std::{
+    fmt,
+    ops::{Range, RangeInclusive},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use bincode::{deserialize, serialize};
+use crc::crc32;
+use futures::{channel::oneshot, lock::Mutex as AsyncMutex};
+use serde::{Deserialize, Serialize};
+
+use spdk_sys::{spdk_bdev_io, spdk_bdev_io_get_io_channel};
+
+use crate::{
+    bdev::nexus::{
+        nexus_bdev::{nexus_lookup, Nexus},
+        nexus_channel::NexusChannel,
+        nexus_child::ChildStatus,
+        nexus_fn_table::NexusFnTable,
+        nexus_io::{io_type, Bio},
+        nexus_label::{Aligned, NexusLabel},
+    },
+    core::{sleep, DmaBuf, Reactors},
+    subsys::Config,
+};
+
+/// bytes reserved for the log at the end of the metadata partition
+pub(crate) const LOG_SIZE: u64 = 64 * 1024;
+/// the bitmap fits in a 4KiB block
+const MAX_REGIONS: u64 = 4096 * 8;
+/// the smallest size of a region in bytes
+const MIN_REGION_SIZE: u64 = 1024 * 1024;
+/// "MayaWIL" followed by the version
+const SIGNATURE: [u8; 8] = [0x4d, 0x61, 0x79, 0x61, 0x57, 0x49, 0x4c, 0x01];
+
+/// the header in the first block of the log, the bitmap follows in the
+/// next blocks
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LogHeader {
+    signature: [u8; 8],
+    /// checksum of the header, with this field 0, and of the bitmap
+    checksum: u32,
+    /// number of blocks of the nexus per region
+    region_blocks: u64,
+    /// number of regions, and bits in the bitmap
+    regions: u64,
+    /// incremented whenever the log is written
+    generation: u64,
+}
+
+/// the writes waiting for the bits of their regions to be written
+#[derive(Default)]
+struct Waiters {
+    /// a flush of the log is scheduled on the master core
+    flushing: bool,
+    list: Vec<oneshot::Sender<bool>>,
+}
+
+pub(crate) struct IntentLog {
+    /// name of the nexus
+    nexus: String,
+    /// first block of the log on the children
+    lba: u64,
+    block_len: u64,
+    alignment: u8,
+    /// number of blocks of the nexus per region, a power of two
+    region_blocks: u64,
+    regions: u64,
+    /// the regions which are marked, or about to be, in memory
+    dirty: Vec<AtomicU64>,
+    /// the regions which are marked on the children
+    synced: Vec<AtomicU64>,
+    /// number of writes in flight per region
+    in_flight: Vec<AtomicU32>,
+    generation: AtomicU64,
+    waiters: Mutex<Waiters>,
+    /// serializes the writes of the log
+    io_lock: AsyncMutex<()>,
+}
+
+impl fmt::Debug for IntentLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IntentLog")
+            .field("lba", &self.lba)
+            .field("region_blocks", &self.region_blocks)
+            .field("regions", &self.regions)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+fn is_set(map: &[AtomicU64], region: u64) -> bool {
+    map[(region / 64) as usize].load(Ordering::SeqCst) & (1 << (region % 64))
+        != 0
+}
+
+fn set(map: &[AtomicU64], region: u64) {
+    map[(region / 64) as usize].fetch_or(1 << (region % 64), Ordering::SeqCst);
+}
+
+fn clear(map: &[AtomicU64], region: u64) {
+    map[(region / 64) as usize]
+        .fetch_and(!(1 << (region % 64)), Ordering::SeqCst);
+}
+
+impl IntentLog {
+    /// A log for a nexus of the given number of blocks, which is kept at the
+    /// end of the metadata partition of the label.
+    fn new(nexus: &Nexus, label: &NexusLabel) -> Self {
+        let block_len = u64::from(nexus.bdev.block_len());
+        let num_blocks = nexus.bdev.num_blocks();
+
+        let mut region_blocks = (MIN_REGION_SIZE / block_len).max(1);
+        while region_blocks * MAX_REGIONS < num_blocks {
+            region_blocks *= 2;
+        }
+        let regions = (num_blocks + region_blocks - 1) / region_blocks;
+        let words = ((regions + 63) / 64) as usize;
+
+        Self {
+            nexus: nexus.name.clone(),
+            lba: label.partitions[0].ent_end + 1
+                - Aligned::get_blocks(LOG_SIZE, block_len),
+            block_len,
+            alignment: nexus.bdev.alignment(),
+            region_blocks,
+            regions,
+            dirty: (0 .. words).map(|_| AtomicU64::new(0)).collect(),
+            synced: (0 .. words).map(|_| AtomicU64::new(0)).collect(),
+            in_flight: (0 .. regions).map(|_| AtomicU32::new(0)).collect(),
+            generation: AtomicU64::new(0),
+            waiters: Mutex::new(Waiters::default()),
+            io_lock: AsyncMutex::new(()),
+        }
+    }
+
+    /// the regions touched by the given range of blocks
+    fn span(&self, offset: u64, num_blocks: u64) -> RangeInclusive<u64> {
+        let last = offset + num_blocks.max(1) - 1;
+        offset / self.region_blocks ..= last / self.region_blocks
+    }
+
+    /// Hold back a write, write zeroes or unmap until the bits of its
+    /// regions are set on the children, after which it is dispatched past
+    /// the log on the core it was submitted on. Returns false if the IO is
+    /// to be dispatched to the children as usual.
+    pub(crate) fn intercept(
+        self: &Arc<Self>,
+        io: *mut spdk_bdev_io,
+        io_type: u32,
+    ) -> bool {
+        let mut nio = Bio(io);
+        match io_type {
+            io_type::WRITE | io_type::WRITE_ZEROES | io_type::UNMAP => {}
+            _ => return false,
+        }
+        if nio.ctx_as_mut_ref().logged {
+            return false;
+        }
+
+        let span = self.span(nio.offset(), nio.num_blocks());
+        if self.begin(span.clone()) {
+            nio.ctx_as_mut_ref().logged = true;
+            return false;
+        }
+
+        let synced = self.sync(span);
+        Reactors::current().send_future(async move {
+            if synced.await.unwrap_or(false) {
+                let ch = NexusChannel::inner_from_channel(unsafe {
+                    spdk_bdev_io_get_io_channel(io)
+                });
+                NexusFnTable::io_dispatch_uncached(io, ch);
+            } else {
+                Bio::new(io, 0).fail();
+            }
+        });
+        true
+    }
+
+    /// Count a write to the regions as in flight if they are marked on the
+    /// children already, returns whether they are.
+    fn begin(&self, span: RangeInclusive<u64>) -> bool {
+        for r in span.clone() {
+            self.in_flight[r as usize].fetch_add(1, Ordering::SeqCst);
+        }
+        if span.clone().all(|r| is_set(&self.synced, r)) {
+            return true;
+        }
+        for r in span {
+            self.in_flight[r as usize].fetch_sub(1, Ordering::SeqCst);
+        }
+        false
+    }
+
+    /// Mark the regions of the given range of blocks on the children before
+    /// writing to it some other way than through the children of the
+    /// channels, returns false if they could not be marked. The write must
+    /// be ended once it has completed.
+    pub(crate) async fn begin_write(
+        self: &Arc<Self>,
+        offset: u64,
+        num_blocks: u64,
+    ) -> bool {
+        let span = self.span(offset, num_blocks);
+        while !self.begin(span.clone()) {
+            if !self.sync(span.clone()).await.unwrap_or(false) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// a write to the given range of blocks has completed
+    pub(crate) fn end_write(&self, offset: u64, num_blocks: u64) {
+        for r in self.span(offset, num_blocks) {
+            self.in_flight[r as usize].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Set the bits of the regions and have the log written on the master
+    /// core, the receiver tells whether it has been written to any child.
+    fn sync(
+        self: &Arc<Self>,
+        span: RangeInclusive<u64>,
+    ) -> oneshot::Receiver<bool> {
+        for r in span {
+            set(&self.dirty, r);
+        }
+
+        let (s, r) = oneshot::channel();
+        let mut waiters = self.waiters.lock().unwrap();
+        waiters.list.push(s);
+        if !waiters.flushing {
+            waiters.flushing = true;
+            let log = Arc::clone(self);
+            Reactors::master().send_future(async move { log.flush().await });
+        }
+        r
+    }
+
+    /// write the log for the waiting writes, until there are none left
+    async fn flush(&self) {
+        loop {
+            let _guard = self.io_lock.lock().await;
+            let list = {
+                let mut waiters = self.waiters.lock().unwrap();
+                if waiters.list.is_empty() {
+                    waiters.flushing = false;
+                    return;
+                }
+                std::mem::take(&mut waiters.list)
+            };
+
+            let bitmap = self.snapshot();
+            let written = self.write(&bitmap).await;
+            if written {
+                for (i, word) in bitmap.iter().enumerate() {
+                    self.synced[i].fetch_or(*word, Ordering::SeqCst);
+                }
+            }
+            for s in list {
+                let _ = s.send(written);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u64> {
+        self.dirty
+            .iter()
+            .map(|w| w.load(Ordering::SeqCst))
+            .collect()
+    }
+
+    /// Clear the regions which have had no write in flight since the last
+    /// time, and write the log if any was cleared. The regions stay marked
+    /// while a child is being rebuilt or lags behind, as they may be all
+    /// that is left to tell which regions are to be resynced.
+    async fn clean(&self, nexus: &Nexus) {
+        if nexus.children.iter().any(|c| c.rebuilding() || c.lagging()) {
+            return;
+        }
+
+        let _guard = self.io_lock.lock().await;
+        let mut cleared = false;
+        for r in 0 .. self.regions {
+            let in_flight = &self.in_flight[r as usize];
+            if !is_set(&self.synced, r) || in_flight.load(Ordering::SeqCst) > 0
+            {
+                continue;
+            }
+            // a write beginning now has to wait for the region to be marked
+            // again, one that began meanwhile keeps it marked
+            clear(&self.synced, r);
+            if in_flight.load(Ordering::SeqCst) > 0 {
+                set(&self.synced, r);
+                continue;
+            }
+            clear(&self.dirty, r);
+            cleared = true;
+        }
+
+        if cleared {
+            let bitmap = self.snapshot();
+            self.write(&bitmap).await;
+        }
+    }
+
+    /// write the bitmap to all the children that can take IO, but those
+    /// which lag behind, returns whether it has been written to any
+    async fn write(&self, bitmap: &[u64]) -> bool {
+        let nexus = match nexus_lookup(&self.nexus) {
+            Some(nexus) => nexus,
+            None => return false,
+        };
+
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let buf = match self.encode(bitmap, generation) {
+            Ok(buf) => buf,
+            Err(e) => {
+                error!(
+                    "{}: failed to encode the intent log: {}",
+                    self.nexus, e
+                );
+                return false;
+            }
+        };
+
+        let mut written = false;
+        for child in
+            nexus.children.iter().filter(|c| c.can_rw() && !c.lagging())
+        {
+            match child.write_at(self.lba * self.block_len, &buf).await {
+                Ok(_) => written = true,
+                Err(e) => error!(
+                    "{}: failed to write the intent log to {}: {}",
+                    self.nexus, child.name, e
+                ),
+            }
+        }
+        written
+    }
+
+    fn encode(
+        &self,
+        bitmap: &[u64],
+        generation: u64,
+    ) -> Result<DmaBuf, String> {
+        let mut bytes = Vec::with_capacity(bitmap.len() * 8);
+        bitmap
+            .iter()
+            .for_each(|w| bytes.extend_from_slice(&w.to_le_bytes()));
+
+        let mut header = LogHeader {
+            signature: SIGNATURE,
+            checksum: 0,
+            region_blocks: self.region_blocks,
+            regions: self.regions,
+            generation,
+        };
+        header.checksum = Self::checksum(&header, &bytes);
+        let header = serialize(&header).map_err(|e| e.to_string())?;
+
+        let len =
+            Aligned::get_blocks(LOG_SIZE, self.block_len) * self.block_len;
+        let mut buf = DmaBuf::new(len as usize, self.alignment)
+            .map_err(|e| e.to_string())?;
+        buf.fill(0);
+        let start = self.block_len as usize;
+        let slice = buf.as_mut_slice();
+        slice[.. header.len()].copy_from_slice(&header);
+        slice[start .. start + bytes.len()].copy_from_slice(&bytes);
+        Ok(buf)
+    }
+
+    fn checksum(header: &LogHeader, bitmap: &[u8]) -> u32 {
+        let mut bytes = serialize(&LogHeader {
+            checksum: 0,
+            ..*header
+        })
+        .unwrap();
+        bytes.extend_from_slice(bitmap);
+        crc32::checksum_ieee(&bytes)
+    }
+
+    /// The bitmap of a log read from a child, in terms of the regions of
+    /// this log, or None if the child has no valid log. A log with other
+    /// regions, of a nexus that has been resized, marks all of them if it
+    /// marks any.
+    fn decode(&self, buf: &DmaBuf) -> Option<Vec<u64>> {
+        let slice = buf.as_slice();
+        let header: LogHeader = deserialize(slice).ok()?;
+        if header.signature != SIGNATURE
+            || header.regions == 0
+            || header.regions > MAX_REGIONS
+        {
+            return None;
+        }
+
+        let start = self.block_len as usize;
+        let len = ((header.regions + 63) / 64 * 8) as usize;
+        let bytes = slice.get(start .. start + len)?;
+        if Self::checksum(&header, bytes) != header.checksum {
+            return None;
+        }
+
+        let words = bytes
+            .chunks(8)
+            .map(|c| {
+                let mut word = [0u8; 8];
+                word.copy_from_slice(c);
+                u64::from_le_bytes(word)
+            })
+            .collect::<Vec<_>>();
+
+        if header.region_blocks == self.region_blocks
+            && header.regions == self.regions
+        {
+            Some(words)
+        } else if words.iter().any(|w| *w != 0) {
+            let mut all = vec![!0u64; self.dirty.len()];
+            if self.regions % 64 != 0 {
+                *all.last_mut().unwrap() = (1 << (self.regions % 64)) - 1;
+            }
+            Some(all)
+        } else {
+            Some(vec![0; self.dirty.len()])
+        }
+    }
+
+    /// Read the logs of the children which can take IO and mark the regions
+    /// marked on any of them, returns the number of those regions.
+    async fn load(&self, nexus: &Nexus) -> u64 {
+        let len =
+            Aligned::get_blocks(LOG_SIZE, self.block_len) * self.block_len;
+        for child in nexus.children.iter().filter(|c| c.can_rw()) {
+            let mut buf = match DmaBuf::new(len as usize, self.alignment) {
+                Ok(buf) => buf,
+                Err(_) => continue,
+            };
+            if let Err(e) =
+                child.read_at(self.lba * self.block_len, &mut buf).await
+            {
+                error!(
+                    "{}: failed to read the intent log of {}: {}",
+                    self.nexus, child.name, e
+                );
+                continue;
+            }
+            if let Some(words) = self.decode(&buf) {
+                for (i, word) in words.iter().enumerate() {
+                    self.dirty[i].fetch_or(*word, Ordering::SeqCst);
+                    self.synced[i].fetch_or(*word, Ordering::SeqCst);
+                }
+            }
+        }
+
+        self.snapshot()
+            .iter()
+            .map(|w| u64::from(w.count_ones()))
+            .sum()
+    }
+
+    /// the ranges of blocks of the marked regions, adjacent ones merged
+    pub(crate) fn dirty_ranges(&self, num_blocks: u64) -> Vec<Range<u64>> {
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        for r in (0 .. self.regions).filter(|r| is_set(&self.dirty, *r)) {
+            let start = r * self.region_blocks;
+            let end = std::cmp::min(start + self.region_blocks, num_blocks);
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start .. end),
+            }
+        }
+        ranges
+    }
+
+    /// whether any write is in flight
+    fn busy(&self) -> bool {
+        self.in_flight.iter().any(|c| c.load(Ordering::SeqCst) > 0)
+    }
+}
+
+impl Nexus {
+    /// Set up the intent log, if enabled, once the labels of the children
+    /// are in sync, and mark the regions left marked on any child. The
+    /// log is set up once only, also when a child is added later on.
+    pub(crate) async fn open_intent_log(&mut self, label: &NexusLabel) {
+        if self.intent_log.is_some()
+            || !Config::get().nexus_opts.intent_log.enable
+        {
+            return;
+        }
+
+        let log = Arc::new(IntentLog::new(self, label));
+        let dirty = log.load(self).await;
+        if dirty > 0 {
+            info!(
+                "{}: {} regions of {} blocks left marked in the intent log",
+                self.name, dirty, log.region_blocks
+            );
+        }
+        self.intent_log = Some(Arc::clone(&log));
+
+        // clear the regions without writes every interval, until the log is
+        // dropped
+        let interval = Duration::from_millis(
+            Config::get().nexus_opts.intent_log.clean_interval_ms.max(1),
+        );
+        Reactors::master().send_future(async move {
+            loop {
+                sleep(interval).await;
+                match nexus_lookup(&log.nexus) {
+                    Some(nexus)
+                        if nexus
+                            .intent_log
+                            .as_ref()
+                            .map_or(false, |l| Arc::ptr_eq(l, &log)) =>
+                    {
+                        log.clean(nexus).await
+                    }
+                    _ => break,
+                }
+            }
+        });
+    }
+
+    /// Copy the regions marked in the intent log when the nexus was opened
+    /// from the first healthy child to the other ones, which are out of
+    /// sync until then.
+    pub(crate) async fn resync_intent_log(&mut self) {
+        let log = match self.intent_log.clone() {
+            Some(log) => log,
+            None => return,
+        };
+        let regions = log.dirty_ranges(self.bdev.num_blocks());
+        let targets = self
+            .children
+            .iter()
+            .filter(|c| c.status() == ChildStatus::Online)
+            .skip(1)
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+        if regions.is_empty() || targets.is_empty() {
+            return;
+        }
+
+        info!(
+            "{}: resyncing {} ranges of blocks of {} children",
+            self.name,
+            regions.len(),
+            targets.len()
+        );
+        for name in &targets {
+            if let Some(child) =
+                self.children.iter_mut().find(|c| &c.name == name)
+            {
+                child.out_of_sync(true);
+            }
+        }
+        for name in &targets {
+            if let Err(e) =
+                self.start_rebuild_of(name, Some(regions.clone())).await
+            {
+                error!("{}: failed to resync {}: {}", self.name, name, e);
+            }
+        }
+    }
+
+    /// Drop the intent log once the writes in flight have completed and
+    /// clear it on the children, unless a rebuild is pending.
+    pub(crate) async fn close_intent_log(&mut self) {
+        let log = match self.intent_log.clone() {
+            Some(log) => log,
+            None => return,
+        };
+
+        for _ in 0 .. 100 {
+            if !log.busy() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        if log.busy() {
+            warn!(
+                "{}: closing the intent log with writes in flight",
+                self.name
+            );
+        } else {
+            log.clean(self).await;
+        }
+        self.intent_log = None;
+    }
+}
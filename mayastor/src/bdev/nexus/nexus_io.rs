@@ -3,13 +3,28 @@ use std::fmt::{Debug, Formatter};
 
 use libc::c_void;
 
-use spdk_sys::{spdk_bdev_free_io, spdk_bdev_io, spdk_bdev_io_complete};
+use spdk_sys::{
+    spdk_bdev,
+    spdk_bdev_free_io,
+    spdk_bdev_io,
+    spdk_bdev_io_complete,
+    spdk_bdev_io_get_io_channel,
+    spdk_bdev_io_get_nvme_status,
+};
 
 use crate::{
-    bdev::nexus::nexus_bdev::{Nexus, NEXUS_PRODUCT_ID},
+    bdev::nexus::{
+        nexus_bdev::{Nexus, NEXUS_PRODUCT_ID},
+        nexus_channel::NexusChannel,
+        nexus_child_error_store::ChildIoFailure,
+    },
     core::Bdev,
+    lvs::read_only::NVME_SC_NAMESPACE_IS_WRITE_PROTECTED,
 };
 
+/// maximum number of children of which an IO keeps track of the retries
+pub(crate) const MAX_RETRIED_CHILDREN: usize = 8;
+
 /// NioCtx provides context on a per IO basis
 #[derive(Debug, Clone)]
 pub struct NioCtx {
@@ -17,6 +32,66 @@ pub struct NioCtx {
     pub(crate) in_flight: i8,
     /// status of the IO
     pub(crate) status: i32,
+    /// the children the IO was resubmitted to after a backoff, with the
+    /// number of times it was resubmitted to each of them
+    pub(crate) resubmitted_to: [(*mut spdk_bdev, u8); MAX_RETRIED_CHILDREN],
+    /// number of entries of resubmitted_to which are set
+    pub(crate) resubmits: u8,
+    /// the children a read has failed on, which it is not failed over to
+    /// again
+    pub(crate) failed_on: [*mut spdk_bdev; MAX_RETRIED_CHILDREN],
+    /// number of entries of failed_on which are set
+    pub(crate) failovers: u8,
+    /// the IO is counted as in flight by the intent log
+    pub(crate) logged: bool,
+}
+
+impl NioCtx {
+    /// The number of times the IO was resubmitted to the child so far, which
+    /// is counted per child as a write is submitted to all of them. None if
+    /// the IO was resubmitted to more children than can be tracked.
+    pub(crate) fn resubmits_to(
+        &mut self,
+        bdev: *mut spdk_bdev,
+    ) -> Option<&mut u8> {
+        let count = self.resubmits as usize;
+        let found = self.resubmitted_to[.. count]
+            .iter()
+            .position(|(b, _)| *b == bdev);
+        match found {
+            Some(i) => Some(&mut self.resubmitted_to[i].1),
+            None if count < MAX_RETRIED_CHILDREN => {
+                self.resubmitted_to[count] = (bdev, 0);
+                self.resubmits += 1;
+                Some(&mut self.resubmitted_to[count].1)
+            }
+            None => None,
+        }
+    }
+}
+
+/// IO statistics of a nexus. The front-end counters are about the IOs
+/// submitted to the nexus itself, the others about the bytes the nexus
+/// writes to its children on their behalf or to rebuild a child. The
+/// counters are kept per IO channel and summed up over all channels when
+/// requested.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NexusIoStats {
+    /// number of IOs submitted to the nexus that have not completed yet
+    pub queue_depth: u64,
+    /// number of bytes of the read and write IOs that are in flight
+    pub in_flight_bytes: u64,
+    /// number of bytes written to the children by the writes to the nexus,
+    /// once for each child a write is dispatched to
+    pub child_bytes_written: u64,
+    /// number of bytes written to the children by rebuilds
+    pub rebuild_bytes_written: u64,
+    /// number of reads served by the read cache
+    pub read_cache_hits: u64,
+    /// number of reads served by the children while there is a read cache
+    pub read_cache_misses: u64,
+    /// number of IOs the nexus has failed
+    pub io_errors: u64,
 }
 
 /// BIO is a wrapper to provides a "less unsafe" wrappers around raw
@@ -55,18 +130,22 @@ pub mod io_type {
     pub const FLUSH: u32 = 4;
     pub const RESET: u32 = 5;
     pub const NVME_ADMIN: u32 = 6;
-    //    pub const NVME_IO: u32 = 7;
+    pub const NVME_IO: u32 = 7;
     //    pub const NVME_IO_MD: u32 = 8;
     pub const WRITE_ZEROES: u32 = 9;
-    //    pub const ZCOPY: u32 = 10;
+    pub const ZCOPY: u32 = 10;
     //    pub const GET_ZONE_INFO: u32 = 11;
     //    pub const ZONE_MANAGMENT: u32 = 12;
     //    pub const ZONE_APPEND: u32 = 13;
-    //    pub const IO_NUM_TYPES: u32 = 14;
+    //    pub const COMPARE: u32 = 14;
+    //    pub const COMPARE_AND_WRITE: u32 = 15;
+    pub const ABORT: u32 = 16;
+    //    pub const IO_NUM_TYPES: u32 = 17;
 }
 
 /// the status of an IO - note: values copied from spdk bdev_module.h
 pub mod io_status {
+    pub const ABORTED: i32 = -7;
     //pub const NOMEM: i32 = -4;
     //pub const SCSI_ERROR: i32 = -3;
     //pub const NVME_ERROR: i32 = -2;
@@ -79,6 +158,47 @@ pub mod io_status {
 pub mod nvme_admin_opc {
     // Vendor-specific
     pub const CREATE_SNAPSHOT: u8 = 0xc0;
+    pub const RESTORE_SNAPSHOT: u8 = 0xc1;
+}
+
+/// NVMe NVM command set opcodes of the reservation commands, from
+/// nvme_spec.h
+pub mod nvme_nvm_opc {
+    pub const RESERVATION_REGISTER: u8 = 0x0d;
+    pub const RESERVATION_REPORT: u8 = 0x0e;
+    pub const RESERVATION_ACQUIRE: u8 = 0x11;
+    pub const RESERVATION_RELEASE: u8 = 0x15;
+}
+
+/// NVMe generic command status of a command aborted by an Abort command, from
+/// nvme_spec.h
+const NVME_SC_ABORTED_BY_REQUEST: i32 = 0x07;
+
+/// NVMe generic command status of a command aborted as its submission queue
+/// was deleted, which is how the nvme bdev completes the IOs of a controller
+/// it resets after an IO timed out, from nvme_spec.h
+const NVME_SC_ABORTED_SQ_DELETION: i32 = 0x08;
+
+/// NVMe status code types, from nvme_spec.h
+const NVME_SCT_GENERIC: i32 = 0x0;
+const NVME_SCT_MEDIA_ERROR: i32 = 0x2;
+const NVME_SCT_PATH: i32 = 0x3;
+
+/// NVMe generic command status of a command issued to a namespace which is
+/// not ready, from nvme_spec.h
+const NVME_SC_NAMESPACE_NOT_READY: i32 = 0x82;
+
+/// the kind of failure of a child IO, which decides whether it is worth to
+/// retry the IO on the same child, only an IO of which the path was down is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ChildIoError {
+    /// the path to the child is down, or the child is not ready, such that
+    /// the IO is likely to succeed once the path recovers
+    PathDown,
+    /// the medium of the child failed the IO, it is bound to fail again
+    Media,
+    /// any other failure
+    Other,
 }
 
 impl Bio {
@@ -92,6 +212,8 @@ impl Bio {
         let mut io = Bio(pio);
         io.ctx_as_mut_ref().in_flight = in_flight;
         io.ctx_as_mut_ref().status = io_status::SUCCESS;
+        io.ctx_as_mut_ref().resubmits = 0;
+        io.ctx_as_mut_ref().failovers = 0;
         io
     }
 
@@ -112,14 +234,52 @@ impl Bio {
             }
         }
 
+        self.account_completion();
         unsafe { spdk_bdev_io_complete(self.0, io_status::SUCCESS) };
     }
     /// mark the IO as failed
     #[inline]
     pub(crate) fn fail(&mut self) {
+        let ch = unsafe { spdk_bdev_io_get_io_channel(self.0) };
+        NexusChannel::inner_from_channel(ch).io_failed();
+        self.account_completion();
         unsafe { spdk_bdev_io_complete(self.0, io_status::FAILED) };
     }
 
+    /// mark the IO as aborted, which is reported to the initiator as
+    /// "command abort requested"
+    #[inline]
+    pub(crate) fn aborted(&mut self) {
+        self.account_completion();
+        unsafe { spdk_bdev_io_complete(self.0, io_status::ABORTED) };
+    }
+
+    /// remove the IO from the stats of the channel it was submitted on, and
+    /// drop the blocks it has changed from the read cache
+    #[inline]
+    fn account_completion(&self) {
+        let ch = unsafe { spdk_bdev_io_get_io_channel(self.0) };
+        NexusChannel::inner_from_channel(ch).io_completed(self.data_bytes());
+
+        if let Some(cache) = self.nexus_as_ref().read_cache.as_ref() {
+            match Bio::io_type(self.0) {
+                Some(io_type::WRITE)
+                | Some(io_type::WRITE_ZEROES)
+                | Some(io_type::UNMAP) => {
+                    cache.invalidate(self.offset(), self.num_blocks())
+                }
+                _ => {}
+            }
+        }
+
+        let ctx = Bio(self.0).ctx_as_mut_ref();
+        if std::mem::replace(&mut ctx.logged, false) {
+            if let Some(log) = self.nexus_as_ref().intent_log.as_ref() {
+                log.end_write(self.offset(), self.num_blocks());
+            }
+        }
+    }
+
     /// assess the IO if we need to mark it failed or ok.
     #[inline]
     pub(crate) fn assess(
@@ -134,26 +294,38 @@ impl Bio {
         }
 
         if !success && !child_io.is_null() {
-            let io_type = Bio::io_type(self.0).unwrap();
-            let io_offset = self.offset();
-            let io_num_blocks = self.num_blocks();
-
-            unsafe {
-                self.nexus_as_ref().error_record_add(
-                    (*child_io).bdev,
-                    io_type,
-                    io_status::FAILED,
-                    io_offset,
-                    io_num_blocks,
-                );
+            if Bio::is_write_protected(child_io) {
+                // not an IO error either, the pool of the replica of the
+                // child has become read-only for good
+                self.nexus_as_ref()
+                    .child_write_protected(unsafe { (*child_io).bdev });
+            } else if !Bio::is_aborted(child_io) {
+                // an aborted IO is not an error of the child
+                let io_type = Bio::io_type(self.0).unwrap();
+                let io_offset = self.offset();
+                let io_num_blocks = self.num_blocks();
+
+                unsafe {
+                    self.nexus_as_ref().error_record_add(
+                        (*child_io).bdev,
+                        io_type,
+                        ChildIoFailure {
+                            timed_out: Bio::is_timed_out(child_io),
+                            retried: false,
+                            resubmitted: false,
+                        },
+                        io_offset,
+                        io_num_blocks,
+                    );
+                }
             }
         }
 
         if self.ctx_as_mut_ref().in_flight == 0 {
-            if self.ctx_as_mut_ref().status == io_status::FAILED {
-                self.fail();
-            } else {
-                self.ok();
+            match self.ctx_as_mut_ref().status {
+                io_status::FAILED => self.fail(),
+                io_status::ABORTED => self.aborted(),
+                _ => self.ok(),
             }
         }
     }
@@ -199,6 +371,18 @@ impl Bio {
         unsafe { (*self.0).u.bdev.num_blocks }
     }
 
+    /// number of bytes transferred by this IO, only reads and writes are
+    /// considered to carry data
+    #[inline]
+    pub(crate) fn data_bytes(&self) -> u64 {
+        match Bio::io_type(self.0) {
+            Some(io_type::READ) | Some(io_type::WRITE) => {
+                self.num_blocks() * self.block_len()
+            }
+            _ => 0,
+        }
+    }
+
     /// NVMe passthru command
     #[inline]
     pub(crate) fn nvme_cmd(&self) -> spdk_sys::spdk_nvme_cmd {
@@ -217,6 +401,71 @@ impl Bio {
         unsafe { (*self.0).u.nvme_passthru.nbytes }
     }
 
+    /// the front-end IO an abort IO is for
+    #[inline]
+    pub(crate) fn bio_to_abort(&self) -> *mut spdk_bdev_io {
+        unsafe { (*self.0).u.abort.bio_to_abort }
+    }
+
+    /// determine if a (child) IO failed because it was aborted, either by the
+    /// bdev layer or by the NVMe controller of the child
+    pub(crate) fn is_aborted(io: *const spdk_bdev_io) -> bool {
+        if unsafe { (*io).internal.status } as i32 == io_status::ABORTED {
+            return true;
+        }
+
+        let mut cdw0 = 0u32;
+        let mut sct = 0i32;
+        let mut sc = 0i32;
+        unsafe {
+            spdk_bdev_io_get_nvme_status(io, &mut cdw0, &mut sct, &mut sc)
+        };
+        sct == 0 && sc == NVME_SC_ABORTED_BY_REQUEST
+    }
+
+    /// determine if a (child) IO failed because it timed out, as far as the
+    /// nvme bdev lets on
+    pub(crate) fn is_timed_out(io: *const spdk_bdev_io) -> bool {
+        let mut cdw0 = 0u32;
+        let mut sct = 0i32;
+        let mut sc = 0i32;
+        unsafe {
+            spdk_bdev_io_get_nvme_status(io, &mut cdw0, &mut sct, &mut sc)
+        };
+        sct == 0 && sc == NVME_SC_ABORTED_SQ_DELETION
+    }
+
+    /// determine the kind of failure of a failed (child) IO
+    pub(crate) fn error_kind(io: *const spdk_bdev_io) -> ChildIoError {
+        let mut cdw0 = 0u32;
+        let mut sct = 0i32;
+        let mut sc = 0i32;
+        unsafe {
+            spdk_bdev_io_get_nvme_status(io, &mut cdw0, &mut sct, &mut sc)
+        };
+        match (sct, sc) {
+            (NVME_SCT_PATH, _)
+            | (NVME_SCT_GENERIC, NVME_SC_ABORTED_SQ_DELETION)
+            | (NVME_SCT_GENERIC, NVME_SC_NAMESPACE_NOT_READY) => {
+                ChildIoError::PathDown
+            }
+            (NVME_SCT_MEDIA_ERROR, _) => ChildIoError::Media,
+            _ => ChildIoError::Other,
+        }
+    }
+
+    /// determine if a (child) IO failed because the pool of the replica of
+    /// the child is read-only
+    pub(crate) fn is_write_protected(io: *const spdk_bdev_io) -> bool {
+        let mut cdw0 = 0u32;
+        let mut sct = 0i32;
+        let mut sc = 0i32;
+        unsafe {
+            spdk_bdev_io_get_nvme_status(io, &mut cdw0, &mut sct, &mut sc)
+        };
+        sct == 0 && sc == NVME_SC_NAMESPACE_IS_WRITE_PROTECTED
+    }
+
     /// free the io directly without completion note that the IO is not freed
     /// but rather put back into the mempool, which is allocated during startup
     #[inline]
@@ -1,17 +1,48 @@
 //! Utility functions and wrappers for working with iSCSI devices in SPDK.
+//!
+//! A target which is only exported to a set of initiators gets an initiator
+//! group of its own listing their IQNs, which replaces the default one
+//! allowing any initiator. A target shared on another portal than the
+//! default one is moved to the portal group of that portal first.
 
-use std::fmt;
+use std::{
+    cell::Cell,
+    ffi::CString,
+    fmt,
+    os::raw::{c_char, c_int},
+};
 
 use snafu::Snafu;
 
+use spdk_sys::{
+    iscsi_find_tgt_node,
+    iscsi_init_grp_create_from_initiator_list,
+    iscsi_init_grp_destroy,
+    iscsi_init_grp_unregister,
+    iscsi_target_node_add_pg_ig_maps,
+    iscsi_target_node_remove_pg_ig_maps,
+};
+
 use crate::{
     core::Bdev,
+    ffihelper::IntoCString,
+    iscsi_portal::{self, Portal},
     target::{
         iscsi::{create_uri, share, target_name, unshare},
         Side,
     },
 };
 
+/// the tags of the portal group of the nexus targets and of the initiator
+/// group allowing any initiator, as created by the iscsi target
+const ISCSI_PORTAL_GROUP_NEXUS: c_int = 0;
+const ISCSI_INITIATOR_GROUP_ANY: c_int = 0;
+
+thread_local! {
+    /// the tag of the last initiator group created for a nexus target
+    static INITIATOR_GROUP_IDX: Cell<c_int> = Cell::new(ISCSI_INITIATOR_GROUP_ANY);
+}
+
 #[derive(Debug, Snafu)]
 pub enum NexusIscsiError {
     #[snafu(display("Bdev not found {}", dev))]
@@ -22,17 +53,41 @@ pub enum NexusIscsiError {
         err
     ))]
     CreateTargetFailed { dev: String, err: String },
+    #[snafu(display(
+        "Failed to allow initiators {:?} for bdev uuid {}",
+        initiators,
+        dev
+    ))]
+    AllowInitiatorsFailed {
+        dev: String,
+        initiators: Vec<String>,
+    },
+    #[snafu(display("Failed to share bdev uuid {} on its portal", dev))]
+    PortalFailed {
+        dev: String,
+        source: iscsi_portal::Error,
+    },
 }
 
 /// Iscsi target representation.
 pub struct NexusIscsiTarget {
     bdev_name: String, /* logically we might store a spdk_iscsi_tgt_node here but ATM the bdev name is all we actually need */
+    /// the initiator group of the target if it is not the default one
+    initiator_group: Option<c_int>,
+    /// the portal group the target is on
+    portal_group: c_int,
 }
 
 impl NexusIscsiTarget {
     /// Allocate iscsi device for the bdev and start it.
     /// When the function returns the iscsi target is ready for IO.
-    pub fn create(bdev_name: &str) -> Result<Self, NexusIscsiError> {
+    /// Only the initiators with the given IQNs can log in to it, or any
+    /// initiator if none are given.
+    pub async fn create(
+        bdev_name: &str,
+        initiators: &[String],
+        portal: &Portal,
+    ) -> Result<Self, NexusIscsiError> {
         let bdev = match Bdev::lookup_by_name(bdev_name) {
             None => {
                 return Err(NexusIscsiError::BdevNotFound {
@@ -42,15 +97,118 @@ impl NexusIscsiTarget {
             Some(bd) => bd,
         };
 
-        match share(bdev_name, &bdev, Side::Nexus) {
-            Ok(_) => Ok(Self {
+        let mut target = match share(bdev_name, &bdev, Side::Nexus) {
+            Ok(_) => Self {
                 bdev_name: bdev_name.to_string(),
-            }),
-            Err(e) => Err(NexusIscsiError::CreateTargetFailed {
-                dev: bdev_name.to_string(),
-                err: e.to_string(),
-            }),
+                initiator_group: None,
+                portal_group: ISCSI_PORTAL_GROUP_NEXUS,
+            },
+            Err(e) => {
+                return Err(NexusIscsiError::CreateTargetFailed {
+                    dev: bdev_name.to_string(),
+                    err: e.to_string(),
+                })
+            }
+        };
+
+        // no initiator can log in before the reactor gets to poll again,
+        // so the target is moved and restricted before any initiator can
+        // use it
+        let iqn = target_name(bdev_name);
+        match iscsi_portal::bind(&iqn, Side::Nexus, portal) {
+            Ok(Some(pg_idx)) => target.portal_group = pg_idx,
+            Ok(None) => (),
+            Err(source) => {
+                target.destroy().await;
+                return Err(NexusIscsiError::PortalFailed {
+                    dev: bdev_name.to_string(),
+                    source,
+                });
+            }
+        }
+
+        if !initiators.is_empty() {
+            if let Err(e) = target.allow_initiators(initiators) {
+                target.destroy().await;
+                return Err(e);
+            }
+        }
+
+        Ok(target)
+    }
+
+    /// replace the default initiator group of the target with one which
+    /// lists the given initiators only
+    fn allow_initiators(
+        &mut self,
+        initiators: &[String],
+    ) -> Result<(), NexusIscsiError> {
+        let dev = self.bdev_name.clone();
+        let err = || NexusIscsiError::AllowInitiatorsFailed {
+            dev: dev.clone(),
+            initiators: initiators.to_vec(),
+        };
+
+        let iqn = target_name(&self.bdev_name).into_cstring();
+        let tgt = unsafe { iscsi_find_tgt_node(iqn.as_ptr()) };
+        if tgt.is_null() {
+            return Err(err());
+        }
+
+        let mut ig_idx = INITIATOR_GROUP_IDX.with(|idx| {
+            idx.set(idx.get() + 1);
+            idx.get()
+        });
+
+        let names = initiators
+            .iter()
+            .map(|i| i.as_str().into_cstring())
+            .collect::<Vec<_>>();
+        let mut name_ptrs = names
+            .iter()
+            .map(|n| n.as_ptr() as *mut c_char)
+            .collect::<Vec<_>>();
+        let netmask = CString::new("ANY").unwrap();
+
+        if unsafe {
+            iscsi_init_grp_create_from_initiator_list(
+                ig_idx,
+                name_ptrs.len() as c_int,
+                name_ptrs.as_mut_ptr(),
+                1,
+                &mut (netmask.as_ptr() as *mut c_char) as *mut _,
+            )
+        } != 0
+        {
+            return Err(err());
         }
+        self.initiator_group = Some(ig_idx);
+
+        let mut pg_idx = self.portal_group;
+        let mut any_idx = ISCSI_INITIATOR_GROUP_ANY;
+        unsafe {
+            if iscsi_target_node_add_pg_ig_maps(
+                tgt,
+                &mut pg_idx,
+                &mut ig_idx,
+                1,
+            ) != 0
+                || iscsi_target_node_remove_pg_ig_maps(
+                    tgt,
+                    &mut pg_idx,
+                    &mut any_idx,
+                    1,
+                ) != 0
+            {
+                return Err(err());
+            }
+        }
+
+        info!(
+            "Allowed initiators {:?} for iscsi target of {}",
+            initiators, self.bdev_name
+        );
+        Ok(())
     }
 
     pub async fn destroy(self) {
@@ -61,10 +219,15 @@ impl NexusIscsiTarget {
                 error!("Failed to destroy iscsi frontend target, error {}", e)
             }
         }
+        if let Some(ig_idx) = self.initiator_group {
+            destroy_initiator_group(ig_idx);
+        }
+        iscsi_portal::release(&target_name(&self.bdev_name));
     }
 
     pub fn as_uri(&self) -> String {
-        create_uri(Side::Nexus, &target_name(&self.bdev_name))
+        let iqn = target_name(&self.bdev_name);
+        iscsi_portal::share_uri(&iqn, create_uri(Side::Nexus, &iqn))
     }
 }
 
@@ -79,3 +242,12 @@ impl fmt::Display for NexusIscsiTarget {
         write!(f, "{}", self.as_uri())
     }
 }
+
+fn destroy_initiator_group(ig_idx: c_int) {
+    unsafe {
+        let ig = iscsi_init_grp_unregister(ig_idx);
+        if !ig.is_null() {
+            iscsi_init_grp_destroy(ig);
+        }
+    }
+}
@@ -0,0 +1,950 @@
+//! Rotation of the key of a nexus published with encryption.
+//!
+//! The data of such a nexus is encrypted by the crypto bdev it is published
+//! through, see nexus_share. The key is rotated online by creating a second
+//! crypto bdev on the nexus with the new key, and re-encrypting the nexus in
+//! the background segment by segment, as a rebuild copies a child: each
+//! segment is read through the crypto bdev with the old key and written
+//! through the one with the new key.
+//!
+//! Meanwhile the IO of the published crypto bdev is intercepted, see
+//! bdev::interpose. The bdev layer splits the IO at the segment boundaries,
+//! and the reads and writes of a segment are sent to the crypto bdev of the
+//! key it is encrypted with. The IO of a segment being re-encrypted is held
+//! until it is done, and the segment is not re-encrypted before the IO in
+//! flight to it has completed. Once all of it is done, the IO goes to the
+//! crypto bdev with the new key until the nexus is unpublished, after which
+//! it is published with the new key.
+//!
+//! How far the re-encryption got is saved in the config every so often. A
+//! rotation which has been interrupted is resumed from there when the nexus
+//! is published with the old key again and rotated to the same new key.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    convert::TryFrom,
+    os::raw::c_void,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{channel::oneshot, stream::FuturesUnordered, StreamExt};
+use once_cell::sync::Lazy;
+
+use rpc::mayastor::KeyRotationProgressReply;
+
+use spdk_sys::{
+    spdk_bdev,
+    spdk_bdev_desc,
+    spdk_bdev_fn_table,
+    spdk_bdev_free_io,
+    spdk_bdev_get_io_channel,
+    spdk_bdev_io,
+    spdk_bdev_io_complete,
+    spdk_bdev_io_get_buf,
+    spdk_bdev_io_get_thread,
+    spdk_bdev_io_type,
+    spdk_bdev_module,
+    spdk_bdev_module_claim_bdev,
+    spdk_bdev_module_release_bdev,
+    spdk_bdev_readv_blocks,
+    spdk_bdev_writev_blocks,
+    spdk_for_each_thread,
+    spdk_get_thread,
+    spdk_io_channel,
+    spdk_put_io_channel,
+    spdk_thread_send_msg,
+    SPDK_BDEV_IO_STATUS_FAILED,
+    SPDK_BDEV_IO_STATUS_NOMEM,
+    SPDK_BDEV_IO_STATUS_SUCCESS,
+    SPDK_BDEV_IO_TYPE_READ,
+    SPDK_BDEV_IO_TYPE_WRITE,
+};
+
+use crate::{
+    bdev::{
+        interpose::{self, Interpose},
+        nexus::{
+            nexus_bdev::{Error, Nexus},
+            nexus_share::{create_crypto_bdev, destroy_crypto_bdev},
+        },
+    },
+    core::{sleep, Bdev, BdevHandle, Descriptor, RangeContext, Reactors},
+    rebuild::SEGMENT_SIZE,
+    subsys::Config,
+};
+
+/// number of segments re-encrypted at once
+const SEGMENT_TASKS: usize = 16;
+
+/// number of slots the state of the segments is kept in, which must be
+/// larger than the number of segments re-encrypted at once
+const SLOTS: u64 = 1024;
+
+/// how often the progress of a rotation is saved in the config
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// how often to check whether the IO in flight has completed
+const POLL_INTERVAL: Duration = Duration::from_micros(200);
+
+/// the segment is encrypted with the old key
+const OLD: u64 = 0;
+/// the segment is being re-encrypted
+const BUSY: u64 = 1;
+/// the segment is encrypted with the new key
+const NEW: u64 = 2;
+
+/// The block up to which the nexuses whose key rotation has not finished
+/// have been re-encrypted, by the name of the nexus.
+static CHECKPOINTS: Lazy<Mutex<HashMap<String, u64>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// the block up to which the nexus has been re-encrypted by a key rotation
+/// which has not finished, if any
+pub(crate) fn checkpoint(nexus: &str) -> Option<u64> {
+    CHECKPOINTS.lock().unwrap().get(nexus).copied()
+}
+
+/// record the checkpoint of a rotation of the nexus loaded from the config
+pub(crate) fn restore_checkpoint(nexus: &str, block: u64) {
+    info!("{}: key rotation to be resumed at block {}", nexus, block);
+    CHECKPOINTS.lock().unwrap().insert(nexus.to_string(), block);
+}
+
+thread_local! {
+    /// the IO channels to the crypto bdevs of the rotations, by the SPDK
+    /// thread and the descriptor they were taken with
+    static CHANNELS: RefCell<HashMap<(usize, usize), *mut spdk_io_channel>> =
+        RefCell::new(HashMap::new());
+}
+
+/// the IO channel of the current thread for the descriptor, which is kept
+/// until the rotation is done with
+fn channel(desc: *mut spdk_bdev_desc) -> *mut spdk_io_channel {
+    let key = (unsafe { spdk_get_thread() } as usize, desc as usize);
+    CHANNELS.with(|c| {
+        let mut channels = c.borrow_mut();
+        if let Some(ch) = channels.get(&key) {
+            return *ch;
+        }
+        let ch = unsafe { spdk_bdev_get_io_channel(desc) };
+        if !ch.is_null() {
+            channels.insert(key, ch);
+        }
+        ch
+    })
+}
+
+/// The IO channels to put on every thread once a rotation is done with.
+struct PutChannels {
+    descs: [usize; 2],
+    sender: oneshot::Sender<()>,
+}
+
+/// put the IO channels of the current thread for the descriptors
+extern "C" fn put_channels(ctx: *mut c_void) {
+    let descs = unsafe { &(*(ctx as *const PutChannels)).descs };
+    let thread = unsafe { spdk_get_thread() } as usize;
+    CHANNELS.with(|c| {
+        c.borrow_mut().retain(|(t, desc), ch| {
+            if *t == thread && descs.contains(desc) {
+                unsafe { spdk_put_io_channel(*ch) };
+                false
+            } else {
+                true
+            }
+        })
+    });
+}
+
+extern "C" fn channels_put(ctx: *mut c_void) {
+    let ctx = unsafe { Box::from_raw(ctx as *mut PutChannels) };
+    let _ = ctx.sender.send(());
+}
+
+/// An IO held off while its segments are re-encrypted.
+struct Held {
+    io: usize,
+    /// the segment whose re-encryption releases the IO
+    segment: u64,
+}
+
+/// The state of the segments of a nexus, which is shared between the
+/// rotation and the IO path.
+struct Segments {
+    /// size of a segment in blocks
+    blocks: u64,
+    /// the last segment of those with the same index modulo SLOTS which is
+    /// or has been re-encrypted, plus one and shifted left by two, with its
+    /// state in the low bits, 0 if there is none; the segments are
+    /// re-encrypted in order so that the segments before it are done
+    slots: Vec<AtomicU64>,
+    /// number of reads and writes in flight, by slot
+    in_flight: Vec<AtomicU32>,
+    /// all of the reads and writes are held while the IO submitted before
+    /// the rotation started drains
+    draining: AtomicBool,
+    held: Mutex<Vec<Held>>,
+    /// the descriptors of the crypto bdevs with the old and the new key
+    old: *mut spdk_bdev_desc,
+    new: *mut spdk_bdev_desc,
+}
+
+unsafe impl Send for Segments {}
+unsafe impl Sync for Segments {}
+
+impl Segments {
+    fn slot(segment: u64) -> usize {
+        (segment % SLOTS) as usize
+    }
+
+    /// the state of the segment
+    fn state(&self, segment: u64) -> u64 {
+        let value = self.slots[Self::slot(segment)].load(Ordering::SeqCst);
+        match (value >> 2).checked_sub(1) {
+            Some(last) if last > segment => NEW,
+            Some(last) if last == segment => value & 3,
+            _ => OLD,
+        }
+    }
+
+    fn set(&self, segment: u64, state: u64) {
+        self.slots[Self::slot(segment)]
+            .store(((segment + 1) << 2) | state, Ordering::SeqCst);
+    }
+
+    /// the segments before the first are encrypted with the new key
+    fn start_at(&self, first: u64) {
+        for slot in 0 .. std::cmp::min(first, SLOTS) {
+            self.set(slot + (first - 1 - slot) / SLOTS * SLOTS, NEW);
+        }
+    }
+
+    /// the first and the last segment of the IO, which is split at the
+    /// segment boundaries unless it was submitted before the rotation
+    fn range(&self, io: *mut spdk_bdev_io) -> (u64, u64) {
+        let args = unsafe { &(*io).u.bdev };
+        (
+            args.offset_blocks / self.blocks,
+            (args.offset_blocks + args.num_blocks - 1) / self.blocks,
+        )
+    }
+
+    /// the key the segments are encrypted with, None if one of them is
+    /// being re-encrypted or they are not encrypted with the same key
+    fn key(&self, (first, last): (u64, u64)) -> Option<bool> {
+        let new = self.state(first) == NEW;
+        for segment in first ..= last {
+            match self.state(segment) {
+                BUSY => return None,
+                state if (state == NEW) != new => return None,
+                _ => {}
+            }
+        }
+        Some(new)
+    }
+
+    /// count the IO to the segments as in flight, returns whether it goes
+    /// to the new key, or None if it is to be held
+    fn enter(&self, range: (u64, u64)) -> Option<bool> {
+        if self.draining.load(Ordering::SeqCst) {
+            return None;
+        }
+        for segment in range.0 ..= range.1 {
+            self.in_flight[Self::slot(segment)].fetch_add(1, Ordering::SeqCst);
+        }
+        let key = self.key(range);
+        if key.is_none() {
+            self.leave(range);
+        }
+        key
+    }
+
+    /// the IO to the segments is no longer in flight
+    fn leave(&self, range: (u64, u64)) {
+        for segment in range.0 ..= range.1 {
+            self.in_flight[Self::slot(segment)].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// hold the IO unless its segments have become available meanwhile,
+    /// until the last of them has been re-encrypted
+    fn hold(&self, io: *mut spdk_bdev_io, range: (u64, u64)) -> bool {
+        let mut held = self.held.lock().unwrap();
+        if self.draining.load(Ordering::SeqCst) || self.key(range).is_none() {
+            held.push(Held {
+                io: io as usize,
+                segment: range.1,
+            });
+            return true;
+        }
+        false
+    }
+
+    /// resubmit the IO held for the segment, or all of it, on the threads
+    /// it was submitted on
+    fn release(&self, segment: Option<u64>) {
+        let released = {
+            let mut held = self.held.lock().unwrap();
+            let (released, kept) = std::mem::take(&mut *held)
+                .into_iter()
+                .partition::<Vec<_>, _>(|h| {
+                    segment.map_or(true, |s| s == h.segment)
+                });
+            *held = kept;
+            released
+        };
+
+        for held in released {
+            let io = held.io as *mut spdk_bdev_io;
+            unsafe {
+                spdk_thread_send_msg(
+                    spdk_bdev_io_get_thread(io),
+                    Some(resubmit),
+                    io as *mut c_void,
+                );
+            }
+        }
+    }
+
+    fn submit(&self, io: *mut spdk_bdev_io) {
+        let range = self.range(io);
+        loop {
+            match self.enter(range) {
+                Some(_) if is_read(io) => unsafe {
+                    let len = (*io).u.bdev.num_blocks
+                        * u64::from((*(*io).bdev).blocklen);
+                    return spdk_bdev_io_get_buf(io, Some(read_buf), len);
+                },
+                Some(new) => return self.dispatch(io, new),
+                None if self.hold(io, range) => return,
+                None => {}
+            }
+        }
+    }
+
+    /// submit the IO, which has been counted as in flight, to the crypto
+    /// bdev with the key of its segments
+    fn dispatch(&self, io: *mut spdk_bdev_io, new: bool) {
+        let desc = if new { self.new } else { self.old };
+        let ch = channel(desc);
+        let rc = if ch.is_null() {
+            -libc::ENOMEM
+        } else {
+            unsafe {
+                let args = &(*io).u.bdev;
+                if is_read(io) {
+                    spdk_bdev_readv_blocks(
+                        desc,
+                        ch,
+                        args.iovs,
+                        args.iovcnt,
+                        args.offset_blocks,
+                        args.num_blocks,
+                        Some(child_done),
+                        io as *mut c_void,
+                    )
+                } else {
+                    spdk_bdev_writev_blocks(
+                        desc,
+                        ch,
+                        args.iovs,
+                        args.iovcnt,
+                        args.offset_blocks,
+                        args.num_blocks,
+                        Some(child_done),
+                        io as *mut c_void,
+                    )
+                }
+            }
+        };
+
+        if rc != 0 {
+            self.leave(self.range(io));
+            let status = if rc == -libc::ENOMEM {
+                SPDK_BDEV_IO_STATUS_NOMEM
+            } else {
+                SPDK_BDEV_IO_STATUS_FAILED
+            };
+            unsafe { spdk_bdev_io_complete(io, status) };
+        }
+    }
+
+    /// number of reads and writes in flight
+    fn in_flight(&self) -> u64 {
+        self.in_flight
+            .iter()
+            .map(|c| u64::from(c.load(Ordering::SeqCst)))
+            .sum()
+    }
+}
+
+fn is_read(io: *mut spdk_bdev_io) -> bool {
+    unsafe { (*io).type_ as spdk_bdev_io_type == SPDK_BDEV_IO_TYPE_READ }
+}
+
+/// the segments of the bdev whose key is being rotated, if any
+fn segments<R>(
+    bdev: *mut spdk_bdev,
+    f: impl FnOnce(&Segments) -> R,
+) -> Option<R> {
+    interpose::with(bdev, |rotating: &Rotating| f(&rotating.0))
+}
+
+extern "C" fn resubmit(ctx: *mut c_void) {
+    let io = ctx as *mut spdk_bdev_io;
+    if segments(unsafe { (*io).bdev }, |s| s.submit(io)).is_none() {
+        unsafe { spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_FAILED) };
+    }
+}
+
+extern "C" fn read_buf(
+    _ch: *mut spdk_io_channel,
+    io: *mut spdk_bdev_io,
+    success: bool,
+) {
+    let bdev = unsafe { (*io).bdev };
+    let done = segments(bdev, |s| {
+        let range = s.range(io);
+        if success {
+            // the segments can not have been re-encrypted meanwhile as the
+            // read is in flight
+            s.dispatch(io, s.state(range.0) == NEW);
+        } else {
+            s.leave(range);
+            unsafe { spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_FAILED) };
+        }
+    });
+
+    if done.is_none() {
+        unsafe { spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_FAILED) };
+    }
+}
+
+extern "C" fn child_done(
+    child: *mut spdk_bdev_io,
+    success: bool,
+    ctx: *mut c_void,
+) {
+    unsafe { spdk_bdev_free_io(child) };
+
+    let io = ctx as *mut spdk_bdev_io;
+    segments(unsafe { (*io).bdev }, |s| s.leave(s.range(io)));
+
+    let status = if success {
+        SPDK_BDEV_IO_STATUS_SUCCESS
+    } else {
+        SPDK_BDEV_IO_STATUS_FAILED
+    };
+    unsafe { spdk_bdev_io_complete(io, status) };
+}
+
+/// The published crypto bdev of a nexus whose key is being rotated.
+struct Rotating(Arc<Segments>);
+
+impl Interpose for Rotating {
+    fn submit_request(
+        &self,
+        module: &'static spdk_bdev_fn_table,
+        ch: *mut spdk_io_channel,
+        io: *mut spdk_bdev_io,
+    ) {
+        let segments = &self.0;
+        let io_type = unsafe { (*io).type_ } as spdk_bdev_io_type;
+
+        // the IO submitted by the rotation itself goes to the old key
+        if unsafe { (*io).internal.desc } == segments.old
+            || (io_type != SPDK_BDEV_IO_TYPE_READ
+                && io_type != SPDK_BDEV_IO_TYPE_WRITE)
+        {
+            return interpose::pass_on(module, ch, io);
+        }
+
+        segments.submit(io);
+    }
+}
+
+/// claim the bdev for the module again unless it is claimed
+fn claim(bdev: &Bdev, module: *mut spdk_bdev_module) {
+    unsafe {
+        if !module.is_null()
+            && (*bdev.as_ptr()).internal.claim_module.is_null()
+            && spdk_bdev_module_claim_bdev(
+                bdev.as_ptr(),
+                std::ptr::null_mut(),
+                module,
+            ) != 0
+        {
+            error!("Failed to claim {} again", bdev.name());
+        }
+    }
+}
+
+/// Take the claim of the bdev out of the way while f runs, and claim the
+/// bdev for the module again after unless f has left it claimed.
+fn step_around_claim<R>(
+    bdev: &Bdev,
+    module: *mut spdk_bdev_module,
+    f: impl FnOnce() -> R,
+) -> R {
+    unsafe {
+        if !(*bdev.as_ptr()).internal.claim_module.is_null() {
+            spdk_bdev_module_release_bdev(bdev.as_ptr());
+        }
+    }
+    let result = f();
+    claim(bdev, module);
+    result
+}
+
+/// A rotation of the key of a nexus, which re-encrypts the nexus in the
+/// background and serves its IO until the nexus is unpublished.
+pub(crate) struct KeyRotation {
+    nexus: String,
+    /// the published crypto bdev with the old key
+    bdev: Bdev,
+    /// the crypto bdev with the new key
+    new_bdev: Bdev,
+    segments: Arc<Segments>,
+    /// the descriptors of the crypto bdevs with the old and the new key,
+    /// which are closed when the rotation is destroyed
+    descriptors: Mutex<Option<(Arc<Descriptor>, Arc<Descriptor>)>>,
+    /// the module which claimed the nexus, the crypto module
+    claim: *mut spdk_bdev_module,
+    /// the optimal IO boundary of the published bdev before the rotation
+    boundary: (u32, bool),
+    /// number of segments of the nexus
+    total: u64,
+    /// number of segments re-encrypted
+    done: AtomicU64,
+    stopped: AtomicBool,
+    running: AtomicBool,
+    /// why the re-encryption failed
+    error: Mutex<Option<String>>,
+}
+
+impl KeyRotation {
+    /// progress of the rotation in % (0-100)
+    pub(crate) fn progress(&self) -> u32 {
+        (self.done.load(Ordering::Relaxed) * 100 / self.total) as u32
+    }
+
+    /// all of the nexus is encrypted with the new key
+    fn completed(&self) -> bool {
+        self.done.load(Ordering::Relaxed) == self.total
+    }
+
+    /// state of the rotation as reported over gRPC
+    fn state(&self) -> &'static str {
+        if self.running.load(Ordering::SeqCst) {
+            "running"
+        } else if self.completed() {
+            "completed"
+        } else if self.error.lock().unwrap().is_some() {
+            "failed"
+        } else {
+            "stopped"
+        }
+    }
+
+    /// handles to the crypto bdevs with the old and the new key
+    fn handles(&self) -> Result<(BdevHandle, BdevHandle), String> {
+        let descriptors = self.descriptors.lock().unwrap().clone();
+        let (old, new) = descriptors.ok_or("the rotation is destroyed")?;
+        let old = BdevHandle::try_from(old).map_err(|e| e.to_string())?;
+        let new = BdevHandle::try_from(new).map_err(|e| e.to_string())?;
+        Ok((old, new))
+    }
+
+    /// re-encrypt the segment with the key of the crypto bdev written to,
+    /// which is the old one when a segment is rolled back
+    async fn reencrypt(
+        &self,
+        from: &BdevHandle,
+        to: &BdevHandle,
+        segment: u64,
+        rollback: bool,
+    ) -> Result<u64, String> {
+        let segments = &self.segments;
+        segments.set(segment, BUSY);
+        while segments.in_flight[Segments::slot(segment)].load(Ordering::SeqCst)
+            > 0
+        {
+            sleep(POLL_INTERVAL).await;
+        }
+
+        let block_len = u64::from(self.bdev.block_len());
+        let start = segment * segments.blocks;
+        let len =
+            std::cmp::min(segments.blocks, self.bdev.num_blocks() - start);
+        let result =
+            async {
+                let mut buf = from
+                    .dma_malloc((len * block_len) as usize)
+                    .map_err(|e| e.to_string())?;
+                from.read_at(start * block_len, &mut buf).await.map_err(
+                    |e| format!("failed to read block {}: {}", start, e),
+                )?;
+                to.write_at(start * block_len, &buf).await.map_err(|e| {
+                    format!("failed to write block {}: {}", start, e)
+                })?;
+                Ok(segment)
+            }
+            .await;
+
+        let state = if result.is_ok() != rollback { NEW } else { OLD };
+        segments.set(segment, state);
+        segments.release(Some(segment));
+        result
+    }
+
+    /// save how far the rotation got in the config, None once it is done
+    async fn save(&self, checkpoint: Option<u64>) {
+        {
+            let mut checkpoints = CHECKPOINTS.lock().unwrap();
+            match checkpoint {
+                Some(segment) => checkpoints
+                    .insert(self.nexus.clone(), segment * self.segments.blocks),
+                None => checkpoints.remove(&self.nexus),
+            };
+        }
+        if let Err(e) = Config::export_config().await {
+            error!("{}: failed to save the key rotation: {}", self.nexus, e);
+        }
+    }
+
+    /// re-encrypt the segments from the first one on, in order
+    async fn run(self: Arc<Self>, first: u64) -> Result<(), String> {
+        let result = match self.handles() {
+            Ok((old, new)) => self.clone().reencrypt_all(old, new, first).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            if !self.stopped.load(Ordering::SeqCst) {
+                *self.error.lock().unwrap() = Some(e.clone());
+            }
+        }
+        self.running.store(false, Ordering::SeqCst);
+        result
+    }
+
+    async fn reencrypt_all(
+        self: Arc<Self>,
+        old: BdevHandle,
+        new: BdevHandle,
+        first: u64,
+    ) -> Result<(), String> {
+        let (old, new) = (Rc::new(old), Rc::new(new));
+        let mut tasks = FuturesUnordered::new();
+        let mut next = first;
+        let mut checkpoint = first;
+        let mut done = BTreeSet::new();
+        let mut saved = Instant::now();
+        let mut result = Ok(());
+
+        loop {
+            while tasks.len() < SEGMENT_TASKS
+                && next < self.total
+                && result.is_ok()
+                && !self.stopped.load(Ordering::SeqCst)
+            {
+                let (job, old, new) = (self.clone(), old.clone(), new.clone());
+                let segment = next;
+                tasks.push(async move {
+                    job.reencrypt(&old, &new, segment, false).await
+                });
+                next += 1;
+            }
+
+            match tasks.next().await {
+                Some(Ok(segment)) => {
+                    done.insert(segment);
+                    while done.remove(&checkpoint) {
+                        checkpoint += 1;
+                    }
+                    self.done.fetch_add(1, Ordering::Relaxed);
+                    if saved.elapsed() >= CHECKPOINT_INTERVAL {
+                        self.save(Some(checkpoint)).await;
+                        saved = Instant::now();
+                    }
+                }
+                Some(Err(e)) => {
+                    error!("{}: key rotation failed: {}", self.nexus, e);
+                    result = Err(e);
+                }
+                None => break,
+            }
+        }
+
+        if result.is_ok() && self.completed() {
+            info!("{}: key rotation completed", self.nexus);
+            self.save(None).await;
+            return Ok(());
+        }
+
+        // the checkpoint is where the rotation resumes, so the segments
+        // after it which have been re-encrypted before one of them failed
+        // are put back to the old key
+        for segment in done.into_iter().rev() {
+            match self.reencrypt(&new, &old, segment, true).await {
+                Ok(_) => {
+                    self.done.fetch_sub(1, Ordering::Relaxed);
+                }
+                Err(e) => error!(
+                    "{}: segment {} is left with the new key: {}",
+                    self.nexus, segment, e
+                ),
+            }
+        }
+
+        self.save(Some(checkpoint)).await;
+        result.and(Err("the key rotation has been stopped".to_string()))
+    }
+
+    /// stop re-encrypting, the rotation resumes from its checkpoint when
+    /// started again
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Stop the rotation and remove the crypto bdev with the new key, as
+    /// the nexus is being unpublished.
+    pub(crate) async fn destroy(&self, nexus: &Bdev) {
+        self.stop();
+        while self.running.load(Ordering::SeqCst)
+            || self.segments.in_flight() > 0
+        {
+            sleep(POLL_INTERVAL).await;
+        }
+
+        if segments(self.bdev.as_ptr(), |_| ()).is_some() {
+            interpose::forget(&self.bdev);
+        }
+        unsafe {
+            let ptr = self.bdev.as_ptr();
+            (*ptr).optimal_io_boundary = self.boundary.0;
+            (*ptr).split_on_optimal_io_boundary = self.boundary.1;
+        }
+
+        let (s, r) = oneshot::channel::<()>();
+        let ctx = Box::into_raw(Box::new(PutChannels {
+            descs: [self.segments.old as usize, self.segments.new as usize],
+            sender: s,
+        }));
+        unsafe {
+            spdk_for_each_thread(
+                Some(put_channels),
+                ctx as *mut c_void,
+                Some(channels_put),
+            );
+        }
+        r.await.expect("channels put sender is gone");
+        self.descriptors.lock().unwrap().take();
+
+        if let Err(e) = destroy_crypto_bdev(&self.new_bdev).await {
+            let name = self.new_bdev.name();
+            error!("{}: failed to destroy {}: {}", self.nexus, name, e);
+        }
+        // the crypto bdev with the new key has taken the claim of the
+        // crypto bdev with the old key on the nexus along with it
+        claim(nexus, self.claim);
+    }
+}
+
+impl Nexus {
+    /// Rotate the key of the nexus, which must be published with
+    /// encryption, to the given key. Returns a channel which the outcome of
+    /// the rotation is sent to once it has re-encrypted all of the nexus.
+    pub async fn start_key_rotation(
+        &mut self,
+        key: &str,
+    ) -> Result<oneshot::Receiver<Result<(), String>>, Error> {
+        let cannot = |reason: &str| Error::CannotRotateKey {
+            name: self.name.clone(),
+            reason: reason.to_string(),
+        };
+
+        if key.len() != 16 {
+            return Err(Error::InvalidKey {});
+        }
+        let share = match self.share_handle.as_ref() {
+            Some(share) if *share != self.name => share.clone(),
+            _ => return Err(cannot("the nexus is not published encrypted")),
+        };
+        if self.key_rotation.is_some() {
+            return Err(cannot(
+                "the key has been rotated since the nexus was published",
+            ));
+        }
+        let bdev = Bdev::lookup_by_name(&share)
+            .ok_or_else(|| cannot("the published bdev is gone"))?;
+        if interpose::module(bdev.as_ptr()).is_some() {
+            return Err(cannot("the IO of the published bdev is intercepted"));
+        }
+
+        let first = checkpoint(&self.name)
+            .map(|block| block * u64::from(bdev.block_len()) / SEGMENT_SIZE)
+            .unwrap_or_default();
+        let rotation = self.create_key_rotation(&bdev, key, first).await?;
+        info!(
+            "{}: rotating the key from segment {} of {}",
+            self.name, first, rotation.total
+        );
+        self.key_rotation = Some(rotation.clone());
+
+        let (s, r) = oneshot::channel();
+        rotation.running.store(true, Ordering::SeqCst);
+        Reactors::current().send_future(async move {
+            let _ = s.send(rotation.run(first).await);
+        });
+        Ok(r)
+    }
+
+    /// create the crypto bdev with the new key, intercept the IO of the
+    /// published bdev and wait for the IO submitted before to complete
+    async fn create_key_rotation(
+        &self,
+        bdev: &Bdev,
+        key: &str,
+        first: u64,
+    ) -> Result<Arc<KeyRotation>, Error> {
+        let failed = |reason: String| Error::RotateKey {
+            name: self.name.clone(),
+            reason,
+        };
+
+        let module = unsafe { (*self.bdev.as_ptr()).internal.claim_module };
+        let name = format!("{}-rotated", bdev.name());
+        let created = step_around_claim(&self.bdev, module, || {
+            create_crypto_bdev(&self.name, &name, key)
+        });
+        if let Err(e) = created {
+            return Err(failed(format!("failed to create {}: {}", name, e)));
+        }
+        let new_bdev = Bdev::lookup_by_name(&name)
+            .ok_or_else(|| failed(format!("{} is gone", name)))?;
+
+        let target = unsafe { (*bdev.as_ptr()).internal.claim_module };
+        let descriptors = step_around_claim(bdev, target, || bdev.open(true))
+            .and_then(|old| Ok((old, new_bdev.open(true)?)));
+        let (old, new) = match descriptors {
+            Ok((old, new)) => (Arc::new(old), Arc::new(new)),
+            Err(e) => {
+                if let Err(e) = destroy_crypto_bdev(&new_bdev).await {
+                    error!("{}: failed to destroy {}: {}", self.name, name, e);
+                }
+                claim(&self.bdev, module);
+                return Err(failed(e.to_string()));
+            }
+        };
+
+        let blocks = SEGMENT_SIZE / u64::from(bdev.block_len());
+        let segments = Arc::new(Segments {
+            blocks,
+            slots: (0 .. SLOTS).map(|_| AtomicU64::new(0)).collect(),
+            in_flight: (0 .. SLOTS).map(|_| AtomicU32::new(0)).collect(),
+            draining: AtomicBool::new(true),
+            held: Mutex::new(Vec::new()),
+            old: old.as_ptr(),
+            new: new.as_ptr(),
+        });
+        segments.start_at(first);
+
+        let ptr = bdev.as_ptr();
+        let rotation = Arc::new(KeyRotation {
+            nexus: self.name.clone(),
+            bdev: bdev.clone(),
+            new_bdev,
+            segments: segments.clone(),
+            descriptors: Mutex::new(Some((old.clone(), new))),
+            claim: module,
+            boundary: unsafe {
+                (
+                    (*ptr).optimal_io_boundary,
+                    (*ptr).split_on_optimal_io_boundary,
+                )
+            },
+            total: (bdev.num_blocks() + blocks - 1) / blocks,
+            done: AtomicU64::new(first),
+            stopped: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            error: Mutex::new(None),
+        });
+
+        // the writes submitted before are waited for by locking all of the
+        // bdev, which holds off the writes submitted from now on, and the
+        // reads by waiting for the nexus to have no IO in flight while the
+        // reads submitted from now on are held
+        let handle = match BdevHandle::try_from(old) {
+            Ok(handle) => handle,
+            Err(e) => {
+                rotation.destroy(&self.bdev).await;
+                return Err(failed(e.to_string()));
+            }
+        };
+        let mut ctx = RangeContext::new(0, bdev.num_blocks());
+        if let Err(e) =
+            handle.desc.lock_lba_range(&mut ctx, &handle.channel).await
+        {
+            drop(handle);
+            rotation.destroy(&self.bdev).await;
+            return Err(failed(format!("failed to quiesce: {}", e)));
+        }
+
+        interpose::install(bdev, Rotating(segments.clone()), |_| {});
+        unsafe {
+            (*ptr).optimal_io_boundary = blocks as u32;
+            (*ptr).split_on_optimal_io_boundary = true;
+        }
+
+        while self.io_stats().await.map_or(0, |s| s.queue_depth) > 0 {
+            sleep(POLL_INTERVAL).await;
+        }
+        segments.draining.store(false, Ordering::SeqCst);
+        segments.release(None);
+
+        if let Err(e) = handle
+            .desc
+            .unlock_lba_range(&mut ctx, &handle.channel)
+            .await
+        {
+            error!("{}: failed to resume the writes: {}", self.name, e);
+        }
+        Ok(rotation)
+    }
+
+    /// progress and state of the key rotation
+    pub fn get_key_rotation_progress(&self) -> KeyRotationProgressReply {
+        match self.key_rotation.as_ref() {
+            Some(rotation) => KeyRotationProgressReply {
+                progress: rotation.progress(),
+                state: rotation.state().to_string(),
+            },
+            None => KeyRotationProgressReply {
+                progress: 0,
+                state: "none".to_string(),
+            },
+        }
+    }
+
+    /// Stop the key rotation of the nexus, which resumes from where it has
+    /// got to when started again.
+    pub fn stop_key_rotation(&self) -> Result<(), Error> {
+        match self.key_rotation.as_ref() {
+            Some(rotation) if !rotation.completed() => {
+                rotation.stop();
+                Ok(())
+            }
+            _ => Err(Error::CannotRotateKey {
+                name: self.name.clone(),
+                reason: "the key is not being rotated".to_string(),
+            }),
+        }
+    }
+}
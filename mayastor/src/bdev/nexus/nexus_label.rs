@@ -77,6 +77,7 @@ use crate::{
 };
 
 #[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
 pub enum LabelError {
     #[snafu(display("{}", source))]
     NexusChildError { source: ChildError },
@@ -131,6 +132,8 @@ pub enum LabelError {
     BackupLocation {},
     #[snafu(display("GPT partition table location is incorrect"))]
     PartitionTableLocation {},
+    #[snafu(display("The v2 label of {} bytes does not fit its slot", size))]
+    LabelV2Size { size: u64 },
 }
 
 struct LabelData {
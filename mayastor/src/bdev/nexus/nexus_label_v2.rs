@@ -0,0 +1,346 @@
+//! Version 2 of the label of a nexus.
+//!
+//! Version 1 of the label is the GPT label of the children, which tells
+//! where the data of the nexus starts but not which children the nexus is
+//! made of. Version 2 adds a record to the metadata partition, right before
+//! the intent log, with the GUID of the GPT label, a generation and the URIs
+//! of the children of the nexus, protected by a CRC32C. There are two slots
+//! for the record and the record of a generation is written to the slot of
+//! its parity, such that the record of the previous generation is left
+//! intact when mayastor stops while writing, and the valid record of the
+//! newest generation of a child is the one that counts.
+//!
+//! The record is written when the nexus is opened and when a child is added
+//! or removed. The children of a nexus labelled by an older mayastor have no
+//! record yet, they are given one when the nexus is opened, leaving their
+//! GPT label as is. A child of which the record is older than the newest one
+//! of the nexus, and which is not among the children listed there, has been
+//! removed from the nexus meanwhile and is rebuilt before it is used.
+
+use bincode::{deserialize, serialize, serialized_size};
+use crc::crc32;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use crate::{
+    bdev::nexus::{
+        nexus_bdev::{Nexus, NexusState},
+        nexus_child::NexusChild,
+        nexus_intent_log,
+        nexus_label::{
+            Aligned,
+            GptGuid,
+            LabelError,
+            NexusChildError,
+            NexusLabel,
+            ReadAlloc,
+            ReadError,
+            SerializeError,
+            WriteAlloc,
+            WriteError,
+        },
+    },
+    core::DmaBuf,
+};
+
+/// bytes reserved for the slots of the record at the end of the metadata
+/// partition, before the intent log
+pub(crate) const LABEL_V2_SIZE: u64 = 2 * SLOT_SIZE;
+/// bytes reserved for each slot
+const SLOT_SIZE: u64 = 4096;
+/// "MayaLBL" followed by the version
+const SIGNATURE: [u8; 8] = [0x4d, 0x61, 0x79, 0x61, 0x4c, 0x42, 0x4c, 0x02];
+
+/// the record of version 2 of the label
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelV2 {
+    signature: [u8; 8],
+    /// CRC32C of the record, with this field 0
+    checksum: u32,
+    /// incremented whenever the record is written
+    pub generation: u64,
+    /// the disk GUID of the GPT label the record belongs to
+    pub guid: GptGuid,
+    /// first block of the data partition
+    pub data_start: u64,
+    /// URIs of the children of the nexus
+    pub children: Vec<String>,
+}
+
+/// number of blocks of a slot
+fn slot_blocks(block_len: u64) -> u64 {
+    Aligned::get_blocks(SLOT_SIZE, block_len)
+}
+
+/// offset in bytes of the slot on a child with the given start of the data
+/// partition, which immediately follows the metadata partition
+fn slot_offset(data_start: u64, block_len: u64, slot: u64) -> u64 {
+    let first = data_start
+        - Aligned::get_blocks(nexus_intent_log::LOG_SIZE, block_len)
+        - Aligned::get_blocks(LABEL_V2_SIZE, block_len);
+    (first + slot * slot_blocks(block_len)) * block_len
+}
+
+impl LabelV2 {
+    fn new(generation: u64, label: &NexusLabel, children: Vec<String>) -> Self {
+        let mut record = Self {
+            signature: SIGNATURE,
+            checksum: 0,
+            generation,
+            guid: label.primary.guid,
+            data_start: label.offset(),
+            children,
+        };
+        record.checksum = record.checksum();
+        record
+    }
+
+    fn checksum(&self) -> u32 {
+        let bytes = serialize(&Self {
+            checksum: 0,
+            ..self.clone()
+        })
+        .unwrap();
+        crc32::checksum_castagnoli(&bytes)
+    }
+
+    /// the record of the next generation, with the given children
+    fn next(&self, children: Vec<String>) -> Self {
+        let mut record = Self {
+            checksum: 0,
+            generation: self.generation + 1,
+            children,
+            ..self.clone()
+        };
+        record.checksum = record.checksum();
+        record
+    }
+
+    fn encode(
+        &self,
+        block_len: u64,
+        alignment: u8,
+    ) -> Result<DmaBuf, LabelError> {
+        let size = serialized_size(self).context(SerializeError {})?;
+        if size > SLOT_SIZE {
+            return Err(LabelError::LabelV2Size {
+                size,
+            });
+        }
+        let bytes = serialize(self).context(SerializeError {})?;
+
+        let mut buf = DmaBuf::new(
+            (slot_blocks(block_len) * block_len) as usize,
+            alignment,
+        )
+        .context(WriteAlloc {
+            name: String::from("v2 label"),
+        })?;
+        buf.fill(0);
+        buf.as_mut_slice()[.. bytes.len()].copy_from_slice(&bytes);
+        Ok(buf)
+    }
+
+    /// the record read from a slot, if it holds a valid one
+    fn decode(buf: &DmaBuf) -> Option<Self> {
+        let record: Self = deserialize(buf.as_slice()).ok()?;
+        if record.signature != SIGNATURE || record.checksum() != record.checksum
+        {
+            return None;
+        }
+        Some(record)
+    }
+
+    /// whether the record is of the given label and lists the children
+    fn matches(&self, label: &NexusLabel, children: &[String]) -> bool {
+        self.guid == label.primary.guid
+            && self.data_start == label.offset()
+            && self.children == children
+    }
+}
+
+impl NexusChild {
+    /// Read the v2 label of this child, the valid record of the given label
+    /// of the newest generation, if any.
+    pub async fn probe_label_v2(
+        &self,
+        label: &NexusLabel,
+    ) -> Result<Option<LabelV2>, LabelError> {
+        let (bdev, desc) = self.get_dev().context(NexusChildError {})?;
+        let block_len = u64::from(bdev.block_len());
+        let data_start = label.offset();
+
+        let mut newest: Option<LabelV2> = None;
+        for slot in 0 .. 2 {
+            let mut buf = desc
+                .dma_malloc((slot_blocks(block_len) * block_len) as usize)
+                .context(ReadAlloc {
+                    name: String::from("v2 label"),
+                })?;
+            self.read_at(slot_offset(data_start, block_len, slot), &mut buf)
+                .await
+                .context(ReadError {
+                    name: String::from("v2 label"),
+                })?;
+            let record = match LabelV2::decode(&buf) {
+                Some(record)
+                    if record.generation % 2 == slot
+                        && record.guid == label.primary.guid
+                        && record.data_start == data_start =>
+                {
+                    record
+                }
+                _ => continue,
+            };
+            if newest
+                .as_ref()
+                .map_or(true, |n| record.generation > n.generation)
+            {
+                newest = Some(record);
+            }
+        }
+        Ok(newest)
+    }
+}
+
+impl Nexus {
+    /// Bring the v2 labels of the children in line with the GPT label and the
+    /// children of the nexus, the children without one are upgraded to it.
+    /// When the nexus is being opened, the children which have been removed
+    /// from it since their record was written are marked out of sync.
+    pub(crate) async fn sync_label_v2(
+        &mut self,
+        label: &NexusLabel,
+    ) -> Result<(), LabelError> {
+        let mut records = Vec::new();
+        for child in self.children.iter().filter(|c| c.can_rw()) {
+            let record = child.probe_label_v2(label).await?;
+            records.push((child.name.clone(), record));
+        }
+        let newest = records
+            .iter()
+            .filter_map(|(_, r)| r.as_ref())
+            .max_by_key(|r| r.generation)
+            .cloned();
+
+        if let Some(newest) = newest.as_ref() {
+            if self.state == NexusState::Init {
+                self.mark_removed_children(newest, &records);
+            }
+        }
+
+        let children = self
+            .children
+            .iter()
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+        let generation = newest.as_ref().map_or(0, |r| r.generation);
+        if records.iter().all(|(_, r)| {
+            r.as_ref().map_or(false, |r| {
+                r.generation == generation && r.matches(label, &children)
+            })
+        }) {
+            self.label_v2 = newest;
+            return Ok(());
+        }
+
+        for (name, _) in records.iter().filter(|(_, r)| r.is_none()) {
+            info!("{}: {}: upgrading the disk label to v2", self.name, name);
+        }
+        let record = match newest {
+            Some(newest) => LabelV2 {
+                guid: label.primary.guid,
+                data_start: label.offset(),
+                ..newest
+            }
+            .next(children),
+            None => LabelV2::new(1, label, children),
+        };
+        self.write_label_v2(record).await
+    }
+
+    /// Mark the children out of sync which are not among the children of the
+    /// newest record, of which the record is older, so they are rebuilt.
+    fn mark_removed_children(
+        &mut self,
+        newest: &LabelV2,
+        records: &[(String, Option<LabelV2>)],
+    ) {
+        let removed = records.iter().filter(|(name, record)| {
+            record.as_ref().map_or(false, |r| {
+                r.generation < newest.generation
+                    && !newest.children.contains(name)
+            })
+        });
+        for (name, _) in removed {
+            warn!(
+                "{}: {}: the child was removed from the nexus at generation \
+                 {} of the disk label, it is out of sync",
+                self.name, name, newest.generation
+            );
+            if let Some(child) =
+                self.children.iter_mut().find(|c| &c.name == name)
+            {
+                child.out_of_sync(true);
+            }
+        }
+    }
+
+    /// Write the record of the next generation of the v2 label, listing the
+    /// children of the nexus as they are now.
+    pub(crate) async fn update_label_v2(&mut self) {
+        let record = match self.label_v2.as_ref() {
+            Some(current) => current
+                .next(self.children.iter().map(|c| c.name.clone()).collect()),
+            None => return,
+        };
+        if let Err(e) = self.write_label_v2(record).await {
+            warn!("{}: failed to update the disk label: {}", self.name, e);
+        }
+    }
+
+    /// write the record to the slot of its generation on all the children
+    /// which can take IO
+    async fn write_label_v2(
+        &mut self,
+        record: LabelV2,
+    ) -> Result<(), LabelError> {
+        let block_len = u64::from(self.bdev.block_len());
+        let buf = record.encode(block_len, self.bdev.alignment())?;
+        let offset =
+            slot_offset(record.data_start, block_len, record.generation % 2);
+
+        let futures = self
+            .children
+            .iter()
+            .filter(|c| c.can_rw())
+            .map(|c| c.write_at(offset, &buf));
+        for result in join_all(futures).await {
+            result.context(WriteError {})?;
+        }
+
+        debug!(
+            "{}: disk label v2 of generation {} written",
+            self.name, record.generation
+        );
+        self.label_v2 = Some(record);
+        Ok(())
+    }
+
+    /// Rebuild the children which were marked out of sync when the nexus was
+    /// opened.
+    pub(crate) async fn rebuild_removed_children(&mut self) {
+        let names = self
+            .children
+            .iter()
+            .filter(|c| c.lagging())
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+        for name in &names {
+            if let Err(e) = self.start_rebuild(name).await {
+                error!("{}: failed to rebuild {}: {}", self.name, name, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Latency watchdog of the writes to the children of a nexus.
+//!
+//! A write to the nexus completes once every child it was dispatched to has
+//! completed it, hence a single slow child makes all writes slow. The time
+//! each write took on a child is taken when it completes, and the writes
+//! which took longer than the threshold are counted per child. A child with
+//! too many of them within the window is degraded, which takes it out of the
+//! IO channels, as long as another child stays healthy. The writes it misses
+//! meanwhile are marked in the intent log, which is not cleaned while a
+//! child lags. Once the delay has passed, the child is caught up by a
+//! rebuild of the marked regions, or by a full rebuild if there is no intent
+//! log, and is healthy again once the rebuild is done.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use spdk_sys::{spdk_get_ticks, spdk_get_ticks_hz};
+
+use crate::{
+    bdev::nexus::{
+        nexus_bdev::{nexus_lookup, Nexus, NexusStatus},
+        nexus_channel::DREvent,
+        nexus_child::ChildStatus,
+    },
+    core::{sleep, Reactors},
+    rebuild::RebuildJob,
+    subsys::{events, Config},
+};
+
+/// the writes to a child which took longer than the threshold
+#[derive(Debug, Default)]
+pub(crate) struct SlowWrites {
+    /// ticks at which the current window started
+    window_start: AtomicU64,
+    /// number of slow writes in the current window
+    count: AtomicU32,
+    /// the child has been reported to the master core
+    reported: AtomicBool,
+}
+
+fn ms_to_ticks(ms: u64) -> u64 {
+    ms * unsafe { spdk_get_ticks_hz() } / 1000
+}
+
+impl SlowWrites {
+    /// Count a write which was submitted at the given ticks, returns true if
+    /// the child is to be degraded, once only until the count is reset.
+    pub(crate) fn record(&self, submitted: u64) -> bool {
+        let opts = Config::get().nexus_opts.child_latency;
+        if opts.threshold_ms == 0 {
+            return false;
+        }
+        let now = unsafe { spdk_get_ticks() };
+        if now.saturating_sub(submitted) <= ms_to_ticks(opts.threshold_ms) {
+            return false;
+        }
+
+        let start = self.window_start.load(Ordering::Relaxed);
+        if now.saturating_sub(start) > ms_to_ticks(opts.window_ms) {
+            self.window_start.store(now, Ordering::Relaxed);
+            self.count.store(0, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed) + 1 >= opts.max_slow_writes
+            && !self.reported.swap(true, Ordering::Relaxed)
+    }
+
+    /// start counting afresh
+    pub(crate) fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.reported.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Nexus {
+    /// Have the child degraded on the master core, as its writes are too
+    /// slow.
+    pub(crate) fn child_too_slow(&self, child: &str) {
+        let nexus = self.name.clone();
+        let child = child.to_owned();
+        Reactors::master().send_future(async move {
+            if let Some(nexus) = nexus_lookup(&nexus) {
+                nexus.degrade_slow_child(&child).await;
+            }
+        });
+    }
+
+    /// Degrade a child which is too slow, unless it is the only healthy one,
+    /// and catch it up once the delay has passed.
+    async fn degrade_slow_child(&mut self, name: &str) {
+        let online = self
+            .children
+            .iter()
+            .filter(|c| c.status() == ChildStatus::Online)
+            .count();
+        let child = match self.children.iter_mut().find(|c| c.name == name) {
+            Some(child) => child,
+            None => return,
+        };
+        child.slow_writes.reset();
+        // a child which is the source of a rebuild has to stay in sync
+        if child.status() != ChildStatus::Online
+            || online < 2
+            || !RebuildJob::lookup_src(name).is_empty()
+        {
+            return;
+        }
+
+        warn!(
+            "{}: the writes to child {} exceed {}ms, degrading it",
+            self.name,
+            name,
+            Config::get().nexus_opts.child_latency.threshold_ms
+        );
+        child.out_of_sync(true);
+        self.reconfigure(DREvent::ChildLagging).await;
+        if self.status() == NexusStatus::Degraded {
+            events::nexus_degraded(&self.name);
+        }
+
+        let nexus = self.name.clone();
+        let child = name.to_owned();
+        let delay = Duration::from_millis(
+            Config::get().nexus_opts.child_latency.catch_up_delay_ms,
+        );
+        Reactors::master().send_future(async move {
+            sleep(delay).await;
+            if let Some(nexus) = nexus_lookup(&nexus) {
+                nexus.catch_up_child(&child).await;
+            }
+        });
+    }
+
+    /// Rebuild a child which has been degraded as it was too slow, and has
+    /// not been rebuilt or removed since.
+    async fn catch_up_child(&mut self, name: &str) {
+        match self.children.iter().find(|c| c.name == name) {
+            Some(child) if child.lagging() => {}
+            _ => return,
+        }
+
+        let regions = self
+            .intent_log
+            .as_ref()
+            .map(|log| log.dirty_ranges(self.bdev.num_blocks()));
+        info!(
+            "{}: catching up child {} with {}",
+            self.name,
+            name,
+            match &regions {
+                Some(r) => format!("{} ranges of blocks", r.len()),
+                None => "a full rebuild".to_string(),
+            }
+        );
+        if let Err(e) = self.start_rebuild_of(name, regions).await {
+            error!("{}: failed to catch up {}: {}", self.name, name, e);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Children of a nexus whose replica is shared by this instance.
+//!
+//! The control plane gives the children of a nexus as nvmf URIs, including
+//! the replicas that live on the same node as the nexus. Rather than
+//! connecting to our own nvmf target and looping the IO through TCP, the
+//! bdev shared at the URI is used as the child directly. The child keeps its
+//! URI, so it is listed, removed and rebuilt by it as usual.
+//!
+//! The nvmf target claims the bdevs it shares, which keeps other writers
+//! out. A descriptor opened before the claim is left alone though, so the
+//! claim is released for as long as it takes to open the bdev and taken
+//! again on behalf of the target right after.
+
+use std::convert::TryFrom;
+
+use url::Url;
+
+use spdk_sys::{spdk_bdev_module_claim_bdev, spdk_bdev_module_release_bdev};
+
+use crate::{
+    core::{Bdev, BdevHandle, CoreError, Descriptor, Protocol, Share},
+    nexus_uri::{bdev_get_name, NexusBdevError},
+    subsys::{Config, NvmfSubsystem},
+};
+
+/// whether the URIs refer to the same subsystem of the same target
+fn same_target(child: &Url, endpoint: &str) -> bool {
+    match Url::parse(endpoint) {
+        Ok(endpoint) => {
+            child.scheme() == endpoint.scheme()
+                && child.host_str() == endpoint.host_str()
+                && child.port() == endpoint.port()
+                && child.path() == endpoint.path()
+        }
+        Err(_) => false,
+    }
+}
+
+/// The bdev our nvmf target shares at the URI of the child, none if the
+/// child is not an nvmf URI of this instance.
+pub(crate) fn lookup(uri: &str) -> Option<Bdev> {
+    let cfg = Config::get();
+    if !cfg.nexus_opts.nvmf_enable || !cfg.nexus_opts.local_children {
+        return None;
+    }
+
+    let url = Url::parse(uri).ok()?;
+    if url.scheme() != "nvmf" {
+        return None;
+    }
+
+    NvmfSubsystem::first()?
+        .into_iter()
+        .find(|s| {
+            s.uri_endpoints()
+                .unwrap_or_default()
+                .iter()
+                .any(|e| same_target(&url, e))
+        })?
+        .bdev()
+}
+
+/// The name of the bdev of the child URI, which is the local bdev it is
+/// shared from if any.
+pub(crate) fn bdev_name(uri: &str) -> Result<String, NexusBdevError> {
+    match lookup(uri) {
+        Some(bdev) => Ok(bdev.name()),
+        None => bdev_get_name(uri),
+    }
+}
+
+/// Open the bdev, stepping around the claim of the nvmf target if it is
+/// shared over nvmf and opened for writing.
+pub(crate) fn open(
+    bdev: &Bdev,
+    read_write: bool,
+) -> Result<Descriptor, CoreError> {
+    if !read_write || bdev.shared() != Some(Protocol::Nvmf) {
+        return bdev.open(read_write);
+    }
+
+    let module = unsafe { (*bdev.as_ptr()).internal.claim_module };
+    unsafe { spdk_bdev_module_release_bdev(bdev.as_ptr()) };
+    let desc = bdev.open(true);
+    let rc = unsafe {
+        spdk_bdev_module_claim_bdev(
+            bdev.as_ptr(),
+            std::ptr::null_mut(),
+            module,
+        )
+    };
+    if rc != 0 {
+        error!("Failed to claim {} for the nvmf target again", bdev.name());
+    }
+    desc
+}
+
+/// Open a handle to the bdev of the given name as [`open`] does.
+pub(crate) fn open_handle(
+    name: &str,
+    read_write: bool,
+) -> Result<BdevHandle, CoreError> {
+    let bdev =
+        Bdev::lookup_by_name(name).ok_or_else(|| CoreError::BdevNotFound {
+            name: name.into(),
+        })?;
+    BdevHandle::try_from(open(&bdev, read_write)?)
+}
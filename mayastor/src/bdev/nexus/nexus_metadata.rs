@@ -49,7 +49,9 @@ use crate::{
     bdev::nexus::{
         nexus_bdev::Nexus,
         nexus_child::{ChildError, ChildIoError, NexusChild},
+        nexus_intent_log,
         nexus_label::{Aligned, GptEntry, GptGuid, LabelError},
+        nexus_label_v2,
         nexus_metadata_content::NexusConfig,
     },
     core::{DmaBuf, DmaError},
@@ -211,7 +213,19 @@ impl MetaDataHeader {
             entry_size: MetaDataHeader::INDEX_ENTRY_SIZE,
             index_checksum: 0,
             data_start: data_start as u64,
-            data_end: partition.ent_end - partition.ent_start - 1,
+            // the end of the partition is reserved for the v2 label and the
+            // intent log
+            data_end: partition.ent_end
+                - partition.ent_start
+                - 1
+                - Aligned::get_blocks(
+                    nexus_label_v2::LABEL_V2_SIZE,
+                    u64::from(block_size),
+                )
+                - Aligned::get_blocks(
+                    nexus_intent_log::LOG_SIZE,
+                    u64::from(block_size),
+                ),
         }
     }
 }
@@ -1,12 +1,20 @@
 //! Utility functions and wrappers for working with NVMEoF devices in SPDK.
 
-use std::fmt;
+use std::{convert::TryFrom, fmt};
 
 use snafu::Snafu;
 
+use rpc::mayastor::NvmeAnaState;
+use spdk_sys::{
+    spdk_nvme_ana_state,
+    SPDK_NVME_ANA_INACCESSIBLE_STATE,
+    SPDK_NVME_ANA_NON_OPTIMIZED_STATE,
+    SPDK_NVME_ANA_OPTIMIZED_STATE,
+};
+
 use crate::{
     core::Bdev,
-    subsys::NvmfSubsystem,
+    subsys::{NvmfError, NvmfSubsystem},
     target::nvmf::{share, unshare},
 };
 
@@ -20,6 +28,14 @@ pub enum NexusNvmfError {
         err
     ))]
     CreateTargetFailed { dev: String, err: String },
+    #[snafu(display("Invalid ANA state {:?} for bdev uuid {}", state, dev))]
+    InvalidAnaState { dev: String, state: NvmeAnaState },
+    #[snafu(display(
+        "Failed to set ANA state of nvmf target for bdev uuid {}, error {}",
+        dev,
+        err
+    ))]
+    SetAnaStateFailed { dev: String, err: String },
 }
 
 /// Nvmf target representation.
@@ -28,7 +44,12 @@ pub struct NexusNvmfTarget {
 }
 
 impl NexusNvmfTarget {
-    pub async fn create(my_uuid: &str) -> Result<Self, NexusNvmfError> {
+    /// Create the target such that only the hosts with the given NQNs can
+    /// connect to it, or any host if none are given.
+    pub async fn create(
+        my_uuid: &str,
+        hosts: &[String],
+    ) -> Result<Self, NexusNvmfError> {
         info!("Creating nvmf nexus target: {}", my_uuid);
         let bdev = match Bdev::lookup_by_name(&my_uuid) {
             None => {
@@ -39,13 +60,21 @@ impl NexusNvmfTarget {
             Some(bd) => bd,
         };
 
-        match share(&my_uuid, &bdev).await {
+        let shared = if hosts.is_empty() {
+            share(&my_uuid, &bdev).await.map_err(|e| e.to_string())
+        } else {
+            share_to_hosts(&bdev, hosts)
+                .await
+                .map_err(|e| e.to_string())
+        };
+
+        match shared {
             Ok(_) => Ok(Self {
                 uuid: my_uuid.to_string(),
             }),
-            Err(e) => Err(NexusNvmfError::CreateTargetFailed {
+            Err(err) => Err(NexusNvmfError::CreateTargetFailed {
                 dev: my_uuid.to_string(),
-                err: e.to_string(),
+                err,
             }),
         }
     }
@@ -59,16 +88,89 @@ impl NexusNvmfTarget {
         }
     }
 
+    /// Set the ANA state the target reports to the hosts. The state of a
+    /// nexus which is about to move to another node is set to inaccessible,
+    /// so that the hosts fail over to the new one.
+    pub async fn set_ana_state(
+        &self,
+        state: NvmeAnaState,
+    ) -> Result<(), NexusNvmfError> {
+        let ana_state: spdk_nvme_ana_state = match state {
+            NvmeAnaState::NvmeAnaOptimizedState => {
+                SPDK_NVME_ANA_OPTIMIZED_STATE
+            }
+            NvmeAnaState::NvmeAnaNonOptimizedState => {
+                SPDK_NVME_ANA_NON_OPTIMIZED_STATE
+            }
+            NvmeAnaState::NvmeAnaInaccessibleState => {
+                SPDK_NVME_ANA_INACCESSIBLE_STATE
+            }
+            state => {
+                return Err(NexusNvmfError::InvalidAnaState {
+                    dev: self.uuid.clone(),
+                    state,
+                })
+            }
+        };
+
+        let ss = NvmfSubsystem::nqn_lookup(&self.uuid).ok_or_else(|| {
+            NexusNvmfError::BdevNotFound {
+                dev: self.uuid.clone(),
+            }
+        })?;
+        ss.set_ana_state(ana_state).await.map_err(|e| {
+            NexusNvmfError::SetAnaStateFailed {
+                dev: self.uuid.clone(),
+                err: e.to_string(),
+            }
+        })
+    }
+
+    /// the ANA state the target reports to the hosts
+    pub fn ana_state(&self) -> NvmeAnaState {
+        match NvmfSubsystem::nqn_lookup(&self.uuid).and_then(|s| s.ana_state())
+        {
+            Some(SPDK_NVME_ANA_OPTIMIZED_STATE) => {
+                NvmeAnaState::NvmeAnaOptimizedState
+            }
+            Some(SPDK_NVME_ANA_NON_OPTIMIZED_STATE) => {
+                NvmeAnaState::NvmeAnaNonOptimizedState
+            }
+            Some(SPDK_NVME_ANA_INACCESSIBLE_STATE) => {
+                NvmeAnaState::NvmeAnaInaccessibleState
+            }
+            _ => NvmeAnaState::NvmeAnaInvalidState,
+        }
+    }
+
     pub fn as_uri(&self) -> String {
         NvmfSubsystem::nqn_lookup(&self.uuid)
             .unwrap()
-            .uri_endpoints()
-            .unwrap()
-            .pop()
+            .share_uri()
             .unwrap()
     }
 }
 
+/// export the bdev over nvmf to the hosts with the given NQNs only
+async fn share_to_hosts(
+    bdev: &Bdev,
+    hosts: &[String],
+) -> Result<(), NvmfError> {
+    if NvmfSubsystem::nqn_lookup(&bdev.name()).is_some() {
+        return Ok(());
+    }
+
+    let ss = NvmfSubsystem::try_from(bdev.clone())?;
+    ss.allow_any(false);
+    for host in hosts {
+        if let Err(e) = ss.add_host(host) {
+            ss.destroy();
+            return Err(e);
+        }
+    }
+    ss.start().await.map(|_| ())
+}
+
 impl fmt::Debug for NexusNvmfTarget {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}@{:?}", self.as_uri(), self.uuid)
@@ -0,0 +1,401 @@
+//! Read cache of a nexus on a local device.
+//!
+//! A nexus whose children are all remote serves every read over the network.
+//! With a read cache, the data read from the children is also copied to a
+//! local bdev, i.e. an NVMe namespace or an lvol, which serves the following
+//! reads of the same blocks.
+//!
+//! The cache is direct mapped: the nexus is divided in lines of
+//! `LINE_SIZE` bytes, each of which can only be held in the slot of the cache
+//! device given by its index modulo the number of slots. A read which lies
+//! within a cached line is a hit. Otherwise the read is served by the
+//! children and the lines it spans are copied to the cache in the background.
+//!
+//! Writes, write zeroes and unmaps drop the lines they overlap from the cache
+//! both when they are dispatched and when they complete, so that a line
+//! copied while such an IO was in flight is never used.
+
+use std::{
+    convert::TryFrom,
+    ffi::c_void,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+    },
+};
+
+use url::Url;
+
+use spdk_sys::{
+    spdk_bdev_io,
+    spdk_bdev_io_get_io_channel,
+    spdk_bdev_readv_blocks,
+};
+
+use crate::{
+    bdev::nexus::{
+        nexus_bdev::{nexus_lookup, Error, Nexus},
+        nexus_channel::{NexusChannel, NexusChannelInner},
+        nexus_fn_table::NexusFnTable,
+        nexus_io::{io_type, Bio},
+    },
+    core::{Bdev, BdevHandle, Descriptor, Reactors},
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+/// size of a cache line in bytes
+const LINE_SIZE: u64 = 64 * 1024;
+
+/// max number of lines being copied to the cache at once
+const MAX_FILLS: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Slot {
+    /// index of the line of the nexus held in the slot
+    line: u64,
+    /// the data of the line has been copied to the slot
+    valid: bool,
+    /// the line is being copied to the slot
+    filling: bool,
+    /// number of reads of the slot in flight
+    readers: u32,
+    /// bumped whenever the line is dropped, to tell whether it was dropped
+    /// while being copied
+    epoch: u64,
+}
+
+pub(crate) struct ReadCache {
+    /// uri of the cache device
+    uri: String,
+    desc: Arc<Descriptor>,
+    /// size of a line in blocks
+    line_blocks: u64,
+    slots: Mutex<Vec<Slot>>,
+    fills: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl fmt::Debug for ReadCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReadCache")
+            .field("uri", &self.uri)
+            .field("line_blocks", &self.line_blocks)
+            .finish()
+    }
+}
+
+impl ReadCache {
+    /// the uri of the cache device
+    pub(crate) fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// number of reads served by the cache and by the children
+    pub(crate) fn hits_and_misses(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Take care of the read if it is served by the cache. Returns false if
+    /// the IO is to be dispatched to the children as usual, in which case
+    /// the lines of a read are copied to the cache.
+    pub(crate) fn intercept(
+        &self,
+        nexus: &Nexus,
+        io: *mut spdk_bdev_io,
+        io_type: u32,
+        ch: &mut NexusChannelInner,
+    ) -> bool {
+        if io_type != io_type::READ {
+            return false;
+        }
+
+        let nio = Bio(io);
+        let first = nio.offset() / self.line_blocks;
+        let last = (nio.offset() + nio.num_blocks() - 1) / self.line_blocks;
+
+        if first == last && self.read(nio.offset(), nio.num_blocks(), io, ch) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        for line in first ..= last {
+            self.fill(nexus, line);
+        }
+        false
+    }
+
+    /// read the blocks from the cache if they are in a valid slot
+    fn read(
+        &self,
+        offset: u64,
+        num_blocks: u64,
+        io: *mut spdk_bdev_io,
+        ch: &mut NexusChannelInner,
+    ) -> bool {
+        let line = offset / self.line_blocks;
+        let index = {
+            let mut slots = self.slots.lock().unwrap();
+            let index = (line % slots.len() as u64) as usize;
+            let slot = &mut slots[index];
+            if !slot.valid || slot.line != line {
+                return false;
+            }
+            // the slot is not to be copied to while it is read
+            slot.readers += 1;
+            index
+        };
+
+        if ch.read_cache.is_none() {
+            ch.read_cache = BdevHandle::try_from(self.desc.clone()).ok();
+        }
+
+        let rc = match ch.read_cache.as_ref() {
+            Some(handle) => unsafe {
+                let nio = Bio(io);
+                let (desc, chan) = handle.io_tuple();
+                spdk_bdev_readv_blocks(
+                    desc,
+                    chan,
+                    nio.iovs(),
+                    nio.iov_count(),
+                    index as u64 * self.line_blocks + offset
+                        - line * self.line_blocks,
+                    num_blocks,
+                    Some(Self::read_done),
+                    io as *mut c_void,
+                )
+            },
+            None => -1,
+        };
+
+        if rc != 0 {
+            self.slots.lock().unwrap()[index].readers -= 1;
+            return false;
+        }
+        true
+    }
+
+    /// completion of a read from the cache, which is retried on the children
+    /// if it failed
+    extern "C" fn read_done(
+        cache_io: *mut spdk_bdev_io,
+        success: bool,
+        ctx: *mut c_void,
+    ) {
+        Bio::io_free(cache_io);
+
+        let io = ctx as *mut spdk_bdev_io;
+        let nio = Bio(io);
+        let nexus = nio.nexus_as_ref();
+        if let Some(cache) = nexus.read_cache.as_ref() {
+            let line = nio.offset() / cache.line_blocks;
+            let mut slots = cache.slots.lock().unwrap();
+            let index = (line % slots.len() as u64) as usize;
+            slots[index].readers -= 1;
+        }
+
+        if success {
+            Bio::new(io, 0).ok();
+        } else {
+            warn!("{}: Failed to read from the read cache", nexus.name);
+            let ch = NexusChannel::inner_from_channel(unsafe {
+                spdk_bdev_io_get_io_channel(io)
+            });
+            NexusFnTable::io_dispatch_uncached(io, ch);
+        }
+    }
+
+    /// Copy the line from a child to its slot in the background, unless its
+    /// slot is busy or too many lines are being copied already.
+    fn fill(&self, nexus: &Nexus, line: u64) {
+        let epoch = {
+            let mut slots = self.slots.lock().unwrap();
+            let index = (line % slots.len() as u64) as usize;
+            let slot = &mut slots[index];
+            if slot.filling
+                || slot.readers > 0
+                || (slot.valid && slot.line == line)
+                || self.fills.load(Ordering::SeqCst) >= MAX_FILLS
+            {
+                return;
+            }
+            slot.line = line;
+            slot.valid = false;
+            slot.filling = true;
+            slot.epoch += 1;
+            self.fills.fetch_add(1, Ordering::SeqCst);
+            slot.epoch
+        };
+
+        let name = nexus.name.clone();
+        Reactors::current().send_future(async move {
+            let nexus = match nexus_lookup(&name) {
+                Some(nexus) => nexus,
+                None => return,
+            };
+            if let Some(cache) = nexus.read_cache.as_ref() {
+                let copied = cache.copy_line(nexus, line).await;
+                let mut slots = cache.slots.lock().unwrap();
+                let index = (line % slots.len() as u64) as usize;
+                let slot = &mut slots[index];
+                slot.filling = false;
+                slot.valid = copied && slot.epoch == epoch;
+                cache.fills.fetch_sub(1, Ordering::SeqCst);
+            }
+        });
+    }
+
+    /// copy the data of the line from a child to its slot
+    async fn copy_line(&self, nexus: &Nexus, line: u64) -> bool {
+        let child = match nexus.children.iter().find(|c| c.can_rw()) {
+            Some(child) => child,
+            None => return false,
+        };
+
+        let block_len = nexus.bdev.block_len() as u64;
+        let num_blocks = std::cmp::min(
+            self.line_blocks,
+            nexus.bdev.num_blocks() - line * self.line_blocks,
+        );
+        let index = line % self.slots.lock().unwrap().len() as u64;
+
+        let result = async {
+            let src = BdevHandle::try_from(child.desc.clone()?).ok()?;
+            let dst = BdevHandle::try_from(self.desc.clone()).ok()?;
+            let mut buf =
+                src.dma_malloc((num_blocks * block_len) as usize).ok()?;
+            src.read_at(
+                (line * self.line_blocks + nexus.data_ent_offset) * block_len,
+                &mut buf,
+            )
+            .await
+            .ok()?;
+            dst.write_at(index * self.line_blocks * block_len, &buf)
+                .await
+                .ok()
+        }
+        .await;
+
+        if result.is_none() {
+            debug!("{}: Failed to copy line {} to the cache", nexus.name, line);
+        }
+        result.is_some()
+    }
+
+    /// destroy the cache device of a nexus which is gone
+    pub(crate) async fn destroy(self: Arc<Self>) {
+        let uri = self.uri.clone();
+        drop(self);
+        if let Err(e) = bdev_destroy(&uri).await {
+            error!("Failed to destroy read cache {}: {}", uri, e);
+        }
+    }
+
+    /// drop the lines overlapping the blocks from the cache
+    pub(crate) fn invalidate(&self, offset: u64, num_blocks: u64) {
+        if num_blocks == 0 {
+            return;
+        }
+        let first = offset / self.line_blocks;
+        let last = (offset + num_blocks - 1) / self.line_blocks;
+
+        let mut slots = self.slots.lock().unwrap();
+        let count = slots.len() as u64;
+        for line in first ..= std::cmp::min(last, first + count - 1) {
+            let slot = &mut slots[(line % count) as usize];
+            if last - first >= count || slot.line == line {
+                slot.valid = false;
+                slot.epoch += 1;
+            }
+        }
+    }
+}
+
+impl Nexus {
+    /// Cache the reads of the nexus on the local device with the given uri.
+    /// All the children must be remote, i.e. nvmf, and the cache device
+    /// must have the block size of the nexus.
+    pub async fn set_read_cache(&mut self, uri: &str) -> Result<(), Error> {
+        let invalid = |reason: &str| Error::InvalidReadCache {
+            name: self.name.clone(),
+            uri: uri.to_string(),
+            reason: reason.to_string(),
+        };
+
+        if self.read_cache.is_some() {
+            return Err(invalid("the nexus has a read cache already"));
+        }
+        if self
+            .children
+            .iter()
+            .any(|c| Url::parse(&c.name).map_or(true, |u| u.scheme() != "nvmf"))
+        {
+            return Err(invalid("not all the children are remote"));
+        }
+        match Url::parse(uri) {
+            Ok(u) if u.scheme() != "nvmf" && u.scheme() != "iscsi" => {}
+            _ => return Err(invalid("the cache device must be local")),
+        }
+
+        let name =
+            bdev_create(uri).await.map_err(|e| Error::CreateReadCache {
+                source: e,
+                name: self.name.clone(),
+                uri: uri.to_string(),
+            })?;
+
+        let result = Bdev::lookup_by_name(&name)
+            .ok_or_else(|| invalid("the cache device is gone"))
+            .and_then(|bdev| {
+                if bdev.block_len() != self.bdev.block_len() {
+                    return Err(invalid("the block sizes differ"));
+                }
+                let line_blocks = LINE_SIZE / bdev.block_len() as u64;
+                let count = bdev.num_blocks() / line_blocks;
+                if count == 0 {
+                    return Err(invalid("the cache device is too small"));
+                }
+                let desc = bdev.open(true).map_err(|_| {
+                    invalid("the cache device can not be opened")
+                })?;
+                Ok(ReadCache {
+                    uri: uri.to_string(),
+                    desc: Arc::new(desc),
+                    line_blocks,
+                    slots: Mutex::new(vec![Slot::default(); count as usize]),
+                    fills: AtomicUsize::new(0),
+                    hits: AtomicU64::new(0),
+                    misses: AtomicU64::new(0),
+                })
+            });
+
+        match result {
+            Ok(cache) => {
+                info!(
+                    "{}: caching reads on {} ({} lines)",
+                    self.name,
+                    uri,
+                    cache.slots.lock().unwrap().len()
+                );
+                self.read_cache = Some(Arc::new(cache));
+                Ok(())
+            }
+            Err(e) => {
+                let _ = bdev_destroy(uri).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// the uri of the read cache device, None if reads are not cached
+    pub fn read_cache_uri(&self) -> Option<String> {
+        self.read_cache.as_ref().map(|c| c.uri().to_string())
+    }
+}
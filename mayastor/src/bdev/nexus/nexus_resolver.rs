@@ -0,0 +1,120 @@
+//!
+//! The control plane may register the endpoint of a UriResolver service, to
+//! which the nexus turns when one of its children becomes permanently
+//! unreachable, i.e. faulted. If the replica of the child has moved or was
+//! shared again, the resolver replies with its new URI. The child is then
+//! replaced with one of the new URI, without the control plane having to
+//! orchestrate the failover itself.
+
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use once_cell::sync::Lazy;
+use rpc::mayastor::{
+    uri_resolver_client::UriResolverClient,
+    ResolveChildUriRequest,
+};
+
+use crate::{
+    bdev::nexus::{instances, nexus_child::ChildStatus},
+    core::Reactors,
+    grpc::name_to_uuid,
+};
+
+/// how long to wait for the resolver to reply
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// endpoint of the resolver, none if not registered
+static ENDPOINT: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+/// children as (nexus, uri) for which a resolution is in progress
+static PENDING: Lazy<Mutex<HashSet<(String, String)>>> =
+    Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// register the endpoint of the resolver, or unregister it if none
+pub fn set_endpoint(endpoint: Option<String>) {
+    match endpoint.as_ref() {
+        Some(endpoint) => info!("Using URI resolver {}", endpoint),
+        None => info!("URI resolver unregistered"),
+    }
+    *ENDPOINT.lock().unwrap() = endpoint;
+}
+
+/// ask the resolver for the new URI of the child, if any
+async fn resolve(
+    endpoint: String,
+    nexus: &str,
+    uri: &str,
+) -> Result<Option<String>, String> {
+    let request = ResolveChildUriRequest {
+        uuid: name_to_uuid(nexus).to_string(),
+        uri: uri.to_string(),
+    };
+
+    let reply = tokio::time::timeout(RESOLVE_TIMEOUT, async {
+        let mut client = UriResolverClient::connect(endpoint)
+            .await
+            .map_err(|e| e.to_string())?;
+        client
+            .resolve_child_uri(request)
+            .await
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|_| "timed out".to_string())??
+    .into_inner();
+
+    if reply.uri.is_empty() || reply.uri == uri {
+        Ok(None)
+    } else {
+        Ok(Some(reply.uri))
+    }
+}
+
+/// Called when a child of the nexus has faulted. If a resolver is registered
+/// it is asked for a new URI of the child in the background, which replaces
+/// the child if the child is still faulted by then.
+pub(crate) fn child_faulted(nexus: &str, uri: &str) {
+    let endpoint = match ENDPOINT.lock().unwrap().clone() {
+        Some(endpoint) => endpoint,
+        None => return,
+    };
+
+    let key = (nexus.to_string(), uri.to_string());
+    if !PENDING.lock().unwrap().insert(key.clone()) {
+        return;
+    }
+
+    Reactors::master().send_future(async move {
+        let (nexus, uri) = &key;
+        match resolve(endpoint, nexus, uri).await {
+            Ok(Some(new_uri)) => {
+                if let Some(n) =
+                    instances().iter_mut().find(|n| &n.name == nexus)
+                {
+                    let faulted = n.children.iter().any(|c| {
+                        &c.name == uri && c.status() == ChildStatus::Faulted
+                    });
+                    if faulted {
+                        info!(
+                            "{}: replacing child {} with {}",
+                            nexus, uri, new_uri
+                        );
+                        if let Err(e) = n.replace_child(uri, &new_uri).await {
+                            error!(
+                                "{}: failed to replace child {} with {}: {}",
+                                nexus, uri, new_uri, e
+                            );
+                        }
+                    }
+                }
+            }
+            Ok(None) => {
+                debug!("{}: no new URI for child {}", nexus, uri);
+            }
+            Err(e) => {
+                warn!("{}: failed to resolve child {}: {}", nexus, uri, e);
+            }
+        }
+        PENDING.lock().unwrap().remove(&key);
+    });
+}
@@ -4,7 +4,7 @@ use async_trait::async_trait;
 use futures::channel::oneshot;
 use snafu::ResultExt;
 
-use rpc::mayastor::ShareProtocolNexus;
+use rpc::mayastor::{NvmeAnaState, ShareProtocolNexus};
 use spdk_sys::create_crypto_disk;
 
 use crate::{
@@ -16,22 +16,111 @@ use crate::{
             Error::AlreadyShared,
             Nexus,
             NexusTarget,
+            NodeLimit,
+            SetAnaState,
             ShareIscsiNexus,
             ShareNbdNexus,
             ShareNvmfNexus,
         },
+        nexus_hooks,
         nexus_iscsi::{NexusIscsiError, NexusIscsiTarget},
         nexus_nbd::NbdDisk,
         nexus_nvmf::{NexusNvmfError, NexusNvmfTarget},
     },
     core::{Bdev, Protocol, Share},
     ffihelper::{cb_arg, done_errno_cb, errno_result_from_i32, ErrnoResult},
+    iscsi_portal::Portal,
+    limits,
+    subsys::events,
 };
 
 /// we are using the multi buffer encryption implementation using CBC as the
 /// algorithm
 const CRYPTO_FLAVOUR: &str = "crypto_aesni_mb";
 
+/// create a crypto bdev with the given name on top of the base bdev, which
+/// encrypts the data written to the base bdev with the key
+pub(crate) fn create_crypto_bdev(
+    base: &str,
+    name: &str,
+    key: &str,
+) -> ErrnoResult<String> {
+    // constant
+    let flavour = CString::new(CRYPTO_FLAVOUR).unwrap();
+    // name of the crypto device
+    let cname = CString::new(name).unwrap();
+    // the nexus device itself
+    let base = CString::new(base).unwrap();
+    // the keys to the castle
+    let key = CString::new(key).unwrap();
+
+    let cipher = CString::new("AES_CBC").unwrap();
+
+    let errno = unsafe {
+        create_crypto_disk(
+            base.as_ptr(),
+            cname.as_ptr(),
+            flavour.as_ptr(),
+            key.as_ptr(),
+            cipher.as_ptr(),
+            std::ptr::null_mut(),
+        )
+    };
+    errno_result_from_i32(name.to_string(), errno)
+}
+
+/// destroy a crypto bdev created by [`create_crypto_bdev`]
+pub(crate) async fn destroy_crypto_bdev(bdev: &Bdev) -> ErrnoResult<()> {
+    let (s, r) = oneshot::channel::<ErrnoResult<()>>();
+    unsafe {
+        spdk_sys::delete_crypto_disk(
+            bdev.as_ptr(),
+            Some(done_errno_cb),
+            cb_arg(s),
+        );
+    }
+    r.await.expect("crypto delete sender is gone")
+}
+
+/// The hosts a nexus is published to. A nexus is used by a single host at a
+/// time, unless it is shared for simultaneous access by a clustered
+/// application on top of a raw block volume, which coordinates the writes of
+/// the hosts itself.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HostAccess {
+    /// more than one host may use the nexus at the same time
+    pub shared: bool,
+    /// the NQNs or IQNs of the hosts allowed to connect, any host may
+    /// connect if empty
+    pub allowed_hosts: Vec<String>,
+}
+
+impl HostAccess {
+    /// check that the hosts can be given for the protocol
+    fn validate(
+        &self,
+        name: &str,
+        share_protocol: ShareProtocolNexus,
+    ) -> Result<(), Error> {
+        let reason = if self.allowed_hosts.len() > 1 && !self.shared {
+            "more than one host requires shared access"
+        } else if !self.allowed_hosts.is_empty()
+            && share_protocol == ShareProtocolNexus::NexusNbd
+        {
+            "a nexus shared over nbd is local to the node"
+        } else if self.allowed_hosts.iter().any(|h| h.is_empty()) {
+            "empty host name"
+        } else {
+            return Ok(());
+        };
+
+        Err(Error::InvalidHosts {
+            name: name.to_string(),
+            reason: reason.to_string(),
+        })
+    }
+}
+
 #[async_trait(? Send)]
 ///
 /// The sharing of the nexus is different compared to regular bdevs
@@ -118,6 +207,69 @@ impl Nexus {
         share_protocol: ShareProtocolNexus,
         key: Option<String>,
     ) -> Result<String, Error> {
+        self.share_as(
+            share_protocol,
+            key,
+            false,
+            HostAccess::default(),
+            &Portal::default(),
+        )
+        .await
+    }
+
+    /// Share the nexus such that all writes to it are failed. This allows
+    /// exposing a nexus over snapshots or clones to backup tooling without
+    /// the risk of it being modified.
+    pub async fn share_read_only(
+        &mut self,
+        share_protocol: ShareProtocolNexus,
+        key: Option<String>,
+    ) -> Result<String, Error> {
+        self.share_as(
+            share_protocol,
+            key,
+            true,
+            HostAccess::default(),
+            &Portal::default(),
+        )
+        .await
+    }
+
+    /// Share the nexus to the given hosts only, or to more than one host at
+    /// the same time if the access is shared. A nexus shared over iscsi is
+    /// shared on the given portal.
+    pub async fn share_to_hosts(
+        &mut self,
+        share_protocol: ShareProtocolNexus,
+        key: Option<String>,
+        read_only: bool,
+        access: HostAccess,
+        portal: &Portal,
+    ) -> Result<String, Error> {
+        self.share_as(share_protocol, key, read_only, access, portal)
+            .await
+    }
+
+    async fn share_as(
+        &mut self,
+        share_protocol: ShareProtocolNexus,
+        key: Option<String>,
+        read_only: bool,
+        access: HostAccess,
+        portal: &Portal,
+    ) -> Result<String, Error> {
+        access.validate(&self.name, share_protocol)?;
+
+        // Sharing it again in the other mode or to other hosts is refused
+        // the same way as sharing it over another protocol is.
+        if self.nexus_target.is_some()
+            && (self.read_only != read_only || self.host_access != access)
+        {
+            return Err(Error::AlreadyShared {
+                name: self.name.clone(),
+            });
+        }
+
         // We could already be shared -- as CSI is idempotent chances are we get
         // called for some odd reason. Validate indeed -- that we are
         // shared by walking the target. If so, and the protocol is
@@ -158,35 +310,18 @@ impl Nexus {
             None => (),
         }
 
+        limits::check_share().context(NodeLimit {
+            name: self.name.clone(),
+        })?;
         assert_eq!(self.share_handle, None);
 
         let name = if let Some(key) = key {
             let name = format!("crypto-{}", self.name);
-
-            // constant
-            let flavour = CString::new(CRYPTO_FLAVOUR).unwrap();
-            // name of the crypto device
-            let cname = CString::new(name.clone()).unwrap();
-            // the nexus device itself
-            let base = CString::new(self.name.clone()).unwrap();
-            // the keys to the castle
-            let key = CString::new(key).unwrap();
-
-            let cipher = CString::new("AES_CBC").unwrap();
-
-            let errno = unsafe {
-                create_crypto_disk(
-                    base.as_ptr(),
-                    cname.as_ptr(),
-                    flavour.as_ptr(),
-                    key.as_ptr(),
-                    cipher.as_ptr(),
-                    std::ptr::null_mut(),
-                )
-            };
-            errno_result_from_i32(name, errno).context(CreateCryptoBdev {
-                name: self.name.clone(),
-            })?
+            create_crypto_bdev(&self.name, &name, &key).context(
+                CreateCryptoBdev {
+                    name: self.name.clone(),
+                },
+            )?
         } else {
             self.name.clone()
         };
@@ -210,22 +345,27 @@ impl Nexus {
             ShareProtocolNexus::NexusIscsi => {
                 // Publish the nexus to system using an iscsi target and return
                 // the IQN
-                let iscsi_target = NexusIscsiTarget::create(&name).context(
-                    ShareIscsiNexus {
-                        name: self.name.clone(),
-                    },
-                )?;
+                let iscsi_target = NexusIscsiTarget::create(
+                    &name,
+                    &access.allowed_hosts,
+                    portal,
+                )
+                .await
+                .context(ShareIscsiNexus {
+                    name: self.name.clone(),
+                })?;
                 let uri = iscsi_target.as_uri();
                 self.nexus_target =
                     Some(NexusTarget::NexusIscsiTarget(iscsi_target));
                 uri
             }
             ShareProtocolNexus::NexusNvmf => {
-                let nvmf_target = NexusNvmfTarget::create(&name)
-                    .await
-                    .context(ShareNvmfNexus {
-                        name: self.name.clone(),
-                    })?;
+                let nvmf_target =
+                    NexusNvmfTarget::create(&name, &access.allowed_hosts)
+                        .await
+                        .context(ShareNvmfNexus {
+                            name: self.name.clone(),
+                        })?;
                 let uri = nvmf_target.as_uri();
                 self.nexus_target =
                     Some(NexusTarget::NexusNvmfTarget(nvmf_target));
@@ -233,6 +373,12 @@ impl Nexus {
             }
         };
         self.share_handle = Some(name);
+        // the device is not known to any initiator before it is returned,
+        // so no write can sneak in before this
+        self.read_only = read_only;
+        self.host_access = access;
+        nexus_hooks::nexus_published(&self.name, &device_id);
+        events::share_published("nexus", &self.name, &device_id);
         Ok(device_id)
     }
 
@@ -256,22 +402,19 @@ impl Nexus {
                 return Ok(());
             }
         };
+        self.read_only = false;
+        self.host_access = HostAccess::default();
 
         let bdev_name = self.share_handle.take().unwrap();
         if let Some(bdev) = Bdev::lookup_by_name(&bdev_name) {
             // if the share handle is the same as bdev name it
             // implies there is no top level bdev, and we are done
             if self.name != bdev.name() {
-                let (s, r) = oneshot::channel::<ErrnoResult<()>>();
                 // currently, we only have the crypto vbdev
-                unsafe {
-                    spdk_sys::delete_crypto_disk(
-                        bdev.as_ptr(),
-                        Some(done_errno_cb),
-                        cb_arg(s),
-                    );
+                if let Some(rotation) = self.key_rotation.take() {
+                    rotation.destroy(&self.bdev).await;
                 }
-                r.await.expect("crypto delete sender is gone").context(
+                destroy_crypto_bdev(&bdev).await.context(
                     DestroyCryptoBdev {
                         name: self.name.clone(),
                     },
@@ -283,6 +426,34 @@ impl Nexus {
         Ok(())
     }
 
+    /// Set the ANA state of the nexus, which must be shared over nvmf.
+    pub async fn set_ana_state(
+        &self,
+        state: NvmeAnaState,
+    ) -> Result<(), Error> {
+        match self.nexus_target {
+            Some(NexusTarget::NexusNvmfTarget(ref nvmf_target)) => {
+                nvmf_target.set_ana_state(state).await.context(SetAnaState {
+                    name: self.name.clone(),
+                })
+            }
+            _ => Err(Error::NotSharedNvmf {
+                name: self.name.clone(),
+            }),
+        }
+    }
+
+    /// Return the ANA state of the nexus, invalid if it is not shared over
+    /// nvmf.
+    pub fn ana_state(&self) -> NvmeAnaState {
+        match self.nexus_target {
+            Some(NexusTarget::NexusNvmfTarget(ref nvmf_target)) => {
+                nvmf_target.ana_state()
+            }
+            _ => NvmeAnaState::NvmeAnaInvalidState,
+        }
+    }
+
     /// Return URI under which the nexus is shared or None if not shared.
     pub fn get_share_uri(&self) -> Option<String> {
         match self.nexus_target {
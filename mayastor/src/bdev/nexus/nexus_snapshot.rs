@@ -0,0 +1,175 @@
+//! Taking snapshots of a nexus and rolling it back to one in place.
+//!
+//! A snapshot of a nexus is a snapshot of each of its replicas, named after
+//! the time the nexus put in the create snapshot command. To restore it, the
+//! writes to the nexus are held off by locking its whole range, the writes
+//! in the write cache are dropped and every child copies back from its
+//! snapshot the clusters written since. Local children are restored
+//! directly, remote ones are sent a vendor specific admin command which the
+//! nvmf target of their node handles. The reads cached meanwhile are dropped
+//! before the writes resume.
+//!
+//! A child which fails to restore its snapshot is left with part of its
+//! clusters rolled back, so it is faulted and rebuilt from the others.
+
+use std::convert::TryFrom;
+
+use snafu::ResultExt;
+use tracing::instrument;
+
+use crate::{
+    bdev::nexus::{
+        nexus_bdev::{CreateSnapshot, Error, Nexus},
+        nexus_child::{ChildStatus, NexusChild},
+        nexus_io::nvme_admin_opc,
+    },
+    core::{BdevHandle, RangeContext},
+    replica::Replica,
+};
+
+impl NexusChild {
+    /// roll the child back to its snapshot taken at the given time
+    async fn restore_snapshot(&self, snapshot_time: u64) -> Result<(), String> {
+        let desc = self.desc.clone().ok_or_else(|| "not open".to_string())?;
+        let handle = BdevHandle::try_from(desc).map_err(|e| e.to_string())?;
+
+        match self.bdev.as_ref().and_then(Replica::from_bdev) {
+            Some(replica) => {
+                let (desc, ch) = handle.io_tuple();
+                replica
+                    .restore_snapshot(snapshot_time, desc, ch)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+            None => {
+                let mut cmd = spdk_sys::spdk_nvme_cmd::default();
+                cmd.set_opc(nvme_admin_opc::RESTORE_SNAPSHOT.into());
+                cmd.__bindgen_anon_1.cdw10 = snapshot_time as u32;
+                cmd.__bindgen_anon_2.cdw11 = (snapshot_time >> 32) as u32;
+                handle
+                    .nvme_admin(&cmd)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+impl Nexus {
+    /// Take a snapshot of all the children by sending the create snapshot
+    /// command through the nexus, as a host would. Returns the time which
+    /// identifies the snapshot.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
+    pub async fn create_snapshot(&self) -> Result<u64, Error> {
+        let nexus = BdevHandle::open(&self.name, false, false).context(
+            CreateSnapshot {
+                name: self.name.clone(),
+            },
+        )?;
+        let snapshot_time =
+            nexus.create_snapshot().await.context(CreateSnapshot {
+                name: self.name.clone(),
+            })?;
+
+        info!("{}: created snapshot {}", self.name, snapshot_time);
+        Ok(snapshot_time)
+    }
+
+    /// Roll the nexus back to its snapshot taken at the given time. All the
+    /// children must be online, as a child being rebuilt has no complete
+    /// snapshot.
+    #[instrument(level = "debug", skip(self), fields(nexus = %self.name), err)]
+    pub async fn restore_snapshot(
+        &mut self,
+        snapshot_time: u64,
+    ) -> Result<(), Error> {
+        let name = self.name.clone();
+        let cannot = |reason: &str| Error::CannotRestoreSnapshot {
+            name: name.clone(),
+            reason: reason.to_string(),
+        };
+        let failed = |reason: String| Error::RestoreSnapshot {
+            name: name.clone(),
+            snapshot: snapshot_time,
+            reason,
+        };
+
+        if self.read_only {
+            return Err(cannot("the nexus is read-only"));
+        }
+        if self
+            .children
+            .iter()
+            .any(|c| c.status() != ChildStatus::Online || c.rebuilding())
+        {
+            return Err(cannot("not all the children are online"));
+        }
+
+        // hold off the writes to the nexus until all the children are
+        // restored
+        let nexus = BdevHandle::open(&self.name, false, false)
+            .map_err(|e| failed(e.to_string()))?;
+        let mut ctx = RangeContext::new(0, self.bdev.num_blocks());
+        nexus
+            .desc
+            .lock_lba_range(&mut ctx, &nexus.channel)
+            .await
+            .map_err(|e| failed(format!("failed to quiesce: {}", e)))?;
+
+        if let Some(cache) = self.write_cache.clone() {
+            let count = cache.discard().await;
+            info!("{}: dropped {} cached writes", self.name, count);
+        }
+
+        let mut restored = 0;
+        let mut errors = Vec::new();
+        for child in &self.children {
+            match child.restore_snapshot(snapshot_time).await {
+                Ok(()) => restored += 1,
+                Err(e) => {
+                    error!(
+                        "{}: failed to restore snapshot {} of child {}: {}",
+                        self.name, snapshot_time, child.name, e
+                    );
+                    errors.push((child.name.clone(), e));
+                }
+            }
+        }
+
+        if let Some(cache) = self.read_cache.as_ref() {
+            cache.invalidate(0, self.bdev.num_blocks());
+        }
+
+        if restored > 0 {
+            for (child, _) in &errors {
+                if let Err(e) = self.fault_child(child).await {
+                    error!("{}: {}", self.name, e);
+                }
+            }
+        }
+
+        nexus
+            .desc
+            .unlock_lba_range(&mut ctx, &nexus.channel)
+            .await
+            .map_err(|e| failed(format!("failed to resume: {}", e)))?;
+
+        if restored == 0 {
+            return Err(failed(
+                errors
+                    .into_iter()
+                    .map(|(child, e)| format!("{}: {}", child, e))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ));
+        }
+
+        info!(
+            "{}: restored snapshot {} on {} children",
+            self.name, snapshot_time, restored
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! Where the children of a nexus are, for the control plane to spread the
+//! replicas of a volume over the nodes and to pick the ones to remove.
+//!
+//! The transport of a child follows from its URI, unless its replica is
+//! shared by this instance and used directly. The remote side of an nvmf
+//! child is identified by the data of the controller it is connected to and
+//! the address it was connected at. The latency of a child is estimated by
+//! timing a few reads of its first block.
+
+use std::{collections::HashSet, time::Instant};
+
+use url::Url;
+
+use spdk_sys::{
+    bdev_nvme_get_ctrlr,
+    spdk_nvme_ctrlr_get_data,
+    spdk_nvme_ctrlr_get_transport_id,
+};
+
+use crate::{
+    bdev::nexus::{nexus_bdev::Nexus, nexus_child::NexusChild},
+    core::{Bdev, MayastorEnvironment},
+};
+
+/// number of reads timed to estimate the latency of a child
+const LATENCY_PROBES: usize = 3;
+
+/// Identity of the nvmf controller a child is connected to.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NvmfController {
+    /// NQN of the subsystem of the controller
+    pub subnqn: String,
+    pub serial: String,
+    pub model: String,
+    pub firmware: String,
+    /// ID of the controller within the subsystem
+    pub cntlid: u16,
+}
+
+/// How a child of the nexus is reached and how fast it is.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChildTopology {
+    /// URI of the child
+    pub uri: String,
+    /// tcp, rdma, iscsi or local if the IO does not leave this node
+    pub transport: String,
+    /// the child is on this node
+    pub local: bool,
+    /// address and port of the target of a remote child
+    pub address: String,
+    /// name of the node the child is on, if it is this one
+    pub node: String,
+    /// the nvmf controller of a child connected over nvmf
+    pub controller: Option<NvmfController>,
+    /// shortest time a read of one block of the child took, in
+    /// microseconds, none if it is not open
+    pub latency_us: Option<u64>,
+}
+
+/// the text of a fixed size field, padded with spaces or NUL bytes
+fn field_str(bytes: impl Iterator<Item = u8>) -> String {
+    let bytes = bytes.take_while(|b| *b != 0).collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+/// the nvmf controller of an nvme bdev and the address it was connected at
+fn nvmf_controller(bdev: &Bdev) -> Option<(NvmfController, String)> {
+    if bdev.driver() != "nvme" {
+        return None;
+    }
+
+    let ctrlr = unsafe { bdev_nvme_get_ctrlr(bdev.as_ptr()) };
+    if ctrlr.is_null() {
+        return None;
+    }
+
+    let cdata = unsafe { &*spdk_nvme_ctrlr_get_data(ctrlr) };
+    let trid = unsafe { &*spdk_nvme_ctrlr_get_transport_id(ctrlr) };
+    let controller = NvmfController {
+        subnqn: field_str(cdata.subnqn.iter().map(|c| *c as u8)),
+        serial: field_str(cdata.sn.iter().map(|c| *c as u8)),
+        model: field_str(cdata.mn.iter().map(|c| *c as u8)),
+        firmware: field_str(cdata.fr.iter().map(|c| *c as u8)),
+        cntlid: cdata.cntlid,
+    };
+    let address = format!(
+        "{}:{}",
+        field_str(trid.traddr.iter().map(|c| *c as u8)),
+        field_str(trid.trsvcid.iter().map(|c| *c as u8))
+    );
+    Some((controller, address))
+}
+
+/// the transport of a remote child and the address of its target, none if
+/// the child is on this node
+fn remote(uri: &str) -> Option<(String, String)> {
+    let url = Url::parse(uri).ok()?;
+    let transport = match url.scheme() {
+        "nvmf" => url
+            .query_pairs()
+            .find(|(k, _)| k == "trtype")
+            .map(|(_, v)| v.to_lowercase())
+            .unwrap_or_else(|| "tcp".into()),
+        "iscsi" => "iscsi".into(),
+        _ => return None,
+    };
+    let address = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str()?, port),
+        None => url.host_str()?.to_string(),
+    };
+    Some((transport, address))
+}
+
+impl NexusChild {
+    /// the shortest time a read of one block of the child takes, in
+    /// microseconds
+    async fn read_latency(&self) -> Option<u64> {
+        let (bdev, handle) = self.get_dev().ok()?;
+        let mut buf = handle.dma_malloc(bdev.block_len() as usize).ok()?;
+
+        let mut latency: Option<u64> = None;
+        for _ in 0 .. LATENCY_PROBES {
+            let start = Instant::now();
+            handle.read_at(0, &mut buf).await.ok()?;
+            let us = start.elapsed().as_micros() as u64;
+            latency = Some(latency.map_or(us, |l| l.min(us)));
+        }
+        latency
+    }
+
+    /// where the child is and how fast it is
+    pub async fn topology(&self) -> ChildTopology {
+        let mut topology = ChildTopology {
+            uri: self.name.clone(),
+            latency_us: self.read_latency().await,
+            ..Default::default()
+        };
+
+        match remote(&self.name).filter(|_| self.local_bdev().is_none()) {
+            Some((transport, address)) => {
+                topology.transport = transport;
+                topology.address = address;
+                if let Some((controller, address)) =
+                    self.bdev.as_ref().and_then(nvmf_controller)
+                {
+                    topology.controller = Some(controller);
+                    topology.address = address;
+                }
+            }
+            None => {
+                topology.transport = "local".into();
+                topology.local = true;
+                topology.node = MayastorEnvironment::global_node_name().into();
+            }
+        }
+
+        topology
+    }
+}
+
+impl Nexus {
+    /// Where the children of the nexus are and how fast they are.
+    pub async fn topology(&self) -> Vec<ChildTopology> {
+        let mut children = Vec::new();
+        for child in &self.children {
+            children.push(child.topology().await);
+        }
+        children
+    }
+}
+
+/// The number of nodes the children are spread over, telling the nodes of
+/// the remote children apart by the host of their target.
+pub fn node_count(children: &[ChildTopology]) -> usize {
+    children
+        .iter()
+        .map(|c| {
+            if c.local {
+                String::new()
+            } else {
+                match c.address.rfind(':') {
+                    Some(idx) => c.address[.. idx].to_string(),
+                    None => c.address.clone(),
+                }
+            }
+        })
+        .collect::<HashSet<_>>()
+        .len()
+}
@@ -0,0 +1,331 @@
+//! Write-back cache of a nexus.
+//!
+//! Writes to a nexus with a cache are completed as soon as their data has
+//! been copied to memory, which absorbs the latency of small synchronous
+//! writes to replicas on slow or distant nodes. The cached writes are
+//! written back to the children, in the order they were received, when:
+//!
+//! * the initiator flushes the nexus, which completes after the write back,
+//! * a read, write zeroes or unmap overlaps a cached write,
+//! * the cache has no room left for a write,
+//! * the cache is disabled or the nexus is destroyed.
+//!
+//! A cached write stays in the cache until it has been written to all the
+//! children, so reads never see older data on the children. Writes which
+//! have not been flushed are lost if mayastor stops, as they are with a
+//! disk with a volatile write cache, which the nexus reports itself to have
+//! while the cache is enabled.
+
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    fmt,
+    slice::from_raw_parts,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Mutex,
+    },
+};
+
+use futures::lock::Mutex as AsyncMutex;
+
+use spdk_sys::{spdk_bdev_io, spdk_bdev_io_get_io_channel};
+
+use crate::{
+    bdev::nexus::{
+        nexus_bdev::{nexus_lookup, Error, Nexus},
+        nexus_channel::NexusChannel,
+        nexus_fn_table::NexusFnTable,
+        nexus_io::{io_type, Bio},
+    },
+    core::{BdevHandle, DmaBuf, Reactors},
+};
+
+/// a write held in the cache
+struct Extent {
+    /// offset of the write in blocks
+    offset: u64,
+    num_blocks: u64,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct CachedWrites {
+    /// the writes in the order they were received
+    extents: VecDeque<Extent>,
+    /// total size of the data of the writes
+    bytes: u64,
+}
+
+pub(crate) struct WriteCache {
+    /// the maximum size of the data of the cached writes
+    capacity: u64,
+    writes: Mutex<CachedWrites>,
+    /// the cache is being disabled, writes go to the children directly
+    draining: AtomicBool,
+    /// serializes writing back the cache
+    write_back: AsyncMutex<()>,
+}
+
+impl fmt::Debug for WriteCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriteCache")
+            .field("capacity", &self.capacity)
+            .field("draining", &self.draining)
+            .finish()
+    }
+}
+
+impl WriteCache {
+    pub(crate) fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            writes: Mutex::new(CachedWrites::default()),
+            draining: AtomicBool::new(false),
+            write_back: AsyncMutex::new(()),
+        }
+    }
+
+    /// Take care of the IO if it is to be served by the cache or has to wait
+    /// for the cache to be written back first. Returns false if the IO is to
+    /// be dispatched to the children as usual.
+    pub(crate) fn intercept(
+        self: &Arc<Self>,
+        nexus: &Nexus,
+        io: *mut spdk_bdev_io,
+        io_type: u32,
+    ) -> bool {
+        let nio = Bio(io);
+        match io_type {
+            io_type::WRITE if !nexus.read_only => {
+                let len = nio.num_blocks() * nio.block_len();
+                if len > self.capacity || self.draining.load(Ordering::SeqCst) {
+                    // write back what is cached before this write
+                    self.write_back_then(nexus, io, false);
+                } else if self.insert(&nio, len) {
+                    Bio::new(io, 0).ok();
+                } else {
+                    // try again once there is room
+                    self.write_back_then(nexus, io, true);
+                }
+                true
+            }
+            io_type::READ | io_type::WRITE_ZEROES | io_type::UNMAP => {
+                if self.overlaps(nio.offset(), nio.num_blocks()) {
+                    self.write_back_then(nexus, io, false);
+                    true
+                } else {
+                    false
+                }
+            }
+            io_type::FLUSH => {
+                if self.is_empty() {
+                    false
+                } else {
+                    self.write_back_then(nexus, io, false);
+                    true
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// the size of the cache in bytes
+    pub(crate) fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn is_empty(&self) -> bool {
+        self.writes.lock().unwrap().extents.is_empty()
+    }
+
+    /// copy the data of the write to the cache if it fits
+    fn insert(&self, nio: &Bio, len: u64) -> bool {
+        let mut writes = self.writes.lock().unwrap();
+        if self.draining.load(Ordering::SeqCst)
+            || writes.bytes + len > self.capacity
+        {
+            return false;
+        }
+
+        let mut data = Vec::with_capacity(len as usize);
+        for i in 0 .. nio.iov_count() as usize {
+            let iov = unsafe { *nio.iovs().add(i) };
+            data.extend_from_slice(unsafe {
+                from_raw_parts(iov.iov_base as *const u8, iov.iov_len as usize)
+            });
+        }
+        data.truncate(len as usize);
+
+        writes.bytes += len;
+        writes.extents.push_back(Extent {
+            offset: nio.offset(),
+            num_blocks: nio.num_blocks(),
+            data,
+        });
+        true
+    }
+
+    /// whether any cached write overlaps the given range of blocks
+    fn overlaps(&self, offset: u64, num_blocks: u64) -> bool {
+        self.writes.lock().unwrap().extents.iter().any(|e| {
+            e.offset < offset + num_blocks && offset < e.offset + e.num_blocks
+        })
+    }
+
+    /// Write back the cache and dispatch the IO afterwards, to the cache
+    /// again or past it, on the core it was submitted on. The IO fails if
+    /// the cache can not be written back.
+    fn write_back_then(
+        self: &Arc<Self>,
+        nexus: &Nexus,
+        io: *mut spdk_bdev_io,
+        cached: bool,
+    ) {
+        let cache = Arc::clone(self);
+        let name = nexus.name.clone();
+        Reactors::current().send_future(async move {
+            if let Err(e) = cache.write_back(&name).await {
+                error!("{}", e);
+                Bio::new(io, 0).fail();
+                return;
+            }
+
+            let ch = NexusChannel::inner_from_channel(unsafe {
+                spdk_bdev_io_get_io_channel(io)
+            });
+            if cached {
+                NexusFnTable::io_dispatch(io, ch);
+            } else {
+                NexusFnTable::io_dispatch_uncached(io, ch);
+            }
+        });
+    }
+
+    /// Write the cached writes to all the children that can take IO, in the
+    /// order they were received. The writes cached meanwhile are left for
+    /// the next write back.
+    pub(crate) async fn write_back(&self, name: &str) -> Result<(), Error> {
+        let _guard = self.write_back.lock().await;
+
+        let count = self.writes.lock().unwrap().extents.len();
+        if count == 0 {
+            return Ok(());
+        }
+
+        let err = |reason: String| Error::WriteBackCache {
+            name: name.to_string(),
+            reason,
+        };
+
+        let nexus = nexus_lookup(name).ok_or_else(|| err("gone".into()))?;
+        let block_len = nexus.bdev.block_len() as u64;
+        let alignment = nexus.bdev.alignment();
+        let data_ent_offset = nexus.data_ent_offset;
+        let handles = nexus
+            .children
+            .iter()
+            .filter(|c| c.can_rw())
+            .filter_map(|c| c.desc.clone())
+            .map(BdevHandle::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| err(e.to_string()))?;
+
+        if handles.is_empty() {
+            return Err(err("no child to write to".into()));
+        }
+
+        let log = nexus.intent_log.clone();
+        for i in 0 .. count {
+            let (blk, num_blocks, buf) = {
+                let writes = self.writes.lock().unwrap();
+                let extent = &writes.extents[i];
+                let mut buf = DmaBuf::new(extent.data.len(), alignment)
+                    .map_err(|e| err(e.to_string()))?;
+                buf.as_mut_slice().copy_from_slice(&extent.data);
+                (extent.offset, extent.num_blocks, buf)
+            };
+            let offset = (blk + data_ent_offset) * block_len;
+
+            if let Some(log) = log.as_ref() {
+                if !log.begin_write(blk, num_blocks).await {
+                    return Err(err("failed to mark the intent log".into()));
+                }
+            }
+            let mut result = Ok(());
+            for h in &handles {
+                result = h.write_at(offset, &buf).await.map(|_| ());
+                if result.is_err() {
+                    break;
+                }
+            }
+            if let Some(log) = log.as_ref() {
+                log.end_write(blk, num_blocks);
+            }
+            result.map_err(|e| err(e.to_string()))?;
+            nexus.write_back_bytes += buf.len() as u64 * handles.len() as u64;
+        }
+
+        if let Some(cache) = nexus.read_cache.as_ref() {
+            let writes = self.writes.lock().unwrap();
+            for extent in writes.extents.iter().take(count) {
+                cache.invalidate(extent.offset, extent.num_blocks);
+            }
+        }
+
+        let mut writes = self.writes.lock().unwrap();
+        for _ in 0 .. count {
+            let extent = writes.extents.pop_front().unwrap();
+            writes.bytes -= extent.data.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Drop the cached writes without writing them back, as the nexus is
+    /// being rolled back to a snapshot taken before them. Returns the number
+    /// of writes dropped.
+    pub(crate) async fn discard(&self) -> usize {
+        let _guard = self.write_back.lock().await;
+        let mut writes = self.writes.lock().unwrap();
+        let count = writes.extents.len();
+        writes.extents.clear();
+        writes.bytes = 0;
+        count
+    }
+}
+
+impl Nexus {
+    /// Set the size of the write-back cache of the nexus in bytes, 0
+    /// disables it. The cached writes are written back to the children
+    /// before the cache is resized or disabled.
+    pub async fn set_write_cache(&mut self, size: u64) -> Result<(), Error> {
+        if let Some(cache) = self.write_cache.clone() {
+            if cache.capacity() == size {
+                return Ok(());
+            }
+
+            cache.draining.store(true, Ordering::SeqCst);
+            if let Err(e) = cache.write_back(&self.name).await {
+                cache.draining.store(false, Ordering::SeqCst);
+                return Err(e);
+            }
+            self.write_cache = None;
+        }
+
+        if size > 0 {
+            self.write_cache = Some(Arc::new(WriteCache::new(size)));
+        }
+
+        // let the initiators know they have to flush their writes
+        unsafe { (*self.bdev.as_ptr()).write_cache = (size > 0) as i32 };
+
+        info!("{}: write cache set to {} bytes", self.name, size);
+        Ok(())
+    }
+
+    /// the size of the write-back cache in bytes, 0 if it is disabled
+    pub fn write_cache_size(&self) -> u64 {
+        self.write_cache.as_ref().map_or(0, |c| c.capacity())
+    }
+}
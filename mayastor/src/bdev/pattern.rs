@@ -0,0 +1,187 @@
+//! Pattern stamping on the null bdevs created with the verify parameter, to
+//! catch the corruption of data on its way through the nexus and the
+//! targets while benchmarking without real media.
+//!
+//! The null module of SPDK throws away what is written and returns whatever
+//! is in the buffer on a read. The IO of our bdevs is intercepted, see
+//! bdev::interpose, to stamp every block read with a pattern instead, and
+//! check every block written against it, failing the write on a mismatch.
+//! In the pattern, every byte holds the byte of the little endian LBA of
+//! its block at the same position modulo 8, so a block which ends up at the
+//! wrong LBA is caught as well as a block which is damaged.
+//!
+//! An initiator is expected to write what it has read, or what it has
+//! stamped with [`stamp`].
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use spdk_sys::{
+    iovec,
+    spdk_bdev_fn_table,
+    spdk_bdev_io,
+    spdk_bdev_io_complete,
+    spdk_bdev_io_get_buf,
+    spdk_bdev_io_type,
+    spdk_io_channel,
+    SPDK_BDEV_IO_STATUS_FAILED,
+    SPDK_BDEV_IO_STATUS_SUCCESS,
+    SPDK_BDEV_IO_TYPE_READ,
+    SPDK_BDEV_IO_TYPE_WRITE,
+};
+
+use crate::{
+    bdev::interpose::{self, Interpose},
+    core::Bdev,
+};
+
+/// fill the buffer which starts at the given byte offset into the bdev
+/// with the pattern
+fn fill(buf: &mut [u8], start: u64, block_len: u64) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        let pos = start + i as u64;
+        *b = (pos / block_len).to_le_bytes()[(pos % 8) as usize];
+    }
+}
+
+/// returns the byte offset into the bdev of the first byte in the buffer,
+/// which starts at the given byte offset, that does not match the pattern
+fn mismatch(buf: &[u8], start: u64, block_len: u64) -> Option<u64> {
+    buf.iter().enumerate().find_map(|(i, b)| {
+        let pos = start + i as u64;
+        if *b != (pos / block_len).to_le_bytes()[(pos % 8) as usize] {
+            Some(pos)
+        } else {
+            None
+        }
+    })
+}
+
+/// stamp the buffer which starts at the given block with the pattern
+pub fn stamp(buf: &mut [u8], offset_blocks: u64, block_len: u32) {
+    let block_len = u64::from(block_len);
+    fill(buf, offset_blocks * block_len, block_len);
+}
+
+/// returns the byte offset into the bdev of the first byte in the buffer,
+/// which starts at the given block, that does not match the pattern
+pub fn verify(buf: &[u8], offset_blocks: u64, block_len: u32) -> Option<u64> {
+    let block_len = u64::from(block_len);
+    mismatch(buf, offset_blocks * block_len, block_len)
+}
+
+/// a bdev which verifies its data
+#[derive(Default)]
+struct Patterned {
+    /// number of writes which failed as their data mismatched the pattern
+    mismatches: AtomicU64,
+}
+
+/// stamp and verify the data of the bdev by intercepting its IO
+pub(crate) fn install(bdev: &Bdev) {
+    interpose::install(bdev, Patterned::default(), |_| {});
+}
+
+/// stop intercepting the IO of the bdev, as it is about to be destroyed,
+/// it is passed on from now on
+pub(crate) fn forget(bdev: &Bdev) {
+    interpose::forget(bdev);
+}
+
+/// returns the number of writes to the bdev which have failed as their data
+/// did not match the pattern, or None if the bdev does not verify its data
+pub fn mismatches(name: &str) -> Option<u64> {
+    interpose::with_name(name, |bdev: &Patterned| {
+        bdev.mismatches.load(Ordering::Relaxed)
+    })
+}
+
+/// returns the buffers of the IO, the byte offset into the bdev it starts
+/// at and the block length
+unsafe fn buffers(io: *mut spdk_bdev_io) -> (&'static [iovec], u64, u64) {
+    let block_len = u64::from((*(*io).bdev).blocklen);
+    (
+        std::slice::from_raw_parts(
+            (*io).u.bdev.iovs,
+            (*io).u.bdev.iovcnt as usize,
+        ),
+        (*io).u.bdev.offset_blocks * block_len,
+        block_len,
+    )
+}
+
+extern "C" fn stamp_read(
+    _ch: *mut spdk_io_channel,
+    io: *mut spdk_bdev_io,
+    success: bool,
+) {
+    if !success {
+        unsafe { spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_FAILED) };
+        return;
+    }
+
+    // the iovecs need not be aligned to blocks
+    let (iovs, mut start, block_len) = unsafe { buffers(io) };
+    for iov in iovs {
+        let buf = unsafe {
+            std::slice::from_raw_parts_mut(
+                iov.iov_base as *mut u8,
+                iov.iov_len as usize,
+            )
+        };
+        fill(buf, start, block_len);
+        start += iov.iov_len as u64;
+    }
+
+    unsafe { spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_SUCCESS) };
+}
+
+/// returns the byte offset into the bdev of the first byte written which
+/// does not match the pattern
+fn verify_write(io: *mut spdk_bdev_io) -> Option<u64> {
+    let (iovs, mut start, block_len) = unsafe { buffers(io) };
+    for iov in iovs {
+        let buf = unsafe {
+            std::slice::from_raw_parts(
+                iov.iov_base as *const u8,
+                iov.iov_len as usize,
+            )
+        };
+        if let Some(pos) = mismatch(buf, start, block_len) {
+            return Some(pos);
+        }
+        start += iov.iov_len as u64;
+    }
+    None
+}
+
+impl Interpose for Patterned {
+    fn submit_request(
+        &self,
+        module: &'static spdk_bdev_fn_table,
+        ch: *mut spdk_io_channel,
+        io: *mut spdk_bdev_io,
+    ) {
+        match unsafe { (*io).type_ } as spdk_bdev_io_type {
+            SPDK_BDEV_IO_TYPE_READ => unsafe {
+                let len =
+                    (*io).u.bdev.num_blocks * u64::from((*(*io).bdev).blocklen);
+                spdk_bdev_io_get_buf(io, Some(stamp_read), len)
+            },
+            SPDK_BDEV_IO_TYPE_WRITE => match verify_write(io) {
+                Some(pos) => {
+                    self.mismatches.fetch_add(1, Ordering::Relaxed);
+                    error!(
+                        "{}: data written at byte {} mismatches the pattern",
+                        unsafe { Bdev::from((*io).bdev) }.name(),
+                        pos
+                    );
+                    unsafe {
+                        spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_FAILED)
+                    }
+                }
+                None => interpose::pass_on(module, ch, io),
+            },
+            _ => interpose::pass_on(module, ch, io),
+        }
+    }
+}
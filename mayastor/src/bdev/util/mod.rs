@@ -1,2 +1,3 @@
+pub(super) mod resolve;
 pub(super) mod uri;
 pub mod uring;
@@ -0,0 +1,81 @@
+//! Resolution of the host names in the URIs of nvmf children.
+//!
+//! A replica can be addressed by a stable service name rather than by the IP
+//! of its pod, which changes when the pod is rescheduled. The name is
+//! resolved by the resolver of the system in a thread of its own, so that a
+//! slow DNS server never holds up a reactor, and the address is handed to
+//! SPDK which only takes addresses.
+//!
+//! The resolver of the system does not tell the TTL of the records, so the
+//! addresses are cached for the time given in the nexus options instead. A
+//! child connecting again after that, i.e. when it is brought back online,
+//! resolves the name again and thus follows the replica to its new address.
+//! The address of a host which could not be connected to is forgotten right
+//! away.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, ToSocketAddrs},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use futures::channel::oneshot;
+use once_cell::sync::Lazy;
+
+use crate::{core::Mthread, subsys::Config};
+
+/// addresses of the host names and when they were resolved
+static CACHE: Lazy<Mutex<HashMap<String, (IpAddr, Instant)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Resolve the host, which may be an IP address already, to an address.
+/// IPv4 addresses are preferred over IPv6 ones.
+pub(crate) async fn resolve(host: &str) -> Result<IpAddr, String> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ip);
+    }
+
+    let ttl = Duration::from_secs(Config::get().nexus_opts.dns_cache_secs);
+    if let Some((ip, resolved)) = CACHE.lock().unwrap().get(host) {
+        if resolved.elapsed() < ttl {
+            return Ok(*ip);
+        }
+    }
+
+    let (s, r) = oneshot::channel();
+    let name = host.to_string();
+    thread::spawn(move || {
+        Mthread::unaffinitize();
+        // the port is required by the resolver but of no consequence
+        let result = (name.as_str(), 0)
+            .to_socket_addrs()
+            .map(|addrs| addrs.map(|a| a.ip()).collect::<Vec<_>>());
+        let _ = s.send(result);
+    });
+
+    let ips = r
+        .await
+        .map_err(|_| "resolver thread is gone".to_string())?
+        .map_err(|e| e.to_string())?;
+    let ip = ips
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or_else(|| ips.first())
+        .copied()
+        .ok_or_else(|| "no address".to_string())?;
+
+    debug!("resolved {} to {}", host, ip);
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(host.to_string(), (ip, Instant::now()));
+    Ok(ip)
+}
+
+/// forget the address of the host, such that it is resolved again the next
+/// time it is connected to
+pub(crate) fn forget(host: &str) {
+    CACHE.lock().unwrap().remove(host);
+}
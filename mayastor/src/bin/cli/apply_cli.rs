@@ -0,0 +1,551 @@
+//! Bulk apply of a declarative document of pools, replicas and nexuses.
+//!
+//! The document is compared with what the list calls report, and only the
+//! calls needed to get from the one to the other are issued: the missing
+//! resources are created, the shares and the children of the existing ones
+//! are changed, and with --prune the resources not in the document are
+//! destroyed. Resources are created bottom up and destroyed top down.
+//!
+//! ```yaml
+//! pools:
+//!   - name: pool1
+//!     disks: ["/dev/nvme0n1"]
+//! replicas:
+//!   - uuid: 0e4a7f5a-0000-0000-0000-000000000001
+//!     pool: pool1
+//!     size: 1GiB
+//!     share: nvmf
+//! nexuses:
+//!   - uuid: 0e4a7f5a-0000-0000-0000-000000000002
+//!     size: 1GiB
+//!     children: ["bdev:///0e4a7f5a-0000-0000-0000-000000000001"]
+//!     publish: nvmf
+//! ```
+
+use std::collections::HashMap;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde::Deserialize;
+use tonic::Status;
+
+use ::rpc::mayastor as rpc;
+
+use crate::{context::Context, parse_size};
+
+/// size in bytes or with units, i.e. 1GiB
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+impl Size {
+    fn bytes(&self) -> Result<u64, Status> {
+        match self {
+            Size::Bytes(n) => Ok(*n),
+            Size::Text(s) => {
+                parse_size(s).map(|b| b.get_bytes() as u64).map_err(|s| {
+                    Status::invalid_argument(format!("Bad size '{}'", s))
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pool {
+    name: String,
+    disks: Vec<String>,
+    #[serde(default)]
+    block_size: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Replica {
+    uuid: String,
+    pool: String,
+    size: Size,
+    #[serde(default)]
+    thin: bool,
+    /// none, nvmf or iscsi
+    #[serde(default)]
+    share: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Nexus {
+    uuid: String,
+    size: Size,
+    children: Vec<String>,
+    /// none, nbd, nvmf or iscsi
+    #[serde(default)]
+    publish: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Resources {
+    #[serde(default)]
+    pools: Vec<Pool>,
+    #[serde(default)]
+    replicas: Vec<Replica>,
+    #[serde(default)]
+    nexuses: Vec<Nexus>,
+}
+
+/// a call to be made to get to the state of the document
+#[derive(Debug)]
+enum Action {
+    CreatePool(rpc::CreatePoolRequest),
+    DestroyPool(String),
+    CreateReplica(rpc::CreateReplicaRequest),
+    ShareReplica(rpc::ShareReplicaRequest),
+    DestroyReplica(String),
+    CreateNexus(rpc::CreateNexusRequest),
+    AddChild(String, String),
+    RemoveChild(String, String),
+    PublishNexus(String, rpc::ShareProtocolNexus),
+    UnpublishNexus(String),
+    DestroyNexus(String),
+}
+
+impl Action {
+    fn describe(&self) -> String {
+        match self {
+            Action::CreatePool(rq) => {
+                format!("create pool {} on {:?}", rq.name, rq.disks)
+            }
+            Action::DestroyPool(name) => format!("destroy pool {}", name),
+            Action::CreateReplica(rq) => format!(
+                "create replica {} of {} bytes on pool {}",
+                rq.uuid, rq.size, rq.pool
+            ),
+            Action::ShareReplica(rq) => format!(
+                "share replica {} over {}",
+                rq.uuid,
+                replica_protocol_to_str(rq.share)
+            ),
+            Action::DestroyReplica(uuid) => {
+                format!("destroy replica {}", uuid)
+            }
+            Action::CreateNexus(rq) => format!(
+                "create nexus {} of {} bytes with children {:?}",
+                rq.uuid, rq.size, rq.children
+            ),
+            Action::AddChild(uuid, uri) => {
+                format!("add child {} to nexus {}", uri, uuid)
+            }
+            Action::RemoveChild(uuid, uri) => {
+                format!("remove child {} from nexus {}", uri, uuid)
+            }
+            Action::PublishNexus(uuid, share) => {
+                format!("publish nexus {} over {:?}", uuid, share)
+            }
+            Action::UnpublishNexus(uuid) => {
+                format!("unpublish nexus {}", uuid)
+            }
+            Action::DestroyNexus(uuid) => format!("destroy nexus {}", uuid),
+        }
+    }
+
+    async fn run(self, ctx: &mut Context) -> Result<(), Status> {
+        match self {
+            Action::CreatePool(rq) => {
+                ctx.client.create_pool(rq).await?;
+            }
+            Action::DestroyPool(name) => {
+                ctx.client
+                    .destroy_pool(rpc::DestroyPoolRequest {
+                        name,
+                        idempotency_key: String::new(),
+                    })
+                    .await?;
+            }
+            Action::CreateReplica(rq) => {
+                ctx.client.create_replica(rq).await?;
+            }
+            Action::ShareReplica(rq) => {
+                ctx.client.share_replica(rq).await?;
+            }
+            Action::DestroyReplica(uuid) => {
+                ctx.client
+                    .destroy_replica(rpc::DestroyReplicaRequest {
+                        uuid,
+                        dependents:
+                            rpc::DestroyReplicaDependents::DependentsRefuse
+                                as i32,
+                        idempotency_key: String::new(),
+                    })
+                    .await?;
+            }
+            Action::CreateNexus(rq) => {
+                ctx.client.create_nexus(rq).await?;
+            }
+            Action::AddChild(uuid, uri) => {
+                ctx.client
+                    .add_child_nexus(rpc::AddChildNexusRequest {
+                        uuid,
+                        uri,
+                        norebuild: false,
+                        rebuild_source: String::new(),
+                    })
+                    .await?;
+            }
+            Action::RemoveChild(uuid, uri) => {
+                ctx.client
+                    .remove_child_nexus(rpc::RemoveChildNexusRequest {
+                        uuid,
+                        uri,
+                    })
+                    .await?;
+            }
+            Action::PublishNexus(uuid, share) => {
+                ctx.client
+                    .publish_nexus(rpc::PublishNexusRequest {
+                        uuid,
+                        key: String::new(),
+                        share: share as i32,
+                        read_only: false,
+                        shared_access: false,
+                        allowed_hosts: Vec::new(),
+                        portal: None,
+                        idempotency_key: String::new(),
+                    })
+                    .await?;
+            }
+            Action::UnpublishNexus(uuid) => {
+                ctx.client
+                    .unpublish_nexus(rpc::UnpublishNexusRequest {
+                        uuid,
+                        idempotency_key: String::new(),
+                    })
+                    .await?;
+            }
+            Action::DestroyNexus(uuid) => {
+                ctx.client
+                    .destroy_nexus(rpc::DestroyNexusRequest {
+                        uuid,
+                        idempotency_key: String::new(),
+                    })
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("apply")
+        .settings(&[AppSettings::ColoredHelp, AppSettings::ColorAlways])
+        .about("Create, share and destroy resources to match a YAML document")
+        .arg(
+            Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .required(true)
+                .takes_value(true)
+                .value_name("FILE")
+                .help("YAML document of pools, replicas and nexuses"),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .help("Destroy the resources which are not in the document"),
+        )
+        .arg(
+            Arg::with_name("dry-run").short("n").long("dry-run").help(
+                "Print the calls which would be made without making them",
+            ),
+        )
+}
+
+pub async fn handler(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let file = matches.value_of("file").unwrap();
+    let text = std::fs::read_to_string(file).map_err(|e| {
+        Status::invalid_argument(format!("Failed to read {}: {}", file, e))
+    })?;
+    let resources: Resources = serde_yaml::from_str(&text).map_err(|e| {
+        Status::invalid_argument(format!("Failed to parse {}: {}", file, e))
+    })?;
+
+    ctx.v2("Listing pools, replicas and nexuses");
+    let pools = ctx
+        .client
+        .list_pools(rpc::Null {})
+        .await?
+        .into_inner()
+        .pools;
+    let replicas = ctx
+        .client
+        .list_replicas(rpc::ListReplicasRequest::default())
+        .await?
+        .into_inner()
+        .replicas;
+    let nexuses = ctx
+        .client
+        .list_nexus(rpc::ListNexusRequest::default())
+        .await?
+        .into_inner()
+        .nexus_list;
+
+    let actions = plan(
+        &ctx,
+        &resources,
+        &pools,
+        &replicas,
+        &nexuses,
+        matches.is_present("prune"),
+    )?;
+
+    if actions.is_empty() {
+        ctx.json(&Vec::<String>::new());
+        ctx.v1("Nothing to do");
+        return Ok(());
+    }
+
+    if matches.is_present("dry-run") {
+        let calls = actions.iter().map(|a| a.describe()).collect::<Vec<_>>();
+        if !ctx.json(&calls) {
+            calls.iter().for_each(|c| println!("{}", c));
+        }
+        return Ok(());
+    }
+
+    let mut done = Vec::new();
+    for action in actions {
+        ctx.v2(&format!("Going to {}", action.describe()));
+        let what = action.describe();
+        action.run(&mut ctx).await.map_err(|e| {
+            Status::new(
+                e.code(),
+                format!("Failed to {}: {}", what, e.message()),
+            )
+        })?;
+        ctx.v1(&format!("Done: {}", what));
+        done.push(what);
+    }
+    ctx.json(&done);
+    Ok(())
+}
+
+/// the calls to be made to get from the live state to the document
+fn plan(
+    ctx: &Context,
+    resources: &Resources,
+    pools: &[rpc::Pool],
+    replicas: &[rpc::Replica],
+    nexuses: &[rpc::Nexus],
+    prune: bool,
+) -> Result<Vec<Action>, Status> {
+    let pools = pools
+        .iter()
+        .map(|p| (p.name.as_str(), p))
+        .collect::<HashMap<_, _>>();
+    let replicas = replicas
+        .iter()
+        .map(|r| (r.uuid.as_str(), r))
+        .collect::<HashMap<_, _>>();
+    let nexuses = nexuses
+        .iter()
+        .map(|n| (n.uuid.as_str(), n))
+        .collect::<HashMap<_, _>>();
+
+    let mut creates = Vec::new();
+    let mut destroys = Vec::new();
+
+    if prune {
+        for uuid in nexuses.keys() {
+            if !resources.nexuses.iter().any(|n| n.uuid == *uuid) {
+                destroys.push(Action::DestroyNexus(uuid.to_string()));
+            }
+        }
+        for uuid in replicas.keys() {
+            if !resources.replicas.iter().any(|r| r.uuid == *uuid) {
+                destroys.push(Action::DestroyReplica(uuid.to_string()));
+            }
+        }
+        for name in pools.keys() {
+            if !resources.pools.iter().any(|p| p.name == *name) {
+                destroys.push(Action::DestroyPool(name.to_string()));
+            }
+        }
+    }
+
+    for pool in &resources.pools {
+        match pools.get(pool.name.as_str()) {
+            None => creates.push(Action::CreatePool(rpc::CreatePoolRequest {
+                name: pool.name.clone(),
+                disks: pool.disks.clone(),
+                block_size: pool.block_size,
+                io_if: rpc::PoolIoIf::PoolIoAuto as i32,
+                adopt: false,
+                idempotency_key: String::new(),
+            })),
+            Some(live) if live.disks.len() != pool.disks.len() => {
+                ctx.v1(&format!(
+                    "Warning: pool {} exists on other disks {:?}, leaving it",
+                    pool.name, live.disks
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+
+    for replica in &resources.replicas {
+        let share = parse_protocol(replica.share.as_deref())?;
+        let size = replica.size.bytes()?;
+        match replicas.get(replica.uuid.as_str()) {
+            None => {
+                creates.push(Action::CreateReplica(rpc::CreateReplicaRequest {
+                    uuid: replica.uuid.clone(),
+                    pool: replica.pool.clone(),
+                    size,
+                    thin: replica.thin,
+                    share,
+                    qos: None,
+                    idempotency_key: String::new(),
+                }))
+            }
+            Some(live) => {
+                if live.pool != replica.pool || live.size != size {
+                    ctx.v1(&format!(
+                        "Warning: replica {} exists on pool {} with {} bytes, leaving it",
+                        replica.uuid, live.pool, live.size
+                    ));
+                }
+                if live.share != share {
+                    creates.push(Action::ShareReplica(
+                        rpc::ShareReplicaRequest {
+                            uuid: replica.uuid.clone(),
+                            share,
+                            portal: None,
+                            idempotency_key: String::new(),
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    for nexus in &resources.nexuses {
+        let publish = parse_nexus_protocol(nexus.publish.as_deref())?;
+        let size = nexus.size.bytes()?;
+        match nexuses.get(nexus.uuid.as_str()) {
+            None => {
+                creates.push(Action::CreateNexus(rpc::CreateNexusRequest {
+                    uuid: nexus.uuid.clone(),
+                    size,
+                    children: nexus.children.clone(),
+                    qos: None,
+                    fault_policy: rpc::FaultPolicy::Fail as i32,
+                    freeze_timeout_ms: 0,
+                    read_cache: String::new(),
+                    idempotency_key: String::new(),
+                    read_policy: rpc::ReadPolicy::RoundRobin as i32,
+                }));
+                if let Some(share) = publish {
+                    creates
+                        .push(Action::PublishNexus(nexus.uuid.clone(), share));
+                }
+            }
+            Some(live) => {
+                if live.size != size {
+                    ctx.v1(&format!(
+                        "Warning: nexus {} exists with {} bytes, leaving its size",
+                        nexus.uuid, live.size
+                    ));
+                }
+                // add the new children before removing the old ones, not to
+                // leave the nexus without a healthy child
+                for uri in &nexus.children {
+                    if !live.children.iter().any(|c| c.uri == *uri) {
+                        creates.push(Action::AddChild(
+                            nexus.uuid.clone(),
+                            uri.clone(),
+                        ));
+                    }
+                }
+                for child in &live.children {
+                    if !nexus.children.contains(&child.uri) {
+                        creates.push(Action::RemoveChild(
+                            nexus.uuid.clone(),
+                            child.uri.clone(),
+                        ));
+                    }
+                }
+
+                let live_share = published_protocol(&live.device_uri);
+                if live_share != publish {
+                    if live_share.is_some() {
+                        creates
+                            .push(Action::UnpublishNexus(nexus.uuid.clone()));
+                    }
+                    if let Some(share) = publish {
+                        creates.push(Action::PublishNexus(
+                            nexus.uuid.clone(),
+                            share,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    destroys.extend(creates);
+    Ok(destroys)
+}
+
+fn parse_protocol(pcol: Option<&str>) -> Result<i32, Status> {
+    match pcol {
+        None | Some("none") => {
+            Ok(rpc::ShareProtocolReplica::ReplicaNone as i32)
+        }
+        Some("nvmf") => Ok(rpc::ShareProtocolReplica::ReplicaNvmf as i32),
+        Some("iscsi") => Ok(rpc::ShareProtocolReplica::ReplicaIscsi as i32),
+        Some(p) => Err(Status::invalid_argument(format!(
+            "Invalid replica share protocol '{}'",
+            p
+        ))),
+    }
+}
+
+fn parse_nexus_protocol(
+    pcol: Option<&str>,
+) -> Result<Option<rpc::ShareProtocolNexus>, Status> {
+    match pcol {
+        None | Some("none") => Ok(None),
+        Some("nbd") => Ok(Some(rpc::ShareProtocolNexus::NexusNbd)),
+        Some("nvmf") => Ok(Some(rpc::ShareProtocolNexus::NexusNvmf)),
+        Some("iscsi") => Ok(Some(rpc::ShareProtocolNexus::NexusIscsi)),
+        Some(p) => Err(Status::invalid_argument(format!(
+            "Invalid nexus publish protocol '{}'",
+            p
+        ))),
+    }
+}
+
+/// the protocol a nexus is published over, from the scheme of its device uri
+fn published_protocol(device_uri: &str) -> Option<rpc::ShareProtocolNexus> {
+    match device_uri.split("://").next() {
+        Some("file") => Some(rpc::ShareProtocolNexus::NexusNbd),
+        Some("nvmf") => Some(rpc::ShareProtocolNexus::NexusNvmf),
+        Some("iscsi") => Some(rpc::ShareProtocolNexus::NexusIscsi),
+        _ => None,
+    }
+}
+
+fn replica_protocol_to_str(idx: i32) -> &'static str {
+    match rpc::ShareProtocolReplica::from_i32(idx) {
+        Some(rpc::ShareProtocolReplica::ReplicaNone) => "none",
+        Some(rpc::ShareProtocolReplica::ReplicaNvmf) => "nvmf",
+        Some(rpc::ShareProtocolReplica::ReplicaIscsi) => "iscsi",
+        None => "unknown",
+    }
+}
@@ -5,7 +5,16 @@ use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use colored_json::prelude::*;
 use tonic::Status;
 
-use rpc::mayastor::{BdevShareRequest, BdevUri, CreateReply, Null};
+use rpc::mayastor::{
+    BdevShareRequest,
+    BdevUri,
+    CreateReply,
+    EnableLatencyHistogramRequest,
+    LatencyHistogramRequest,
+    ListHandlesRequest,
+    Null,
+    NvmeTelemetryLogRequest,
+};
 
 use crate::context::Context;
 
@@ -19,6 +28,9 @@ pub async fn handler(
         ("share", Some(args)) => share(ctx, args).await,
         ("destroy", Some(args)) => destroy(ctx, args).await,
         ("unshare", Some(args)) => unshare(ctx, args).await,
+        ("handles", Some(args)) => handles(ctx, args).await,
+        ("histogram", Some(args)) => histogram(ctx, args).await,
+        ("telemetry", Some(args)) => telemetry(ctx, args).await,
         (cmd, _) => {
             Err(Status::not_found(format!("command {} does not exist", cmd)))
         }
@@ -49,6 +61,42 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .about("unshare the given bdev")
         .arg(Arg::with_name("name").required(true).index(1));
 
+    let handles = SubCommand::with_name("handles")
+        .about("List the descriptors and io channels open on bdevs")
+        .arg(
+            Arg::with_name("name")
+                .help("only list the handles of the given bdev")
+                .required(false)
+                .index(1),
+        );
+
+    let histogram = SubCommand::with_name("histogram")
+        .about("Show, enable or disable the latency histogram of a bdev")
+        .arg(Arg::with_name("name").required(true).index(1))
+        .arg(
+            Arg::with_name("enable")
+                .long("enable")
+                .conflicts_with("disable")
+                .help("start tallying the latency of IOs"),
+        )
+        .arg(
+            Arg::with_name("disable")
+                .long("disable")
+                .help("stop tallying and discard the histogram"),
+        );
+
+    let telemetry = SubCommand::with_name("telemetry")
+        .about("Save the NVMe telemetry log of an NVMe bdev to a file")
+        .arg(Arg::with_name("name").required(true).index(1))
+        .arg(Arg::with_name("file").required(true).index(2))
+        .arg(
+            Arg::with_name("data-area")
+                .long("data-area")
+                .value_name("1-3")
+                .default_value("3")
+                .help("include the data areas up to this one"),
+        );
+
     SubCommand::with_name("bdev")
         .settings(&[
             AppSettings::SubcommandRequiredElseHelp,
@@ -61,6 +109,9 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .subcommand(unshare)
         .subcommand(create)
         .subcommand(destroy)
+        .subcommand(handles)
+        .subcommand(histogram)
+        .subcommand(telemetry)
 }
 
 async fn list(mut ctx: Context, _args: &ArgMatches<'_>) -> Result<(), Status> {
@@ -168,3 +219,83 @@ async fn unshare(
     );
     Ok(())
 }
+
+async fn handles(
+    mut ctx: Context,
+    args: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let name = args.value_of("name").unwrap_or_default().to_owned();
+    let response = ctx
+        .bdev
+        .list_handles(ListHandlesRequest {
+            name,
+        })
+        .await?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&response.into_inner())
+            .unwrap()
+            .to_colored_json_auto()
+            .unwrap()
+    );
+    Ok(())
+}
+
+async fn histogram(
+    mut ctx: Context,
+    args: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let name = args.value_of("name").unwrap().to_owned();
+
+    if args.is_present("enable") || args.is_present("disable") {
+        ctx.bdev
+            .enable_latency_histogram(EnableLatencyHistogramRequest {
+                name,
+                enable: args.is_present("enable"),
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let response = ctx
+        .bdev
+        .get_latency_histogram(LatencyHistogramRequest {
+            name,
+        })
+        .await?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&response.into_inner())
+            .unwrap()
+            .to_colored_json_auto()
+            .unwrap()
+    );
+    Ok(())
+}
+
+async fn telemetry(
+    mut ctx: Context,
+    args: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let name = args.value_of("name").unwrap().to_owned();
+    let file = args.value_of("file").unwrap();
+    let data_area = args
+        .value_of("data-area")
+        .unwrap()
+        .parse::<u32>()
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+    let response = ctx
+        .bdev
+        .get_nvme_telemetry_log(NvmeTelemetryLogRequest {
+            name,
+            data_area,
+        })
+        .await?;
+
+    let log = response.into_inner().log;
+    std::fs::write(file, &log)
+        .map_err(|e| Status::internal(format!("{}: {}", file, e)))?;
+    println!("{} bytes written to {}", log.len(), file);
+    Ok(())
+}
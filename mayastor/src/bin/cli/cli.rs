@@ -2,22 +2,30 @@
 extern crate clap;
 
 use byte_unit::Byte;
-use clap::{App, AppSettings, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
 use tonic::{transport::Channel, Status};
 
 use ::rpc::mayastor::{
     bdev_rpc_client::BdevRpcClient,
     mayastor_client::MayastorClient,
+    IscsiPortal,
+    QosLimits,
 };
 
 use crate::context::Context;
 
+mod apply_cli;
 mod bdev_cli;
 mod context;
+mod core_cli;
 mod nexus_cli;
+mod operation_cli;
 mod pool_cli;
+mod qos_cli;
 mod rebuild_cli;
 mod replica_cli;
+mod snapshot_cli;
+mod verify_cli;
 
 type MayaClient = MayastorClient<Channel>;
 type BdevClient = BdevRpcClient<Channel>;
@@ -26,9 +34,105 @@ pub(crate) fn parse_size(src: &str) -> Result<Byte, String> {
     Byte::from_str(src).map_err(|_| src.to_string())
 }
 
+/// arguments for the QoS rate limits of a replica or nexus
+pub(crate) fn qos_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("rw-iops")
+            .long("rw-iops")
+            .takes_value(true)
+            .value_name("NUMBER")
+            .help("Read and write IOs per second, a multiple of 1000"),
+        Arg::with_name("rw-mbps")
+            .long("rw-mbps")
+            .takes_value(true)
+            .value_name("NUMBER")
+            .help("Read and write MiB per second"),
+        Arg::with_name("r-mbps")
+            .long("r-mbps")
+            .takes_value(true)
+            .value_name("NUMBER")
+            .help("Read MiB per second"),
+        Arg::with_name("w-mbps")
+            .long("w-mbps")
+            .takes_value(true)
+            .value_name("NUMBER")
+            .help("Write MiB per second"),
+    ]
+}
+
+/// parse the QoS rate limits, None if none of them has been given
+pub(crate) fn parse_qos(
+    matches: &ArgMatches<'_>,
+) -> Result<Option<QosLimits>, Status> {
+    let limit = |name| {
+        matches.value_of(name).map_or(Ok(0), |v| {
+            v.parse::<u64>().map_err(|_| {
+                Status::invalid_argument(format!("Bad {} '{}'", name, v))
+            })
+        })
+    };
+
+    let qos = QosLimits {
+        rw_ios_per_sec: limit("rw-iops")?,
+        rw_mbytes_per_sec: limit("rw-mbps")?,
+        r_mbytes_per_sec: limit("r-mbps")?,
+        w_mbytes_per_sec: limit("w-mbps")?,
+    };
+
+    if qos == QosLimits::default() {
+        Ok(None)
+    } else {
+        Ok(Some(qos))
+    }
+}
+
+/// arguments for the iSCSI portal of a replica or nexus
+pub(crate) fn portal_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("portal-address")
+            .long("portal-address")
+            .takes_value(true)
+            .value_name("IP")
+            .help("Address of the iSCSI portal to share on"),
+        Arg::with_name("portal-port")
+            .long("portal-port")
+            .takes_value(true)
+            .value_name("NUMBER")
+            .help("Port of the iSCSI portal to share on"),
+        Arg::with_name("portal-interface")
+            .long("portal-interface")
+            .takes_value(true)
+            .value_name("NAME")
+            .help("Network interface of the iSCSI portal to share on"),
+    ]
+}
+
+/// parse the iSCSI portal, None if none of its arguments has been given
+pub(crate) fn parse_portal(
+    matches: &ArgMatches<'_>,
+) -> Result<Option<IscsiPortal>, Status> {
+    let port = matches.value_of("portal-port").map_or(Ok(0), |v| {
+        v.parse::<u16>().map_err(|_| {
+            Status::invalid_argument(format!("Bad portal-port '{}'", v))
+        })
+    })?;
+
+    let portal = IscsiPortal {
+        address: matches.value_of("portal-address").unwrap_or("").into(),
+        port: u32::from(port),
+        interface: matches.value_of("portal-interface").unwrap_or("").into(),
+    };
+
+    if portal == IscsiPortal::default() {
+        Ok(None)
+    } else {
+        Ok(Some(portal))
+    }
+}
+
 #[tokio::main(max_threads = 2)]
 async fn main() -> Result<(), Status> {
-    env_logger::init();
+    tracing_subscriber::fmt::init();
 
     let matches = App::new("Mayastor CLI")
         .version("0.1")
@@ -71,11 +175,25 @@ async fn main() -> Result<(), Status> {
                 .hide_possible_values(true)
                 .next_line_help(true)
                 .help("Output with large units: i for kiB, etc. or d for kB, etc."))
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FORMAT")
+                .possible_values(&["table", "json"])
+                .default_value("table")
+                .help("Output format, json prints the replies as they are"))
         .subcommand(pool_cli::subcommands())
         .subcommand(nexus_cli::subcommands())
         .subcommand(replica_cli::subcommands())
         .subcommand(bdev_cli::subcommands())
         .subcommand(rebuild_cli::subcommands())
+        .subcommand(operation_cli::subcommands())
+        .subcommand(snapshot_cli::subcommands())
+        .subcommand(qos_cli::subcommands())
+        .subcommand(apply_cli::subcommands())
+        .subcommand(core_cli::subcommands())
+        .subcommand(verify_cli::subcommands())
         .get_matches();
 
     let ctx = Context::new(&matches).await;
@@ -86,6 +204,12 @@ async fn main() -> Result<(), Status> {
         ("pool", Some(args)) => pool_cli::handler(ctx, args).await?,
         ("replica", Some(args)) => replica_cli::handler(ctx, args).await?,
         ("rebuild", Some(args)) => rebuild_cli::handler(ctx, args).await?,
+        ("operation", Some(args)) => operation_cli::handler(ctx, args).await?,
+        ("snapshot", Some(args)) => snapshot_cli::handler(ctx, args).await?,
+        ("qos", Some(args)) => qos_cli::handler(ctx, args).await?,
+        ("apply", Some(args)) => apply_cli::handler(ctx, args).await?,
+        ("core", Some(args)) => core_cli::handler(ctx, args).await?,
+        ("verify", Some(args)) => verify_cli::handler(ctx, args).await?,
 
         _ => eprintln!("Internal Error: Not implemented"),
     };
@@ -1,6 +1,7 @@
 use crate::{BdevClient, MayaClient};
 use byte_unit::Byte;
 use clap::ArgMatches;
+use serde::Serialize;
 use std::cmp::max;
 
 pub struct Context {
@@ -8,11 +9,14 @@ pub struct Context {
     pub(crate) bdev: BdevClient,
     verbosity: u64,
     units: char,
+    json: bool,
 }
 
 impl Context {
     pub(crate) async fn new(matches: &ArgMatches<'_>) -> Self {
-        let verbosity = if matches.is_present("quiet") {
+        // the replies are the only output in json mode
+        let json = matches.value_of("output") == Some("json");
+        let verbosity = if matches.is_present("quiet") || json {
             0
         } else {
             matches.occurrences_of("verbose") + 1
@@ -37,6 +41,7 @@ impl Context {
             bdev: BdevClient::connect(uri).await.unwrap(),
             verbosity,
             units,
+            json,
         }
     }
     pub(crate) fn v1(&self, s: &str) {
@@ -51,6 +56,14 @@ impl Context {
         }
     }
 
+    /// print the reply in json mode, returns whether it has been printed
+    pub(crate) fn json<T: Serialize>(&self, reply: &T) -> bool {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(reply).unwrap());
+        }
+        self.json
+    }
+
     pub(crate) fn units(&self, n: Byte) -> String {
         match self.units {
             'i' => n.get_appropriate_unit(true).to_string(),
@@ -64,6 +77,9 @@ impl Context {
         headers: Vec<&str>,
         mut data: Vec<Vec<String>>,
     ) {
+        if self.json {
+            return;
+        }
         assert_ne!(data.len(), 0);
         let ncols = data.first().unwrap().len();
         assert_eq!(headers.len(), ncols);
@@ -0,0 +1,125 @@
+//!
+//! methods to show how busy the reactor of each core is
+
+use std::time::Duration;
+
+use super::context::Context;
+use ::rpc::mayastor as rpc;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use tokio::time::delay_for;
+use tonic::Status;
+
+pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("core")
+        .settings(&[
+            AppSettings::SubcommandRequiredElseHelp,
+            AppSettings::ColoredHelp,
+            AppSettings::ColorAlways,
+        ])
+        .about("CPU core management")
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Show the busy and idle cycles of the reactors")
+                .arg(
+                    Arg::with_name("interval")
+                        .short("i")
+                        .long("interval")
+                        .value_name("SECONDS")
+                        .help(
+                            "Only count the cycles of this interval rather \
+                             than those since the start",
+                        ),
+                ),
+        )
+}
+
+pub async fn handler(
+    ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    match matches.subcommand() {
+        ("stats", Some(args)) => stats(ctx, args).await,
+        (cmd, _) => {
+            Err(Status::not_found(format!("command {} does not exist", cmd)))
+        }
+    }
+}
+
+async fn stats(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let interval = match matches.value_of("interval") {
+        Some(_) => {
+            Some(value_t!(matches.value_of("interval"), u64).map_err(|e| {
+                Status::invalid_argument(format!("invalid interval: {}", e))
+            })?)
+        }
+        None => None,
+    };
+
+    let mut reply = ctx.client.get_core_stats(rpc::Null {}).await?.into_inner();
+    if let Some(secs) = interval {
+        ctx.v2(&format!("Sampling the cycles for {}s", secs));
+        delay_for(Duration::from_secs(secs)).await;
+        let first = reply;
+        reply = ctx.client.get_core_stats(rpc::Null {}).await?.into_inner();
+        for core in reply.cores.iter_mut() {
+            if let Some(f) = first.cores.iter().find(|f| f.core == core.core) {
+                core.busy_cycles -= f.busy_cycles.min(core.busy_cycles);
+                core.idle_cycles -= f.idle_cycles.min(core.idle_cycles);
+            }
+        }
+    }
+
+    if ctx.json(&reply) {
+        return Ok(());
+    }
+    if reply.cores.is_empty() {
+        ctx.v1("No reactors found");
+        return Ok(());
+    }
+
+    let secs = |cycles: u64| {
+        format!("{:.1}", cycles as f64 / reply.tick_rate.max(1) as f64)
+    };
+    let table = reply
+        .cores
+        .iter()
+        .map(|c| {
+            let total = c.busy_cycles + c.idle_cycles;
+            let busy = if total == 0 {
+                0.0
+            } else {
+                c.busy_cycles as f64 * 100.0 / total as f64
+            };
+            let role = match (c.management, c.io) {
+                (true, true) => "mgmt,io",
+                (true, false) => "mgmt",
+                (false, true) => "io",
+                (false, false) => "-",
+            };
+            vec![
+                c.core.to_string(),
+                role.to_string(),
+                c.threads.to_string(),
+                format!("{:.1}%", busy),
+                secs(c.busy_cycles),
+                secs(c.idle_cycles),
+            ]
+        })
+        .collect();
+    ctx.print_list(
+        vec![
+            "CORE",
+            "ROLE",
+            ">THREADS",
+            ">BUSY",
+            ">BUSY_SECS",
+            ">IDLE_SECS",
+        ],
+        table,
+    );
+
+    Ok(())
+}
@@ -1,4 +1,11 @@
-use crate::{context::Context, parse_size};
+use crate::{
+    context::Context,
+    parse_portal,
+    parse_qos,
+    parse_size,
+    portal_args,
+    qos_args,
+};
 use ::rpc::mayastor as rpc;
 use byte_unit::Byte;
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
@@ -25,6 +32,138 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .multiple(true)
                 .index(3)
                 .help("list of children to add"),
+        )
+        .arg(
+            Arg::with_name("freeze")
+                .long("freeze")
+                .takes_value(true)
+                .min_values(0)
+                .value_name("MS")
+                .help(
+                    "freeze IO for up to MS milliseconds (default 30000) \
+                     rather than failing it when no healthy child is left",
+                ),
+        )
+        .arg(
+            Arg::with_name("read-cache")
+                .long("read-cache")
+                .takes_value(true)
+                .value_name("URI")
+                .help("local device to cache the reads of remote children on"),
+        )
+        .arg(
+            Arg::with_name("read-policy")
+                .long("read-policy")
+                .takes_value(true)
+                .value_name("POLICY")
+                .possible_values(READ_POLICIES)
+                .help("which child a read is sent to (default round_robin)"),
+        )
+        .args(&qos_args());
+
+    let qos = SubCommand::with_name("qos")
+        .about("set the QoS rate limits of a nexus, no limits remove them")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        )
+        .args(&qos_args());
+
+    let ana = SubCommand::with_name("ana")
+        .about("set the ANA state of a nexus published over nvmf")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        )
+        .arg(
+            Arg::with_name("state")
+                .required(true)
+                .index(2)
+                .possible_values(&[
+                    "optimized",
+                    "non_optimized",
+                    "inaccessible",
+                ])
+                .help("ANA state reported to the hosts"),
+        );
+
+    let cache = SubCommand::with_name("cache")
+        .about("set the size of the write-back cache of a nexus, 0 disables it")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .required(true)
+                .index(2)
+                .help("size of the cache, i.e. 64MiB"),
+        );
+
+    let restore = SubCommand::with_name("restore")
+        .about("roll a nexus and its replicas back to a snapshot")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .required(true)
+                .index(2)
+                .help("time the snapshot was taken at, which ends its name"),
+        );
+
+    let read_policy = SubCommand::with_name("read-policy")
+        .about("set which child of a nexus a read is sent to")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        )
+        .arg(
+            Arg::with_name("policy")
+                .required(true)
+                .index(2)
+                .possible_values(READ_POLICIES)
+                .help("read policy of the nexus"),
+        );
+
+    let faults = SubCommand::with_name("faults")
+        .about("set when the children of a nexus are faulted due to IO errors")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        )
+        .arg(
+            Arg::with_name("max-errors")
+                .required(true)
+                .index(2)
+                .help("errors at which a child is faulted, 0 for the config"),
+        )
+        .arg(
+            Arg::with_name("window")
+                .required(true)
+                .index(3)
+                .help("milliseconds the errors are counted in"),
+        )
+        .arg(
+            Arg::with_name("spare")
+                .long("spare")
+                .value_name("URI")
+                .multiple(true)
+                .number_of_values(1)
+                .help("replica replacing a faulted child, in order"),
         );
 
     let destroy = SubCommand::with_name("destroy")
@@ -43,7 +182,15 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .arg(Arg::with_name("uuid").required(true).index(1)
             .help("uuid for the nexus"))
         .arg(Arg::with_name("key").required(false).index(2)
-            .help("crypto key to use"));
+            .help("crypto key to use"))
+        .arg(Arg::with_name("read-only").short("r").long("read-only")
+            .help("Fail all writes to the published nexus"))
+        .arg(Arg::with_name("shared").short("s").long("shared")
+            .help("Allow more than one host to use the nexus at the same time"))
+        .arg(Arg::with_name("host").long("host").value_name("NQN/IQN")
+            .multiple(true).number_of_values(1)
+            .help("Host allowed to connect to the nexus, any host if none"))
+        .args(&portal_args());
 
     let unpublish = SubCommand::with_name("unpublish")
         .about("unpublish the nexus")
@@ -54,6 +201,39 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .help("uuid for the nexus"),
         );
 
+    let rotate_key = SubCommand::with_name("rotate-key")
+        .about("rotate the key of a nexus published with encryption")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid for the nexus"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .required(true)
+                .index(2)
+                .help("new crypto key to use"),
+        );
+
+    let stop_key_rotation = SubCommand::with_name("stop-key-rotation")
+        .about("stop rotating the key of a nexus")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid for the nexus"),
+        );
+
+    let key_rotation_progress = SubCommand::with_name("key-rotation-progress")
+        .about("show the progress of rotating the key of a nexus")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid for the nexus"),
+        );
+
     let add = SubCommand::with_name("add")
         .about("add a child")
         .arg(
@@ -73,6 +253,17 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .default_value("false")
                 .index(3)
                 .help("specify if a rebuild job runs automatically"),
+        )
+        .arg(
+            Arg::with_name("source")
+                .short("s")
+                .long("source")
+                .takes_value(true)
+                .value_name("URI")
+                .help(
+                    "uri of the child to rebuild from \
+                     (default any healthy child)",
+                ),
         );
 
     let remove = SubCommand::with_name("remove")
@@ -90,6 +281,27 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .help("uri of child to remove"),
         );
 
+    let switch = SubCommand::with_name("switch")
+        .about("switch a child to another uri of its replica")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid for the nexus"),
+        )
+        .arg(
+            Arg::with_name("uri")
+                .required(true)
+                .index(2)
+                .help("uri of child to switch"),
+        )
+        .arg(
+            Arg::with_name("new_uri")
+                .required(true)
+                .index(3)
+                .help("uri of the same replica to switch the child to"),
+        );
+
     let list = SubCommand::with_name("list")
         .about("list all nexus devices")
         .arg(
@@ -109,6 +321,15 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .help("uuid of nexus"),
         );
 
+    let topology = SubCommand::with_name("topology")
+        .about("show where the children of a nexus are")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        );
+
     SubCommand::with_name("nexus")
         .settings(&[
             AppSettings::SubcommandRequiredElseHelp,
@@ -121,9 +342,20 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .subcommand(publish)
         .subcommand(add)
         .subcommand(remove)
+        .subcommand(switch)
         .subcommand(unpublish)
+        .subcommand(rotate_key)
+        .subcommand(stop_key_rotation)
+        .subcommand(key_rotation_progress)
         .subcommand(list)
         .subcommand(children)
+        .subcommand(topology)
+        .subcommand(qos)
+        .subcommand(ana)
+        .subcommand(cache)
+        .subcommand(restore)
+        .subcommand(faults)
+        .subcommand(read_policy)
 }
 
 pub async fn handler(
@@ -135,10 +367,25 @@ pub async fn handler(
         ("destroy", Some(args)) => nexus_destroy(ctx, &args).await,
         ("list", Some(args)) => nexus_list(ctx, &args).await,
         ("children", Some(args)) => nexus_children(ctx, &args).await,
+        ("topology", Some(args)) => nexus_topology(ctx, &args).await,
         ("publish", Some(args)) => nexus_publish(ctx, &args).await,
         ("unpublish", Some(args)) => nexus_unpublish(ctx, &args).await,
+        ("rotate-key", Some(args)) => nexus_rotate_key(ctx, &args).await,
+        ("stop-key-rotation", Some(args)) => {
+            nexus_stop_key_rotation(ctx, &args).await
+        }
+        ("key-rotation-progress", Some(args)) => {
+            nexus_key_rotation_progress(ctx, &args).await
+        }
         ("add", Some(args)) => nexus_add(ctx, &args).await,
         ("remove", Some(args)) => nexus_remove(ctx, &args).await,
+        ("switch", Some(args)) => nexus_switch(ctx, &args).await,
+        ("qos", Some(args)) => nexus_qos(ctx, &args).await,
+        ("ana", Some(args)) => nexus_ana(ctx, &args).await,
+        ("cache", Some(args)) => nexus_cache(ctx, &args).await,
+        ("restore", Some(args)) => nexus_restore(ctx, &args).await,
+        ("faults", Some(args)) => nexus_faults(ctx, &args).await,
+        ("read-policy", Some(args)) => nexus_read_policy(ctx, &args).await,
         (cmd, _) => {
             Err(Status::not_found(format!("command {} does not exist", cmd)))
         }
@@ -166,17 +413,60 @@ async fn nexus_create(
     ));
     ctx.v2(&format!(" with children {:?}", children));
     let size = size.get_bytes() as u64;
-    ctx.client
+    let (fault_policy, freeze_timeout_ms) = if matches.is_present("freeze") {
+        let timeout = matches.value_of("freeze").map_or(Ok(0), |v| {
+            v.parse::<u32>().map_err(|_| {
+                Status::invalid_argument(format!("Bad freeze timeout '{}'", v))
+            })
+        })?;
+        (rpc::FaultPolicy::Freeze, timeout)
+    } else {
+        (rpc::FaultPolicy::Fail, 0)
+    };
+    let reply = ctx
+        .client
         .create_nexus(rpc::CreateNexusRequest {
             uuid: uuid.clone(),
             size,
             children,
+            qos: parse_qos(matches)?,
+            fault_policy: fault_policy as i32,
+            freeze_timeout_ms,
+            read_cache: matches
+                .value_of("read-cache")
+                .unwrap_or_default()
+                .to_string(),
+            idempotency_key: String::new(),
+            read_policy: parse_read_policy(
+                matches.value_of("read-policy").unwrap_or("round_robin"),
+            ) as i32,
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!("Nexus {} created", uuid));
     Ok(())
 }
 
+async fn nexus_qos(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let limits = parse_qos(matches)?;
+
+    ctx.v2(&format!("Setting QoS limits of nexus {}", uuid));
+    let reply = ctx
+        .client
+        .set_bdev_qos(rpc::SetBdevQosRequest {
+            uuid: uuid.clone(),
+            limits,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Set QoS limits of nexus {}", uuid));
+    Ok(())
+}
+
 async fn nexus_destroy(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
@@ -184,11 +474,14 @@ async fn nexus_destroy(
     let uuid = matches.value_of("uuid").unwrap().to_string();
 
     ctx.v2(&format!("Destroying nexus {}", uuid));
-    ctx.client
+    let reply = ctx
+        .client
         .destroy_nexus(rpc::DestroyNexusRequest {
             uuid: uuid.clone(),
+            idempotency_key: String::new(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!("Nexus {} destroyed", uuid));
     Ok(())
 }
@@ -197,7 +490,13 @@ async fn nexus_list(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
 ) -> Result<(), Status> {
-    let resp = ctx.client.list_nexus(rpc::Null {}).await?;
+    let resp = ctx
+        .client
+        .list_nexus(rpc::ListNexusRequest::default())
+        .await?;
+    if ctx.json(resp.get_ref()) {
+        return Ok(());
+    }
     let nexus = &resp.get_ref().nexus_list;
     if nexus.is_empty() {
         ctx.v1("No nexus found");
@@ -218,6 +517,7 @@ async fn nexus_list(
                 size,
                 state.to_string(),
                 n.rebuilds.to_string(),
+                read_policy_to_str(n.read_policy).to_string(),
             ];
             if show_child {
                 row.push(
@@ -231,7 +531,8 @@ async fn nexus_list(
             row
         })
         .collect();
-    let mut hdr = vec!["NAME", "PATH", ">SIZE", "STATE", ">REBUILDS"];
+    let mut hdr =
+        vec!["NAME", "PATH", ">SIZE", "STATE", ">REBUILDS", "READ_POLICY"];
     if show_child {
         hdr.push("CHILDREN");
     }
@@ -246,7 +547,13 @@ async fn nexus_children(
 ) -> Result<(), Status> {
     let uuid = matches.value_of("uuid").unwrap().to_string();
 
-    let resp = ctx.client.list_nexus(rpc::Null {}).await?;
+    let resp = ctx
+        .client
+        .list_nexus(rpc::ListNexusRequest {
+            volume: uuid.clone(),
+            ..Default::default()
+        })
+        .await?;
     let nexus = resp
         .get_ref()
         .nexus_list
@@ -258,6 +565,9 @@ async fn nexus_children(
                 "Specified nexus not found".to_owned(),
             )
         })?;
+    if ctx.json(&nexus.children) {
+        return Ok(());
+    }
 
     ctx.v2(&format!("Children of nexus {}:", uuid));
 
@@ -266,10 +576,64 @@ async fn nexus_children(
         .iter()
         .map(|c| {
             let state = child_state_to_str(c.state);
-            vec![c.uri.clone(), state.to_string()]
+            vec![
+                c.uri.clone(),
+                state.to_string(),
+                c.io_errors.to_string(),
+                c.io_timeouts.to_string(),
+                c.io_retries.to_string(),
+                c.local_bdev.clone(),
+            ]
+        })
+        .collect();
+    ctx.print_list(
+        vec!["NAME", "STATE", ">ERRORS", ">TIMEOUTS", ">RETRIES", "LOCAL"],
+        table,
+    );
+    Ok(())
+}
+
+async fn nexus_topology(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+
+    let resp = ctx
+        .client
+        .get_nexus_topology(rpc::GetNexusTopologyRequest {
+            uuid: uuid.clone(),
+        })
+        .await?;
+    if ctx.json(resp.get_ref()) {
+        return Ok(());
+    }
+
+    ctx.v2(&format!(
+        "Children of nexus {} on {} nodes:",
+        uuid,
+        resp.get_ref().nodes
+    ));
+
+    let table = resp
+        .get_ref()
+        .children
+        .iter()
+        .map(|c| {
+            let remote = match &c.controller {
+                Some(ctrlr) => format!("{} ({})", c.address, ctrlr.subnqn),
+                None if c.local => c.node.clone(),
+                None => c.address.clone(),
+            };
+            vec![
+                c.uri.clone(),
+                c.transport.clone(),
+                remote,
+                c.latency_us.to_string(),
+            ]
         })
         .collect();
-    ctx.print_list(vec!["NAME", "STATE"], table);
+    ctx.print_list(vec!["NAME", "TRANSPORT", "NODE", ">LATENCY_US"], table);
     Ok(())
 }
 
@@ -292,14 +656,27 @@ async fn nexus_publish(
     };
 
     ctx.v2(&format!("Publishing nexus {} over {:?}", uuid, prot));
+    let read_only = matches.is_present("read-only");
+    let shared_access = matches.is_present("shared");
+    let allowed_hosts = matches
+        .values_of("host")
+        .map(|hosts| hosts.map(|h| h.to_owned()).collect())
+        .unwrap_or_default();
+    let portal = parse_portal(matches)?;
     let resp = ctx
         .client
         .publish_nexus(rpc::PublishNexusRequest {
             uuid,
             key,
             share: prot.into(),
+            read_only,
+            shared_access,
+            allowed_hosts,
+            portal,
+            idempotency_key: String::new(),
         })
         .await?;
+    ctx.json(resp.get_ref());
     ctx.v1(&format!("Nexus published at {}", resp.get_ref().device_uri));
     Ok(())
 }
@@ -311,15 +688,213 @@ async fn nexus_unpublish(
     let uuid = matches.value_of("uuid").unwrap().to_string();
 
     ctx.v2(&format!("Unpublishing nexus {}", uuid));
-    ctx.client
+    let reply = ctx
+        .client
         .unpublish_nexus(rpc::UnpublishNexusRequest {
             uuid: uuid.clone(),
+            idempotency_key: String::new(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!("Nexus {} unpublished", uuid));
     Ok(())
 }
 
+async fn nexus_rotate_key(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let key = matches.value_of("key").unwrap().to_string();
+
+    ctx.v2(&format!("Rotating the key of nexus {}", uuid));
+    let reply = ctx
+        .client
+        .rotate_nexus_key(rpc::RotateNexusKeyRequest {
+            uuid: uuid.clone(),
+            key,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!(
+        "Rotating the key of nexus {} in operation {}",
+        uuid,
+        reply.get_ref().id
+    ));
+    Ok(())
+}
+
+async fn nexus_stop_key_rotation(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+
+    ctx.v2(&format!("Stopping the key rotation of nexus {}", uuid));
+    let reply = ctx
+        .client
+        .stop_key_rotation(rpc::StopKeyRotationRequest {
+            uuid: uuid.clone(),
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Stopped the key rotation of nexus {}", uuid));
+    Ok(())
+}
+
+async fn nexus_key_rotation_progress(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+
+    ctx.v2(&format!("Getting the key rotation progress of nexus {}", uuid));
+    let reply = ctx
+        .client
+        .get_key_rotation_progress(rpc::KeyRotationProgressRequest {
+            uuid: uuid.clone(),
+        })
+        .await?;
+    let reply = reply.get_ref();
+    if ctx.json(reply) {
+        return Ok(());
+    }
+    ctx.v1(&format!("{}% ({})", reply.progress, reply.state));
+    Ok(())
+}
+
+async fn nexus_ana(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let ana_state = match matches.value_of("state").unwrap() {
+        "optimized" => rpc::NvmeAnaState::NvmeAnaOptimizedState,
+        "non_optimized" => rpc::NvmeAnaState::NvmeAnaNonOptimizedState,
+        _ => rpc::NvmeAnaState::NvmeAnaInaccessibleState,
+    };
+
+    ctx.v2(&format!(
+        "Setting ANA state of nexus {} to {:?}",
+        uuid, ana_state
+    ));
+    let reply = ctx
+        .client
+        .set_nexus_ana_state(rpc::SetNexusAnaStateRequest {
+            uuid: uuid.clone(),
+            ana_state: ana_state as i32,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Nexus {} ANA state set to {:?}", uuid, ana_state));
+    Ok(())
+}
+
+async fn nexus_cache(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let size = parse_size(matches.value_of("size").unwrap())
+        .map_err(|s| Status::invalid_argument(format!("Bad size '{}'", s)))?
+        .get_bytes() as u64;
+
+    ctx.v2(&format!(
+        "Setting write cache of nexus {} to {}",
+        uuid, size
+    ));
+    let reply = ctx
+        .client
+        .set_nexus_write_cache(rpc::SetNexusWriteCacheRequest {
+            uuid: uuid.clone(),
+            size,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Nexus {} write cache set to {} bytes", uuid, size));
+    Ok(())
+}
+
+async fn nexus_read_policy(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let policy = matches.value_of("policy").unwrap();
+
+    ctx.v2(&format!("Setting read policy of nexus {} to {}", uuid, policy));
+    let reply = ctx
+        .client
+        .set_nexus_read_policy(rpc::SetNexusReadPolicyRequest {
+            uuid: uuid.clone(),
+            read_policy: parse_read_policy(policy) as i32,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Nexus {} read policy set to {}", uuid, policy));
+    Ok(())
+}
+
+async fn nexus_faults(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let max_errors =
+        value_t!(matches.value_of("max-errors"), u32).map_err(|e| {
+            Status::invalid_argument(format!("Bad max errors: {}", e))
+        })?;
+    let window_ms = value_t!(matches.value_of("window"), u32)
+        .map_err(|e| Status::invalid_argument(format!("Bad window: {}", e)))?;
+    let spares = matches
+        .values_of("spare")
+        .map(|v| v.map(|s| s.to_string()).collect())
+        .unwrap_or_else(Vec::new);
+
+    ctx.v2(&format!(
+        "Setting child fault policy of nexus {} to {} errors in {} ms",
+        uuid, max_errors, window_ms
+    ));
+    let reply = ctx
+        .client
+        .set_child_fault_policy(rpc::SetChildFaultPolicyRequest {
+            uuid: uuid.clone(),
+            max_errors,
+            window_ms,
+            spares,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Nexus {} child fault policy set", uuid));
+    Ok(())
+}
+
+async fn nexus_restore(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let snapshot =
+        value_t!(matches.value_of("snapshot"), u64).map_err(|e| {
+            Status::invalid_argument(format!("Bad snapshot: {}", e))
+        })?;
+
+    ctx.v2(&format!(
+        "Restoring snapshot {} of nexus {}",
+        snapshot, uuid
+    ));
+    let reply = ctx
+        .client
+        .restore_nexus_from_snapshot(rpc::RestoreNexusFromSnapshotRequest {
+            uuid: uuid.clone(),
+            snapshot,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Nexus {} restored to snapshot {}", uuid, snapshot));
+    Ok(())
+}
+
 async fn nexus_add(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
@@ -331,15 +906,19 @@ async fn nexus_add(
         .unwrap_or("false")
         .parse::<bool>()
         .unwrap_or(false);
+    let rebuild_source = matches.value_of("source").unwrap_or("").to_string();
 
     ctx.v2(&format!("Adding {} to children of {}", uri, uuid));
-    ctx.client
+    let reply = ctx
+        .client
         .add_child_nexus(rpc::AddChildNexusRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
             norebuild,
+            rebuild_source,
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!("Added {} to children of {}", uri, uuid));
     Ok(())
 }
@@ -352,16 +931,60 @@ async fn nexus_remove(
     let uri = matches.value_of("uri").unwrap().to_string();
 
     ctx.v2(&format!("Removing {} from children of {}", uri, uuid));
-    ctx.client
+    let reply = ctx
+        .client
         .remove_child_nexus(rpc::RemoveChildNexusRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!("Removed {} from children of {}", uri, uuid));
     Ok(())
 }
 
+async fn nexus_switch(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let uri = matches.value_of("uri").unwrap().to_string();
+    let new_uri = matches.value_of("new_uri").unwrap().to_string();
+
+    ctx.v2(&format!("Switching child {} of {} to {}", uri, uuid, new_uri));
+    let reply = ctx
+        .client
+        .switch_nexus_child(rpc::SwitchNexusChildRequest {
+            uuid: uuid.clone(),
+            uri: uri.clone(),
+            new_uri: new_uri.clone(),
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Switched child {} of {} to {}", uri, uuid, new_uri));
+    Ok(())
+}
+
+/// names of the read policies, as taken by the arguments
+const READ_POLICIES: &[&str] = &["round_robin", "queue_depth", "prefer_local"];
+
+fn parse_read_policy(policy: &str) -> rpc::ReadPolicy {
+    match policy {
+        "queue_depth" => rpc::ReadPolicy::QueueDepth,
+        "prefer_local" => rpc::ReadPolicy::PreferLocal,
+        _ => rpc::ReadPolicy::RoundRobin,
+    }
+}
+
+fn read_policy_to_str(idx: i32) -> &'static str {
+    match rpc::ReadPolicy::from_i32(idx) {
+        Some(rpc::ReadPolicy::RoundRobin) => "round_robin",
+        Some(rpc::ReadPolicy::QueueDepth) => "queue_depth",
+        Some(rpc::ReadPolicy::PreferLocal) => "prefer_local",
+        None => "unknown",
+    }
+}
+
 fn nexus_state_to_str(idx: i32) -> &'static str {
     match rpc::NexusState::from_i32(idx).unwrap() {
         rpc::NexusState::NexusUnknown => "unknown",
@@ -0,0 +1,141 @@
+//!
+//! methods to follow and cancel the operations running in the background
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use tonic::Status;
+
+use ::rpc::mayastor as rpc;
+
+use crate::context::Context;
+
+pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
+    let list = SubCommand::with_name("list")
+        .about("list the operations running and those done");
+
+    let get = SubCommand::with_name("get").about("get an operation").arg(
+        Arg::with_name("id")
+            .required(true)
+            .index(1)
+            .help("uuid of the operation"),
+    );
+
+    let cancel = SubCommand::with_name("cancel")
+        .about("cancel a running operation")
+        .arg(
+            Arg::with_name("id")
+                .required(true)
+                .index(1)
+                .help("uuid of the operation"),
+        );
+
+    SubCommand::with_name("operation")
+        .settings(&[
+            AppSettings::SubcommandRequiredElseHelp,
+            AppSettings::ColoredHelp,
+            AppSettings::ColorAlways,
+        ])
+        .about("Operations running in the background")
+        .subcommand(list)
+        .subcommand(get)
+        .subcommand(cancel)
+}
+
+pub async fn handler(
+    ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    match matches.subcommand() {
+        ("list", Some(args)) => list(ctx, &args).await,
+        ("get", Some(args)) => get(ctx, &args).await,
+        ("cancel", Some(args)) => cancel(ctx, &args).await,
+        (cmd, _) => {
+            Err(Status::not_found(format!("command {} does not exist", cmd)))
+        }
+    }
+}
+
+fn state(op: &rpc::Operation) -> &'static str {
+    match rpc::OperationState::from_i32(op.state) {
+        Some(rpc::OperationState::OperationRunning) => "running",
+        Some(rpc::OperationState::OperationSucceeded) => "succeeded",
+        Some(rpc::OperationState::OperationFailed) => "failed",
+        Some(rpc::OperationState::OperationCancelled) => "cancelled",
+        None => "unknown",
+    }
+}
+
+fn print_operations(ctx: &Context, operations: &[rpc::Operation]) {
+    let table = operations
+        .iter()
+        .map(|op| {
+            vec![
+                op.id.clone(),
+                op.kind.clone(),
+                op.target.clone(),
+                state(op).to_string(),
+                format!("{}%", op.progress),
+                op.error.clone(),
+            ]
+        })
+        .collect();
+    ctx.print_list(
+        vec!["ID", "KIND", "TARGET", "STATE", ">PROGRESS", "ERROR"],
+        table,
+    );
+}
+
+async fn list(
+    mut ctx: Context,
+    _matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    ctx.v2("Requesting a list of operations");
+
+    let resp = ctx.client.list_operations(rpc::Null {}).await?;
+    if ctx.json(resp.get_ref()) {
+        return Ok(());
+    }
+    let operations = &resp.get_ref().operations;
+    if operations.is_empty() {
+        ctx.v1("No operations found");
+        return Ok(());
+    }
+
+    ctx.v2("Found following operations:");
+    print_operations(&ctx, operations);
+    Ok(())
+}
+
+async fn get(mut ctx: Context, matches: &ArgMatches<'_>) -> Result<(), Status> {
+    let id = matches.value_of("id").unwrap().to_owned();
+
+    ctx.v2(&format!("Requesting operation {}", id));
+    let resp = ctx
+        .client
+        .get_operation(rpc::OperationRequest {
+            id,
+        })
+        .await?;
+    if ctx.json(resp.get_ref()) {
+        return Ok(());
+    }
+    print_operations(&ctx, &[resp.into_inner()]);
+    Ok(())
+}
+
+async fn cancel(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let id = matches.value_of("id").unwrap().to_owned();
+
+    ctx.v2(&format!("Cancelling operation {}", id));
+    let reply = ctx
+        .client
+        .cancel_operation(rpc::OperationRequest {
+            id: id.clone(),
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Cancelled operation {}", id));
+    Ok(())
+}
@@ -21,6 +21,12 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .value_name("IF")
                 .help("I/O interface for the underlying devices"),
         )
+        .arg(
+            Arg::with_name("adopt")
+                .long("adopt")
+                .takes_value(false)
+                .help("Adopt a pool created by other tools on the disk"),
+        )
         .arg(
             Arg::with_name("pool")
                 .required(true)
@@ -52,6 +58,15 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .subcommand(create)
         .subcommand(destroy)
         .subcommand(SubCommand::with_name("list").about("List storage pools"))
+        .subcommand(
+            SubCommand::with_name("health")
+                .about("Show the health of the disks of storage pools")
+                .arg(
+                    Arg::with_name("pool")
+                        .index(1)
+                        .help("Storage pool name, all pools if omitted"),
+                ),
+        )
 }
 
 pub async fn handler(
@@ -62,6 +77,7 @@ pub async fn handler(
         ("create", Some(args)) => create(ctx, args).await,
         ("destroy", Some(args)) => destroy(ctx, args).await,
         ("list", Some(args)) => list(ctx, args).await,
+        ("health", Some(args)) => health(ctx, args).await,
         (cmd, _) => {
             Err(Status::not_found(format!("command {} does not exist", cmd)))
         }
@@ -90,14 +106,18 @@ async fn create(
     }?;
 
     ctx.v2(&format!("Creating pool {}", name));
-    ctx.client
+    let reply = ctx
+        .client
         .create_pool(rpc::CreatePoolRequest {
             name: name.clone(),
             disks,
             block_size,
             io_if,
+            adopt: matches.is_present("adopt"),
+            idempotency_key: String::new(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!("Created pool {}", name));
     Ok(())
 }
@@ -109,11 +129,14 @@ async fn destroy(
     let name = matches.value_of("pool").unwrap().to_owned();
 
     ctx.v2(&format!("Destroying pool {}", name));
-    ctx.client
+    let reply = ctx
+        .client
         .destroy_pool(rpc::DestroyPoolRequest {
             name: name.clone(),
+            idempotency_key: String::new(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!("Destroyed pool {}", name));
     Ok(())
 }
@@ -125,6 +148,9 @@ async fn list(
     ctx.v2("Requesting a list of pools");
 
     let reply = ctx.client.list_pools(rpc::Null {}).await?;
+    if ctx.json(reply.get_ref()) {
+        return Ok(());
+    }
     let pools: &Vec<rpc::Pool> = &reply.get_ref().pools;
     if pools.is_empty() {
         ctx.v1("No pools found");
@@ -141,14 +167,79 @@ async fn list(
             let state = pool_state_to_str(p.state);
             vec![
                 p.name.clone(),
+                p.uuid.clone(),
                 state.to_string(),
                 ctx.units(cap),
                 ctx.units(used),
+                p.label.as_ref().map_or("", |l| l.node.as_str()).to_string(),
                 p.disks.join(" "),
             ]
         })
         .collect();
-    ctx.print_list(vec!["NAME", "STATE", ">CAPACITY", ">USED", "DISKS"], table);
+    ctx.print_list(
+        vec![
+            "NAME",
+            "UUID",
+            "STATE",
+            ">CAPACITY",
+            ">USED",
+            "NODE",
+            "DISKS",
+        ],
+        table,
+    );
+
+    Ok(())
+}
+
+async fn health(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let pool = matches.value_of("pool").unwrap_or_default().to_owned();
+
+    ctx.v2("Requesting the health of the pool disks");
+    let reply = ctx
+        .client
+        .get_disk_health(rpc::GetDiskHealthRequest {
+            pool,
+        })
+        .await?;
+    if ctx.json(reply.get_ref()) {
+        return Ok(());
+    }
+    let disks = &reply.get_ref().disks;
+    if disks.is_empty() {
+        ctx.v1("No disk reports its health");
+        return Ok(());
+    }
+
+    let table = disks
+        .iter()
+        .map(|d| {
+            vec![
+                d.pool.clone(),
+                d.disk.clone(),
+                format!("{:#04x}", d.critical_warning),
+                format!("{}%", d.available_spare),
+                format!("{}%", d.percentage_used),
+                d.media_errors.to_string(),
+                d.power_on_hours.to_string(),
+            ]
+        })
+        .collect();
+    ctx.print_list(
+        vec![
+            "POOL",
+            "DISK",
+            "WARNING",
+            ">SPARE",
+            ">USED",
+            ">MEDIA_ERRORS",
+            ">POWER_ON_HOURS",
+        ],
+        table,
+    );
 
     Ok(())
 }
@@ -159,5 +250,6 @@ fn pool_state_to_str(idx: i32) -> &'static str {
         rpc::PoolState::PoolOnline => "online",
         rpc::PoolState::PoolDegraded => "degraded",
         rpc::PoolState::PoolFaulted => "faulted",
+        rpc::PoolState::PoolReadOnly => "read-only",
     }
 }
@@ -0,0 +1,122 @@
+//!
+//! methods to set and get the QoS rate limits of replicas and nexuses
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use tonic::Status;
+
+use ::rpc::mayastor as rpc;
+
+use crate::{context::Context, parse_qos, qos_args};
+
+pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
+    let set = SubCommand::with_name("set")
+        .about("set the rate limits, those not given are removed")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of the replica or nexus"),
+        )
+        .args(&qos_args());
+
+    let get = SubCommand::with_name("get")
+        .about("get the rate limits, 0 meaning no limit")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of the replica or nexus"),
+        );
+
+    SubCommand::with_name("qos")
+        .settings(&[
+            AppSettings::SubcommandRequiredElseHelp,
+            AppSettings::ColoredHelp,
+            AppSettings::ColorAlways,
+        ])
+        .about("QoS rate limits of replicas and nexuses")
+        .subcommand(set)
+        .subcommand(get)
+}
+
+pub async fn handler(
+    ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    match matches.subcommand() {
+        ("set", Some(args)) => set(ctx, &args).await,
+        ("get", Some(args)) => get(ctx, &args).await,
+        (cmd, _) => {
+            Err(Status::not_found(format!("command {} does not exist", cmd)))
+        }
+    }
+}
+
+async fn set(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let limits = parse_qos(matches)?;
+
+    ctx.v2(&format!("Setting QoS limits of {}", uuid));
+    let reply = ctx
+        .client
+        .set_bdev_qos(rpc::SetBdevQosRequest {
+            uuid: uuid.clone(),
+            limits,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Set QoS limits of {}", uuid));
+    Ok(())
+}
+
+async fn get(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+
+    // there is no call to get the limits, they are part of the listings
+    ctx.v2(&format!("Requesting the QoS limits of {}", uuid));
+    let replicas = ctx
+        .client
+        .list_replicas(rpc::ListReplicasRequest::default())
+        .await?
+        .into_inner()
+        .replicas;
+    let qos = match replicas.into_iter().find(|r| r.uuid == uuid) {
+        Some(replica) => replica.qos,
+        None => ctx
+            .client
+            .list_nexus(rpc::ListNexusRequest::default())
+            .await?
+            .into_inner()
+            .nexus_list
+            .into_iter()
+            .find(|n| n.uuid == uuid)
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "Replica or nexus {} not found",
+                    uuid
+                ))
+            })?
+            .qos,
+    }
+    .unwrap_or_default();
+
+    if ctx.json(&qos) {
+        return Ok(());
+    }
+    ctx.print_list(
+        vec![">RW_IOPS", ">RW_MBPS", ">R_MBPS", ">W_MBPS"],
+        vec![vec![
+            qos.rw_ios_per_sec.to_string(),
+            qos.rw_mbytes_per_sec.to_string(),
+            qos.r_mbytes_per_sec.to_string(),
+            qos.w_mbytes_per_sec.to_string(),
+        ]],
+    );
+    Ok(())
+}
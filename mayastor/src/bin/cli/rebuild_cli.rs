@@ -38,6 +38,14 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .required(true)
                 .index(2)
                 .help("uri of child to start rebuilding"),
+        )
+        .arg(
+            Arg::with_name("source")
+                .short("s")
+                .long("source")
+                .takes_value(true)
+                .value_name("URI")
+                .help("uri of the child to rebuild from"),
         );
 
     let stop = SubCommand::with_name("stop")
@@ -136,16 +144,22 @@ async fn start(
 ) -> Result<(), Status> {
     let uuid = matches.value_of("uuid").unwrap().to_string();
     let uri = matches.value_of("uri").unwrap().to_string();
+    let source = matches.value_of("source").unwrap_or("").to_string();
 
-    ctx.client
+    let reply = ctx
+        .client
         .start_rebuild(rpc::StartRebuildRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
+            source,
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!(
-        "Starting rebuild of child {} on nexus {}",
-        uri, uuid
+        "Starting rebuild of child {} on nexus {} in operation {}",
+        uri,
+        uuid,
+        reply.get_ref().id
     ));
     Ok(())
 }
@@ -157,12 +171,14 @@ async fn stop(
     let uuid = matches.value_of("uuid").unwrap().to_string();
     let uri = matches.value_of("uri").unwrap().to_string();
 
-    ctx.client
+    let reply = ctx
+        .client
         .stop_rebuild(rpc::StopRebuildRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!(
         "Stopping rebuild of child {} on nexus {}",
         uri, uuid
@@ -177,12 +193,14 @@ async fn pause(
     let uuid = matches.value_of("uuid").unwrap().to_string();
     let uri = matches.value_of("uri").unwrap().to_string();
 
-    ctx.client
+    let reply = ctx
+        .client
         .pause_rebuild(rpc::PauseRebuildRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!(
         "Pausing rebuild of child {} on nexus {}",
         uri, uuid
@@ -197,12 +215,14 @@ async fn resume(
     let uuid = matches.value_of("uuid").unwrap().to_string();
     let uri = matches.value_of("uri").unwrap().to_string();
 
-    ctx.client
+    let reply = ctx
+        .client
         .resume_rebuild(rpc::ResumeRebuildRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     ctx.v1(&format!(
         "Resuming rebuild of child {} on nexus {}",
         uri, uuid
@@ -229,6 +249,9 @@ async fn state(
         })
         .await?
         .into_inner();
+    if ctx.json(&response) {
+        return Ok(());
+    }
     println!("{}", response.state);
     Ok(())
 }
@@ -252,6 +275,9 @@ async fn progress(
         })
         .await?
         .into_inner();
+    if ctx.json(&response) {
+        return Ok(());
+    }
     println!("{}% complete", response.progress);
     Ok(())
 }
@@ -1,10 +1,19 @@
+use std::collections::HashMap;
+
 use byte_unit::Byte;
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use tonic::{Code, Status};
 
 use ::rpc::mayastor as rpc;
 
-use crate::{context::Context, parse_size};
+use crate::{
+    context::Context,
+    parse_portal,
+    parse_qos,
+    parse_size,
+    portal_args,
+    qos_args,
+};
 
 pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
     let create = SubCommand::with_name("create")
@@ -40,7 +49,28 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .short("t")
                 .long("thin")
                 .takes_value(false)
-                .help("Whether replica is thin provisioned (default false)"));
+                .help("Whether replica is thin provisioned (default false)"))
+        .args(&qos_args());
+
+    let clone = SubCommand::with_name("clone")
+        .about("Create replica from a snapshot")
+        .arg(
+            Arg::with_name("snapshot")
+                .required(true)
+                .index(1)
+                .help("Name of the snapshot"))
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(2)
+                .help("Unique replica uuid"))
+        .arg(
+            Arg::with_name("protocol")
+                .short("p")
+                .long("protocol")
+                .takes_value(true)
+                .value_name("PROTOCOL")
+                .help("Name of a protocol (nvmf, iSCSI) used for sharing the replica (default none)"));
 
     let destroy = SubCommand::with_name("destroy")
         .about("Destroy replica")
@@ -48,8 +78,15 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("uuid")
                 .required(true)
                 .index(1)
-                .help("Replica uuid"),
-        );
+                .help("Replica uuid"))
+        .arg(
+            Arg::with_name("dependents")
+                .short("d")
+                .long("dependents")
+                .takes_value(true)
+                .value_name("POLICY")
+                .possible_values(&["refuse", "flatten", "cascade"])
+                .help("What to do with clones of a snapshot replica (default refuse)"));
 
     let share = SubCommand::with_name("share").about("Share or unshare replica")
         .arg(
@@ -61,7 +98,77 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("protocol")
                 .required(true)
                 .index(2)
-                .help("Name of a protocol (nvmf, iscsi) used for sharing or \"none\" to unshare the replica"));
+                .help("Name of a protocol (nvmf, iscsi) used for sharing or \"none\" to unshare the replica"))
+        .args(&portal_args());
+
+    let reshare = SubCommand::with_name("reshare")
+        .about("Share replica over another protocol, keeping the old share for a grace period")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("Replica uuid"))
+        .arg(
+            Arg::with_name("protocol")
+                .required(true)
+                .index(2)
+                .help("Name of the new protocol (nvmf, iscsi)"))
+        .arg(
+            Arg::with_name("grace")
+                .short("g")
+                .long("grace")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Seconds to keep the old share (default 60)"));
+
+    let switch = SubCommand::with_name("switch")
+        .about("Share replica over another protocol in place of its share, switching the local nexuses using it")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("Replica uuid"))
+        .arg(
+            Arg::with_name("protocol")
+                .required(true)
+                .index(2)
+                .help("Name of the new protocol (nvmf, iscsi) or \"none\" to unshare the replica"));
+
+    let qos = SubCommand::with_name("qos")
+        .about("Set the QoS rate limits of a replica, no limits remove them")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("Replica uuid"),
+        )
+        .args(&qos_args());
+
+    let metadata = SubCommand::with_name("metadata")
+        .about("Show the metadata of a replica, or set it (an empty value removes the key)")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("Replica uuid"))
+        .arg(
+            Arg::with_name("set")
+                .index(2)
+                .multiple(true)
+                .value_name("KEY=VALUE")
+                .help("Keys and values to set"));
+
+    let diff = SubCommand::with_name("diff")
+        .about("List ranges written since a snapshot was taken")
+        .arg(
+            Arg::with_name("target")
+                .required(true)
+                .index(1)
+                .help("Snapshot or replica to list the written ranges of"),
+        )
+        .arg(Arg::with_name("base").required(false).index(2).help(
+            "Older snapshot to compare with (default all ranges holding data)",
+        ));
 
     SubCommand::with_name("replica")
         .settings(&[
@@ -71,9 +178,30 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         ])
         .about("Replica management")
         .subcommand(create)
+        .subcommand(clone)
         .subcommand(destroy)
         .subcommand(share)
-        .subcommand(SubCommand::with_name("list").about("List replicas"))
+        .subcommand(reshare)
+        .subcommand(switch)
+        .subcommand(qos)
+        .subcommand(metadata)
+        .subcommand(diff)
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List replicas")
+                .arg(
+                    Arg::with_name("pool")
+                        .long("pool")
+                        .takes_value(true)
+                        .help("Only the replicas of the pool"),
+                )
+                .arg(
+                    Arg::with_name("volume")
+                        .long("volume")
+                        .takes_value(true)
+                        .help("Only the replicas of the volume"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("stats").about("IO stats of replicas"),
         )
@@ -85,9 +213,15 @@ pub async fn handler(
 ) -> Result<(), Status> {
     match matches.subcommand() {
         ("create", Some(args)) => replica_create(ctx, &args).await,
+        ("clone", Some(args)) => replica_clone(ctx, &args).await,
         ("destroy", Some(args)) => replica_destroy(ctx, &args).await,
         ("list", Some(args)) => replica_list(ctx, &args).await,
         ("share", Some(args)) => replica_share(ctx, &args).await,
+        ("reshare", Some(args)) => replica_reshare(ctx, &args).await,
+        ("switch", Some(args)) => replica_switch(ctx, &args).await,
+        ("qos", Some(args)) => replica_qos(ctx, &args).await,
+        ("metadata", Some(args)) => replica_metadata(ctx, &args).await,
+        ("diff", Some(args)) => replica_diff(ctx, &args).await,
         ("stats", Some(args)) => replica_stat(ctx, &args).await,
         (cmd, _) => {
             Err(Status::not_found(format!("command {} does not exist", cmd)))
@@ -105,6 +239,7 @@ async fn replica_create(
         .map_err(|s| Status::invalid_argument(format!("Bad size '{}'", s)))?;
     let thin = matches.is_present("thin");
     let share = parse_replica_protocol(matches.value_of("protocol"))?;
+    let qos = parse_qos(matches)?;
 
     ctx.v2(&format!("Creating replica {} on pool {}", uuid, pool));
     let rq = rpc::CreateReplicaRequest {
@@ -113,8 +248,35 @@ async fn replica_create(
         thin,
         share,
         size: size.get_bytes() as u64,
+        qos,
+        idempotency_key: String::new(),
     };
     let resp = ctx.client.create_replica(rq).await?;
+    ctx.json(resp.get_ref());
+    ctx.v1(&format!("Created {}", resp.get_ref().uri));
+    Ok(())
+}
+
+async fn replica_clone(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let snapshot = matches.value_of("snapshot").unwrap().to_owned();
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let share = parse_replica_protocol(matches.value_of("protocol"))?;
+
+    ctx.v2(&format!(
+        "Creating replica {} from snapshot {}",
+        uuid, snapshot
+    ));
+    let rq = rpc::CreateReplicaFromSnapshotRequest {
+        uuid,
+        snapshot,
+        share,
+        idempotency_key: String::new(),
+    };
+    let resp = ctx.client.create_replica_from_snapshot(rq).await?;
+    ctx.json(resp.get_ref());
     ctx.v1(&format!("Created {}", resp.get_ref().uri));
     Ok(())
 }
@@ -124,23 +286,38 @@ async fn replica_destroy(
     matches: &ArgMatches<'_>,
 ) -> Result<(), Status> {
     let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let dependents = parse_dependents(matches.value_of("dependents"))?;
 
     ctx.v2(&format!("Destroying replica {}", uuid));
-    ctx.client
+    let reply = ctx
+        .client
         .destroy_replica(rpc::DestroyReplicaRequest {
             uuid,
+            dependents: dependents as i32,
+            idempotency_key: String::new(),
         })
         .await?;
+    ctx.json(reply.get_ref());
     Ok(())
 }
 
 async fn replica_list(
     mut ctx: Context,
-    _matches: &ArgMatches<'_>,
+    matches: &ArgMatches<'_>,
 ) -> Result<(), Status> {
     ctx.v2("Requesting a list of replicas");
 
-    let resp = ctx.client.list_replicas(rpc::Null {}).await?;
+    let resp = ctx
+        .client
+        .list_replicas(rpc::ListReplicasRequest {
+            pool: matches.value_of("pool").unwrap_or_default().to_owned(),
+            volume: matches.value_of("volume").unwrap_or_default().to_owned(),
+            ..Default::default()
+        })
+        .await?;
+    if ctx.json(resp.get_ref()) {
+        return Ok(());
+    }
     let replicas = &resp.get_ref().replicas;
     if replicas.is_empty() {
         ctx.v1("No replicas found");
@@ -178,6 +355,7 @@ async fn replica_share(
 ) -> Result<(), Status> {
     let uuid = matches.value_of("uuid").unwrap().to_owned();
     let share = parse_replica_protocol(matches.value_of("protocol"))?;
+    let portal = parse_portal(matches)?;
 
     ctx.v2(&format!("Sharing replica {} on {}", uuid, share));
 
@@ -186,12 +364,196 @@ async fn replica_share(
         .share_replica(rpc::ShareReplicaRequest {
             uuid,
             share,
+            portal,
+            idempotency_key: String::new(),
         })
         .await?;
+    ctx.json(resp.get_ref());
     ctx.v1(&format!("Shared {}", resp.get_ref().uri));
     Ok(())
 }
 
+async fn replica_reshare(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let share = parse_replica_protocol(matches.value_of("protocol"))?;
+    let grace_period = match matches.value_of("grace") {
+        Some(grace) => grace.parse().map_err(|_| {
+            Status::invalid_argument("Invalid value of grace period")
+        })?,
+        None => 0,
+    };
+
+    ctx.v2(&format!("Resharing replica {} on {}", uuid, share));
+
+    let resp = ctx
+        .client
+        .reshare_replica(rpc::ReshareReplicaRequest {
+            uuid,
+            share,
+            grace_period,
+        })
+        .await?;
+    ctx.json(resp.get_ref());
+    ctx.v1(&format!("Shared {}", resp.get_ref().uri));
+    if !resp.get_ref().previous_uri.is_empty() {
+        ctx.v1(&format!("Unsharing {}", resp.get_ref().previous_uri));
+    }
+    Ok(())
+}
+
+async fn replica_switch(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let share = parse_replica_protocol(matches.value_of("protocol"))?;
+
+    ctx.v2(&format!("Switching replica {} to {}", uuid, share));
+
+    let resp = ctx
+        .client
+        .switch_replica_share(rpc::SwitchReplicaShareRequest {
+            uuid,
+            share,
+        })
+        .await?;
+    ctx.json(resp.get_ref());
+    for nexus in &resp.get_ref().nexuses {
+        ctx.v2(&format!("Switched the child of nexus {}", nexus));
+    }
+    ctx.v1(&format!(
+        "Switched {} to {}",
+        resp.get_ref().previous_uri,
+        resp.get_ref().uri
+    ));
+    Ok(())
+}
+
+async fn replica_qos(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let limits = parse_qos(matches)?;
+
+    ctx.v2(&format!("Setting QoS limits of replica {}", uuid));
+    let reply = ctx
+        .client
+        .set_bdev_qos(rpc::SetBdevQosRequest {
+            uuid: uuid.clone(),
+            limits,
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Set QoS limits of replica {}", uuid));
+    Ok(())
+}
+
+async fn replica_metadata(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let set = matches
+        .values_of("set")
+        .map(|pairs| {
+            pairs
+                .map(|pair| match pair.find('=') {
+                    Some(idx) => Ok((
+                        pair[.. idx].to_string(),
+                        pair[idx + 1 ..].to_string(),
+                    )),
+                    None => Err(Status::invalid_argument(format!(
+                        "Bad metadata '{}', expected KEY=VALUE",
+                        pair
+                    ))),
+                })
+                .collect::<Result<HashMap<_, _>, _>>()
+        })
+        .transpose()?;
+
+    let reply = match set {
+        Some(metadata) => {
+            ctx.v2(&format!("Setting metadata of replica {}", uuid));
+            ctx.client
+                .set_replica_metadata(rpc::SetReplicaMetadataRequest {
+                    uuid,
+                    metadata,
+                })
+                .await?
+        }
+        None => {
+            ctx.v2(&format!("Requesting metadata of replica {}", uuid));
+            ctx.client
+                .get_replica_metadata(rpc::GetReplicaMetadataRequest {
+                    uuid,
+                })
+                .await?
+        }
+    };
+    if ctx.json(reply.get_ref()) {
+        return Ok(());
+    }
+
+    let mut table = reply
+        .get_ref()
+        .metadata
+        .iter()
+        .map(|(key, value)| vec![key.clone(), value.clone()])
+        .collect::<Vec<_>>();
+    table.sort();
+    ctx.print_list(vec!["KEY", "VALUE"], table);
+    Ok(())
+}
+
+async fn replica_diff(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let target = matches.value_of("target").unwrap().to_owned();
+    let base = matches.value_of("base").unwrap_or("").to_owned();
+
+    ctx.v2(&format!(
+        "Requesting ranges of {} changed since {}",
+        target, base
+    ));
+    let mut stream = ctx
+        .client
+        .diff_snapshots(rpc::DiffSnapshotsRequest {
+            base,
+            target,
+        })
+        .await?
+        .into_inner();
+
+    let mut ranges = Vec::new();
+    while let Some(mut reply) = stream.message().await? {
+        ranges.append(&mut reply.ranges);
+    }
+    if ctx.json(&ranges) {
+        return Ok(());
+    }
+
+    let table = ranges
+        .iter()
+        .map(|r| {
+            vec![
+                r.offset.to_string(),
+                ctx.units(Byte::from_bytes(r.length.into())),
+            ]
+        })
+        .collect::<Vec<_>>();
+    if table.is_empty() {
+        ctx.v1("No changed ranges found");
+        return Ok(());
+    }
+    ctx.print_list(vec![">OFFSET", ">LENGTH"], table);
+    Ok(())
+}
+
 async fn replica_stat(
     mut ctx: Context,
     _matches: &ArgMatches<'_>,
@@ -199,6 +561,9 @@ async fn replica_stat(
     ctx.v2("Requesting replicas stats");
 
     let resp = ctx.client.stat_replicas(rpc::Null {}).await?;
+    if ctx.json(resp.get_ref()) {
+        return Ok(());
+    }
     let replicas = &resp.get_ref().replicas;
     if replicas.is_empty() {
         ctx.v1("No replicas have been created");
@@ -226,6 +591,23 @@ async fn replica_stat(
     Ok(())
 }
 
+/// parse what to do with the clones of a snapshot replica being destroyed
+pub(crate) fn parse_dependents(
+    policy: Option<&str>,
+) -> Result<rpc::DestroyReplicaDependents, Status> {
+    match policy {
+        None | Some("refuse") => {
+            Ok(rpc::DestroyReplicaDependents::DependentsRefuse)
+        }
+        Some("flatten") => Ok(rpc::DestroyReplicaDependents::DependentsFlatten),
+        Some("cascade") => Ok(rpc::DestroyReplicaDependents::DependentsCascade),
+        Some(_) => Err(Status::new(
+            Code::InvalidArgument,
+            "Invalid value of dependents policy".to_owned(),
+        )),
+    }
+}
+
 fn parse_replica_protocol(pcol: Option<&str>) -> Result<i32, Status> {
     match pcol {
         None => Ok(rpc::ShareProtocolReplica::ReplicaNone as i32),
@@ -0,0 +1,149 @@
+//!
+//! methods to take, list and destroy snapshots of nexuses and replicas
+
+use byte_unit::Byte;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use tonic::Status;
+
+use ::rpc::mayastor as rpc;
+
+use crate::{context::Context, replica_cli::parse_dependents};
+
+pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
+    let list = SubCommand::with_name("list").about("list the snapshots");
+
+    let create = SubCommand::with_name("create")
+        .about("take a snapshot of a nexus and all its replicas")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of the nexus"),
+        );
+
+    let destroy = SubCommand::with_name("destroy")
+        .about("destroy a snapshot of a replica")
+        .arg(
+            Arg::with_name("name")
+                .required(true)
+                .index(1)
+                .help("name of the snapshot"),
+        )
+        .arg(
+            Arg::with_name("dependents")
+                .short("d")
+                .long("dependents")
+                .takes_value(true)
+                .value_name("POLICY")
+                .possible_values(&["refuse", "flatten", "cascade"])
+                .help("What to do with its clones (default refuse)"),
+        );
+
+    SubCommand::with_name("snapshot")
+        .settings(&[
+            AppSettings::SubcommandRequiredElseHelp,
+            AppSettings::ColoredHelp,
+            AppSettings::ColorAlways,
+        ])
+        .about("Snapshot management")
+        .subcommand(list)
+        .subcommand(create)
+        .subcommand(destroy)
+}
+
+pub async fn handler(
+    ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    match matches.subcommand() {
+        ("list", Some(args)) => list(ctx, &args).await,
+        ("create", Some(args)) => create(ctx, &args).await,
+        ("destroy", Some(args)) => destroy(ctx, &args).await,
+        (cmd, _) => {
+            Err(Status::not_found(format!("command {} does not exist", cmd)))
+        }
+    }
+}
+
+async fn list(
+    mut ctx: Context,
+    _matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    ctx.v2("Requesting a list of snapshots");
+
+    let resp = ctx.client.list_snapshots(rpc::Null {}).await?;
+    if ctx.json(resp.get_ref()) {
+        return Ok(());
+    }
+    let snapshots = &resp.get_ref().snapshots;
+    if snapshots.is_empty() {
+        ctx.v1("No snapshots found");
+        return Ok(());
+    }
+
+    ctx.v2("Found following snapshots:");
+
+    let table = snapshots
+        .iter()
+        .map(|s| {
+            let size = ctx.units(Byte::from_bytes(s.size.into()));
+            vec![
+                s.pool.clone(),
+                s.name.clone(),
+                s.replica.clone(),
+                s.snapshot.to_string(),
+                size,
+                s.clones.join(","),
+            ]
+        })
+        .collect();
+    ctx.print_list(
+        vec!["POOL", "NAME", "REPLICA", ">TIME", ">SIZE", "CLONES"],
+        table,
+    );
+
+    Ok(())
+}
+
+async fn create(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+
+    ctx.v2(&format!("Taking a snapshot of nexus {}", uuid));
+    let resp = ctx
+        .client
+        .create_nexus_snapshot(rpc::CreateNexusSnapshotRequest {
+            uuid: uuid.clone(),
+        })
+        .await?;
+    ctx.json(resp.get_ref());
+    ctx.v1(&format!(
+        "Created snapshot {} of nexus {}",
+        resp.get_ref().snapshot,
+        uuid
+    ));
+    Ok(())
+}
+
+async fn destroy(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let name = matches.value_of("name").unwrap().to_owned();
+    let dependents = parse_dependents(matches.value_of("dependents"))?;
+
+    ctx.v2(&format!("Destroying snapshot {}", name));
+    let reply = ctx
+        .client
+        .destroy_replica(rpc::DestroyReplicaRequest {
+            uuid: name.clone(),
+            dependents: dependents as i32,
+            idempotency_key: String::new(),
+        })
+        .await?;
+    ctx.json(reply.get_ref());
+    ctx.v1(&format!("Destroyed snapshot {}", name));
+    Ok(())
+}
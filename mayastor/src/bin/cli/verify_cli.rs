@@ -0,0 +1,80 @@
+//!
+//! read-only check of the nexus labels, pool metadata and config of a node
+
+use super::context::Context;
+use ::rpc::mayastor as rpc;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use tonic::Status;
+
+pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("verify")
+        .settings(&[AppSettings::ColoredHelp, AppSettings::ColorAlways])
+        .about(
+            "Check the nexus labels, pool metadata and config without \
+             changing them",
+        )
+        .arg(
+            Arg::with_name("nexus")
+                .long("nexus")
+                .takes_value(true)
+                .value_name("UUID")
+                .help("Only check the labels of the children of this nexus"),
+        )
+        .arg(
+            Arg::with_name("pool")
+                .long("pool")
+                .takes_value(true)
+                .value_name("NAME")
+                .help("Only check the metadata of this pool"),
+        )
+}
+
+pub async fn handler(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let request = rpc::VerifyMetadataRequest {
+        nexus: matches.value_of("nexus").unwrap_or_default().to_string(),
+        pool: matches.value_of("pool").unwrap_or_default().to_string(),
+    };
+
+    ctx.v2("Verifying the metadata");
+    let reply = ctx.client.verify_metadata(request).await?.into_inner();
+    if ctx.json(&reply) {
+        return Ok(());
+    }
+    if reply.findings.is_empty() {
+        ctx.v1(&format!("Checked {} objects, no findings", reply.checked));
+        return Ok(());
+    }
+
+    let table = reply
+        .findings
+        .iter()
+        .map(|f| {
+            vec![
+                severity(f.severity).to_string(),
+                f.object.clone(),
+                f.message.clone(),
+                f.action.clone(),
+            ]
+        })
+        .collect();
+    ctx.print_list(vec!["SEVERITY", "OBJECT", "MESSAGE", "ACTION"], table);
+    ctx.v1(&format!(
+        "Checked {} objects, {} findings",
+        reply.checked,
+        reply.findings.len()
+    ));
+
+    Ok(())
+}
+
+fn severity(idx: i32) -> &'static str {
+    match rpc::FindingSeverity::from_i32(idx) {
+        Some(rpc::FindingSeverity::FindingInfo) => "info",
+        Some(rpc::FindingSeverity::FindingWarning) => "warning",
+        Some(rpc::FindingSeverity::FindingError) => "error",
+        None => "unknown",
+    }
+}
@@ -9,8 +9,10 @@ use std::{
 use async_trait::async_trait;
 use futures::channel::oneshot;
 use nix::errno::Errno;
+use snafu::ResultExt;
 
 use spdk_sys::{
+    bdev_nvme_get_ctrlr,
     spdk_bdev,
     spdk_bdev_first,
     spdk_bdev_get_aliases,
@@ -20,25 +22,50 @@ use spdk_sys::{
     spdk_bdev_get_name,
     spdk_bdev_get_num_blocks,
     spdk_bdev_get_product_name,
+    spdk_bdev_get_qos_rate_limits,
     spdk_bdev_get_uuid,
+    spdk_bdev_histogram_enable,
+    spdk_bdev_histogram_get,
     spdk_bdev_io_stat,
     spdk_bdev_io_type_supported,
     spdk_bdev_next,
     spdk_bdev_open,
+    spdk_bdev_set_qos_rate_limits,
+    spdk_get_ticks_hz,
+    spdk_histogram_data,
+    spdk_nvme_cmd,
+    spdk_nvme_cpl,
+    spdk_nvme_ctrlr_cmd_admin_raw,
+    spdk_nvme_ctrlr_get_data,
+    spdk_nvme_ctrlr_get_max_xfer_size,
     spdk_uuid_generate,
+    SPDK_BDEV_QOS_NUM_RATE_LIMIT_TYPES,
+    SPDK_BDEV_QOS_RW_BPS_RATE_LIMIT,
+    SPDK_BDEV_QOS_RW_IOPS_RATE_LIMIT,
+    SPDK_BDEV_QOS_R_BPS_RATE_LIMIT,
+    SPDK_BDEV_QOS_W_BPS_RATE_LIMIT,
 };
 
 use crate::{
-    bdev::nexus::instances,
+    bdev::{
+        nexus::{instances, nexus_hooks, nexus_resolver},
+        NexusStatus,
+    },
     core::{
         share::{Protocol, Share},
         uuid::Uuid,
         CoreError,
         CoreError::{ShareIscsi, ShareNvmf},
         Descriptor,
+        DmaBuf,
+        GetHistogram,
+        NvmeTelemetryAlloc,
+        SetHistogram,
+        SetQos,
     },
-    ffihelper::{cb_arg, AsStr},
-    subsys::NvmfSubsystem,
+    ffihelper::{cb_arg, done_errno_cb, AsStr, ErrnoResult},
+    iscsi_portal,
+    subsys::{events, NvmfSubsystem},
     target::{iscsi, nvmf, Side},
 };
 
@@ -50,6 +77,73 @@ pub struct Stat {
     pub bytes_written: u64,
 }
 
+/// QoS rate limits of a bdev, where 0 means no limit. The IOPS limit must
+/// be a multiple of 1000.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct QosLimits {
+    pub rw_ios_per_sec: u64,
+    pub rw_mbytes_per_sec: u64,
+    pub r_mbytes_per_sec: u64,
+    pub w_mbytes_per_sec: u64,
+}
+
+/// number of buckets per power of two range of a latency histogram, as a
+/// shift (SPDK_HISTOGRAM_BUCKET_SHIFT_DEFAULT)
+const HISTOGRAM_BUCKET_SHIFT: u32 = 7;
+
+/// Bucket of a latency histogram, counting the IOs which completed in
+/// [start_ns, end_ns).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyBucket {
+    pub start_ns: u64,
+    pub end_ns: u64,
+    pub count: u64,
+}
+
+/// the get log page admin command and the telemetry host-initiated log
+/// page, whose log specific field makes the controller capture new data,
+/// from nvme_spec.h
+const NVME_OPC_GET_LOG_PAGE: u16 = 0x02;
+const NVME_LOG_TELEMETRY_HOST_INITIATED: u32 = 0x07;
+const NVME_TELEMETRY_CREATE_HOST_DATA: u32 = 1 << 8;
+const NVME_GLOBAL_NS_TAG: u32 = 0xffff_ffff;
+
+/// the telemetry log consists of blocks of this size, the first of which is
+/// the header telling the last block of each data area
+const NVME_TELEMETRY_BLOCK_SIZE: usize = 512;
+
+/// upper limit of a single transfer of the telemetry log, which is lowered
+/// to the maximum transfer size of the controller
+const NVME_TELEMETRY_MAX_CHUNK: usize = 128 * 1024;
+
+/// completion of an admin command, whether it succeeded
+extern "C" fn nvme_admin_cb(arg: *mut c_void, cpl: *const spdk_nvme_cpl) {
+    let sender = unsafe { Box::from_raw(arg as *mut oneshot::Sender<bool>) };
+    let status = unsafe { (*cpl).__bindgen_anon_1.status };
+    let _ = sender.send(status.sct() == 0 && status.sc() == 0);
+}
+
+/// callback for spdk_bdev_histogram_get(), the histogram is merged into the
+/// buckets passed in by the caller
+extern "C" fn histogram_get_cb(
+    sender_ptr: *mut c_void,
+    errno: i32,
+    _histogram: *mut spdk_histogram_data,
+) {
+    done_errno_cb(sender_ptr, errno);
+}
+
+/// End of the bucket of a histogram in ticks, which is exclusive
+/// (__spdk_histogram_data_get_bucket_start() of the next bucket).
+fn histogram_bucket_end(range: u32, index: u64) -> u64 {
+    let index = index + 1;
+    if range > 0 {
+        (1u64 << (range + HISTOGRAM_BUCKET_SHIFT - 1)) + (index << (range - 1))
+    } else {
+        index
+    }
+}
+
 /// Newtype structure that represents a block device. The soundness of the API
 /// is based on the fact that opening and finding of a bdev, returns a valid
 /// bdev or None. Once the bdev is given, the operations on the bdev are safe.
@@ -126,7 +220,11 @@ impl Share for Bdev {
     fn share_uri(&self) -> Option<String> {
         match self.shared() {
             Some(Protocol::Nvmf) => nvmf::get_uri(&self.name()),
-            Some(Protocol::Iscsi) => iscsi::get_uri(Side::Nexus, &self.name()),
+            Some(Protocol::Iscsi) => {
+                let iqn = iscsi::target_name(&self.name());
+                iscsi::get_uri(Side::Nexus, &self.name())
+                    .map(|uri| iscsi_portal::share_uri(&iqn, uri))
+            }
             None => None,
         }
     }
@@ -162,18 +260,31 @@ impl Bdev {
     extern "C" fn hot_remove(ctx: *mut c_void) {
         let bdev = Bdev(NonNull::new(ctx as *mut spdk_bdev).unwrap());
         instances().iter_mut().for_each(|n| {
+            let mut removed = false;
             n.children.iter_mut().for_each(|b| {
                 // note: it would perhaps be wise to close all children
                 // here in one blow to avoid unneeded lookups
                 if b.bdev.as_ref().unwrap().name() == bdev.name() {
                     info!("hot remove {} from {}", b.name, b.parent);
                     b.close();
+                    nexus_resolver::child_faulted(&b.parent, &b.name);
+                    nexus_hooks::child_faulted(
+                        &b.parent,
+                        &b.name,
+                        "hot removed",
+                    );
+                    events::child_faulted(&b.parent, &b.name, "hot removed");
+                    removed = true;
                 }
-            })
+            });
+            if removed && n.status() == NexusStatus::Degraded {
+                events::nexus_degraded(&n.name);
+            }
         });
     }
 
     /// open a bdev by its name in read_write mode.
+    #[track_caller]
     pub fn open_by_name(
         name: &str,
         read_write: bool,
@@ -189,6 +300,7 @@ impl Bdev {
 
     /// open the current bdev, the bdev can be opened multiple times resulting
     /// in a new descriptor for each call.
+    #[track_caller]
     pub fn open(&self, read_write: bool) -> Result<Descriptor, CoreError> {
         let mut descriptor = std::ptr::null_mut();
         let rc = unsafe {
@@ -421,6 +533,242 @@ impl Bdev {
             })
         }
     }
+
+    /// Get the QoS rate limits in effect for the bdev.
+    pub fn qos_limits(&self) -> QosLimits {
+        let mut limits = [0u64; SPDK_BDEV_QOS_NUM_RATE_LIMIT_TYPES as usize];
+        unsafe {
+            spdk_bdev_get_qos_rate_limits(self.0.as_ptr(), limits.as_mut_ptr())
+        };
+
+        QosLimits {
+            rw_ios_per_sec: limits[SPDK_BDEV_QOS_RW_IOPS_RATE_LIMIT as usize],
+            rw_mbytes_per_sec: limits[SPDK_BDEV_QOS_RW_BPS_RATE_LIMIT as usize],
+            r_mbytes_per_sec: limits[SPDK_BDEV_QOS_R_BPS_RATE_LIMIT as usize],
+            w_mbytes_per_sec: limits[SPDK_BDEV_QOS_W_BPS_RATE_LIMIT as usize],
+        }
+    }
+
+    /// Set the QoS rate limits of the bdev, replacing all current limits.
+    /// The limits are enforced by the bdev layer for all IO submitted to the
+    /// bdev, regardless of the descriptor used.
+    pub async fn set_qos_limits(
+        &self,
+        qos: QosLimits,
+    ) -> Result<(), CoreError> {
+        let mut limits = [0u64; SPDK_BDEV_QOS_NUM_RATE_LIMIT_TYPES as usize];
+        limits[SPDK_BDEV_QOS_RW_IOPS_RATE_LIMIT as usize] = qos.rw_ios_per_sec;
+        limits[SPDK_BDEV_QOS_RW_BPS_RATE_LIMIT as usize] =
+            qos.rw_mbytes_per_sec;
+        limits[SPDK_BDEV_QOS_R_BPS_RATE_LIMIT as usize] = qos.r_mbytes_per_sec;
+        limits[SPDK_BDEV_QOS_W_BPS_RATE_LIMIT as usize] = qos.w_mbytes_per_sec;
+
+        let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
+
+        unsafe {
+            spdk_bdev_set_qos_rate_limits(
+                self.0.as_ptr(),
+                limits.as_mut_ptr(),
+                Some(done_errno_cb),
+                cb_arg(sender),
+            );
+        }
+
+        receiver
+            .await
+            .expect("Cancellation is not supported")
+            .context(SetQos {
+                name: self.name(),
+            })
+    }
+
+    /// returns true if the latency of IOs to this bdev is being tallied
+    pub fn histogram_enabled(&self) -> bool {
+        unsafe { self.0.as_ref().internal.histogram_enabled }
+    }
+
+    /// enable or disable the latency histogram of the bdev, disabling it
+    /// discards the IOs tallied so far
+    pub async fn enable_histogram(
+        &self,
+        enable: bool,
+    ) -> Result<(), CoreError> {
+        let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
+
+        unsafe {
+            spdk_bdev_histogram_enable(
+                self.0.as_ptr(),
+                Some(done_errno_cb),
+                cb_arg(sender),
+                enable,
+            );
+        }
+
+        receiver
+            .await
+            .expect("Cancellation is not supported")
+            .context(SetHistogram {
+                name: self.name(),
+            })
+    }
+
+    /// return the non-empty buckets of the latency histogram of the bdev,
+    /// which has to be enabled. SPDK keeps a single histogram for all types
+    /// of IO, so reads and writes are counted together.
+    pub async fn latency_histogram(
+        &self,
+    ) -> Result<Vec<LatencyBucket>, CoreError> {
+        let ranges = 64 - HISTOGRAM_BUCKET_SHIFT + 1;
+        let per_range = 1u64 << HISTOGRAM_BUCKET_SHIFT;
+        let mut buckets = vec![0u64; (ranges as u64 * per_range) as usize];
+        let mut histogram = spdk_histogram_data {
+            bucket_shift: HISTOGRAM_BUCKET_SHIFT,
+            bucket: buckets.as_mut_ptr(),
+        };
+
+        let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
+
+        unsafe {
+            spdk_bdev_histogram_get(
+                self.0.as_ptr(),
+                &mut histogram,
+                Some(histogram_get_cb),
+                cb_arg(sender),
+            );
+        }
+
+        receiver
+            .await
+            .expect("Cancellation is not supported")
+            .context(GetHistogram {
+                name: self.name(),
+            })?;
+
+        let ticks_hz = unsafe { spdk_get_ticks_hz() } as u128;
+        let to_ns =
+            |ticks: u64| (ticks as u128 * 1_000_000_000 / ticks_hz) as u64;
+
+        let mut list = Vec::new();
+        let mut start = 0;
+
+        for range in 0 .. ranges {
+            for index in 0 .. per_range {
+                let end = histogram_bucket_end(range, index);
+                let count =
+                    buckets[(range as u64 * per_range + index) as usize];
+                if count > 0 {
+                    list.push(LatencyBucket {
+                        start_ns: to_ns(start),
+                        end_ns: to_ns(end),
+                        count,
+                    });
+                }
+                start = end;
+            }
+        }
+
+        Ok(list)
+    }
+
+    /// Read the telemetry host-initiated log of an NVMe device, including
+    /// the data areas up to the given one (1 to 3). The controller captures
+    /// new data for it first. The log is transferred in chunks through the
+    /// admin queue of the controller, so it can be read while the bdev is
+    /// claimed, i.e. by a pool.
+    pub async fn nvme_telemetry_log(
+        &self,
+        data_area: u8,
+    ) -> Result<Vec<u8>, CoreError> {
+        let unsupported = || CoreError::NvmeTelemetryUnsupported {
+            name: self.name(),
+        };
+
+        if self.driver() != "nvme" {
+            return Err(unsupported());
+        }
+
+        let ctrlr = unsafe { bdev_nvme_get_ctrlr(self.as_ptr()) };
+        if ctrlr.is_null() {
+            return Err(unsupported());
+        }
+
+        // telemetry requires the log page offset to read it in chunks
+        let cdata = unsafe { &*spdk_nvme_ctrlr_get_data(ctrlr) };
+        if cdata.lpa.telemetry() == 0 || cdata.lpa.edlp() == 0 {
+            return Err(unsupported());
+        }
+
+        let chunk = (unsafe { spdk_nvme_ctrlr_get_max_xfer_size(ctrlr) }
+            as usize)
+            .min(NVME_TELEMETRY_MAX_CHUNK)
+            / NVME_TELEMETRY_BLOCK_SIZE
+            * NVME_TELEMETRY_BLOCK_SIZE;
+
+        let read_chunk = |offset: usize, len: usize, lsp: u32| async move {
+            let mut buf = DmaBuf::new(len, self.alignment()).context(
+                NvmeTelemetryAlloc {
+                    name: self.name(),
+                },
+            )?;
+
+            let dwords = (len / 4) as u32 - 1;
+            let mut cmd = spdk_nvme_cmd::default();
+            cmd.set_opc(NVME_OPC_GET_LOG_PAGE);
+            cmd.nsid = NVME_GLOBAL_NS_TAG;
+            cmd.__bindgen_anon_1.cdw10 =
+                NVME_LOG_TELEMETRY_HOST_INITIATED | lsp | (dwords << 16);
+            cmd.__bindgen_anon_2.cdw11 = dwords >> 16;
+            cmd.cdw12 = offset as u32;
+            cmd.cdw13 = (offset as u64 >> 32) as u32;
+
+            let (s, r) = oneshot::channel::<bool>();
+            let rc = unsafe {
+                spdk_nvme_ctrlr_cmd_admin_raw(
+                    ctrlr,
+                    &mut cmd,
+                    *buf,
+                    len as u32,
+                    Some(nvme_admin_cb),
+                    cb_arg(s),
+                )
+            };
+
+            if rc != 0 || !r.await.unwrap_or(false) {
+                return Err(CoreError::NvmeTelemetryFailed {
+                    name: self.name(),
+                    offset: offset as u64,
+                });
+            }
+            Ok(buf.as_slice().to_vec())
+        };
+
+        // reading the header with the create bit set captures the data
+        let mut log = read_chunk(
+            0,
+            NVME_TELEMETRY_BLOCK_SIZE,
+            NVME_TELEMETRY_CREATE_HOST_DATA,
+        )
+        .await?;
+
+        // the last blocks of the data areas follow the identifier of the log
+        // and the IEEE OUI of the vendor
+        let at = 8 + 2 * (data_area.max(1).min(3) as usize - 1);
+        let last_block = u16::from_le_bytes([log[at], log[at + 1]]) as usize;
+        let size = (last_block + 1) * NVME_TELEMETRY_BLOCK_SIZE;
+
+        while log.len() < size {
+            let len = chunk.min(size - log.len());
+            log.extend(read_chunk(log.len(), len, 0).await?);
+        }
+
+        debug!(
+            "read {} bytes of the NVMe telemetry log of {}",
+            log.len(),
+            self.name()
+        );
+        Ok(log)
+    }
+
     /// returns the first bdev in the list
     pub fn bdev_first() -> Option<Bdev> {
         let bdev = unsafe { spdk_bdev_first() };
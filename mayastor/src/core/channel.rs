@@ -1,20 +1,32 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, panic::Location};
 
 use serde::export::{fmt::Error, Formatter};
 
 use spdk_sys::{spdk_io_channel, spdk_put_io_channel};
 
-pub struct IoChannel(*mut spdk_io_channel);
+use crate::core::tracker::{self, HandleKind};
+
+pub struct IoChannel(*mut spdk_io_channel, u64);
 
 impl IoChannel {
     pub fn from_null_checked(ch: *mut spdk_io_channel) -> Option<IoChannel> {
         if ch.is_null() {
             None
         } else {
-            Some(IoChannel(ch))
+            Some(IoChannel(ch, 0))
         }
     }
 
+    /// record the channel as an open handle on the given bdev
+    pub(crate) fn tracked(
+        mut self,
+        bdev: &str,
+        owner: &Location<'static>,
+    ) -> Self {
+        self.1 = tracker::track(HandleKind::IoChannel, bdev, owner);
+        self
+    }
+
     /// return the ptr
     pub fn as_ptr(&self) -> *mut spdk_io_channel {
         self.0
@@ -51,6 +63,7 @@ impl Drop for IoChannel {
         // temporarily comment out the trace message as it floods the test logs
         // (1 per rebuild IO)
         // trace!("[D] {:?}", self);
+        tracker::untrack(self.1);
         unsafe { spdk_put_io_channel(self.0) }
     }
 }
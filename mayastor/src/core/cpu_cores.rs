@@ -1,3 +1,5 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use spdk_sys::{
     spdk_cpuset,
     spdk_cpuset_set_cpu,
@@ -20,6 +22,10 @@ pub enum Core {
 #[derive(Debug)]
 pub struct Cores(u32);
 
+/// when set, the first core only runs the management thread (gRPC, config
+/// export, stats sampling) and no IO threads are placed on it
+static DEDICATED_MGMT_CORE: AtomicBool = AtomicBool::new(false);
+
 impl Cores {
     pub fn count() -> Self {
         Cores(Self::get_core(Core::Count))
@@ -37,6 +43,63 @@ impl Cores {
         unsafe { spdk_env_get_current_core() }
     }
 
+    /// reserve the first core for management, this has no effect when we
+    /// only run on a single core
+    pub fn set_dedicated_mgmt(dedicated: bool) {
+        DEDICATED_MGMT_CORE.store(dedicated, Ordering::SeqCst);
+    }
+
+    /// returns true if the first core is reserved for management
+    pub fn is_mgmt_dedicated() -> bool {
+        DEDICATED_MGMT_CORE.load(Ordering::SeqCst)
+            && Self::get_core(Core::Count) > 1
+    }
+
+    /// returns true if IO threads may be placed on the given core
+    pub fn is_io_core(core: u32) -> bool {
+        !(Self::is_mgmt_dedicated() && core == Self::first())
+    }
+
+    /// the number of cores IO threads are placed on
+    pub fn io_count() -> u32 {
+        Self::count()
+            .into_iter()
+            .filter(|c| Self::is_io_core(*c))
+            .count() as u32
+    }
+
+    /// parse a list of cores as taken by isolcpus, i.e. "2,3,8-11", into
+    /// the sorted core numbers
+    pub fn parse_list(list: &str) -> Result<Vec<u32>, String> {
+        let parse = |core: &str| {
+            core.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("invalid core {:?}", core.trim()))
+        };
+
+        let mut cores = Vec::new();
+        for range in list.split(',').filter(|r| !r.trim().is_empty()) {
+            match range.find('-') {
+                Some(i) => {
+                    let (first, last) =
+                        (parse(&range[.. i])?, parse(&range[i + 1 ..])?);
+                    if first > last {
+                        return Err(format!("invalid range {:?}", range));
+                    }
+                    cores.extend(first ..= last);
+                }
+                None => cores.push(parse(range)?),
+            }
+        }
+
+        if cores.is_empty() {
+            return Err("no cores given".into());
+        }
+        cores.sort_unstable();
+        cores.dedup();
+        Ok(cores)
+    }
+
     fn get_core(c: Core) -> u32 {
         unsafe {
             match c {
@@ -1,4 +1,4 @@
-use std::{convert::TryFrom, fmt::Debug, os::raw::c_void};
+use std::{convert::TryFrom, fmt::Debug, os::raw::c_void, panic::Location};
 
 use futures::channel::oneshot;
 use serde::export::{fmt::Error, Formatter};
@@ -16,7 +16,14 @@ use spdk_sys::{
 
 use crate::{
     bdev::nexus::nexus_module::NEXUS_MODULE,
-    core::{channel::IoChannel, Bdev, BdevHandle, CoreError, Mthread},
+    core::{
+        channel::IoChannel,
+        tracker::{self, HandleKind},
+        Bdev,
+        BdevHandle,
+        CoreError,
+        Mthread,
+    },
 };
 
 /// NewType around a descriptor, multiple descriptor to the same bdev is
@@ -25,7 +32,7 @@ use crate::{
 /// is. Typically, the target, exporting the bdev will claim the device. In the
 /// case of the nexus, we do not claim the children for exclusive access to
 /// allow for the rebuild to happen across multiple cores.
-pub struct Descriptor(*mut spdk_bdev_desc);
+pub struct Descriptor(*mut spdk_bdev_desc, u64);
 
 impl Descriptor {
     /// returns the underling ptr
@@ -34,12 +41,15 @@ impl Descriptor {
     }
 
     /// Get a channel to the underlying bdev
+    #[track_caller]
     pub fn get_channel(&self) -> Option<IoChannel> {
         let ch = unsafe { spdk_bdev_get_io_channel(self.0) };
         if ch.is_null() {
             None
         } else {
-            IoChannel::from_null_checked(ch)
+            IoChannel::from_null_checked(ch).map(|ch| {
+                ch.tracked(&self.get_bdev().name(), Location::caller())
+            })
         }
     }
 
@@ -79,11 +89,18 @@ impl Descriptor {
 
     /// create a Descriptor from a raw spdk_bdev_desc pointer this is the only
     /// way to create a new descriptor
+    #[track_caller]
     pub fn from_null_checked(desc: *mut spdk_bdev_desc) -> Option<Descriptor> {
         if desc.is_null() {
             None
         } else {
-            Some(Descriptor(desc))
+            let bdev = Bdev::from(unsafe { spdk_bdev_desc_get_bdev(desc) });
+            let id = tracker::track(
+                HandleKind::Descriptor,
+                &bdev.name(),
+                Location::caller(),
+            );
+            Some(Descriptor(desc, id))
         }
     }
 
@@ -171,6 +188,7 @@ extern "C" fn _bdev_close(arg: *mut c_void) {
 impl Drop for Descriptor {
     fn drop(&mut self) {
         trace!("[D] {:?}", self);
+        tracker::untrack(self.1);
         if Mthread::current().unwrap() == Mthread::get_init() {
             unsafe {
                 spdk_bdev_close(self.0);
@@ -12,6 +12,8 @@ use snafu::Snafu;
 
 use spdk_sys::{spdk_dma_free, spdk_dma_zmalloc};
 
+use crate::core::DmaPool;
+
 #[derive(Debug, Snafu, Clone)]
 pub enum DmaError {
     #[snafu(display("Failed to allocate DMA buffer"))]
@@ -25,6 +27,8 @@ pub struct DmaBuf {
     buf: *mut c_void,
     /// the length of the allocated buffer
     length: usize,
+    /// the class of the ['DmaPool'] the buffer is returned to, if any
+    class: Option<usize>,
 }
 
 impl DmaBuf {
@@ -66,10 +70,30 @@ impl DmaBuf {
             Ok(DmaBuf {
                 buf,
                 length: size,
+                class: None,
             })
         }
     }
 
+    /// Take a buffer from the ['DmaPool'] of the core if the size and
+    /// alignment fit in one of its classes, or else allocate it as new does.
+    /// The buffer is zeroed either way.
+    pub fn pooled(size: usize, alignment: u8) -> Result<Self, DmaError> {
+        let class = match DmaPool::class(size, alignment) {
+            Some(class) => class,
+            None => return Self::new(size, alignment),
+        };
+
+        match DmaPool::take(class, size) {
+            Some(buf) => Ok(DmaBuf {
+                buf,
+                length: size,
+                class: Some(class),
+            }),
+            None => Err(DmaError::Alloc {}),
+        }
+    }
+
     /// Return length of the allocated buffer.
     pub fn len(&self) -> usize {
         self.length
@@ -101,6 +125,9 @@ impl Drop for DmaBuf {
         if cfg!(debug_assertions) {
             trace!("dropping Dmabuf {:?}", self);
         }
-        unsafe { spdk_dma_free(self.buf as *mut c_void) }
+        match self.class {
+            Some(class) => DmaPool::put(class, self.buf),
+            None => unsafe { spdk_dma_free(self.buf as *mut c_void) },
+        }
     }
 }
@@ -0,0 +1,140 @@
+//!
+//! Pool of DMA buffers of the sizes IO is commonly done in.
+//!
+//! Rebuilds copy a segment at a time and the metadata of the nexus is read
+//! and written a few blocks at a time, each time allocating a buffer from
+//! huge page memory and freeing it again. Instead, each core keeps the
+//! buffers of a few size classes once they are freed, and hands them out
+//! again for the next IO. A request is served by the smallest class it fits
+//! in, larger ones are allocated as before.
+//!
+//! The buffers of the pool are page aligned, which satisfies the alignment
+//! of all but exotic bdevs. A buffer is returned to the pool of the core it
+//! is dropped on, which keeps the pool free of locking.
+
+use std::{cell::RefCell, os::raw::c_void};
+
+use spdk_sys::{spdk_dma_free, spdk_dma_zmalloc};
+
+/// sizes of the buffers in the pool: a block, a rebuild segment and the
+/// buffer a snapshot is restored through
+const CLASSES: [usize; 3] = [4096, 64 * 1024, 1024 * 1024];
+
+/// buffers of each class allocated up front on a core
+const PREALLOC: [usize; 3] = [64, 16, 2];
+
+/// most buffers of each class kept on a core, the others are freed
+const MAX_CACHED: [usize; 3] = [256, 64, 8];
+
+/// alignment of the buffers of the pool as a power of 2
+pub(crate) const POOL_ALIGNMENT: u8 = 12;
+
+#[derive(Debug, Default)]
+struct Pool {
+    /// free buffers per class
+    free: [Vec<*mut c_void>; 3],
+    filled: bool,
+    hits: u64,
+    misses: u64,
+}
+
+impl Pool {
+    /// allocate the buffers of each class on first use of the pool
+    fn fill(&mut self) {
+        if self.filled {
+            return;
+        }
+        self.filled = true;
+        for (class, count) in PREALLOC.iter().enumerate() {
+            for _ in 0 .. *count {
+                if let Some(buf) = alloc(class) {
+                    self.free[class].push(buf);
+                }
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// free buffers of this core, they are not freed when the thread exits
+    /// as the memory of the environment may be gone by then
+    static POOL: RefCell<Pool> = RefCell::new(Pool::default());
+}
+
+fn alloc(class: usize) -> Option<*mut c_void> {
+    let buf = unsafe {
+        spdk_dma_zmalloc(
+            CLASSES[class] as u64,
+            1 << POOL_ALIGNMENT as usize,
+            std::ptr::null_mut(),
+        )
+    };
+    if buf.is_null() {
+        None
+    } else {
+        Some(buf)
+    }
+}
+
+/// The pool of DMA buffers of the current core
+pub struct DmaPool;
+
+impl DmaPool {
+    /// the class of a buffer of the given size and alignment, none if it
+    /// does not fit in any
+    pub(crate) fn class(size: usize, alignment: u8) -> Option<usize> {
+        if alignment > POOL_ALIGNMENT {
+            return None;
+        }
+        CLASSES.iter().position(|c| size <= *c)
+    }
+
+    /// Take a zeroed buffer of the class from the pool, allocating one if
+    /// the pool has none left.
+    pub(crate) fn take(class: usize, size: usize) -> Option<*mut c_void> {
+        POOL.with(|p| {
+            let mut p = p.borrow_mut();
+            p.fill();
+            match p.free[class].pop() {
+                Some(buf) => {
+                    p.hits += 1;
+                    unsafe { std::ptr::write_bytes(buf as *mut u8, 0, size) };
+                    Some(buf)
+                }
+                None => {
+                    p.misses += 1;
+                    alloc(class)
+                }
+            }
+        })
+    }
+
+    /// give a buffer of the class back to the pool, freeing it if the pool
+    /// has enough of them
+    pub(crate) fn put(class: usize, buf: *mut c_void) {
+        POOL.with(|p| {
+            let mut p = p.borrow_mut();
+            if p.free[class].len() < MAX_CACHED[class] {
+                p.free[class].push(buf);
+            } else {
+                unsafe { spdk_dma_free(buf) };
+            }
+        })
+    }
+
+    /// number of free buffers in the pool of the current core
+    pub fn cached() -> usize {
+        POOL.with(|p| p.borrow().free.iter().map(Vec::len).sum())
+    }
+
+    /// number of buffers taken from the pool of the current core
+    pub fn hits() -> u64 {
+        POOL.with(|p| p.borrow().hits)
+    }
+
+    /// number of buffers allocated as the pool of the current core had none
+    /// of their class left
+    pub fn misses() -> u64 {
+        POOL.with(|p| p.borrow().misses)
+    }
+}
@@ -8,6 +8,7 @@ use std::{
         atomic::{AtomicBool, Ordering::SeqCst},
         Arc,
         Mutex,
+        RwLock,
     },
     time::Duration,
 };
@@ -26,6 +27,7 @@ use spdk_sys::{
     spdk_conf_free,
     spdk_conf_read,
     spdk_conf_set_as_default,
+    spdk_env_thread_wait_all,
     spdk_log_level,
     spdk_log_open,
     spdk_log_set_level,
@@ -41,14 +43,18 @@ use spdk_sys::{
 
 use crate::{
     core::{
+        preflight::{pci_address, Preflight, PreflightMode},
         reactor::{Reactor, ReactorState, Reactors},
+        shutdown,
         Cores,
         Mthread,
     },
     grpc,
-    logger,
+    logger::{self, LogFormat},
+    lvs,
     nats,
-    subsys::Config,
+    stats_store,
+    subsys::{Config, ConfigStore, EtcdStore, FileStore},
     target::iscsi,
 };
 
@@ -99,12 +105,23 @@ pub struct MayastorCliArgs {
     #[structopt(short = "g")]
     /// IP address and port for gRPC server to listen on
     pub grpc_endpoint: Option<String>,
+    #[structopt(long = "grpc-socket")]
+    /// Path of a Unix domain socket for the gRPC server to listen on, for
+    /// co-located agents, in addition to or instead of the IP address
+    pub grpc_socket: Option<String>,
     #[structopt(short = "L")]
     /// Enable logging for sub components
     pub log_components: Vec<String>,
+    #[structopt(long = "log-format", default_value = "default")]
+    /// Format of the log messages: default or json
+    pub log_format: LogFormat,
     #[structopt(short = "m", default_value = "0x1")]
     /// The reactor mask to be used for starting up the instance
     pub reactor_mask: String,
+    #[structopt(short = "l", long = "core-list")]
+    /// The cores to run the reactors on, i.e. "2,3,8-11", instead of the
+    /// reactor mask. The lowest of them runs the management (gRPC)
+    pub core_list: Option<String>,
     #[structopt(short = "N")]
     /// Name of the node where mayastor is running (ID used by control plane)
     pub node_name: Option<String>,
@@ -122,6 +139,9 @@ pub struct MayastorCliArgs {
     #[structopt(short = "u")]
     /// Disable the use of PCIe devices
     pub no_pci: bool,
+    #[structopt(long = "no-huge")]
+    /// Use ordinary memory instead of hugepages (testing only)
+    pub no_huge: bool,
     #[structopt(short = "r", default_value = "/var/tmp/mayastor.sock")]
     /// Path to create the rpc socket
     pub rpc_address: String,
@@ -134,6 +154,26 @@ pub struct MayastorCliArgs {
     #[structopt(long = "env-context")]
     /// pass additional arguments to the EAL environment
     pub env_context: Option<String>,
+    #[structopt(long = "dedicated-mgmt-core")]
+    /// Reserve the first core for management (gRPC, config export and stats)
+    /// and do not run IO on it
+    pub dedicated_mgmt_core: bool,
+    #[structopt(long = "preflight", default_value = "enforce")]
+    /// Check the host before starting: off, warn or enforce
+    pub preflight: PreflightMode,
+    #[structopt(long = "audit-log")]
+    /// File to append the audit log of the gRPC methods to
+    pub audit_log: Option<String>,
+    #[structopt(long = "etcd-endpoints")]
+    /// Comma separated etcd endpoints to store the config of the node in,
+    /// instead of the config file
+    pub etcd_endpoints: Option<String>,
+    #[structopt(long = "etcd-user")]
+    /// User to authenticate to etcd as
+    pub etcd_user: Option<String>,
+    #[structopt(long = "etcd-password-file")]
+    /// File holding the password of the etcd user
+    pub etcd_password_file: Option<String>,
 }
 
 /// Defaults are redefined here in case of using it during tests
@@ -141,17 +181,27 @@ impl Default for MayastorCliArgs {
     fn default() -> Self {
         Self {
             grpc_endpoint: None,
+            grpc_socket: None,
             nats_endpoint: None,
             node_name: None,
             env_context: None,
             reactor_mask: "0x1".into(),
+            core_list: None,
             mem_size: 0,
             rpc_address: "/var/tmp/mayastor.sock".to_string(),
             no_pci: true,
+            no_huge: false,
             log_components: vec![],
+            log_format: LogFormat::Default,
             config: None,
             mayastor_config: None,
             hugedir: None,
+            dedicated_mgmt_core: false,
+            preflight: PreflightMode::Warn,
+            audit_log: None,
+            etcd_endpoints: None,
+            etcd_user: None,
+            etcd_password_file: None,
         }
     }
 }
@@ -161,10 +211,29 @@ impl Default for MayastorCliArgs {
 pub static GLOBAL_RC: Lazy<Arc<Mutex<i32>>> =
     Lazy::new(|| Arc::new(Mutex::new(-1)));
 
+/// name of the node given to the environment which was initialized
+static NODE_NAME: Lazy<RwLock<Option<&'static str>>> =
+    Lazy::new(|| RwLock::new(None));
+
+/// path of the socket of the JSON-RPC server of the initialized environment
+static RPC_ADDR: Lazy<RwLock<Option<&'static str>>> =
+    Lazy::new(|| RwLock::new(None));
+
+/// set the name or the path of the initialized environment, the previous
+/// value is leaked as it may still be referenced
+fn set_global(global: &RwLock<Option<&'static str>>, value: Option<&str>) {
+    *global.write().unwrap() =
+        value.map(|v| &*Box::leak(v.to_string().into_boxed_str()));
+}
+
 /// keep track if we have received a signal already
 pub static SIG_RECIEVED: Lazy<AtomicBool> =
     Lazy::new(|| AtomicBool::new(false));
 
+/// DPDK can be initialized only once per process, so it is kept when the
+/// environment is torn down to be initialized again
+static EAL_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
 /// FFI functions that are needed to initialize the environment
 extern "C" {
     pub fn rte_eal_init(argc: i32, argv: *mut *mut libc::c_char) -> i32;
@@ -206,18 +275,26 @@ pub struct MayastorEnvironment {
     node_name: String,
     nats_endpoint: Option<String>,
     grpc_endpoint: Option<String>,
+    grpc_socket: Option<String>,
+    audit_log: Option<String>,
     mayastor_config: Option<String>,
+    etcd_endpoints: Option<String>,
+    etcd_user: Option<String>,
+    etcd_password_file: Option<String>,
     delay_subsystem_init: bool,
     enable_coredump: bool,
     env_context: Option<String>,
     hugedir: Option<String>,
     hugepage_single_segments: bool,
+    dedicated_mgmt_core: bool,
+    preflight: PreflightMode,
     json_config_file: Option<String>,
     master_core: i32,
     mem_channel: i32,
     pub mem_size: i32,
     pub name: String,
     no_pci: bool,
+    no_huge: bool,
     num_entries: u64,
     num_pci_addr: usize,
     pci_blacklist: Vec<spdk_pci_addr>,
@@ -225,6 +302,7 @@ pub struct MayastorEnvironment {
     print_level: spdk_log_level,
     debug_level: spdk_log_level,
     reactor_mask: String,
+    core_list: Option<String>,
     pub rpc_addr: String,
     shm_id: i32,
     shutdown_cb: spdk_app_shutdown_cb,
@@ -240,18 +318,26 @@ impl Default for MayastorEnvironment {
             node_name: "mayastor-node".into(),
             nats_endpoint: None,
             grpc_endpoint: None,
+            grpc_socket: None,
+            audit_log: None,
             mayastor_config: None,
+            etcd_endpoints: None,
+            etcd_user: None,
+            etcd_password_file: None,
             delay_subsystem_init: false,
             enable_coredump: true,
             env_context: None,
             hugedir: None,
             hugepage_single_segments: false,
+            dedicated_mgmt_core: false,
+            preflight: PreflightMode::Warn,
             json_config_file: None,
             master_core: -1,
             mem_channel: -1,
             mem_size: -1,
             name: "mayastor".into(),
             no_pci: false,
+            no_huge: false,
             num_entries: 0,
             num_pci_addr: 0,
             pci_blacklist: vec![],
@@ -259,6 +345,7 @@ impl Default for MayastorEnvironment {
             print_level: SPDK_LOG_INFO,
             debug_level: SPDK_LOG_INFO,
             reactor_mask: "0x1".into(),
+            core_list: None,
             rpc_addr: "/var/tmp/mayastor.sock".into(),
             shm_id: -1,
             shutdown_cb: None,
@@ -286,6 +373,9 @@ async fn do_shutdown(arg: *mut c_void) {
         warn!("Mayastor stopped non-zero: {}", rc);
     }
 
+    // keep the IO counters up to the last moment
+    stats_store::flush().await;
+
     nats::message_bus_stop();
     iscsi::fini();
 
@@ -297,11 +387,20 @@ async fn do_shutdown(arg: *mut c_void) {
 
 /// main shutdown routine for mayastor
 pub fn mayastor_env_stop(rc: i32) {
+    env_stop(rc, false);
+}
+
+/// stop mayastor, draining the IO of the nexuses first if graceful
+fn env_stop(rc: i32, graceful: bool) {
+    grpc::set_serving(false);
     let r = Reactors::master();
 
     match r.get_state() {
         ReactorState::Running | ReactorState::Delayed | ReactorState::Init => {
             r.send_future(async move {
+                if graceful {
+                    shutdown::drain().await;
+                }
                 do_shutdown(rc as *const i32 as *mut c_void).await;
             });
         }
@@ -313,7 +412,7 @@ pub fn mayastor_env_stop(rc: i32) {
 
 #[inline(always)]
 unsafe extern "C" fn signal_trampoline(_: *mut c_void) {
-    mayastor_env_stop(0);
+    env_stop(0, true);
 }
 
 /// called on SIGINT and SIGTERM
@@ -332,6 +431,26 @@ extern "C" fn mayastor_signal_handler(signo: i32) {
     };
 }
 
+unsafe extern "C" fn reload_trampoline(_: *mut c_void) {
+    if let Err(e) = Config::reload() {
+        error!("Failed to reload the config: {}", e);
+    }
+}
+
+/// called on SIGHUP
+extern "C" fn mayastor_reload_handler() {
+    if SIG_RECIEVED.load(SeqCst) {
+        return;
+    }
+
+    unsafe {
+        spdk_thread_send_critical_msg(
+            Mthread::get_init().0,
+            Some(reload_trampoline),
+        );
+    };
+}
+
 #[derive(Debug)]
 struct SubsystemCtx {
     rpc: CString,
@@ -342,21 +461,36 @@ impl MayastorEnvironment {
     pub fn new(args: MayastorCliArgs) -> Self {
         Self {
             grpc_endpoint: add_default_port(args.grpc_endpoint, 10124),
+            grpc_socket: args.grpc_socket,
             nats_endpoint: add_default_port(args.nats_endpoint, 4222),
             node_name: args.node_name.unwrap_or_else(|| "mayastor-node".into()),
             config: args.config,
             mayastor_config: args.mayastor_config,
+            etcd_endpoints: args.etcd_endpoints,
+            etcd_user: args.etcd_user,
+            etcd_password_file: args.etcd_password_file,
             log_component: args.log_components,
             mem_size: args.mem_size,
             no_pci: args.no_pci,
+            no_huge: args.no_huge,
+            unlink_hugepage: !args.no_huge,
             reactor_mask: args.reactor_mask,
+            core_list: args.core_list,
             rpc_addr: args.rpc_address,
             hugedir: args.hugedir,
             env_context: args.env_context,
+            dedicated_mgmt_core: args.dedicated_mgmt_core,
+            preflight: args.preflight,
+            audit_log: args.audit_log,
             ..Default::default()
         }
     }
 
+    /// name of the node the initialized environment runs on
+    pub fn global_node_name() -> &'static str {
+        NODE_NAME.read().unwrap().unwrap_or("mayastor-node")
+    }
+
     /// configure signal handling
     fn install_signal_handlers(&self) -> Result<()> {
         unsafe {
@@ -373,6 +507,13 @@ impl MayastorEnvironment {
         }
         .unwrap();
 
+        unsafe {
+            signal_hook::register(signal_hook::SIGHUP, || {
+                mayastor_reload_handler()
+            })
+        }
+        .unwrap();
+
         Ok(())
     }
 
@@ -420,7 +561,13 @@ impl MayastorEnvironment {
 
         args.push(CString::new(self.name.clone()).unwrap());
 
-        args.push(CString::new(format!("-c {}", self.reactor_mask)).unwrap());
+        if let Some(list) = &self.core_list {
+            args.push(CString::new(format!("-l {}", list)).unwrap());
+        } else {
+            args.push(
+                CString::new(format!("-c {}", self.reactor_mask)).unwrap(),
+            );
+        }
 
         if self.mem_channel > 0 {
             args.push(
@@ -447,6 +594,10 @@ impl MayastorEnvironment {
             args.push(CString::new("--no-pci").unwrap());
         }
 
+        if self.no_huge {
+            args.push(CString::new("--no-huge").unwrap());
+        }
+
         if self.hugepage_single_segments {
             args.push(CString::new("--single-file-segments").unwrap());
         }
@@ -493,7 +644,9 @@ impl MayastorEnvironment {
         args.push(CString::new("--log-level=lib.eal:6").unwrap());
         args.push(CString::new("--log-level=lib.cryptodev:5").unwrap());
         args.push(CString::new("--log-level=user1:6").unwrap());
-        args.push(CString::new("--match-allocations").unwrap());
+        if !self.no_huge {
+            args.push(CString::new("--match-allocations").unwrap());
+        }
 
         // any additional parameters we want to pass down to the eal. These
         // arguments are not checked or validated.
@@ -592,6 +745,12 @@ impl MayastorEnvironment {
         }
     }
 
+    /// path of the socket the JSON-RPC server of the initialized
+    /// environment listens on
+    pub fn global_rpc_addr() -> &'static str {
+        RPC_ADDR.read().unwrap().unwrap_or("/var/tmp/mayastor.sock")
+    }
+
     /// start the  JSON rpc server which listens only to a local path
     extern "C" fn start_rpc(rc: i32, arg: *mut c_void) {
         let ctx = unsafe { Box::from_raw(arg as *mut SubsystemCtx) };
@@ -614,10 +773,13 @@ impl MayastorEnvironment {
     /// load the config and apply it before any subsystems have started.
     /// there is currently no run time check that enforces this.
     fn load_yaml_config(&self) {
-        let cfg = if let Some(yaml) = &self.mayastor_config {
-            info!("loading YAML config file {}", yaml);
+        let store = self.config_store();
+        let cfg = if let Some(store) = store {
+            info!("loading YAML config from {}", store.location());
             Config::get_or_init(|| {
-                if let Ok(cfg) = Config::read(yaml) {
+                if let Ok(cfg) =
+                    Config::load(store, self.mayastor_config.as_deref())
+                {
                     cfg
                 } else {
                     // if the configuration is invalid exit early
@@ -630,17 +792,122 @@ impl MayastorEnvironment {
         cfg.apply();
     }
 
+    /// the store of the config: etcd when its endpoints are given, otherwise
+    /// the config file, if any
+    fn config_store(&self) -> Option<Box<dyn ConfigStore>> {
+        if let Some(endpoints) = &self.etcd_endpoints {
+            let credentials = self.etcd_user.as_ref().map(|user| {
+                let password = match &self.etcd_password_file {
+                    Some(file) => match std::fs::read_to_string(file) {
+                        Ok(password) => password.trim_end().to_string(),
+                        Err(e) => {
+                            error!(
+                                "Failed to read etcd password file {}: {}",
+                                file, e
+                            );
+                            std::process::exit(-1);
+                        }
+                    },
+                    None => String::new(),
+                };
+                (user.clone(), password)
+            });
+            return Some(Box::new(EtcdStore::new(
+                endpoints,
+                &self.node_name,
+                credentials,
+            )));
+        }
+
+        self.mayastor_config.as_ref().map(|yaml| {
+            Box::new(FileStore::new(yaml)) as Box<dyn ConfigStore>
+        })
+    }
+
+    /// take the placement of the reactors from the config, where it was not
+    /// given on the command line
+    fn reactor_placement(&mut self) {
+        let opts = &Config::get().reactor_opts;
+        if self.core_list.is_none() {
+            self.core_list = opts.core_list.clone();
+        }
+        self.dedicated_mgmt_core |= opts.dedicated_mgmt_core;
+
+        if let Some(list) = &self.core_list {
+            match Cores::parse_list(list) {
+                Ok(cores) => {
+                    self.core_list = Some(
+                        cores
+                            .iter()
+                            .map(|c| c.to_string())
+                            .collect::<Vec<_>>()
+                            .join(","),
+                    );
+                }
+                Err(e) => {
+                    error!("invalid core list {}: {}", list, e);
+                    std::process::exit(-1);
+                }
+            }
+        }
+    }
+
+    /// run the preflight checks against the PCIe devices referenced by the
+    /// YAML configuration
+    fn preflight(&self) {
+        let cfg = Config::get();
+        let mut uris = Vec::new();
+        if let Some(bdevs) = &cfg.base_bdevs {
+            uris.extend(bdevs.iter().map(|b| b.uri.clone()));
+        }
+        if let Some(pools) = &cfg.pools {
+            uris.extend(pools.iter().flat_map(|p| p.disks.clone()));
+        }
+        if let Some(nexus_bdevs) = &cfg.nexus_bdevs {
+            uris.extend(nexus_bdevs.iter().flat_map(|n| n.children.clone()));
+        }
+
+        let mut pci_devices = uris
+            .iter()
+            .filter_map(|u| pci_address(u))
+            .collect::<Vec<_>>();
+        pci_devices.sort();
+        pci_devices.dedup();
+
+        Preflight {
+            no_huge: self.no_huge,
+            no_pci: self.no_pci,
+            mem_size: self.mem_size,
+            hugedir: self.hugedir.clone(),
+            pci_devices,
+        }
+        .check(self.preflight);
+    }
+
     /// initialize the core, call this before all else
     pub fn init(mut self) -> Self {
         // setup the logger as soon as possible
         self.init_logger().unwrap();
+        set_global(&NODE_NAME, Some(&self.node_name));
+        set_global(&RPC_ADDR, Some(&self.rpc_addr));
+
+        *GLOBAL_RC.lock().unwrap() = -1;
+        SIG_RECIEVED.store(false, SeqCst);
+
+        if EAL_INITIALIZED.load(SeqCst) {
+            return self.reinit();
+        }
 
         self.load_yaml_config();
+        self.reactor_placement();
         // load the .ini format file, still here to allow CI passing. There is
         // no real harm of loading this ini file as long as there are no
         // conflicting bdev definitions
         self.read_config_file().unwrap();
 
+        // check the host before DPDK gets a chance to abort on it
+        self.preflight();
+
         // bootstrap DPDK and its magic
         self.initialize_eal();
 
@@ -654,9 +921,31 @@ impl MayastorEnvironment {
             Cores::count().into_iter().count()
         );
 
+        Cores::set_dedicated_mgmt(self.dedicated_mgmt_core);
+        if Cores::is_mgmt_dedicated() {
+            info!("Core {} is dedicated to management", Cores::first());
+        } else if self.dedicated_mgmt_core {
+            warn!("Cannot dedicate a core to management with a single core");
+        }
+
         // setup our signal handlers
         self.install_signal_handlers().unwrap();
+        EAL_INITIALIZED.store(true, SeqCst);
+
+        self.init_reactors()
+    }
+
+    /// initialize the core again after ['shutdown'], keeping DPDK and the
+    /// placement of the reactors of the first initialization
+    fn reinit(self) -> Self {
+        info!("Initializing the environment again");
+        self.load_yaml_config();
+        self.read_config_file().unwrap();
+        self.init_reactors()
+    }
 
+    /// launch the reactors and initialize the subsystems on them
+    fn init_reactors(self) -> Self {
         // allocate a Reactor per core
         Reactors::init();
 
@@ -705,6 +994,36 @@ impl MayastorEnvironment {
         self
     }
 
+    /// Stop the environment initialized with ['init'] and wait until it has
+    /// been torn down: the targets, the bdevs and the subsystems are
+    /// finalized, the reactors stop polling and their threads are destroyed.
+    /// DPDK is kept, such that the environment can be initialized again in
+    /// the same process, with the config it is given then. This is for an
+    /// environment which is driven by the caller, i.e. with
+    /// ['Reactor::block_on'], rather than by ['start']. Returns the exit code
+    /// the environment stopped with.
+    pub fn shutdown(rc: i32) -> i32 {
+        env_stop(rc, false);
+
+        let master = Reactors::master();
+        Mthread::get_init().enter();
+        while master.get_state() != ReactorState::Shutdown {
+            master.poll_once();
+        }
+
+        // wait for the remote reactors to leave their poll loops
+        unsafe { spdk_env_thread_wait_all() };
+        Reactors::fini();
+
+        // the environment initialized next starts from scratch
+        Config::reset();
+        set_global(&NODE_NAME, None);
+        set_global(&RPC_ADDR, None);
+
+        info!("environment stopped");
+        *GLOBAL_RC.lock().unwrap()
+    }
+
     // finalize our environment
     fn fini() {
         unsafe {
@@ -722,6 +1041,11 @@ impl MayastorEnvironment {
     {
         type FutureResult = Result<(), ()>;
         let grpc_endpoint = self.grpc_endpoint.clone();
+        let grpc_socket = self
+            .grpc_socket
+            .as_ref()
+            .map(|path| format!("unix:{}", path));
+        let audit_log = self.audit_log.clone();
         let nats_endpoint = self.nats_endpoint.clone();
         let node_name = self.node_name.clone();
         self.init();
@@ -737,20 +1061,37 @@ impl MayastorEnvironment {
             local
                 .run_until(async {
                     let master = Reactors::current();
+                    // the init is complete by now, we are ready once the
+                    // reactor runs
+                    master.send_future(async { grpc::set_serving(true) });
                     master.send_future(async { f() });
+                    master.send_future(lvs::read_only::monitor());
+                    master.send_future(stats_store::monitor());
                     let mut futures: Vec<
                         Pin<Box<dyn future::Future<Output = FutureResult>>>,
                     > = Vec::new();
-                    if let Some(grpc_ep) = grpc_endpoint.as_ref() {
+                    let endpoints = grpc_endpoint
+                        .iter()
+                        .chain(grpc_socket.iter())
+                        .map(String::as_str)
+                        .collect::<Vec<_>>();
+                    if !endpoints.is_empty()
+                        || !Config::get().grpc.endpoints.is_empty()
+                    {
                         futures.push(Box::pin(grpc::MayastorGrpcServer::run(
-                            grpc_ep,
+                            endpoints,
+                            audit_log.as_deref(),
                         )));
-                        if let Some(nats_ep) = nats_endpoint.as_ref() {
-                            futures.push(Box::pin(nats::message_bus_run(
-                                nats_ep, &node_name, grpc_ep,
-                            )));
-                        }
-                    };
+                    }
+                    // the control plane is told of the TCP endpoint given on
+                    // the command line
+                    if let (Some(grpc_ep), Some(nats_ep)) =
+                        (grpc_endpoint.as_ref(), nats_endpoint.as_ref())
+                    {
+                        futures.push(Box::pin(nats::message_bus_run(
+                            nats_ep, &node_name, grpc_ep,
+                        )));
+                    }
                     futures.push(Box::pin(master));
                     let _out = future::try_join_all(futures).await;
                     info!("reactors stopped");
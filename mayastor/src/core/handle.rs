@@ -2,43 +2,68 @@ use std::{
     convert::TryFrom,
     fmt::Debug,
     mem::ManuallyDrop,
-    os::raw::c_void,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use futures::channel::oneshot;
+use futures::future::{join_all, select, Either};
 use nix::errno::Errno;
 use serde::export::{fmt::Error, Formatter};
 
 use spdk_sys::{
+    spdk_bdev_abort,
     spdk_bdev_desc,
-    spdk_bdev_free_io,
-    spdk_bdev_io,
+    spdk_bdev_flush_blocks,
     spdk_bdev_nvme_admin_passthru,
+    spdk_bdev_nvme_io_passthru,
     spdk_bdev_read,
     spdk_bdev_reset,
     spdk_bdev_write,
     spdk_io_channel,
+    SPDK_BDEV_IO_TYPE_ABORT,
 };
 
 use crate::{
     bdev::nexus::nexus_io::nvme_admin_opc,
-    core::{Bdev, CoreError, Descriptor, DmaBuf, DmaError, IoChannel},
-    ffihelper::cb_arg,
+    core::{
+        io_ctx::Completion,
+        timer::sleep,
+        Bdev,
+        CoreError,
+        Descriptor,
+        DmaBuf,
+        DmaError,
+        IoChannel,
+        IoCtxPool,
+    },
 };
 
+/// An IO of a batch submitted with ['BdevHandle::submit_batch'], the offsets
+/// are in bytes.
+pub enum IoDescriptor<'a> {
+    /// read at the offset into the buffer
+    Read { offset: u64, buf: &'a mut DmaBuf },
+    /// write the buffer at the offset
+    Write { offset: u64, buf: &'a DmaBuf },
+}
+
 /// A handle to a bdev, is an interface to submit IO. The ['Descriptor'] may be
 /// shared between cores freely. The ['IoChannel'] however, must be allocated on
 /// the core where the IO is submitted from.
+///
+/// The reads and writes of a handle with a timeout which take longer than
+/// that are aborted, or the bdev is reset when it can not abort IOs, and fail
+/// with ['CoreError::IoTimeout'] once the IO has been completed by the bdev.
 pub struct BdevHandle {
     pub desc: ManuallyDrop<Arc<Descriptor>>,
     pub channel: ManuallyDrop<IoChannel>,
+    timeout: Option<Duration>,
 }
 
 impl BdevHandle {
     /// open a new bdev handle allocating a new ['Descriptor'] as well as a new
     /// ['IoChannel']
+    #[track_caller]
     pub fn open(
         name: &str,
         read_write: bool,
@@ -68,34 +93,27 @@ impl BdevHandle {
         self.desc.get_bdev()
     }
 
+    /// the time after which the reads and writes of the handle are aborted
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// set the time after which the reads and writes of the handle are
+    /// aborted, none to wait for their completion forever
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
     /// return a tuple to be used directly for read/write operations
     pub fn io_tuple(&self) -> (*mut spdk_bdev_desc, *mut spdk_io_channel) {
         (self.desc.as_ptr(), self.channel.as_ptr())
     }
 
     /// Allocate memory from the memory pool (the mem is zeroed out)
-    /// with given size and proper alignment for the bdev.
+    /// with given size and proper alignment for the bdev, taking it from the
+    /// ['DmaPool'] of the core if it is of a common size.
     pub fn dma_malloc(&self, size: usize) -> Result<DmaBuf, DmaError> {
-        DmaBuf::new(size, self.desc.get_bdev().alignment())
-    }
-
-    /// private io completion callback that sends back the success status of the
-    /// IO. When the IO is freed, it is returned to the memory pool. The
-    /// buffer is not freed.
-    extern "C" fn io_completion_cb(
-        io: *mut spdk_bdev_io,
-        success: bool,
-        arg: *mut c_void,
-    ) {
-        let sender = unsafe {
-            Box::from_raw(arg as *const _ as *mut oneshot::Sender<bool>)
-        };
-
-        unsafe {
-            spdk_bdev_free_io(io);
-        }
-
-        sender.send(success).expect("io completion error");
+        DmaBuf::pooled(size, self.desc.get_bdev().alignment())
     }
 
     /// write the ['DmaBuf'] to the given offset. This function is implemented
@@ -105,28 +123,26 @@ impl BdevHandle {
         offset: u64,
         buffer: &DmaBuf,
     ) -> Result<usize, CoreError> {
-        let (s, r) = oneshot::channel::<bool>();
-        let errno = unsafe {
+        let completion = IoCtxPool::dispatch(|cb, arg| unsafe {
             spdk_bdev_write(
                 self.desc.as_ptr(),
                 self.channel.as_ptr(),
                 **buffer,
                 offset,
                 buffer.len() as u64,
-                Some(Self::io_completion_cb),
-                cb_arg(s),
+                Some(cb),
+                arg,
             )
-        };
+        })
+        .map_err(|errno| CoreError::WriteDispatch {
+            source: Errno::from_i32(errno),
+            offset,
+            len: buffer.len(),
+        })?;
 
-        if errno != 0 {
-            return Err(CoreError::WriteDispatch {
-                source: Errno::from_i32(errno),
-                offset,
-                len: buffer.len(),
-            });
-        }
+        let success = self.complete(completion, offset, buffer.len()).await?;
 
-        if r.await.expect("Failed awaiting write IO") {
+        if success {
             Ok(buffer.len() as usize)
         } else {
             Err(CoreError::WriteFailed {
@@ -142,28 +158,26 @@ impl BdevHandle {
         offset: u64,
         buffer: &mut DmaBuf,
     ) -> Result<usize, CoreError> {
-        let (s, r) = oneshot::channel::<bool>();
-        let errno = unsafe {
+        let completion = IoCtxPool::dispatch(|cb, arg| unsafe {
             spdk_bdev_read(
                 self.desc.as_ptr(),
                 self.channel.as_ptr(),
                 **buffer,
                 offset,
                 buffer.len() as u64,
-                Some(Self::io_completion_cb),
-                cb_arg(s),
+                Some(cb),
+                arg,
             )
-        };
+        })
+        .map_err(|errno| CoreError::ReadDispatch {
+            source: Errno::from_i32(errno),
+            offset,
+            len: buffer.len(),
+        })?;
 
-        if errno != 0 {
-            return Err(CoreError::ReadDispatch {
-                source: Errno::from_i32(errno),
-                offset,
-                len: buffer.len(),
-            });
-        }
+        let success = self.complete(completion, offset, buffer.len()).await?;
 
-        if r.await.expect("Failed awaiting read IO") {
+        if success {
             Ok(buffer.len())
         } else {
             Err(CoreError::ReadFailed {
@@ -173,32 +187,206 @@ impl BdevHandle {
         }
     }
 
+    /// Await the completion of the IO, giving up on it after the timeout of
+    /// the handle. A timed out IO is aborted, or the bdev is reset if it can
+    /// not abort IOs, and its completion is awaited still such that its
+    /// buffer is no longer used by the bdev once this returns.
+    async fn complete(
+        &self,
+        completion: Completion,
+        offset: u64,
+        len: usize,
+    ) -> Result<bool, CoreError> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok(completion.await),
+        };
+
+        let completion = match select(completion, sleep(timeout)).await {
+            Either::Left((success, _)) => return Ok(success),
+            Either::Right(((), completion)) => completion,
+        };
+
+        // it may have completed right as the timer fired
+        if completion.completed() {
+            return Ok(completion.await);
+        }
+
+        let bdev = self.get_bdev();
+        warn!(
+            "IO at offset {} length {} of bdev {} timed out after {:?}",
+            offset,
+            len,
+            bdev.name(),
+            timeout
+        );
+
+        let aborted = if bdev.io_type_supported(SPDK_BDEV_IO_TYPE_ABORT) {
+            IoCtxPool::submit(|cb, arg| unsafe {
+                spdk_bdev_abort(
+                    self.desc.as_ptr(),
+                    self.channel.as_ptr(),
+                    completion.arg(),
+                    Some(cb),
+                    arg,
+                )
+            })
+            .await
+            .unwrap_or(false)
+        } else {
+            false
+        };
+
+        if !aborted && !completion.completed() {
+            warn!("resetting bdev {} to cancel the IO", bdev.name());
+            if let Err(error) = self.reset().await {
+                error!("failed to reset bdev {}: {}", bdev.name(), error);
+            }
+        }
+
+        completion.await;
+        Err(CoreError::IoTimeout {
+            offset,
+            len,
+            timeout,
+        })
+    }
+
+    /// Submit all the reads and writes of the batch before awaiting any of
+    /// them, such that they are all in flight at once and the task is only
+    /// woken up by their completions. The results are in the order of the
+    /// IOs, with the number of bytes read or written.
+    pub async fn submit_batch(
+        &self,
+        ios: Vec<IoDescriptor<'_>>,
+    ) -> Vec<Result<usize, CoreError>> {
+        let (desc, ch) = self.io_tuple();
+        let dispatched = ios
+            .into_iter()
+            .map(|io| match io {
+                IoDescriptor::Read {
+                    offset,
+                    buf,
+                } => {
+                    let len = buf.len();
+                    let completion = IoCtxPool::dispatch(|cb, arg| unsafe {
+                        spdk_bdev_read(
+                            desc,
+                            ch,
+                            **buf,
+                            offset,
+                            len as u64,
+                            Some(cb),
+                            arg,
+                        )
+                    });
+                    (false, offset, len, completion)
+                }
+                IoDescriptor::Write {
+                    offset,
+                    buf,
+                } => {
+                    let len = buf.len();
+                    let completion = IoCtxPool::dispatch(|cb, arg| unsafe {
+                        spdk_bdev_write(
+                            desc,
+                            ch,
+                            **buf,
+                            offset,
+                            len as u64,
+                            Some(cb),
+                            arg,
+                        )
+                    });
+                    (true, offset, len, completion)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        join_all(dispatched.into_iter().map(
+            |(write, offset, len, completion)| async move {
+                let success = completion.map_err(|errno| {
+                    let source = Errno::from_i32(errno);
+                    if write {
+                        CoreError::WriteDispatch {
+                            source,
+                            offset,
+                            len,
+                        }
+                    } else {
+                        CoreError::ReadDispatch {
+                            source,
+                            offset,
+                            len,
+                        }
+                    }
+                })?;
+
+                match (success.await, write) {
+                    (true, _) => Ok(len),
+                    (false, true) => Err(CoreError::WriteFailed {
+                        offset,
+                        len,
+                    }),
+                    (false, false) => Err(CoreError::ReadFailed {
+                        offset,
+                        len,
+                    }),
+                }
+            },
+        ))
+        .await
+    }
+
     pub async fn reset(&self) -> Result<usize, CoreError> {
-        let (s, r) = oneshot::channel::<bool>();
-        let errno = unsafe {
+        let status = IoCtxPool::submit(|cb, arg| unsafe {
             spdk_bdev_reset(
                 self.desc.as_ptr(),
                 self.channel.as_ptr(),
-                Some(Self::io_completion_cb),
-                cb_arg(s),
+                Some(cb),
+                arg,
             )
-        };
+        })
+        .await;
 
-        if errno != 0 {
-            return Err(CoreError::ResetDispatch {
-                source: Errno::from_i32(errno),
-            });
-        }
+        let success = status.map_err(|errno| CoreError::ResetDispatch {
+            source: Errno::from_i32(errno),
+        })?;
 
-        if r.await.expect("Failed awaiting reset IO") {
+        if success {
             Ok(0)
         } else {
             Err(CoreError::ResetFailed {})
         }
     }
 
-    /// create a snapshot on all children
-    pub async fn create_snapshot(&self) -> Result<usize, CoreError> {
+    /// flush the volatile cache of the whole bdev
+    pub async fn flush(&self) -> Result<usize, CoreError> {
+        let status = IoCtxPool::submit(|cb, arg| unsafe {
+            spdk_bdev_flush_blocks(
+                self.desc.as_ptr(),
+                self.channel.as_ptr(),
+                0,
+                self.get_bdev().num_blocks(),
+                Some(cb),
+                arg,
+            )
+        })
+        .await;
+
+        let success = status.map_err(|errno| CoreError::FlushDispatch {
+            source: Errno::from_i32(errno),
+        })?;
+
+        if success {
+            Ok(0)
+        } else {
+            Err(CoreError::FlushFailed {})
+        }
+    }
+
+    /// create a snapshot on all children, returns the time identifying it
+    pub async fn create_snapshot(&self) -> Result<u64, CoreError> {
         let mut cmd = spdk_sys::spdk_nvme_cmd::default();
         cmd.set_opc(nvme_admin_opc::CREATE_SNAPSHOT.into());
         // Snapshot time as u64 seconds since Unix epoch encoded in cdw10/11
@@ -209,7 +397,8 @@ impl BdevHandle {
         cmd.__bindgen_anon_1.cdw10 = now as u32;
         cmd.__bindgen_anon_2.cdw11 = (now >> 32) as u32;
         debug!("Creating snapshot at {}", now);
-        self.nvme_admin(&cmd).await
+        self.nvme_admin(&cmd).await?;
+        Ok(now)
     }
 
     /// sends an NVMe Admin command with a custom opcode to all children
@@ -228,27 +417,25 @@ impl BdevHandle {
         nvme_cmd: &spdk_sys::spdk_nvme_cmd,
     ) -> Result<usize, CoreError> {
         trace!("Sending nvme_admin {}", nvme_cmd.opc());
-        let (s, r) = oneshot::channel::<bool>();
-        let errno = unsafe {
+        let status = IoCtxPool::submit(|cb, arg| unsafe {
             spdk_bdev_nvme_admin_passthru(
                 self.desc.as_ptr(),
                 self.channel.as_ptr(),
                 &*nvme_cmd,
                 std::ptr::null_mut(),
                 0,
-                Some(Self::io_completion_cb),
-                cb_arg(s),
+                Some(cb),
+                arg,
             )
-        };
+        })
+        .await;
 
-        if errno != 0 {
-            return Err(CoreError::NvmeAdminDispatch {
-                source: Errno::from_i32(errno),
-                opcode: (*nvme_cmd).opc(),
-            });
-        }
+        let success = status.map_err(|errno| CoreError::NvmeAdminDispatch {
+            source: Errno::from_i32(errno),
+            opcode: (*nvme_cmd).opc(),
+        })?;
 
-        if r.await.expect("Failed awaiting NVME Admin IO") {
+        if success {
             Ok(0)
         } else {
             Err(CoreError::NvmeAdminFailed {
@@ -256,6 +443,46 @@ impl BdevHandle {
             })
         }
     }
+
+    /// sends the specified NVMe IO command, i.e. a reservation command,
+    /// with the data of the buffer if any
+    pub async fn nvme_io_passthru(
+        &self,
+        nvme_cmd: &spdk_sys::spdk_nvme_cmd,
+        buffer: Option<&mut DmaBuf>,
+    ) -> Result<usize, CoreError> {
+        trace!("Sending nvme_io_passthru {}", nvme_cmd.opc());
+        let (buf, len) = match buffer {
+            Some(buffer) => (**buffer, buffer.len()),
+            None => (std::ptr::null_mut(), 0),
+        };
+        let status = IoCtxPool::submit(|cb, arg| unsafe {
+            spdk_bdev_nvme_io_passthru(
+                self.desc.as_ptr(),
+                self.channel.as_ptr(),
+                &*nvme_cmd,
+                buf,
+                len as u64,
+                Some(cb),
+                arg,
+            )
+        })
+        .await;
+
+        let success =
+            status.map_err(|errno| CoreError::NvmeIoPassthruDispatch {
+                source: Errno::from_i32(errno),
+                opcode: nvme_cmd.opc(),
+            })?;
+
+        if success {
+            Ok(len)
+        } else {
+            Err(CoreError::NvmeIoPassthruFailed {
+                opcode: nvme_cmd.opc(),
+            })
+        }
+    }
 }
 
 impl Drop for BdevHandle {
@@ -279,11 +506,13 @@ impl Debug for BdevHandle {
 impl TryFrom<Descriptor> for BdevHandle {
     type Error = CoreError;
 
+    #[track_caller]
     fn try_from(desc: Descriptor) -> Result<Self, Self::Error> {
         if let Some(channel) = desc.get_channel() {
             return Ok(Self {
                 desc: ManuallyDrop::new(Arc::new(desc)),
                 channel: ManuallyDrop::new(channel),
+                timeout: None,
             });
         }
 
@@ -296,11 +525,13 @@ impl TryFrom<Descriptor> for BdevHandle {
 impl TryFrom<Arc<Descriptor>> for BdevHandle {
     type Error = CoreError;
 
+    #[track_caller]
     fn try_from(desc: Arc<Descriptor>) -> Result<Self, Self::Error> {
         if let Some(channel) = desc.get_channel() {
             return Ok(Self {
                 desc: ManuallyDrop::new(desc),
                 channel: ManuallyDrop::new(channel),
+                timeout: None,
             });
         }
 
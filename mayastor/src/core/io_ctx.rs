@@ -0,0 +1,239 @@
+//!
+//! Completion contexts of the IOs submitted through a ['BdevHandle'].
+//!
+//! Rather than boxing a oneshot sender for every IO, each core keeps a pool
+//! of contexts which are handed to SPDK as the callback argument. The
+//! completion callback stores the status of the IO in the context and wakes
+//! the future awaiting it, which is polled by the reactor of the core. The
+//! pool only allocates when more IOs are in flight on the core than it has
+//! contexts for, after which the additional contexts are kept for reuse.
+//!
+//! SPDK completes an IO on the thread it was submitted from, so the contexts
+//! never cross cores and need no locking.
+
+use std::{
+    cell::RefCell,
+    future::Future,
+    os::raw::c_void,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use spdk_sys::{spdk_bdev_free_io, spdk_bdev_io};
+
+/// number of contexts allocated up front on each core
+const IO_CTX_PREALLOC: usize = 256;
+
+#[derive(Debug, Default)]
+struct IoCtx {
+    /// success status of the IO once it has completed
+    status: Option<bool>,
+    /// waker of the future awaiting the IO
+    waker: Option<Waker>,
+    /// set when the future was dropped before the IO completed, such that
+    /// the callback returns the context to the pool
+    abandoned: bool,
+}
+
+#[derive(Debug)]
+struct Pool {
+    ctx: Vec<IoCtx>,
+    free: Vec<usize>,
+}
+
+impl Pool {
+    fn new() -> Self {
+        Self {
+            ctx: (0 .. IO_CTX_PREALLOC).map(|_| IoCtx::default()).collect(),
+            free: (0 .. IO_CTX_PREALLOC).rev().collect(),
+        }
+    }
+
+    fn get(&mut self) -> usize {
+        match self.free.pop() {
+            Some(idx) => idx,
+            None => {
+                self.ctx.push(IoCtx::default());
+                self.ctx.len() - 1
+            }
+        }
+    }
+
+    fn put(&mut self, idx: usize) {
+        self.ctx[idx] = IoCtx::default();
+        self.free.push(idx);
+    }
+}
+
+/// the callback argument of the context with the given index, which is never
+/// null as spdk_bdev_abort() rejects a null argument to identify the IO by
+fn ctx_arg(idx: usize) -> *mut c_void {
+    (idx + 1) as *mut c_void
+}
+
+/// the index of the context of the given callback argument
+fn ctx_idx(arg: *mut c_void) -> usize {
+    arg as usize - 1
+}
+
+thread_local! {
+    /// completion contexts of the IOs submitted from this core
+    static POOL: RefCell<Pool> = RefCell::new(Pool::new());
+}
+
+/// The pool of IO completion contexts of the current core
+pub struct IoCtxPool;
+
+impl IoCtxPool {
+    /// number of contexts allocated on the current core
+    pub fn allocated() -> usize {
+        POOL.with(|p| p.borrow().ctx.len())
+    }
+
+    /// number of contexts of the current core awaiting the completion of
+    /// an IO
+    pub fn in_use() -> usize {
+        POOL.with(|p| {
+            let p = p.borrow();
+            p.ctx.len() - p.free.len()
+        })
+    }
+
+    /// Submit an IO and wait for its completion. The closure is given the
+    /// completion callback and its argument to pass to SPDK, and returns the
+    /// errno of the submission. Returns the success status of the IO, or the
+    /// errno if it could not be submitted.
+    pub(crate) async fn submit<F>(submit: F) -> Result<bool, i32>
+    where
+        F: FnOnce(
+            unsafe extern "C" fn(*mut spdk_bdev_io, bool, *mut c_void),
+            *mut c_void,
+        ) -> i32,
+    {
+        Ok(Self::dispatch(submit)?.await)
+    }
+
+    /// Submit an IO right away, returning the future of its completion
+    /// rather than awaiting it, such that many IOs can be submitted before
+    /// any of them is awaited.
+    pub(crate) fn dispatch<F>(submit: F) -> Result<Completion, i32>
+    where
+        F: FnOnce(
+            unsafe extern "C" fn(*mut spdk_bdev_io, bool, *mut c_void),
+            *mut c_void,
+        ) -> i32,
+    {
+        let idx = POOL.with(|p| p.borrow_mut().get());
+        let errno = submit(Self::completion_cb, ctx_arg(idx));
+
+        if errno != 0 {
+            POOL.with(|p| p.borrow_mut().put(idx));
+            return Err(errno);
+        }
+
+        Ok(Completion {
+            idx,
+            done: false,
+        })
+    }
+
+    /// completion callback of the IOs, which records the status in the
+    /// context and wakes up the future awaiting it. The IO is returned to
+    /// the memory pool but the buffer is not freed.
+    unsafe extern "C" fn completion_cb(
+        io: *mut spdk_bdev_io,
+        success: bool,
+        arg: *mut c_void,
+    ) {
+        spdk_bdev_free_io(io);
+
+        let idx = ctx_idx(arg);
+        let waker = POOL.with(|p| {
+            let mut p = p.borrow_mut();
+            if p.ctx[idx].abandoned {
+                p.put(idx);
+                return None;
+            }
+            let ctx = &mut p.ctx[idx];
+            ctx.status = Some(success);
+            ctx.waker.take()
+        });
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// future resolving to the status of a submitted IO
+pub(crate) struct Completion {
+    idx: usize,
+    done: bool,
+}
+
+impl Completion {
+    /// the callback argument the IO was submitted with, which identifies it
+    /// to abort it
+    pub(crate) fn arg(&self) -> *mut c_void {
+        ctx_arg(self.idx)
+    }
+
+    /// returns true when the IO has completed, without consuming its status
+    pub(crate) fn completed(&self) -> bool {
+        self.done || POOL.with(|p| p.borrow().ctx[self.idx].status.is_some())
+    }
+}
+
+impl Future for Completion {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let idx = self.idx;
+        let status = POOL.with(|p| {
+            let mut p = p.borrow_mut();
+            match p.ctx[idx].status {
+                Some(status) => {
+                    p.put(idx);
+                    Some(status)
+                }
+                None => {
+                    let ctx = &mut p.ctx[idx];
+                    match &ctx.waker {
+                        Some(w) if w.will_wake(cx.waker()) => {}
+                        _ => ctx.waker = Some(cx.waker().clone()),
+                    }
+                    None
+                }
+            }
+        });
+
+        match status {
+            Some(status) => {
+                self.done = true;
+                Poll::Ready(status)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let idx = self.idx;
+        POOL.with(|p| {
+            let mut p = p.borrow_mut();
+            if p.ctx[idx].status.is_some() {
+                p.put(idx);
+            } else {
+                // the IO is still in flight, leave the context to the
+                // callback
+                p.ctx[idx].abandoned = true;
+                p.ctx[idx].waker = None;
+            }
+        });
+    }
+}
@@ -0,0 +1,175 @@
+//! Spreading IO over the reactor cores.
+//!
+//! The futures of the management path, i.e. those of `rpc_call` and
+//! `locally!`, all run on the first core, so that a large copy started from
+//! there, such as a rebuild, is bound to what a single core can submit.
+//! An ['IoSpread'] sends IO futures to the IO cores in turn instead. On every
+//! core the futures run in the context of an SPDK thread of that core, such
+//! that the handles they get on the descriptors are backed by IO channels of
+//! that core.
+
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::{channel::oneshot, Future};
+use once_cell::sync::Lazy;
+
+use crate::core::{
+    BdevHandle,
+    CoreError,
+    Cores,
+    Descriptor,
+    Mthread,
+    Reactors,
+};
+
+/// the SPDK thread of each IO core which the spread IO runs on
+struct IoThreads(Vec<(u32, Mthread)>);
+
+unsafe impl Sync for IoThreads {}
+unsafe impl Send for IoThreads {}
+
+/// created on first use, and dropped when the reactors are torn down
+static IO_THREADS: Lazy<Mutex<Option<IoThreads>>> =
+    Lazy::new(|| Mutex::new(None));
+
+impl IoThreads {
+    /// call f with the threads, creating them if they do not exist yet
+    fn with<R>(f: impl FnOnce(&[(u32, Mthread)]) -> R) -> R {
+        let mut threads = IO_THREADS.lock().unwrap();
+        let threads = threads.get_or_insert_with(|| {
+            Self(
+                Reactors::iter()
+                    .filter(|r| Cores::is_io_core(r.core()))
+                    .filter_map(|r| {
+                        Mthread::new(
+                            format!("mayastor_io_spread_core_{}", r.core()),
+                            r.core(),
+                        )
+                        .map(|t| (r.core(), t))
+                    })
+                    .collect(),
+            )
+        });
+        f(&threads.0)
+    }
+}
+
+/// Forget the threads, which are destroyed along with the other threads of
+/// the reactors.
+pub(crate) fn reset() {
+    IO_THREADS.lock().unwrap().take();
+}
+
+/// a future which is polled in the context of the given thread
+pub(crate) struct InThread<F> {
+    thread: Mthread,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> InThread<F> {
+    pub(crate) fn new(thread: Mthread, future: F) -> Self {
+        Self {
+            thread,
+            future: Box::pin(future),
+        }
+    }
+}
+
+impl<F: Future> Future for InThread<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let previous = Mthread::current();
+        self.thread.enter();
+        let poll = self.future.as_mut().poll(cx);
+        match previous {
+            Some(t) => t.enter(),
+            None => self.thread.exit(),
+        };
+        poll
+    }
+}
+
+/// Sends IO futures to the IO cores in turn, each of which gets handles of
+/// its own on the descriptors.
+#[derive(Debug)]
+pub struct IoSpread {
+    descriptors: Vec<Arc<Descriptor>>,
+    next: Cell<usize>,
+}
+
+impl IoSpread {
+    /// spread IO to the given descriptors over the IO cores
+    pub fn new(descriptors: Vec<Arc<Descriptor>>) -> Self {
+        Self {
+            descriptors,
+            next: Cell::new(0),
+        }
+    }
+
+    /// number of cores the IO is spread over
+    pub fn cores() -> usize {
+        IoThreads::with(|threads| threads.len()).max(1)
+    }
+
+    /// Run the future made by `f` on the next IO core. It is passed a handle
+    /// on each of the descriptors, in the order they were given in. Its
+    /// output is sent back to the calling core, which the receiver must be
+    /// awaited on.
+    pub fn spawn<F, Fut, R>(
+        &self,
+        f: F,
+    ) -> oneshot::Receiver<Result<R, CoreError>>
+    where
+        F: FnOnce(Vec<BdevHandle>) -> Fut + 'static,
+        Fut: Future<Output = R> + 'static,
+        R: 'static,
+    {
+        let (s, r) = oneshot::channel();
+        let origin = Cores::current();
+        let descriptors = self.descriptors.clone();
+
+        let (core, thread) = IoThreads::with(|threads| {
+            if threads.is_empty() {
+                (origin, Mthread::current().unwrap_or_else(Mthread::get_init))
+            } else {
+                let n = self.next.get() % threads.len();
+                self.next.set((n + 1) % threads.len());
+                threads[n]
+            }
+        });
+
+        let future = async move {
+            let handles = descriptors
+                .into_iter()
+                .map(BdevHandle::try_from)
+                .collect::<Result<Vec<_>, _>>();
+            let output = match handles {
+                Ok(handles) => Ok(f(handles).await),
+                Err(e) => Err(e),
+            };
+
+            // the waker of the receiver is only to be woken on its own core
+            let reply = async move {
+                let _ = s.send(output);
+            };
+            match Reactors::get_by_core(origin) {
+                Some(reactor) if origin != Cores::current() => {
+                    reactor.send_future(reply)
+                }
+                _ => reply.await,
+            }
+        };
+
+        Reactors::get_by_core(core)
+            .unwrap_or_else(Reactors::current)
+            .send_future(InThread::new(thread, future));
+        r
+    }
+}
@@ -1,37 +1,61 @@
 //!
 //! core contains the primary abstractions around the SPDK primitives.
 pub use ::uuid::Uuid;
+use std::time::Duration;
+
 use nix::errno::Errno;
 use snafu::Snafu;
 
 use crate::{subsys::NvmfError, target::iscsi};
-pub use bdev::{Bdev, BdevIter};
+pub use bdev::{Bdev, BdevIter, LatencyBucket, QosLimits};
 pub use channel::IoChannel;
 pub use cpu_cores::{Core, Cores};
 pub use descriptor::{Descriptor, RangeContext};
 pub use dma::{DmaBuf, DmaError};
+pub use dma_pool::DmaPool;
 pub use env::{
     mayastor_env_stop,
     MayastorCliArgs,
     MayastorEnvironment,
     GLOBAL_RC,
 };
-pub use handle::BdevHandle;
-pub use reactor::{Reactor, ReactorState, Reactors, REACTOR_LIST};
+pub use handle::{BdevHandle, IoDescriptor};
+pub use io_ctx::IoCtxPool;
+pub(crate) use io_spread::InThread;
+pub use io_spread::IoSpread;
+pub use preflight::PreflightMode;
+pub use reactor::{
+    Reactor,
+    ReactorState,
+    ReactorStats,
+    Reactors,
+    REACTOR_LIST,
+};
 pub use share::{Protocol, Share};
 pub use thread::Mthread;
+pub(crate) use timer::sleep;
+pub use tracker::{HandleInfo, HandleKind};
+pub use zcopy::ZcopyBuf;
 
 mod bdev;
 mod channel;
 mod cpu_cores;
 mod descriptor;
 mod dma;
+mod dma_pool;
 mod env;
 mod handle;
+mod io_ctx;
+mod io_spread;
+pub mod preflight;
 mod reactor;
 mod share;
+pub mod shutdown;
 pub(crate) mod thread;
+mod timer;
+pub mod tracker;
 mod uuid;
+mod zcopy;
 
 #[derive(Debug, Snafu, Clone)]
 #[snafu(visibility = "pub")]
@@ -75,15 +99,34 @@ pub enum CoreError {
         offset: u64,
         len: usize,
     },
+    #[snafu(display(
+        "Failed to dispatch zero-copy read at offset {} length {}",
+        offset,
+        len
+    ))]
+    ZcopyDispatch {
+        source: Errno,
+        offset: u64,
+        len: usize,
+    },
     #[snafu(display("Failed to dispatch reset",))]
     ResetDispatch {
         source: Errno,
     },
+    #[snafu(display("Failed to dispatch flush",))]
+    FlushDispatch {
+        source: Errno,
+    },
     #[snafu(display("Failed to dispatch NVMe Admin",))]
     NvmeAdminDispatch {
         source: Errno,
         opcode: u16,
     },
+    #[snafu(display("Failed to dispatch NVMe IO passthru"))]
+    NvmeIoPassthruDispatch {
+        source: Errno,
+        opcode: u16,
+    },
     #[snafu(display("Write failed at offset {} length {}", offset, len))]
     WriteFailed {
         offset: u64,
@@ -94,12 +137,48 @@ pub enum CoreError {
         offset: u64,
         len: usize,
     },
+    #[snafu(display(
+        "Zero-copy read failed at offset {} length {}",
+        offset,
+        len
+    ))]
+    ZcopyFailed {
+        offset: u64,
+        len: usize,
+    },
+    #[snafu(display(
+        "Failed to allocate buffer to read at offset {} length {}",
+        offset,
+        len
+    ))]
+    ReadAlloc {
+        source: DmaError,
+        offset: u64,
+        len: usize,
+    },
+    #[snafu(display(
+        "IO at offset {} length {} timed out after {:?}",
+        offset,
+        len,
+        timeout
+    ))]
+    IoTimeout {
+        offset: u64,
+        len: usize,
+        timeout: Duration,
+    },
     #[snafu(display("Reset failed"))]
     ResetFailed {},
+    #[snafu(display("Flush failed"))]
+    FlushFailed {},
     #[snafu(display("NVMe Admin failed"))]
     NvmeAdminFailed {
         opcode: u16,
     },
+    #[snafu(display("NVMe IO passthru failed"))]
+    NvmeIoPassthruFailed {
+        opcode: u16,
+    },
     #[snafu(display("failed to share {}", source))]
     ShareNvmf {
         source: NvmfError,
@@ -112,4 +191,40 @@ pub enum CoreError {
     NotSupported {
         source: Errno,
     },
+    #[snafu(display("failed to set QoS limits of bdev {}", name))]
+    SetQos {
+        source: Errno,
+        name: String,
+    },
+    #[snafu(display("failed to change latency histogram of bdev {}", name))]
+    SetHistogram {
+        source: Errno,
+        name: String,
+    },
+    #[snafu(display("failed to get latency histogram of bdev {}", name))]
+    GetHistogram {
+        source: Errno,
+        name: String,
+    },
+    #[snafu(display("bdev {} does not support the NVMe telemetry log", name))]
+    NvmeTelemetryUnsupported {
+        name: String,
+    },
+    #[snafu(display(
+        "failed to allocate buffer for the NVMe telemetry log of bdev {}",
+        name
+    ))]
+    NvmeTelemetryAlloc {
+        source: DmaError,
+        name: String,
+    },
+    #[snafu(display(
+        "failed to read the NVMe telemetry log of bdev {} at offset {}",
+        name,
+        offset
+    ))]
+    NvmeTelemetryFailed {
+        name: String,
+        offset: u64,
+    },
 }
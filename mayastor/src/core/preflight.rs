@@ -0,0 +1,340 @@
+//! Self-check of the host, performed before the SPDK environment is set up.
+//!
+//! When the host is not prepared for mayastor, DPDK aborts the EAL
+//! initialization with an error that rarely points at the cause. The checks
+//! in here look at the usual suspects: hugepages, the driver binding and the
+//! IOMMU grouping of the configured PCIe devices and the presence of the
+//! nvme-tcp kernel module. All problems are reported at once, each of them
+//! with a hint on how to fix it.
+
+use std::{
+    fmt::{Display, Formatter},
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+/// drivers which allow SPDK to claim a PCIe device from user space
+const USERSPACE_DRIVERS: [&str; 3] = ["vfio-pci", "uio_pci_generic", "igb_uio"];
+
+/// what to do with the outcome of the preflight checks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PreflightMode {
+    /// do not check anything
+    Off,
+    /// log the problems found and carry on
+    Warn,
+    /// refuse to start when a fatal problem is found
+    Enforce,
+}
+
+impl FromStr for PreflightMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(PreflightMode::Off),
+            "warn" => Ok(PreflightMode::Warn),
+            "enforce" => Ok(PreflightMode::Enforce),
+            _ => Err(format!(
+                "invalid preflight mode {}, expected off, warn or enforce",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for PreflightMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreflightMode::Off => write!(f, "off"),
+            PreflightMode::Warn => write!(f, "warn"),
+            PreflightMode::Enforce => write!(f, "enforce"),
+        }
+    }
+}
+
+/// a problem found by one of the checks
+#[derive(Debug, Clone)]
+pub struct Finding {
+    /// name of the check that found the problem
+    pub check: &'static str,
+    /// fatal problems keep mayastor from starting, the others only limit
+    /// what it can do
+    pub fatal: bool,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.check, self.message)
+    }
+}
+
+/// the part of the environment the checks depend on
+#[derive(Debug, Default, Clone)]
+pub struct Preflight {
+    /// hugepages are not used at all
+    pub no_huge: bool,
+    /// PCIe devices are not used at all
+    pub no_pci: bool,
+    /// hugepage memory to be allocated in MiB, 0 or less for all of it
+    pub mem_size: i32,
+    /// hugetlbfs mount to be used instead of the default one
+    pub hugedir: Option<String>,
+    /// addresses of the PCIe devices referenced by the configuration
+    pub pci_devices: Vec<String>,
+}
+
+impl Preflight {
+    /// run all checks and return the problems found
+    pub fn run(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        self.check_hugepages(&mut findings);
+        self.check_pci_devices(&mut findings);
+        self.check_nvme_tcp(&mut findings);
+        findings
+    }
+
+    /// run all checks and act on the result according to the mode, exiting
+    /// the process when enforcing and a fatal problem was found
+    pub fn check(&self, mode: PreflightMode) {
+        if mode == PreflightMode::Off {
+            return;
+        }
+
+        let findings = self.run();
+        if findings.is_empty() {
+            info!("Preflight checks passed");
+            return;
+        }
+
+        if mode == PreflightMode::Enforce && findings.iter().any(|f| f.fatal) {
+            error!("Preflight checks failed, mayastor can not start:");
+            findings.iter().for_each(|f| error!("  {}", f));
+            error!("Use --preflight=warn to start regardless");
+            std::process::exit(1);
+        }
+
+        findings.iter().for_each(|f| warn!("Preflight: {}", f));
+    }
+
+    fn check_hugepages(&self, findings: &mut Vec<Finding>) {
+        if self.no_huge {
+            return;
+        }
+
+        let mut fatal = |message: String| {
+            findings.push(Finding {
+                check: "hugepages",
+                fatal: true,
+                message,
+            })
+        };
+
+        let entries = match fs::read_dir("/sys/kernel/mm/hugepages") {
+            Ok(entries) => entries,
+            Err(e) => {
+                fatal(format!(
+                    "hugepages are not supported by the kernel: {}",
+                    e
+                ));
+                return;
+            }
+        };
+
+        let mut total: u64 = 0;
+        let mut free: u64 = 0;
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            // directories are named like hugepages-2048kB
+            let size_kb = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| {
+                    n.trim_start_matches("hugepages-").trim_end_matches("kB")
+                })
+                .and_then(|n| n.parse::<u64>().ok());
+
+            if let Some(size_kb) = size_kb {
+                let pages =
+                    |attr| sysfs::parse_value::<u64>(&path, attr).unwrap_or(0);
+                total += pages("nr_hugepages") * size_kb * 1024;
+                free += pages("free_hugepages") * size_kb * 1024;
+            }
+        }
+
+        let mib = |bytes: u64| bytes / (1024 * 1024);
+
+        if total == 0 {
+            fatal(
+                "no hugepages are reserved, reserve them with \
+                 'sysctl vm.nr_hugepages=<count>'"
+                    .into(),
+            );
+        } else if free == 0 {
+            fatal(format!(
+                "all {} MiB of reserved hugepages are in use",
+                mib(total)
+            ));
+        } else if self.mem_size > 0 && mib(free) < self.mem_size as u64 {
+            fatal(format!(
+                "{} MiB of hugepage memory requested but only {} MiB are free, \
+                 reserve more hugepages or lower the memory size (-s)",
+                self.mem_size,
+                mib(free)
+            ));
+        }
+
+        match &self.hugedir {
+            Some(dir) if !Path::new(dir).is_dir() => {
+                fatal(format!("hugepage directory {} does not exist", dir))
+            }
+            Some(_) => {}
+            None => {
+                let mounted = fs::read_to_string("/proc/mounts")
+                    .map(|mounts| {
+                        mounts.lines().any(|line| {
+                            line.split_whitespace().nth(2) == Some("hugetlbfs")
+                        })
+                    })
+                    .unwrap_or(false);
+
+                if !mounted {
+                    fatal(
+                        "no hugetlbfs is mounted, mount one with \
+                         'mount -t hugetlbfs nodev /dev/hugepages'"
+                            .into(),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_pci_devices(&self, findings: &mut Vec<Finding>) {
+        if self.pci_devices.is_empty() {
+            return;
+        }
+
+        let mut fatal = |check, message| {
+            findings.push(Finding {
+                check,
+                fatal: true,
+                message,
+            })
+        };
+
+        if self.no_pci {
+            fatal(
+                "pcie",
+                format!(
+                    "PCIe devices {} are configured but the use of PCIe \
+                     devices is disabled (-u)",
+                    self.pci_devices.join(", ")
+                ),
+            );
+            return;
+        }
+
+        let mut vfio = false;
+
+        for addr in &self.pci_devices {
+            let device = Path::new("/sys/bus/pci/devices").join(addr);
+            if !device.exists() {
+                fatal("pcie", format!("PCIe device {} does not exist", addr));
+                continue;
+            }
+
+            let driver =
+                fs::read_link(device.join("driver")).ok().and_then(|p| {
+                    p.file_name().map(|n| n.to_string_lossy().to_string())
+                });
+
+            match driver.as_deref() {
+                Some("vfio-pci") => {
+                    vfio = true;
+                    match fs::read_link(device.join("iommu_group")) {
+                        Ok(group) => {
+                            let group = group
+                                .file_name()
+                                .map(|n| n.to_string_lossy().to_string())
+                                .unwrap_or_default();
+                            let dev = Path::new("/dev/vfio");
+                            if !dev.join(&group).exists()
+                                && !dev
+                                    .join(format!("noiommu-{}", group))
+                                    .exists()
+                            {
+                                fatal(
+                                    "iommu",
+                                    format!(
+                                        "IOMMU group {} of PCIe device {} has \
+                                         no entry in /dev/vfio",
+                                        group, addr
+                                    ),
+                                );
+                            }
+                        }
+                        Err(_) => fatal(
+                            "iommu",
+                            format!(
+                                "PCIe device {} is not part of an IOMMU group, \
+                                 enable the IOMMU (i.e. intel_iommu=on) or \
+                                 bind the device to uio_pci_generic instead",
+                                addr
+                            ),
+                        ),
+                    }
+                }
+                Some(d) if USERSPACE_DRIVERS.contains(&d) => {}
+                Some(d) => fatal(
+                    "pcie",
+                    format!(
+                        "PCIe device {} is bound to the kernel driver {}, \
+                         bind it to one of {} (i.e. with SPDK's setup.sh)",
+                        addr,
+                        d,
+                        USERSPACE_DRIVERS.join(", ")
+                    ),
+                ),
+                None => fatal(
+                    "pcie",
+                    format!(
+                        "PCIe device {} is not bound to any driver, bind it \
+                         to one of {}",
+                        addr,
+                        USERSPACE_DRIVERS.join(", ")
+                    ),
+                ),
+            }
+        }
+
+        if vfio && !Path::new("/dev/vfio/vfio").exists() {
+            fatal(
+                "iommu",
+                "/dev/vfio/vfio does not exist, load the vfio-pci kernel module"
+                    .into(),
+            );
+        }
+    }
+
+    fn check_nvme_tcp(&self, findings: &mut Vec<Finding>) {
+        if !Path::new("/sys/module/nvme_tcp").exists() {
+            findings.push(Finding {
+                check: "nvme-tcp",
+                fatal: false,
+                message: "the nvme_tcp kernel module is not loaded, volumes \
+                          shared over NVMe-oF can not be attached on this node \
+                          (modprobe nvme-tcp)"
+                    .into(),
+            });
+        }
+    }
+}
+
+/// extract the PCIe address from a pcie:/// URI
+pub fn pci_address(uri: &str) -> Option<String> {
+    uri.strip_prefix("pcie:///")
+        .map(|addr| addr.split('?').next().unwrap_or(addr).to_string())
+}
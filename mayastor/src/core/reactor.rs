@@ -42,12 +42,13 @@ use std::{
 
 use crossbeam::channel::{unbounded, Receiver, Sender};
 use futures::{
+    channel::oneshot,
     task::{Context, Poll},
     Future,
 };
-use log::info;
 use once_cell::sync::OnceCell;
 use serde::export::Formatter;
+use tracing::info;
 
 use spdk_sys::{
     spdk_cpuset_get_cpu,
@@ -55,10 +56,11 @@ use spdk_sys::{
     spdk_env_thread_wait_all,
     spdk_thread,
     spdk_thread_get_cpumask,
+    spdk_thread_lib_fini,
     spdk_thread_lib_init_ext,
 };
 
-use crate::core::{Cores, Mthread};
+use crate::core::{io_spread, Cores, Mthread};
 use std::cell::Cell;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -84,6 +86,19 @@ impl Display for ReactorState {
 #[derive(Debug)]
 pub struct Reactors(Vec<Reactor>);
 
+/// the cycles the threads of a reactor have spent polling
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReactorStats {
+    /// core of the reactor
+    pub core: u32,
+    /// number of threads on the reactor
+    pub threads: u32,
+    /// cycles spent in pollers and messages which did work
+    pub busy_tsc: u64,
+    /// cycles spent in pollers which found no work
+    pub idle_tsc: u64,
+}
+
 unsafe impl Sync for Reactors {}
 unsafe impl Send for Reactors {}
 
@@ -122,16 +137,14 @@ thread_local! {
 impl Reactors {
     /// initialize the reactor subsystem for each core assigned to us
     pub fn init() {
-        REACTOR_LIST.get_or_init(|| {
-            let rc = unsafe {
-                spdk_thread_lib_init_ext(
-                    Some(Self::do_op),
-                    Some(Self::can_op),
-                    0,
-                )
-            };
-            assert_eq!(rc, 0);
+        let rc = unsafe {
+            spdk_thread_lib_init_ext(Some(Self::do_op), Some(Self::can_op), 0)
+        };
+        assert_eq!(rc, 0);
 
+        // the reactors outlive a teardown of the environment, and are
+        // reused when it is initialized again
+        REACTOR_LIST.get_or_init(|| {
             Reactors(
                 Cores::count()
                     .into_iter()
@@ -142,14 +155,47 @@ impl Reactors {
                     .collect::<Vec<_>>(),
             )
         });
+        Reactors::iter().for_each(|r| r.set_state(ReactorState::Init));
 
         // construct one main init thread, this thread is used to bootstrap
         // and should be used to teardown as well.
         if let Some(t) = Mthread::new("init_thread".into(), Cores::first()) {
             info!("Init thread ID {}", t.id());
+            Mthread::set_init(Some(t));
+        }
+
+        // the management thread keeps the management work apart from the
+        // init thread, and from the IO threads on the first core if any
+        if let Some(t) = Mthread::new("mgmt_thread".into(), Cores::first()) {
+            info!("Management thread ID {}", t.id());
+            Mthread::set_mgmt(Some(t));
         }
     }
 
+    /// Destroy the threads of all reactors and finalize the thread library,
+    /// after the reactors have stopped polling. The reactors can be
+    /// initialized again with ['init'].
+    pub(crate) fn fini() {
+        io_spread::reset();
+
+        Reactors::iter().for_each(|r| {
+            while let Ok(t) = r.incoming.pop() {
+                r.threads.borrow_mut().push_back(t);
+            }
+            let threads = r.threads.borrow_mut().drain(..).collect::<Vec<_>>();
+            threads.into_iter().for_each(Mthread::destroy);
+            // drop the futures which were sent, but never ran
+            r.rx.try_iter().for_each(drop);
+        });
+
+        if let Some(t) = Mthread::current() {
+            t.exit();
+        }
+        Mthread::set_init(None);
+        Mthread::set_mgmt(None);
+        unsafe { spdk_thread_lib_fini() };
+    }
+
     /// advertise what scheduling options we support
     extern "C" fn can_op(op: spdk_sys::spdk_thread_op) -> bool {
         matches!(op, spdk_sys::SPDK_THREAD_OP_NEW)
@@ -231,7 +277,7 @@ impl Reactors {
 
     /// get a reference to a ['Reactor'] associated with the given core.
     pub fn get_by_core(core: u32) -> Option<&'static Reactor> {
-        Reactors::iter().find(|c| c.lcore == core)
+        REACTOR_LIST.get()?.0.iter().find(|c| c.lcore == core)
     }
 
     /// get a reference to a reactor on the current core
@@ -247,6 +293,50 @@ impl Reactors {
     pub fn iter() -> Iter<'static, Reactor> {
         REACTOR_LIST.get().unwrap().into_iter()
     }
+
+    /// collect the stats of all reactors, each of which is read on its own
+    /// core. This must be awaited on a reactor core.
+    pub async fn stats() -> Vec<ReactorStats> {
+        Reactors::on_each(|| Reactors::current().stats()).await
+    }
+
+    /// run the closure on every reactor and collect what it returns, in the
+    /// order of the reactors. This must be awaited on a reactor core.
+    pub async fn on_each<F, R>(f: F) -> Vec<R>
+    where
+        F: Fn() -> R + Clone + Send + 'static,
+        R: Send + 'static,
+    {
+        let origin = Cores::current();
+        let receivers = Reactors::iter()
+            .map(|reactor| {
+                let (s, r) = oneshot::channel();
+                let f = f.clone();
+                reactor.send_future(async move {
+                    let result = f();
+                    // the receiver is only to be woken on its own core
+                    let reply = async move {
+                        let _ = s.send(result);
+                    };
+                    match Reactors::get_by_core(origin) {
+                        Some(reactor) if origin != Cores::current() => {
+                            reactor.send_future(reply)
+                        }
+                        _ => reply.await,
+                    }
+                });
+                r
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::new();
+        for r in receivers {
+            if let Ok(result) = r.await {
+                results.push(result);
+            }
+        }
+        results
+    }
 }
 
 impl<'a> IntoIterator for &'a Reactors {
@@ -417,6 +507,24 @@ impl Reactor {
         self.lcore
     }
 
+    /// the cycles of the threads of this reactor, which must only be called
+    /// on its own core
+    pub fn stats(&self) -> ReactorStats {
+        self.threads.borrow().iter().fold(
+            ReactorStats {
+                core: self.lcore,
+                ..Default::default()
+            },
+            |mut stats, t| {
+                let (busy, idle) = t.cycles();
+                stats.threads += 1;
+                stats.busy_tsc += busy;
+                stats.idle_tsc += idle;
+                stats
+            },
+        )
+    }
+
     /// poll this reactor to complete any work that is pending
     pub fn poll_reactor(&self) {
         loop {
@@ -0,0 +1,111 @@
+//!
+//! Graceful shutdown of mayastor on SIGTERM. Before the reactors are stopped
+//! the gRPC methods which change the configuration are refused, the nexuses
+//! are unpublished such that the initiators are disconnected cleanly rather
+//! than left with hung IO, the IO in flight is drained and flushed to the
+//! children, and the configuration is exported.
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bdev::nexus::{instances, nexus_io::io_type},
+    core::Bdev,
+    grpc,
+    subsys::Config,
+};
+
+/// max time to wait for the IO in flight on a nexus to complete
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// interval at which the IO in flight is checked while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// the graceful shutdown has started, no configuration changes are accepted
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// wait for the front-end IO of a nexus to complete, false if it has not
+/// completed in time
+async fn drain_nexus(name: &str) -> bool {
+    let started = Instant::now();
+
+    loop {
+        let nexus = match instances().iter().find(|n| n.name == name) {
+            Some(nexus) => nexus,
+            None => return true,
+        };
+
+        match nexus.io_stats().await {
+            Some(stats) if stats.queue_depth > 0 => {
+                if started.elapsed() >= DRAIN_TIMEOUT {
+                    return false;
+                }
+            }
+            _ => return true,
+        }
+
+        tokio::time::delay_for(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// flush the volatile caches of the children of a nexus, if supported
+async fn flush_nexus(name: &str) {
+    let bdev = match Bdev::lookup_by_name(name) {
+        Some(bdev) if bdev.io_type_supported(io_type::FLUSH) => bdev,
+        _ => return,
+    };
+
+    let result = match Bdev::open_by_name(name, true) {
+        Ok(desc) => match desc.into_handle() {
+            Ok(handle) => handle.flush().await.map(|_| ()),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        error!("{}: Failed to flush: {}", bdev.name(), e);
+    }
+}
+
+/// Prepare for the shutdown, to be run on the master core before the
+/// subsystems are stopped.
+pub(crate) async fn drain() {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    info!("Shutting down gracefully ...");
+    grpc::set_serving(false);
+
+    let names = instances()
+        .iter()
+        .map(|n| n.name.clone())
+        .collect::<Vec<_>>();
+
+    for name in &names {
+        // the targets complete the IO they have submitted when stopped
+        if let Some(nexus) = instances().iter_mut().find(|n| &n.name == name) {
+            if nexus.nexus_target.is_some() {
+                if let Err(e) = nexus.unshare_nexus().await {
+                    error!("{}: Failed to unpublish: {}", name, e);
+                }
+            }
+        }
+
+        if !drain_nexus(name).await {
+            warn!("{}: IO still in flight after {:?}", name, DRAIN_TIMEOUT);
+        }
+
+        flush_nexus(name).await;
+        info!("{}: Unpublished and flushed", name);
+    }
+
+    if let Err(e) = Config::export_config().await {
+        error!("Failed to export config: {}", e);
+    }
+}
@@ -1,4 +1,8 @@
-use std::ffi::{c_void, CString};
+use std::{
+    ffi::{c_void, CString},
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering::SeqCst},
+};
 
 use snafu::Snafu;
 
@@ -31,9 +35,45 @@ pub enum Error {
 /// the submitted work to completion.
 pub struct Mthread(pub(crate) *mut spdk_thread);
 
+/// the init thread of the environment, which is created anew each time the
+/// environment is initialized
+static INIT_THREAD: AtomicPtr<spdk_thread> = AtomicPtr::new(ptr::null_mut());
+
+/// the management thread of the environment, created along with the init
+/// thread on the first core
+static MGMT_THREAD: AtomicPtr<spdk_thread> = AtomicPtr::new(ptr::null_mut());
+
 impl Mthread {
     pub fn get_init() -> Mthread {
-        Mthread::from_null_checked(unsafe { spdk_thread_get_by_id(1) }).unwrap()
+        Mthread::from_null_checked(INIT_THREAD.load(SeqCst))
+            .or_else(|| {
+                Mthread::from_null_checked(unsafe { spdk_thread_get_by_id(1) })
+            })
+            .unwrap()
+    }
+
+    /// set the thread returned by ['get_init'], or clear it when the
+    /// environment is torn down
+    pub(crate) fn set_init(thread: Option<Mthread>) {
+        INIT_THREAD.store(thread.map_or(ptr::null_mut(), |t| t.0), SeqCst);
+    }
+
+    /// The thread the management work which only reads the state of
+    /// mayastor runs on, i.e. the config export, the sampling of the IO
+    /// stats and the gRPC calls which list or stat the bdevs, nexuses, pools
+    /// and replicas. The work which creates or destroys them stays on the
+    /// init thread, as what it creates is bound to the thread it was created
+    /// on and is torn down on the init thread when mayastor stops. Falls
+    /// back to the init thread if there is no management thread.
+    pub fn get_mgmt() -> Mthread {
+        Mthread::from_null_checked(MGMT_THREAD.load(SeqCst))
+            .unwrap_or_else(Mthread::get_init)
+    }
+
+    /// set the thread returned by ['get_mgmt'], or clear it when the
+    /// environment is torn down
+    pub(crate) fn set_mgmt(thread: Option<Mthread>) {
+        MGMT_THREAD.store(thread.map_or(ptr::null_mut(), |t| t.0), SeqCst);
     }
 
     ///
@@ -103,6 +143,12 @@ impl Mthread {
         }
     }
 
+    /// the cycles the pollers and messages of this thread have been busy
+    /// and idle for, as (busy, idle)
+    pub fn cycles(&self) -> (u64, u64) {
+        unsafe { ((*self.0).stats.busy_tsc, (*self.0).stats.idle_tsc) }
+    }
+
     /// destroy the given thread waiting for it to become ready to destroy
     pub fn destroy(self) {
         debug!("destroying thread {}...{:p}", self.name(), self.0);
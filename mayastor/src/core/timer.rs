@@ -0,0 +1,82 @@
+//!
+//! A future which resolves once a duration has passed, driven by an SPDK
+//! poller of the current core rather than by the timers of tokio, which are
+//! only available on the master reactor.
+
+use std::{
+    cell::RefCell,
+    future::Future,
+    os::raw::c_void,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use spdk_sys::{spdk_poller, spdk_poller_register, spdk_poller_unregister};
+
+#[derive(Debug, Default)]
+struct State {
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+/// future returned by [`sleep`], it must be polled and dropped on the core
+/// it was created on
+pub(crate) struct Sleep {
+    poller: *mut spdk_poller,
+    state: Box<RefCell<State>>,
+}
+
+/// sleep for the given duration without blocking the reactor
+pub(crate) fn sleep(duration: Duration) -> Sleep {
+    let state = Box::new(RefCell::new(State::default()));
+    let poller = unsafe {
+        spdk_poller_register(
+            Some(Sleep::expired),
+            &*state as *const RefCell<State> as *mut c_void,
+            duration.as_micros() as u64,
+        )
+    };
+
+    Sleep {
+        poller,
+        state,
+    }
+}
+
+impl Sleep {
+    /// called by the poller once the duration has passed
+    extern "C" fn expired(ctx: *mut c_void) -> i32 {
+        let state = unsafe { &*(ctx as *const RefCell<State>) };
+        let waker = {
+            let mut state = state.borrow_mut();
+            state.fired = true;
+            state.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        1
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.borrow_mut();
+        if state.fired {
+            return Poll::Ready(());
+        }
+
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        unsafe { spdk_poller_unregister(&mut self.poller) };
+    }
+}
@@ -0,0 +1,167 @@
+//! Accounting of open descriptors and IO channels per bdev.
+//!
+//! A bdev can not be destroyed as long as there are descriptors open to it,
+//! and SPDK reports this as nothing more than "busy". To find out who is
+//! holding on to the bdev, every ['Descriptor'] and ['IoChannel'] is recorded
+//! together with the code location that created it. The accounting is enabled
+//! in debug builds or when MAYASTOR_TRACK_HANDLES is set in the environment.
+//!
+//! As handles are opened and closed on the IO path, each core keeps the
+//! handles created on it in a table of its own, which needs no locking. A
+//! handle closed on another core is removed by a message to the core which
+//! created it, and the handles are listed by asking every core for its own.
+//! Handles created outside of the reactors are not accounted.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    panic::Location,
+};
+
+use once_cell::sync::Lazy;
+
+use crate::core::{Cores, Mthread, Reactors};
+
+/// the kind of handle that is held on a bdev
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandleKind {
+    Descriptor,
+    IoChannel,
+}
+
+impl Display for HandleKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandleKind::Descriptor => write!(f, "descriptor"),
+            HandleKind::IoChannel => write!(f, "io_channel"),
+        }
+    }
+}
+
+/// an open handle on a bdev
+#[derive(Debug, Clone)]
+pub struct HandleInfo {
+    /// unique id of the handle
+    pub id: u64,
+    pub kind: HandleKind,
+    /// name of the bdev the handle refers to
+    pub bdev: String,
+    /// code location which created the handle
+    pub owner: String,
+    /// spdk thread the handle was created on
+    pub thread: String,
+}
+
+static ENABLED: Lazy<bool> = Lazy::new(|| {
+    cfg!(debug_assertions) || std::env::var("MAYASTOR_TRACK_HANDLES").is_ok()
+});
+
+/// the id of a handle holds the core which created it in its upper bits
+const CORE_SHIFT: u32 = 48;
+
+thread_local! {
+    /// the open handles created on this core
+    static HANDLES: RefCell<HashMap<u64, HandleInfo>> =
+        RefCell::new(HashMap::new());
+    /// the number of handles created on this core so far
+    static CREATED: Cell<u64> = Cell::new(0);
+}
+
+/// returns true if handles are being tracked
+pub fn enabled() -> bool {
+    *ENABLED
+}
+
+/// record a new handle, returns the id to untrack it with. The id 0 is
+/// returned when tracking is disabled.
+pub(crate) fn track(
+    kind: HandleKind,
+    bdev: &str,
+    owner: &Location<'static>,
+) -> u64 {
+    if !enabled() {
+        return 0;
+    }
+
+    let core = Cores::current();
+    if Reactors::get_by_core(core).is_none() {
+        return 0;
+    }
+
+    let id = CREATED.with(|created| {
+        created.set(created.get() + 1);
+        (u64::from(core) << CORE_SHIFT) | created.get()
+    });
+    let info = HandleInfo {
+        id,
+        kind,
+        bdev: bdev.to_string(),
+        owner: format!("{}:{}", owner.file(), owner.line()),
+        thread: Mthread::current()
+            .map_or_else(|| "none".to_string(), |t| t.name().to_string()),
+    };
+
+    HANDLES.with(|handles| handles.borrow_mut().insert(id, info));
+    id
+}
+
+/// remove a handle when it is closed, on the core which created it
+pub(crate) fn untrack(id: u64) {
+    if id == 0 {
+        return;
+    }
+
+    let core = (id >> CORE_SHIFT) as u32;
+    if core == Cores::current() {
+        HANDLES.with(|handles| handles.borrow_mut().remove(&id));
+    } else if let Some(reactor) = Reactors::get_by_core(core) {
+        reactor.send_future(async move {
+            HANDLES.with(|handles| handles.borrow_mut().remove(&id));
+        });
+    }
+}
+
+/// list the open handles of all cores, optionally only those of the given
+/// bdev
+pub async fn handles(bdev: Option<&str>) -> Vec<HandleInfo> {
+    let bdev = bdev.map(String::from);
+    let mut list = Reactors::on_each(move || {
+        HANDLES.with(|handles| {
+            handles
+                .borrow()
+                .values()
+                .filter(|h| bdev.as_ref().map_or(true, |name| &h.bdev == name))
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+    })
+    .await
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    list.sort_by_key(|h| h.id);
+    list
+}
+
+/// log the handles which are still open on a bdev that failed to be
+/// destroyed
+pub async fn warn_open_handles(bdev: &str) {
+    let list = handles(Some(bdev)).await;
+    if list.is_empty() {
+        return;
+    }
+
+    warn!(
+        "bdev {} has {} open handle(s) which may block its destruction",
+        bdev,
+        list.len()
+    );
+
+    list.iter().for_each(|h| {
+        warn!(
+            "  {} #{} opened by {} on thread {}",
+            h.kind, h.id, h.owner, h.thread
+        )
+    });
+}
@@ -0,0 +1,239 @@
+//!
+//! Zero-copy reads of a bdev.
+//!
+//! A bdev which supports zero-copy lends the buffers holding the data of a
+//! read rather than copying the data to ours, i.e. the malloc bdev hands out
+//! its own memory. A ['ZcopyBuf'] holds on to the buffers of such a read
+//! until it is dropped, which gives them back to the bdev. With a bdev that
+//! does not support it, the data is read into a ['DmaBuf'] instead, so the
+//! caller does not have to care which of the two it got.
+
+use std::{os::raw::c_void, slice::from_raw_parts};
+
+use futures::channel::oneshot;
+use nix::errno::Errno;
+
+use spdk_sys::{
+    iovec,
+    spdk_bdev_free_io,
+    spdk_bdev_io,
+    spdk_bdev_io_get_iovec,
+    spdk_bdev_writev,
+    spdk_bdev_zcopy_end,
+    spdk_bdev_zcopy_start,
+};
+
+use crate::{
+    bdev::nexus::nexus_io::io_type,
+    core::{BdevHandle, CoreError, DmaBuf, IoCtxPool},
+    ffihelper::cb_arg,
+};
+
+/// The data of a read, in buffers lent by the bdev or in a buffer of ours.
+/// The buffers lent by the bdev are only valid as long as the handle they
+/// were read with, and must be given back on the core they were read on.
+pub struct ZcopyBuf<'a> {
+    _handle: &'a BdevHandle,
+    /// the zero-copy IO which holds the buffers, null for a copy
+    io: *mut spdk_bdev_io,
+    iovs: Vec<iovec>,
+    /// our buffer, if the data was copied
+    _copy: Option<DmaBuf>,
+}
+
+impl<'a> ZcopyBuf<'a> {
+    /// true if the buffers are lent by the bdev rather than copied
+    pub fn is_zero_copy(&self) -> bool {
+        !self.io.is_null()
+    }
+
+    /// the buffers holding the data, in order
+    pub fn slices(&self) -> impl Iterator<Item = &[u8]> {
+        self.iovs.iter().map(|iov| unsafe {
+            from_raw_parts(iov.iov_base as *const u8, iov.iov_len as usize)
+        })
+    }
+
+    /// total length of the data in bytes
+    pub fn len(&self) -> usize {
+        self.iovs.iter().map(|iov| iov.iov_len as usize).sum()
+    }
+
+    /// true if there is no data
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for ZcopyBuf<'_> {
+    fn drop(&mut self) {
+        if self.io.is_null() {
+            return;
+        }
+        // the data was only read, give the buffers back without committing
+        let rc = unsafe {
+            spdk_bdev_zcopy_end(
+                self.io,
+                false,
+                Some(zcopy_end_cb),
+                std::ptr::null_mut(),
+            )
+        };
+        if rc != 0 {
+            error!("failed to end zero-copy IO {:p}: {}", self.io, rc);
+            unsafe { spdk_bdev_free_io(self.io) };
+        }
+    }
+}
+
+/// completion of the start of a zero-copy IO, which is only freed once it
+/// has been ended
+extern "C" fn zcopy_start_cb(
+    io: *mut spdk_bdev_io,
+    success: bool,
+    arg: *mut c_void,
+) {
+    let sender = unsafe {
+        Box::from_raw(arg as *mut oneshot::Sender<(bool, *mut spdk_bdev_io)>)
+    };
+    if sender.send((success, io)).is_err() {
+        unsafe { spdk_bdev_free_io(io) };
+    }
+}
+
+extern "C" fn zcopy_end_cb(
+    io: *mut spdk_bdev_io,
+    _success: bool,
+    _arg: *mut c_void,
+) {
+    unsafe { spdk_bdev_free_io(io) };
+}
+
+impl BdevHandle {
+    /// true if the bdev lends the buffers of its reads
+    pub fn supports_zcopy(&self) -> bool {
+        self.get_bdev().io_type_supported(io_type::ZCOPY)
+    }
+
+    /// Read len bytes at the given offset, both of which must be multiples
+    /// of the block size, in the buffers of the bdev if it supports
+    /// zero-copy, or else into a buffer of ours.
+    pub async fn read_zcopy_at(
+        &self,
+        offset: u64,
+        len: u64,
+    ) -> Result<ZcopyBuf<'_>, CoreError> {
+        let bdev = self.get_bdev();
+        let block_len = bdev.block_len() as u64;
+        if offset % block_len != 0 || len % block_len != 0 {
+            return Err(CoreError::InvalidOffset {
+                offset,
+            });
+        }
+
+        if !self.supports_zcopy() {
+            let mut buf = self.dma_malloc(len as usize).map_err(|source| {
+                CoreError::ReadAlloc {
+                    source,
+                    offset,
+                    len: len as usize,
+                }
+            })?;
+            self.read_at(offset, &mut buf).await?;
+            let iovs = vec![iovec {
+                iov_base: *buf,
+                iov_len: buf.len() as _,
+            }];
+            return Ok(ZcopyBuf {
+                _handle: self,
+                io: std::ptr::null_mut(),
+                iovs,
+                _copy: Some(buf),
+            });
+        }
+
+        let (s, r) = oneshot::channel::<(bool, *mut spdk_bdev_io)>();
+        let (desc, ch) = self.io_tuple();
+        let errno = unsafe {
+            spdk_bdev_zcopy_start(
+                desc,
+                ch,
+                offset / block_len,
+                len / block_len,
+                true,
+                Some(zcopy_start_cb),
+                cb_arg(s),
+            )
+        };
+        if errno != 0 {
+            return Err(CoreError::ZcopyDispatch {
+                source: Errno::from_i32(errno),
+                offset,
+                len: len as usize,
+            });
+        }
+
+        let (success, io) = r.await.expect("Failed awaiting zero-copy IO");
+        if !success {
+            unsafe { spdk_bdev_free_io(io) };
+            return Err(CoreError::ZcopyFailed {
+                offset,
+                len: len as usize,
+            });
+        }
+
+        let mut iovp: *mut iovec = std::ptr::null_mut();
+        let mut iovcnt = 0;
+        let iovs = unsafe {
+            spdk_bdev_io_get_iovec(io, &mut iovp, &mut iovcnt);
+            if iovp.is_null() {
+                Vec::new()
+            } else {
+                from_raw_parts(iovp, iovcnt as usize).to_vec()
+            }
+        };
+
+        Ok(ZcopyBuf {
+            _handle: self,
+            io,
+            iovs,
+            _copy: None,
+        })
+    }
+
+    /// write the data of a read, possibly of another bdev, at the given
+    /// offset without copying it
+    pub async fn write_zcopy_at(
+        &self,
+        offset: u64,
+        buf: &ZcopyBuf<'_>,
+    ) -> Result<usize, CoreError> {
+        let len = buf.len();
+        let status = IoCtxPool::submit(|cb, arg| unsafe {
+            spdk_bdev_writev(
+                self.desc.as_ptr(),
+                self.channel.as_ptr(),
+                buf.iovs.as_ptr() as *mut iovec,
+                buf.iovs.len() as i32,
+                offset,
+                len as u64,
+                Some(cb),
+                arg,
+            )
+        })
+        .await;
+
+        match status {
+            Ok(true) => Ok(len),
+            Ok(false) => Err(CoreError::WriteFailed {
+                offset,
+                len,
+            }),
+            Err(errno) => Err(CoreError::WriteDispatch {
+                source: Errno::from_i32(errno),
+                offset,
+                len,
+            }),
+        }
+    }
+}
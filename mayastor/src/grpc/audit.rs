@@ -0,0 +1,251 @@
+//!
+//! Audit log of the gRPC methods which change the state of mayastor. Every
+//! call of such a method is recorded with its arguments, its result and how
+//! long it took, to find out after the fact what the control plane did. The
+//! most recent records are kept in memory and can be queried by gRPC, all of
+//! them can be appended to a file as JSON lines as well.
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{future::BoxFuture, TryStreamExt};
+use http::{HeaderMap, Request, Response};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tonic::transport::Body;
+use tower::Service;
+
+use rpc::mayastor::*;
+
+/// max number of records kept in memory
+const AUDIT_LOG_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    /// start of the call in milliseconds since the epoch
+    pub timestamp_ms: u64,
+    /// id from the x-request-id header, if the caller passed one
+    pub request_id: String,
+    /// full path of the method, i.e. /mayastor.Mayastor/CreateNexus
+    pub method: String,
+    pub arguments: String,
+    /// gRPC status code of the reply, 0 if the call succeeded
+    pub code: i32,
+    pub message: String,
+    pub duration_us: u64,
+}
+
+#[derive(Default)]
+struct AuditLog {
+    records: VecDeque<AuditRecord>,
+    file: Option<File>,
+}
+
+static AUDIT_LOG: Lazy<Mutex<AuditLog>> =
+    Lazy::new(|| Mutex::new(AuditLog::default()));
+
+/// Generates the list of the audited methods and the decoding of their
+/// arguments, which arrive as a gRPC message in the body of the request.
+macro_rules! audited {
+    ($($method:expr => $args:ty,)*) => {
+        /// whether the method changes the state of mayastor
+        pub(crate) fn is_audited(method: &str) -> bool {
+            match method {
+                $($method => true,)*
+                _ => false,
+            }
+        }
+
+        fn arguments(method: &str, body: &[u8]) -> String {
+            match method {
+                $($method => decode::<$args>(body),)*
+                _ => String::new(),
+            }
+        }
+    };
+}
+
+audited! {
+    "/mayastor.Mayastor/CreatePool" => CreatePoolRequest,
+    "/mayastor.Mayastor/DestroyPool" => DestroyPoolRequest,
+    "/mayastor.Mayastor/CreateReplica" => CreateReplicaRequest,
+    "/mayastor.Mayastor/CreateReplicaFromSnapshot" =>
+        CreateReplicaFromSnapshotRequest,
+    "/mayastor.Mayastor/DestroyReplica" => DestroyReplicaRequest,
+    "/mayastor.Mayastor/ShareReplica" => ShareReplicaRequest,
+    "/mayastor.Mayastor/ReshareReplica" => ReshareReplicaRequest,
+    "/mayastor.Mayastor/SetReplicaFlushPolicy" => SetReplicaFlushPolicyRequest,
+    "/mayastor.Mayastor/SetReplicaMetadata" => SetReplicaMetadataRequest,
+    "/mayastor.Mayastor/SetBdevQos" => SetBdevQosRequest,
+    "/mayastor.Mayastor/CreateNexus" => CreateNexusRequest,
+    "/mayastor.Mayastor/StartCreateNexus" => CreateNexusRequest,
+    "/mayastor.Mayastor/DestroyNexus" => DestroyNexusRequest,
+    "/mayastor.Mayastor/AddChildNexus" => AddChildNexusRequest,
+    "/mayastor.Mayastor/RemoveChildNexus" => RemoveChildNexusRequest,
+    "/mayastor.Mayastor/ResetStats" => ResetStatsRequest,
+    "/mayastor.Mayastor/PublishNexus" => PublishNexusRequest,
+    "/mayastor.Mayastor/UnpublishNexus" => UnpublishNexusRequest,
+    "/mayastor.Mayastor/RotateNexusKey" => RotateNexusKeyRequest,
+    "/mayastor.Mayastor/StopKeyRotation" => StopKeyRotationRequest,
+    "/mayastor.Mayastor/SetNexusAnaState" => SetNexusAnaStateRequest,
+    "/mayastor.Mayastor/SetNexusWriteCache" => SetNexusWriteCacheRequest,
+    "/mayastor.Mayastor/CreateNexusSnapshot" => CreateNexusSnapshotRequest,
+    "/mayastor.Mayastor/RestoreNexusFromSnapshot" => RestoreNexusFromSnapshotRequest,
+    "/mayastor.Mayastor/SetChildFaultPolicy" => SetChildFaultPolicyRequest,
+    "/mayastor.Mayastor/SetNexusReadPolicy" => SetNexusReadPolicyRequest,
+    "/mayastor.Mayastor/SetUriResolver" => SetUriResolverRequest,
+    "/mayastor.Mayastor/ChildOperation" => ChildNexusRequest,
+    "/mayastor.Mayastor/StartRebuild" => StartRebuildRequest,
+    "/mayastor.Mayastor/StopRebuild" => StopRebuildRequest,
+    "/mayastor.Mayastor/PauseRebuild" => PauseRebuildRequest,
+    "/mayastor.Mayastor/ResumeRebuild" => ResumeRebuildRequest,
+    "/mayastor.Mayastor/CancelOperation" => OperationRequest,
+    "/mayastor.Mayastor/ReloadConfig" => Null,
+    "/mayastor.Mayastor/JsonRpcCall" => JsonRpcCallRequest,
+    "/mayastor.BdevRpc/Create" => BdevUri,
+    "/mayastor.BdevRpc/Destroy" => BdevUri,
+    "/mayastor.BdevRpc/Share" => BdevShareRequest,
+    "/mayastor.BdevRpc/Unshare" => CreateReply,
+    "/mayastor.BdevRpc/EnableLatencyHistogram" =>
+        EnableLatencyHistogramRequest,
+    "/mayastor.BdevRpc/InjectFault" => InjectFaultRequest,
+    "/mayastor.BdevRpc/ClearFaults" => FaultsRequest,
+    "/mayastor.BdevRpc/SetDelay" => SetDelayRequest,
+}
+
+/// decode the first message of a request body, which is prefixed by a
+/// compression flag and its length
+fn decode<T: prost::Message + Default + Debug>(body: &[u8]) -> String {
+    if body.len() < 5 || body[0] != 0 {
+        return String::new();
+    }
+
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]);
+    body.get(5 .. 5 + len as usize)
+        .and_then(|msg| T::decode(msg).ok())
+        .map(|args| format!("{:?}", args))
+        .unwrap_or_default()
+}
+
+/// The status of a failed call is sent in the headers of the reply, that of
+/// a successful call in the trailers after the reply message.
+fn status(headers: &HeaderMap) -> (i32, String) {
+    let code = headers
+        .get("grpc-status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let message = headers
+        .get("grpc-message")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    (code, message)
+}
+
+/// Open the file the records are appended to, in addition to keeping them in
+/// memory.
+pub fn init(path: Option<&str>) {
+    let path = match path {
+        Some(path) => path,
+        None => return,
+    };
+
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => {
+            info!("gRPC audit log is written to {}", path);
+            AUDIT_LOG.lock().unwrap().file = Some(file);
+        }
+        Err(e) => {
+            error!("Failed to open gRPC audit log {}: {}", path, e);
+        }
+    }
+}
+
+fn record(record: AuditRecord) {
+    let mut log = AUDIT_LOG.lock().unwrap();
+
+    if let Some(file) = log.file.as_mut() {
+        let line = serde_json::to_string(&record).unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            error!("Failed to write gRPC audit log: {}", e);
+        }
+    }
+
+    if log.records.len() == AUDIT_LOG_SIZE {
+        log.records.pop_front();
+    }
+    log.records.push_back(record);
+}
+
+/// the records in memory, oldest first
+pub fn records() -> Vec<AuditRecord> {
+    AUDIT_LOG.lock().unwrap().records.iter().cloned().collect()
+}
+
+/// Interceptor of the gRPC server which records the calls of the audited
+/// methods. The body of the request is copied while the method reads it, as
+/// it only is available as a stream.
+pub(crate) fn intercept<S, B>(
+    svc: &mut S,
+    req: Request<Body>,
+) -> BoxFuture<'static, Result<Response<B>, S::Error>>
+where
+    S: Service<Request<Body>, Response = Response<B>>,
+    S::Future: Send + 'static,
+    S::Error: ToString,
+{
+    let method = req.uri().path().to_string();
+    if !is_audited(&method) {
+        return Box::pin(svc.call(req));
+    }
+
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let copy = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&copy);
+    let (parts, body) = req.into_parts();
+    let body = Body::wrap_stream(body.inspect_ok(move |chunk| {
+        sink.lock().unwrap().extend_from_slice(&chunk[..])
+    }));
+
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let started = Instant::now();
+    let reply = svc.call(Request::from_parts(parts, body));
+
+    Box::pin(async move {
+        let reply = reply.await;
+        let duration_us = started.elapsed().as_micros() as u64;
+
+        let (code, message) = match &reply {
+            Ok(reply) => status(reply.headers()),
+            Err(e) => (tonic::Code::Unknown as i32, e.to_string()),
+        };
+        let arguments = arguments(&method, &copy.lock().unwrap());
+
+        record(AuditRecord {
+            timestamp_ms,
+            request_id,
+            method,
+            arguments,
+            code,
+            message,
+            duration_us,
+        });
+
+        reply
+    })
+}
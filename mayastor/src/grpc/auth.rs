@@ -0,0 +1,127 @@
+//!
+//! Auth policies of the endpoints of the gRPC server. The Unix domain socket
+//! of the co-located CSI node plugin is protected by the permissions of the
+//! socket file, while a TCP endpoint reachable by the whole cluster can
+//! require a token, possibly only for the methods which change the state of
+//! mayastor, or only allow the methods which do not, i.e. for monitoring. It
+//! can also require the callers to present a certificate over TLS, which is
+//! set up along with the server. The methods which bypass the typed API
+//! are only allowed on the endpoints marked admin. A call refused by the
+//! policy of its endpoint never reaches the service, nor the audit log.
+use std::fs;
+
+use futures::future::{self, BoxFuture};
+use http::{HeaderValue, Request, Response};
+use tonic::{body::BoxBody, transport::Body, Code};
+use tower::Service;
+
+use crate::{grpc::audit, subsys::GrpcEndpointOpts};
+
+/// whether the method bypasses the typed API, which only the callers of an
+/// admin endpoint are trusted with
+fn is_admin(method: &str) -> bool {
+    method == "/mayastor.Mayastor/JsonRpcCall"
+}
+
+/// what the callers of an endpoint are allowed to do
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Policy {
+    /// token the callers must pass, if any
+    token: Option<String>,
+    token_for_changes_only: bool,
+    read_only: bool,
+    admin: bool,
+}
+
+impl Policy {
+    /// the policy of the endpoint, with the token read from its file
+    pub(crate) fn new(opts: &GrpcEndpointOpts) -> Result<Self, String> {
+        let token = match opts.token_file.as_ref() {
+            Some(path) => {
+                let token = fs::read_to_string(path).map_err(|e| {
+                    format!("failed to read token file {}: {}", path, e)
+                })?;
+                let token = token.trim().to_string();
+                if token.is_empty() {
+                    return Err(format!("token file {} is empty", path));
+                }
+                Some(token)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            token,
+            token_for_changes_only: opts.token_for_changes_only,
+            read_only: opts.read_only,
+            admin: opts.admin,
+        })
+    }
+
+    /// the code and reason the call is refused with, none if it is allowed
+    fn check(&self, req: &Request<Body>) -> Option<(Code, &'static str)> {
+        let changes = audit::is_audited(req.uri().path());
+
+        if let Some(token) = self
+            .token
+            .as_ref()
+            .filter(|_| changes || !self.token_for_changes_only)
+        {
+            let bearer = req
+                .headers()
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| v.starts_with("Bearer "))
+                .map(|v| &v["Bearer ".len() ..]);
+            if bearer != Some(token.as_str()) {
+                return Some((
+                    Code::Unauthenticated,
+                    "invalid or missing token",
+                ));
+            }
+        }
+
+        if self.read_only && changes {
+            return Some((Code::PermissionDenied, "the endpoint is read-only"));
+        }
+
+        if !self.admin && is_admin(req.uri().path()) {
+            return Some((
+                Code::PermissionDenied,
+                "the method is only allowed on admin endpoints",
+            ));
+        }
+
+        None
+    }
+}
+
+/// Interceptor of the gRPC server which refuses the calls the policy of the
+/// endpoint does not allow, and passes the others on to the audit log.
+pub(crate) fn intercept<S>(
+    policy: &Policy,
+    svc: &mut S,
+    req: Request<Body>,
+) -> BoxFuture<'static, Result<Response<BoxBody>, S::Error>>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: ToString + Send + 'static,
+{
+    let (code, message) = match policy.check(&req) {
+        Some(refused) => refused,
+        None => return audit::intercept(svc, req),
+    };
+
+    warn!("Refused gRPC call {}: {}", req.uri().path(), message);
+
+    // the status of a call which fails before its reply is sent in the
+    // headers, as for any other failed call
+    let mut reply = Response::new(BoxBody::empty());
+    let headers = reply.headers_mut();
+    headers
+        .insert("content-type", HeaderValue::from_static("application/grpc"));
+    headers.insert("grpc-status", HeaderValue::from(code as i32));
+    headers.insert("grpc-message", HeaderValue::from_static(message));
+    Box::pin(future::ready(Ok(reply)))
+}
@@ -1,7 +1,7 @@
 use tonic::{Request, Response, Status};
 use tracing::instrument;
 
-use std::convert::TryFrom;
+use std::{convert::TryFrom, time::Duration};
 use url::Url;
 
 use rpc::mayastor::{
@@ -12,13 +12,39 @@ use rpc::mayastor::{
     BdevUri,
     Bdevs,
     CreateReply,
+    DelayReply,
+    DelayRequest,
+    EnableLatencyHistogramRequest,
+    Fault as RpcFault,
+    FaultIoType,
+    FaultsRequest,
+    InjectFaultRequest,
+    IoDelay as RpcIoDelay,
+    LatencyBucket,
+    LatencyHistogramReply,
+    LatencyHistogramRequest,
+    ListFaultsReply,
+    ListHandlesReply,
+    ListHandlesRequest,
     Null,
+    NvmeTelemetryLogReply,
+    NvmeTelemetryLogRequest,
+    OpenHandle,
+    SetDelayRequest,
 };
 
 use crate::{
-    core::{Bdev, Reactors, Share},
-    grpc::{sync_config, GrpcResult},
-    nexus_uri::{bdev_create, bdev_destroy, NexusBdevError},
+    bdev::{
+        delay::{self, Delays, IoDelay},
+        fault::{self, Fault, FaultIo},
+    },
+    core::{tracker, Bdev, CoreError, Reactors, Share},
+    grpc::{
+        queue::{self, Resource},
+        sync_config,
+        GrpcResult,
+    },
+    nexus_uri::{bdev_create, bdev_destroy, bdev_get_name, NexusBdevError},
 };
 
 impl From<NexusBdevError> for tonic::Status {
@@ -33,6 +59,12 @@ impl From<NexusBdevError> for tonic::Status {
             NexusBdevError::UriInvalid {
                 ..
             } => Status::invalid_argument(e.to_string()),
+            NexusBdevError::ResolveHost {
+                ..
+            } => Status::unavailable(e.to_string()),
+            NexusBdevError::ResolveLink {
+                ..
+            } => Status::not_found(e.to_string()),
             e => Status::internal(e.to_string()),
         }
     }
@@ -55,6 +87,80 @@ impl From<Bdev> for RpcBdev {
     }
 }
 
+impl From<RpcFault> for Fault {
+    fn from(f: RpcFault) -> Self {
+        Self {
+            io: match FaultIoType::from_i32(f.io_type) {
+                Some(FaultIoType::FaultIoRead) => FaultIo::Read,
+                Some(FaultIoType::FaultIoWrite) => FaultIo::Write,
+                _ => FaultIo::All,
+            },
+            offset: f.offset,
+            num_blocks: f.num_blocks,
+            frequency: f.frequency,
+            count: f.count,
+            delay: if f.delay_us > 0 {
+                Some(Duration::from_micros(f.delay_us))
+            } else {
+                None
+            },
+            matched: f.matched,
+            injected: f.injected,
+        }
+    }
+}
+
+impl From<Fault> for RpcFault {
+    fn from(f: Fault) -> Self {
+        Self {
+            io_type: match f.io {
+                FaultIo::All => FaultIoType::FaultIoAll,
+                FaultIo::Read => FaultIoType::FaultIoRead,
+                FaultIo::Write => FaultIoType::FaultIoWrite,
+            } as i32,
+            offset: f.offset,
+            num_blocks: f.num_blocks,
+            frequency: f.frequency,
+            count: f.count,
+            delay_us: f.delay.map_or(0, |d| d.as_micros() as u64),
+            matched: f.matched,
+            injected: f.injected,
+        }
+    }
+}
+
+impl From<RpcIoDelay> for IoDelay {
+    fn from(d: RpcIoDelay) -> Self {
+        Self {
+            latency: Duration::from_micros(d.latency_us),
+            jitter: Duration::from_micros(d.jitter_us),
+        }
+    }
+}
+
+impl From<IoDelay> for RpcIoDelay {
+    fn from(d: IoDelay) -> Self {
+        Self {
+            latency_us: d.latency.as_micros() as u64,
+            jitter_us: d.jitter.as_micros() as u64,
+        }
+    }
+}
+
+/// the name of the error or delay bdev given by its name or its URI
+fn test_bdev_name(name: String) -> String {
+    if name.contains("://") {
+        bdev_get_name(&name).unwrap_or(name)
+    } else {
+        name
+    }
+}
+
+/// the bdev created or destroyed by its URI, which is queued on by its name
+fn resource(uri: &str) -> Resource {
+    Resource::Bdev(bdev_get_name(uri).unwrap_or_else(|_| uri.to_string()))
+}
+
 #[derive(Debug)]
 pub struct BdevSvc {}
 
@@ -62,10 +168,13 @@ pub struct BdevSvc {}
 impl BdevRpc for BdevSvc {
     #[instrument(level = "debug", err)]
     async fn list(&self, _request: Request<Null>) -> GrpcResult<Bdevs> {
-        let mut list: Vec<RpcBdev> = Vec::new();
-        if let Some(bdev) = Bdev::bdev_first() {
-            bdev.into_iter().for_each(|bdev| list.push(bdev.into()))
-        }
+        let list = on_mgmt! { async {
+            let mut list: Vec<RpcBdev> = Vec::new();
+            if let Some(bdev) = Bdev::bdev_first() {
+                bdev.into_iter().for_each(|bdev| list.push(bdev.into()))
+            }
+            Ok::<_, Status>(list)
+        }};
 
         Ok(Response::new(Bdevs {
             bdevs: list,
@@ -79,6 +188,7 @@ impl BdevRpc for BdevSvc {
     ) -> Result<Response<CreateReply>, Status> {
         sync_config(async {
             let uri = request.into_inner().uri;
+            let _turn = queue::turn(resource(&uri)).await?;
             let bdev = locally! { async move { bdev_create(&uri).await } };
 
             Ok(Response::new(CreateReply {
@@ -92,6 +202,7 @@ impl BdevRpc for BdevSvc {
     async fn destroy(&self, request: Request<BdevUri>) -> GrpcResult<Null> {
         sync_config(async {
             let uri = request.into_inner().uri;
+            let _turn = queue::turn(resource(&uri)).await?;
             let _bdev = locally! { async move { bdev_destroy(&uri).await } };
 
             Ok(Response::new(Null {}))
@@ -108,6 +219,7 @@ impl BdevRpc for BdevSvc {
             let r = request.into_inner();
             let name = r.name;
             let proto = r.proto;
+            let _turn = queue::turn(Resource::Bdev(name.clone())).await?;
 
             if Bdev::lookup_by_name(&name).is_none() {
                 return Err(Status::not_found(name));
@@ -150,6 +262,7 @@ impl BdevRpc for BdevSvc {
     async fn unshare(&self, request: Request<CreateReply>) -> GrpcResult<Null> {
         sync_config(async {
             let name = request.into_inner().name;
+            let _turn = queue::turn(Resource::Bdev(name.clone())).await?;
             let hdl = Reactors::master().spawn_local(async move {
                 let bdev = Bdev::lookup_by_name(&name).unwrap();
                 let _ = bdev
@@ -163,4 +276,241 @@ impl BdevRpc for BdevSvc {
         })
         .await
     }
+
+    #[instrument(level = "debug", err)]
+    async fn list_handles(
+        &self,
+        request: Request<ListHandlesRequest>,
+    ) -> GrpcResult<ListHandlesReply> {
+        let name = request.into_inner().name;
+        let filter = if name.is_empty() {
+            None
+        } else {
+            Some(name.as_str())
+        };
+
+        Ok(Response::new(ListHandlesReply {
+            enabled: tracker::enabled(),
+            handles: tracker::handles(filter)
+                .await
+                .into_iter()
+                .map(|h| OpenHandle {
+                    id: h.id,
+                    kind: h.kind.to_string(),
+                    bdev: h.bdev,
+                    owner: h.owner,
+                    thread: h.thread,
+                })
+                .collect(),
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn enable_latency_histogram(
+        &self,
+        request: Request<EnableLatencyHistogramRequest>,
+    ) -> GrpcResult<Null> {
+        sync_config(async {
+            let r = request.into_inner();
+            let _turn = queue::turn(Resource::Bdev(r.name.clone())).await?;
+
+            if Bdev::lookup_by_name(&r.name).is_none() {
+                return Err(Status::not_found(r.name));
+            }
+
+            Reactors::master()
+                .spawn_local(async move {
+                    let bdev = Bdev::lookup_by_name(&r.name).unwrap();
+                    bdev.enable_histogram(r.enable)
+                        .await
+                        .map_err(|e| Status::internal(e.to_string()))
+                })
+                .await
+                .unwrap()?;
+
+            Ok(Response::new(Null {}))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_latency_histogram(
+        &self,
+        request: Request<LatencyHistogramRequest>,
+    ) -> GrpcResult<LatencyHistogramReply> {
+        let name = request.into_inner().name;
+
+        match Bdev::lookup_by_name(&name) {
+            None => return Err(Status::not_found(name)),
+            Some(bdev) if !bdev.histogram_enabled() => {
+                return Ok(Response::new(LatencyHistogramReply {
+                    enabled: false,
+                    count: 0,
+                    buckets: Vec::new(),
+                }))
+            }
+            Some(_) => {}
+        }
+
+        let buckets = Reactors::master()
+            .spawn_local(async move {
+                let bdev = Bdev::lookup_by_name(&name).unwrap();
+                bdev.latency_histogram()
+                    .await
+                    .map_err(|e| Status::internal(e.to_string()))
+            })
+            .await
+            .unwrap()?;
+
+        Ok(Response::new(LatencyHistogramReply {
+            enabled: true,
+            count: buckets.iter().map(|b| b.count).sum(),
+            buckets: buckets
+                .into_iter()
+                .map(|b| LatencyBucket {
+                    start_ns: b.start_ns,
+                    end_ns: b.end_ns,
+                    count: b.count,
+                })
+                .collect(),
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_nvme_telemetry_log(
+        &self,
+        request: Request<NvmeTelemetryLogRequest>,
+    ) -> GrpcResult<NvmeTelemetryLogReply> {
+        let r = request.into_inner();
+
+        let data_area = match r.data_area {
+            0 => 3,
+            1 ..= 3 => r.data_area as u8,
+            n => {
+                return Err(Status::invalid_argument(format!(
+                    "invalid telemetry data area {}",
+                    n
+                )))
+            }
+        };
+
+        if Bdev::lookup_by_name(&r.name).is_none() {
+            return Err(Status::not_found(r.name));
+        }
+
+        let log = Reactors::master()
+            .spawn_local(async move {
+                let bdev = Bdev::lookup_by_name(&r.name).unwrap();
+                bdev.nvme_telemetry_log(data_area)
+                    .await
+                    .map_err(|e| match e {
+                        CoreError::NvmeTelemetryUnsupported {
+                            ..
+                        } => Status::failed_precondition(e.to_string()),
+                        e => Status::internal(e.to_string()),
+                    })
+            })
+            .await
+            .unwrap()?;
+
+        Ok(Response::new(NvmeTelemetryLogReply {
+            log,
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn inject_fault(
+        &self,
+        request: Request<InjectFaultRequest>,
+    ) -> GrpcResult<Null> {
+        let r = request.into_inner();
+        let name = test_bdev_name(r.name);
+        let fault = r.fault.map(Fault::from).unwrap_or_default();
+
+        if !fault::inject(&name, fault) {
+            return Err(Status::not_found(format!(
+                "no error bdev named {}",
+                name
+            )));
+        }
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn list_faults(
+        &self,
+        request: Request<FaultsRequest>,
+    ) -> GrpcResult<ListFaultsReply> {
+        let name = test_bdev_name(request.into_inner().name);
+
+        let faults = fault::list(&name).ok_or_else(|| {
+            Status::not_found(format!("no error bdev named {}", name))
+        })?;
+
+        Ok(Response::new(ListFaultsReply {
+            faults: faults.into_iter().map(RpcFault::from).collect(),
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn clear_faults(
+        &self,
+        request: Request<FaultsRequest>,
+    ) -> GrpcResult<Null> {
+        let name = test_bdev_name(request.into_inner().name);
+
+        if !fault::clear(&name) {
+            return Err(Status::not_found(format!(
+                "no error bdev named {}",
+                name
+            )));
+        }
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_delay(
+        &self,
+        request: Request<SetDelayRequest>,
+    ) -> GrpcResult<Null> {
+        let r = request.into_inner();
+        let name = test_bdev_name(r.name);
+
+        let current = delay::get(&name).ok_or_else(|| {
+            Status::not_found(format!("no delay bdev named {}", name))
+        })?;
+
+        let delays = Delays {
+            read: r.read.map_or(current.read, IoDelay::from),
+            write: r.write.map_or(current.write, IoDelay::from),
+        };
+
+        if !delay::set(&name, delays) {
+            return Err(Status::not_found(format!(
+                "no delay bdev named {}",
+                name
+            )));
+        }
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_delay(
+        &self,
+        request: Request<DelayRequest>,
+    ) -> GrpcResult<DelayReply> {
+        let name = test_bdev_name(request.into_inner().name);
+
+        let delays = delay::get(&name).ok_or_else(|| {
+            Status::not_found(format!("no delay bdev named {}", name))
+        })?;
+
+        Ok(Response::new(DelayReply {
+            read: Some(delays.read.into()),
+            write: Some(delays.write.into()),
+        }))
+    }
 }
@@ -0,0 +1,91 @@
+//!
+//! The standard gRPC health service, which Kubernetes probes use to tell
+//! whether mayastor is ready. The services are reported as not serving until
+//! the reactors are running, with the targets initialized and the pools of
+//! the configuration imported, and again from the moment mayastor shuts down.
+use std::pin::Pin;
+
+use futures::{stream, Stream, StreamExt};
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+use tonic::{Request, Response, Status};
+
+use rpc::health::{
+    health_check_response::ServingStatus,
+    health_server,
+    HealthCheckRequest,
+    HealthCheckResponse,
+};
+
+use crate::grpc::GrpcResult;
+
+/// services of the gRPC server, the empty name stands for the server as a
+/// whole
+const SERVICES: [&str; 3] = ["", "mayastor.Mayastor", "mayastor.BdevRpc"];
+
+static SERVING: Lazy<(
+    watch::Sender<ServingStatus>,
+    watch::Receiver<ServingStatus>,
+)> = Lazy::new(|| watch::channel(ServingStatus::NotServing));
+
+/// set whether mayastor is ready to serve requests
+pub fn set_serving(serving: bool) {
+    let status = if serving {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    };
+    info!("gRPC health status set to {:?}", status);
+    let _ = SERVING.0.broadcast(status);
+}
+
+fn reply(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse {
+        status: status as i32,
+    }
+}
+
+#[derive(Debug)]
+pub struct HealthSvc {}
+
+#[tonic::async_trait]
+impl health_server::Health for HealthSvc {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> GrpcResult<HealthCheckResponse> {
+        let service = request.into_inner().service;
+        if !SERVICES.contains(&service.as_str()) {
+            return Err(Status::not_found(format!(
+                "unknown service {}",
+                service
+            )));
+        }
+        Ok(Response::new(reply(*SERVING.1.borrow())))
+    }
+
+    type WatchStream = Pin<
+        Box<
+            dyn Stream<Item = Result<HealthCheckResponse, Status>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >;
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> GrpcResult<Self::WatchStream> {
+        let service = request.into_inner().service;
+        if !SERVICES.contains(&service.as_str()) {
+            return Ok(Response::new(Box::pin(stream::once(async {
+                Ok(reply(ServingStatus::ServiceUnknown))
+            }))));
+        }
+        // the receiver yields the current status first, then every change
+        Ok(Response::new(Box::pin(
+            SERVING.1.clone().map(|status| Ok(reply(status))),
+        )))
+    }
+}
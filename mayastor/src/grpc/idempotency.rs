@@ -0,0 +1,183 @@
+//!
+//! Deduplication of the gRPC calls which create, destroy or share an object
+//! by the idempotency key the caller passes along. The control plane retries
+//! a call which has timed out, while the call may still be running, as the
+//! work is done on the management core whether or not the caller is still
+//! waiting for the reply. Rather than racing with it, and failing because
+//! the object exists already or is half created, the retry waits for the
+//! reply of the first call. The replies of the calls which succeeded are
+//! kept, and exported to the config file along with the objects they
+//! created, so a retry after the call is done, or after a restart, gets the
+//! same reply again. A call which failed is forgotten once its reply is
+//! sent, so that it can be retried.
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Mutex,
+};
+
+use futures::channel::oneshot;
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tonic::{Code, Response, Status};
+
+use crate::{core::Reactors, grpc::GrpcResult, subsys::Operation};
+
+/// max number of the replies kept, the oldest are forgotten first
+const MAX_OPERATIONS: usize = 1024;
+
+/// the reply of a call, or the code and message of its error
+type Reply = Result<Value, (Code, String)>;
+
+/// the method and the idempotency key of a call
+type Id = (String, String);
+
+enum State {
+    /// the call is running, the callers wait for its reply
+    Running(Vec<oneshot::Sender<Reply>>),
+    /// the call succeeded with the reply
+    Done(Value),
+}
+
+#[derive(Default)]
+struct Operations {
+    states: HashMap<Id, State>,
+    /// the calls which are done, oldest first
+    done: VecDeque<Id>,
+}
+
+impl Operations {
+    fn insert_done(&mut self, id: Id, reply: Value) {
+        if self.done.len() == MAX_OPERATIONS {
+            if let Some(oldest) = self.done.pop_front() {
+                self.states.remove(&oldest);
+            }
+        }
+        self.states.insert(id.clone(), State::Done(reply));
+        self.done.push_back(id);
+    }
+}
+
+static OPERATIONS: Lazy<Mutex<Operations>> =
+    Lazy::new(|| Mutex::new(Operations::default()));
+
+fn response<T: DeserializeOwned>(reply: Value) -> GrpcResult<T> {
+    serde_json::from_value(reply).map(Response::new).map_err(|e| {
+        Status::internal(format!("Invalid reply of the operation: {}", e))
+    })
+}
+
+/// Record the reply of a call which is done and send it to its callers.
+fn finish(id: Id, reply: Reply) {
+    let mut operations = OPERATIONS.lock().unwrap();
+    let waiters = match operations.states.remove(&id) {
+        Some(State::Running(waiters)) => waiters,
+        _ => Vec::new(),
+    };
+    if let Ok(reply) = &reply {
+        operations.insert_done(id, reply.clone());
+    }
+    drop(operations);
+
+    for waiter in waiters {
+        let _ = waiter.send(reply.clone());
+    }
+}
+
+/// Run the call of the method unless a call with the same idempotency key is
+/// running or done, in which case its reply is returned instead. The call
+/// runs to completion on the current core even if the caller gives up on it.
+/// Without a key the call is simply run.
+pub(crate) async fn idempotent<F, T>(
+    method: &str,
+    key: &str,
+    future: F,
+) -> GrpcResult<T>
+where
+    F: Future<Output = GrpcResult<T>> + 'static,
+    T: Serialize + DeserializeOwned + 'static,
+{
+    if key.is_empty() {
+        return future.await;
+    }
+
+    let id = (method.to_string(), key.to_string());
+    let (sender, receiver) = oneshot::channel();
+    {
+        let mut operations = OPERATIONS.lock().unwrap();
+        match operations.states.get_mut(&id) {
+            Some(State::Done(reply)) => {
+                debug!("{} {} is done already", method, key);
+                return response(reply.clone());
+            }
+            Some(State::Running(waiters)) => {
+                debug!("{} {} is running, waiting for its reply", method, key);
+                waiters.push(sender);
+            }
+            None => {
+                operations
+                    .states
+                    .insert(id.clone(), State::Running(vec![sender]));
+                Reactors::current().spawn_local(
+                    tracing_futures::Instrument::instrument(
+                        async move {
+                            let reply = match future.await {
+                                Ok(reply) => {
+                                    serde_json::to_value(reply.get_ref())
+                                        .map_err(|e| {
+                                            (Code::Internal, e.to_string())
+                                        })
+                                }
+                                Err(status) => Err((
+                                    status.code(),
+                                    status.message().to_string(),
+                                )),
+                            };
+                            finish(id, reply);
+                        },
+                        tracing::Span::current(),
+                    ),
+                );
+            }
+        }
+    }
+
+    match receiver.await {
+        Ok(Ok(reply)) => response(reply),
+        Ok(Err((code, message))) => Err(Status::new(code, message)),
+        Err(_) => Err(Status::aborted(format!(
+            "{} {} has been cancelled",
+            method, key
+        ))),
+    }
+}
+
+/// the replies of the calls which are done, to be exported to the config
+pub(crate) fn done_operations() -> Vec<Operation> {
+    let operations = OPERATIONS.lock().unwrap();
+    operations
+        .done
+        .iter()
+        .filter_map(|id| match operations.states.get(id) {
+            Some(State::Done(reply)) => Some(Operation {
+                method: id.0.clone(),
+                key: id.1.clone(),
+                reply: reply.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Keep the replies of the calls done before mayastor was restarted, as read
+/// from the config.
+pub(crate) fn restore_operations(done: &[Operation]) {
+    let mut operations = OPERATIONS.lock().unwrap();
+    for op in done {
+        let id = (op.method.clone(), op.key.clone());
+        if !operations.states.contains_key(&id) {
+            operations.insert_done(id, op.reply.clone());
+        }
+    }
+}
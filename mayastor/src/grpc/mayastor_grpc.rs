@@ -1,28 +1,53 @@
 //! Mayastor grpc methods implementation.
 
+use std::{time::Duration, vec};
+
+use futures::stream;
 use tonic::{Request, Response, Status};
 use tracing::instrument;
 
 use rpc::mayastor::*;
 
 use crate::{
-    bdev::{
-        nexus::{instances, nexus_bdev},
-        nexus_create,
+    bdev::nexus::{
+        instances,
+        nexus_bdev,
+        nexus_bdev::ChildFaultPolicy,
+        nexus_resolver,
+        nexus_share::HostAccess,
     },
-    core::Cores,
+    core::{Cores, MayastorEnvironment},
     grpc::{
+        self,
+        audit,
+        idempotency::idempotent,
         nexus_grpc::{
+            create_nexus,
+            fault_policy_from_grpc,
             nexus_add_child,
             nexus_destroy,
             nexus_lookup,
+            nexus_stat,
+            nexus_switch_child,
+            nexus_topology,
+            read_policy_from_grpc,
             uuid_to_name,
         },
+        operation,
+        queue::{self, Resource},
+        resource::{get_core_stats, get_resource_usage},
         sync_config,
+        watch::{watch_nexuses, watch_pools, WatchStream},
         GrpcResult,
     },
+    iscsi_portal::Portal,
     pool,
+    probe,
+    rebuild::RebuildState,
     replica,
+    stats_store,
+    subsys::{connection_stats, Config},
+    verify,
 };
 
 #[derive(Debug)]
@@ -38,10 +63,12 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<CreatePoolRequest>,
     ) -> GrpcResult<Pool> {
-        sync_config(async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("CreatePool", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
             let name = args.name.clone();
+            let _turn = queue::turn(Resource::Pool(name.clone())).await?;
 
             if args.disks.is_empty() {
                 return Err(Status::invalid_argument("Missing devices"));
@@ -58,7 +85,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
 
             info!("Created or imported pool {}", name);
             Ok(Response::new(pool))
-        })
+        }))
         .await
     }
 
@@ -67,15 +94,17 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<DestroyPoolRequest>,
     ) -> GrpcResult<Null> {
-        sync_config(async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("DestroyPool", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
             let name = args.name.clone();
+            let _turn = queue::turn(Resource::Pool(name.clone())).await?;
             debug!("Destroying pool {} ...", name);
             locally! { pool::destroy_pool(args) };
             info!("Destroyed pool {}", name);
             Ok(Response::new(Null {}))
-        })
+        }))
         .await
     }
 
@@ -87,10 +116,38 @@ impl mayastor_server::Mayastor for MayastorSvc {
         let args = request.into_inner();
         trace!("{:?}", args);
         assert_eq!(Cores::current(), Cores::first());
-        let reply = ListPoolsReply {
-            pools: pool::PoolsIter::new().map(|p| p.into()).collect::<Vec<_>>(),
-        };
+        let reply = on_mgmt! { async {
+            Ok::<_, Status>(ListPoolsReply {
+                pools: pool::PoolsIter::new()
+                    .map(|p| p.into())
+                    .collect::<Vec<_>>(),
+            })
+        }};
+
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    type WatchPoolsStream = WatchStream<WatchPoolsReply>;
 
+    #[instrument(level = "debug", err)]
+    async fn watch_pools(
+        &self,
+        request: Request<Null>,
+    ) -> GrpcResult<Self::WatchPoolsStream> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        Ok(Response::new(watch_pools()))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_disk_health(
+        &self,
+        request: Request<GetDiskHealthRequest>,
+    ) -> GrpcResult<GetDiskHealthReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let reply = locally! { pool::disk_health(args) };
         trace!("{:?}", reply);
         Ok(Response::new(reply))
     }
@@ -100,15 +157,40 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<CreateReplicaRequest>,
     ) -> GrpcResult<Replica> {
-        sync_config(async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("CreateReplica", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Replica(uuid.clone())).await?;
             debug!("Creating replica {} on {} ...", uuid, args.pool);
             let replica = locally! { replica::create_replica(args) };
             info!("Created replica {} ...", uuid);
             Ok(Response::new(replica))
-        })
+        }))
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn create_replica_from_snapshot(
+        &self,
+        request: Request<CreateReplicaFromSnapshotRequest>,
+    ) -> GrpcResult<Replica> {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("CreateReplicaFromSnapshot", &key, async move {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Replica(uuid.clone())).await?;
+            debug!(
+                "Creating replica {} from snapshot {} ...",
+                uuid, args.snapshot
+            );
+            let replica =
+                locally! { replica::create_replica_from_snapshot(args) };
+            info!("Created replica {} from snapshot", uuid);
+            Ok(Response::new(replica))
+        }))
         .await
     }
 
@@ -117,27 +199,31 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<DestroyReplicaRequest>,
     ) -> GrpcResult<Null> {
-        sync_config(async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("DestroyReplica", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Replica(uuid.clone())).await?;
             debug!("Destroying replica {} ...", uuid);
             locally! { replica::destroy_replica(args) };
             info!("Destroyed replica {} ...", uuid);
             Ok(Response::new(Null {}))
-        })
+        }))
         .await
     }
 
     #[instrument(level = "debug", err)]
     async fn list_replicas(
         &self,
-        request: Request<Null>,
+        request: Request<ListReplicasRequest>,
     ) -> GrpcResult<ListReplicasReply> {
         let args = request.into_inner();
         trace!("{:?}", args);
         assert_eq!(Cores::current(), Cores::first());
-        let reply = replica::list_replicas();
+        let reply = on_mgmt! { async move {
+            Ok::<_, Status>(replica::list_replicas(args))
+        }};
         trace!("{:?}", reply);
         Ok(Response::new(reply))
     }
@@ -149,7 +235,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
     ) -> GrpcResult<StatReplicasReply> {
         let args = request.into_inner();
         trace!("{:?}", args);
-        let reply = locally! { replica::stat_replicas() };
+        let reply = on_mgmt! { replica::stat_replicas() };
         trace!("{:?}", reply);
         Ok(Response::new(reply))
     }
@@ -159,37 +245,210 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<ShareReplicaRequest>,
     ) -> GrpcResult<ShareReplicaReply> {
-        sync_config(async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("ShareReplica", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Replica(uuid.clone())).await?;
             debug!("Sharing replica {} ...", uuid);
             let reply = locally! { replica::share_replica(args) };
             info!("Shared replica {}", uuid);
             trace!("{:?}", reply);
             Ok(Response::new(reply))
+        }))
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn reshare_replica(
+        &self,
+        request: Request<ReshareReplicaRequest>,
+    ) -> GrpcResult<ReshareReplicaReply> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Replica(uuid.clone())).await?;
+            debug!("Resharing replica {} ...", uuid);
+            let reply = locally! { replica::reshare_replica(args) };
+            info!("Reshared replica {}", uuid);
+            trace!("{:?}", reply);
+            Ok(Response::new(reply))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn switch_replica_share(
+        &self,
+        request: Request<SwitchReplicaShareRequest>,
+    ) -> GrpcResult<SwitchReplicaShareReply> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Replica(uuid.clone())).await?;
+            debug!("Switching the share of replica {} ...", uuid);
+            let reply = locally! { replica::switch_replica_share(args) };
+            info!("Switched the share of replica {}", uuid);
+            trace!("{:?}", reply);
+            Ok(Response::new(reply))
+        })
+        .await
+    }
+
+    type DiffSnapshotsStream =
+        stream::Iter<vec::IntoIter<Result<DiffSnapshotsReply, Status>>>;
+
+    #[instrument(level = "debug", err)]
+    async fn diff_snapshots(
+        &self,
+        request: Request<DiffSnapshotsRequest>,
+    ) -> GrpcResult<Self::DiffSnapshotsStream> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let replies = locally! { replica::diff_snapshots(args) };
+        Ok(Response::new(stream::iter(
+            replies.into_iter().map(Ok).collect::<Vec<_>>(),
+        )))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn list_snapshots(
+        &self,
+        request: Request<Null>,
+    ) -> GrpcResult<ListSnapshotsReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        assert_eq!(Cores::current(), Cores::first());
+        let reply = on_mgmt! { async {
+            Ok::<_, Status>(replica::list_snapshots())
+        }};
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_bdev_qos(
+        &self,
+        request: Request<SetBdevQosRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let uuid = args.uuid.clone();
+        let is_replica = replica::Replica::lookup(&args.uuid).is_some();
+        let _turn = queue::turn(if is_replica {
+            Resource::Replica(uuid.clone())
+        } else {
+            Resource::Nexus(uuid.clone())
+        })
+        .await?;
+        if is_replica {
+            locally! { replica::set_replica_qos(args) };
+        } else {
+            locally! { async move {
+                let limits = args.limits.unwrap_or_default().into();
+                nexus_lookup(&args.uuid)?.set_qos(limits).await
+            }};
+        }
+        info!("Set QoS limits of {}", uuid);
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_replica_flush_policy(
+        &self,
+        request: Request<SetReplicaFlushPolicyRequest>,
+    ) -> GrpcResult<Null> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Replica(uuid.clone())).await?;
+            locally! { replica::set_replica_flush_policy(args) };
+            info!("Set flush policy of replica {}", uuid);
+            Ok(Response::new(Null {}))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_replica_metadata(
+        &self,
+        request: Request<SetReplicaMetadataRequest>,
+    ) -> GrpcResult<ReplicaMetadata> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Replica(uuid.clone())).await?;
+            let reply = locally! { replica::set_replica_metadata(args) };
+            info!("Set metadata of replica {}", uuid);
+            trace!("{:?}", reply);
+            Ok(Response::new(reply))
         })
         .await
     }
 
+    #[instrument(level = "debug", err)]
+    async fn get_replica_metadata(
+        &self,
+        request: Request<GetReplicaMetadataRequest>,
+    ) -> GrpcResult<ReplicaMetadata> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        assert_eq!(Cores::current(), Cores::first());
+        let reply = replica::get_replica_metadata(args)?;
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
     #[instrument(level = "debug", err)]
     async fn create_nexus(
         &self,
         request: Request<CreateNexusRequest>,
     ) -> GrpcResult<Nexus> {
-        sync_config( async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("CreateNexus", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
-            let name = uuid_to_name(&args.uuid)?;
+            let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
             debug!("Creating nexus {} ...", uuid);
-            locally! { async move {
-                nexus_create(&name, args.size, Some(&args.uuid), &args.children).await
-            }};
-            let nexus = nexus_lookup(&uuid)?;
+            let nexus = create_nexus(args).await?;
             info!("Created nexus {}", uuid);
-            Ok(Response::new(nexus.to_grpc()))
-        }).await
+            Ok(Response::new(nexus))
+        }))
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn start_create_nexus(
+        &self,
+        request: Request<CreateNexusRequest>,
+    ) -> GrpcResult<Operation> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        // fail the call rather than the operation on invalid arguments
+        uuid_to_name(&args.uuid)?;
+        fault_policy_from_grpc(args.fault_policy, args.freeze_timeout_ms)?;
+        read_policy_from_grpc(args.read_policy)?;
+
+        let uuid = args.uuid.clone();
+        let resource = Resource::Nexus(uuid.clone());
+        let create = async move {
+            sync_config(async {
+                let _turn = queue::turn(resource).await?;
+                create_nexus(args).await.map(Response::new)
+            })
+            .await
+            .map(|_| ())
+            .map_err(|status| status.message().to_string())
+        };
+        let id = operation::start(operation::Kind::CreateNexus, &uuid, create);
+        info!("Creating nexus {} in operation {}", uuid, id);
+        Ok(Response::new(operation::get(&id)?))
     }
 
     #[instrument(level = "debug", err)]
@@ -197,34 +456,96 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<DestroyNexusRequest>,
     ) -> GrpcResult<Null> {
-        sync_config(async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("DestroyNexus", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
+            let _turn = queue::turn(Resource::Nexus(args.uuid.clone())).await?;
             locally! { async move {
                 nexus_destroy(&args.uuid).await
             }};
             Ok(Response::new(Null {}))
-        })
+        }))
         .await
     }
 
     #[instrument(level = "debug", err)]
     async fn list_nexus(
         &self,
-        request: Request<Null>,
+        request: Request<ListNexusRequest>,
     ) -> GrpcResult<ListNexusReply> {
         let args = request.into_inner();
         trace!("{:?}", args);
+        let (volume, states) = (args.volume.clone(), args.states.clone());
+        let nexus_list = on_mgmt! { async move {
+            Ok::<_, Status>(
+                instances()
+                    .iter()
+                    .map(|n| n.to_grpc())
+                    .filter(|n| volume.is_empty() || n.uuid == volume)
+                    .filter(|n| states.is_empty() || states.contains(&n.state))
+                    .map(|n| (n.uuid.clone(), n))
+                    .collect(),
+            )
+        }};
+
+        let (nexus_list, next_page_token) =
+            grpc::page(nexus_list, &args.page_token, args.max_entries);
         let reply = ListNexusReply {
-            nexus_list: instances()
-                .iter()
-                .map(|n| n.to_grpc())
-                .collect::<Vec<_>>(),
+            nexus_list,
+            next_page_token,
         };
         trace!("{:?}", reply);
         Ok(Response::new(reply))
     }
 
+    type WatchNexusesStream = WatchStream<WatchNexusesReply>;
+
+    #[instrument(level = "debug", err)]
+    async fn watch_nexuses(
+        &self,
+        request: Request<Null>,
+    ) -> GrpcResult<Self::WatchNexusesStream> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        Ok(Response::new(watch_nexuses()))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn stat_nexus(
+        &self,
+        request: Request<Null>,
+    ) -> GrpcResult<StatNexusReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let reply = on_mgmt! { nexus_stat() };
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_nexus_topology(
+        &self,
+        request: Request<GetNexusTopologyRequest>,
+    ) -> GrpcResult<GetNexusTopologyReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let reply = locally! { nexus_topology(args) };
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn reset_stats(
+        &self,
+        request: Request<ResetStatsRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        on_mgmt! { async move { stats_store::reset(&args.uuid).await } };
+        Ok(Response::new(Null {}))
+    }
+
     #[instrument(level = "debug", err)]
     async fn add_child_nexus(
         &self,
@@ -234,6 +555,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
             let args = request.into_inner();
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
             debug!("Adding child {} to nexus {} ...", args.uri, uuid);
             let child = locally! { async move {
                 nexus_add_child(args).await
@@ -244,6 +566,29 @@ impl mayastor_server::Mayastor for MayastorSvc {
         .await
     }
 
+    #[instrument(level = "debug", err)]
+    async fn switch_nexus_child(
+        &self,
+        request: Request<SwitchNexusChildRequest>,
+    ) -> GrpcResult<Child> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
+            debug!(
+                "Switching child {} of nexus {} to {} ...",
+                args.uri, uuid, args.new_uri
+            );
+            let child = locally! { async move {
+                nexus_switch_child(args).await
+            }};
+            info!("Switched child of nexus {}", uuid);
+            Ok(Response::new(child))
+        })
+        .await
+    }
+
     #[instrument(level = "debug", err)]
     async fn remove_child_nexus(
         &self,
@@ -253,6 +598,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
             let args = request.into_inner();
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
             debug!("Removing child {} from nexus {} ...", args.uri, uuid);
             locally! { async move {
                 nexus_lookup(&args.uuid)?.remove_child(&args.uri).await
@@ -268,10 +614,12 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<PublishNexusRequest>,
     ) -> GrpcResult<PublishNexusReply> {
-        sync_config(async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("PublishNexus", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
             debug!("Publishing nexus {} ...", uuid);
 
             if args.key != "" && args.key.len() != 16 {
@@ -295,15 +643,38 @@ impl mayastor_server::Mayastor for MayastorSvc {
                 }
             };
 
+            let read_only = args.read_only;
+            let access = HostAccess {
+                shared: args.shared_access,
+                allowed_hosts: args.allowed_hosts,
+            };
+            let shared = access.shared;
+            let portal = args.portal.map(Portal::from).unwrap_or_default();
+
             let device_uri = locally! { async move {
-                nexus_lookup(&args.uuid)?.share(share_protocol, key).await
+                let nexus = nexus_lookup(&uuid)?;
+                nexus
+                    .share_to_hosts(
+                        share_protocol,
+                        key,
+                        read_only,
+                        access,
+                        &portal,
+                    )
+                    .await
             }};
 
-            info!("Published nexus {} under {}", uuid, device_uri);
+            info!(
+                "Published nexus {} under {}{}{}",
+                args.uuid,
+                device_uri,
+                if read_only { " (read-only)" } else { "" },
+                if shared { " (shared)" } else { "" }
+            );
             Ok(Response::new(PublishNexusReply {
                 device_uri,
             }))
-        })
+        }))
         .await
     }
 
@@ -312,20 +683,210 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<UnpublishNexusRequest>,
     ) -> GrpcResult<Null> {
-        sync_config(async {
+        let key = request.get_ref().idempotency_key.clone();
+        sync_config(idempotent("UnpublishNexus", &key, async move {
             let args = request.into_inner();
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
+            let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
             debug!("Unpublishing nexus {} ...", uuid);
             locally! { async move {
                 nexus_lookup(&args.uuid)?.unshare_nexus().await
             }};
             info!("Unpublished nexus {}", uuid);
             Ok(Response::new(Null {}))
-        })
+        }))
         .await
     }
 
+    // the request is not traced as it holds the key
+    #[instrument(level = "debug", skip(request), err)]
+    async fn rotate_nexus_key(
+        &self,
+        request: Request<RotateNexusKeyRequest>,
+    ) -> GrpcResult<Operation> {
+        let args = request.into_inner();
+        let uuid = args.uuid.clone();
+        let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
+        let complete = locally! { async move {
+            nexus_lookup(&args.uuid)?.start_key_rotation(&args.key).await
+        }};
+
+        let id =
+            operation::start(operation::Kind::RotateKey, &uuid, async move {
+                match complete.await {
+                    Ok(result) => result,
+                    Err(_) => Err("the key rotation has gone".to_string()),
+                }
+            });
+        info!("Rotating the key of nexus {} in operation {}", uuid, id);
+        Ok(Response::new(operation::get(&id)?))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn stop_key_rotation(
+        &self,
+        request: Request<StopKeyRotationRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let _turn = queue::turn(Resource::Nexus(args.uuid.clone())).await?;
+        locally! { async move {
+            nexus_lookup(&args.uuid)?.stop_key_rotation()
+        }};
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_key_rotation_progress(
+        &self,
+        request: Request<KeyRotationProgressRequest>,
+    ) -> GrpcResult<KeyRotationProgressReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        Ok(Response::new(locally! { async move {
+            nexus_lookup(&args.uuid).map(|n| n.get_key_rotation_progress())
+        }}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_nexus_ana_state(
+        &self,
+        request: Request<SetNexusAnaStateRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let uuid = args.uuid.clone();
+        let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
+        let state =
+            NvmeAnaState::from_i32(args.ana_state).ok_or_else(|| {
+                Status::invalid_argument(format!(
+                    "Invalid ANA state {}",
+                    args.ana_state
+                ))
+            })?;
+        debug!("Setting ANA state of nexus {} to {:?} ...", uuid, state);
+        locally! { async move {
+            nexus_lookup(&args.uuid)?.set_ana_state(state).await
+        }};
+        info!("Set ANA state of nexus {} to {:?}", uuid, state);
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_nexus_write_cache(
+        &self,
+        request: Request<SetNexusWriteCacheRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let uuid = args.uuid.clone();
+        let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
+        let size = args.size;
+        debug!(
+            "Setting write cache of nexus {} to {} bytes ...",
+            uuid, size
+        );
+        locally! { async move {
+            nexus_lookup(&args.uuid)?.set_write_cache(args.size).await
+        }};
+        info!("Set write cache of nexus {} to {} bytes", uuid, size);
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn create_nexus_snapshot(
+        &self,
+        request: Request<CreateNexusSnapshotRequest>,
+    ) -> GrpcResult<CreateNexusSnapshotReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let uuid = args.uuid.clone();
+        let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
+        debug!("Creating a snapshot of nexus {} ...", uuid);
+        let snapshot = locally! { async move {
+            nexus_lookup(&args.uuid)?.create_snapshot().await
+        }};
+        info!("Created snapshot {} of nexus {}", snapshot, uuid);
+        Ok(Response::new(CreateNexusSnapshotReply {
+            snapshot,
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn restore_nexus_from_snapshot(
+        &self,
+        request: Request<RestoreNexusFromSnapshotRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let uuid = args.uuid.clone();
+        let _turn = queue::turn(Resource::Nexus(uuid.clone())).await?;
+        let snapshot = args.snapshot;
+        debug!("Restoring snapshot {} of nexus {} ...", snapshot, uuid);
+        locally! { async move {
+            nexus_lookup(&args.uuid)?.restore_snapshot(args.snapshot).await
+        }};
+        info!("Restored snapshot {} of nexus {}", snapshot, uuid);
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_child_fault_policy(
+        &self,
+        request: Request<SetChildFaultPolicyRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let _turn = queue::turn(Resource::Nexus(args.uuid.clone())).await?;
+        let policy = match (args.max_errors, args.window_ms) {
+            (0, _) => None,
+            (_, 0) => {
+                return Err(Status::invalid_argument(
+                    "The window of the policy must not be empty",
+                ))
+            }
+            (max_errors, window_ms) => Some(ChildFaultPolicy {
+                max_errors,
+                window: Duration::from_millis(window_ms.into()),
+            }),
+        };
+        nexus_lookup(&args.uuid)?.set_child_fault_policy(policy, args.spares);
+        info!("Set child fault policy of nexus {}", args.uuid);
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_nexus_read_policy(
+        &self,
+        request: Request<SetNexusReadPolicyRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let _turn = queue::turn(Resource::Nexus(args.uuid.clone())).await?;
+        let policy = read_policy_from_grpc(args.read_policy)?;
+        nexus_lookup(&args.uuid)?.set_read_policy(policy);
+        info!("Set read policy of nexus {} to {:?}", args.uuid, policy);
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_uri_resolver(
+        &self,
+        request: Request<SetUriResolverRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let endpoint = if args.endpoint.is_empty() {
+            None
+        } else {
+            Some(args.endpoint)
+        };
+        nexus_resolver::set_endpoint(endpoint);
+        Ok(Response::new(Null {}))
+    }
+
     #[instrument(level = "debug", err)]
     async fn child_operation(
         &self,
@@ -334,6 +895,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
         sync_config(async {
             let args = request.into_inner();
             trace!("{:?}", args);
+            let _turn = queue::turn(Resource::Nexus(args.uuid.clone())).await?;
 
             let onl = match args.action {
                 1 => Ok(true),
@@ -359,14 +921,35 @@ impl mayastor_server::Mayastor for MayastorSvc {
     async fn start_rebuild(
         &self,
         request: Request<StartRebuildRequest>,
-    ) -> GrpcResult<Null> {
+    ) -> GrpcResult<Operation> {
         let args = request.into_inner();
         trace!("{:?}", args);
-        locally! { async move {
-            nexus_lookup(&args.uuid)?.start_rebuild(&args.uri).await.map(|_|{})
+        let _turn = queue::turn(Resource::Nexus(args.uuid.clone())).await?;
+        let nexus = args.uuid.clone();
+        let uri = args.uri.clone();
+        let complete = locally! { async move {
+            let nexus = nexus_lookup(&args.uuid)?;
+            if args.source.is_empty() {
+                nexus.start_rebuild(&args.uri).await
+            } else {
+                nexus.start_rebuild_from(&args.uri, &args.source).await
+            }
         }};
 
-        Ok(Response::new(Null {}))
+        let id = operation::start(
+            operation::Kind::Rebuild {
+                nexus,
+            },
+            &uri,
+            async move {
+                match complete.await {
+                    Ok(RebuildState::Completed) => Ok(()),
+                    Ok(state) => Err(format!("the rebuild has {}", state)),
+                    Err(_) => Err("the rebuild has gone".to_string()),
+                }
+            },
+        );
+        Ok(Response::new(operation::get(&id)?))
     }
 
     #[instrument(level = "debug", err)]
@@ -376,6 +959,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
     ) -> GrpcResult<Null> {
         let args = request.into_inner();
         trace!("{:?}", args);
+        let _turn = queue::turn(Resource::Nexus(args.uuid.clone())).await?;
         locally! { async move {
           nexus_lookup(&args.uuid)?.stop_rebuild(&args.uri).await
         }};
@@ -389,6 +973,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
         request: Request<PauseRebuildRequest>,
     ) -> GrpcResult<Null> {
         let msg = request.into_inner();
+        let _turn = queue::turn(Resource::Nexus(msg.uuid.clone())).await?;
         locally! { async move {
           nexus_lookup(&msg.uuid)?.pause_rebuild(&msg.uri).await
         }};
@@ -402,6 +987,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
         request: Request<ResumeRebuildRequest>,
     ) -> GrpcResult<Null> {
         let msg = request.into_inner();
+        let _turn = queue::turn(Resource::Nexus(msg.uuid.clone())).await?;
         locally! { async move {
           nexus_lookup(&msg.uuid)?.resume_rebuild(&msg.uri).await
         }};
@@ -432,4 +1018,195 @@ impl mayastor_server::Mayastor for MayastorSvc {
             nexus_lookup(&args.uuid)?.get_rebuild_progress(&args.uri)
         }}))
     }
+
+    #[instrument(level = "debug", err)]
+    async fn get_operation(
+        &self,
+        request: Request<OperationRequest>,
+    ) -> GrpcResult<Operation> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        Ok(Response::new(operation::get(&args.id)?))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn list_operations(
+        &self,
+        request: Request<Null>,
+    ) -> GrpcResult<ListOperationsReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        Ok(Response::new(ListOperationsReply {
+            operations: operation::list(),
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn cancel_operation(
+        &self,
+        request: Request<OperationRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        locally! { async move { operation::cancel(&args.id).await } };
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_resource_usage(
+        &self,
+        request: Request<Null>,
+    ) -> GrpcResult<GetResourceUsageReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let reply = get_resource_usage()
+            .map_err(|e| Status::internal(e.to_string()))?;
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_core_stats(
+        &self,
+        _request: Request<Null>,
+    ) -> GrpcResult<GetCoreStatsReply> {
+        let reply = get_core_stats().await;
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn stat_nvmf_connections(
+        &self,
+        _request: Request<Null>,
+    ) -> GrpcResult<StatNvmfConnectionsReply> {
+        let stats = connection_stats();
+        let reply = StatNvmfConnectionsReply {
+            accepted: stats.accepted,
+            rejected_rate: stats.rejected_rate,
+            rejected_controllers: stats.rejected_controllers,
+            sources: stats
+                .sources
+                .into_iter()
+                .map(|s| NvmfSourceStats {
+                    address: s.address,
+                    accepted: s.accepted,
+                    rejected: s.rejected,
+                    reaped: s.reaped,
+                })
+                .collect(),
+            reaped: stats.reaped,
+            shares: stats
+                .shares
+                .into_iter()
+                .map(|s| NvmfShareStats {
+                    nqn: s.nqn,
+                    accepted: s.accepted,
+                    rejected: s.rejected,
+                    reaped: s.reaped,
+                })
+                .collect(),
+        };
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_audit_log(
+        &self,
+        _request: Request<Null>,
+    ) -> GrpcResult<GetAuditLogReply> {
+        let records = audit::records()
+            .into_iter()
+            .map(|r| AuditRecord {
+                timestamp_ms: r.timestamp_ms,
+                request_id: r.request_id,
+                method: r.method,
+                arguments: r.arguments,
+                code: r.code,
+                message: r.message,
+                duration_us: r.duration_us,
+            })
+            .collect();
+        Ok(Response::new(GetAuditLogReply {
+            records,
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn reload_config(
+        &self,
+        _request: Request<Null>,
+    ) -> GrpcResult<ReloadConfigReply> {
+        let changed = locally! { async { Config::reload() } };
+        Ok(Response::new(ReloadConfigReply {
+            changed,
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn json_rpc_call(
+        &self,
+        request: Request<JsonRpcCallRequest>,
+    ) -> GrpcResult<JsonRpcCallReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+
+        if args.method.is_empty() {
+            return Err(Status::invalid_argument("Missing JSON-RPC method"));
+        }
+        let params = if args.params.is_empty() {
+            None
+        } else {
+            Some(
+                serde_json::from_str::<serde_json::Value>(&args.params)
+                    .map_err(|e| {
+                        Status::invalid_argument(format!(
+                            "Invalid JSON-RPC params: {}",
+                            e
+                        ))
+                    })?,
+            )
+        };
+
+        info!("Calling JSON-RPC method {}", args.method);
+        let result: serde_json::Value = jsonrpc::call(
+            MayastorEnvironment::global_rpc_addr(),
+            &args.method,
+            params,
+        )
+        .await
+        .map_err(|e| {
+            error!("JSON-RPC method {} failed: {}", args.method, e);
+            e.into_status()
+        })?;
+
+        Ok(Response::new(JsonRpcCallReply {
+            result: result.to_string(),
+        }))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn probe_replica_path(
+        &self,
+        request: Request<ProbeReplicaPathRequest>,
+    ) -> GrpcResult<ProbeReplicaPathReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let reply = locally! { probe::probe_replica_path(args) };
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn verify_metadata(
+        &self,
+        request: Request<VerifyMetadataRequest>,
+    ) -> GrpcResult<VerifyMetadataReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let reply = locally! { verify::verify_metadata(args) };
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
 }
@@ -14,10 +14,17 @@ fn print_error_chain(err: &dyn std::error::Error) -> String {
 /// the input/output parameters don't have to be Send and Sync in that case,
 /// which simplifies the code. The value of the macro is Ok() variant of the
 /// expression in the macro. Err() variant returns from the function.
+/// The future runs in the span of the caller, such that what it logs can be
+/// correlated with the gRPC request.
 #[macro_export]
 macro_rules! locally {
     ($body:expr) => {{
-        let hdl = crate::core::Reactors::current().spawn_local($body);
+        let hdl = crate::core::Reactors::current().spawn_local(
+            tracing_futures::Instrument::instrument(
+                $body,
+                tracing::Span::current(),
+            ),
+        );
         match hdl.await.unwrap() {
             Ok(res) => res,
             Err(err) => {
@@ -28,13 +35,43 @@ macro_rules! locally {
     }};
 }
 
+/// Macro on_mgmt is like ['locally'], but the future is polled in the context
+/// of the management thread rather than that of the init thread, see
+/// ['Mthread::get_mgmt']. It is used for the calls which only read the state,
+/// i.e. those which list or stat the bdevs, nexuses, pools and replicas.
+#[macro_export]
+macro_rules! on_mgmt {
+    ($body:expr) => {{
+        locally!(crate::core::InThread::new(
+            crate::core::Mthread::get_mgmt(),
+            $body
+        ))
+    }};
+}
+
+mod audit;
+mod auth;
 mod bdev_grpc;
+mod health;
+mod idempotency;
 mod mayastor_grpc;
 mod nexus_grpc;
+mod operation;
+mod placement;
+pub mod queue;
+mod resource;
 mod server;
+mod watch;
 
-use crate::subsys::Config;
+use crate::{
+    core::{shutdown, QosLimits},
+    subsys::Config,
+};
 use futures::Future;
+pub use health::set_serving;
+pub(crate) use idempotency::{done_operations, restore_operations};
+pub(crate) use nexus_grpc::name_to_uuid;
+pub(crate) use placement::placement;
 pub use server::MayastorGrpcServer;
 use tonic::{Response, Status};
 
@@ -44,19 +81,69 @@ pub type GrpcResult<T> = std::result::Result<Response<T>, Status>;
 /// exporting it to a config file
 /// If `sync_config` fails then the method should return a failure
 /// requiring the gRPC caller to retry the method, which should be idempotent
+/// Once mayastor shuts down the method is refused, as the configuration has
+/// been exported for the last time.
 pub async fn sync_config<F, T>(future: F) -> GrpcResult<T>
 where
     F: Future<Output = GrpcResult<T>>,
 {
+    if shutdown::is_shutting_down() {
+        return Err(Status::unavailable("Mayastor is shutting down"));
+    }
     let result = future.await;
     if result.is_ok() {
-        match Config::export_config() {
+        match Config::export_config().await {
             Ok(_) => {}
             Err(e) => {
-                error!("Failed to export config file: {}", e);
+                error!("Failed to export config: {}", e);
                 return Err(Status::data_loss("Failed to export config"));
             }
         }
     }
     result
 }
+
+/// The page of the entries of a list call which follows the page token, the
+/// key of the last entry of the previous page, along with the token of the
+/// next page, which is empty if it is the last one. The entries are paged in
+/// the order of their keys, all of them are returned if max is 0.
+pub(crate) fn page<T>(
+    mut entries: Vec<(String, T)>,
+    token: &str,
+    max: u32,
+) -> (Vec<T>, String) {
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut entries = entries
+        .into_iter()
+        .skip_while(|(key, _)| !token.is_empty() && key.as_str() <= token)
+        .collect::<Vec<_>>();
+
+    let mut next = String::new();
+    if max > 0 && entries.len() > max as usize {
+        entries.truncate(max as usize);
+        next = entries.last().map(|(key, _)| key.clone()).unwrap_or_default();
+    }
+    (entries.into_iter().map(|(_, entry)| entry).collect(), next)
+}
+
+impl From<rpc::mayastor::QosLimits> for QosLimits {
+    fn from(l: rpc::mayastor::QosLimits) -> Self {
+        Self {
+            rw_ios_per_sec: l.rw_ios_per_sec,
+            rw_mbytes_per_sec: l.rw_mbytes_per_sec,
+            r_mbytes_per_sec: l.r_mbytes_per_sec,
+            w_mbytes_per_sec: l.w_mbytes_per_sec,
+        }
+    }
+}
+
+impl From<QosLimits> for rpc::mayastor::QosLimits {
+    fn from(l: QosLimits) -> Self {
+        Self {
+            rw_ios_per_sec: l.rw_ios_per_sec,
+            rw_mbytes_per_sec: l.rw_mbytes_per_sec,
+            r_mbytes_per_sec: l.r_mbytes_per_sec,
+            w_mbytes_per_sec: l.w_mbytes_per_sec,
+        }
+    }
+}
@@ -1,16 +1,31 @@
 //! Helpers related to nexus grpc methods.
 
 use rpc::mayastor as rpc;
-use std::convert::From;
+use std::{convert::From, time::Duration};
+use tonic::Status;
 use uuid::Uuid;
 
 use crate::{
-    bdev::nexus::{
-        instances,
-        nexus_bdev::{Error, Nexus, NexusStatus},
-        nexus_child::{ChildStatus, NexusChild},
+    bdev::{
+        nexus::{
+            instances,
+            nexus_bdev::{
+                Error,
+                FaultPolicy,
+                Nexus,
+                NexusStatus,
+                ReadPolicy,
+                DEFAULT_FREEZE_TIMEOUT,
+            },
+            nexus_child::{ChildStatus, NexusChild},
+            nexus_io::NexusIoStats,
+            nexus_topology::{node_count, ChildTopology},
+        },
+        nexus_create,
     },
+    grpc::placement,
     rebuild::RebuildJob,
+    stats_store::{self, Counters},
 };
 
 impl From<ChildStatus> for rpc::ChildState {
@@ -32,16 +47,72 @@ impl From<NexusStatus> for rpc::NexusState {
     }
 }
 
+/// Convert the fault policy of a request, a timeout of 0 stands for the
+/// default.
+pub fn fault_policy_from_grpc(
+    policy: i32,
+    freeze_timeout_ms: u32,
+) -> Result<FaultPolicy, Error> {
+    match rpc::FaultPolicy::from_i32(policy) {
+        Some(rpc::FaultPolicy::Fail) => Ok(FaultPolicy::Fail),
+        Some(rpc::FaultPolicy::Freeze) => {
+            Ok(FaultPolicy::Freeze(if freeze_timeout_ms == 0 {
+                DEFAULT_FREEZE_TIMEOUT
+            } else {
+                Duration::from_millis(u64::from(freeze_timeout_ms))
+            }))
+        }
+        None => Err(Error::InvalidFaultPolicy {
+            value: policy,
+        }),
+    }
+}
+
+/// the read policy of the nexus from the read policy of a request
+pub fn read_policy_from_grpc(policy: i32) -> Result<ReadPolicy, Error> {
+    match rpc::ReadPolicy::from_i32(policy) {
+        Some(rpc::ReadPolicy::RoundRobin) => Ok(ReadPolicy::RoundRobin),
+        Some(rpc::ReadPolicy::QueueDepth) => Ok(ReadPolicy::QueueDepth),
+        Some(rpc::ReadPolicy::PreferLocal) => Ok(ReadPolicy::PreferLocal),
+        None => Err(Error::InvalidReadPolicy {
+            value: policy,
+        }),
+    }
+}
+
 impl NexusChild {
     /// Convert nexus child object to grpc representation.
     ///
     /// We cannot use From trait because it is not value to value conversion.
     /// All we have is a reference to a child.
     pub fn to_grpc(&self) -> rpc::Child {
+        let stats = self.io_stats();
         rpc::Child {
             uri: self.name.clone(),
             state: rpc::ChildState::from(self.status()) as i32,
             rebuild_progress: self.get_rebuild_progress(),
+            read_only: self.is_read_only(),
+            io_errors: stats.errors,
+            io_timeouts: stats.timeouts,
+            io_retries: stats.retries,
+            local_bdev: self.local_bdev().unwrap_or_default(),
+        }
+    }
+
+    /// the stats of the IO of the nexus routed to the child
+    pub fn to_grpc_stats(&self) -> rpc::ChildStats {
+        let stats = self.io_stats();
+        rpc::ChildStats {
+            uri: self.name.clone(),
+            stats: Some(rpc::Stats {
+                num_read_ops: stats.num_read_ops,
+                num_write_ops: stats.num_write_ops,
+                bytes_read: stats.bytes_read,
+                bytes_written: stats.bytes_written,
+            }),
+            rebuild_bytes_read: stats.rebuild_bytes_read,
+            rebuild_bytes_written: stats.rebuild_bytes_written,
+            io_errors: stats.errors,
         }
     }
 }
@@ -63,6 +134,29 @@ impl Nexus {
                 .map(|ch| ch.to_grpc())
                 .collect::<Vec<_>>(),
             rebuilds: RebuildJob::count() as u32,
+            qos: Some(self.qos().into()),
+            fault_policy: match self.fault_policy() {
+                FaultPolicy::Fail => rpc::FaultPolicy::Fail,
+                FaultPolicy::Freeze(_) => rpc::FaultPolicy::Freeze,
+            } as i32,
+            freeze_timeout_ms: match self.fault_policy() {
+                FaultPolicy::Fail => 0,
+                FaultPolicy::Freeze(timeout) => timeout.as_millis() as u32,
+            },
+            placement: Some(placement(
+                &self.bdev,
+                self.share_handle.as_deref(),
+                &self.get_share_uri().unwrap_or_default(),
+            )),
+            ana_state: self.ana_state() as i32,
+            write_cache_size: self.write_cache_size(),
+            read_cache: self.read_cache_uri().unwrap_or_default(),
+            spares: self.spares().to_vec(),
+            read_policy: match self.read_policy() {
+                ReadPolicy::RoundRobin => rpc::ReadPolicy::RoundRobin,
+                ReadPolicy::QueueDepth => rpc::ReadPolicy::QueueDepth,
+                ReadPolicy::PreferLocal => rpc::ReadPolicy::PreferLocal,
+            } as i32,
         }
     }
 }
@@ -72,7 +166,7 @@ impl Nexus {
 /// This function never fails which means that if there is a nexus with
 /// unconventional name that likely means it was not created using nexus
 /// rpc api, we return the whole name without modifications as it is.
-fn name_to_uuid(name: &str) -> &str {
+pub(crate) fn name_to_uuid(name: &str) -> &str {
     if name.starts_with("nexus-") {
         &name[6 ..]
     } else {
@@ -115,10 +209,153 @@ pub async fn nexus_add_child(
     // TODO: do not add child if it already exists (idempotency)
     // For that we need api to check existence of child by name (not uri that
     // contain parameters that may change).
-    n.add_child(&args.uri, args.norebuild).await?;
+    let source = Some(args.rebuild_source.as_str()).filter(|s| !s.is_empty());
+    n.add_child_from(&args.uri, args.norebuild, source).await?;
     n.get_child_by_name(&args.uri).map(|ch| ch.to_grpc())
 }
 
+/// Switch a child of the nexus to another URI of its replica.
+pub async fn nexus_switch_child(
+    args: rpc::SwitchNexusChildRequest,
+) -> Result<rpc::Child, Error> {
+    let n = nexus_lookup(&args.uuid)?;
+    n.switch_child(&args.uri, &args.new_uri).await?;
+    n.get_child_by_name(&args.new_uri).map(|ch| ch.to_grpc())
+}
+
+impl From<ChildTopology> for rpc::ChildTopology {
+    fn from(child: ChildTopology) -> Self {
+        Self {
+            uri: child.uri,
+            transport: child.transport,
+            local: child.local,
+            address: child.address,
+            node: child.node,
+            controller: child.controller.map(|c| rpc::NvmfController {
+                subnqn: c.subnqn,
+                serial: c.serial,
+                model: c.model,
+                firmware: c.firmware,
+                cntlid: u32::from(c.cntlid),
+            }),
+            latency_us: child.latency_us.unwrap_or(0),
+        }
+    }
+}
+
+/// Report where the children of the nexus are.
+pub async fn nexus_topology(
+    args: rpc::GetNexusTopologyRequest,
+) -> Result<rpc::GetNexusTopologyReply, Error> {
+    let n = nexus_lookup(&args.uuid)?;
+    let children = n.topology().await;
+    Ok(rpc::GetNexusTopologyReply {
+        uuid: name_to_uuid(&n.name).to_string(),
+        nodes: node_count(&children) as u32,
+        children: children.into_iter().map(rpc::ChildTopology::from).collect(),
+    })
+}
+
+/// Collect the stats of all nexus instances which are open for IO. Apart
+/// from the counters of the nexus bdev, this includes the number of IOs
+/// and bytes currently in flight and the bytes written to the children.
+pub async fn nexus_stat() -> Result<rpc::StatNexusReply, Error> {
+    let mut stats = Vec::new();
+
+    for nexus in instances().iter() {
+        let io_stats = match nexus.io_stats().await {
+            Some(io_stats) => io_stats,
+            None => continue,
+        };
+
+        match nexus.bdev.stats().await {
+            Ok(st) => {
+                let current = Counters {
+                    num_read_ops: st.num_read_ops,
+                    num_write_ops: st.num_write_ops,
+                    bytes_read: st.bytes_read,
+                    bytes_written: st.bytes_written,
+                    io_errors: io_stats.io_errors,
+                };
+                let lifetime = stats_store::nexus_lifetime(nexus, current);
+                stats.push(rpc::NexusStats {
+                    uuid: name_to_uuid(&nexus.name).to_string(),
+                    stats: Some(current.into()),
+                    queue_depth: io_stats.queue_depth,
+                    in_flight_bytes: io_stats.in_flight_bytes,
+                    child_bytes_written: io_stats.child_bytes_written,
+                    rebuild_bytes_written: io_stats.rebuild_bytes_written,
+                    write_amplification: write_amplification(
+                        st.bytes_written,
+                        &io_stats,
+                    ),
+                    read_cache_hits: io_stats.read_cache_hits,
+                    read_cache_misses: io_stats.read_cache_misses,
+                    io_errors: io_stats.io_errors,
+                    lifetime_stats: Some(lifetime.into()),
+                    lifetime_io_errors: lifetime.io_errors,
+                    children: nexus
+                        .children
+                        .iter()
+                        .map(NexusChild::to_grpc_stats)
+                        .collect(),
+                });
+            }
+            Err(errno) => {
+                warn!(
+                    "Failed to get stats for {} (errno={})",
+                    nexus.name, errno
+                );
+            }
+        }
+    }
+
+    Ok(rpc::StatNexusReply {
+        nexus_list: stats,
+    })
+}
+
+/// ratio of the bytes written to the children to the bytes written to the
+/// nexus
+fn write_amplification(bytes_written: u64, io_stats: &NexusIoStats) -> f64 {
+    if bytes_written == 0 {
+        return 0.0;
+    }
+    (io_stats.child_bytes_written + io_stats.rebuild_bytes_written) as f64
+        / bytes_written as f64
+}
+
+/// Create the nexus and apply the options of the request to it.
+pub(crate) async fn create_nexus(
+    args: rpc::CreateNexusRequest,
+) -> Result<rpc::Nexus, Status> {
+    let uuid = args.uuid.clone();
+    let name = uuid_to_name(&args.uuid)?;
+    let qos = args.qos.clone();
+    let read_cache = args.read_cache.clone();
+    let policy =
+        fault_policy_from_grpc(args.fault_policy, args.freeze_timeout_ms)?;
+    let read_policy = read_policy_from_grpc(args.read_policy)?;
+    locally! { async move {
+        nexus_create(&name, args.size, Some(&args.uuid), &args.children).await
+    }};
+    nexus_lookup(&uuid)?.set_fault_policy(policy);
+    nexus_lookup(&uuid)?.set_read_policy(read_policy);
+    if let Some(qos) = qos {
+        let uuid = uuid.clone();
+        locally! { async move {
+            nexus_lookup(&uuid)?.set_qos(qos.into()).await
+        }};
+    }
+    if !read_cache.is_empty() {
+        let uuid = uuid.clone();
+        locally! { async move {
+            nexus_lookup(&uuid)?.set_read_cache(&read_cache).await
+        }};
+    }
+    Ok(nexus_lookup(&uuid)?.to_grpc())
+}
+
 /// Idempotent destruction of the nexus.
 pub async fn nexus_destroy(uuid: &str) -> Result<(), Error> {
     if let Ok(n) = nexus_lookup(uuid) {
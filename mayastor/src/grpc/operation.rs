@@ -0,0 +1,263 @@
+//!
+//! Operations which may take longer than a gRPC call should wait for, i.e.
+//! creating a nexus on large devices or rebuilding a child. Such an
+//! operation runs in the background on the management core and is known by
+//! its id, by which the control plane follows how far it has got and how it
+//! ended, or cancels it, rather than hitting the deadline of the call.
+//! Cancelling a rebuild or a key rotation stops it, cancelling the creation
+//! of a nexus lets it finish and destroys the nexus again.
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use once_cell::sync::Lazy;
+use snafu::Snafu;
+use uuid::Uuid;
+
+use rpc::mayastor::{Operation, OperationState};
+
+use crate::{
+    core::Reactors,
+    grpc::nexus_grpc::{nexus_destroy, nexus_lookup},
+    rebuild::{ClientOperations, RebuildJob},
+};
+
+/// max number of the operations kept once they are done
+const MAX_DONE: usize = 256;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Operation {} not found", id))]
+    NotFound { id: String },
+    #[snafu(display("Operation {} is done already", id))]
+    AlreadyDone { id: String },
+}
+
+impl From<Error> for tonic::Status {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::NotFound {
+                ..
+            } => Self::not_found(e.to_string()),
+            Error::AlreadyDone {
+                ..
+            } => Self::failed_precondition(e.to_string()),
+        }
+    }
+}
+
+/// What an operation does, to its target.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Kind {
+    /// create the nexus of the uuid
+    CreateNexus,
+    /// rebuild the child of the uri, of the nexus of the uuid
+    Rebuild { nexus: String },
+    /// rotate the key of the nexus of the uuid
+    RotateKey,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::CreateNexus => "create_nexus",
+            Self::Rebuild {
+                ..
+            } => "rebuild",
+            Self::RotateKey => "rotate_key",
+        }
+    }
+}
+
+struct Entry {
+    kind: Kind,
+    target: String,
+    state: OperationState,
+    error: String,
+    started: u64,
+    finished: u64,
+    /// the operation has been cancelled while it was running
+    cancelled: bool,
+}
+
+static OPERATIONS: Lazy<Mutex<HashMap<String, Entry>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn to_grpc(id: &str, entry: &Entry) -> Operation {
+    let progress = match (&entry.kind, entry.state) {
+        (_, OperationState::OperationSucceeded) => 100,
+        (
+            Kind::Rebuild {
+                ..
+            },
+            OperationState::OperationRunning,
+        ) => RebuildJob::lookup(&entry.target)
+            .map(|job| job.as_client().stats().progress as u32)
+            .unwrap_or_default(),
+        (Kind::RotateKey, OperationState::OperationRunning) => {
+            nexus_lookup(&entry.target)
+                .map(|nexus| nexus.get_key_rotation_progress().progress)
+                .unwrap_or_default()
+        }
+        _ => 0,
+    };
+
+    Operation {
+        id: id.to_string(),
+        kind: entry.kind.name().to_string(),
+        target: entry.target.clone(),
+        state: entry.state as i32,
+        progress,
+        error: entry.error.clone(),
+        started: entry.started,
+        finished: entry.finished,
+    }
+}
+
+/// Start the operation on the current core, returns its id.
+pub(crate) fn start<F>(kind: Kind, target: &str, future: F) -> String
+where
+    F: Future<Output = Result<(), String>> + 'static,
+{
+    let id = Uuid::new_v4().to_hyphenated().to_string();
+    OPERATIONS.lock().unwrap().insert(
+        id.clone(),
+        Entry {
+            kind,
+            target: target.to_string(),
+            state: OperationState::OperationRunning,
+            error: String::new(),
+            started: now_ms(),
+            finished: 0,
+            cancelled: false,
+        },
+    );
+
+    let op = id.clone();
+    Reactors::current().spawn_local(async move {
+        let result = future.await;
+        finish(&op, result).await;
+    });
+    id
+}
+
+/// Record how the operation ended, and forget the oldest operations done.
+async fn finish(id: &str, result: Result<(), String>) {
+    let (kind, target, cancelled) = match OPERATIONS.lock().unwrap().get(id) {
+        Some(e) => (e.kind.clone(), e.target.clone(), e.cancelled),
+        None => return,
+    };
+
+    // the nexus has been created after all
+    if cancelled && result.is_ok() && kind == Kind::CreateNexus {
+        if let Err(e) = nexus_destroy(&target).await {
+            error!(
+                "Failed to destroy nexus {} of operation {}: {}",
+                target, id, e
+            );
+        }
+    }
+
+    let mut operations = OPERATIONS.lock().unwrap();
+    if let Some(entry) = operations.get_mut(id) {
+        entry.finished = now_ms();
+        entry.state = match result {
+            _ if cancelled => OperationState::OperationCancelled,
+            Ok(()) => OperationState::OperationSucceeded,
+            Err(error) => {
+                warn!("Operation {} on {} failed: {}", id, target, error);
+                entry.error = error;
+                OperationState::OperationFailed
+            }
+        };
+    }
+
+    let mut done = operations
+        .iter()
+        .filter(|(_, e)| e.finished > 0)
+        .map(|(id, e)| (e.finished, id.clone()))
+        .collect::<Vec<_>>();
+    if done.len() > MAX_DONE {
+        done.sort();
+        for (_, id) in done.iter().take(done.len() - MAX_DONE) {
+            operations.remove(id);
+        }
+    }
+}
+
+/// the operation of the id
+pub(crate) fn get(id: &str) -> Result<Operation, Error> {
+    match OPERATIONS.lock().unwrap().get(id) {
+        Some(entry) => Ok(to_grpc(id, entry)),
+        None => Err(Error::NotFound {
+            id: id.to_string(),
+        }),
+    }
+}
+
+/// the operations running and done, oldest first
+pub(crate) fn list() -> Vec<Operation> {
+    let operations = OPERATIONS.lock().unwrap();
+    let mut list = operations
+        .iter()
+        .map(|(id, entry)| to_grpc(id, entry))
+        .collect::<Vec<_>>();
+    list.sort_by_key(|op| op.started);
+    list
+}
+
+/// Cancel the operation, which ends as cancelled unless it is done already.
+pub(crate) async fn cancel(id: &str) -> Result<(), Error> {
+    let (kind, target) = {
+        let mut operations = OPERATIONS.lock().unwrap();
+        let entry = operations.get_mut(id).ok_or_else(|| Error::NotFound {
+            id: id.to_string(),
+        })?;
+        if entry.state != OperationState::OperationRunning {
+            return Err(Error::AlreadyDone {
+                id: id.to_string(),
+            });
+        }
+        if entry.cancelled {
+            return Ok(());
+        }
+        entry.cancelled = true;
+        (entry.kind.clone(), entry.target.clone())
+    };
+
+    info!("Cancelling operation {} on {}", id, target);
+    match kind {
+        Kind::Rebuild {
+            nexus,
+        } => {
+            // the rebuild may have ended meanwhile
+            if let Ok(nexus) = nexus_lookup(&nexus) {
+                if let Err(e) = nexus.stop_rebuild(&target).await {
+                    warn!("Failed to stop the rebuild of {}: {}", target, e);
+                }
+            }
+        }
+        Kind::RotateKey => {
+            if let Ok(nexus) = nexus_lookup(&target) {
+                if let Err(e) = nexus.stop_key_rotation() {
+                    warn!(
+                        "Failed to stop the key rotation of {}: {}",
+                        target, e
+                    );
+                }
+            }
+        }
+        Kind::CreateNexus => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! Placement of the replicas and nexuses on the NUMA nodes and cores of the
+//! machine. The NUMA node of the local PCIe device backing a bdev is compared
+//! with that of the cores polling the connections of its share, to find data
+//! paths which cross NUMA nodes.
+use std::{convert::TryFrom, path::Path};
+
+use rpc::mayastor::{Placement, ServingCore};
+use spdk_sys::spdk_env_get_socket_id;
+
+use crate::{
+    bdev::nexus::instances,
+    core::{preflight::pci_address, Bdev, Share},
+    lvs::{Lvol, Lvs},
+    subsys::{Config, NvmfSubsystem},
+};
+
+/// NUMA node of a core, none if not known
+fn core_numa_node(core: u32) -> Option<i32> {
+    match unsafe { spdk_env_get_socket_id(core) } {
+        std::u32::MAX => None,
+        node => Some(node as i32),
+    }
+}
+
+/// NUMA node of the local PCIe device backing a bdev, none if it is not
+/// backed by one or the machine does not tell. The devices of a nexus must
+/// all be on the same node.
+fn numa_node(bdev: &Bdev) -> Option<i32> {
+    match bdev.driver().as_str() {
+        "lvol" => {
+            let lvol = Lvol::try_from(bdev.clone()).ok()?;
+            let lvs = Lvs::lookup(&lvol.pool())?;
+            numa_node(&lvs.base_bdev())
+        }
+        "nexus" => {
+            let nexus = instances().iter().find(|n| n.name == bdev.name())?;
+            let mut nodes = nexus
+                .children
+                .iter()
+                .filter_map(|c| c.bdev.as_ref())
+                .filter_map(numa_node);
+            let first = nodes.next()?;
+            if nodes.all(|n| n == first) {
+                Some(first)
+            } else {
+                None
+            }
+        }
+        _ => {
+            let address = pci_address(&bdev.bdev_uri()?)?;
+            let device = Path::new("/sys/bus/pci/devices").join(address);
+            match sysfs::parse_value::<i32>(&device, "numa_node") {
+                Ok(node) if node >= 0 => Some(node),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// the address an iSCSI share listens on, from its URI
+fn iscsi_listener(uri: &str) -> Option<String> {
+    let uri = url::Url::parse(uri).ok()?;
+    if uri.scheme() != "iscsi" {
+        return None;
+    }
+    Some(format!("iscsi://{}:{}", uri.host_str()?, uri.port()?))
+}
+
+/// Placement of the bdev and of its share, if any. The share is the bdev of
+/// that name, which is exported with the given URI.
+pub(crate) fn placement(
+    bdev: &Bdev,
+    share: Option<&str>,
+    uri: &str,
+) -> Placement {
+    let numa_node = numa_node(bdev);
+    let mut placement = Placement {
+        numa_node: numa_node.unwrap_or(-1),
+        ..Default::default()
+    };
+
+    let subsystem = match share {
+        Some(name) if Config::get().nexus_opts.nvmf_enable => {
+            NvmfSubsystem::nqn_lookup(name)
+        }
+        _ => None,
+    };
+
+    if let Some(subsystem) = subsystem {
+        placement.cores = subsystem
+            .qpairs_per_core()
+            .into_iter()
+            .map(|(core, qpairs)| ServingCore {
+                core,
+                numa_node: core_numa_node(core).unwrap_or(-1),
+                qpairs,
+            })
+            .collect();
+        placement.listeners = subsystem.listeners();
+    } else if let Some(listener) = iscsi_listener(uri) {
+        placement.listeners.push(listener);
+    }
+
+    placement.cross_numa = numa_node.is_some()
+        && placement
+            .cores
+            .iter()
+            .any(|c| c.numa_node >= 0 && Some(c.numa_node) != numa_node);
+
+    placement
+}
@@ -0,0 +1,120 @@
+//!
+//! Queues of the gRPC methods which change the state of mayastor, one per
+//! pool, replica, nexus or bdev. The methods run on the management core and
+//! only yield to each other where they wait, i.e. for a disk or a remote
+//! target, where another call changing the same object could otherwise find
+//! it half changed. So a method waits for its turn on the object it changes,
+//! while the calls which change other objects and those which only read the
+//! state go ahead, i.e. a ListNexus, a CreateNexus or the CreatePool of
+//! another pool are not held up by a CreatePool importing a large pool.
+//! The long running parts of a method which are started in the background,
+//! i.e. a rebuild or a key rotation, do not hold the turn. The methods which
+//! change the node as a whole, i.e. ReloadConfig, and the faults and delays
+//! of the test bdevs are not queued.
+//! A call which does not get its turn in time fails as unavailable, to be
+//! retried by the caller.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::Mutex,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use tokio::sync::oneshot;
+use tonic::Status;
+
+/// how long a call waits for the calls queued before it on the same object
+const TURN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An object changed by the gRPC methods, by its name or uuid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Pool(String),
+    Replica(String),
+    Nexus(String),
+    Bdev(String),
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Pool(name) => write!(f, "pool {}", name),
+            Resource::Replica(uuid) => write!(f, "replica {}", uuid),
+            Resource::Nexus(uuid) => write!(f, "nexus {}", uuid),
+            Resource::Bdev(name) => write!(f, "bdev {}", name),
+        }
+    }
+}
+
+type Waiting = VecDeque<oneshot::Sender<Turn>>;
+
+/// The calls waiting for their turn on each object. An object is in here for
+/// as long as a call has its turn on it.
+static QUEUES: Lazy<Mutex<HashMap<Resource, Waiting>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The turn of a call on an object, which passes to the next call queued on
+/// the object when dropped.
+#[derive(Debug)]
+pub struct Turn(Option<Resource>);
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        let resource = match self.0.take() {
+            Some(resource) => resource,
+            None => return,
+        };
+
+        let mut queues = QUEUES.lock().unwrap();
+        if let Some(waiting) = queues.get_mut(&resource) {
+            while let Some(next) = waiting.pop_front() {
+                match next.send(Turn(Some(resource.clone()))) {
+                    Ok(_) => return,
+                    // the call has given up waiting, the turn is not passed
+                    // on from here as the queues are locked
+                    Err(mut turn) => {
+                        turn.0.take();
+                    }
+                }
+            }
+        }
+        queues.remove(&resource);
+    }
+}
+
+/// Wait until the calls queued before on the object are done. The next call
+/// goes ahead once the returned turn is dropped.
+pub async fn turn(resource: Resource) -> Result<Turn, Status> {
+    turn_within(resource, TURN_TIMEOUT).await
+}
+
+/// Wait for the turn on the object for at most the given time.
+pub async fn turn_within(
+    resource: Resource,
+    timeout: Duration,
+) -> Result<Turn, Status> {
+    let waiting = {
+        let mut queues = QUEUES.lock().unwrap();
+        if !queues.contains_key(&resource) {
+            queues.insert(resource.clone(), VecDeque::new());
+            return Ok(Turn(Some(resource)));
+        }
+        let (sender, receiver) = oneshot::channel();
+        queues.get_mut(&resource).unwrap().push_back(sender);
+        receiver
+    };
+
+    debug!("gRPC call on {} is queued", resource);
+    match tokio::time::timeout(timeout, waiting).await {
+        Ok(Ok(turn)) => Ok(turn),
+        Ok(Err(_)) => Err(Status::internal(format!(
+            "the queue of {} has gone",
+            resource
+        ))),
+        Err(_) => Err(Status::unavailable(format!(
+            "{} is busy with another call",
+            resource
+        ))),
+    }
+}
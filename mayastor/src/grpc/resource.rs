@@ -0,0 +1,82 @@
+//! Resource usage of the mayastor process, including the estimated memory
+//! of the nvme controllers of nvmf and pcie children, and the cycles spent
+//! by the reactor of each core.
+
+use rpc::mayastor::{
+    ChildMemoryUsage,
+    CoreStats,
+    GetCoreStatsReply,
+    GetResourceUsageReply,
+    ResourceUsage,
+};
+use spdk_sys::spdk_get_ticks_hz;
+
+use crate::{
+    bdev::dev::memory,
+    core::{Cores, Reactors},
+};
+
+/// obtain the resource usage of the process from getrusage(2)
+fn getrusage() -> Result<ResourceUsage, std::io::Error> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(ResourceUsage {
+        soft_faults: usage.ru_minflt,
+        hard_faults: usage.ru_majflt,
+        swaps: usage.ru_nswap,
+        in_block_ops: usage.ru_inblock,
+        out_block_ops: usage.ru_oublock,
+        ipc_msg_send: usage.ru_msgsnd,
+        ipc_msg_rcv: usage.ru_msgrcv,
+        signals: usage.ru_nsignals,
+        vol_csw: usage.ru_nvcsw,
+        invol_csw: usage.ru_nivcsw,
+        max_rss: usage.ru_maxrss,
+    })
+}
+
+pub(crate) fn get_resource_usage(
+) -> Result<GetResourceUsageReply, std::io::Error> {
+    let children = memory::children()
+        .into_iter()
+        .map(|c| ChildMemoryUsage {
+            name: c.name,
+            transport: c.transport.to_string(),
+            qpairs: c.qpairs,
+            requests: c.requests,
+            bytes: c.bytes,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(GetResourceUsageReply {
+        usage: Some(getrusage()?),
+        children_bytes: children.iter().map(|c| c.bytes).sum(),
+        children_bytes_limit: memory::limit(),
+        children,
+    })
+}
+
+/// the busy and idle cycles of the reactors, to spot saturated cores
+pub(crate) async fn get_core_stats() -> GetCoreStatsReply {
+    let cores = Reactors::stats()
+        .await
+        .into_iter()
+        .map(|s| CoreStats {
+            core: s.core,
+            busy_cycles: s.busy_tsc,
+            idle_cycles: s.idle_tsc,
+            threads: s.threads,
+            management: s.core == Cores::first(),
+            io: Cores::is_io_core(s.core),
+        })
+        .collect();
+
+    GetCoreStatsReply {
+        cores,
+        tick_rate: unsafe { spdk_get_ticks_hz() },
+    }
+}
@@ -1,27 +1,181 @@
-use tonic::transport::Server;
+use std::{
+    fs,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use futures::future::try_join_all;
+use http::HeaderMap;
+use tokio::net::UnixListener;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tracing::Span;
 
-use crate::grpc::{bdev_grpc::BdevSvc, mayastor_grpc::MayastorSvc};
-use rpc::mayastor::{
-    bdev_rpc_server::BdevRpcServer,
-    mayastor_server::MayastorServer as MayastorRpcServer,
+use crate::{
+    grpc::{
+        audit,
+        auth::{self, Policy},
+        bdev_grpc::BdevSvc,
+        health::HealthSvc,
+        mayastor_grpc::MayastorSvc,
+    },
+    subsys::{Config, GrpcEndpointOpts},
 };
+use rpc::{
+    health::health_server::HealthServer,
+    mayastor::{
+        bdev_rpc_server::BdevRpcServer,
+        mayastor_server::MayastorServer as MayastorRpcServer,
+    },
+};
+
+/// id of the next request without an x-request-id header
+static REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Span for the handling of a request, which carries the id of the request
+/// so all messages logged for it can be correlated. The caller can pass its
+/// own id in the x-request-id header, otherwise one is generated.
+fn request_span(headers: &HeaderMap) -> Span {
+    let id = headers
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+        .unwrap_or_else(|| {
+            REQUEST_ID.fetch_add(1, Ordering::Relaxed).to_string()
+        });
+    info_span!("grpc", request_id = %id)
+}
+
+/// the path of the Unix domain socket of an endpoint address of the form
+/// "unix:<path>", none for a TCP endpoint
+fn uds_path(address: &str) -> Option<&str> {
+    if address.starts_with("unix:") {
+        Some(address["unix:".len() ..].trim_start_matches("//"))
+    } else {
+        None
+    }
+}
+
+/// The TLS config of an endpoint, none if it serves plain text. The files
+/// are read once, i.e. those of a kubernetes secret mounted in the pod, so
+/// that renewed certificates take effect when mayastor is restarted.
+fn tls_config(
+    endpoint: &GrpcEndpointOpts,
+) -> Result<Option<ServerTlsConfig>, String> {
+    let (cert, key) = match (&endpoint.tls_cert_file, &endpoint.tls_key_file) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if endpoint.tls_client_ca_file.is_none() => {
+            return Ok(None)
+        }
+        _ => return Err("TLS requires both a certificate and its key".into()),
+    };
+    if uds_path(&endpoint.address).is_some() {
+        return Err("TLS is not supported on Unix domain sockets".into());
+    }
+
+    let read = |path: &str| {
+        fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))
+    };
+    let mut tls = ServerTlsConfig::with_rustls();
+    tls.identity(Identity::from_pem(read(cert)?, read(key)?));
+    if let Some(ca) = endpoint.tls_client_ca_file.as_ref() {
+        tls.client_ca_root(Certificate::from_pem(read(ca)?));
+    }
+    Ok(Some(tls))
+}
 
 pub struct MayastorGrpcServer {}
 
 impl MayastorGrpcServer {
-    pub async fn run(endpoint: &str) -> Result<(), ()> {
-        info!("gRPC server configured at address {}", endpoint);
-        let svc = Server::builder()
+    /// Serve the endpoints given on the command line, i.e. an IP address and
+    /// a Unix domain socket, with no auth policy along with the endpoints of
+    /// the config, each with its own policy. The server fails as soon as any
+    /// of them does.
+    pub async fn run(
+        cli_endpoints: Vec<&str>,
+        audit_log: Option<&str>,
+    ) -> Result<(), ()> {
+        audit::init(audit_log);
+
+        let mut endpoints = Config::get().grpc.endpoints.clone();
+        for endpoint in cli_endpoints.into_iter().rev() {
+            if !endpoints.iter().any(|e| e.address == endpoint) {
+                endpoints.insert(
+                    0,
+                    GrpcEndpointOpts {
+                        address: endpoint.to_string(),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+
+        try_join_all(endpoints.into_iter().map(Self::serve))
+            .await
+            .map(|_| ())
+    }
+
+    async fn serve(endpoint: GrpcEndpointOpts) -> Result<(), ()> {
+        let address = endpoint.address.clone();
+        let policy = Policy::new(&endpoint).map_err(|e| {
+            error!("gRPC endpoint {}: {}", address, e);
+        })?;
+        let tls = tls_config(&endpoint).map_err(|e| {
+            error!("gRPC endpoint {}: {}", address, e);
+        })?;
+        info!(
+            "gRPC server configured at address {}{}{}{}{}",
+            address,
+            match (&tls, &endpoint.tls_client_ca_file) {
+                (Some(_), Some(_)) => ", mutual TLS",
+                (Some(_), None) => ", TLS",
+                (None, _) => "",
+            },
+            match (&endpoint.token_file, endpoint.token_for_changes_only) {
+                (Some(_), true) => ", token required for changes",
+                (Some(_), false) => ", token required",
+                (None, _) => "",
+            },
+            if endpoint.read_only {
+                ", read-only"
+            } else {
+                ""
+            },
+            if endpoint.admin {
+                ", admin"
+            } else {
+                ""
+            }
+        );
+
+        let builder = match tls.as_ref() {
+            Some(tls) => Server::builder().tls_config(tls),
+            None => Server::builder(),
+        };
+        let router = builder
+            .trace_fn(request_span)
+            .interceptor_fn(move |svc, req| auth::intercept(&policy, svc, req))
             .add_service(MayastorRpcServer::new(MayastorSvc {}))
             .add_service(BdevRpcServer::new(BdevSvc {}))
-            .serve(endpoint.parse().unwrap());
+            .add_service(HealthServer::new(HealthSvc {}));
 
-        match svc.await {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                error!("gRPC server failed with error: {}", e);
-                Err(())
+        let result = match uds_path(&address) {
+            Some(path) => {
+                // a socket left behind by a previous instance is in the way
+                let _ = std::fs::remove_file(path);
+                let mut listener = UnixListener::bind(path).map_err(|e| {
+                    error!("Failed to bind gRPC socket {}: {}", path, e);
+                })?;
+                router.serve_with_incoming(listener.incoming()).await
             }
-        }
+            None => {
+                let addr = address.parse().map_err(|e| {
+                    error!("Invalid gRPC endpoint {}: {}", address, e);
+                })?;
+                router.serve(addr).await
+            }
+        };
+
+        result.map_err(|e| {
+            error!("gRPC server at {} failed with error: {}", address, e);
+        })
     }
 }
@@ -0,0 +1,146 @@
+//! Watch calls of the gRPC server, for deployments without a message bus.
+//!
+//! A watch call streams the nexuses or pools as they are, followed by a
+//! reply marking the end of this snapshot. From then on, each event about a
+//! nexus or pool is followed by its state at the time, or by a reply saying
+//! it has been removed if it no longer exists. The events are received from
+//! the event dispatcher of the event bus, before the snapshot is taken, so
+//! that no change is missed. The call runs until the client cancels it,
+//! which is noticed when the next reply is sent.
+
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use tonic::Status;
+
+use rpc::mayastor::{
+    Nexus as RpcNexus,
+    Pool as RpcPool,
+    WatchEventType,
+    WatchNexusesReply,
+    WatchPoolsReply,
+};
+
+use crate::{
+    bdev::nexus::{instances, nexus_bdev::nexus_lookup},
+    core::Reactors,
+    grpc::name_to_uuid,
+    pool::{Pool, PoolsIter},
+    subsys::events,
+};
+
+/// number of replies queued for a client before the watch waits for it
+const WATCH_QUEUE: usize = 64;
+
+pub(crate) type WatchStream<T> = mpsc::Receiver<Result<T, Status>>;
+
+fn nexus_reply(kind: WatchEventType, nexus: RpcNexus) -> WatchNexusesReply {
+    WatchNexusesReply {
+        r#type: kind as i32,
+        nexus: Some(nexus),
+    }
+}
+
+fn pool_reply(kind: WatchEventType, pool: RpcPool) -> WatchPoolsReply {
+    WatchPoolsReply {
+        r#type: kind as i32,
+        pool: Some(pool),
+    }
+}
+
+/// Stream the nexuses and the changes of their state.
+pub(crate) fn watch_nexuses() -> WatchStream<WatchNexusesReply> {
+    let mut events = events::subscribe();
+    let (mut sender, receiver) = mpsc::channel(WATCH_QUEUE);
+
+    Reactors::master().send_future(async move {
+        let snapshot = instances()
+            .iter()
+            .map(|n| nexus_reply(WatchEventType::WatchSnapshot, n.to_grpc()))
+            .collect::<Vec<_>>();
+        for reply in snapshot {
+            if sender.send(Ok(reply)).await.is_err() {
+                return;
+            }
+        }
+        let synced = WatchNexusesReply {
+            r#type: WatchEventType::WatchSynced as i32,
+            nexus: None,
+        };
+        if sender.send(Ok(synced)).await.is_err() {
+            return;
+        }
+
+        while let Some(message) = events.next().await {
+            let name = match message.event.nexus() {
+                Some(name) => name,
+                None => continue,
+            };
+            let reply = match nexus_lookup(name) {
+                Some(nexus) => {
+                    nexus_reply(WatchEventType::WatchUpdated, nexus.to_grpc())
+                }
+                None => nexus_reply(
+                    WatchEventType::WatchRemoved,
+                    RpcNexus {
+                        uuid: name_to_uuid(name).to_string(),
+                        ..Default::default()
+                    },
+                ),
+            };
+            if sender.send(Ok(reply)).await.is_err() {
+                break;
+            }
+        }
+        debug!("nexus watch has ended");
+    });
+
+    receiver
+}
+
+/// Stream the pools and the changes of their state.
+pub(crate) fn watch_pools() -> WatchStream<WatchPoolsReply> {
+    let mut events = events::subscribe();
+    let (mut sender, receiver) = mpsc::channel(WATCH_QUEUE);
+
+    Reactors::master().send_future(async move {
+        let snapshot = PoolsIter::new()
+            .map(|p| pool_reply(WatchEventType::WatchSnapshot, p.into()))
+            .collect::<Vec<_>>();
+        for reply in snapshot {
+            if sender.send(Ok(reply)).await.is_err() {
+                return;
+            }
+        }
+        let synced = WatchPoolsReply {
+            r#type: WatchEventType::WatchSynced as i32,
+            pool: None,
+        };
+        if sender.send(Ok(synced)).await.is_err() {
+            return;
+        }
+
+        while let Some(message) = events.next().await {
+            let name = match message.event.pool() {
+                Some(name) => name,
+                None => continue,
+            };
+            let reply = match Pool::lookup(name) {
+                Some(pool) => {
+                    pool_reply(WatchEventType::WatchUpdated, pool.into())
+                }
+                None => pool_reply(
+                    WatchEventType::WatchRemoved,
+                    RpcPool {
+                        name: name.to_string(),
+                        ..Default::default()
+                    },
+                ),
+            };
+            if sender.send(Ok(reply)).await.is_err() {
+                break;
+            }
+        }
+        debug!("pool watch has ended");
+    });
+
+    receiver
+}
@@ -0,0 +1,373 @@
+//! Portals of the iSCSI targets of shared nexuses and replicas.
+//!
+//! The iSCSI target listens on the pod IP, on a port for the nexus targets
+//! and one for the replica targets. A share may ask for another address,
+//! port or the address of a network interface instead, and the config may
+//! change the address for all shares of a side. Shares on the same portal
+//! use the same portal group, which is created by the first of them and
+//! closed again when the last one is gone. The URI of the share names the
+//! portal it is on.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    os::raw::c_int,
+};
+
+use nix::{ifaddrs::getifaddrs, sys::socket::SockAddr};
+use snafu::Snafu;
+use tonic::Status;
+use url::Url;
+
+use spdk_sys::{
+    iscsi_find_tgt_node,
+    iscsi_portal_create,
+    iscsi_portal_grp_add_portal,
+    iscsi_portal_grp_create,
+    iscsi_portal_grp_destroy,
+    iscsi_portal_grp_open,
+    iscsi_portal_grp_register,
+    iscsi_portal_grp_release,
+    iscsi_portal_grp_unregister,
+    iscsi_target_node_add_pg_ig_maps,
+    iscsi_target_node_remove_pg_ig_maps,
+    spdk_iscsi_tgt_node,
+};
+
+use crate::{
+    core::MayastorEnvironment,
+    ffihelper::IntoCString,
+    subsys::Config,
+    target::Side,
+};
+
+/// tag of the first portal group created for a share, well clear of the
+/// portal groups created by the iscsi target
+const PORTAL_GROUP_TAG_BASE: c_int = 256;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid iSCSI portal address {}", address))]
+    InvalidAddress { address: String },
+    #[snafu(display("No IPv4 address on interface {}", interface))]
+    InterfaceNotFound { interface: String },
+    #[snafu(display("Failed to create iSCSI portal {}:{}", address, port))]
+    CreatePortal { address: String, port: u16 },
+    #[snafu(display("iSCSI target {} does not exist", iqn))]
+    TargetNotFound { iqn: String },
+    #[snafu(display("Failed to move iSCSI target {} to its portal", iqn))]
+    MoveTarget { iqn: String },
+}
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::InvalidAddress {
+                ..
+            }
+            | Error::InterfaceNotFound {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            _ => Status::internal(e.to_string()),
+        }
+    }
+}
+
+/// The portal to share on, the fields which are not given are taken from
+/// the config of the side and the defaults of the node.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Portal {
+    /// IPv4 address to listen on
+    pub address: String,
+    /// TCP port to listen on, 0 for the port of the side
+    pub port: u16,
+    /// network interface whose address is listened on, when no address is
+    /// given
+    pub interface: String,
+}
+
+impl From<rpc::mayastor::IscsiPortal> for Portal {
+    fn from(p: rpc::mayastor::IscsiPortal) -> Self {
+        Self {
+            address: p.address,
+            port: p.port as u16,
+            interface: p.interface,
+        }
+    }
+}
+
+impl Portal {
+    /// the address and port of the portal for shares of the given side
+    fn resolve(&self, side: &Side) -> Result<(String, u16), Error> {
+        let opts = &Config::get().nexus_opts;
+        let (defaults, default_port) = match side {
+            Side::Nexus => (&opts.iscsi_nexus_portal, opts.iscsi_nexus_port),
+            Side::Replica => {
+                (&opts.iscsi_replica_portal, opts.iscsi_replica_port)
+            }
+        };
+
+        let address = if !self.address.is_empty() {
+            self.address.clone()
+        } else if !self.interface.is_empty() {
+            interface_address(&self.interface)?
+        } else if !defaults.address.is_empty() {
+            defaults.address.clone()
+        } else if !defaults.interface.is_empty() {
+            interface_address(&defaults.interface)?
+        } else {
+            pod_address()
+        };
+
+        if address.parse::<Ipv4Addr>().is_err() {
+            return Err(Error::InvalidAddress {
+                address,
+            });
+        }
+
+        let port = if self.port != 0 {
+            self.port
+        } else {
+            default_port
+        };
+
+        Ok((address, port))
+    }
+
+    /// whether the target of the side listens on the portal anyway
+    fn is_default(address: &str, port: u16, side: &Side) -> bool {
+        let opts = &Config::get().nexus_opts;
+        address == pod_address()
+            && port
+                == match side {
+                    Side::Nexus => opts.iscsi_nexus_port,
+                    Side::Replica => opts.iscsi_replica_port,
+                }
+    }
+}
+
+fn pod_address() -> String {
+    MayastorEnvironment::get_pod_ip().unwrap_or_else(|_| "127.0.0.1".into())
+}
+
+/// the first IPv4 address of the network interface
+fn interface_address(interface: &str) -> Result<String, Error> {
+    getifaddrs()
+        .ok()
+        .and_then(|mut addrs| {
+            addrs.find_map(|a| match a.address {
+                Some(SockAddr::Inet(inet)) if a.interface_name == interface => {
+                    match inet.to_std().ip() {
+                        IpAddr::V4(ip) => Some(ip.to_string()),
+                        IpAddr::V6(_) => None,
+                    }
+                }
+                _ => None,
+            })
+        })
+        .ok_or_else(|| Error::InterfaceNotFound {
+            interface: interface.into(),
+        })
+}
+
+/// a portal group created for shares, with the number of targets on it
+struct PortalGroup {
+    tag: c_int,
+    targets: usize,
+}
+
+thread_local! {
+    /// the portal groups created for shares by address and port
+    static PORTAL_GROUPS: RefCell<HashMap<(String, u16), PortalGroup>> =
+        RefCell::new(HashMap::new());
+    /// the address and port of the targets moved to a portal group of ours
+    static TARGETS: RefCell<HashMap<String, (String, u16)>> =
+        RefCell::new(HashMap::new());
+    /// the tag of the last portal group created
+    static PORTAL_GROUP_IDX: Cell<c_int> = Cell::new(PORTAL_GROUP_TAG_BASE);
+}
+
+/// create, open and register a portal group listening on the portal
+fn create_portal_group(address: &str, port: u16) -> Result<c_int, Error> {
+    let err = || Error::CreatePortal {
+        address: address.into(),
+        port,
+    };
+
+    let tag = PORTAL_GROUP_IDX.with(|idx| {
+        idx.set(idx.get() + 1);
+        idx.get()
+    });
+    let host = address.into_cstring();
+    let svc = port.to_string().into_cstring();
+
+    unsafe {
+        let pg = iscsi_portal_grp_create(tag);
+        if pg.is_null() {
+            return Err(err());
+        }
+        let p = iscsi_portal_create(host.as_ptr(), svc.as_ptr());
+        if p.is_null() {
+            iscsi_portal_grp_destroy(pg);
+            return Err(err());
+        }
+        iscsi_portal_grp_add_portal(pg, p);
+        if iscsi_portal_grp_open(pg) != 0 || iscsi_portal_grp_register(pg) != 0
+        {
+            iscsi_portal_grp_release(pg);
+            return Err(err());
+        }
+    }
+
+    info!("Created iSCSI portal group {} on {}:{}", tag, address, port);
+    Ok(tag)
+}
+
+fn destroy_portal_group(tag: c_int) {
+    unsafe {
+        let pg = iscsi_portal_grp_unregister(tag);
+        if !pg.is_null() {
+            iscsi_portal_grp_release(pg);
+        }
+    }
+}
+
+/// the tags of the portal and initiator groups the target is mapped to
+fn pg_ig_maps(tgt: *mut spdk_iscsi_tgt_node) -> Vec<(c_int, c_int)> {
+    let mut maps = Vec::new();
+    unsafe {
+        let mut pg_map = (*tgt).pg_map_head.tqh_first;
+        while !pg_map.is_null() {
+            let mut ig_map = (*pg_map).ig_map_head.tqh_first;
+            while !ig_map.is_null() {
+                maps.push(((*(*pg_map).pg).tag, (*(*ig_map).ig).tag));
+                ig_map = (*ig_map).tailq.tqe_next;
+            }
+            pg_map = (*pg_map).tailq.tqe_next;
+        }
+    }
+    maps
+}
+
+/// map the initiator groups of the target to the portal group instead of
+/// the portal groups they are mapped to now
+fn move_target(iqn: &str, tag: c_int) -> Result<(), Error> {
+    let err = || Error::MoveTarget {
+        iqn: iqn.into(),
+    };
+
+    let c_iqn = iqn.into_cstring();
+    let tgt = unsafe { iscsi_find_tgt_node(c_iqn.as_ptr()) };
+    if tgt.is_null() {
+        return Err(Error::TargetNotFound {
+            iqn: iqn.into(),
+        });
+    }
+
+    for (mut old_pg, mut ig) in pg_ig_maps(tgt) {
+        let mut pg = tag;
+        unsafe {
+            if iscsi_target_node_add_pg_ig_maps(tgt, &mut pg, &mut ig, 1) != 0
+                || iscsi_target_node_remove_pg_ig_maps(
+                    tgt,
+                    &mut old_pg,
+                    &mut ig,
+                    1,
+                ) != 0
+            {
+                return Err(err());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move the freshly created target of the side to the portal, unless the
+/// target listens on it already. Returns the tag of the portal group the
+/// target is on, or none if it is still on the portal group of the side.
+pub(crate) fn bind(
+    iqn: &str,
+    side: Side,
+    portal: &Portal,
+) -> Result<Option<c_int>, Error> {
+    let (address, port) = portal.resolve(&side)?;
+    if Portal::is_default(&address, port, &side) {
+        return Ok(None);
+    }
+
+    let key = (address.clone(), port);
+    let existing =
+        PORTAL_GROUPS.with(|g| g.borrow().get(&key).map(|pg| pg.tag));
+    let tag = match existing {
+        Some(tag) => tag,
+        None => create_portal_group(&address, port)?,
+    };
+
+    if let Err(e) = move_target(iqn, tag) {
+        if existing.is_none() {
+            destroy_portal_group(tag);
+        }
+        return Err(e);
+    }
+
+    PORTAL_GROUPS.with(|g| {
+        g.borrow_mut()
+            .entry(key.clone())
+            .or_insert(PortalGroup {
+                tag,
+                targets: 0,
+            })
+            .targets += 1
+    });
+    TARGETS.with(|t| t.borrow_mut().insert(iqn.into(), key));
+    info!("iSCSI target {} is on portal {}:{}", iqn, address, port);
+    Ok(Some(tag))
+}
+
+/// Forget the target after it has been destroyed, closing its portal group
+/// if no other target is on it.
+pub(crate) fn release(iqn: &str) {
+    let key = match TARGETS.with(|t| t.borrow_mut().remove(iqn)) {
+        Some(key) => key,
+        None => return,
+    };
+
+    let unused = PORTAL_GROUPS.with(|g| {
+        let mut groups = g.borrow_mut();
+        match groups.get_mut(&key) {
+            Some(pg) if pg.targets > 1 => {
+                pg.targets -= 1;
+                None
+            }
+            Some(_) => groups.remove(&key).map(|pg| pg.tag),
+            None => None,
+        }
+    });
+
+    if let Some(tag) = unused {
+        info!("Closing iSCSI portal group {} on {}:{}", tag, key.0, key.1);
+        destroy_portal_group(tag);
+    }
+}
+
+/// The URI of the share of the target, naming the portal it is on.
+pub(crate) fn share_uri(iqn: &str, uri: String) -> String {
+    let portal = TARGETS.with(|t| t.borrow().get(iqn).cloned());
+    let (address, port) = match portal {
+        Some(portal) => portal,
+        None => return uri,
+    };
+
+    match Url::parse(&uri) {
+        Ok(mut url) => {
+            if url.set_host(Some(&address)).is_err()
+                || url.set_port(Some(port)).is_err()
+            {
+                return uri;
+            }
+            url.to_string()
+        }
+        Err(_) => uri,
+    }
+}
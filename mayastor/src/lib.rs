@@ -1,7 +1,7 @@
 #[macro_use]
 extern crate ioctl_gen;
 #[macro_use]
-extern crate log;
+extern crate tracing;
 extern crate nix;
 #[macro_use]
 extern crate serde;
@@ -14,16 +14,21 @@ pub mod core;
 pub mod delay;
 pub mod ffihelper;
 pub mod grpc;
+pub mod iscsi_portal;
 pub mod jsonrpc;
+pub mod limits;
 pub mod logger;
 pub mod lvs;
 pub mod nats;
 pub mod nexus_uri;
 pub mod pool;
+pub mod probe;
 pub mod rebuild;
 pub mod replica;
+pub mod stats_store;
 pub mod subsys;
 pub mod target;
+pub mod verify;
 
 #[macro_export]
 macro_rules! CPS_INIT {
@@ -37,4 +42,6 @@ macro_rules! CPS_INIT {
 pub extern "C" fn cps_init() {
     subsys::register_subsystem();
     bdev::nexus::register_module();
+    #[cfg(feature = "mock-children")]
+    bdev::mock::register_module();
 }
@@ -0,0 +1,84 @@
+//! Limits on the resources of the node.
+//!
+//! A control plane gone wrong could keep creating nexuses, replicas or shares
+//! on a node until it runs out of memory. The node_limits section of the
+//! config caps them, which can be changed while running. The calls which
+//! would exceed a limit fail with RESOURCE_EXHAUSTED, naming the limit.
+
+use snafu::Snafu;
+use tonic::Status;
+
+use crate::{bdev::nexus::instances, replica::ReplicaIter, subsys::LiveOpts};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("The node limit {} of {} has been reached", name, limit))]
+    LimitReached { name: &'static str, limit: u64 },
+}
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Self {
+        Status::resource_exhausted(e.to_string())
+    }
+}
+
+/// fail if adding to what is in use would exceed the limit, if any
+fn check(
+    name: &'static str,
+    used: u64,
+    add: u64,
+    limit: u64,
+) -> Result<(), Error> {
+    if limit != 0 && used + add > limit {
+        warn!("refusing to exceed the node limit {} of {}", name, limit);
+        Err(Error::LimitReached {
+            name,
+            limit,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// check that one more nexus may be created
+pub fn check_nexus() -> Result<(), Error> {
+    let limits = LiveOpts::get().node_limits;
+    check(
+        "max_nexuses",
+        instances().len() as u64,
+        1,
+        limits.max_nexuses,
+    )
+}
+
+/// check that one more replica of the given size may be created
+pub fn check_replica(size: u64) -> Result<(), Error> {
+    let limits = LiveOpts::get().node_limits;
+    let replicas = ReplicaIter::new().collect::<Vec<_>>();
+    check(
+        "max_replicas",
+        replicas.len() as u64,
+        1,
+        limits.max_replicas,
+    )?;
+
+    let capacity = replicas
+        .iter()
+        .filter(|r| !r.is_snapshot())
+        .map(|r| r.get_size())
+        .sum();
+    check("max_capacity", capacity, size, limits.max_capacity)
+}
+
+/// check that one more nexus or replica may be shared
+pub fn check_share() -> Result<(), Error> {
+    let limits = LiveOpts::get().node_limits;
+    let shares = instances()
+        .iter()
+        .filter(|n| n.nexus_target.is_some())
+        .count()
+        + ReplicaIter::new()
+            .filter(|r| r.get_share_type().is_some())
+            .count();
+    check("max_shares", shares as u64, 1, limits.max_shares)
+}
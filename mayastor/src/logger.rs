@@ -1,7 +1,13 @@
-use std::{ffi::CStr, io::Write, os::raw::c_char, path::Path};
+use std::{
+    ffi::CStr,
+    fmt::{Display, Formatter},
+    os::raw::c_char,
+    str::FromStr,
+};
 
-use env_logger::{Builder, Env};
 use log::{logger, Level, Record};
+use once_cell::sync::OnceCell;
+use tracing_subscriber::EnvFilter;
 
 use spdk_sys::spdk_log_get_print_level;
 
@@ -53,6 +59,46 @@ pub extern "C" fn log_impl(
     );
 }
 
+/// Format of the log messages, selected on the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// human readable lines
+    Default,
+    /// one JSON object per line, including the fields of the spans the
+    /// message was logged in, for log aggregators such as Loki or ELK
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Default
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(LogFormat::Default),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "invalid log format {}, expected default or json",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Default => write!(f, "default"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
 /// This function configures the logging format. The loglevel is also processed
 /// here i.e `RUST_LOG=mayastor=TRACE` will print all trace!() and higher
 /// messages to the console.
@@ -60,25 +106,52 @@ pub extern "C" fn log_impl(
 /// We might want to suppress certain messages, as some of them are redundant,
 /// in particular, the NOTICE messages as such, they are mapped to debug.
 pub fn init(level: &str) {
-    let mut builder =
-        Builder::from_env(Env::default().default_filter_or(level.to_string()));
-
-    builder.format(|buf, record| {
-        let mut level_style = buf.default_level_style(record.level());
-        level_style.set_intense(true);
-        writeln!(
-            buf,
-            "[{} {} {}:{}] {}",
-            buf.timestamp_nanos(),
-            level_style.value(record.level()),
-            Path::new(record.file().unwrap())
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap(),
-            record.line().unwrap(),
-            record.args()
-        )
-    });
-    builder.init();
+    init_with_format(level, LogFormat::default())
+}
+
+/// Configure logging as init() does, in the given format. Messages logged
+/// with the log crate, by our dependencies and by SPDK, are forwarded to
+/// tracing as well.
+pub fn init_with_format(level: &str, format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(level));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match format {
+        LogFormat::Default => {
+            let builder = builder.with_filter_reloading();
+            let handle = builder.reload_handle();
+            let _ = RELOAD_FILTER.set(Box::new(move |filter| {
+                handle.reload(filter).map_err(|e| e.to_string())
+            }));
+            builder.init()
+        }
+        LogFormat::Json => {
+            let builder = builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_filter_reloading();
+            let handle = builder.reload_handle();
+            let _ = RELOAD_FILTER.set(Box::new(move |filter| {
+                handle.reload(filter).map_err(|e| e.to_string())
+            }));
+            builder.init()
+        }
+    }
+}
+
+type ReloadFilter = Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>;
+
+/// replaces the filter of the subscriber, its type depends on the format
+static RELOAD_FILTER: OnceCell<ReloadFilter> = OnceCell::new();
+
+/// Change the log level while running, the level can be given as filter
+/// directives as well, i.e. `mayastor=debug,h2=info`.
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(level).map_err(|e| e.to_string())?;
+    match RELOAD_FILTER.get() {
+        Some(reload) => reload(filter),
+        None => Err("logging has not been initialized".into()),
+    }
 }
@@ -34,4 +34,12 @@ pub enum Error {
     LvolUnShare { source: CoreError, msg: String },
     #[snafu(display("{}", msg))]
     Property { source: Errno, msg: String },
+    #[snafu(display("{}", msg))]
+    Decouple { source: Errno, msg: String },
+    #[snafu(display("{}", msg))]
+    NotAncestor { msg: String },
+    #[snafu(display("{}", msg))]
+    Label { source: Errno, msg: String },
+    #[snafu(display("the pool {} belongs to {}", name, owner))]
+    Foreign { name: String, owner: String },
 }
@@ -0,0 +1,304 @@
+//! Handling of FLUSH requests sent to an lvol (replica) by remote nexuses.
+//!
+//! An lvol itself does not support FLUSH, so a flush issued by a nexus does
+//! not reach the base device of the pool. Depending on the device this is
+//! either exactly what we want (i.e. battery backed caches) or it risks data
+//! loss. The flush policy of an lvol determines what happens:
+//!
+//! - forward: every flush is sent to the base device of the pool
+//! - batch: flushes that arrive while a flush is outstanding on the same core
+//!   are coalesced into a single flush to the base device
+//! - ignore: flushes are completed without touching the base device
+//!
+//! The policy is applied by intercepting the IO of the lvol bdev, see
+//! bdev::interpose, all IO types other than FLUSH are passed on to the lvol
+//! module.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    os::raw::c_void,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use spdk_sys::{
+    spdk_bdev_fn_table,
+    spdk_bdev_io,
+    spdk_bdev_io_complete,
+    spdk_bdev_io_type,
+    spdk_bs_dev,
+    spdk_bs_dev_cb_args,
+    spdk_io_channel,
+    spdk_lvol,
+    SPDK_BDEV_IO_STATUS_FAILED,
+    SPDK_BDEV_IO_STATUS_SUCCESS,
+    SPDK_BDEV_IO_TYPE_FLUSH,
+};
+
+use crate::{
+    bdev::interpose::{self, Interpose},
+    core::Bdev,
+};
+
+/// what to do with a FLUSH request sent to an lvol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlushPolicy {
+    /// send every flush to the base device of the pool
+    Forward,
+    /// coalesce concurrent flushes into one flush to the base device
+    Batch,
+    /// complete flushes without sending them to the base device
+    Ignore,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy::Forward
+    }
+}
+
+impl Display for FlushPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let policy = match self {
+            FlushPolicy::Forward => "forward",
+            FlushPolicy::Batch => "batch",
+            FlushPolicy::Ignore => "ignore",
+        };
+        write!(f, "{}", policy)
+    }
+}
+
+impl FromStr for FlushPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forward" => Ok(FlushPolicy::Forward),
+            "batch" => Ok(FlushPolicy::Batch),
+            "ignore" => Ok(FlushPolicy::Ignore),
+            _ => Err(format!("invalid flush policy {}", s)),
+        }
+    }
+}
+
+impl From<u8> for FlushPolicy {
+    fn from(policy: u8) -> Self {
+        match policy {
+            1 => FlushPolicy::Batch,
+            2 => FlushPolicy::Ignore,
+            _ => FlushPolicy::Forward,
+        }
+    }
+}
+
+impl From<FlushPolicy> for u8 {
+    fn from(policy: FlushPolicy) -> Self {
+        match policy {
+            FlushPolicy::Forward => 0,
+            FlushPolicy::Batch => 1,
+            FlushPolicy::Ignore => 2,
+        }
+    }
+}
+
+/// the flush policy of an lvol, which can be changed while it is in use
+struct Flush {
+    policy: AtomicU8,
+}
+
+impl Flush {
+    fn new(policy: FlushPolicy) -> Self {
+        Self {
+            policy: AtomicU8::new(policy.into()),
+        }
+    }
+
+    fn get(&self) -> FlushPolicy {
+        self.policy.load(Ordering::Relaxed).into()
+    }
+
+    fn set(&self, policy: FlushPolicy) {
+        self.policy.store(policy.into(), Ordering::Relaxed);
+    }
+}
+
+/// flushes waiting for the outstanding flush on a base device to complete
+#[derive(Default)]
+struct Batch {
+    in_flight: bool,
+    pending: Vec<*mut spdk_bdev_io>,
+}
+
+thread_local! {
+    /// batches per base device on this core, keyed by the address of the
+    /// blobstore device
+    static BATCHES: RefCell<HashMap<usize, Batch>> = RefCell::new(HashMap::new());
+}
+
+/// context of a flush sent to the base device
+#[repr(C)]
+struct FlushCtx {
+    /// must be the first field as the blobstore device hands us back a
+    /// pointer to it
+    args: spdk_bs_dev_cb_args,
+    dev: *mut spdk_bs_dev,
+    /// the lvol flushes that complete together with this flush
+    ios: Vec<*mut spdk_bdev_io>,
+    batched: bool,
+}
+
+/// set the flush policy of the lvol and intercept the IO of its bdev if not
+/// done already
+pub(crate) fn apply(lvol: *mut spdk_lvol, policy: FlushPolicy) {
+    let bdev = unsafe { (*lvol).bdev };
+
+    if interpose::with(bdev, |flush: &Flush| flush.set(policy)).is_none() {
+        interpose::install(&Bdev::from(bdev), Flush::new(policy), |table| {
+            table.io_type_supported = Some(io_type_supported)
+        });
+    }
+
+    info!(
+        "flush policy of {} set to {}",
+        Bdev::from(bdev).name(),
+        policy
+    );
+}
+
+/// stop intercepting the IO of the lvol, as it is about to be destroyed
+pub(crate) fn forget(lvol: *mut spdk_lvol) {
+    interpose::forget(&Bdev::from(unsafe { (*lvol).bdev }));
+}
+
+/// returns the flush policy of the lvol, None if no policy has been set
+pub(crate) fn policy(lvol: *mut spdk_lvol) -> Option<FlushPolicy> {
+    interpose::with(unsafe { (*lvol).bdev }, Flush::get)
+}
+
+/// FLUSH is supported unless flushes are ignored, in which case the nvmf
+/// target completes flushes without sending them to us
+extern "C" fn io_type_supported(
+    ctx: *mut c_void,
+    io_type: spdk_bdev_io_type,
+) -> bool {
+    let lvol = ctx as *mut spdk_lvol;
+    if io_type == SPDK_BDEV_IO_TYPE_FLUSH {
+        return policy(lvol).unwrap_or_default() != FlushPolicy::Ignore;
+    }
+
+    let module = interpose::module(unsafe { (*lvol).bdev })
+        .expect("flush policy not applied");
+    unsafe { module.io_type_supported.unwrap()(ctx, io_type) }
+}
+
+impl Interpose for Flush {
+    fn submit_request(
+        &self,
+        module: &'static spdk_bdev_fn_table,
+        ch: *mut spdk_io_channel,
+        io: *mut spdk_bdev_io,
+    ) {
+        if unsafe { (*io).type_ } as spdk_bdev_io_type
+            != SPDK_BDEV_IO_TYPE_FLUSH
+        {
+            return interpose::pass_on(module, ch, io);
+        }
+
+        let lvol = unsafe { (*(*io).bdev).ctxt } as *mut spdk_lvol;
+        let dev = unsafe { (*(*lvol).lvol_store).bs_dev };
+
+        match self.get() {
+            FlushPolicy::Forward => flush_dev(dev, vec![io], false),
+            FlushPolicy::Batch => {
+                let submit = BATCHES.with(|b| {
+                    let mut batches = b.borrow_mut();
+                    let batch = batches.entry(dev as usize).or_default();
+                    if batch.in_flight {
+                        batch.pending.push(io);
+                        false
+                    } else {
+                        batch.in_flight = true;
+                        true
+                    }
+                });
+
+                if submit {
+                    flush_dev(dev, vec![io], true);
+                }
+            }
+            FlushPolicy::Ignore => unsafe {
+                spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_SUCCESS)
+            },
+        }
+    }
+}
+
+/// send a flush to the base device on the current core
+fn flush_dev(
+    dev: *mut spdk_bs_dev,
+    ios: Vec<*mut spdk_bdev_io>,
+    batched: bool,
+) {
+    let mut ctx = Box::new(FlushCtx {
+        args: spdk_bs_dev_cb_args::default(),
+        dev,
+        ios,
+        batched,
+    });
+
+    unsafe {
+        // the channel is reference counted per core, this does not create
+        // a new channel but takes a reference on the existing one
+        let channel = (*dev).create_channel.unwrap()(dev);
+        ctx.args.cb_fn = Some(flush_done);
+        ctx.args.channel = channel;
+        let ctx = Box::into_raw(ctx);
+        (*ctx).args.cb_arg = ctx as *mut c_void;
+        (*dev).flush.unwrap()(dev, channel, &mut (*ctx).args);
+    }
+}
+
+/// completion of a flush to the base device, complete all lvol flushes that
+/// were waiting for it and start the next batch if any
+extern "C" fn flush_done(
+    channel: *mut spdk_io_channel,
+    cb_arg: *mut c_void,
+    bserrno: i32,
+) {
+    let ctx = unsafe { Box::from_raw(cb_arg as *mut FlushCtx) };
+    let dev = ctx.dev;
+
+    unsafe { (*dev).destroy_channel.unwrap()(dev, channel) };
+
+    let status = if bserrno == 0 {
+        SPDK_BDEV_IO_STATUS_SUCCESS
+    } else {
+        error!("flush of blobstore device failed (errno={})", bserrno);
+        SPDK_BDEV_IO_STATUS_FAILED
+    };
+
+    ctx.ios
+        .iter()
+        .for_each(|io| unsafe { spdk_bdev_io_complete(*io, status) });
+
+    if !ctx.batched {
+        return;
+    }
+
+    let next = BATCHES.with(|b| {
+        let mut batches = b.borrow_mut();
+        let batch = batches.entry(dev as usize).or_default();
+        if batch.pending.is_empty() {
+            batch.in_flight = false;
+            None
+        } else {
+            Some(std::mem::take(&mut batch.pending))
+        }
+    });
+
+    if let Some(ios) = next {
+        flush_dev(dev, ios, true);
+    }
+}
@@ -0,0 +1,232 @@
+//! Health of the base devices of the pools.
+//!
+//! NVMe devices report their health in the SMART / health information log
+//! page. Other block devices used through aio or uring are asked with
+//! smartctl, if it is installed. The pools are checked along with their
+//! read-only state, and marked read-only once the media errors or the
+//! available spare of their device cross the thresholds of the config.
+
+use std::{
+    convert::TryInto,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+};
+
+use futures::channel::oneshot;
+use serde_json::Value;
+
+use crate::{
+    core::{Bdev, Mthread},
+    lvs::{read_only, Lvs},
+    subsys::{Config, PoolHealthOpts},
+};
+
+/// ATA attributes which count sectors that could not be read, reported
+/// uncorrectable errors and offline uncorrectable sectors
+const ATA_UNCORRECTABLE_ATTRIBUTES: [u64; 2] = [187, 198];
+
+/// health of the base device of a pool
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DiskHealth {
+    /// where the information comes from, "nvme" or "smartctl"
+    pub source: String,
+    /// NVMe critical warning bits, 0 for other devices
+    pub critical_warning: u8,
+    /// temperature in kelvin, 0 if not known
+    pub temperature: u16,
+    /// remaining spare capacity in percent
+    pub available_spare: Option<u8>,
+    /// spare capacity below which the device warns in percent
+    pub available_spare_threshold: Option<u8>,
+    /// estimate of the life of the device used in percent
+    pub percentage_used: Option<u8>,
+    /// number of unrecovered data integrity errors
+    pub media_errors: u64,
+    pub power_on_hours: u64,
+    pub unsafe_shutdowns: u64,
+}
+
+/// the low 64 bits of a little endian 128 bit counter of the log page
+fn counter(page: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(page[offset .. offset + 8].try_into().unwrap())
+}
+
+impl DiskHealth {
+    /// parse the SMART / health information log page of an NVMe device
+    pub fn from_nvme_log(page: &[u8]) -> Self {
+        assert!(page.len() >= 192);
+        Self {
+            source: "nvme".to_string(),
+            critical_warning: page[0],
+            temperature: u16::from_le_bytes([page[1], page[2]]),
+            available_spare: Some(page[3]),
+            available_spare_threshold: Some(page[4]),
+            percentage_used: Some(page[5]),
+            power_on_hours: counter(page, 128),
+            unsafe_shutdowns: counter(page, 144),
+            media_errors: counter(page, 160),
+        }
+    }
+
+    /// parse the output of smartctl --json, as for NVMe devices attached to
+    /// the kernel or ATA and SCSI disks
+    fn from_smartctl(json: &Value) -> Option<Self> {
+        json.get("device")?;
+
+        let u64_at = |path: &str| json.pointer(path).and_then(Value::as_u64);
+        let u8_at = |path: &str| u64_at(path).map(|v| v as u8);
+
+        let mut health = Self {
+            source: "smartctl".to_string(),
+            temperature: u64_at("/temperature/current")
+                .map_or(0, |c| (c + 273) as u16),
+            power_on_hours: u64_at("/power_on_time/hours").unwrap_or(0),
+            ..Default::default()
+        };
+
+        let nvme = "/nvme_smart_health_information_log";
+        if json.pointer(nvme).is_some() {
+            health.critical_warning =
+                u8_at(&format!("{}/critical_warning", nvme)).unwrap_or(0);
+            health.available_spare =
+                u8_at(&format!("{}/available_spare", nvme));
+            health.available_spare_threshold =
+                u8_at(&format!("{}/available_spare_threshold", nvme));
+            health.percentage_used =
+                u8_at(&format!("{}/percentage_used", nvme));
+            health.media_errors =
+                u64_at(&format!("{}/media_errors", nvme)).unwrap_or(0);
+            health.unsafe_shutdowns =
+                u64_at(&format!("{}/unsafe_shutdowns", nvme)).unwrap_or(0);
+        } else if let Some(table) = json
+            .pointer("/ata_smart_attributes/table")
+            .and_then(Value::as_array)
+        {
+            health.media_errors = table
+                .iter()
+                .filter(|a| {
+                    a.get("id").and_then(Value::as_u64).map_or(false, |id| {
+                        ATA_UNCORRECTABLE_ATTRIBUTES.contains(&id)
+                    })
+                })
+                .filter_map(|a| a.pointer("/raw/value").and_then(Value::as_u64))
+                .sum();
+        } else {
+            health.media_errors = ["read", "write"]
+                .iter()
+                .filter_map(|op| {
+                    u64_at(&format!(
+                        "/scsi_error_counter_log/{}/total_uncorrected_errors",
+                        op
+                    ))
+                })
+                .sum();
+        }
+
+        Some(health)
+    }
+
+    /// Returns why a pool on the device should no longer be written to
+    /// according to the thresholds, None if it is healthy enough.
+    pub fn fault_reason(&self, opts: &PoolHealthOpts) -> Option<String> {
+        if opts.max_media_errors > 0
+            && self.media_errors > opts.max_media_errors
+        {
+            return Some(format!(
+                "the device has {} media errors, more than {}",
+                self.media_errors, opts.max_media_errors
+            ));
+        }
+
+        match self.available_spare {
+            Some(spare)
+                if opts.min_available_spare > 0
+                    && spare < opts.min_available_spare =>
+            {
+                Some(format!(
+                    "the available spare of the device is {}%, below {}%",
+                    spare, opts.min_available_spare
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// run smartctl on the device in a thread of its own, not to block the
+/// reactor
+async fn smartctl(path: PathBuf) -> Option<Value> {
+    let (s, r) = oneshot::channel();
+    thread::spawn(move || {
+        Mthread::unaffinitize();
+        let output = Command::new("smartctl")
+            .arg("--json")
+            .arg("-a")
+            .arg(&path)
+            .output();
+        let _ = s.send(output);
+    });
+
+    // smartctl exits with a bit mask of warnings, the output is what counts
+    let output = r.await.ok()?.ok()?;
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+/// the health of a block device, the name of an aio or uring bdev is the
+/// path of its device
+async fn block_health(bdev: &Bdev) -> Option<DiskHealth> {
+    let path = std::fs::canonicalize(bdev.name()).ok()?;
+    if !path.starts_with(Path::new("/dev")) {
+        return None;
+    }
+    DiskHealth::from_smartctl(&smartctl(path).await?)
+}
+
+/// the health of the base device of a pool, None if it does not tell
+async fn device_health(bdev: &Bdev) -> Option<DiskHealth> {
+    match bdev.driver().as_str() {
+        "nvme" => read_only::nvme_health_log(bdev)
+            .await
+            .map(|page| DiskHealth::from_nvme_log(&page)),
+        "aio" | "uring" => block_health(bdev).await,
+        _ => None,
+    }
+}
+
+impl Lvs {
+    /// the health of the base device of the pool, None if the device does
+    /// not report it
+    pub async fn disk_health(&self) -> Option<DiskHealth> {
+        device_health(&self.base_bdev()).await
+    }
+}
+
+/// check the health of the device of every pool and mark the pool
+/// read-only once it crosses a threshold of the config
+pub(crate) async fn check_pools() {
+    let opts = Config::get().pool_health_opts.clone();
+    if opts.max_media_errors == 0 && opts.min_available_spare == 0 {
+        return;
+    }
+
+    let names = Lvs::iter()
+        .map(|lvs| lvs.name().to_string())
+        .collect::<Vec<_>>();
+
+    for name in names {
+        let health = match Lvs::lookup(&name) {
+            Some(lvs) if lvs.read_only_reason().is_none() => {
+                lvs.disk_health().await
+            }
+            _ => continue,
+        };
+
+        if let Some(reason) = health.and_then(|h| h.fault_reason(&opts)) {
+            // the pool may have been destroyed meanwhile
+            if let Some(lvs) = Lvs::lookup(&name) {
+                lvs.set_read_only(&reason).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Labels of the pools.
+//!
+//! When a pool is created, a label with its UUID, the time it was created
+//! and the cluster and node it was created by is stored as an xattr of the
+//! super blob of its lvol store. The label is checked whenever the pool is
+//! imported, so a disk that belongs to another cluster or another node is
+//! left alone rather than taken over, or wiped by creating a pool on it. A
+//! pool without a label, created before labels existed, is given one by the
+//! node which imports it first.
+//!
+//! The labels of the pools which are loaded are kept in memory, for them to
+//! be listed without reading the disks.
+
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    os::raw::{c_char, c_void},
+    sync::RwLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use spdk_sys::{
+    spdk_blob,
+    spdk_blob_close,
+    spdk_blob_get_xattr_value,
+    spdk_blob_set_xattr,
+    spdk_blob_sync_md,
+    spdk_bs_open_blob,
+    spdk_lvol_store,
+};
+
+use crate::{
+    core::{MayastorEnvironment, Uuid},
+    ffihelper::{cb_arg, done_cb, pair, ErrnoResult, FfiResult, IntoCString},
+    lvs::Error,
+    subsys::Config,
+};
+
+/// name of the xattr of the super blob holding the label
+const LABEL_XATTR: &str = "mayastor_label";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolLabel {
+    /// UUID of the pool
+    pub uuid: String,
+    /// seconds since the epoch when the pool was created
+    pub created_at: u64,
+    /// cluster of the node which created the pool, empty if not set
+    pub cluster_id: String,
+    /// name of the node which created the pool
+    pub node: String,
+}
+
+impl PoolLabel {
+    /// the label of a pool created by this node now
+    fn local(uuid: String) -> Self {
+        Self {
+            uuid,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            cluster_id: Config::get().cluster_id.clone().unwrap_or_default(),
+            node: MayastorEnvironment::global_node_name().to_string(),
+        }
+    }
+
+    /// who owns the pool if it is not this node, none if it is
+    pub(crate) fn foreign_owner(&self) -> Option<String> {
+        let cluster_id = Config::get().cluster_id.clone().unwrap_or_default();
+        if !self.cluster_id.is_empty()
+            && !cluster_id.is_empty()
+            && self.cluster_id != cluster_id
+        {
+            return Some(format!("cluster {}", self.cluster_id));
+        }
+        if self.node != MayastorEnvironment::global_node_name() {
+            return Some(format!("node {}", self.node));
+        }
+        None
+    }
+}
+
+/// the label per loaded pool, keyed by the address of the lvol store
+static LABELS: Lazy<RwLock<HashMap<usize, PoolLabel>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// the label of the pool, if it has been read or written
+pub(crate) fn get(lvs: *mut spdk_lvol_store) -> Option<PoolLabel> {
+    LABELS.read().unwrap().get(&(lvs as usize)).cloned()
+}
+
+/// the pool is gone, drop its label
+pub(crate) fn forget(lvs: *mut spdk_lvol_store) {
+    LABELS.write().unwrap().remove(&(lvs as usize));
+}
+
+fn uuid(lvs: *mut spdk_lvol_store) -> String {
+    Uuid::from_bytes(unsafe { (*lvs).uuid.u.raw }).to_string()
+}
+
+/// Label a pool which has just been created by this node.
+pub(crate) async fn write_new(
+    lvs: *mut spdk_lvol_store,
+) -> Result<PoolLabel, Error> {
+    let label = PoolLabel::local(uuid(lvs));
+    write(lvs, &label).await?;
+    LABELS.write().unwrap().insert(lvs as usize, label.clone());
+    Ok(label)
+}
+
+/// Check the label of an imported pool, which fails if the pool belongs to
+/// another cluster or node. A pool without a label is labelled now.
+pub(crate) async fn check(
+    lvs: *mut spdk_lvol_store,
+    name: &str,
+) -> Result<PoolLabel, Error> {
+    let label = match read(lvs).await? {
+        Some(label) => label,
+        None => {
+            info!("Labelling the pool {} which has no label yet", name);
+            let label = PoolLabel::local(uuid(lvs));
+            write(lvs, &label).await?;
+            label
+        }
+    };
+
+    if let Some(owner) = label.foreign_owner() {
+        return Err(Error::Foreign {
+            name: name.to_string(),
+            owner,
+        });
+    }
+
+    LABELS.write().unwrap().insert(lvs as usize, label.clone());
+    Ok(label)
+}
+
+/// callback of opening a blob
+extern "C" fn blob_open_cb(arg: *mut c_void, blob: *mut spdk_blob, errno: i32) {
+    done_cb(arg, (blob as usize, errno));
+}
+
+/// Run the function on the opened super blob of the store and close it
+/// again, syncing its metadata first if asked to.
+async fn with_super_blob<T>(
+    lvs: *mut spdk_lvol_store,
+    sync: bool,
+    f: impl FnOnce(*mut spdk_blob) -> Result<T, Errno>,
+) -> Result<T, Error> {
+    let err = |source: Errno| Error::Label {
+        source,
+        msg: format!("failed to access the label of pool {}", uuid(lvs)),
+    };
+
+    let (s, r) = pair::<(usize, i32)>();
+    unsafe {
+        spdk_bs_open_blob(
+            (*lvs).blobstore,
+            (*lvs).super_blob_id,
+            Some(blob_open_cb),
+            cb_arg(s),
+        );
+    }
+    let (blob, errno) = r.await.expect("blob open callback is gone");
+    errno.to_result(|e| err(Errno::from_i32(e)))?;
+    let blob = blob as *mut spdk_blob;
+
+    let mut result = f(blob);
+    if result.is_ok() && sync {
+        let (s, r) = pair::<i32>();
+        unsafe { spdk_blob_sync_md(blob, Some(done_cb), cb_arg(s)) };
+        if let Err(e) = r
+            .await
+            .expect("blob sync callback is gone")
+            .to_result(Errno::from_i32)
+        {
+            result = Err(e);
+        }
+    }
+
+    let (s, r) = pair::<i32>();
+    unsafe { spdk_blob_close(blob, Some(done_cb), cb_arg(s)) };
+    let closed: ErrnoResult<()> = r
+        .await
+        .expect("blob close callback is gone")
+        .to_result(Errno::from_i32);
+
+    result.and_then(|v| closed.map(|_| v)).map_err(err)
+}
+
+/// read the label of the pool from disk, none if it has none
+pub(crate) async fn read(
+    lvs: *mut spdk_lvol_store,
+) -> Result<Option<PoolLabel>, Error> {
+    let value = with_super_blob(lvs, false, |blob| {
+        let name = LABEL_XATTR.into_cstring();
+        let mut value: *const c_char = std::ptr::null();
+        let mut value_len: u64 = 0;
+        let rc = unsafe {
+            spdk_blob_get_xattr_value(
+                blob,
+                name.as_ptr(),
+                &mut value as *mut *const c_char as *mut *const c_void,
+                &mut value_len,
+            )
+        };
+        if rc != 0 || value.is_null() {
+            return Ok(None);
+        }
+        Ok(unsafe { CStr::from_ptr(value) }
+            .to_str()
+            .ok()
+            .map(String::from))
+    })
+    .await?;
+
+    match value {
+        Some(value) => {
+            serde_json::from_str(&value)
+                .map(Some)
+                .map_err(|e| Error::Label {
+                    source: Errno::EINVAL,
+                    msg: format!("invalid label of pool {}: {}", uuid(lvs), e),
+                })
+        }
+        None => Ok(None),
+    }
+}
+
+/// write the label of the pool to disk
+async fn write(
+    lvs: *mut spdk_lvol_store,
+    label: &PoolLabel,
+) -> Result<(), Error> {
+    let value = serde_json::to_string(label).unwrap().into_cstring();
+    with_super_blob(lvs, true, |blob| {
+        let name = LABEL_XATTR.into_cstring();
+        unsafe {
+            spdk_blob_set_xattr(
+                blob,
+                name.as_ptr(),
+                value.as_bytes_with_nul().as_ptr() as *const _,
+                value.as_bytes_with_nul().len() as u16,
+            )
+        }
+        .to_result(Errno::from_i32)
+    })
+    .await
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::{BTreeSet, HashMap},
     convert::TryFrom,
     ffi::{c_void, CStr},
     fmt::Display,
@@ -13,16 +14,30 @@ use pin_utils::core_reexport::fmt::Formatter;
 use tracing::instrument;
 
 use spdk_sys::{
+    spdk_blob_get_clones,
+    spdk_blob_get_id,
+    spdk_blob_get_parent_snapshot,
+    spdk_blob_get_xattr_names,
     spdk_blob_get_xattr_value,
+    spdk_blob_id,
+    spdk_blob_is_snapshot,
+    spdk_blob_remove_xattr,
     spdk_blob_set_xattr,
     spdk_blob_sync_md,
+    spdk_bs_get_cluster_size,
     spdk_lvol,
+    spdk_lvol_decouple_parent,
+    spdk_xattr_names,
+    spdk_xattr_names_free,
+    spdk_xattr_names_get_count,
+    spdk_xattr_names_get_name,
     vbdev_lvol_destroy,
     vbdev_lvol_get_from_bdev,
 };
 
 use crate::{
-    core::{Bdev, CoreError, Protocol, Share},
+    bdev::interpose,
+    core::{tracker, Bdev, CoreError, Protocol, Share},
     ffihelper::{
         cb_arg,
         errno_result_from_i32,
@@ -31,21 +46,34 @@ use crate::{
         FfiResult,
         IntoCString,
     },
-    lvs::{error::Error, pool::Lvs},
+    lvs::{
+        error::Error,
+        flush::{self, FlushPolicy},
+        pool::Lvs,
+    },
 };
 
+/// prefix of the xattrs holding the metadata set by the control plane, which
+/// keeps them apart from the properties
+const METADATA_PREFIX: &str = "metadata.";
+
 /// properties we allow for being set on the lvol, this information is stored on
 /// disk
 #[derive(Debug, PartialEq)]
 #[non_exhaustive]
 pub enum PropValue {
     Shared(bool),
+    FlushPolicy(FlushPolicy),
+    /// name the lvol had before its pool was adopted from other tools
+    OriginName(String),
 }
 
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum PropName {
     Shared,
+    FlushPolicy,
+    OriginName,
 }
 
 #[derive(Debug)]
@@ -109,6 +137,7 @@ impl Share for Lvol {
         })?;
 
         self.set(PropValue::Shared(true)).await?;
+        self.load_flush_policy().await;
 
         Ok(share)
     }
@@ -197,6 +226,186 @@ impl Lvol {
         unsafe { self.0.as_ref().thin_provision }
     }
 
+    /// returns a boolean indicating if the lvol is a snapshot
+    pub fn is_snapshot(&self) -> bool {
+        unsafe { spdk_blob_is_snapshot(self.0.as_ref().blob) }
+    }
+
+    /// returns the lvols which are clones of this lvol, only snapshots can
+    /// have clones
+    pub fn clones(&self) -> Vec<Lvol> {
+        let (bs, id) = unsafe {
+            let lvol = self.0.as_ref();
+            ((*lvol.lvol_store).blobstore, spdk_blob_get_id(lvol.blob))
+        };
+
+        // the first call only returns the number of clones
+        let mut count: u64 = 0;
+        unsafe {
+            spdk_blob_get_clones(bs, id, std::ptr::null_mut(), &mut count)
+        };
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let mut ids: Vec<spdk_blob_id> = vec![0; count as usize];
+        let rc = unsafe {
+            spdk_blob_get_clones(bs, id, ids.as_mut_ptr(), &mut count)
+        };
+        if rc != 0 {
+            return Vec::new();
+        }
+
+        self.siblings(|l| ids.contains(&l.blob_id()))
+    }
+
+    /// returns the snapshot this lvol is a clone of, if any
+    pub fn parent(&self) -> Option<Lvol> {
+        let id = unsafe {
+            let lvol = self.0.as_ref();
+            spdk_blob_get_parent_snapshot(
+                (*lvol.lvol_store).blobstore,
+                self.blob_id(),
+            )
+        };
+        // SPDK_BLOBID_INVALID
+        if id == u64::MAX {
+            return None;
+        }
+        self.siblings(|l| l.blob_id() == id).pop()
+    }
+
+    /// returns the lvols in the pool of this lvol which match the predicate
+    fn siblings(&self, predicate: impl Fn(&Lvol) -> bool) -> Vec<Lvol> {
+        let lvs =
+            unsafe { Lvs(NonNull::new_unchecked(self.0.as_ref().lvol_store)) };
+        lvs.lvols()
+            .map(|lvols| lvols.filter(|l| predicate(l)).collect())
+            .unwrap_or_default()
+    }
+
+    fn blob_id(&self) -> spdk_blob_id {
+        unsafe { spdk_blob_get_id(self.0.as_ref().blob) }
+    }
+
+    /// returns the size of a cluster of the pool in bytes
+    pub fn cluster_size(&self) -> u64 {
+        unsafe {
+            spdk_bs_get_cluster_size((*self.0.as_ref().lvol_store).blobstore)
+        }
+    }
+
+    /// returns the indices of the clusters which are allocated by this lvol
+    /// itself, as opposed to being inherited from its snapshot
+    pub fn allocated_clusters(&self) -> Vec<u64> {
+        let clusters = unsafe {
+            let active = &(*self.0.as_ref().blob).active;
+            if active.clusters.is_null() {
+                return Vec::new();
+            }
+            std::slice::from_raw_parts(
+                active.clusters,
+                active.num_clusters as usize,
+            )
+        };
+        // a cluster which is not allocated maps to LBA 0
+        clusters
+            .iter()
+            .enumerate()
+            .filter(|(_, lba)| **lba != 0)
+            .map(|(idx, _)| idx as u64)
+            .collect()
+    }
+
+    /// returns the indices of the clusters which have been written since the
+    /// base snapshot was taken, in ascending order. These are the clusters
+    /// allocated by this lvol or any of the snapshots between it and the
+    /// base. Without base, all clusters holding data are returned.
+    pub fn changed_clusters(
+        &self,
+        base: Option<&Lvol>,
+    ) -> Result<Vec<u64>, Error> {
+        let mut changed = BTreeSet::new();
+        let mut current = Lvol(self.0);
+
+        loop {
+            if base.map_or(false, |b| b.0 == current.0) {
+                break;
+            }
+            changed.extend(current.allocated_clusters());
+            current = match current.parent() {
+                Some(parent) => parent,
+                None if base.is_none() => break,
+                None => {
+                    return Err(Error::NotAncestor {
+                        msg: format!(
+                            "{} is not a snapshot {} was derived from",
+                            base.unwrap().name(),
+                            self.name()
+                        ),
+                    })
+                }
+            };
+        }
+
+        Ok(changed.into_iter().collect())
+    }
+
+    /// copy the clusters which this clone shares with its snapshot, such that
+    /// it no longer depends on the snapshot. If the snapshot is a clone
+    /// itself, the lvol becomes a clone of the parent of the snapshot instead.
+    #[instrument(level = "debug", err)]
+    pub async fn decouple_parent(&self) -> Result<(), Error> {
+        extern "C" fn decouple_cb(sender: *mut c_void, errno: i32) {
+            let sender =
+                unsafe { Box::from_raw(sender as *mut oneshot::Sender<i32>) };
+            sender.send(errno).unwrap();
+        }
+
+        let (s, r) = pair::<i32>();
+        unsafe {
+            spdk_lvol_decouple_parent(
+                self.0.as_ptr(),
+                Some(decouple_cb),
+                cb_arg(s),
+            )
+        };
+
+        r.await
+            .expect("lvol decouple callback is gone")
+            .to_result(|e| Error::Decouple {
+                source: Errno::from_i32(e),
+                msg: format!("failed to decouple lvol {}", self.name()),
+            })?;
+
+        info!("Decoupled lvol {} from its snapshot", self.name());
+        Ok(())
+    }
+
+    /// returns the flush policy which is in effect for this lvol
+    pub fn flush_policy(&self) -> FlushPolicy {
+        flush::policy(self.0.as_ptr()).unwrap_or_default()
+    }
+
+    /// apply the flush policy stored on disk, lvols created before flush
+    /// policies existed have none and get the default
+    pub(crate) async fn load_flush_policy(&self) {
+        let policy = match self.get(PropName::FlushPolicy).await {
+            Ok(PropValue::FlushPolicy(policy)) => policy,
+            _ => FlushPolicy::default(),
+        };
+        flush::apply(self.0.as_ptr(), policy);
+    }
+
+    /// fail the writes to the lvol as its pool has become read-only, which
+    /// its bdev does by intercepting the IO, as it does for the flush policy
+    pub(crate) async fn write_protect(&self) {
+        if flush::policy(self.0.as_ptr()).is_none() {
+            self.load_flush_policy().await;
+        }
+        interpose::write_protect(unsafe { self.0.as_ref().bdev }, true);
+    }
+
     /// destroy the lvol
     #[instrument(level = "debug", err)]
     pub async fn destroy(self) -> Result<String, Error> {
@@ -207,18 +416,21 @@ impl Lvol {
         }
 
         let name = self.name();
+        flush::forget(self.0.as_ptr());
 
         let (s, r) = pair::<i32>();
         unsafe {
             vbdev_lvol_destroy(self.0.as_ptr(), Some(destroy_cb), cb_arg(s))
         };
 
-        r.await
-            .expect("lvol destroy callback is gone")
-            .to_result(|e| Error::RepDestroy {
-                source: Errno::from_i32(e),
-                msg: format!("failed to destroy lvol {}", name),
-            })?;
+        let errno = r.await.expect("lvol destroy callback is gone");
+        if errno != 0 {
+            tracker::warn_open_handles(&name).await;
+        }
+        errno.to_result(|e| Error::RepDestroy {
+            source: Errno::from_i32(e),
+            msg: format!("failed to destroy lvol {}", name),
+        })?;
 
         info!("Destroyed lvol {}", name);
         Ok(name)
@@ -238,78 +450,198 @@ impl Lvol {
         let blob = unsafe { self.0.as_ref().blob };
         assert_ne!(blob.is_null(), true);
 
-        match prop {
+        let (name, value) = match prop {
             PropValue::Shared(val) => {
-                let name = "shared".into_cstring();
-                let value = if val { "true" } else { "false" }.into_cstring();
-                unsafe {
-                    spdk_blob_set_xattr(
-                        blob,
-                        name.as_ptr(),
-                        value.as_bytes_with_nul().as_ptr() as *const _,
-                        value.as_bytes_with_nul().len() as u16,
-                    )
-                }
-                .to_result(|e| Error::Property {
-                    source: Errno::from_i32(e),
-                    msg: format!(
-                        "failed to set the property {:?} on {}",
-                        prop,
-                        self.name()
-                    ),
-                })?;
+                ("shared", if val { "true" } else { "false" }.to_string())
+            }
+            PropValue::FlushPolicy(policy) => {
+                ("flush_policy", policy.to_string())
+            }
+            PropValue::OriginName(ref origin) => {
+                ("origin_name", origin.clone())
             }
         };
 
+        let name = name.into_cstring();
+        let value = value.into_cstring();
+        unsafe {
+            spdk_blob_set_xattr(
+                blob,
+                name.as_ptr(),
+                value.as_bytes_with_nul().as_ptr() as *const _,
+                value.as_bytes_with_nul().len() as u16,
+            )
+        }
+        .to_result(|e| Error::Property {
+            source: Errno::from_i32(e),
+            msg: format!(
+                "failed to set the property {:?} on {}",
+                prop,
+                self.name()
+            ),
+        })?;
+
+        self.sync_md().await?;
+
+        if let PropValue::FlushPolicy(policy) = prop {
+            flush::apply(self.0.as_ptr(), policy);
+        }
+
+        Ok(())
+    }
+
+    /// write the metadata of the blob of the lvol to disk
+    async fn sync_md(&self) -> Result<(), Error> {
+        let blob = unsafe { self.0.as_ref().blob };
         let (s, r) = pair::<i32>();
         unsafe {
             spdk_blob_sync_md(blob, Some(Self::blob_sync_cb), cb_arg(s));
         };
 
-        r.await.expect("sync callback is gone").to_result(|e| {
-            Error::Property {
+        r.await
+            .expect("sync callback is gone")
+            .to_result(|e| Error::Property {
                 source: Errno::from_i32(e),
                 msg: format!("failed to sync blob md for {}", self.name()),
-            }
-        })?;
-
-        Ok(())
+            })
     }
 
-    /// get/read a property from this lvol from disk
+    /// write the metadata of the control plane on to the lvol, along with
+    /// its properties. The keys with an empty value are removed, the other
+    /// keys stored already are kept.
     #[instrument(level = "debug", err)]
-    pub async fn get(&self, prop: PropName) -> Result<PropValue, Error> {
+    pub async fn set_metadata(
+        &self,
+        metadata: &HashMap<String, String>,
+    ) -> Result<(), Error> {
         let blob = unsafe { self.0.as_ref().blob };
         assert_ne!(blob.is_null(), true);
 
-        match prop {
-            PropName::Shared => {
-                let name = "shared".into_cstring();
-                let mut value: *const libc::c_char =
-                    std::ptr::null::<libc::c_char>();
-                let mut value_len: u64 = 0;
+        for (key, value) in metadata {
+            let name = format!("{}{}", METADATA_PREFIX, key).into_cstring();
+            let rc = if value.is_empty() {
+                match unsafe { spdk_blob_remove_xattr(blob, name.as_ptr()) } {
+                    rc if rc == -libc::ENOENT => 0,
+                    rc => rc,
+                }
+            } else {
+                let value = value.as_str().into_cstring();
                 unsafe {
-                    spdk_blob_get_xattr_value(
+                    spdk_blob_set_xattr(
                         blob,
                         name.as_ptr(),
-                        &mut value as *mut *const c_char as *mut *const c_void,
-                        &mut value_len,
+                        value.as_bytes_with_nul().as_ptr() as *const _,
+                        value.as_bytes_with_nul().len() as u16,
                     )
                 }
-                .to_result(|e| Error::Property {
-                    source: Errno::from_i32(e),
-                    msg: format!("failed to get the property {:?}", prop),
-                })?;
-                match unsafe { CStr::from_ptr(value).to_str() } {
-                    Ok("true") => Ok(PropValue::Shared(true)),
-                    Ok("false") => Ok(PropValue::Shared(false)),
-                    _ => Err(Error::Property {
-                        source: Errno::EINVAL,
-                        msg: "the property contained an invalid value"
-                            .to_string(),
-                    }),
-                }
+            };
+            rc.to_result(|e| Error::Property {
+                source: Errno::from_i32(e),
+                msg: format!(
+                    "failed to set the metadata {} on {}",
+                    key,
+                    self.name()
+                ),
+            })?;
+        }
+
+        self.sync_md().await
+    }
+
+    /// returns the metadata of the control plane stored on the lvol
+    pub fn metadata(&self) -> Result<HashMap<String, String>, Error> {
+        let blob = unsafe { self.0.as_ref().blob };
+        assert_ne!(blob.is_null(), true);
+
+        let mut names: *mut spdk_xattr_names = std::ptr::null_mut();
+        unsafe { spdk_blob_get_xattr_names(blob, &mut names) }.to_result(
+            |e| Error::Property {
+                source: Errno::from_i32(e),
+                msg: format!("failed to list the metadata of {}", self.name()),
+            },
+        )?;
+
+        let mut metadata = HashMap::new();
+        for idx in 0 .. unsafe { spdk_xattr_names_get_count(names) } {
+            let name = unsafe { spdk_xattr_names_get_name(names, idx) };
+            let name = unsafe { CStr::from_ptr(name) };
+            let key = match name
+                .to_str()
+                .ok()
+                .and_then(|n| n.strip_prefix(METADATA_PREFIX))
+            {
+                Some(key) => key.to_string(),
+                None => continue,
+            };
+
+            let mut value: *const c_char = std::ptr::null();
+            let mut value_len: u64 = 0;
+            let rc = unsafe {
+                spdk_blob_get_xattr_value(
+                    blob,
+                    name.as_ptr(),
+                    &mut value as *mut *const c_char as *mut *const c_void,
+                    &mut value_len,
+                )
+            };
+            if rc != 0 || value.is_null() {
+                continue;
             }
+            let value = unsafe { CStr::from_ptr(value) };
+            metadata.insert(key, value.to_string_lossy().into_owned());
+        }
+        unsafe { spdk_xattr_names_free(names) };
+
+        Ok(metadata)
+    }
+
+    /// get/read a property from this lvol from disk
+    #[instrument(level = "debug", err)]
+    pub async fn get(&self, prop: PropName) -> Result<PropValue, Error> {
+        let blob = unsafe { self.0.as_ref().blob };
+        assert_ne!(blob.is_null(), true);
+
+        let name = match prop {
+            PropName::Shared => "shared",
+            PropName::FlushPolicy => "flush_policy",
+            PropName::OriginName => "origin_name",
+        }
+        .into_cstring();
+
+        let mut value: *const libc::c_char = std::ptr::null::<libc::c_char>();
+        let mut value_len: u64 = 0;
+        unsafe {
+            spdk_blob_get_xattr_value(
+                blob,
+                name.as_ptr(),
+                &mut value as *mut *const c_char as *mut *const c_void,
+                &mut value_len,
+            )
+        }
+        .to_result(|e| Error::Property {
+            source: Errno::from_i32(e),
+            msg: format!("failed to get the property {:?}", prop),
+        })?;
+
+        let invalid = || Error::Property {
+            source: Errno::EINVAL,
+            msg: "the property contained an invalid value".to_string(),
+        };
+
+        let value =
+            unsafe { CStr::from_ptr(value).to_str() }.map_err(|_| invalid())?;
+
+        match prop {
+            PropName::Shared => match value {
+                "true" => Ok(PropValue::Shared(true)),
+                "false" => Ok(PropValue::Shared(false)),
+                _ => Err(invalid()),
+            },
+            PropName::FlushPolicy => value
+                .parse::<FlushPolicy>()
+                .map(PropValue::FlushPolicy)
+                .map_err(|_| invalid()),
+            PropName::OriginName => Ok(PropValue::OriginName(value.into())),
         }
     }
 }
@@ -1,7 +1,14 @@
 pub use error::Error;
+pub use flush::FlushPolicy;
+pub use health::DiskHealth;
+pub use label::PoolLabel;
 pub use lvol::{Lvol, PropName, PropValue};
 pub use pool::Lvs;
 
 mod error;
+mod flush;
+pub(crate) mod health;
+pub(crate) mod label;
 mod lvol;
 mod pool;
+pub(crate) mod read_only;
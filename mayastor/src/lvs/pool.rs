@@ -32,8 +32,9 @@ use crate::{
     bdev::Uri,
     core::{Bdev, Share, Uuid},
     ffihelper::{cb_arg, pair, AsStr, ErrnoResult, FfiResult, IntoCString},
-    lvs::{Error, Lvol, PropName, PropValue},
+    lvs::{label, read_only, Error, Lvol, PropName, PropValue},
     nexus_uri::{bdev_destroy, NexusBdevError},
+    subsys::events,
 };
 
 impl From<*mut spdk_lvol_store> for Lvs {
@@ -119,13 +120,13 @@ impl Lvs {
         LvsIterator::default()
     }
 
-    /// lookup a lvol store by its name
+    /// lookup a lvol store by its name, or by its uuid
     pub fn lookup(name: &str) -> Option<Self> {
-        let name = name.into_cstring();
+        let c_name = name.into_cstring();
 
-        let lvs = unsafe { vbdev_get_lvol_store_by_name(name.as_ptr()) };
+        let lvs = unsafe { vbdev_get_lvol_store_by_name(c_name.as_ptr()) };
         if lvs.is_null() {
-            None
+            Self::iter().find(|lvs| lvs.uuid() == name)
         } else {
             Some(Lvs::from(lvs))
         }
@@ -218,7 +219,7 @@ impl Lvs {
         // could be that a pool with a different name was imported
         match Self::lookup(&name) {
             Some(pool) => {
-                pool.share_all().await;
+                let pool = pool.check_label().await?;
                 info!("The pool '{}' has been imported", name);
                 Ok(pool)
             }
@@ -229,6 +230,34 @@ impl Lvs {
         }
     }
 
+    /// Check the label of the pool which has just been loaded and share its
+    /// replicas. A pool which belongs to another cluster or node is exported
+    /// again. A pool whose label could not be read stays loaded, and its
+    /// label is checked again when the pool is created next, rather than the
+    /// disk being taken for one without a pool.
+    async fn check_label(self) -> Result<Lvs, Error> {
+        let name = self.name().to_string();
+        match label::check(self.0.as_ptr(), &name).await {
+            Ok(_) => {
+                self.share_all().await;
+                Ok(self)
+            }
+            Err(
+                e @ Error::Foreign {
+                    ..
+                },
+            ) => {
+                error!("Not importing the pool '{}': {}", name, e);
+                self.export().await?;
+                Err(e)
+            }
+            Err(e) => {
+                error!("Failed to check the label of pool '{}': {}", name, e);
+                Err(e)
+            }
+        }
+    }
+
     #[instrument(level = "debug", err)]
     /// Create a pool on base bdev
     pub async fn create(name: &str, bdev: &str) -> Result<Lvs, Error> {
@@ -264,6 +293,7 @@ impl Lvs {
 
         match Self::lookup(&name) {
             Some(pool) => {
+                label::write_new(pool.0.as_ptr()).await?;
                 info!("The pool '{}' has been created on {}", name, bdev);
                 Ok(pool)
             }
@@ -274,7 +304,8 @@ impl Lvs {
         }
     }
 
-    /// imports the pool if it exists, otherwise try to create it
+    /// imports the pool if it exists, otherwise creates it if the disk has no
+    /// lvol store
     #[instrument(level = "debug", err)]
     pub async fn create_or_import(
         args: CreatePoolRequest,
@@ -292,13 +323,15 @@ impl Lvs {
             })?;
 
         if let Some(pool) = Self::lookup(&args.name) {
-            return if pool.base_bdev().name() == parsed.get_name() {
-                Ok(pool)
-            } else {
+            return if pool.base_bdev().name() != parsed.get_name() {
                 Err(Error::Create {
                     err: Errno::EEXIST,
                     msg: format!("pool {} already exists", args.name),
                 })
+            } else if label::get(pool.0.as_ptr()).is_some() {
+                Ok(pool)
+            } else {
+                pool.check_label().await
             };
         }
 
@@ -315,9 +348,14 @@ impl Lvs {
             Ok(name) => Ok(name),
         }?;
 
+        // a pool is only created on a disk without an lvol store, as it would
+        // wipe the pool which failed to import otherwise
         match Self::import(&args.name, &bdev).await {
-            Ok(pool) => Ok(pool),
-            Err(_) => Self::create(&args.name, &bdev).await,
+            Err(Error::Import {
+                err: Errno::EILSEQ,
+                ..
+            }) => Self::create(&args.name, &bdev).await,
+            result => result,
         }
     }
 
@@ -342,6 +380,8 @@ impl Lvs {
                 msg: format!("failed to export pool {}", pool),
             })?;
 
+        read_only::forget(self.0.as_ptr());
+        label::forget(self.0.as_ptr());
         info!("pool {} exported successfully", pool);
         bdev_destroy(&base_bdev.bdev_uri().unwrap())
             .await
@@ -387,6 +427,7 @@ impl Lvs {
                         PropValue::Shared(false) => {
                             debug!("{} not shared on disk", l.name())
                         }
+                        _ => {}
                     }
                 }
             }
@@ -421,6 +462,8 @@ impl Lvs {
                 msg: format!("failed to export pool {}", pool),
             })?;
 
+        read_only::forget(self.0.as_ptr());
+        label::forget(self.0.as_ptr());
         info!("pool {} destroyed successfully", pool);
 
         bdev_destroy(&base_bdev.bdev_uri().unwrap())
@@ -437,6 +480,35 @@ impl Lvs {
         Ok(())
     }
 
+    /// Mark the pool read-only as its base device no longer accepts writes.
+    /// From then on writes to its lvols fail with a status which tells the
+    /// nexuses of the replicas that the pool is read-only. The pool remains
+    /// read-only until it is imported again.
+    pub async fn set_read_only(&self, reason: &str) {
+        if !read_only::set(self.0.as_ptr(), Some(reason.to_string())) {
+            return;
+        }
+
+        error!(
+            "pool {} on {} is read-only: {}",
+            self.name(),
+            self.base_bdev().name(),
+            reason
+        );
+        events::pool_degraded(self.name(), reason);
+
+        let lvols: Vec<Lvol> =
+            self.lvols().map(|l| l.collect()).unwrap_or_default();
+        for l in lvols {
+            l.write_protect().await;
+        }
+    }
+
+    /// returns why the pool is read-only, None if it is writable
+    pub fn read_only_reason(&self) -> Option<String> {
+        read_only::reason(self.0.as_ptr())
+    }
+
     /// return an iterator that filters out all bdevs that patch the pool
     /// signature
     pub fn lvols(&self) -> Option<impl Iterator<Item = Lvol>> {
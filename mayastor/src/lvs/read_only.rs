@@ -0,0 +1,182 @@
+//! Pools whose base device has become read-only.
+//!
+//! A device may stop accepting writes, i.e. when its media has worn out or
+//! its controller locked it. Writes to the replicas of such a pool would fail
+//! with generic errors, which a nexus can not tell apart from a flaky
+//! connection. Instead, the writes are failed with the NVMe status
+//! "Namespace is Write Protected", which is passed on as is to the nexuses
+//! of the replicas over nvmf, so they can retire the child for good.
+//!
+//! The writes are failed by intercepting the IO of the lvol bdevs, as is
+//! done for their flush policy, see bdev::interpose.
+//!
+//! The base devices of the pools are checked periodically: NVMe devices by
+//! the critical warning of their SMART log, block devices used through aio
+//! or uring by their read-only flag in sysfs. Pools whose device is wearing
+//! out are marked read-only as well, see the `health` module.
+
+use std::{
+    collections::HashMap,
+    os::raw::c_void,
+    path::Path,
+    sync::RwLock,
+    time::Duration,
+};
+
+use futures::channel::oneshot;
+use once_cell::sync::Lazy;
+
+use spdk_sys::{
+    bdev_nvme_get_ctrlr,
+    spdk_lvol_store,
+    spdk_nvme_cpl,
+    spdk_nvme_ctrlr_cmd_get_log_page,
+};
+
+use crate::{
+    core::Bdev,
+    ffihelper::cb_arg,
+    lvs::{health, Lvs},
+};
+
+/// how often the base devices of the pools are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// the SMART / health information log page and its critical warning bit
+/// which is set once the media has been placed in read only mode, from
+/// nvme_spec.h
+const NVME_LOG_HEALTH_INFORMATION: u8 = 0x02;
+const NVME_HEALTH_INFORMATION_SIZE: usize = 512;
+const NVME_CRITICAL_WARNING_READ_ONLY: u8 = 1 << 3;
+const NVME_GLOBAL_NS_TAG: u32 = 0xffff_ffff;
+
+/// NVMe generic command status of a write to a write protected namespace,
+/// from nvme_spec.h
+pub(crate) const NVME_SC_NAMESPACE_IS_WRITE_PROTECTED: i32 = 0x20;
+
+/// the reason per read-only pool, keyed by the address of the lvol store
+static READ_ONLY: Lazy<RwLock<HashMap<usize, String>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// mark the pool read-only for the given reason, or writable again if none,
+/// returns true if this changes the state of the pool
+pub(crate) fn set(lvs: *mut spdk_lvol_store, reason: Option<String>) -> bool {
+    let mut pools = READ_ONLY.write().unwrap();
+    match reason {
+        Some(reason) => pools.insert(lvs as usize, reason).is_none(),
+        None => pools.remove(&(lvs as usize)).is_some(),
+    }
+}
+
+/// remove the pool from the registry as it is about to go away
+pub(crate) fn forget(lvs: *mut spdk_lvol_store) {
+    set(lvs, None);
+}
+
+/// returns why the pool is read-only, None if it is writable
+pub(crate) fn reason(lvs: *mut spdk_lvol_store) -> Option<String> {
+    READ_ONLY.read().unwrap().get(&(lvs as usize)).cloned()
+}
+
+/// completion of the get log page command, whether it succeeded
+extern "C" fn log_page_cb(arg: *mut c_void, cpl: *const spdk_nvme_cpl) {
+    let sender = unsafe { Box::from_raw(arg as *mut oneshot::Sender<bool>) };
+    let status = unsafe { (*cpl).__bindgen_anon_1.status };
+    let _ = sender.send(status.sct() == 0 && status.sc() == 0);
+}
+
+/// read the SMART / health information log page of an NVMe device
+pub(crate) async fn nvme_health_log(bdev: &Bdev) -> Option<Vec<u8>> {
+    let ctrlr = unsafe { bdev_nvme_get_ctrlr(bdev.as_ptr()) };
+    if ctrlr.is_null() {
+        return None;
+    }
+
+    let mut page = vec![0u8; NVME_HEALTH_INFORMATION_SIZE];
+    let (s, r) = oneshot::channel::<bool>();
+    let rc = unsafe {
+        spdk_nvme_ctrlr_cmd_get_log_page(
+            ctrlr,
+            NVME_LOG_HEALTH_INFORMATION,
+            NVME_GLOBAL_NS_TAG,
+            page.as_mut_ptr() as *mut c_void,
+            NVME_HEALTH_INFORMATION_SIZE as u32,
+            0,
+            Some(log_page_cb),
+            cb_arg(s),
+        )
+    };
+
+    if rc != 0 || !r.await.unwrap_or(false) {
+        warn!("failed to read the health log page of {}", bdev.name());
+        return None;
+    }
+    Some(page)
+}
+
+/// read the critical warning of the health log page of an NVMe device
+async fn nvme_read_only(bdev: &Bdev) -> Option<String> {
+    let page = nvme_health_log(bdev).await?;
+    if page[0] & NVME_CRITICAL_WARNING_READ_ONLY != 0 {
+        Some("NVMe media has been placed in read-only mode".to_string())
+    } else {
+        None
+    }
+}
+
+/// read the read-only flag of a block device, the name of an aio or uring
+/// bdev is the path of its device
+fn block_read_only(bdev: &Bdev) -> Option<String> {
+    let path = std::fs::canonicalize(bdev.name()).ok()?;
+    if !path.starts_with("/dev") {
+        return None;
+    }
+
+    let dir = Path::new("/sys/class/block").join(path.file_name()?);
+    match sysfs::parse_value::<u32>(&dir, "ro") {
+        Ok(1) => Some(format!("block device {} is read-only", path.display())),
+        _ => None,
+    }
+}
+
+/// returns why the base device of a pool no longer accepts writes, None if
+/// it does or this is not known for its type of device
+async fn device_read_only(bdev: &Bdev) -> Option<String> {
+    match bdev.driver().as_str() {
+        "nvme" => nvme_read_only(bdev).await,
+        "aio" | "uring" => block_read_only(bdev),
+        _ => None,
+    }
+}
+
+/// check the base device of every pool and mark the pool read-only if it
+/// no longer accepts writes
+pub(crate) async fn check_pools() {
+    let names = Lvs::iter()
+        .map(|lvs| lvs.name().to_string())
+        .collect::<Vec<_>>();
+
+    for name in names {
+        let base_bdev = match Lvs::lookup(&name) {
+            Some(lvs) if lvs.read_only_reason().is_none() => lvs.base_bdev(),
+            _ => continue,
+        };
+
+        if let Some(reason) = device_read_only(&base_bdev).await {
+            // the pool may have been destroyed meanwhile
+            if let Some(lvs) = Lvs::lookup(&name) {
+                lvs.set_read_only(&reason).await;
+            }
+        }
+    }
+}
+
+/// check the pools periodically, runs on the master reactor for as long as
+/// mayastor does
+pub(crate) async fn monitor() {
+    loop {
+        check_pools().await;
+        health::check_pools().await;
+        tokio::time::delay_for(CHECK_INTERVAL).await;
+    }
+}
@@ -1,8 +1,9 @@
 //! NATS message bus connecting mayastor to control plane (moac).
 //!
-//! It is designed to make sending events to control plane easy in the future.
-//! That's the reason for global sender protected by the mutex, that normally
-//! would not be needed and currently is used only to terminate the message bus.
+//! Besides the periodic register messages, the messages queued by
+//! message_bus_publish() are published on the bus, i.e. the events of the
+//! event bus subsystem. The global sender protected by the mutex is the end
+//! of the queue, dropping it terminates the message bus.
 
 use std::{
     env,
@@ -25,11 +26,16 @@ use tokio_nats::{
     NatsConfigBuilder,
 };
 
+use crate::subsys::Config;
+
 /// Mayastor sends registration messages in this interval (kind of heart-beat)
 const HB_INTERVAL: u64 = 10;
 
+/// A message to publish: the subject and the payload.
+type Message = (String, Vec<u8>);
+
 /// The end of channel used to send messages to or terminate the NATS client.
-static SENDER: Lazy<Mutex<Option<mpsc::Sender<()>>>> =
+static SENDER: Lazy<Mutex<Option<mpsc::Sender<Message>>>> =
     Lazy::new(|| Mutex::new(None));
 
 /// Errors for pool operations.
@@ -71,6 +77,15 @@ enum Error {
     QueueRegister { cause: TokioNatsError },
     #[snafu(display("Failed to queue deregister request: {:?}", cause))]
     QueueDeregister { cause: TokioNatsError },
+    #[snafu(display(
+        "Failed to queue message for subject {}: {:?}",
+        subject,
+        cause
+    ))]
+    QueuePublish {
+        cause: TokioNatsError,
+        subject: String,
+    },
 }
 
 /// Register message payload
@@ -134,11 +149,12 @@ impl MessageBus {
         }
     }
 
-    /// Connect to the server and start emitting periodic register messages.
-    /// Runs until the sender side of mpsc channel is closed.
+    /// Connect to the server and start emitting periodic register messages,
+    /// publishing the messages received in between. Runs until the sender
+    /// side of mpsc channel is closed.
     pub async fn run(
         &mut self,
-        mut receiver: mpsc::Receiver<()>,
+        mut receiver: mpsc::Receiver<Message>,
     ) -> Result<(), Error> {
         assert!(self.client.is_none());
 
@@ -160,22 +176,31 @@ impl MessageBus {
             "Registering '{}' and grpc server {} ...",
             self.node, self.grpc_endpoint
         );
-        loop {
+        'outer: loop {
             if let Err(err) = self.register().await {
                 error!("Registration failed: {:?}", err);
             };
-            let _res = select! {
-                () = delay_for(self.hb_interval).fuse() => (),
-                msg = receiver.next() => {
-                    match msg {
-                        Some(_) => warn!("Messages have not been implemented yet"),
-                        None => {
-                            info!("Terminating the NATS client");
-                            break;
+            let mut heartbeat = delay_for(self.hb_interval).fuse();
+            loop {
+                select! {
+                    () = heartbeat => break,
+                    msg = receiver.next() => {
+                        match msg {
+                            Some((subject, payload)) => {
+                                if let Err(err) =
+                                    self.publish(&subject, payload).await
+                                {
+                                    error!("{}", err);
+                                }
+                            }
+                            None => {
+                                info!("Terminating the NATS client");
+                                break 'outer;
+                            }
                         }
                     }
-                }
-            };
+                };
+            }
         }
 
         if let Err(err) = self.deregister().await {
@@ -233,6 +258,25 @@ impl MessageBus {
         Ok(())
     }
 
+    /// Publish a message on the subject.
+    async fn publish(
+        &mut self,
+        subject: &str,
+        payload: Vec<u8>,
+    ) -> Result<(), Error> {
+        match &mut self.client {
+            Some(client) => {
+                client.publish(subject, payload).await.map_err(|cause| {
+                    Error::QueuePublish {
+                        cause,
+                        subject: subject.to_owned(),
+                    }
+                })
+            }
+            None => Err(Error::NotStarted {}),
+        }
+    }
+
     /// Send a deregister message to the NATS server.
     async fn deregister(&mut self) -> Result<(), Error> {
         let payload = DeregisterArgs {
@@ -262,7 +306,8 @@ pub async fn message_bus_run(
     node: &str,
     grpc_endpoint: &str,
 ) -> Result<(), ()> {
-    let (sender, receiver) = mpsc::channel::<()>(1);
+    let queue_size = Config::get().event_bus.queue_size.max(1);
+    let (sender, receiver) = mpsc::channel::<Message>(queue_size);
     {
         let mut sender_maybe = SENDER.lock().unwrap();
         if sender_maybe.is_some() {
@@ -285,3 +330,13 @@ pub fn message_bus_stop() {
     // this will free the sender and unblock the receiver waiting for a message
     let _sender_maybe = SENDER.lock().unwrap().take();
 }
+
+/// Queue a message to be published on the subject, returns false if the
+/// message bus has not been started or its queue is full, in which case the
+/// message is dropped.
+pub(crate) fn message_bus_publish(subject: &str, payload: Vec<u8>) -> bool {
+    match SENDER.lock().unwrap().as_mut() {
+        Some(sender) => sender.try_send((subject.to_owned(), payload)).is_ok(),
+        None => false,
+    }
+}
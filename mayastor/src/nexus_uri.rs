@@ -5,7 +5,10 @@ use nix::errno::Errno;
 use snafu::Snafu;
 use url::ParseError;
 
-use crate::{bdev::Uri, core::Bdev};
+use crate::{
+    bdev::Uri,
+    core::{tracker, Bdev},
+};
 
 // parse URI and bdev create/destroy errors common for all types of bdevs
 #[derive(Debug, Snafu, Clone)]
@@ -54,6 +57,14 @@ pub enum NexusBdevError {
         uri: String,
     },
     // Bdev create/destroy errors
+    #[snafu(display("Failed to resolve host {}: {}", host, message))]
+    ResolveHost { host: String, message: String },
+    #[snafu(display("Failed to resolve {} as {}: {}", uri, link, message))]
+    ResolveLink {
+        uri: String,
+        link: String,
+        message: String,
+    },
     #[snafu(display("bdev {} already exists", name))]
     BdevExists { name: String },
     #[snafu(display("bdev {} not found", name))]
@@ -66,6 +77,13 @@ pub enum NexusBdevError {
     DestroyBdev { source: Errno, name: String },
     #[snafu(display("Command canceled for bdev {}", name))]
     CancelBdev { source: Canceled, name: String },
+    #[snafu(display(
+        "Attaching {} exceeds the memory limit of nvme children: {} of {} bytes in use",
+        name,
+        used,
+        limit
+    ))]
+    MemoryLimit { name: String, used: u64, limit: u64 },
 }
 
 /// Parse URI and create bdev described in the URI.
@@ -76,7 +94,13 @@ pub async fn bdev_create(uri: &str) -> Result<String, NexusBdevError> {
 
 /// Parse URI and destroy bdev described in the URI.
 pub async fn bdev_destroy(uri: &str) -> Result<(), NexusBdevError> {
-    Uri::parse(uri)?.destroy().await
+    let device = Uri::parse(uri)?;
+    let name = device.get_name();
+    let result = device.destroy().await;
+    if result.is_err() {
+        tracker::warn_open_handles(&name).await;
+    }
+    result
 }
 
 pub fn bdev_get_name(uri: &str) -> Result<String, NexusBdevError> {
@@ -90,6 +114,7 @@ impl std::cmp::PartialEq<url::Url> for &Bdev {
                 self.driver()
                     == match uri.scheme() {
                         "nvmf" | "pcie" => "nvme",
+                        "partuuid" | "devlink" => "aio",
                         scheme => scheme,
                     }
             }
@@ -105,6 +130,7 @@ impl std::cmp::PartialEq<url::Url> for Bdev {
                 self.driver()
                     == match uri.scheme() {
                         "nvmf" | "pcie" => "nvme",
+                        "partuuid" | "devlink" => "aio",
                         scheme => scheme,
                     }
             }
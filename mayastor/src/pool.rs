@@ -24,20 +24,25 @@ use spdk_sys::{
     spdk_lvol_store,
     vbdev_get_lvol_store_by_name,
     vbdev_get_lvs_bdev_by_lvs,
+    vbdev_lvol_rename,
     vbdev_lvol_store_first,
     vbdev_lvol_store_next,
     vbdev_lvs_create,
     vbdev_lvs_destruct,
     vbdev_lvs_examine,
+    vbdev_lvs_rename,
+    vbdev_lvs_unload,
     LVS_CLEAR_WITH_NONE,
 };
 
 use crate::{
-    bdev::{util::uring, Uri},
-    core::{Bdev, Share},
+    bdev::{dev::link::link_uri, util::uring, Uri},
+    core::{Bdev, Share, Uuid},
     ffihelper::{cb_arg, done_cb},
+    lvs::{label, read_only, Error as LvsError, Lvs, PropName, PropValue},
     nexus_uri::{bdev_destroy, NexusBdevError},
     replica::ReplicaIter,
+    subsys::events,
 };
 
 /// Errors for pool operations.
@@ -75,8 +80,27 @@ pub enum Error {
     PoolGone { name: String },
     #[snafu(display("The device {} hosts another pool", name))]
     DeviceAlreadyUsed { name: String },
+    #[snafu(display(
+        "The pool {} on device {} belongs to {}, refusing to import it",
+        name,
+        disk,
+        owner
+    ))]
+    ForeignPool {
+        name: String,
+        disk: String,
+        owner: String,
+    },
+    #[snafu(display("Failed to label the pool {}: {}", name, reason))]
+    FailedLabel { name: String, reason: String },
     #[snafu(display("Failed to import the pool {} (errno={})", name, errno))]
     FailedImport { name: String, errno: i32 },
+    #[snafu(display(
+        "Failed to adopt the pool on {} (errno={})",
+        name,
+        errno
+    ))]
+    FailedAdopt { name: String, errno: i32 },
     #[snafu(display("Failed to unshare replica: {}", msg))]
     FailedUnshareReplica { msg: String },
     #[snafu(display("Failed to destroy pool {} (errno={})", name, errno))]
@@ -135,9 +159,18 @@ impl From<Error> for tonic::Status {
             Error::DeviceAlreadyUsed {
                 ..
             } => Self::unavailable(e.to_string()),
+            Error::ForeignPool {
+                ..
+            } => Self::failed_precondition(e.to_string()),
+            Error::FailedLabel {
+                ..
+            } => Self::internal(e.to_string()),
             Error::FailedImport {
                 ..
             } => Self::internal(e.to_string()),
+            Error::FailedAdopt {
+                ..
+            } => Self::internal(e.to_string()),
             Error::FailedUnshareReplica {
                 ..
             } => Self::internal(e.to_string()),
@@ -225,12 +258,12 @@ impl Pool {
         }
     }
 
-    /// Look up existing pool by name
+    /// Look up existing pool by name, or by uuid
     pub fn lookup(name: &str) -> Option<Self> {
-        let name = CString::new(name).unwrap();
-        let lvs_ptr = unsafe { vbdev_get_lvol_store_by_name(name.as_ptr()) };
+        let c_name = CString::new(name).unwrap();
+        let lvs_ptr = unsafe { vbdev_get_lvol_store_by_name(c_name.as_ptr()) };
         if lvs_ptr.is_null() {
-            return None;
+            return PoolsIter::new().find(|p| p.get_uuid() == name);
         }
         let lvs_bdev_ptr = unsafe { vbdev_get_lvs_bdev_by_lvs(lvs_ptr) };
         if lvs_bdev_ptr.is_null() {
@@ -251,6 +284,11 @@ impl Pool {
         }
     }
 
+    /// Get uuid of the pool, which stays the same when it is renamed.
+    pub fn get_uuid(&self) -> String {
+        Uuid::from_bytes(unsafe { (*self.lvs_ptr).uuid.u.raw }).to_string()
+    }
+
     /// Get base bdev for the pool (in our case AIO or uring bdev).
     pub fn get_base_bdev(&self) -> Bdev {
         let base_bdev_ptr = unsafe { (*self.lvs_bdev_ptr).bdev };
@@ -276,6 +314,12 @@ impl Pool {
             spdk_bs_free_cluster_count(lvs.blobstore) * cluster_size
         }
     }
+
+    /// Get why the pool is read-only, None if it is writable.
+    pub fn read_only_reason(&self) -> Option<String> {
+        read_only::reason(self.lvs_ptr)
+    }
+
     /// Return raw pointer to spdk lvol store structure
     pub fn as_ptr(&self) -> *mut spdk_lvol_store {
         self.lvs_ptr
@@ -324,6 +368,12 @@ impl Pool {
 
         match Pool::lookup(&name) {
             Some(pool) => {
+                label::write_new(pool.lvs_ptr).await.map_err(|e| {
+                    Error::FailedLabel {
+                        name: String::from(name),
+                        reason: e.to_string(),
+                    }
+                })?;
                 info!("The pool {} has been created", name);
                 Ok(pool)
             }
@@ -344,6 +394,13 @@ impl Pool {
             }
         };
 
+        // examining a bdev which is claimed by a pool already would hang
+        if base_bdev.is_claimed() {
+            return Err(Error::DeviceAlreadyUsed {
+                name: String::from(disk),
+            });
+        }
+
         let (sender, receiver) = oneshot::channel::<i32>();
 
         debug!("Trying to import pool {}", name);
@@ -360,6 +417,8 @@ impl Pool {
             // could be that a pool with a different name was imported
             match Pool::lookup(&name) {
                 Some(pool) => {
+                    let pool = pool.check_label(disk).await?;
+                    pool.alias_origin_names().await;
                     info!("The pool {} has been imported", name);
                     Ok(pool)
                 }
@@ -375,6 +434,223 @@ impl Pool {
         }
     }
 
+    /// Adopt the lvol store on a disk which has been created by other tools
+    /// than mayastor, i.e. by hand with the SPDK RPCs. The store is renamed to
+    /// the name of the pool, and the lvols which are not named by a UUID, as
+    /// replicas are, are renamed to the UUID of their blob. Their former name
+    /// is kept in the metadata of the lvol and added as an alias of its bdev
+    /// on every import, so references by that name keep working. The store
+    /// keeps its cluster size, no data is copied.
+    pub async fn adopt<'a>(name: &'a str, disk: &'a str) -> Result<Pool> {
+        let base_bdev = match Bdev::lookup_by_name(disk) {
+            Some(bdev) => bdev,
+            None => {
+                return Err(Error::UnknownBdev {
+                    name: String::from(disk),
+                });
+            }
+        };
+
+        let lvs_ptr = match Self::on_disk(&base_bdev).await {
+            Some(pool) => pool.lvs_ptr,
+            None => {
+                return Err(Error::FailedAdopt {
+                    name: String::from(disk),
+                    errno: libc::ENOENT,
+                })
+            }
+        };
+        let pool = Lvs::from(lvs_ptr);
+
+        if pool.name() != name {
+            if Pool::lookup(name).is_some() {
+                return Err(Error::AlreadyExists {
+                    name: String::from(name),
+                });
+            }
+
+            info!("Renaming the pool {} on {} to {}", pool.name(), disk, name);
+            let new_name = CString::new(name).unwrap();
+            let (sender, receiver) = oneshot::channel::<i32>();
+            unsafe {
+                vbdev_lvs_rename(
+                    lvs_ptr,
+                    new_name.as_ptr(),
+                    Some(done_cb),
+                    cb_arg(sender),
+                );
+            }
+            let errno = receiver.await.expect("Cancellation is not supported");
+            if errno != 0 {
+                return Err(Error::FailedAdopt {
+                    name: String::from(disk),
+                    errno,
+                });
+            }
+        }
+
+        let mut renamed = 0;
+        for lvol in pool.lvols().into_iter().flatten().collect::<Vec<_>>() {
+            let origin = lvol.name();
+            if Uuid::parse_str(&origin).is_ok() {
+                continue;
+            }
+
+            let uuid = lvol.uuid();
+            info!(
+                "Renaming the lvol {} of the pool {} to {}",
+                origin, name, uuid
+            );
+
+            lvol.set(PropValue::OriginName(origin.clone()))
+                .await
+                .map_err(|_| Error::FailedAdopt {
+                    name: String::from(disk),
+                    errno: libc::EIO,
+                })?;
+
+            let new_name = CString::new(uuid).unwrap();
+            let (sender, receiver) = oneshot::channel::<i32>();
+            unsafe {
+                vbdev_lvol_rename(
+                    lvol.0.as_ptr(),
+                    new_name.as_ptr(),
+                    Some(done_cb),
+                    cb_arg(sender),
+                );
+            }
+            let errno = receiver.await.expect("Cancellation is not supported");
+            if errno != 0 {
+                return Err(Error::FailedAdopt {
+                    name: String::from(disk),
+                    errno,
+                });
+            }
+            renamed += 1;
+        }
+
+        // the bdevs of the lvols are named when the store is loaded, reload
+        // it for the new names to take effect
+        let pool = if renamed > 0 {
+            let (sender, receiver) = oneshot::channel::<i32>();
+            unsafe {
+                vbdev_lvs_unload(lvs_ptr, Some(done_cb), cb_arg(sender));
+            }
+            let errno = receiver.await.expect("Cancellation is not supported");
+            if errno != 0 {
+                return Err(Error::FailedAdopt {
+                    name: String::from(disk),
+                    errno,
+                });
+            }
+            Pool::import(name, disk).await?
+        } else {
+            let pool = Pool::lookup(name).ok_or_else(|| Error::PoolGone {
+                name: String::from(name),
+            })?;
+            let pool = pool.check_label(disk).await?;
+            pool.alias_origin_names().await;
+            pool
+        };
+
+        info!(
+            "The pool {} has been adopted with {} lvols renamed",
+            name, renamed
+        );
+        Ok(pool)
+    }
+
+    /// Check the label of the pool which has just been loaded from the disk.
+    /// A pool which belongs to another cluster or node is unloaded again. A
+    /// pool whose label could not be read stays loaded, and its label is
+    /// checked again when the pool is created next.
+    async fn check_label(self, disk: &str) -> Result<Pool> {
+        let name = self.get_name().to_string();
+        match label::check(self.lvs_ptr, &name).await {
+            Ok(_) => Ok(self),
+            Err(LvsError::Foreign {
+                owner, ..
+            }) => {
+                error!(
+                    "The pool {} on {} belongs to {}, unloading it",
+                    name, disk, owner
+                );
+                let (sender, receiver) = oneshot::channel::<i32>();
+                unsafe {
+                    vbdev_lvs_unload(
+                        self.lvs_ptr,
+                        Some(done_cb),
+                        cb_arg(sender),
+                    );
+                }
+                receiver.await.expect("Cancellation is not supported");
+                Err(Error::ForeignPool {
+                    name,
+                    disk: String::from(disk),
+                    owner,
+                })
+            }
+            Err(e) => Err(Error::FailedLabel {
+                name,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    /// the pool, once its label has been checked if that failed on import
+    async fn check_label_again(self, disk: &str) -> Result<Pool> {
+        if label::get(self.lvs_ptr).is_some() {
+            return Ok(self);
+        }
+        self.check_label(disk).await
+    }
+
+    /// the pool on a disk, which is examined if it has not been imported yet
+    async fn on_disk(base_bdev: &Bdev) -> Option<Pool> {
+        let find = || {
+            PoolsIter::new()
+                .find(|p| p.get_base_bdev().name() == base_bdev.name())
+        };
+
+        if let Some(pool) = find() {
+            return Some(pool);
+        }
+
+        let (sender, receiver) = oneshot::channel::<i32>();
+        unsafe {
+            vbdev_lvs_examine(
+                base_bdev.as_ptr(),
+                Some(pool_done_cb),
+                cb_arg(sender),
+            );
+        }
+        if receiver.await.expect("Cancellation is not supported") != 0 {
+            return None;
+        }
+        find()
+    }
+
+    /// add the names the lvols had before the pool was adopted as aliases of
+    /// their bdevs
+    async fn alias_origin_names(&self) {
+        let lvols = match Lvs::from(self.lvs_ptr).lvols() {
+            Some(lvols) => lvols.collect::<Vec<_>>(),
+            None => return,
+        };
+
+        for lvol in lvols {
+            if let Ok(PropValue::OriginName(origin)) =
+                lvol.get(PropName::OriginName).await
+            {
+                let bdev = lvol.as_bdev();
+                if !bdev.aliases().contains(&origin) && !bdev.add_alias(&origin)
+                {
+                    warn!("Failed to add alias {} to {}", origin, bdev.name());
+                }
+            }
+        }
+    }
+
     /// Destroy the pool
     pub async fn destroy(self) -> Result<()> {
         let name = self.get_name().to_string();
@@ -406,6 +682,9 @@ impl Pool {
                 errno: lvs_errno,
             });
         }
+        read_only::forget(self.lvs_ptr);
+        label::forget(self.lvs_ptr);
+        events::pool_destroyed(&name);
 
         // we will destroy base bdev now
         let base_bdev = match Bdev::lookup_by_name(&base_bdev_name) {
@@ -514,17 +793,27 @@ impl Iterator for PoolsIter {
 
 impl From<Pool> for rpc::Pool {
     fn from(pool: Pool) -> Self {
+        let reason = pool.read_only_reason();
+        let base_bdev = pool.get_base_bdev();
         rpc::Pool {
             name: pool.get_name().to_owned(),
-            disks: vec![
-                pool.get_base_bdev().driver()
-                    + "://"
-                    + &pool.get_base_bdev().name(),
-            ],
-            // TODO: figure out how to detect state of pool
-            state: rpc::PoolState::PoolOnline as i32,
+            disks: vec![link_uri(&base_bdev).unwrap_or_else(|| {
+                base_bdev.driver() + "://" + &base_bdev.name()
+            })],
+            state: match reason {
+                Some(_) => rpc::PoolState::PoolReadOnly,
+                None => rpc::PoolState::PoolOnline,
+            } as i32,
             capacity: pool.get_capacity(),
             used: pool.get_capacity() - pool.get_free(),
+            state_reason: reason.unwrap_or_default(),
+            label: label::get(pool.lvs_ptr).map(|l| rpc::PoolLabel {
+                uuid: l.uuid,
+                created_at: l.created_at,
+                cluster_id: l.cluster_id,
+                node: l.node,
+            }),
+            uuid: pool.get_uuid(),
         }
     }
 }
@@ -539,7 +828,7 @@ async fn create_pool_legacy(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
 
     if let Some(pool) = Pool::lookup(&args.name) {
         return if pool.get_base_bdev().name() == args.disks[0] {
-            Ok(pool.into())
+            Ok(pool.check_label_again(&args.disks[0]).await?.into())
         } else {
             Err(Error::AlreadyExists {
                 name: args.name,
@@ -576,13 +865,46 @@ async fn create_pool_legacy(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
     };
     create_base_bdev(disk, block_size, io_if)?;
 
-    if let Ok(pool) = Pool::import(&args.name, disk).await {
-        return Ok(pool.into());
-    };
-    let pool = Pool::create(&args.name, disk).await?;
+    let pool = import_or_create(&args.name, disk, args.adopt).await?;
     Ok(pool.into())
 }
 
+/// Import the pool on the disk, or adopt it if asked to. A new pool is only
+/// created on a disk which has no lvol store at all, any other failure to
+/// import is returned, as creating a pool would wipe the one on the disk.
+async fn import_or_create(name: &str, disk: &str, adopt: bool) -> Result<Pool> {
+    match Pool::import(name, disk).await {
+        Ok(pool) => return Ok(pool),
+        Err(Error::FailedImport {
+            errno, ..
+        }) if errno.abs() == libc::EILSEQ => {}
+        // the lvol store on the disk is named differently
+        Err(Error::DeviceAlreadyUsed {
+            ..
+        }) if adopt => {}
+        Err(e) => return Err(e),
+    }
+    if adopt {
+        if let Some(pool) = adopt_pool(name, disk).await? {
+            return Ok(pool);
+        }
+    }
+    Pool::create(name, disk).await
+}
+
+/// adopt the pool created by other tools on the disk, none if the disk has no
+/// pool on it and a new one can be created
+async fn adopt_pool(name: &str, disk: &str) -> Result<Option<Pool>> {
+    match Pool::adopt(name, disk).await {
+        Ok(pool) => Ok(Some(pool)),
+        Err(Error::FailedAdopt {
+            errno: libc::ENOENT,
+            ..
+        }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 fn is_uri_scheme(disks: &[String]) -> bool {
     !disks.iter().any(|d| Url::parse(d).is_err())
 }
@@ -601,7 +923,7 @@ async fn create_pool_uri(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
 
     if let Some(pool) = Pool::lookup(&args.name) {
         return if pool.get_base_bdev().name() == parsed.get_name() {
-            Ok(pool.into())
+            Ok(pool.check_label_again(&args.disks[0]).await?.into())
         } else {
             Err(Error::AlreadyExists {
                 name: args.name,
@@ -622,22 +944,60 @@ async fn create_pool_uri(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
         Ok(name) => Ok(name),
     }?;
 
-    if let Ok(pool) = Pool::import(&args.name, &bdev).await {
-        return Ok(pool.into());
-    }
-
-    let pool = Pool::create(&args.name, &bdev).await?;
+    let pool = import_or_create(&args.name, &bdev, args.adopt).await?;
     Ok(pool.into())
 }
 
 pub async fn create_pool(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
-    if is_uri_scheme(&args.disks) {
+    let pool = if is_uri_scheme(&args.disks) {
         debug!("pool creation with URI scheme");
-        create_pool_uri(args).await
+        create_pool_uri(args).await?
     } else {
         debug!("pool creation with legacy scheme");
-        create_pool_legacy(args).await
+        create_pool_legacy(args).await?
+    };
+    events::pool_created(&pool.name, &pool.disks);
+    Ok(pool)
+}
+
+/// Collect the health of the base devices of the given pool or all pools.
+pub(crate) async fn disk_health(
+    args: rpc::GetDiskHealthRequest,
+) -> Result<rpc::GetDiskHealthReply> {
+    let pools = if args.pool.is_empty() {
+        Lvs::iter().collect::<Vec<_>>()
+    } else {
+        vec![Lvs::lookup(&args.pool).ok_or(Error::UnknownPool {
+            name: args.pool.clone(),
+        })?]
+    };
+
+    let mut disks = Vec::new();
+    for lvs in pools {
+        if let Some(health) = lvs.disk_health().await {
+            let bdev = lvs.base_bdev();
+            disks.push(rpc::DiskHealth {
+                pool: lvs.name().to_string(),
+                disk: bdev.driver() + "://" + &bdev.name(),
+                source: health.source,
+                critical_warning: health.critical_warning as u32,
+                temperature: health.temperature as u32,
+                available_spare: health.available_spare.unwrap_or(0) as u32,
+                available_spare_threshold: health
+                    .available_spare_threshold
+                    .unwrap_or(0)
+                    as u32,
+                percentage_used: health.percentage_used.unwrap_or(0) as u32,
+                media_errors: health.media_errors,
+                power_on_hours: health.power_on_hours,
+                unsafe_shutdowns: health.unsafe_shutdowns,
+            });
+        }
     }
+
+    Ok(rpc::GetDiskHealthReply {
+        disks,
+    })
 }
 
 pub(crate) async fn destroy_pool(args: rpc::DestroyPoolRequest) -> Result<()> {
@@ -0,0 +1,269 @@
+//! Probe of the data path to a replica, which is what a nexus child would
+//! use. Reads of a scratch region at the start of the replica are issued for
+//! a while at each of several queue depths, which gives the throughput and
+//! latency achievable over the network. Comparing it with that of the disk
+//! of the replica tells whether a slow rebuild is due to the one or the other.
+//!
+//! Nothing is written, so the replica may be in use by a nexus meanwhile,
+//! in which case its bdev is shared with the probe.
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use rpc::mayastor::{
+    ProbeReplicaPathReply,
+    ProbeReplicaPathRequest,
+    QueueDepthProfile,
+};
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    core::{Bdev, BdevHandle, CoreError, DmaError},
+    nexus_uri::{bdev_create, bdev_destroy, bdev_get_name, NexusBdevError},
+};
+
+/// queue depths probed if none are given
+const DEFAULT_QUEUE_DEPTHS: [u32; 4] = [1, 4, 16, 32];
+/// size of a read if none is given
+const DEFAULT_IO_SIZE: u32 = 64 * 1024;
+/// duration of the probe at each depth if none is given
+const DEFAULT_DURATION: Duration = Duration::from_secs(1);
+/// limits which keep a probe from hogging the replica
+const MAX_QUEUE_DEPTH: u32 = 128;
+const MAX_IO_SIZE: u32 = 1024 * 1024;
+const MAX_DURATION: Duration = Duration::from_secs(60);
+/// size of the region at the start of the replica which is read
+const SCRATCH_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid probe of replica {}: {}", uri, msg))]
+    InvalidArgs { uri: String, msg: String },
+    #[snafu(display("Failed to create bdev for replica {}", uri))]
+    CreateBdev { source: NexusBdevError, uri: String },
+    #[snafu(display("Failed to open bdev {}", name))]
+    OpenBdev { source: CoreError, name: String },
+    #[snafu(display("Failed to allocate read buffer"))]
+    AllocBuffer { source: DmaError },
+    #[snafu(display("Failed to read from replica {}", uri))]
+    ReadReplica { source: CoreError, uri: String },
+}
+
+impl From<Error> for tonic::Status {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::InvalidArgs {
+                ..
+            } => Self::invalid_argument(e.to_string()),
+            Error::CreateBdev {
+                source, ..
+            } => Self::from(source),
+            e => Self::internal(e.to_string()),
+        }
+    }
+}
+
+/// the arguments of a probe with the defaults filled in
+struct Probe {
+    uri: String,
+    duration: Duration,
+    queue_depths: Vec<u32>,
+    io_size: u32,
+}
+
+impl Probe {
+    fn new(args: ProbeReplicaPathRequest) -> Result<Self, Error> {
+        let invalid = |msg: &str| Error::InvalidArgs {
+            uri: args.uri.clone(),
+            msg: msg.to_string(),
+        };
+
+        let duration = match args.duration_ms {
+            0 => DEFAULT_DURATION,
+            ms => Duration::from_millis(ms),
+        };
+        if duration > MAX_DURATION {
+            return Err(invalid("duration exceeds 60s"));
+        }
+
+        let queue_depths = if args.queue_depths.is_empty() {
+            DEFAULT_QUEUE_DEPTHS.to_vec()
+        } else {
+            args.queue_depths.clone()
+        };
+        if queue_depths.iter().any(|&d| d == 0 || d > MAX_QUEUE_DEPTH) {
+            return Err(invalid("queue depth must be between 1 and 128"));
+        }
+
+        let io_size = match args.io_size {
+            0 => DEFAULT_IO_SIZE,
+            size => size,
+        };
+        if io_size > MAX_IO_SIZE {
+            return Err(invalid("io size exceeds 1MiB"));
+        }
+
+        Ok(Self {
+            uri: args.uri,
+            duration,
+            queue_depths,
+            io_size,
+        })
+    }
+
+    /// Issue reads of the scratch region with `depth` of them in flight,
+    /// until the duration of the probe has passed.
+    async fn run(
+        &self,
+        handle: &BdevHandle,
+        region: u64,
+        depth: u32,
+    ) -> Result<QueueDepthProfile, Error> {
+        let next = Cell::new(0u64);
+        let start = Instant::now();
+        let deadline = start + self.duration;
+
+        let workers = (0 .. depth).map(|_| {
+            let next = &next;
+            async move {
+                let mut buf = handle
+                    .dma_malloc(self.io_size as usize)
+                    .context(AllocBuffer {})?;
+                let mut reads = 0u64;
+                let mut total = Duration::default();
+                let mut max = Duration::default();
+
+                while Instant::now() < deadline {
+                    let offset = next.get();
+                    next.set((offset + self.io_size as u64) % region);
+
+                    let issued = Instant::now();
+                    handle.read_at(offset, &mut buf).await.context(
+                        ReadReplica {
+                            uri: self.uri.clone(),
+                        },
+                    )?;
+                    let latency = issued.elapsed();
+
+                    reads += 1;
+                    total += latency;
+                    max = max.max(latency);
+                }
+                Ok((reads, total, max))
+            }
+        });
+
+        let mut profile = QueueDepthProfile {
+            queue_depth: depth,
+            ..Default::default()
+        };
+        let mut total = Duration::default();
+        let mut max = Duration::default();
+
+        for result in join_all(workers).await {
+            let (reads, latency, max_latency) = result?;
+            profile.reads += reads;
+            total += latency;
+            max = max.max(max_latency);
+        }
+
+        // the last reads may complete after the deadline
+        let elapsed = start.elapsed().as_secs_f64();
+        profile.bytes_per_sec =
+            ((profile.reads * self.io_size as u64) as f64 / elapsed) as u64;
+        if profile.reads > 0 {
+            profile.avg_latency_us =
+                (total.as_micros() / profile.reads as u128) as u64;
+        }
+        profile.max_latency_us = max.as_micros() as u64;
+        Ok(profile)
+    }
+
+    /// probe the replica through the given bdev at each of the queue depths
+    async fn run_all(
+        &self,
+        bdev: &Bdev,
+    ) -> Result<Vec<QueueDepthProfile>, Error> {
+        let handle =
+            BdevHandle::open(&bdev.name(), false, false).context(OpenBdev {
+                name: bdev.name(),
+            })?;
+
+        let block_len = bdev.block_len();
+        if self.io_size % block_len != 0 {
+            return Err(Error::InvalidArgs {
+                uri: self.uri.clone(),
+                msg: format!(
+                    "io size is not a multiple of the block size {}",
+                    block_len
+                ),
+            });
+        }
+
+        let size = bdev.size_in_bytes().min(SCRATCH_SIZE);
+        let region = size - size % self.io_size as u64;
+        if region == 0 {
+            return Err(Error::InvalidArgs {
+                uri: self.uri.clone(),
+                msg: "io size exceeds the size of the replica".to_string(),
+            });
+        }
+
+        let mut profiles = Vec::new();
+        for &depth in &self.queue_depths {
+            let profile = self.run(&handle, region, depth).await?;
+            debug!(
+                "Probed {} at depth {}: {} B/s, {}us on average",
+                self.uri, depth, profile.bytes_per_sec, profile.avg_latency_us
+            );
+            profiles.push(profile);
+        }
+        Ok(profiles)
+    }
+}
+
+/// Measure the throughput and latency of reads from a replica given by its
+/// URI. A bdev is created for the replica unless it already exists, i.e.
+/// when the replica is the child of a nexus, and destroyed again afterwards.
+pub async fn probe_replica_path(
+    args: ProbeReplicaPathRequest,
+) -> Result<ProbeReplicaPathReply, Error> {
+    let probe = Probe::new(args)?;
+    let uri = probe.uri.clone();
+
+    let name = bdev_get_name(&uri).context(CreateBdev {
+        uri: uri.clone(),
+    })?;
+    let existing = Bdev::lookup_by_name(&name);
+    let bdev = match existing.clone() {
+        Some(bdev) => bdev,
+        None => {
+            let name = bdev_create(&uri).await.context(CreateBdev {
+                uri: uri.clone(),
+            })?;
+            Bdev::lookup_by_name(&name).expect("created bdev not found")
+        }
+    };
+
+    info!(
+        "Probing {} for {:?} at queue depths {:?}",
+        uri, probe.duration, probe.queue_depths
+    );
+    let result = probe.run_all(&bdev).await;
+
+    if existing.is_none() {
+        if let Err(error) = bdev_destroy(&uri).await {
+            warn!(
+                "Failed to destroy bdev of probed replica {}: {}",
+                uri, error
+            );
+        }
+    }
+
+    Ok(ProbeReplicaPathReply {
+        io_size: probe.io_size,
+        profiles: result?,
+    })
+}
@@ -8,7 +8,7 @@ use snafu::Snafu;
 
 use crate::{
     bdev::VerboseError,
-    core::{BdevHandle, CoreError, Descriptor, DmaError},
+    core::{BdevHandle, CoreError, DmaError, IoSpread},
     nexus_uri::NexusBdevError,
 };
 
@@ -70,6 +70,8 @@ pub enum RebuildError {
         len: u64,
         source: nix::errno::Errno,
     },
+    #[snafu(display("The copy of the segment at blk {} was cancelled", blk))]
+    SegmentCancelled { blk: u64 },
     #[snafu(display("Failed to get bdev name from URI {}", uri))]
     BdevInvalidURI { source: NexusBdevError, uri: String },
 }
@@ -112,8 +114,9 @@ impl fmt::Display for RebuildState {
 pub struct RebuildJob {
     /// name of the nexus associated with the rebuild job
     pub nexus: String,
-    /// descriptor for the nexus
-    pub(super) nexus_descriptor: Descriptor,
+    /// spreads the copies over the cores, with the descriptors of the nexus,
+    /// the source and the destination
+    pub(super) spread: IoSpread,
     /// source URI of the healthy child to rebuild from
     pub source: String,
     pub(super) source_hdl: BdevHandle,
@@ -122,6 +125,9 @@ pub struct RebuildJob {
     pub(super) destination_hdl: BdevHandle,
     pub(super) block_size: u64,
     pub(super) range: std::ops::Range<u64>,
+    /// the parts of the range which are copied, sorted, all of it unless
+    /// the job is restricted
+    pub(super) regions: Vec<std::ops::Range<u64>>,
     pub(super) next: u64,
     pub(super) segment_size_blks: u64,
     pub(super) task_pool: RebuildTasks,
@@ -190,6 +196,27 @@ impl RebuildJob {
         Ok(Self::lookup(destination)?)
     }
 
+    /// Restrict the job, before it is started, to copy the given ranges of
+    /// blocks only, which are sorted and within the range of the job, as
+    /// only those may differ between the source and the destination
+    pub fn restrict(
+        &mut self,
+        regions: Vec<std::ops::Range<u64>>,
+    ) -> Result<(), RebuildError> {
+        let sorted = regions.windows(2).all(|w| w[0].end <= w[1].start);
+        if regions.is_empty()
+            || !sorted
+            || !regions.iter().all(|r| r.within(self.range.clone()))
+            || self.state() != RebuildState::Init
+        {
+            return Err(RebuildError::InvalidParameters {});
+        }
+
+        self.next = regions[0].start;
+        self.regions = regions;
+        Ok(())
+    }
+
     /// Lookup a rebuild job by its destination uri and return it
     pub fn lookup(name: &str) -> Result<&mut Self, RebuildError> {
         if let Some(job) = Self::get_instances().get_mut(name) {
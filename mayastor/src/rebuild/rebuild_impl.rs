@@ -1,6 +1,6 @@
 #![warn(missing_docs)]
 
-use std::{cell::UnsafeCell, collections::HashMap};
+use std::{cell::UnsafeCell, collections::HashMap, sync::Arc};
 
 use crossbeam::channel::unbounded;
 use futures::{
@@ -13,9 +13,8 @@ use snafu::ResultExt;
 use spdk_sys::{spdk_get_thread, SPDK_BDEV_LARGE_BUF_MAX_SIZE};
 
 use crate::{
-    bdev::VerboseError,
-    core::{Bdev, BdevHandle, DmaBuf, RangeContext, Reactors},
-    nexus_uri::bdev_get_name,
+    bdev::{nexus::nexus_local, VerboseError},
+    core::{Bdev, BdevHandle, DmaBuf, IoSpread, RangeContext, Reactors},
 };
 
 use super::rebuild_api::*;
@@ -48,9 +47,10 @@ pub const SEGMENT_SIZE: u64 = SPDK_BDEV_LARGE_BUF_MAX_SIZE as u64;
 
 /// Each rebuild task needs a unique buffer to read/write from source to target
 /// A mpsc channel is used to communicate with the management task
+/// The buffer is handed over to the copy of a segment while it is in flight
 #[derive(Debug)]
 struct RebuildTask {
-    buffer: DmaBuf,
+    buffer: Option<DmaBuf>,
     sender: mpsc::Sender<TaskResult>,
     error: Option<TaskResult>,
 }
@@ -109,22 +109,21 @@ impl RebuildJob {
         range: std::ops::Range<u64>,
         notify_fn: fn(String, String) -> (),
     ) -> Result<Self, RebuildError> {
-        let source_hdl = BdevHandle::open(
-            &bdev_get_name(source).context(BdevInvalidURI {
+        // either child may be a bdev shared by our own nvmf target
+        let source_hdl = nexus_local::open_handle(
+            &nexus_local::bdev_name(source).context(BdevInvalidURI {
                 uri: source.to_string(),
             })?,
             false,
-            false,
         )
         .context(NoBdevHandle {
             bdev: source,
         })?;
-        let destination_hdl = BdevHandle::open(
-            &bdev_get_name(destination).context(BdevInvalidURI {
+        let destination_hdl = nexus_local::open_handle(
+            &nexus_local::bdev_name(destination).context(BdevInvalidURI {
                 uri: source.to_string(),
             })?,
             true,
-            false,
         )
         .context(NoBdevHandle {
             bdev: destination,
@@ -157,7 +156,7 @@ impl RebuildJob {
                 .dma_malloc((segment_size_blks * block_size) as usize)
                 .context(NoCopyBuffer {})?;
             tasks.tasks.push(RebuildTask {
-                buffer: copy_buffer,
+                buffer: Some(copy_buffer),
                 sender: tasks.channel.0.clone(),
                 error: None,
             });
@@ -174,14 +173,22 @@ impl RebuildJob {
                 bdev: nexus.to_string(),
             })?;
 
+        // the segments are copied on all the cores, each with its own handles
+        let spread = IoSpread::new(vec![
+            Arc::new(nexus_descriptor),
+            (*source_hdl.desc).clone(),
+            (*destination_hdl.desc).clone(),
+        ]);
+
         Ok(Self {
             nexus,
-            nexus_descriptor,
+            spread,
             source,
             source_hdl,
             destination,
             destination_hdl,
             next: range.start,
+            regions: vec![range.clone()],
             range,
             block_size,
             segment_size_blks,
@@ -234,116 +241,6 @@ impl RebuildJob {
         self.reconcile();
     }
 
-    /// Return the size of the segment to be copied.
-    fn get_segment_size_blks(&self, blk: u64) -> u64 {
-        // Adjust the segments size for the last segment
-        if (blk + self.segment_size_blks) > self.range.end {
-            return self.range.end - blk;
-        }
-        self.segment_size_blks
-    }
-
-    /// Copies one segment worth of data from source into destination. During
-    /// this time the LBA range being copied is locked so that there cannot be
-    /// front end I/O to the same LBA range.
-    ///
-    /// # Safety
-    ///
-    /// The lock and unlock functions internally reference the RangeContext as a
-    /// raw pointer, so rust cannot correctly manage its lifetime. The
-    /// RangeContext MUST NOT be dropped until after the lock and unlock have
-    /// completed.
-    ///
-    /// The use of RangeContext here is safe because it is stored on the stack
-    /// for the duration of the calls to lock and unlock.
-    async fn locked_copy_one(
-        &mut self,
-        id: usize,
-        blk: u64,
-    ) -> Result<(), RebuildError> {
-        let len = self.get_segment_size_blks(blk);
-        // The nexus children have metadata and data partitions, whereas the
-        // nexus has a data partition only. Because we are locking the range on
-        // the nexus, we need to calculate the offset from the start of the data
-        // partition.
-        let mut ctx = RangeContext::new(blk - self.range.start, len);
-        let ch = self
-            .nexus_descriptor
-            .get_channel()
-            .expect("Failed to get nexus channel");
-
-        // Wait for LBA range to be locked.
-        // This prevents other I/Os being issued to this LBA range whilst it is
-        // being rebuilt.
-        self.nexus_descriptor
-            .lock_lba_range(&mut ctx, &ch)
-            .await
-            .context(RangeLockError {
-                blk,
-                len,
-            })?;
-
-        // Perform the copy
-        let result = self.copy_one(id, blk).await;
-
-        // Wait for the LBA range to be unlocked.
-        // This allows others I/Os to be issued to this LBA range once again.
-        self.nexus_descriptor
-            .unlock_lba_range(&mut ctx, &ch)
-            .await
-            .context(RangeUnLockError {
-                blk,
-                len,
-            })?;
-
-        result
-    }
-
-    /// Copies one segment worth of data from source into destination.
-    async fn copy_one(
-        &mut self,
-        id: usize,
-        blk: u64,
-    ) -> Result<(), RebuildError> {
-        let mut copy_buffer: DmaBuf;
-
-        let copy_buffer = if self.get_segment_size_blks(blk)
-            == self.segment_size_blks
-        {
-            &mut self.task_pool.tasks[id].buffer
-        } else {
-            let segment_size_blks = self.range.end - blk;
-
-            trace!(
-                    "Adjusting last segment size from {} to {}. offset: {}, range: {:?}",
-                    self.segment_size_blks, segment_size_blks, blk, self.range,
-                );
-
-            copy_buffer = self
-                .destination_hdl
-                .dma_malloc((segment_size_blks * self.block_size) as usize)
-                .context(NoCopyBuffer {})?;
-
-            &mut copy_buffer
-        };
-
-        self.source_hdl
-            .read_at(blk * self.block_size, copy_buffer)
-            .await
-            .context(ReadIoError {
-                bdev: &self.source,
-            })?;
-
-        self.destination_hdl
-            .write_at(blk * self.block_size, copy_buffer)
-            .await
-            .context(WriteIoError {
-                bdev: &self.destination,
-            })?;
-
-        Ok(())
-    }
-
     fn notify(&mut self) {
         self.stats();
         self.send_notify();
@@ -467,7 +364,8 @@ impl std::fmt::Display for RebuildOperation {
 
 impl ClientOperations for RebuildJob {
     fn stats(&self) -> RebuildStats {
-        let blocks_total = self.range.end - self.range.start;
+        let blocks_total =
+            self.regions.iter().map(|r| r.end - r.start).sum::<u64>();
 
         // segment size may not be aligned to the total size
         let blocks_recovered = std::cmp::min(
@@ -601,37 +499,230 @@ impl RebuildJob {
         }
     }
 
-    /// Sends one segment worth of data in a reactor future and notifies the
-    /// management channel. Returns the next segment offset to rebuild, if any
-    fn send_segment_task(&self, id: usize) -> Option<u64> {
-        if self.next >= self.range.end {
-            None
-        } else {
-            let blk = self.next;
-            let next = std::cmp::min(
-                self.next + self.segment_size_blks,
-                self.range.end,
+    /// Sends one segment worth of data to be copied on the next core and
+    /// notifies the management channel once it is back on this core.
+    /// Returns the next segment offset to rebuild, if any
+    fn send_segment_task(&mut self, id: usize) -> Option<u64> {
+        // skip to the region the next segment is in
+        let region = self.regions.iter().find(|r| r.end > self.next)?;
+        let blk = std::cmp::max(self.next, region.start);
+        let next = std::cmp::min(blk + self.segment_size_blks, region.end);
+        let name = self.destination.clone();
+
+        let segment = Segment {
+            blk,
+            len: next - blk,
+            start: self.range.start,
+            block_size: self.block_size,
+            source: self.source.clone(),
+            destination: self.destination.clone(),
+        };
+        let buffer = self.task_pool.tasks[id].buffer.take();
+        let copy = self
+            .spread
+            .spawn(move |handles| segment.locked_copy(handles, buffer));
+
+        Reactors::current().send_future(async move {
+            let (error, buffer) = match copy.await {
+                Ok(Ok((result, buffer))) => (result.err(), buffer),
+                Ok(Err(source)) => (
+                    Some(RebuildError::NoBdevHandle {
+                        source,
+                        bdev: name.clone(),
+                    }),
+                    None,
+                ),
+                Err(_) => (
+                    Some(RebuildError::SegmentCancelled {
+                        blk,
+                    }),
+                    None,
+                ),
+            };
+
+            let job = Self::lookup(&name).unwrap();
+            let r = TaskResult {
+                blk,
+                id,
+                error,
+            };
+
+            let task = &mut job.task_pool.tasks[id];
+            if buffer.is_some() {
+                task.buffer = buffer;
+            }
+            if let Err(e) = task.sender.start_send(r) {
+                error!("Failed to notify job of segment id: {} blk: {} completion, err: {}", id, blk, e.verbose());
+            }
+        });
+
+        Some(next)
+    }
+}
+
+/// One segment to be copied from the source to the destination, on whichever
+/// core it has been sent to
+struct Segment {
+    /// first block of the segment
+    blk: u64,
+    /// number of blocks of the segment
+    len: u64,
+    /// first block of the rebuild range, i.e. of the data partition
+    start: u64,
+    block_size: u64,
+    source: String,
+    destination: String,
+}
+
+impl Segment {
+    /// Copies the segment from source into destination. During this time the
+    /// LBA range being copied is locked so that there cannot be front end
+    /// I/O to the same LBA range. The handles are those of the nexus, the
+    /// source and the destination, in that order. The buffer of the task is
+    /// returned along with the result.
+    ///
+    /// # Safety
+    ///
+    /// The lock and unlock functions internally reference the RangeContext as a
+    /// raw pointer, so rust cannot correctly manage its lifetime. The
+    /// RangeContext MUST NOT be dropped until after the lock and unlock have
+    /// completed.
+    ///
+    /// The use of RangeContext here is safe because it is stored on the stack
+    /// for the duration of the calls to lock and unlock.
+    async fn locked_copy(
+        self,
+        handles: Vec<BdevHandle>,
+        buffer: Option<DmaBuf>,
+    ) -> (Result<(), RebuildError>, Option<DmaBuf>) {
+        let (nexus, source, destination) =
+            (&handles[0], &handles[1], &handles[2]);
+        let (blk, len) = (self.blk, self.len);
+
+        // The nexus children have metadata and data partitions, whereas the
+        // nexus has a data partition only. Because we are locking the range on
+        // the nexus, we need to calculate the offset from the start of the data
+        // partition.
+        let mut ctx = RangeContext::new(blk - self.start, len);
+
+        // Wait for LBA range to be locked.
+        // This prevents other I/Os being issued to this LBA range whilst it is
+        // being rebuilt.
+        if let Err(source) =
+            nexus.desc.lock_lba_range(&mut ctx, &nexus.channel).await
+        {
+            return (
+                Err(RebuildError::RangeLockError {
+                    blk,
+                    len,
+                    source,
+                }),
+                buffer,
             );
-            let name = self.destination.clone();
+        }
 
-            Reactors::current().send_future(async move {
-                let job = Self::lookup(&name).unwrap();
+        // Perform the copy
+        let (result, buffer) = self.copy(source, destination, buffer).await;
 
-                let r = TaskResult {
+        // Wait for the LBA range to be unlocked.
+        // This allows others I/Os to be issued to this LBA range once again.
+        if let Err(source) =
+            nexus.desc.unlock_lba_range(&mut ctx, &nexus.channel).await
+        {
+            return (
+                Err(RebuildError::RangeUnLockError {
                     blk,
-                    id,
-                    error: job.locked_copy_one(id, blk).await.err(),
-                };
+                    len,
+                    source,
+                }),
+                buffer,
+            );
+        }
 
-                let task = &mut job.task_pool.tasks[id];
-                if let Err(e) = task.sender.start_send(r) {
-                    error!("Failed to notify job of segment id: {} blk: {} completion, err: {}", id, blk, e.verbose());
+        (result, buffer)
+    }
+
+    /// Copies the segment from source into destination, with the buffer of
+    /// the task unless the segment is the last and a shorter one.
+    async fn copy(
+        &self,
+        source: &BdevHandle,
+        destination: &BdevHandle,
+        buffer: Option<DmaBuf>,
+    ) -> (Result<(), RebuildError>, Option<DmaBuf>) {
+        let size = (self.len * self.block_size) as usize;
+
+        // the source lends us its buffers, write them out as they are
+        if source.supports_zcopy() {
+            return (self.copy_zcopy(source, destination).await, buffer);
+        }
+
+        let (mut copy_buffer, buffer) = match buffer {
+            Some(buffer) if buffer.len() == size => (buffer, None),
+            buffer => {
+                trace!(
+                    "Adjusting last segment size to {} blocks. offset: {}",
+                    self.len,
+                    self.blk,
+                );
+                match destination.dma_malloc(size).context(NoCopyBuffer {}) {
+                    Ok(copy_buffer) => (copy_buffer, buffer),
+                    Err(e) => return (Err(e), buffer),
                 }
-            });
+            }
+        };
+
+        let offset = self.blk * self.block_size;
+        let result = async {
+            source.read_at(offset, &mut copy_buffer).await.context(
+                ReadIoError {
+                    bdev: &self.source,
+                },
+            )?;
+
+            destination.write_at(offset, &copy_buffer).await.context(
+                WriteIoError {
+                    bdev: &self.destination,
+                },
+            )?;
 
-            Some(next)
+            Ok(())
+        }
+        .await;
+
+        // hand back the buffer of the task rather than the one of a shorter
+        // last segment
+        match buffer {
+            Some(buffer) if buffer.len() > copy_buffer.len() => {
+                (result, Some(buffer))
+            }
+            _ => (result, Some(copy_buffer)),
         }
     }
+
+    /// Copies the segment from source into destination straight from the
+    /// buffers of the source.
+    async fn copy_zcopy(
+        &self,
+        source: &BdevHandle,
+        destination: &BdevHandle,
+    ) -> Result<(), RebuildError> {
+        let offset = self.blk * self.block_size;
+        let buf = source
+            .read_zcopy_at(offset, self.len * self.block_size)
+            .await
+            .context(ReadIoError {
+                bdev: &self.source,
+            })?;
+
+        destination.write_zcopy_at(offset, &buf).await.context(
+            WriteIoError {
+                bdev: &self.destination,
+            },
+        )?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default)]
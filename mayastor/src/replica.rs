@@ -3,19 +3,35 @@
 //! Replica is a logical data volume exported over nvmf (in SPDK terminology
 //! an lvol). Here we define methods for easy management of replicas.
 
-use std::ffi::{c_void, CStr, CString};
+use std::{
+    collections::HashMap,
+    ffi::{c_void, CStr, CString},
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use futures::channel::oneshot;
 use nix::errno::Errno;
+use once_cell::sync::Lazy;
 use rpc::mayastor as rpc;
 use snafu::{ResultExt, Snafu};
+use tracing::instrument;
 
 use spdk_sys::{
+    spdk_bdev_desc,
+    spdk_bdev_write,
+    spdk_blob_is_snapshot,
+    spdk_io_channel,
     spdk_lvol,
     spdk_nvme_cpl,
     spdk_nvme_status,
     spdk_nvmf_request,
     vbdev_lvol_create,
+    vbdev_lvol_create_clone,
     vbdev_lvol_create_snapshot,
     vbdev_lvol_destroy,
     vbdev_lvol_get_from_bdev,
@@ -25,7 +41,22 @@ use spdk_sys::{
 };
 
 use crate::{
-    core::Bdev,
+    bdev::nexus::{
+        instances,
+        nexus_bdev::{self, nexus_lookup},
+    },
+    core::{
+        sleep,
+        Bdev,
+        BdevHandle,
+        CoreError,
+        DmaBuf,
+        DmaError,
+        IoCtxPool,
+        QosLimits,
+        Reactors,
+        Uuid,
+    },
     ffihelper::{
         cb_arg,
         done_errno_cb,
@@ -33,8 +64,13 @@ use crate::{
         ErrnoResult,
         IntoCString,
     },
+    grpc,
+    iscsi_portal::{self, Portal},
+    limits,
+    lvs::{self, FlushPolicy, Lvol, PropValue},
     pool::Pool,
-    subsys::NvmfSubsystem,
+    stats_store::{self, Counters},
+    subsys::{events, Config, NvmfSubsystem},
     target,
 };
 
@@ -43,10 +79,26 @@ use crate::{
 pub enum RpcError {
     #[snafu(display("Failed to create replica {}", uuid))]
     CreateReplica { source: Error, uuid: String },
+    #[snafu(display("Failed to create replica {} from snapshot", uuid))]
+    CreateReplicaFromSnapshot { source: Error, uuid: String },
     #[snafu(display("Failed to destroy replica {}", uuid))]
     DestroyReplica { source: Error, uuid: String },
     #[snafu(display("Failed to (un)share replica {}", uuid))]
     ShareReplica { source: Error, uuid: String },
+    #[snafu(display("Failed to set flush policy of replica {}", uuid))]
+    SetFlushPolicy { source: Error, uuid: String },
+    #[snafu(display("Failed to reshare replica {}", uuid))]
+    ReshareReplica { source: Error, uuid: String },
+    #[snafu(display("Failed to switch the share of replica {}", uuid))]
+    SwitchReplicaShare { source: Error, uuid: String },
+    #[snafu(display("Failed to diff snapshots of {}", uuid))]
+    DiffSnapshots { source: Error, uuid: String },
+    #[snafu(display("Failed to set QoS limits of replica {}", uuid))]
+    SetReplicaQos { source: Error, uuid: String },
+    #[snafu(display("Failed to set metadata of replica {}", uuid))]
+    SetReplicaMetadata { source: Error, uuid: String },
+    #[snafu(display("Failed to get metadata of replica {}", uuid))]
+    GetReplicaMetadata { source: Error, uuid: String },
 }
 
 impl From<RpcError> for tonic::Status {
@@ -55,12 +107,36 @@ impl From<RpcError> for tonic::Status {
             RpcError::CreateReplica {
                 source, ..
             } => Self::from(source),
+            RpcError::CreateReplicaFromSnapshot {
+                source, ..
+            } => Self::from(source),
             RpcError::DestroyReplica {
                 source, ..
             } => Self::from(source),
             RpcError::ShareReplica {
                 source, ..
             } => Self::from(source),
+            RpcError::SetFlushPolicy {
+                source, ..
+            } => Self::from(source),
+            RpcError::ReshareReplica {
+                source, ..
+            } => Self::from(source),
+            RpcError::SwitchReplicaShare {
+                source, ..
+            } => Self::from(source),
+            RpcError::DiffSnapshots {
+                source, ..
+            } => Self::from(source),
+            RpcError::SetReplicaQos {
+                source, ..
+            } => Self::from(source),
+            RpcError::SetReplicaMetadata {
+                source, ..
+            } => Self::from(source),
+            RpcError::GetReplicaMetadata {
+                source, ..
+            } => Self::from(source),
         }
     }
 }
@@ -70,6 +146,8 @@ impl From<RpcError> for tonic::Status {
 pub enum Error {
     #[snafu(display("The pool \"{}\" does not exist", pool))]
     PoolNotFound { pool: String },
+    #[snafu(display("The pool \"{}\" is read-only: {}", pool, reason))]
+    PoolReadOnly { pool: String, reason: String },
     #[snafu(display("Replica already exists"))]
     ReplicaExists {},
     #[snafu(display("Invalid parameters"))]
@@ -78,12 +156,24 @@ pub enum Error {
     CreateLvol { source: Errno },
     #[snafu(display("Failed to destroy lvol"))]
     DestroyLvol { source: Errno },
+    #[snafu(display(
+        "Replica is a snapshot with clones {}, which must be flattened or \
+         destroyed first",
+        clones
+    ))]
+    HasClones { clones: String },
+    #[snafu(display("Failed to flatten clone {}", clone))]
+    FlattenClone { source: lvs::Error, clone: String },
+    #[snafu(display("Invalid dependents policy {} in request", policy))]
+    InvalidDependents { policy: i32 },
     #[snafu(display("Replica has been already shared"))]
     ReplicaShared {},
     #[snafu(display("share nvmf"))]
     ShareNvmf { source: target::nvmf::Error },
     #[snafu(display("share iscsi"))]
     ShareIscsi { source: target::iscsi::Error },
+    #[snafu(display("Failed to share on the iSCSI portal"))]
+    IscsiPortal { source: iscsi_portal::Error },
     #[snafu(display("unshare nvmf"))]
     UnshareNvmf { source: target::nvmf::Error },
     #[snafu(display("unshare iscsi"))]
@@ -92,6 +182,37 @@ pub enum Error {
     InvalidProtocol { protocol: i32 },
     #[snafu(display("Replica does not exist"))]
     ReplicaNotFound {},
+    #[snafu(display("Invalid flush policy {} in request", policy))]
+    InvalidFlushPolicy { policy: i32 },
+    #[snafu(display("Failed to store flush policy"))]
+    StoreFlushPolicy { source: lvs::Error },
+    #[snafu(display("Replica is already being reshared"))]
+    ReshareInProgress {},
+    #[snafu(display("Failed to switch the child of nexus {}", nexus))]
+    SwitchNexusChild {
+        source: nexus_bdev::Error,
+        nexus: String,
+    },
+    #[snafu(display("The snapshot \"{}\" does not exist", snapshot))]
+    SnapshotNotFound { snapshot: String },
+    #[snafu(display("Replica \"{}\" is not a snapshot", snapshot))]
+    NotSnapshot { snapshot: String },
+    #[snafu(display("Failed to create clone"))]
+    CreateClone { source: Errno },
+    #[snafu(display("Invalid snapshots to diff"))]
+    InvalidDiff { source: lvs::Error },
+    #[snafu(display("Failed to set QoS limits"))]
+    SetQos { source: CoreError },
+    #[snafu(display("Replica exceeds the limits of the node"))]
+    NodeLimit { source: limits::Error },
+    #[snafu(display("Failed to restore snapshot {}", snapshot))]
+    RestoreSnapshot { source: CoreError, snapshot: String },
+    #[snafu(display("Failed to allocate the buffer to restore a snapshot"))]
+    RestoreBuffer { source: DmaError },
+    #[snafu(display("Invalid metadata: {}", reason))]
+    InvalidMetadata { reason: String },
+    #[snafu(display("Failed to access the metadata"))]
+    Metadata { source: lvs::Error },
 }
 
 impl From<Error> for tonic::Status {
@@ -100,6 +221,9 @@ impl From<Error> for tonic::Status {
             Error::PoolNotFound {
                 ..
             } => Self::not_found(e.to_string()),
+            Error::PoolReadOnly {
+                ..
+            } => Self::failed_precondition(e.to_string()),
             Error::ReplicaExists {
                 ..
             } => Self::already_exists(e.to_string()),
@@ -112,6 +236,15 @@ impl From<Error> for tonic::Status {
             Error::DestroyLvol {
                 ..
             } => Self::internal(e.to_string()),
+            Error::HasClones {
+                ..
+            } => Self::failed_precondition(e.to_string()),
+            Error::FlattenClone {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::InvalidDependents {
+                ..
+            } => Self::invalid_argument(e.to_string()),
             Error::ReplicaShared {
                 ..
             } => Self::internal(e.to_string()),
@@ -121,6 +254,9 @@ impl From<Error> for tonic::Status {
             Error::ShareIscsi {
                 ..
             } => Self::internal(e.to_string()),
+            Error::IscsiPortal {
+                source,
+            } => Self::from(source),
             Error::UnshareNvmf {
                 ..
             } => Self::internal(e.to_string()),
@@ -133,6 +269,48 @@ impl From<Error> for tonic::Status {
             Error::ReplicaNotFound {
                 ..
             } => Self::not_found(e.to_string()),
+            Error::InvalidFlushPolicy {
+                ..
+            } => Self::invalid_argument(e.to_string()),
+            Error::StoreFlushPolicy {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::ReshareInProgress {
+                ..
+            } => Self::failed_precondition(e.to_string()),
+            Error::SwitchNexusChild {
+                source, ..
+            } => Self::from(source),
+            Error::SnapshotNotFound {
+                ..
+            } => Self::not_found(e.to_string()),
+            Error::NotSnapshot {
+                ..
+            } => Self::failed_precondition(e.to_string()),
+            Error::CreateClone {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::InvalidDiff {
+                source,
+            } => Self::invalid_argument(source.to_string()),
+            Error::SetQos {
+                ..
+            } => Self::invalid_argument(e.to_string()),
+            Error::NodeLimit {
+                source,
+            } => Self::from(source),
+            Error::RestoreSnapshot {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::RestoreBuffer {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::InvalidMetadata {
+                ..
+            } => Self::invalid_argument(e.to_string()),
+            Error::Metadata {
+                ..
+            } => Self::internal(e.to_string()),
         }
     }
 }
@@ -156,26 +334,109 @@ pub enum ShareType {
     Iscsi,
 }
 
-/// Detect share protocol (if any) for replica with given uuid and share ID
-/// string.
-fn detect_share(uuid: &str) -> Option<(ShareType, String)> {
-    // first try nvmf and then try iscsi
+/// What to do with the clones of a snapshot when the snapshot is destroyed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Dependents {
+    /// refuse to destroy the snapshot
+    Refuse,
+    /// copy the data the clones share with the snapshot into the clones
+    Flatten,
+    /// destroy the clones, and any clones of their snapshots, as well
+    Cascade,
+}
+
+/// Maximum number of changed ranges sent in a single diff message.
+const DIFF_RANGES_PER_REPLY: usize = 1024;
+
+/// size of the copies made when restoring a snapshot
+const RESTORE_BUF_SIZE: u64 = 1024 * 1024;
+
+/// Max length of a key of the metadata of a replica.
+const METADATA_MAX_KEY: usize = 64;
+
+/// Max length of a value of the metadata of a replica.
+const METADATA_MAX_VALUE: usize = 1024;
+
+/// Max number of keys of the metadata of a replica.
+const METADATA_MAX_KEYS: usize = 32;
+
+/// Key of the metadata of a replica holding the uuid of its volume, by which
+/// the replicas are listed.
+const VOLUME_KEY: &str = "volume";
+
+/// Time for which the old share of a reshared replica is kept by default.
+const RESHARE_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// The old share of a replica which is being reshared, removed when the
+/// grace period is over. Every reshare gets a new id, so that a timer which
+/// fires after the replica has been unshared or reshared again does not
+/// remove the wrong share.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Retiring {
+    kind: ShareType,
+    id: u64,
+    /// end of the grace period in milliseconds since the epoch
+    deadline: u64,
+}
+
+/// Replicas which are being reshared, by uuid.
+static RETIRING: Lazy<Mutex<HashMap<String, Retiring>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static RESHARE_ID: AtomicU64 = AtomicU64::new(0);
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The protocol of the old share of a replica which is being reshared, and
+/// the time in milliseconds since the epoch at which it is removed. These
+/// are persisted in the config, so that the old share is restored for the
+/// rest of its grace period when mayastor is restarted.
+pub(crate) fn retiring_share(uuid: &str) -> Option<(ShareType, u64)> {
+    RETIRING
+        .lock()
+        .unwrap()
+        .get(uuid)
+        .map(|r| (r.kind, r.deadline))
+}
+
+/// Return all shares of the replica with given uuid, nvmf first.
+fn detect_shares(uuid: &str) -> Vec<(ShareType, String)> {
+    let mut shares = Vec::new();
+
     if let Some(s) = NvmfSubsystem::nqn_lookup(uuid) {
-        let mut ep = s.uri_endpoints().unwrap();
-        return Some((ShareType::Nvmf, ep.pop().unwrap()));
+        shares.push((ShareType::Nvmf, s.share_uri().unwrap()));
+    } else if let Some(uri) = target::nvmf::get_uri(uuid) {
+        shares.push((ShareType::Nvmf, uri));
     }
 
-    match target::nvmf::get_uri(uuid) {
-        Some(uri) => Some((ShareType::Nvmf, uri)),
-        None => match target::iscsi::get_uri(target::Side::Replica, uuid) {
-            Some(uri) => Some((ShareType::Iscsi, uri)),
-            None => None,
-        },
+    if let Some(uri) = target::iscsi::get_uri(target::Side::Replica, uuid) {
+        let iqn = target::iscsi::target_name(uuid);
+        shares.push((ShareType::Iscsi, iscsi_portal::share_uri(&iqn, uri)));
+    }
+
+    shares
+}
+
+/// Detect share protocol (if any) for replica with given uuid and share ID
+/// string. While a replica is being reshared, the new share is returned.
+fn detect_share(uuid: &str) -> Option<(ShareType, String)> {
+    let mut shares = detect_shares(uuid);
+    let retiring = RETIRING.lock().unwrap().get(uuid).map(|r| r.kind);
+
+    match shares.iter().position(|s| Some(s.0) != retiring) {
+        Some(idx) => Some(shares.remove(idx)),
+        None => shares.pop(),
     }
 }
 
 impl Replica {
     /// Create replica on storage pool.
+    #[instrument(level = "debug", skip(uuid), fields(replica = %uuid), err)]
     pub async fn create(
         uuid: &str,
         pool: &str,
@@ -190,6 +451,12 @@ impl Replica {
                 })
             }
         };
+        if let Some(reason) = pool.read_only_reason() {
+            return Err(Error::PoolReadOnly {
+                pool: pool.get_name().to_owned(),
+                reason,
+            });
+        }
         let clear_method = if pool
             .get_base_bdev()
             .io_type_supported(SPDK_BDEV_IO_TYPE_UNMAP)
@@ -202,6 +469,7 @@ impl Replica {
         if Self::lookup(uuid).is_some() {
             return Err(Error::ReplicaExists {});
         }
+        limits::check_replica(size).context(NodeLimit {})?;
         let c_uuid = CString::new(uuid).unwrap();
         let (sender, receiver) =
             oneshot::channel::<ErrnoResult<*mut spdk_lvol>>();
@@ -232,11 +500,11 @@ impl Replica {
         })
     }
 
-    /// Lookup replica by uuid (=name).
+    /// Lookup replica by uuid (=name), or by the uuid of its lvol.
     pub fn lookup(uuid: &str) -> Option<Self> {
         match Bdev::lookup_by_name(uuid) {
             Some(bdev) => Replica::from_bdev(&bdev),
-            None => None,
+            None => ReplicaIter::new().find(|r| r.get_lvol_uuid() == uuid),
         }
     }
 
@@ -258,10 +526,71 @@ impl Replica {
     //
     // TODO: Error value should contain self so that it can be used when
     // destroy fails.
-    pub async fn destroy(self) -> Result<()> {
+    #[instrument(
+        level = "debug",
+        skip(self),
+        fields(replica = %self.get_uuid()),
+        err
+    )]
+    pub async fn destroy(self, dependents: Dependents) -> Result<()> {
+        let clones = self
+            .as_lvol()
+            .clones()
+            .iter()
+            .map(|c| c.name())
+            .collect::<Vec<_>>();
+
+        if !clones.is_empty() {
+            match dependents {
+                Dependents::Refuse => {
+                    return Err(Error::HasClones {
+                        clones: clones.join(", "),
+                    })
+                }
+                Dependents::Flatten => {
+                    for clone in clones {
+                        if let Some(replica) = Self::lookup(&clone) {
+                            replica.as_lvol().decouple_parent().await.context(
+                                FlattenClone {
+                                    clone,
+                                },
+                            )?;
+                        }
+                    }
+                }
+                Dependents::Cascade => {
+                    for name in self.dependents() {
+                        if let Some(replica) = Self::lookup(&name) {
+                            replica.destroy_lvol().await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.destroy_lvol().await
+    }
+
+    /// Return the names of all replicas which depend on this one, directly
+    /// or through snapshots of its clones. The list is ordered such that
+    /// every replica comes before the replica it depends on.
+    fn dependents(&self) -> Vec<String> {
+        let mut list = Vec::new();
+        for clone in self.as_lvol().clones() {
+            let replica = Replica {
+                lvol_ptr: clone.0.as_ptr(),
+            };
+            list.extend(replica.dependents());
+            list.push(clone.name());
+        }
+        list
+    }
+
+    /// Unshare and destroy the lvol of the replica.
+    async fn destroy_lvol(self) -> Result<()> {
         self.unshare().await?;
 
-        let uuid = self.get_uuid();
+        let uuid = self.get_uuid().to_owned();
         let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
         unsafe {
             vbdev_lvol_destroy(
@@ -276,6 +605,7 @@ impl Replica {
             .expect("Cancellation is not supported")
             .context(DestroyLvol {})?;
 
+        stats_store::forget_replica(&uuid);
         info!("Destroyed replica {}", uuid);
         Ok(())
     }
@@ -329,45 +659,408 @@ impl Replica {
         info!("Creating snapshot {}", snapshot_name);
     }
 
+    /// name of the snapshot of the replica taken at the given time, which is
+    /// the same for all the replicas of a nexus
+    pub(crate) fn snapshot_name(&self, snapshot_time: u64) -> String {
+        format!("{}-snap-{}", self.as_bdev().name(), snapshot_time)
+    }
+
+    /// Roll the replica back to its snapshot taken at the given time, by
+    /// copying the clusters written since then back from the snapshot. The
+    /// writes go through the given descriptor and channel, as the bdev of a
+    /// shared replica is claimed by the nvmf target. Returns the number of
+    /// bytes copied.
+    pub(crate) async fn restore_snapshot(
+        &self,
+        snapshot_time: u64,
+        desc: *mut spdk_bdev_desc,
+        ch: *mut spdk_io_channel,
+    ) -> Result<u64> {
+        let name = self.snapshot_name(snapshot_time);
+        let snapshot = match Self::lookup(&name) {
+            Some(snapshot) if snapshot.is_snapshot() => snapshot,
+            Some(_) => {
+                return Err(Error::NotSnapshot {
+                    snapshot: name,
+                })
+            }
+            None => {
+                return Err(Error::SnapshotNotFound {
+                    snapshot: name,
+                })
+            }
+        };
+
+        let lvol = self.as_lvol();
+        let clusters = lvol
+            .changed_clusters(Some(&snapshot.as_lvol()))
+            .context(InvalidDiff {})?;
+        let ranges = changed_ranges(clusters, lvol.cluster_size());
+
+        let source =
+            BdevHandle::open(&name, false, false).context(RestoreSnapshot {
+                snapshot: name.clone(),
+            })?;
+        let mut buf = source
+            .dma_malloc(RESTORE_BUF_SIZE as usize)
+            .context(RestoreBuffer {})?;
+
+        let mut copied = 0;
+        for range in ranges {
+            let end = range.offset + range.length;
+            let mut offset = range.offset;
+            while offset < end {
+                if end - offset < buf.len() as u64 {
+                    buf = source
+                        .dma_malloc((end - offset) as usize)
+                        .context(RestoreBuffer {})?;
+                }
+                source.read_at(offset, &mut buf).await.context(
+                    RestoreSnapshot {
+                        snapshot: name.clone(),
+                    },
+                )?;
+                write_at(desc, ch, offset, &buf).await.context(
+                    RestoreSnapshot {
+                        snapshot: name.clone(),
+                    },
+                )?;
+                offset += buf.len() as u64;
+                copied += buf.len() as u64;
+            }
+        }
+
+        info!(
+            "Restored replica {} from snapshot {} ({} bytes)",
+            self.get_uuid(),
+            name,
+            copied
+        );
+        Ok(copied)
+    }
+
+    /// Create a writable clone of the snapshot. The clone shares all data
+    /// with the snapshot and only allocates clusters which are written to,
+    /// hence creating it is fast regardless of the size.
+    pub async fn create_clone(&self, uuid: &str) -> Result<Self> {
+        if !self.is_snapshot() {
+            return Err(Error::NotSnapshot {
+                snapshot: self.get_uuid().to_owned(),
+            });
+        }
+        if Self::lookup(uuid).is_some() {
+            return Err(Error::ReplicaExists {});
+        }
+        limits::check_replica(self.get_size()).context(NodeLimit {})?;
+
+        let c_uuid = CString::new(uuid).unwrap();
+        let (sender, receiver) =
+            oneshot::channel::<ErrnoResult<*mut spdk_lvol>>();
+        unsafe {
+            vbdev_lvol_create_clone(
+                self.lvol_ptr,
+                c_uuid.as_ptr(),
+                Some(Self::replica_done_cb),
+                cb_arg(sender),
+            );
+        }
+
+        let lvol_ptr = receiver
+            .await
+            .expect("Cancellation is not supported")
+            .context(CreateClone {})?;
+
+        info!("Created replica {} from snapshot {}", uuid, self.get_uuid());
+        Ok(Self {
+            lvol_ptr,
+        })
+    }
+
     /// Expose replica over supported remote access storage protocols (nvmf
     /// and iscsi).
+    #[instrument(
+        level = "debug",
+        skip(self),
+        fields(replica = %self.get_uuid()),
+        err
+    )]
     pub async fn share(&self, kind: ShareType) -> Result<()> {
-        let uuid = self.get_uuid().to_owned();
-        if detect_share(&uuid).is_some() {
+        self.share_on_portal(kind, &Portal::default()).await
+    }
+
+    /// Share the replica as [`Replica::share`] does, on the given portal if
+    /// it is shared over iscsi.
+    pub async fn share_on_portal(
+        &self,
+        kind: ShareType,
+        portal: &Portal,
+    ) -> Result<()> {
+        if detect_share(self.get_uuid()).is_some() {
             return Err(Error::ReplicaShared {});
         }
+        limits::check_share().context(NodeLimit {})?;
 
+        self.share_as(kind, portal).await
+    }
+
+    /// Add a share over the given protocol, regardless of existing shares.
+    async fn share_as(&self, kind: ShareType, portal: &Portal) -> Result<()> {
+        let uuid = self.get_uuid().to_owned();
         let bdev = unsafe { Bdev::from((*self.lvol_ptr).bdev) };
 
         match kind {
-            ShareType::Nvmf => target::nvmf::share(&uuid, &bdev)
-                .await
-                .context(ShareNvmf {})?,
+            ShareType::Nvmf => {
+                target::nvmf::share(&uuid, &bdev)
+                    .await
+                    .context(ShareNvmf {})?;
+                self.as_lvol().load_flush_policy().await;
+            }
             ShareType::Iscsi => {
                 target::iscsi::share(&uuid, &bdev, target::Side::Replica)
                     .context(ShareIscsi {})?;
+                let iqn = target::iscsi::target_name(&uuid);
+                if let Err(e) =
+                    iscsi_portal::bind(&iqn, target::Side::Replica, portal)
+                {
+                    let _ = target::iscsi::unshare(&uuid).await;
+                    return Err(Error::IscsiPortal {
+                        source: e,
+                    });
+                }
             }
         }
+        events::share_published("replica", &uuid, &self.get_share_uri());
         Ok(())
     }
 
     /// The opposite of share. It is not an error to call unshare on a replica
-    /// which is not shared.
+    /// which is not shared. A replica which is being reshared loses both of
+    /// its shares.
+    #[instrument(
+        level = "debug",
+        skip(self),
+        fields(replica = %self.get_uuid()),
+        err
+    )]
     pub async fn unshare(&self) -> Result<()> {
         let uuid = self.get_uuid().to_owned();
-        if let Some((share_type, _)) = detect_share(&uuid) {
-            match share_type {
-                ShareType::Nvmf => target::nvmf::unshare(&uuid)
-                    .await
-                    .context(UnshareNvmf {})?,
-                ShareType::Iscsi => target::iscsi::unshare(&uuid)
-                    .await
-                    .context(UnshareIscsi {})?,
+        RETIRING.lock().unwrap().remove(&uuid);
+
+        for (share_type, _) in detect_shares(&uuid) {
+            self.unshare_as(share_type).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the share over the given protocol.
+    async fn unshare_as(&self, kind: ShareType) -> Result<()> {
+        let uuid = self.get_uuid();
+        match kind {
+            ShareType::Nvmf => {
+                target::nvmf::unshare(uuid).await.context(UnshareNvmf {})
+            }
+            ShareType::Iscsi => {
+                target::iscsi::unshare(uuid).await.context(UnshareIscsi {})?;
+                iscsi_portal::release(&target::iscsi::target_name(uuid));
+                Ok(())
+            }
+        }
+    }
+
+    /// Share the replica over another protocol while keeping the existing
+    /// share for the grace period, so that nexuses can switch over to the new
+    /// share one at a time. Returns the URI of the previous share, if any.
+    pub async fn reshare(
+        &self,
+        kind: ShareType,
+        grace: Duration,
+    ) -> Result<Option<String>> {
+        let uuid = self.get_uuid().to_owned();
+        if RETIRING.lock().unwrap().contains_key(&uuid) {
+            return Err(Error::ReshareInProgress {});
+        }
+
+        let (old, old_uri) = match detect_share(&uuid) {
+            Some((share_type, _)) if share_type == kind => return Ok(None),
+            Some(share) => share,
+            None => {
+                self.share_as(kind, &Portal::default()).await?;
+                return Ok(None);
             }
         };
+
+        self.share_as(kind, &Portal::default()).await?;
+
+        info!(
+            "Replica {} shared over {:?}, {:?} share is removed in {:?}",
+            uuid, kind, old, grace
+        );
+        Self::retire_at(uuid, old, now_ms() + grace.as_millis() as u64);
+
+        Ok(Some(old_uri))
+    }
+
+    /// Share the replica over the protocol of the old share of a reshare
+    /// which was still in its grace period when mayastor was stopped, until
+    /// the grace period is over. Nothing is shared once it is over.
+    pub(crate) async fn restore_retiring(
+        &self,
+        kind: ShareType,
+        deadline: u64,
+    ) -> Result<()> {
+        let uuid = self.get_uuid().to_owned();
+        if deadline <= now_ms()
+            || detect_shares(&uuid).iter().any(|s| s.0 == kind)
+        {
+            return Ok(());
+        }
+
+        self.share_as(kind, &Portal::default()).await?;
+        info!(
+            "Restored {:?} share of reshared replica {} until its removal",
+            kind, uuid
+        );
+        Self::retire_at(uuid, kind, deadline);
         Ok(())
     }
 
+    /// Mark the share as retiring and arm a timer on the management core
+    /// which removes it at the deadline.
+    fn retire_at(uuid: String, kind: ShareType, deadline: u64) {
+        let id = RESHARE_ID.fetch_add(1, Ordering::Relaxed);
+        RETIRING.lock().unwrap().insert(
+            uuid.clone(),
+            Retiring {
+                kind,
+                id,
+                deadline,
+            },
+        );
+
+        let grace = Duration::from_millis(deadline.saturating_sub(now_ms()));
+        Reactors::master().send_future(async move {
+            sleep(grace).await;
+            Self::retire_share(uuid, kind, id).await;
+        });
+    }
+
+    /// Remove the old share of a reshared replica, unless the replica has
+    /// been unshared or reshared again in the mean time. The config is
+    /// exported again, so that the share is not restored on a restart.
+    async fn retire_share(uuid: String, kind: ShareType, id: u64) {
+        {
+            let mut retiring = RETIRING.lock().unwrap();
+            match retiring.get(&uuid) {
+                Some(r) if r.kind == kind && r.id == id => {}
+                _ => return,
+            }
+            retiring.remove(&uuid);
+        }
+
+        if let Some(replica) = Replica::lookup(&uuid) {
+            match replica.unshare_as(kind).await {
+                Ok(_) => info!("Removed {:?} share of replica {}", kind, uuid),
+                Err(e) => error!(
+                    "Failed to remove {:?} share of replica {}: {}",
+                    kind, uuid, e
+                ),
+            }
+        }
+
+        if let Err(e) = Config::export_config().await {
+            error!("Failed to export config: {}", e);
+        }
+    }
+
+    /// Share the replica over another protocol, or not at all, in place of
+    /// its current share. The children of the local nexuses which use the
+    /// replica are switched to the new URI before the old share is removed,
+    /// so that they are not rebuilt. Nexuses on other nodes are switched with
+    /// a reshare instead, which keeps the old share around for them. Returns
+    /// the URI of the old share and the nexuses whose child was switched.
+    #[instrument(
+        level = "debug",
+        skip(self),
+        fields(replica = %self.get_uuid()),
+        err
+    )]
+    pub async fn switch_share(
+        &self,
+        kind: Option<ShareType>,
+    ) -> Result<(String, Vec<String>)> {
+        let uuid = self.get_uuid().to_owned();
+        if RETIRING.lock().unwrap().contains_key(&uuid) {
+            return Err(Error::ReshareInProgress {});
+        }
+
+        let old = self.get_share_type();
+        let old_uri = self.get_share_uri();
+        if old == kind {
+            return Ok((old_uri, Vec::new()));
+        }
+
+        let new_uri = match kind {
+            Some(kind) => {
+                if old.is_none() {
+                    limits::check_share().context(NodeLimit {})?;
+                }
+                self.share_as(kind, &Portal::default()).await?;
+                detect_shares(&uuid)
+                    .into_iter()
+                    .find(|s| s.0 == kind)
+                    .map(|s| s.1)
+                    .ok_or(Error::ReplicaNotFound {})?
+            }
+            None => format!("bdev:///{}", uuid),
+        };
+
+        let nexuses = instances()
+            .iter()
+            .filter(|n| n.children.iter().any(|c| c.name == old_uri))
+            .map(|n| n.name.clone())
+            .collect::<Vec<_>>();
+        let mut switched = Vec::new();
+
+        for name in nexuses {
+            let nexus = match nexus_lookup(&name) {
+                Some(nexus) => nexus,
+                None => continue,
+            };
+            if let Err(e) = nexus.switch_child(&old_uri, &new_uri).await {
+                // leave the replica as it was
+                for name in &switched {
+                    if let Some(nexus) = nexus_lookup(name) {
+                        if let Err(e) =
+                            nexus.switch_child(&new_uri, &old_uri).await
+                        {
+                            error!("{}", e);
+                        }
+                    }
+                }
+                if let Some(kind) = kind {
+                    if let Err(e) = self.unshare_as(kind).await {
+                        error!("{}", e);
+                    }
+                }
+                return Err(Error::SwitchNexusChild {
+                    source: e,
+                    nexus: name,
+                });
+            }
+            switched.push(name);
+        }
+
+        if let Some(old) = old {
+            self.unshare_as(old).await?;
+        }
+
+        info!(
+            "Replica {} switched from {:?} to {:?} share, along with {:?}",
+            uuid, old, kind, switched
+        );
+        Ok((old_uri, switched))
+    }
+
     /// Return either a type of share and a string identifying the share
     /// (nqn for nvmf and iqn for iscsi) or none if the replica is not
     /// shared.
@@ -406,11 +1099,117 @@ impl Replica {
         }
     }
 
+    /// Get uuid of the lvol of the replica, which unlike its name never
+    /// changes.
+    pub fn get_lvol_uuid(&self) -> String {
+        self.as_bdev().uuid_as_string()
+    }
+
+    /// Get uuid of the pool which replica belongs to.
+    pub fn get_pool_uuid(&self) -> String {
+        Uuid::from_bytes(unsafe { (*(*self.lvol_ptr).lvol_store).uuid.u.raw })
+            .to_string()
+    }
+
+    /// Return if the replica is a (read-only) snapshot of another replica.
+    pub fn is_snapshot(&self) -> bool {
+        unsafe { spdk_blob_is_snapshot((*self.lvol_ptr).blob) }
+    }
+
     /// Return if replica has been thin provisioned.
     pub fn is_thin(&self) -> bool {
         unsafe { (*self.lvol_ptr).thin_provision }
     }
 
+    /// Return if the pool of the replica has become read-only, in which case
+    /// writes to the replica fail.
+    pub fn is_read_only(&self) -> bool {
+        lvs::read_only::reason(unsafe { (*self.lvol_ptr).lvol_store }).is_some()
+    }
+
+    /// Set the policy for flushes received over nvmf and persist it in the
+    /// pool so that it survives a re-import.
+    pub async fn set_flush_policy(&self, policy: FlushPolicy) -> Result<()> {
+        self.as_lvol()
+            .set(PropValue::FlushPolicy(policy))
+            .await
+            .context(StoreFlushPolicy {})
+    }
+
+    /// Set the metadata of the replica, which is persisted in the pool. The
+    /// keys with an empty value are removed.
+    pub async fn set_metadata(
+        &self,
+        metadata: HashMap<String, String>,
+    ) -> Result<HashMap<String, String>> {
+        let mut current = self.get_metadata()?;
+        for (key, value) in &metadata {
+            if key.is_empty()
+                || key.len() > METADATA_MAX_KEY
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-/".contains(c))
+            {
+                return Err(Error::InvalidMetadata {
+                    reason: format!("invalid key \"{}\"", key),
+                });
+            }
+            if value.len() > METADATA_MAX_VALUE || value.contains('\0') {
+                return Err(Error::InvalidMetadata {
+                    reason: format!("invalid value of key {}", key),
+                });
+            }
+            if value.is_empty() {
+                current.remove(key);
+            } else {
+                current.insert(key.clone(), value.clone());
+            }
+        }
+        if current.len() > METADATA_MAX_KEYS {
+            return Err(Error::InvalidMetadata {
+                reason: format!("more than {} keys", METADATA_MAX_KEYS),
+            });
+        }
+
+        self.as_lvol()
+            .set_metadata(&metadata)
+            .await
+            .context(Metadata {})?;
+        Ok(current)
+    }
+
+    /// Return the metadata of the replica.
+    pub fn get_metadata(&self) -> Result<HashMap<String, String>> {
+        self.as_lvol().metadata().context(Metadata {})
+    }
+
+    /// Return the flush policy in effect for the replica.
+    pub fn get_flush_policy(&self) -> FlushPolicy {
+        self.as_lvol().flush_policy()
+    }
+
+    /// Set the QoS rate limits of the replica. The limits are not persisted
+    /// and have to be set again when the pool is re-imported.
+    pub async fn set_qos(&self, limits: QosLimits) -> Result<()> {
+        self.as_bdev()
+            .set_qos_limits(limits)
+            .await
+            .context(SetQos {})
+    }
+
+    /// Return the QoS rate limits in effect for the replica.
+    pub fn get_qos(&self) -> QosLimits {
+        self.as_bdev().qos_limits()
+    }
+
+    pub(crate) fn as_bdev(&self) -> Bdev {
+        unsafe { (*self.lvol_ptr).bdev.into() }
+    }
+
+    fn as_lvol(&self) -> Lvol {
+        Lvol(NonNull::new(self.lvol_ptr).expect("lvol pointer is null"))
+    }
+
     /// Return raw pointer to lvol (C struct spdk_lvol).
     pub fn as_ptr(&self) -> *mut spdk_lvol {
         self.lvol_ptr
@@ -500,6 +1299,15 @@ impl Iterator for ReplicaIter {
     }
 }
 
+/// the protocol the replica is shared over
+fn share_protocol(r: &Replica) -> rpc::ShareProtocolReplica {
+    match r.get_share_type() {
+        Some(ShareType::Iscsi) => rpc::ShareProtocolReplica::ReplicaIscsi,
+        Some(ShareType::Nvmf) => rpc::ShareProtocolReplica::ReplicaNvmf,
+        None => rpc::ShareProtocolReplica::ReplicaNone,
+    }
+}
+
 impl From<Replica> for rpc::Replica {
     fn from(r: Replica) -> Self {
         rpc::Replica {
@@ -507,14 +1315,22 @@ impl From<Replica> for rpc::Replica {
             pool: r.get_pool_name().to_owned(),
             size: r.get_size(),
             thin: r.is_thin(),
-            share: match r.get_share_type() {
-                Some(share_type) => match share_type {
-                    ShareType::Iscsi => rpc::ShareProtocolReplica::ReplicaIscsi,
-                    ShareType::Nvmf => rpc::ShareProtocolReplica::ReplicaNvmf,
-                },
-                None => rpc::ShareProtocolReplica::ReplicaNone,
-            } as i32,
+            share: share_protocol(&r) as i32,
             uri: r.get_share_uri(),
+            flush_policy: match r.get_flush_policy() {
+                FlushPolicy::Forward => rpc::ReplicaFlushPolicy::FlushForward,
+                FlushPolicy::Batch => rpc::ReplicaFlushPolicy::FlushBatch,
+                FlushPolicy::Ignore => rpc::ReplicaFlushPolicy::FlushIgnore,
+            } as i32,
+            qos: Some(r.get_qos().into()),
+            placement: Some(grpc::placement(
+                &r.as_bdev(),
+                r.get_share_type().map(|_| r.get_uuid()),
+                &r.get_share_uri(),
+            )),
+            read_only: r.is_read_only(),
+            lvol_uuid: r.get_lvol_uuid(),
+            pool_uuid: r.get_pool_uuid(),
         }
     }
 }
@@ -540,6 +1356,13 @@ pub(crate) async fn create_replica(
             })?,
     };
 
+    // throttle the replica before it is exposed to anyone
+    if let Some(qos) = args.qos {
+        replica.set_qos(qos.into()).await.context(CreateReplica {
+            uuid: args.uuid.clone(),
+        })?;
+    }
+
     // TODO: destroy replica if the share operation fails
     match want_share {
         rpc::ShareProtocolReplica::ReplicaNvmf => replica
@@ -559,22 +1382,152 @@ pub(crate) async fn create_replica(
     Ok(replica.into())
 }
 
+pub(crate) async fn create_replica_from_snapshot(
+    args: rpc::CreateReplicaFromSnapshotRequest,
+) -> Result<rpc::Replica, RpcError> {
+    let want_share = match rpc::ShareProtocolReplica::from_i32(args.share) {
+        Some(val) => val,
+        None => Err(Error::InvalidProtocol {
+            protocol: args.share,
+        })
+        .context(CreateReplicaFromSnapshot {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(r) => r,
+        None => {
+            let snapshot = match Replica::lookup(&args.snapshot) {
+                Some(snapshot) => snapshot,
+                None => Err(Error::SnapshotNotFound {
+                    snapshot: args.snapshot.clone(),
+                })
+                .context(CreateReplicaFromSnapshot {
+                    uuid: args.uuid.clone(),
+                })?,
+            };
+            snapshot.create_clone(&args.uuid).await.context(
+                CreateReplicaFromSnapshot {
+                    uuid: args.uuid.clone(),
+                },
+            )?
+        }
+    };
+
+    let kind = match want_share {
+        rpc::ShareProtocolReplica::ReplicaNvmf => Some(ShareType::Nvmf),
+        rpc::ShareProtocolReplica::ReplicaIscsi => Some(ShareType::Iscsi),
+        rpc::ShareProtocolReplica::ReplicaNone => None,
+    };
+    if let Some(kind) = kind {
+        if replica.get_share_type().is_none() {
+            replica
+                .share(kind)
+                .await
+                .context(CreateReplicaFromSnapshot {
+                    uuid: args.uuid.clone(),
+                })?;
+        }
+    }
+
+    Ok(replica.into())
+}
+
 pub(crate) async fn destroy_replica(
     args: rpc::DestroyReplicaRequest,
 ) -> Result<(), RpcError> {
+    let dependents =
+        match rpc::DestroyReplicaDependents::from_i32(args.dependents) {
+            Some(rpc::DestroyReplicaDependents::DependentsRefuse) => {
+                Dependents::Refuse
+            }
+            Some(rpc::DestroyReplicaDependents::DependentsFlatten) => {
+                Dependents::Flatten
+            }
+            Some(rpc::DestroyReplicaDependents::DependentsCascade) => {
+                Dependents::Cascade
+            }
+            None => Err(Error::InvalidDependents {
+                policy: args.dependents,
+            })
+            .context(DestroyReplica {
+                uuid: args.uuid.clone(),
+            })?,
+        };
     match Replica::lookup(&args.uuid) {
-        Some(replica) => replica.destroy().await.context(DestroyReplica {
-            uuid: args.uuid,
-        }),
+        Some(replica) => {
+            replica.destroy(dependents).await.context(DestroyReplica {
+                uuid: args.uuid,
+            })
+        }
         None => Ok(()),
     }
 }
 
-pub(crate) fn list_replicas() -> rpc::ListReplicasReply {
+/// List the replicas which pass the filters of the request, a page of them
+/// if the number of replicas is limited.
+pub(crate) fn list_replicas(
+    args: rpc::ListReplicasRequest,
+) -> rpc::ListReplicasReply {
+    let replicas = ReplicaIter::new()
+        .filter(|r| {
+            args.pool.is_empty()
+                || r.get_pool_name() == args.pool
+                || r.get_pool_uuid() == args.pool
+        })
+        .filter(|r| {
+            args.volume.is_empty()
+                || r.get_metadata()
+                    .map(|m| m.get(VOLUME_KEY) == Some(&args.volume))
+                    .unwrap_or(false)
+        })
+        .filter(|r| {
+            args.shares.is_empty()
+                || args.shares.contains(&(share_protocol(r) as i32))
+        })
+        .map(|r| (r.get_uuid().to_string(), r))
+        .collect();
+
+    let (replicas, next_page_token) =
+        grpc::page(replicas, &args.page_token, args.max_entries);
     rpc::ListReplicasReply {
-        replicas: ReplicaIter::new()
-            .map(|r| r.into())
-            .collect::<Vec<rpc::Replica>>(),
+        replicas: replicas.into_iter().map(|r| r.into()).collect(),
+        next_page_token,
+    }
+}
+
+/// split the name of a snapshot of a nexus into the name of the replica and
+/// the time it was taken at, see [`Replica::snapshot_name`]
+fn parse_snapshot_name(name: &str) -> Option<(&str, u64)> {
+    let mut parts = name.rsplitn(2, "-snap-");
+    let snapshot_time = parts.next()?.parse().ok()?;
+    Some((parts.next()?, snapshot_time))
+}
+
+pub(crate) fn list_snapshots() -> rpc::ListSnapshotsReply {
+    rpc::ListSnapshotsReply {
+        snapshots: ReplicaIter::new()
+            .filter(|r| r.is_snapshot())
+            .map(|r| {
+                let name = r.get_uuid().to_owned();
+                let (replica, snapshot) = parse_snapshot_name(&name)
+                    .map(|(replica, time)| (replica.to_owned(), time))
+                    .unwrap_or_default();
+                rpc::Snapshot {
+                    pool: r.get_pool_name().to_owned(),
+                    replica,
+                    snapshot,
+                    size: r.get_size(),
+                    clones: r
+                        .as_lvol()
+                        .clones()
+                        .iter()
+                        .map(|c| c.name())
+                        .collect(),
+                    name,
+                }
+            })
+            .collect(),
     }
 }
 
@@ -595,15 +1548,19 @@ pub(crate) async fn stat_replicas() -> Result<rpc::StatReplicasReply, RpcError>
 
         match st {
             Ok(st) => {
+                let current = Counters {
+                    num_read_ops: st.num_read_ops,
+                    num_write_ops: st.num_write_ops,
+                    bytes_read: st.bytes_read,
+                    bytes_written: st.bytes_written,
+                    io_errors: 0,
+                };
+                let lifetime = stats_store::replica_lifetime(&uuid, current);
                 stats.push(rpc::ReplicaStats {
                     uuid,
                     pool,
-                    stats: Some(rpc::Stats {
-                        num_read_ops: st.num_read_ops,
-                        num_write_ops: st.num_write_ops,
-                        bytes_read: st.bytes_read,
-                        bytes_written: st.bytes_written,
-                    }),
+                    stats: Some(current.into()),
+                    lifetime_stats: Some(lifetime.into()),
                 });
             }
             Err(errno) => {
@@ -660,7 +1617,10 @@ pub(crate) async fn share_replica(
     if replica.get_share_type().is_none() {
         match want_share {
             rpc::ShareProtocolReplica::ReplicaIscsi => replica
-                .share(ShareType::Iscsi)
+                .share_on_portal(
+                    ShareType::Iscsi,
+                    &args.portal.map(Portal::from).unwrap_or_default(),
+                )
                 .await
                 .context(ShareReplica {
                     uuid: args.uuid.clone(),
@@ -677,3 +1637,255 @@ pub(crate) async fn share_replica(
         uri: replica.get_share_uri(),
     })
 }
+
+pub(crate) async fn reshare_replica(
+    args: rpc::ReshareReplicaRequest,
+) -> Result<rpc::ReshareReplicaReply, RpcError> {
+    let kind = match rpc::ShareProtocolReplica::from_i32(args.share) {
+        Some(rpc::ShareProtocolReplica::ReplicaNvmf) => ShareType::Nvmf,
+        Some(rpc::ShareProtocolReplica::ReplicaIscsi) => ShareType::Iscsi,
+        _ => Err(Error::InvalidProtocol {
+            protocol: args.share,
+        })
+        .context(ReshareReplica {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(replica) => replica,
+        None => Err(Error::ReplicaNotFound {}).context(ReshareReplica {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let grace = match args.grace_period {
+        0 => RESHARE_GRACE_PERIOD,
+        secs => Duration::from_secs(u64::from(secs)),
+    };
+    let previous_uri =
+        replica.reshare(kind, grace).await.context(ReshareReplica {
+            uuid: args.uuid.clone(),
+        })?;
+    Ok(rpc::ReshareReplicaReply {
+        uri: replica.get_share_uri(),
+        previous_uri: previous_uri.unwrap_or_default(),
+    })
+}
+
+pub(crate) async fn switch_replica_share(
+    args: rpc::SwitchReplicaShareRequest,
+) -> Result<rpc::SwitchReplicaShareReply, RpcError> {
+    let kind = match rpc::ShareProtocolReplica::from_i32(args.share) {
+        Some(rpc::ShareProtocolReplica::ReplicaNvmf) => Some(ShareType::Nvmf),
+        Some(rpc::ShareProtocolReplica::ReplicaIscsi) => Some(ShareType::Iscsi),
+        Some(rpc::ShareProtocolReplica::ReplicaNone) => None,
+        None => Err(Error::InvalidProtocol {
+            protocol: args.share,
+        })
+        .context(SwitchReplicaShare {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(replica) => replica,
+        None => Err(Error::ReplicaNotFound {}).context(SwitchReplicaShare {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let (previous_uri, nexuses) =
+        replica.switch_share(kind).await.context(SwitchReplicaShare {
+            uuid: args.uuid.clone(),
+        })?;
+    Ok(rpc::SwitchReplicaShareReply {
+        uri: replica.get_share_uri(),
+        previous_uri,
+        nexuses,
+    })
+}
+
+/// merge the adjacent changed clusters into ranges
+fn changed_ranges(
+    clusters: Vec<u64>,
+    cluster_size: u64,
+) -> Vec<rpc::ChangedRange> {
+    let mut ranges: Vec<rpc::ChangedRange> = Vec::new();
+    for cluster in clusters {
+        let offset = cluster * cluster_size;
+        match ranges.last_mut() {
+            Some(last) if last.offset + last.length == offset => {
+                last.length += cluster_size
+            }
+            _ => ranges.push(rpc::ChangedRange {
+                offset,
+                length: cluster_size,
+            }),
+        }
+    }
+    ranges
+}
+
+/// write the buffer through a descriptor and channel of which we hold no
+/// handle
+async fn write_at(
+    desc: *mut spdk_bdev_desc,
+    ch: *mut spdk_io_channel,
+    offset: u64,
+    buffer: &DmaBuf,
+) -> Result<(), CoreError> {
+    let status = IoCtxPool::submit(|cb, arg| unsafe {
+        spdk_bdev_write(
+            desc,
+            ch,
+            **buffer,
+            offset,
+            buffer.len() as u64,
+            Some(cb),
+            arg,
+        )
+    })
+    .await;
+
+    match status {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(CoreError::WriteFailed {
+            offset,
+            len: buffer.len(),
+        }),
+        Err(errno) => Err(CoreError::WriteDispatch {
+            source: Errno::from_i32(errno),
+            offset,
+            len: buffer.len(),
+        }),
+    }
+}
+
+pub(crate) async fn diff_snapshots(
+    args: rpc::DiffSnapshotsRequest,
+) -> Result<Vec<rpc::DiffSnapshotsReply>, RpcError> {
+    let target = match Replica::lookup(&args.target) {
+        Some(replica) => replica,
+        None => Err(Error::SnapshotNotFound {
+            snapshot: args.target.clone(),
+        })
+        .context(DiffSnapshots {
+            uuid: args.target.clone(),
+        })?,
+    };
+    let base = if args.base.is_empty() {
+        None
+    } else {
+        match Replica::lookup(&args.base) {
+            Some(base) if base.is_snapshot() => Some(base.as_lvol()),
+            Some(_) => Err(Error::NotSnapshot {
+                snapshot: args.base.clone(),
+            })
+            .context(DiffSnapshots {
+                uuid: args.target.clone(),
+            })?,
+            None => Err(Error::SnapshotNotFound {
+                snapshot: args.base.clone(),
+            })
+            .context(DiffSnapshots {
+                uuid: args.target.clone(),
+            })?,
+        }
+    };
+
+    let lvol = target.as_lvol();
+    let clusters = lvol
+        .changed_clusters(base.as_ref())
+        .context(InvalidDiff {})
+        .context(DiffSnapshots {
+            uuid: args.target.clone(),
+        })?;
+
+    let ranges = changed_ranges(clusters, lvol.cluster_size());
+    Ok(ranges
+        .chunks(DIFF_RANGES_PER_REPLY)
+        .map(|chunk| rpc::DiffSnapshotsReply {
+            ranges: chunk.to_vec(),
+        })
+        .collect())
+}
+
+pub(crate) async fn set_replica_flush_policy(
+    args: rpc::SetReplicaFlushPolicyRequest,
+) -> Result<(), RpcError> {
+    let policy = match rpc::ReplicaFlushPolicy::from_i32(args.policy) {
+        Some(rpc::ReplicaFlushPolicy::FlushForward) => FlushPolicy::Forward,
+        Some(rpc::ReplicaFlushPolicy::FlushBatch) => FlushPolicy::Batch,
+        Some(rpc::ReplicaFlushPolicy::FlushIgnore) => FlushPolicy::Ignore,
+        None => Err(Error::InvalidFlushPolicy {
+            policy: args.policy,
+        })
+        .context(SetFlushPolicy {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(replica) => replica,
+        None => Err(Error::ReplicaNotFound {}).context(SetFlushPolicy {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    replica
+        .set_flush_policy(policy)
+        .await
+        .context(SetFlushPolicy {
+            uuid: args.uuid,
+        })
+}
+
+pub(crate) async fn set_replica_qos(
+    args: rpc::SetBdevQosRequest,
+) -> Result<(), RpcError> {
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(replica) => replica,
+        None => Err(Error::ReplicaNotFound {}).context(SetReplicaQos {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    replica
+        .set_qos(args.limits.unwrap_or_default().into())
+        .await
+        .context(SetReplicaQos {
+            uuid: args.uuid,
+        })
+}
+
+pub(crate) async fn set_replica_metadata(
+    args: rpc::SetReplicaMetadataRequest,
+) -> Result<rpc::ReplicaMetadata, RpcError> {
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(replica) => replica,
+        None => Err(Error::ReplicaNotFound {}).context(SetReplicaMetadata {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let metadata = replica.set_metadata(args.metadata).await.context(
+        SetReplicaMetadata {
+            uuid: args.uuid.clone(),
+        },
+    )?;
+    Ok(rpc::ReplicaMetadata {
+        uuid: replica.get_uuid().to_string(),
+        metadata,
+    })
+}
+
+pub(crate) fn get_replica_metadata(
+    args: rpc::GetReplicaMetadataRequest,
+) -> Result<rpc::ReplicaMetadata, RpcError> {
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(replica) => replica,
+        None => Err(Error::ReplicaNotFound {}).context(GetReplicaMetadata {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let metadata = replica.get_metadata().context(GetReplicaMetadata {
+        uuid: args.uuid.clone(),
+    })?;
+    Ok(rpc::ReplicaMetadata {
+        uuid: replica.get_uuid().to_string(),
+        metadata,
+    })
+}
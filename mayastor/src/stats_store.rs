@@ -0,0 +1,346 @@
+//! Lifetime IO counters of the nexuses and replicas.
+//!
+//! The IO counters of a bdev start from zero whenever it is created, so that
+//! they are lost when mayastor restarts or a volume is published again. Here
+//! the counters are added up per uuid over the successive bdevs of a volume,
+//! and written periodically to the file given in the stats_store section of
+//! the config, from which they are read back on start. The counters of a
+//! replica are dropped when it is destroyed. Those of a nexus are kept, as
+//! the nexus of a volume is destroyed and created again whenever the volume
+//! is unpublished and published, until they are reset with ResetStats.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    ops::{Add, Sub},
+    sync::Mutex,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use rpc::mayastor as rpc;
+use snafu::{ResultExt, Snafu};
+use tonic::Status;
+
+use crate::{
+    bdev::{nexus::instances, Nexus, VerboseError},
+    core::{InThread, Mthread},
+    grpc::name_to_uuid,
+    replica::{Replica, ReplicaIter},
+    subsys::Config,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("No nexus or replica {} has IO counters", uuid))]
+    VolumeNotFound { uuid: String },
+    #[snafu(display("Failed to write the stats store {}", path))]
+    WriteStore { source: io::Error, path: String },
+}
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::VolumeNotFound {
+                ..
+            } => Status::not_found(e.to_string()),
+            Error::WriteStore {
+                ..
+            } => Status::internal(e.to_string()),
+        }
+    }
+}
+
+/// IO counters of a nexus or replica
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Counters {
+    pub num_read_ops: u64,
+    pub num_write_ops: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// failed IOs, only counted by nexuses
+    pub io_errors: u64,
+}
+
+impl Add for Counters {
+    type Output = Self;
+
+    fn add(self, o: Self) -> Self {
+        Self {
+            num_read_ops: self.num_read_ops + o.num_read_ops,
+            num_write_ops: self.num_write_ops + o.num_write_ops,
+            bytes_read: self.bytes_read + o.bytes_read,
+            bytes_written: self.bytes_written + o.bytes_written,
+            io_errors: self.io_errors + o.io_errors,
+        }
+    }
+}
+
+impl Sub for Counters {
+    type Output = Self;
+
+    fn sub(self, o: Self) -> Self {
+        Self {
+            num_read_ops: self.num_read_ops.saturating_sub(o.num_read_ops),
+            num_write_ops: self.num_write_ops.saturating_sub(o.num_write_ops),
+            bytes_read: self.bytes_read.saturating_sub(o.bytes_read),
+            bytes_written: self.bytes_written.saturating_sub(o.bytes_written),
+            io_errors: self.io_errors.saturating_sub(o.io_errors),
+        }
+    }
+}
+
+impl Counters {
+    /// true if any counter is below that of other, i.e. the counters are
+    /// those of a new bdev
+    fn below(&self, other: &Self) -> bool {
+        self.num_read_ops < other.num_read_ops
+            || self.num_write_ops < other.num_write_ops
+            || self.bytes_read < other.bytes_read
+            || self.bytes_written < other.bytes_written
+            || self.io_errors < other.io_errors
+    }
+}
+
+impl From<Counters> for rpc::Stats {
+    fn from(c: Counters) -> Self {
+        Self {
+            num_read_ops: c.num_read_ops,
+            num_write_ops: c.num_write_ops,
+            bytes_read: c.bytes_read,
+            bytes_written: c.bytes_written,
+        }
+    }
+}
+
+/// lifetime counters of a volume: those of the bdevs before the current one
+/// plus those of the current one, less those it had when last reset
+#[derive(Debug, Default)]
+struct Entry {
+    base: Counters,
+    last: Counters,
+    skip: Counters,
+}
+
+impl Entry {
+    fn lifetime(&self) -> Counters {
+        self.base + (self.last - self.skip)
+    }
+
+    fn update(&mut self, current: Counters) -> Counters {
+        if current.below(&self.last) {
+            self.base = self.lifetime();
+            self.skip = Counters::default();
+        }
+        self.last = current;
+        self.lifetime()
+    }
+
+    fn reset(&mut self) {
+        self.base = Counters::default();
+        self.skip = self.last;
+    }
+}
+
+static STORE: Lazy<Mutex<HashMap<String, Entry>>> =
+    Lazy::new(|| Mutex::new(load()));
+
+/// read the counters persisted by a previous run, if any
+fn load() -> HashMap<String, Entry> {
+    let path = match &Config::get().stats_store.path {
+        Some(path) => path.clone(),
+        None => return HashMap::new(),
+    };
+
+    let stored = match fs::read_to_string(&path) {
+        Ok(s) => serde_json::from_str::<HashMap<String, Counters>>(&s)
+            .unwrap_or_else(|e| {
+                warn!("ignoring the invalid stats store {}: {}", path, e);
+                HashMap::new()
+            }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            warn!("failed to read the stats store {}: {}", path, e);
+            HashMap::new()
+        }
+    };
+
+    info!("loaded the IO counters of {} volumes", stored.len());
+    stored
+        .into_iter()
+        .map(|(key, base)| {
+            (
+                key,
+                Entry {
+                    base,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect()
+}
+
+fn nexus_key(uuid: &str) -> String {
+    format!("nexus/{}", uuid)
+}
+
+fn replica_key(uuid: &str) -> String {
+    format!("replica/{}", uuid)
+}
+
+/// the lifetime counters of the volume given the current counters of its
+/// bdev
+fn lifetime(key: String, current: Counters) -> Counters {
+    STORE
+        .lock()
+        .unwrap()
+        .entry(key)
+        .or_default()
+        .update(current)
+}
+
+/// the lifetime counters of a nexus given the current counters of its bdev
+pub(crate) fn nexus_lifetime(nexus: &Nexus, current: Counters) -> Counters {
+    lifetime(nexus_key(name_to_uuid(&nexus.name)), current)
+}
+
+/// the lifetime counters of a replica given the current counters of its
+/// bdev
+pub(crate) fn replica_lifetime(uuid: &str, current: Counters) -> Counters {
+    lifetime(replica_key(uuid), current)
+}
+
+/// drop the counters of a replica which has been destroyed
+pub(crate) fn forget_replica(uuid: &str) {
+    STORE.lock().unwrap().remove(&replica_key(uuid));
+}
+
+/// the current counters of the bdev of a nexus
+async fn nexus_counters(nexus: &Nexus) -> Option<Counters> {
+    let io_stats = nexus.io_stats().await?;
+    let st = nexus.bdev.stats().await.ok()?;
+    Some(Counters {
+        num_read_ops: st.num_read_ops,
+        num_write_ops: st.num_write_ops,
+        bytes_read: st.bytes_read,
+        bytes_written: st.bytes_written,
+        io_errors: io_stats.io_errors,
+    })
+}
+
+/// the current counters of the bdev of a replica
+async fn replica_counters(replica: &Replica) -> Option<Counters> {
+    let st = replica.as_bdev().stats().await.ok()?;
+    Some(Counters {
+        num_read_ops: st.num_read_ops,
+        num_write_ops: st.num_write_ops,
+        bytes_read: st.bytes_read,
+        bytes_written: st.bytes_written,
+        io_errors: 0,
+    })
+}
+
+/// bring the lifetime counters of all nexuses and replicas up to date, the
+/// counters are sampled on the management thread
+async fn sample() {
+    InThread::new(Mthread::get_mgmt(), async {
+        for nexus in instances().iter() {
+            if let Some(current) = nexus_counters(nexus).await {
+                nexus_lifetime(nexus, current);
+            }
+        }
+
+        let uuids = ReplicaIter::new()
+            .map(|r| r.get_uuid().to_string())
+            .collect::<Vec<_>>();
+        for uuid in uuids {
+            // the replica may have been destroyed meanwhile
+            let replica = match Replica::lookup(&uuid) {
+                Some(replica) => replica,
+                None => continue,
+            };
+            if let Some(current) = replica_counters(&replica).await {
+                replica_lifetime(&uuid, current);
+            }
+        }
+    })
+    .await
+}
+
+/// write the lifetime counters to the file of the config, if any
+fn save() -> Result<(), Error> {
+    let path = match &Config::get().stats_store.path {
+        Some(path) => path.clone(),
+        None => return Ok(()),
+    };
+
+    let stored = STORE
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(key, entry)| (key.clone(), entry.lifetime()))
+        .collect::<HashMap<_, _>>();
+
+    // replace the file at once, such that a crash never leaves half of it
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, serde_json::to_string_pretty(&stored).unwrap())
+        .and_then(|_| fs::rename(&tmp, &path))
+        .context(WriteStore {
+            path,
+        })
+}
+
+/// sample and persist the lifetime counters, i.e. before shutting down
+pub(crate) async fn flush() {
+    sample().await;
+    if let Err(e) = save() {
+        error!("{}", e.verbose());
+    }
+}
+
+/// Reset the lifetime counters of the nexus and replica with the given
+/// uuid, or of all of them if it is empty.
+pub async fn reset(uuid: &str) -> Result<(), Error> {
+    sample().await;
+    {
+        let mut store = STORE.lock().unwrap();
+        if uuid.is_empty() {
+            store.values_mut().for_each(Entry::reset);
+        } else {
+            let keys = [nexus_key(uuid), replica_key(uuid)];
+            let entries = store
+                .iter_mut()
+                .filter(|(key, _)| keys.contains(key))
+                .map(|(_, entry)| entry)
+                .collect::<Vec<_>>();
+            if entries.is_empty() {
+                return Err(Error::VolumeNotFound {
+                    uuid: uuid.to_string(),
+                });
+            }
+            entries.into_iter().for_each(Entry::reset);
+        }
+    }
+    info!(
+        "reset the IO counters of {}",
+        if uuid.is_empty() { "all volumes" } else { uuid }
+    );
+    save()
+}
+
+/// persist the lifetime counters periodically, runs on the master reactor
+/// for as long as mayastor does
+pub(crate) async fn monitor() {
+    let opts = &Config::get().stats_store;
+    if opts.path.is_none() {
+        return;
+    }
+    let interval = Duration::from_secs(opts.interval_secs.max(1));
+
+    loop {
+        tokio::time::delay_for(interval).await;
+        flush().await;
+    }
+}
@@ -0,0 +1,201 @@
+//! Options which can be changed while mayastor is running, by reloading the
+//! config file on SIGHUP or with the ReloadConfig gRPC method. The config
+//! read at startup is immutable, so the reloaded values of these options are
+//! kept aside and take precedence over it. Changes to any other option in the
+//! file only take effect after a restart.
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+
+use crate::{
+    logger,
+    subsys::config::{
+        opts::{NodeLimitOpts, QosOpts},
+        Config,
+        Error,
+    },
+};
+
+/// the options of the config which can be changed while running
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveOpts {
+    /// log level or filter directives, as for RUST_LOG
+    pub log_level: Option<String>,
+    /// QoS limits a new nexus is created with
+    pub qos_defaults: QosOpts,
+    /// connection limits of the nvmf target
+    pub max_connects_per_sec: u32,
+    pub connect_burst: u32,
+    pub max_controllers_per_subsystem: u32,
+    pub keep_alive_timeout_ms: u32,
+    /// limits on the resources of the node
+    pub node_limits: NodeLimitOpts,
+}
+
+/// the options as last reloaded, none if they never have been
+static LIVE: Lazy<RwLock<Option<LiveOpts>>> = Lazy::new(|| RwLock::new(None));
+
+/// forget the options reloaded in the environment which has been shut down
+pub(super) fn reset() {
+    LIVE.write().unwrap().take();
+}
+
+impl From<&Config> for LiveOpts {
+    fn from(cfg: &Config) -> Self {
+        Self {
+            log_level: cfg.log_level.clone(),
+            qos_defaults: cfg.nexus_opts.qos_defaults,
+            max_connects_per_sec: cfg.nvmf_tcp_tgt_conf.max_connects_per_sec,
+            connect_burst: cfg.nvmf_tcp_tgt_conf.connect_burst,
+            max_controllers_per_subsystem: cfg
+                .nvmf_tcp_tgt_conf
+                .max_controllers_per_subsystem,
+            keep_alive_timeout_ms: cfg.nvmf_tcp_tgt_conf.keep_alive_timeout_ms,
+            node_limits: cfg.node_limits,
+        }
+    }
+}
+
+impl LiveOpts {
+    /// the options in effect
+    pub fn get() -> Self {
+        match LIVE.read().unwrap().as_ref() {
+            Some(live) => live.clone(),
+            None => Self::from(Config::get()),
+        }
+    }
+
+    /// write the options into a config, i.e. to export them
+    pub(crate) fn store(&self, cfg: &mut Config) {
+        cfg.log_level = self.log_level.clone();
+        cfg.nexus_opts.qos_defaults = self.qos_defaults;
+        cfg.nvmf_tcp_tgt_conf.max_connects_per_sec = self.max_connects_per_sec;
+        cfg.nvmf_tcp_tgt_conf.connect_burst = self.connect_burst;
+        cfg.nvmf_tcp_tgt_conf.max_controllers_per_subsystem =
+            self.max_controllers_per_subsystem;
+        cfg.nvmf_tcp_tgt_conf.keep_alive_timeout_ms =
+            self.keep_alive_timeout_ms;
+        cfg.node_limits = self.node_limits;
+    }
+
+    /// names of the options which differ between the two
+    fn changes(&self, new: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.log_level != new.log_level {
+            changes.push("log_level".to_string());
+        }
+        if self.qos_defaults != new.qos_defaults {
+            changes.push("nexus_opts.qos_defaults".to_string());
+        }
+        if self.max_connects_per_sec != new.max_connects_per_sec {
+            changes.push("nvmf_tcp_tgt_conf.max_connects_per_sec".to_string());
+        }
+        if self.connect_burst != new.connect_burst {
+            changes.push("nvmf_tcp_tgt_conf.connect_burst".to_string());
+        }
+        if self.max_controllers_per_subsystem
+            != new.max_controllers_per_subsystem
+        {
+            changes.push(
+                "nvmf_tcp_tgt_conf.max_controllers_per_subsystem".to_string(),
+            );
+        }
+        if self.keep_alive_timeout_ms != new.keep_alive_timeout_ms {
+            changes.push("nvmf_tcp_tgt_conf.keep_alive_timeout_ms".to_string());
+        }
+        if self.node_limits != new.node_limits {
+            changes.push("node_limits".to_string());
+        }
+        changes
+    }
+}
+
+/// sections of the config which changed but can not be applied while running
+fn restart_required(current: &Config, mut new: Config) -> Vec<&'static str> {
+    let mut sections = Vec::new();
+
+    // the live options are compared separately
+    LiveOpts::from(current).store(&mut new);
+
+    if current.nvmf_tcp_tgt_conf != new.nvmf_tcp_tgt_conf {
+        sections.push("nvmf_tcp_tgt_conf");
+    }
+    if current.iscsi_tgt_conf != new.iscsi_tgt_conf {
+        sections.push("iscsi_tgt_conf");
+    }
+    if current.nvme_bdev_opts != new.nvme_bdev_opts {
+        sections.push("nvme_bdev_opts");
+    }
+    if current.bdev_opts != new.bdev_opts {
+        sections.push("bdev_opts");
+    }
+    if current.nexus_opts != new.nexus_opts {
+        sections.push("nexus_opts");
+    }
+    if current.err_store_opts != new.err_store_opts {
+        sections.push("err_store_opts");
+    }
+    if current.pool_health_opts != new.pool_health_opts {
+        sections.push("pool_health_opts");
+    }
+    if current.event_hooks != new.event_hooks {
+        sections.push("event_hooks");
+    }
+    if current.event_bus != new.event_bus {
+        sections.push("event_bus");
+    }
+    if current.reactor_opts != new.reactor_opts {
+        sections.push("reactor_opts");
+    }
+    if current.stats_store != new.stats_store {
+        sections.push("stats_store");
+    }
+    if current.grpc != new.grpc {
+        sections.push("grpc");
+    }
+    sections
+}
+
+/// Read the config file again and apply the options which can be changed
+/// while running. Returns the names of the options which have changed.
+pub(crate) fn reload() -> Result<Vec<String>, Error> {
+    let cfg = Config::get();
+    let source = cfg.source.as_ref().ok_or(Error::NoSource)?;
+
+    let new = Config::read(source).map_err(|_| Error::ReadConfig {
+        path: source.clone(),
+    })?;
+
+    let live = LiveOpts::from(&new);
+    let sections = restart_required(cfg, new);
+    if !sections.is_empty() {
+        warn!(
+            "Changes to {} in {} require a restart",
+            sections.join(", "),
+            source
+        );
+    }
+
+    let current = LiveOpts::get();
+
+    if current.log_level != live.log_level {
+        // without a level in the config the current one is kept, the level
+        // given on the command line is not known here
+        if let Some(level) = live.log_level.as_ref() {
+            logger::set_level(level).map_err(|msg| Error::InvalidLogLevel {
+                level: level.clone(),
+                msg,
+            })?;
+        }
+    }
+
+    let changes = current.changes(&live);
+    *LIVE.write().unwrap() = Some(live);
+
+    if changes.is_empty() {
+        info!("Reloaded {}, nothing changed", source);
+    } else {
+        info!("Reloaded {}, changed {}", source, changes.join(", "));
+    }
+    Ok(changes)
+}
@@ -0,0 +1,59 @@
+//! Migration of the config files written by older versions of mayastor. The
+//! version of the layout of the config is recorded in the file, the files
+//! without one have been written before the layout was versioned and are
+//! version 0. The YAML of a file is migrated one version at a time before it
+//! is parsed, as an older layout may have options which are no longer known.
+//! The file is written in the current layout the next time the config is
+//! exported. A file of a newer version is refused rather than having the
+//! options this mayastor does not know about dropped from it.
+
+use serde_yaml::{Mapping, Value};
+
+/// version of the layout of the config written by this mayastor
+pub const CONFIG_VERSION: u32 = 1;
+
+type Migration = fn(&mut Mapping) -> Result<(), String>;
+
+/// the migration of each version to the next, by the version
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [v0_to_v1];
+
+/// Version 1 only adds the version to the layout.
+fn v0_to_v1(_config: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+/// Migrate the YAML of a config file to the current version, returns the
+/// version it had.
+pub(super) fn migrate(config: &mut Value) -> Result<u32, String> {
+    let map = match config {
+        Value::Mapping(map) => map,
+        _ => return Err("The config file is not a mapping".to_string()),
+    };
+
+    let key = Value::String("version".to_string());
+    let version = match map.get(&key) {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .filter(|v| *v <= u64::from(u32::MAX))
+            .ok_or_else(|| format!("Invalid config version {:?}", v))?
+            as u32,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "The config file is of version {}, newer than version {} of \
+             this mayastor",
+            version, CONFIG_VERSION
+        ));
+    }
+
+    for (from, migration) in
+        MIGRATIONS.iter().enumerate().skip(version as usize)
+    {
+        migration(map).map_err(|e| {
+            format!("Failed to migrate the config from version {}: {}", from, e)
+        })?;
+    }
+    map.insert(key, Value::Number(u64::from(CONFIG_VERSION).into()));
+    Ok(version)
+}
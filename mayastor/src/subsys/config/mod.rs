@@ -10,14 +10,13 @@ use std::{
     convert::TryFrom,
     fmt::Display,
     fs,
-    fs::File,
-    io::Write,
     path::Path,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
 };
 
 use byte_unit::Byte;
 use futures::FutureExt;
-use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use spdk_sys::{
@@ -29,37 +28,86 @@ use spdk_sys::{
 };
 
 use crate::{
-    bdev::{nexus::instances, nexus_create, VerboseError},
-    core::{Bdev, Cores, Reactor},
+    bdev::{
+        dev::link::link_uri,
+        nexus::{instances, nexus_key_rotation},
+        nexus_create,
+        VerboseError,
+    },
+    core::{Bdev, Cores, InThread, Mthread, Reactor, Reactors},
+    grpc::{done_operations, restore_operations},
     jsonrpc::{jsonrpc_register, Code, RpcErrorCode},
+    logger,
     nexus_uri::bdev_create,
     pool::{create_pool, PoolsIter},
     replica::{self, ReplicaIter, ShareType},
     subsys::{
-        config::opts::{
-            BdevOpts,
-            ErrStoreOpts,
-            GetOpts,
-            IscsiTgtOpts,
-            NexusOpts,
-            NvmeBdevOpts,
-            NvmfTgtConfig,
+        config::{
+            live::LiveOpts,
+            opts::{
+                BdevOpts,
+                ErrStoreOpts,
+                EventBusOpts,
+                EventHookOpts,
+                GetOpts,
+                GrpcOpts,
+                IscsiTgtOpts,
+                NexusOpts,
+                NodeLimitOpts,
+                NvmeBdevOpts,
+                NvmfTgtConfig,
+                PoolHealthOpts,
+                ReactorOpts,
+                StatsStoreOpts,
+            },
         },
         NvmfSubsystem,
     },
 };
 
 #[derive(Debug, Clone, Snafu)]
-pub enum Error {}
+pub enum Error {
+    #[snafu(display("No config file was given to reload"))]
+    NoSource,
+    #[snafu(display("Failed to read the config file {}", path))]
+    ReadConfig { path: String },
+    #[snafu(display("Invalid log level {}: {}", level, msg))]
+    InvalidLogLevel { level: String, msg: String },
+}
 
 impl RpcErrorCode for Error {
     fn rpc_error_code(&self) -> Code {
         Code::InternalError
     }
 }
+
+impl From<Error> for tonic::Status {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::NoSource {
+                ..
+            } => Self::failed_precondition(e.to_string()),
+            Error::ReadConfig {
+                ..
+            } => Self::invalid_argument(e.to_string()),
+            Error::InvalidLogLevel {
+                ..
+            } => Self::invalid_argument(e.to_string()),
+        }
+    }
+}
+pub(crate) mod live;
+mod migrate;
 pub(crate) mod opts;
+pub(crate) mod store;
+
+pub use migrate::CONFIG_VERSION;
+pub use store::{ConfigStore, EtcdStore, FileStore, StoreError};
 
-pub static CONFIG: OnceCell<Config> = OnceCell::new();
+/// the config of the initialized environment. It is leaked, so that it can
+/// be referenced for the lifetime of the process, also once it has been
+/// replaced by the config of an environment which is initialized again.
+static CONFIG: AtomicPtr<Config> = AtomicPtr::new(ptr::null_mut());
 
 pub struct ConfigSubsystem(pub *mut spdk_subsystem);
 
@@ -73,18 +121,15 @@ impl ConfigSubsystem {
     extern "C" fn init() {
         debug!("mayastor subsystem init");
 
-        // write the config out to disk where the target is the same as source
-        // if no config file is given, simply return Ok().
+        // write the config out to the store it has been loaded from, if no
+        // config file or etcd is given, simply return Ok().
         jsonrpc_register::<(), _, _, Error>("mayastor_config_export", |_| {
             let f = async move {
-                let cfg = Config::get().refresh().unwrap();
-                if let Some(target) = cfg.source.as_ref() {
-                    if let Err(e) = cfg.write(&target) {
-                        error!("error writing config file {} {}", target, e);
-                    }
-                } else {
+                if store::get().is_none() {
                     warn!("request to save config file but no source file was given, guess \
                     you have to scribble it down yourself {}", '\u{1f609}');
+                } else if let Err(e) = Config::export_config().await {
+                    error!("error exporting config {}", e);
                 }
                 Ok(())
             };
@@ -132,8 +177,17 @@ impl ConfigSubsystem {
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct Config {
+    /// version of the layout of the config, 0 for the files written before
+    /// the layout was versioned
+    pub version: u32,
     /// location of the config file that we loaded
     pub source: Option<String>,
+    /// log level or filter directives as for RUST_LOG, overrides the level
+    /// given on the command line
+    pub log_level: Option<String>,
+    /// ID of the cluster the node belongs to, recorded in the labels of the
+    /// pools it creates, which are not imported by nodes of other clusters
+    pub cluster_id: Option<String>,
     /// these options are not set/copied but are applied
     /// on target creation.
     pub nvmf_tcp_tgt_conf: NvmfTgtConfig,
@@ -147,6 +201,23 @@ pub struct Config {
     pub nexus_opts: NexusOpts,
     /// error store opts
     pub err_store_opts: ErrStoreOpts,
+    /// thresholds of the health of the pool devices
+    pub pool_health_opts: PoolHealthOpts,
+    /// commands run on events of the nexuses
+    pub event_hooks: EventHookOpts,
+    /// state changes published over the message bus
+    pub event_bus: EventBusOpts,
+    /// placement of the reactors on the cores
+    pub reactor_opts: ReactorOpts,
+    /// limits on the nexuses, replicas and shares of the node
+    pub node_limits: NodeLimitOpts,
+    /// where the lifetime IO counters are persisted
+    pub stats_store: StatsStoreOpts,
+    /// endpoints of the gRPC server and their auth policies
+    pub grpc: GrpcOpts,
+    /// replies of the gRPC calls made with an idempotency key, returned
+    /// again to their retries
+    pub operations: Vec<Operation>,
     ///
     /// The next options are intended for usage during testing
     ///
@@ -158,6 +229,9 @@ pub struct Config {
     pub pools: Option<Vec<Pool>>,
     /// any  base bdevs created implicitly share them over nvmf
     pub implicit_share_base: bool,
+    /// names of the bdevs to enable the latency histogram of, after all
+    /// bdevs have been created
+    pub latency_histograms: Option<Vec<String>>,
 }
 
 impl Config {
@@ -167,17 +241,52 @@ impl Config {
     where
         F: FnOnce() -> Config,
     {
-        CONFIG.get_or_init(f)
+        let current = CONFIG.load(Ordering::Acquire);
+        if !current.is_null() {
+            return unsafe { &*current };
+        }
+
+        let config = Box::into_raw(Box::new(f()));
+        match CONFIG.compare_exchange(
+            ptr::null_mut(),
+            config,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => unsafe { &*config },
+            Err(current) => {
+                drop(unsafe { Box::from_raw(config) });
+                unsafe { &*current }
+            }
+        }
     }
 
     /// mostly similar as above, but we do not need to pass a closure
     pub fn get() -> &'static Self {
-        CONFIG.get().unwrap()
+        let config = CONFIG.load(Ordering::Acquire);
+        assert!(!config.is_null(), "the config has not been initialized");
+        unsafe { &*config }
+    }
+
+    /// forget the config of the environment which has been shut down, along
+    /// with its store and reloaded options, such that the environment which
+    /// is initialized next loads its own
+    pub(crate) fn reset() {
+        CONFIG.store(ptr::null_mut(), Ordering::Release);
+        store::reset();
+        live::reset();
+    }
+
+    /// read the config file again and apply the options which can be changed
+    /// while running, returns the names of those which have changed
+    pub fn reload() -> Result<Vec<String>, Error> {
+        live::reload()
     }
 
     /// read the config file from disk. If the config file is empty, return the
     /// default config, but store the empty config file with in the struct to be
-    /// used during saving to disk.
+    /// used during saving to disk. A config file of an older version is
+    /// migrated to the current one.
     pub fn read<P>(file: P) -> Result<Config, ()>
     where
         P: AsRef<Path> + Display + ToString,
@@ -188,13 +297,7 @@ impl Config {
         // only parse the file when its not empty, otherwise
         // just store the filepath to write it out later
         if !cfg.is_empty() {
-            match serde_yaml::from_slice(&cfg) {
-                Ok(v) => config = v,
-                Err(e) => {
-                    error!("{}", e);
-                    return Err(());
-                }
-            };
+            config = Self::from_yaml(&cfg, &file.to_string())?;
         } else {
             info!("Config file {} is empty, reverting to default config", file);
             // the file is empty
@@ -213,7 +316,10 @@ impl Config {
         // gets loaded with the current settings, as we know that these
         // are immutable, we can copy them with any locks held
         let mut current = Config {
+            version: CONFIG_VERSION,
             source: self.source.clone(),
+            log_level: self.log_level.clone(),
+            cluster_id: self.cluster_id.clone(),
             nvmf_tcp_tgt_conf: self.nvmf_tcp_tgt_conf.get(),
             iscsi_tgt_conf: self.iscsi_tgt_conf.get(),
             nvme_bdev_opts: self.nvme_bdev_opts.get(),
@@ -224,6 +330,15 @@ impl Config {
             pools: None,
             implicit_share_base: true,
             err_store_opts: self.err_store_opts.get(),
+            pool_health_opts: self.pool_health_opts.get(),
+            event_hooks: self.event_hooks.get(),
+            event_bus: self.event_bus.get(),
+            reactor_opts: self.reactor_opts.get(),
+            node_limits: self.node_limits.get(),
+            stats_store: self.stats_store.get(),
+            grpc: self.grpc.get(),
+            operations: done_operations(),
+            latency_histograms: None,
         };
 
         // collect nexus bdevs and insert them into the config
@@ -238,6 +353,7 @@ impl Config {
                     .iter()
                     .map(|child| child.name.clone())
                     .collect::<Vec<_>>(),
+                key_rotation: nexus_key_rotation::checkpoint(&nexus.name),
             })
             .collect::<Vec<_>>();
 
@@ -257,17 +373,29 @@ impl Config {
 
         current.nexus_bdevs = Some(nexus_bdevs);
 
+        // the options which have been reloaded since
+        LiveOpts::get().store(&mut current);
+
         // collect any pools that are on the system, and insert them
         let pools = PoolsIter::new()
             .map(|p| Pool {
                 name: p.get_name().into(),
-                disks: vec![p.get_base_bdev().name()],
+                uuid: p.get_uuid(),
+                disks: vec![link_uri(&p.get_base_bdev())
+                    .unwrap_or_else(|| p.get_base_bdev().name())],
                 blk_size: p.get_base_bdev().block_len(),
                 io_if: 0, // AIO
                 replicas: ReplicaIter::new()
                     .map(|p| Replica {
                         name: p.get_uuid().to_string(),
+                        uuid: p.get_lvol_uuid(),
                         share: p.get_share_type(),
+                        retiring: replica::retiring_share(p.get_uuid()).map(
+                            |(share, deadline)| RetiringShare {
+                                share,
+                                deadline,
+                            },
+                        ),
                     })
                     .collect::<Vec<_>>(),
             })
@@ -275,22 +403,85 @@ impl Config {
 
         current.pools = Some(pools);
 
+        // collect the bdevs with their latency histogram enabled
+        current.latency_histograms = Bdev::bdev_first().map(|bdevs| {
+            bdevs
+                .into_iter()
+                .filter(|b| b.histogram_enabled())
+                .map(|b| b.name())
+                .collect::<Vec<_>>()
+        });
+
         Ok(current)
     }
 
-    /// write the current configuration to disk
+    /// Load the config from the store, which it is exported to from now on.
+    /// The config file is read instead if nothing has been stored in etcd
+    /// yet, so that a node moves its config to etcd once it is given the
+    /// endpoints.
+    pub fn load(
+        store: Box<dyn ConfigStore>,
+        file: Option<&str>,
+    ) -> Result<Config, ()> {
+        let location = store.location();
+        let yaml = store.load().map_err(|e| {
+            error!("Failed to load config from {}: {}", location, e);
+        })?;
+        store::set(store);
+
+        match (yaml, file) {
+            (Some(yaml), _) => {
+                let mut config = Self::from_yaml(&yaml, &location)?;
+                config.source = file.map(String::from);
+                Ok(config)
+            }
+            (None, Some(file)) => Self::read(file),
+            (None, None) => Ok(Config::default()),
+        }
+    }
+
+    /// parse the YAML of a config, migrated to the current version
+    fn from_yaml(yaml: &[u8], location: &str) -> Result<Config, ()> {
+        let parse = || -> Result<(Config, u32), String> {
+            let mut value: serde_yaml::Value =
+                serde_yaml::from_slice(yaml).map_err(|e| e.to_string())?;
+            let version = migrate::migrate(&mut value)?;
+            let config =
+                serde_yaml::from_value(value).map_err(|e| e.to_string())?;
+            Ok((config, version))
+        };
+
+        match parse() {
+            Ok((config, version)) => {
+                if version < CONFIG_VERSION {
+                    info!(
+                        "Migrated config of {} from version {} to {}",
+                        location, version, CONFIG_VERSION
+                    );
+                }
+                Ok(config)
+            }
+            Err(e) => {
+                error!("Invalid config of {}: {}", location, e);
+                Err(())
+            }
+        }
+    }
+
+    /// write the current configuration to disk. It is written to a temporary
+    /// file first, which then replaces the config file, so that a crash
+    /// while writing does not leave a partial config file behind.
     pub fn write<P>(&self, file: P) -> Result<(), std::io::Error>
     where
         P: AsRef<Path>,
     {
-        if let Ok(s) = serde_yaml::to_string(&self) {
-            let mut file = File::create(file)?;
-            return file.write_all(s.as_bytes());
-        }
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "failed to serialize config",
-        ))
+        let s = serde_yaml::to_string(&self).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "failed to serialize config",
+            )
+        })?;
+        store::write_file(file.as_ref(), s.as_bytes())
     }
 
     /// apply the hybrid configuration that is loaded from YAML. Hybrid in the
@@ -302,6 +493,13 @@ impl Config {
         self.nvme_bdev_opts.set();
         self.bdev_opts.set();
         self.iscsi_tgt_conf.set();
+        restore_operations(&self.operations);
+
+        if let Some(level) = self.log_level.as_ref() {
+            if let Err(e) = logger::set_level(level) {
+                error!("Invalid log level {}: {}", level, e);
+            }
+        }
     }
 
     /// create any nexus bdevs any failure will be logged, but we will silently
@@ -315,6 +513,9 @@ impl Config {
         if let Some(bdevs) = self.nexus_bdevs.as_ref() {
             for nexus in bdevs {
                 info!("creating nexus {}", nexus.name);
+                if let Some(block) = nexus.key_rotation {
+                    nexus_key_rotation::restore_checkpoint(&nexus.name, block);
+                }
                 match Byte::from_str(&nexus.size) {
                     Ok(val) => {
                         if let Err(e) = nexus_create(
@@ -388,10 +589,49 @@ impl Config {
         if let Some(pools) = self.pools.as_ref() {
             for pool in pools {
                 info!("creating pool {}", pool.name);
-                if let Err(e) = create_pool(pool.into()).await {
+                match create_pool(pool.into()).await {
+                    Ok(created)
+                        if !pool.uuid.is_empty()
+                            && created.uuid != pool.uuid =>
+                    {
+                        warn!(
+                            "Pool {} has uuid {} rather than {}, it has been \
+                             created again",
+                            pool.name, created.uuid, pool.uuid
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!(
+                            "Failed to create pool {}. {}",
+                            pool.name,
+                            e.verbose()
+                        );
+                        failures += 1;
+                    }
+                }
+            }
+        }
+        failures
+    }
+
+    /// Enable the latency histograms of the bdevs listed in the config file.
+    async fn enable_latency_histograms(&self) -> usize {
+        let mut failures = 0;
+        if let Some(names) = self.latency_histograms.as_ref() {
+            for name in names {
+                let result = match Bdev::lookup_by_name(name) {
+                    Some(bdev) => bdev.enable_histogram(true).await,
+                    None => {
+                        error!("Failed to find bdev {} for histogram", name);
+                        failures += 1;
+                        continue;
+                    }
+                };
+                if let Err(e) = result {
                     error!(
-                        "Failed to create pool {}. {}",
-                        pool.name,
+                        "Failed to enable latency histogram of {}. {}",
+                        name,
                         e.verbose()
                     );
                     failures += 1;
@@ -412,15 +652,19 @@ impl Config {
                         .filter(|r| r.share.is_some())
                         .filter_map(|replica| {
                             ReplicaIter::new()
-                                .find(|dev| dev.get_uuid() == replica.name)
-                                .map(|dev| (dev, replica.share.unwrap()))
+                                .find(|dev| {
+                                    dev.get_lvol_uuid() == replica.uuid
+                                        || dev.get_uuid() == replica.name
+                                })
+                                .map(|dev| (dev, replica))
                         })
-                        .collect::<Vec<(replica::Replica, ShareType)>>()
+                        .collect::<Vec<(replica::Replica, &Replica)>>()
                 })
                 .flatten()
-                .collect::<Vec<(replica::Replica, ShareType)>>();
+                .collect::<Vec<(replica::Replica, &Replica)>>();
 
-            for (dev, share) in replicas {
+            for (dev, replica) in replicas {
+                let share = replica.share.unwrap();
                 if let Err(error) = dev.share(share).await {
                     error!(
                         "Failed to share {} over {:?}, error={}",
@@ -428,6 +672,22 @@ impl Config {
                         share,
                         error
                     );
+                    continue;
+                }
+
+                // the old share of a reshare which has not been removed yet
+                if let Some(retiring) = replica.retiring.as_ref() {
+                    if let Err(error) = dev
+                        .restore_retiring(retiring.share, retiring.deadline)
+                        .await
+                    {
+                        error!(
+                            "Failed to share {} over {:?}, error={}",
+                            dev.get_uuid(),
+                            retiring.share,
+                            error
+                        );
+                    }
                 }
             }
         }
@@ -460,6 +720,7 @@ impl Config {
             }
 
             errors += self.create_base_bdevs().await;
+            errors += self.enable_latency_histograms().await;
 
             if errors != 0 {
                 warn!("Not all bdevs({}) where imported successfully", errors);
@@ -467,14 +728,30 @@ impl Config {
         });
     }
 
-    /// exports the current configuration to the mayastor config file
-    pub(crate) fn export_config() -> Result<(), std::io::Error> {
-        let cfg = Config::get().refresh().unwrap();
-        match cfg.source.as_ref() {
-            Some(target) => cfg.write(&target),
-            // no config file to export to
-            None => Ok(()),
-        }
+    /// exports the current configuration to the store it has been loaded
+    /// from, the mayastor config file or etcd. The snapshot is taken and
+    /// serialized on the management thread, a config file is written out on
+    /// a blocking thread so that it does not stall the reactor
+    pub(crate) async fn export_config() -> Result<(), StoreError> {
+        let store = match store::get() {
+            Some(store) => store,
+            // no config file or etcd to export to
+            None => return Ok(()),
+        };
+
+        let hdl = Reactors::current().spawn_local(InThread::new(
+            Mthread::get_mgmt(),
+            async {
+                let cfg = Config::get().refresh().unwrap();
+                serde_yaml::to_string(&cfg).map_err(|e| {
+                    StoreError::Serialize {
+                        reason: e.to_string(),
+                    }
+                })
+            },
+        ));
+        let yaml = hdl.await.unwrap()?;
+        store.save(yaml.into_bytes()).await
     }
 }
 
@@ -492,6 +769,10 @@ pub struct NexusBdev {
     pub size: String,
     /// the children the nexus should be created on
     pub children: Vec<String>,
+    /// the block up to which the nexus has been re-encrypted by a rotation
+    /// of its key which has not finished
+    #[serde(default)]
+    pub key_rotation: Option<u64>,
 }
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -509,6 +790,9 @@ pub struct BaseBdev {
 pub struct Pool {
     /// name of the pool to be created or imported
     pub name: String,
+    /// uuid of the pool, empty if not known
+    #[serde(default)]
+    pub uuid: String,
     /// bdevs to create outside of the nexus control
     pub disks: Vec<String>,
     /// the block_size the pool should use
@@ -527,15 +811,46 @@ impl From<&Pool> for rpc::mayastor::CreatePoolRequest {
             disks: o.disks.clone(),
             block_size: o.blk_size,
             io_if: o.io_if,
+            adopt: false,
+            idempotency_key: String::new(),
         }
     }
 }
 
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+/// The reply of a gRPC call made with an idempotency key
+pub struct Operation {
+    /// name of the method, i.e. CreatePool
+    pub method: String,
+    /// the idempotency key of the call
+    pub key: String,
+    /// the reply message
+    pub reply: serde_json::Value,
+}
+
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 /// Pool replicas that we share via `ShareType`
 pub struct Replica {
     /// name of the replica
     pub name: String,
+    /// uuid of the lvol of the replica, by which it is found when it has
+    /// been renamed, empty if not known
+    #[serde(default)]
+    pub uuid: String,
     /// share type if shared
     pub share: Option<ShareType>,
+    /// the old share of a reshare which is kept for the rest of its grace
+    /// period
+    #[serde(default)]
+    pub retiring: Option<RetiringShare>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// The old share of a replica which is being reshared
+pub struct RetiringShare {
+    /// protocol of the old share
+    pub share: ShareType,
+    /// end of the grace period in milliseconds since the epoch, after which
+    /// the old share is removed
+    pub deadline: u64,
 }
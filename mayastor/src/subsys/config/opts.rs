@@ -2,7 +2,7 @@
 //! types. Naturally this is a good reason, but it means we have to copy things
 //! around. If the structures change, we will know about it because we use the
 //! from trait, and we are not allowed to skip or use different types.
-use std::ptr::copy_nonoverlapping;
+use std::{ptr::copy_nonoverlapping, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
@@ -18,7 +18,7 @@ use spdk_sys::{
     spdk_nvmf_transport_opts,
 };
 
-use crate::bdev::ActionType;
+use crate::{bdev::ActionType, core::QosLimits, subsys::NvmfTransport};
 
 pub trait GetOpts {
     fn get(&self) -> Self;
@@ -45,6 +45,32 @@ pub struct NexusOpts {
     pub iscsi_nexus_port: u16,
     /// Port for replica target portal
     pub iscsi_replica_port: u16,
+    /// address of the portal of the nexus targets shared on another portal
+    /// than the default one
+    pub iscsi_nexus_portal: IscsiPortalOpts,
+    /// address of the portal of the replica targets shared on another
+    /// portal than the default one
+    pub iscsi_replica_portal: IscsiPortalOpts,
+    /// limit of the memory used by the nvme controllers of nvmf and pcie
+    /// children together in MiB, 0 for no limit
+    pub nvme_children_mem_limit_mb: u64,
+    /// QoS limits a new nexus is created with
+    pub qos_defaults: QosOpts,
+    /// seconds the address of a host name in the URI of an nvmf child is
+    /// reused for before it is resolved again
+    pub dns_cache_secs: u64,
+    /// seconds after which the reads and writes of the labels and metadata
+    /// of the children are aborted, 0 to wait for them forever
+    pub child_io_timeout_secs: u64,
+    /// retries of the reads and writes that fail on a child
+    pub child_io_retry: ChildRetryOpts,
+    /// write-intent log of the regions with writes in flight
+    pub intent_log: IntentLogOpts,
+    /// degrading the children whose writes are too slow
+    pub child_latency: ChildLatencyOpts,
+    /// use the bdevs shared by our own nvmf target directly as the children
+    /// of the nexuses, rather than connecting to ourselves over the network
+    pub local_children: bool,
 }
 
 /// Default nvmf port used for replicas.
@@ -67,6 +93,16 @@ impl Default for NexusOpts {
             iscsi_enable: true,
             iscsi_nexus_port: ISCSI_PORT_NEXUS,
             iscsi_replica_port: ISCSI_PORT_REPLICA,
+            iscsi_nexus_portal: IscsiPortalOpts::default(),
+            iscsi_replica_portal: IscsiPortalOpts::default(),
+            nvme_children_mem_limit_mb: 0,
+            qos_defaults: QosOpts::default(),
+            dns_cache_secs: 30,
+            child_io_timeout_secs: 30,
+            child_io_retry: ChildRetryOpts::default(),
+            intent_log: IntentLogOpts::default(),
+            child_latency: ChildLatencyOpts::default(),
+            local_children: true,
         }
     }
 }
@@ -77,6 +113,133 @@ impl GetOpts for NexusOpts {
     }
 }
 
+/// Address of an iSCSI portal, which is the pod IP if neither field is set.
+/// The address takes precedence over the interface.
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IscsiPortalOpts {
+    /// IPv4 address to listen on
+    pub address: String,
+    /// network interface whose first IPv4 address is listened on
+    pub interface: String,
+}
+
+/// QoS rate limits, where 0 means no limit. The IOPS limit must be a
+/// multiple of 1000.
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QosOpts {
+    pub rw_ios_per_sec: u64,
+    pub rw_mbytes_per_sec: u64,
+    pub r_mbytes_per_sec: u64,
+    pub w_mbytes_per_sec: u64,
+}
+
+impl From<QosOpts> for QosLimits {
+    fn from(o: QosOpts) -> Self {
+        Self {
+            rw_ios_per_sec: o.rw_ios_per_sec,
+            rw_mbytes_per_sec: o.rw_mbytes_per_sec,
+            r_mbytes_per_sec: o.r_mbytes_per_sec,
+            w_mbytes_per_sec: o.w_mbytes_per_sec,
+        }
+    }
+}
+
+/// Retries of the reads and writes of a nexus which fail on a child because
+/// its path is down. Such an IO is resubmitted to the same child after a
+/// backoff, which doubles for every retry, and the failure is only held
+/// against the child once the retries are exhausted. Media and other errors
+/// are never retried on the same child, but a read which fails can be failed
+/// over to the other children, each of which it is tried on once.
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChildRetryOpts {
+    /// number of times an IO is resubmitted to a child, 0 to not retry
+    pub max_retries: u8,
+    /// milliseconds before the first retry
+    pub backoff_ms: u64,
+    /// upper bound of the milliseconds between two retries
+    pub max_backoff_ms: u64,
+    /// retry a read which failed on a child on the other children
+    pub read_failover: bool,
+}
+
+impl Default for ChildRetryOpts {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff_ms: 10,
+            max_backoff_ms: 1000,
+            read_failover: false,
+        }
+    }
+}
+
+impl ChildRetryOpts {
+    /// the backoff before the given retry, counting from 0
+    pub fn backoff(&self, retry: u8) -> Duration {
+        let factor = 1u64.checked_shl(u32::from(retry)).unwrap_or(u64::MAX);
+        let ms = self.backoff_ms.saturating_mul(factor);
+        Duration::from_millis(std::cmp::min(ms, self.max_backoff_ms))
+    }
+}
+
+/// Write-intent log of a nexus. The regions of the nexus with writes in
+/// flight are marked in the metadata partition of its children before the
+/// writes are dispatched, such that after an unclean shutdown only the
+/// regions that were marked are resynced when the nexus is created again.
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IntentLogOpts {
+    /// enable the log for new nexus instances
+    pub enable: bool,
+    /// milliseconds a region without writes in flight stays marked before
+    /// it is cleared again
+    pub clean_interval_ms: u64,
+}
+
+impl Default for IntentLogOpts {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            clean_interval_ms: 5000,
+        }
+    }
+}
+
+/// Latency objective of the writes to the children of a nexus. A write to
+/// the nexus completes once all its children have written it, so a single
+/// slow child holds up all writes. A child which completes more writes than
+/// allowed past the threshold within the window is degraded, such that the
+/// writes go to the other children only, as long as there is another
+/// healthy child. The child is caught up by a rebuild after the delay, of
+/// the regions marked in the intent log if it is enabled, or as a whole.
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChildLatencyOpts {
+    /// milliseconds a write to a child may take, 0 to never degrade a child
+    /// due to its latency
+    pub threshold_ms: u64,
+    /// number of writes past the threshold a child may have in the window
+    pub max_slow_writes: u32,
+    /// milliseconds the slow writes are counted in
+    pub window_ms: u64,
+    /// milliseconds after which a degraded child is rebuilt
+    pub catch_up_delay_ms: u64,
+}
+
+impl Default for ChildLatencyOpts {
+    fn default() -> Self {
+        Self {
+            threshold_ms: 0,
+            max_slow_writes: 16,
+            window_ms: 10_000,
+            catch_up_delay_ms: 30_000,
+        }
+    }
+}
+
 #[serde(default, deny_unknown_fields)]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NvmfTgtConfig {
@@ -86,6 +249,26 @@ pub struct NvmfTgtConfig {
     pub max_namespaces: u32,
     /// TCP transport options
     pub opts: TcpTransportOpts,
+    /// listen on the RDMA transport next to TCP, which needs an RDMA
+    /// capable NIC on the node
+    pub rdma: bool,
+    /// RDMA transport options
+    pub rdma_opts: TcpTransportOpts,
+    /// transport of the URI a nexus or replica is shared at, when it is
+    /// shared over more than one
+    pub share_transport: NvmfTransport,
+    /// new controllers per second allowed from a single source address, 0
+    /// for no limit
+    pub max_connects_per_sec: u32,
+    /// controllers a source address may create in a burst before the rate
+    /// limit kicks in
+    pub connect_burst: u32,
+    /// max number of controllers of a subsystem, 0 for no limit
+    pub max_controllers_per_subsystem: u32,
+    /// controllers whose host has not sent a keep-alive for this long are
+    /// disconnected, also when the host asked for a longer keep-alive
+    /// timeout or for none at all, 0 to leave it to the host
+    pub keep_alive_timeout_ms: u32,
 }
 
 impl From<NvmfTgtConfig> for Box<spdk_nvmf_target_opts> {
@@ -109,6 +292,13 @@ impl Default for NvmfTgtConfig {
             name: "mayastor_target".to_string(),
             max_namespaces: 110,
             opts: TcpTransportOpts::default(),
+            rdma: false,
+            rdma_opts: TcpTransportOpts::rdma(),
+            share_transport: NvmfTransport::Tcp,
+            max_connects_per_sec: 0,
+            connect_burst: 32,
+            max_controllers_per_subsystem: 0,
+            keep_alive_timeout_ms: 0,
         }
     }
 }
@@ -172,6 +362,21 @@ impl Default for TcpTransportOpts {
     }
 }
 
+impl TcpTransportOpts {
+    /// the defaults of the RDMA transport, which uses the same settings
+    fn rdma() -> Self {
+        Self {
+            max_queue_depth: 128,
+            io_unit_size: 8192,
+            ch2_success: false,
+            num_shared_buf: 4095,
+            buf_cache_size: 32,
+            max_srq_depth: 4096,
+            ..Default::default()
+        }
+    }
+}
+
 /// we cannot add derives for YAML to these structs directly, so we need to
 /// copy them. The upside though, is that if the FFI structures change, we will
 /// know about it during compile time.
@@ -220,7 +425,7 @@ pub struct NvmeBdevOpts {
     /// ioq polling period
     nvme_ioq_poll_period_us: u64,
     /// number of requests per nvme IO queue
-    io_queue_requests: u32,
+    pub(crate) io_queue_requests: u32,
     /// allow for batching of commands
     delay_cmd_submit: bool,
 }
@@ -516,3 +721,200 @@ impl GetOpts for ErrStoreOpts {
         self.clone()
     }
 }
+
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolHealthOpts {
+    /// mark a pool read-only once its device reports more media errors,
+    /// 0 disables the check
+    pub max_media_errors: u64,
+
+    /// mark a pool read-only once the available spare of its device drops
+    /// below this percentage, 0 disables the check
+    pub min_available_spare: u8,
+}
+
+impl Default for PoolHealthOpts {
+    fn default() -> Self {
+        Self {
+            max_media_errors: 0,
+            min_available_spare: 0,
+        }
+    }
+}
+
+impl GetOpts for PoolHealthOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
+
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventHookOpts {
+    /// command run by the shell when a child of a nexus is faulted
+    pub child_faulted: Option<String>,
+
+    /// command run by the shell when the rebuild of a child has finished,
+    /// whether it succeeded or not
+    pub rebuild_complete: Option<String>,
+
+    /// command run by the shell when a nexus is published
+    pub nexus_published: Option<String>,
+
+    /// seconds after which a hook still running is killed
+    pub timeout_secs: u64,
+}
+
+impl Default for EventHookOpts {
+    fn default() -> Self {
+        Self {
+            child_faulted: None,
+            rebuild_complete: None,
+            nexus_published: None,
+            timeout_secs: 30,
+        }
+    }
+}
+
+impl GetOpts for EventHookOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
+
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventBusOpts {
+    /// publish the state changes of the pools, nexuses and their children
+    /// over the message bus, if mayastor is connected to one
+    pub enable: bool,
+
+    /// NATS subject the events are published to
+    pub subject: String,
+
+    /// number of events queued for the message bus, before any more are
+    /// dropped
+    pub queue_size: usize,
+}
+
+impl Default for EventBusOpts {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            subject: "mayastor.events".into(),
+            queue_size: 1024,
+        }
+    }
+}
+
+impl GetOpts for EventBusOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
+
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ReactorOpts {
+    /// the cores to run a reactor on, i.e. "2,3,8-11", which replaces the
+    /// reactor mask given on the command line
+    pub core_list: Option<String>,
+
+    /// reserve the first core of the list for management (gRPC, config
+    /// export and stats) and do not run IO on it
+    pub dedicated_mgmt_core: bool,
+}
+
+impl GetOpts for ReactorOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
+
+/// Limits on the resources of the node, where 0 means no limit.
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NodeLimitOpts {
+    /// number of nexuses
+    pub max_nexuses: u64,
+    /// number of replicas, including snapshots
+    pub max_replicas: u64,
+    /// number of nexuses and replicas which are shared
+    pub max_shares: u64,
+    /// total size in bytes of the replicas which are not snapshots
+    pub max_capacity: u64,
+}
+
+impl GetOpts for NodeLimitOpts {
+    fn get(&self) -> Self {
+        *self
+    }
+}
+
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsStoreOpts {
+    /// file the lifetime IO counters of the nexuses and replicas are kept
+    /// in, they are not persisted if none
+    pub path: Option<String>,
+
+    /// seconds between writes of the counters to the file
+    pub interval_secs: u64,
+}
+
+impl Default for StatsStoreOpts {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval_secs: 60,
+        }
+    }
+}
+
+impl GetOpts for StatsStoreOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
+
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GrpcOpts {
+    /// endpoints the gRPC server listens on besides the one given on the
+    /// command line, i.e. a Unix domain socket for the co-located CSI node
+    /// plugin and TCP for the control plane
+    pub endpoints: Vec<GrpcEndpointOpts>,
+}
+
+impl GetOpts for GrpcOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
+
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GrpcEndpointOpts {
+    /// "ip:port" of a TCP endpoint, or "unix:" followed by the path of a
+    /// Unix domain socket
+    pub address: String,
+    /// file holding the token callers must pass in the authorization header
+    /// as "Bearer <token>", none if no token is required
+    pub token_file: Option<String>,
+    /// only require the token for the methods which change the state of
+    /// mayastor, so that monitoring needs no token
+    pub token_for_changes_only: bool,
+    /// PEM file of the certificate the endpoint serves TLS with, along with
+    /// its private key, none for plain text
+    pub tls_cert_file: Option<String>,
+    /// PEM file of the private key of the certificate
+    pub tls_key_file: Option<String>,
+    /// PEM file of the CA which must have issued the certificates of the
+    /// callers, none if the callers are not authenticated by TLS
+    pub tls_client_ca_file: Option<String>,
+    /// only allow the methods which do not change the state of mayastor
+    pub read_only: bool,
+    /// allow the methods which bypass the typed API, i.e. JsonRpcCall
+    pub admin: bool,
+}
@@ -0,0 +1,290 @@
+//! Where the config of the node is persisted: the config file given on the
+//! command line, or etcd, so that the state of the node, i.e. the children
+//! of its nexuses, survives the loss of its disks and can be read by the
+//! control plane. The config is stored under /mayastor/nodes/<node>/config
+//! in etcd, as the YAML of the config file. etcd is reached over its JSON
+//! gateway of the v3 API, trying the endpoints in turn, and with a token
+//! for the user if authentication is enabled in etcd.
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::RwLock,
+};
+
+use async_trait::async_trait;
+use hyper::{Body, Client, Method, Request};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum StoreError {
+    #[snafu(display("Failed to access config file {}: {}", path, source))]
+    ConfigFile {
+        source: std::io::Error,
+        path: String,
+    },
+    #[snafu(display("Failed to request {} from etcd: {}", uri, reason))]
+    Etcd { uri: String, reason: String },
+    #[snafu(display("No etcd endpoint is reachable: {}", reason))]
+    EtcdUnreachable { reason: String },
+    #[snafu(display("Failed to serialize the config: {}", reason))]
+    Serialize { reason: String },
+}
+
+/// A backend the config is loaded from and exported to.
+#[async_trait(?Send)]
+pub trait ConfigStore: Send + Sync {
+    /// where the config is stored, for the log
+    fn location(&self) -> String;
+    /// Read the YAML of the config, none if nothing has been stored yet.
+    /// This is done once at startup, before the reactors run.
+    fn load(&self) -> Result<Option<Vec<u8>>, StoreError>;
+    /// Store the YAML of the config, replacing what has been stored before.
+    async fn save(&self, yaml: Vec<u8>) -> Result<(), StoreError>;
+}
+
+/// the store the config is exported to, if any. It is leaked, so that it
+/// can be referenced after it has been reset.
+static STORE: Lazy<RwLock<Option<&'static dyn ConfigStore>>> =
+    Lazy::new(|| RwLock::new(None));
+
+pub(crate) fn set(store: Box<dyn ConfigStore>) {
+    info!("config is stored in {}", store.location());
+    let mut current = STORE.write().unwrap();
+    if current.is_some() {
+        warn!("config store has been set already");
+    } else {
+        *current = Some(Box::leak(store));
+    }
+}
+
+pub(crate) fn get() -> Option<&'static dyn ConfigStore> {
+    *STORE.read().unwrap()
+}
+
+/// forget the store of the environment which has been shut down
+pub(super) fn reset() {
+    STORE.write().unwrap().take();
+}
+
+/// Write the file as a whole, by writing a temporary file first which then
+/// replaces the file, so that a crash while writing does not leave a partial
+/// file behind.
+pub(crate) fn write_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    // the rename is only durable once the directory is synced
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// The config file given on the command line.
+pub struct FileStore {
+    path: String,
+}
+
+impl FileStore {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl ConfigStore for FileStore {
+    fn location(&self) -> String {
+        format!("config file {}", self.path)
+    }
+
+    fn load(&self) -> Result<Option<Vec<u8>>, StoreError> {
+        match fs::read(&self.path) {
+            Ok(yaml) if yaml.is_empty() => Ok(None),
+            Ok(yaml) => Ok(Some(yaml)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(ConfigFile {
+                path: self.path.clone(),
+            }),
+        }
+    }
+
+    async fn save(&self, yaml: Vec<u8>) -> Result<(), StoreError> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || write_file(Path::new(&path), &yaml))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            .and_then(|r| r)
+            .context(ConfigFile {
+                path: self.path.clone(),
+            })
+    }
+}
+
+/// The config of the node in etcd.
+pub struct EtcdStore {
+    /// base URIs of the endpoints, i.e. http://10.0.0.1:2379
+    endpoints: Vec<String>,
+    /// key of the config of the node
+    key: String,
+    /// user and password, if authentication is enabled
+    credentials: Option<(String, String)>,
+}
+
+impl EtcdStore {
+    /// The store of the config of the node, on the comma separated endpoints
+    /// given as host:port or as an http URI.
+    pub fn new(
+        endpoints: &str,
+        node: &str,
+        credentials: Option<(String, String)>,
+    ) -> Self {
+        let endpoints = endpoints
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .map(|e| {
+                if e.contains("://") {
+                    e.trim_end_matches('/').to_string()
+                } else {
+                    format!("http://{}", e)
+                }
+            })
+            .collect();
+        Self {
+            endpoints,
+            key: format!("/mayastor/nodes/{}/config", node),
+            credentials,
+        }
+    }
+
+    /// POST the JSON body to the path of the v3 API on the endpoint
+    async fn post(
+        &self,
+        endpoint: &str,
+        path: &str,
+        token: Option<&str>,
+        body: Value,
+    ) -> Result<Value, StoreError> {
+        let uri = format!("{}{}", endpoint, path);
+        let failed = |reason: String| StoreError::Etcd {
+            uri: uri.clone(),
+            reason,
+        };
+
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&uri)
+            .header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", token);
+        }
+        let request = request
+            .body(Body::from(body.to_string()))
+            .map_err(|e| failed(e.to_string()))?;
+
+        let response = Client::new()
+            .request(request)
+            .await
+            .map_err(|e| failed(e.to_string()))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| failed(e.to_string()))?;
+        if !status.is_success() {
+            return Err(failed(format!(
+                "{}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        serde_json::from_slice(&body).map_err(|e| failed(e.to_string()))
+    }
+
+    /// POST the request to the first endpoint which answers, authenticated
+    /// as the user, if any
+    async fn call(&self, path: &str, body: Value) -> Result<Value, StoreError> {
+        let mut reason = String::from("no endpoints");
+        for endpoint in &self.endpoints {
+            let token = match &self.credentials {
+                Some((name, password)) => {
+                    let auth = json!({ "name": name, "password": password });
+                    match self
+                        .post(endpoint, "/v3/auth/authenticate", None, auth)
+                        .await
+                    {
+                        Ok(reply) => reply["token"].as_str().map(String::from),
+                        Err(e) => {
+                            reason = e.to_string();
+                            continue;
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            match self
+                .post(endpoint, path, token.as_deref(), body.clone())
+                .await
+            {
+                Ok(reply) => return Ok(reply),
+                Err(e) => {
+                    warn!("{}", e);
+                    reason = e.to_string();
+                }
+            }
+        }
+        Err(StoreError::EtcdUnreachable {
+            reason,
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl ConfigStore for EtcdStore {
+    fn location(&self) -> String {
+        format!("etcd {} at {}", self.key, self.endpoints.join(","))
+    }
+
+    fn load(&self) -> Result<Option<Vec<u8>>, StoreError> {
+        let range = json!({ "key": base64::encode(&self.key) });
+        let mut rt = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .map_err(|e| StoreError::EtcdUnreachable {
+                reason: e.to_string(),
+            })?;
+        let reply = rt.block_on(self.call("/v3/kv/range", range))?;
+
+        match reply["kvs"][0]["value"].as_str() {
+            Some(value) => {
+                base64::decode(value)
+                    .map(Some)
+                    .map_err(|e| StoreError::Etcd {
+                        uri: self.key.clone(),
+                        reason: e.to_string(),
+                    })
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, yaml: Vec<u8>) -> Result<(), StoreError> {
+        let put = json!({
+            "key": base64::encode(&self.key),
+            "value": base64::encode(&yaml),
+        });
+        self.call("/v3/kv/put", put).await.map(|_| ())
+    }
+}
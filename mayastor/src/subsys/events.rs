@@ -0,0 +1,316 @@
+//! Events of the state changes of the pools, nexuses and their children,
+//! published over the message bus so that the control plane learns about
+//! them without having to poll the list calls of the gRPC server.
+//!
+//! Each event is a JSON object naming the node it comes from, the time it
+//! happened at in milliseconds since the epoch and the event itself in the
+//! `event` field, i.e.:
+//!
+//! ```json
+//! {"node":"node-1","timestamp":1600000000000,"event":"child_faulted",
+//!  "nexus":"nexus-<uuid>","uuid":"<uuid>","child":"nvmf://...",
+//!  "reason":"io errors"}
+//! ```
+//!
+//! The events are published to the subject of the event_bus section of the
+//! config. They are queued for the message bus, so publishing one never
+//! holds up a reactor, and are dropped if mayastor is not connected to a
+//! message bus or the queue is full. The control plane therefore still has
+//! to list the objects once it (re)connects, but not periodically.
+//!
+//! The events are dispatched to the subscribers within mayastor as well,
+//! i.e. the watch calls of the gRPC server, whether or not they are
+//! published over the message bus.
+
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::channel::mpsc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::MayastorEnvironment,
+    grpc::name_to_uuid,
+    nats::message_bus_publish,
+    subsys::Config,
+};
+
+/// A change of the state of a pool, nexus or child.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// a pool has been created or imported
+    PoolCreated { pool: String, disks: Vec<String> },
+    /// a pool has become read-only
+    PoolDegraded { pool: String, reason: String },
+    /// a pool has been destroyed
+    PoolDestroyed { pool: String },
+    /// a nexus has been created
+    NexusCreated { nexus: String, uuid: String },
+    /// a nexus has lost a child, but IO still flows
+    NexusDegraded { nexus: String, uuid: String },
+    /// a nexus has been destroyed
+    NexusDestroyed { nexus: String, uuid: String },
+    /// a child has been added to a nexus
+    ChildAdded {
+        nexus: String,
+        uuid: String,
+        child: String,
+    },
+    /// a child has been removed from a nexus
+    ChildRemoved {
+        nexus: String,
+        uuid: String,
+        child: String,
+    },
+    /// a child of a nexus has been faulted
+    ChildFaulted {
+        nexus: String,
+        uuid: String,
+        child: String,
+        reason: String,
+    },
+    /// the rebuild of a child from another child has started
+    RebuildStarted {
+        nexus: String,
+        uuid: String,
+        child: String,
+        source: String,
+    },
+    /// the rebuild of a child has finished in the given state
+    RebuildCompleted {
+        nexus: String,
+        uuid: String,
+        child: String,
+        state: String,
+    },
+    /// a nexus or replica has been shared at the uri
+    SharePublished {
+        kind: String,
+        name: String,
+        uri: String,
+    },
+}
+
+impl Event {
+    /// the name of the pool the event is about, if any
+    pub fn pool(&self) -> Option<&str> {
+        match self {
+            Self::PoolCreated {
+                pool,
+                ..
+            }
+            | Self::PoolDegraded {
+                pool,
+                ..
+            }
+            | Self::PoolDestroyed {
+                pool,
+            } => Some(pool),
+            _ => None,
+        }
+    }
+
+    /// the name of the nexus the event is about, if any
+    pub fn nexus(&self) -> Option<&str> {
+        match self {
+            Self::NexusCreated {
+                nexus,
+                ..
+            }
+            | Self::NexusDegraded {
+                nexus,
+                ..
+            }
+            | Self::NexusDestroyed {
+                nexus,
+                ..
+            }
+            | Self::ChildAdded {
+                nexus,
+                ..
+            }
+            | Self::ChildRemoved {
+                nexus,
+                ..
+            }
+            | Self::ChildFaulted {
+                nexus,
+                ..
+            }
+            | Self::RebuildStarted {
+                nexus,
+                ..
+            }
+            | Self::RebuildCompleted {
+                nexus,
+                ..
+            } => Some(nexus),
+            Self::SharePublished {
+                kind,
+                name,
+                ..
+            } if kind == "nexus" => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// The message published for an event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventMessage {
+    /// name of the node the event happened on
+    pub node: String,
+    /// milliseconds since the epoch
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl EventMessage {
+    /// the message of an event which happened just now on this node
+    pub fn new(event: Event) -> Self {
+        Self {
+            node: MayastorEnvironment::global_node_name().to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            event,
+        }
+    }
+}
+
+/// the subscribers to the events within mayastor
+static SUBSCRIBERS: Lazy<Mutex<Vec<mpsc::UnboundedSender<EventMessage>>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Receive the events published from now on, until the receiver is dropped.
+pub fn subscribe() -> mpsc::UnboundedReceiver<EventMessage> {
+    let (sender, receiver) = mpsc::unbounded();
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    receiver
+}
+
+/// Dispatch the event to the subscribers and queue it to be published on the
+/// message bus, if enabled.
+pub fn publish(event: Event) {
+    let message = EventMessage::new(event);
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|s| s.unbounded_send(message.clone()).is_ok());
+
+    let opts = &Config::get().event_bus;
+    if !opts.enable {
+        return;
+    }
+
+    let payload = match serde_json::to_vec(&message) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("failed to serialize event {:?}: {}", message.event, e);
+            return;
+        }
+    };
+
+    if !message_bus_publish(&opts.subject, payload) {
+        debug!("event {:?} has not been published", message.event);
+    }
+}
+
+pub(crate) fn pool_created(pool: &str, disks: &[String]) {
+    publish(Event::PoolCreated {
+        pool: pool.to_string(),
+        disks: disks.to_vec(),
+    });
+}
+
+pub(crate) fn pool_degraded(pool: &str, reason: &str) {
+    publish(Event::PoolDegraded {
+        pool: pool.to_string(),
+        reason: reason.to_string(),
+    });
+}
+
+pub(crate) fn pool_destroyed(pool: &str) {
+    publish(Event::PoolDestroyed {
+        pool: pool.to_string(),
+    });
+}
+
+pub(crate) fn nexus_created(nexus: &str) {
+    publish(Event::NexusCreated {
+        nexus: nexus.to_string(),
+        uuid: name_to_uuid(nexus).to_string(),
+    });
+}
+
+pub(crate) fn nexus_destroyed(nexus: &str) {
+    publish(Event::NexusDestroyed {
+        nexus: nexus.to_string(),
+        uuid: name_to_uuid(nexus).to_string(),
+    });
+}
+
+pub(crate) fn child_added(nexus: &str, child: &str) {
+    publish(Event::ChildAdded {
+        nexus: nexus.to_string(),
+        uuid: name_to_uuid(nexus).to_string(),
+        child: child.to_string(),
+    });
+}
+
+pub(crate) fn child_removed(nexus: &str, child: &str) {
+    publish(Event::ChildRemoved {
+        nexus: nexus.to_string(),
+        uuid: name_to_uuid(nexus).to_string(),
+        child: child.to_string(),
+    });
+}
+
+pub(crate) fn nexus_degraded(nexus: &str) {
+    publish(Event::NexusDegraded {
+        nexus: nexus.to_string(),
+        uuid: name_to_uuid(nexus).to_string(),
+    });
+}
+
+pub(crate) fn child_faulted(nexus: &str, child: &str, reason: &str) {
+    publish(Event::ChildFaulted {
+        nexus: nexus.to_string(),
+        uuid: name_to_uuid(nexus).to_string(),
+        child: child.to_string(),
+        reason: reason.to_string(),
+    });
+}
+
+pub(crate) fn rebuild_started(nexus: &str, child: &str, source: &str) {
+    publish(Event::RebuildStarted {
+        nexus: nexus.to_string(),
+        uuid: name_to_uuid(nexus).to_string(),
+        child: child.to_string(),
+        source: source.to_string(),
+    });
+}
+
+pub(crate) fn rebuild_completed(nexus: &str, child: &str, state: &str) {
+    publish(Event::RebuildCompleted {
+        nexus: nexus.to_string(),
+        uuid: name_to_uuid(nexus).to_string(),
+        child: child.to_string(),
+        state: state.to_string(),
+    });
+}
+
+/// The nexus or replica, as the kind says, has been shared at the uri.
+pub(crate) fn share_published(kind: &str, name: &str, uri: &str) {
+    publish(Event::SharePublished {
+        kind: kind.to_string(),
+        name: name.to_string(),
+        uri: uri.to_string(),
+    });
+}
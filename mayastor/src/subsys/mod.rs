@@ -2,16 +2,43 @@
 //! Main file to register additional subsystems
 
 pub use config::{
-    opts::NexusOpts,
+    live::LiveOpts,
+    opts::{
+        ChildLatencyOpts,
+        ChildRetryOpts,
+        EventBusOpts,
+        EventHookOpts,
+        GrpcEndpointOpts,
+        GrpcOpts,
+        IntentLogOpts,
+        IscsiPortalOpts,
+        NexusOpts,
+        NodeLimitOpts,
+        PoolHealthOpts,
+        QosOpts,
+        ReactorOpts,
+        StatsStoreOpts,
+    },
     BaseBdev,
     Config,
+    ConfigStore,
     ConfigSubsystem,
+    Error as ConfigError,
+    EtcdStore,
+    FileStore,
     NexusBdev,
+    Operation,
     Pool,
+    StoreError,
+    CONFIG_VERSION,
 };
+pub use events::{subscribe as subscribe_events, Event, EventMessage};
 pub use nvmf::{
+    connection_stats,
+    ConnectionStats,
     Error as NvmfError,
     NvmfSubsystem,
+    NvmfTransport,
     SubType,
     Target as NvmfTarget,
 };
@@ -24,6 +51,7 @@ use spdk_sys::{
 use crate::subsys::nvmf::Nvmf;
 
 mod config;
+pub(crate) mod events;
 mod nvmf;
 
 pub(crate) fn register_subsystem() {
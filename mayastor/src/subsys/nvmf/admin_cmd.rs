@@ -1,29 +1,35 @@
 //! Handlers for custom NVMe Admin commands
 
-use spdk_sys::{spdk_bdev, spdk_bdev_desc, spdk_io_channel, spdk_nvmf_request};
+use spdk_sys::{
+    spdk_bdev,
+    spdk_bdev_desc,
+    spdk_io_channel,
+    spdk_nvme_cpl,
+    spdk_nvme_status,
+    spdk_nvmf_request,
+};
 
 use crate::{
     bdev::nexus::nexus_io::nvme_admin_opc,
-    core::Bdev,
+    core::{Bdev, InThread, Mthread, Reactors},
     replica::Replica,
 };
 
-/// NVMf custom command handler for opcode c0h
-/// Called from nvmf_ctrlr_process_admin_cmd
-/// Return: <0 for any error, caller handles it as unsupported opcode
-extern "C" fn nvmf_create_snapshot_hdlr(req: *mut spdk_nvmf_request) -> i32 {
-    debug!("nvmf_create_snapshot_hdlr {:?}", req);
-
+/// the replica of the only namespace of the subsystem the request is for,
+/// with the descriptor and channel of the target on its bdev
+fn request_replica(
+    req: *mut spdk_nvmf_request,
+) -> Option<(Replica, *mut spdk_bdev_desc, *mut spdk_io_channel)> {
     let subsys = unsafe { spdk_sys::spdk_nvmf_request_get_subsystem(req) };
     if subsys.is_null() {
         debug!("subsystem is null");
-        return -1;
+        return None;
     }
 
     /* Only process this request if it has exactly one namespace */
     if unsafe { spdk_sys::spdk_nvmf_subsystem_get_max_nsid(subsys) } != 1 {
         debug!("multiple namespaces");
-        return -1;
+        return None;
     }
 
     /* Forward to first namespace if it supports NVME admin commands */
@@ -38,17 +44,29 @@ extern "C" fn nvmf_create_snapshot_hdlr(req: *mut spdk_nvmf_request) -> i32 {
     if rc != 0 {
         /* No bdev found for this namespace. Continue. */
         debug!("no bdev found");
-        return -1;
+        return None;
     }
 
-    let bd = Bdev::from(bdev);
-    if let Some(replica) = Replica::from_bdev(&bd) {
-        let cmd = unsafe { &*spdk_sys::spdk_nvmf_request_get_cmd(req) };
-        let snapshot_time = unsafe {
-            cmd.__bindgen_anon_1.cdw10 as u64
-                | (cmd.__bindgen_anon_2.cdw11 as u64) << 32
-        };
-        let snapshot_name = format!("{}-snap-{}", bd.name(), snapshot_time);
+    Replica::from_bdev(&Bdev::from(bdev)).map(|r| (r, desc, ch))
+}
+
+/// the snapshot time the nexus has put in cdw10 and cdw11
+fn snapshot_time(req: *mut spdk_nvmf_request) -> u64 {
+    let cmd = unsafe { &*spdk_sys::spdk_nvmf_request_get_cmd(req) };
+    unsafe {
+        cmd.__bindgen_anon_1.cdw10 as u64
+            | (cmd.__bindgen_anon_2.cdw11 as u64) << 32
+    }
+}
+
+/// NVMf custom command handler for opcode c0h
+/// Called from nvmf_ctrlr_process_admin_cmd
+/// Return: <0 for any error, caller handles it as unsupported opcode
+extern "C" fn nvmf_create_snapshot_hdlr(req: *mut spdk_nvmf_request) -> i32 {
+    debug!("nvmf_create_snapshot_hdlr {:?}", req);
+
+    if let Some((replica, _, _)) = request_replica(req) {
+        let snapshot_name = replica.snapshot_name(snapshot_time(req));
         replica.create_snapshot(req, &snapshot_name);
         1 // SPDK_NVMF_REQUEST_EXEC_STATUS_ASYNCHRONOUS
     } else {
@@ -56,12 +74,57 @@ extern "C" fn nvmf_create_snapshot_hdlr(req: *mut spdk_nvmf_request) -> i32 {
     }
 }
 
-/// Register custom NVMe admin command handler
+/// NVMf custom command handler for opcode c1h, which rolls the replica back
+/// to its snapshot taken at the time in cdw10 and cdw11. The copy runs as a
+/// future in the context of the thread of the request, which owns the
+/// channel the writes go through.
+extern "C" fn nvmf_restore_snapshot_hdlr(req: *mut spdk_nvmf_request) -> i32 {
+    debug!("nvmf_restore_snapshot_hdlr {:?}", req);
+
+    let (replica, desc, ch) = match request_replica(req) {
+        Some(r) => r,
+        None => return -1,
+    };
+    let thread = match Mthread::current() {
+        Some(thread) => thread,
+        None => return -1,
+    };
+
+    let time = snapshot_time(req);
+    Reactors::current().send_future(InThread::new(thread, async move {
+        let sc = match replica.restore_snapshot(time, desc, ch).await {
+            Ok(_) => 0,
+            Err(e) => {
+                error!("failed to restore snapshot {}: {}", time, e);
+                0x06 // SPDK_NVME_SC_INTERNAL_DEVICE_ERROR
+            }
+        };
+
+        let rsp: &mut spdk_nvme_cpl =
+            unsafe { &mut *spdk_sys::spdk_nvmf_request_get_response(req) };
+        let nvme_status: &mut spdk_nvme_status =
+            unsafe { &mut rsp.__bindgen_anon_1.status };
+        nvme_status.set_sct(0); // SPDK_NVME_SCT_GENERIC
+        nvme_status.set_sc(sc);
+
+        unsafe {
+            spdk_sys::spdk_nvmf_request_complete(req);
+        }
+    }));
+
+    1 // SPDK_NVMF_REQUEST_EXEC_STATUS_ASYNCHRONOUS
+}
+
+/// Register custom NVMe admin command handlers
 pub fn setup_create_snapshot_hdlr() {
     unsafe {
         spdk_sys::spdk_nvmf_set_custom_admin_cmd_hdlr(
             nvme_admin_opc::CREATE_SNAPSHOT,
             Some(nvmf_create_snapshot_hdlr),
         );
+        spdk_sys::spdk_nvmf_set_custom_admin_cmd_hdlr(
+            nvme_admin_opc::RESTORE_SNAPSHOT,
+            Some(nvmf_restore_snapshot_hdlr),
+        );
     }
 }
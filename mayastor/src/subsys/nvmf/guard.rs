@@ -0,0 +1,399 @@
+//!
+//! Protection of the target against connection storms. An initiator stuck in
+//! a retry loop can create controllers faster than they are torn down, each
+//! of them taking queue pairs and buffers that the healthy volumes need.
+//!
+//! New controllers are looked for by a poller on the thread of the
+//! subsystems. Those of a source address that exceeds its rate, or of a
+//! subsystem that has reached its maximum number of controllers, are
+//! disconnected again. Both limits are set in the target configuration and
+//! are off by default, they can be changed by reloading the configuration.
+//!
+//! The same poller reaps the controllers of hosts which have vanished. A
+//! host which crashed never closes its connections, so its controllers would
+//! hold on to their queue pairs, and hold up the stop of their subsystem,
+//! until TCP gives up on them minutes later. With a keep-alive timeout set
+//! in the target configuration, a controller whose host has not sent a
+//! keep-alive for that long is disconnected, as is one whose host asked for
+//! no keep-alive at all. Hosts asking for a shorter timeout are left to the
+//! keep-alive timer of the controller.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    os::raw::c_void,
+    ptr,
+    sync::Mutex,
+    time::Instant,
+};
+
+use once_cell::sync::Lazy;
+
+use spdk_sys::{
+    spdk_get_ticks,
+    spdk_get_ticks_hz,
+    spdk_nvme_transport_id,
+    spdk_nvmf_ctrlr,
+    spdk_nvmf_qpair,
+    spdk_nvmf_qpair_disconnect,
+    spdk_nvmf_qpair_get_peer_trid,
+};
+
+use crate::{
+    core::Mthread,
+    ffihelper::AsStr,
+    subsys::{
+        nvmf::subsystem::{NvmfSubsystem, SubType},
+        LiveOpts,
+    },
+};
+
+/// interval of the poller looking for new controllers in microseconds
+pub(crate) const GUARD_POLL_PERIOD_US: u64 = 100_000;
+
+/// sources which have not connected for this long are forgotten, their
+/// bucket would have filled up again by then
+const IDLE_BUCKET_SECS: f64 = 60.0;
+
+/// connections of a single source address
+#[derive(Debug, Default, Clone)]
+pub struct SourceStats {
+    pub address: String,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub reaped: u64,
+}
+
+/// controllers of a single subsystem
+#[derive(Debug, Default, Clone)]
+pub struct ShareStats {
+    pub nqn: String,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub reaped: u64,
+}
+
+/// counters of the controllers created on the target since it was started
+#[derive(Debug, Default, Clone)]
+pub struct ConnectionStats {
+    pub accepted: u64,
+    /// rejected because the source address exceeded its rate
+    pub rejected_rate: u64,
+    /// rejected because the subsystem had too many controllers
+    pub rejected_controllers: u64,
+    /// disconnected because their host stopped sending keep-alives
+    pub reaped: u64,
+    pub sources: Vec<SourceStats>,
+    pub shares: Vec<ShareStats>,
+}
+
+/// counters of a single source address or subsystem
+#[derive(Debug, Default)]
+struct Counters {
+    accepted: u64,
+    rejected_rate: u64,
+    rejected_controllers: u64,
+    reaped: u64,
+}
+
+/// counters per source address
+static STATS: Lazy<Mutex<HashMap<String, Counters>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// counters per subsystem by its NQN
+static SHARES: Lazy<Mutex<HashMap<String, Counters>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// token bucket limiting the rate of new controllers of a source address
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// a controller seen by the guard
+#[derive(Debug, Clone, Copy)]
+struct Seen {
+    /// accepted and not reaped since
+    admitted: bool,
+    /// tick it was first seen at
+    since: u64,
+}
+
+#[derive(Debug, Default)]
+struct Guard {
+    /// controllers seen per subsystem by their id
+    known: HashMap<String, HashMap<u16, Seen>>,
+    buckets: HashMap<String, Bucket>,
+}
+
+thread_local! {
+    static GUARD: RefCell<Guard> = RefCell::new(Guard::default());
+}
+
+/// what became of a new controller
+enum Verdict {
+    Accepted,
+    RejectedRate,
+    RejectedControllers,
+    Reaped,
+}
+
+impl Guard {
+    /// take a token from the bucket of the source address
+    fn admit(&mut self, source: &str, rate: u32, burst: u32) -> bool {
+        if rate == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let burst = f64::from(burst.max(1));
+        let bucket = self.buckets.entry(source.to_string()).or_insert(Bucket {
+            tokens: burst,
+            last: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * f64::from(rate)).min(burst);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn poll(&mut self) {
+        let cfg = LiveOpts::get();
+        let mut known = HashMap::new();
+
+        if let Some(first) = NvmfSubsystem::first() {
+            for ss in first.into_iter() {
+                if ss.subtype() != SubType::Nvme {
+                    continue;
+                }
+
+                let nqn = ss.get_nqn();
+                let previous = self.known.remove(&nqn).unwrap_or_default();
+                let mut current = HashMap::new();
+                let mut admitted = 0;
+
+                for ctrlr in ss.controllers() {
+                    let cntlid = unsafe { (*ctrlr).cntlid };
+
+                    if let Some(seen) = previous.get(&cntlid) {
+                        let mut seen = *seen;
+                        if seen.admitted
+                            && expired(
+                                ctrlr,
+                                seen.since,
+                                cfg.keep_alive_timeout_ms,
+                            )
+                        {
+                            let source =
+                                peer_address(unsafe { (*ctrlr).admin_qpair });
+                            warn!(
+                                "Reaping controller {} of {} from {}: no \
+                                 keep-alive within {} ms",
+                                cntlid, nqn, source, cfg.keep_alive_timeout_ms
+                            );
+                            disconnect(ctrlr);
+                            record(&source, &nqn, &Verdict::Reaped);
+                            seen.admitted = false;
+                        }
+                        if seen.admitted {
+                            admitted += 1;
+                        }
+                        current.insert(cntlid, seen);
+                        continue;
+                    }
+
+                    let admin_qpair = unsafe { (*ctrlr).admin_qpair };
+                    if admin_qpair.is_null() {
+                        // not connected yet, look again next time
+                        continue;
+                    }
+                    let source = peer_address(admin_qpair);
+
+                    let verdict = if !self.admit(
+                        &source,
+                        cfg.max_connects_per_sec,
+                        cfg.connect_burst,
+                    ) {
+                        Verdict::RejectedRate
+                    } else if cfg.max_controllers_per_subsystem > 0
+                        && admitted >= cfg.max_controllers_per_subsystem
+                    {
+                        Verdict::RejectedControllers
+                    } else {
+                        Verdict::Accepted
+                    };
+
+                    let accepted = match verdict {
+                        Verdict::Accepted => {
+                            admitted += 1;
+                            true
+                        }
+                        Verdict::RejectedRate => {
+                            warn!(
+                                "Rejecting controller {} of {} from {}: \
+                                 connection rate exceeded",
+                                cntlid, nqn, source
+                            );
+                            false
+                        }
+                        Verdict::RejectedControllers => {
+                            warn!(
+                                "Rejecting controller {} of {} from {}: \
+                                 too many controllers",
+                                cntlid, nqn, source
+                            );
+                            false
+                        }
+                        Verdict::Reaped => unreachable!(),
+                    };
+
+                    if !accepted {
+                        disconnect(ctrlr);
+                    }
+
+                    record(&source, &nqn, &verdict);
+                    current.insert(
+                        cntlid,
+                        Seen {
+                            admitted: accepted,
+                            since: unsafe { spdk_get_ticks() },
+                        },
+                    );
+                }
+
+                known.insert(nqn, current);
+            }
+        }
+
+        self.known = known;
+
+        // forget about the sources which have been quiet for a while
+        let now = Instant::now();
+        self.buckets.retain(|_, b| {
+            now.duration_since(b.last).as_secs_f64() < IDLE_BUCKET_SECS
+        });
+    }
+}
+
+/// Whether the host of an accepted controller has not sent a keep-alive
+/// within the timeout of the target, counting from when the controller was
+/// first seen if it never has. A host which asked for a timeout no longer
+/// than that is left to the keep-alive timer of the controller.
+fn expired(ctrlr: *mut spdk_nvmf_ctrlr, since: u64, timeout_ms: u32) -> bool {
+    if timeout_ms == 0 {
+        return false;
+    }
+
+    let (kato, last) = unsafe {
+        (
+            (*ctrlr).feat.keep_alive_timer.raw,
+            (*ctrlr).last_keep_alive_tick,
+        )
+    };
+    if kato != 0 && kato <= timeout_ms {
+        return false;
+    }
+
+    let timeout = u64::from(timeout_ms) * unsafe { spdk_get_ticks_hz() } / 1000;
+    unsafe { spdk_get_ticks() }.saturating_sub(last.max(since)) > timeout
+}
+
+/// the IP address the admin queue pair of a controller connected from
+fn peer_address(qpair: *mut spdk_nvmf_qpair) -> String {
+    if qpair.is_null() {
+        return "unknown".into();
+    }
+    let mut trid = spdk_nvme_transport_id::default();
+    if unsafe { spdk_nvmf_qpair_get_peer_trid(qpair, &mut trid) } != 0 {
+        return "unknown".into();
+    }
+    trid.traddr.as_str().to_string()
+}
+
+/// disconnect the admin queue pair of a controller, which tears down the
+/// controller with all its queue pairs. Queue pairs are disconnected on the
+/// thread of their poll group.
+fn disconnect(ctrlr: *mut spdk_nvmf_ctrlr) {
+    extern "C" fn disconnect_qpair(arg: *mut c_void) {
+        unsafe {
+            spdk_nvmf_qpair_disconnect(
+                arg as *mut spdk_nvmf_qpair,
+                None,
+                ptr::null_mut(),
+            );
+        }
+    }
+
+    unsafe {
+        let qpair = (*ctrlr).admin_qpair;
+        if qpair.is_null() {
+            return;
+        }
+        if let Some(thread) =
+            Mthread::from_null_checked((*(*qpair).group).thread)
+        {
+            thread.send_msg(disconnect_qpair, qpair as *mut _);
+        }
+    }
+}
+
+fn record(source: &str, nqn: &str, verdict: &Verdict) {
+    let count = |counters: &mut Counters| match verdict {
+        Verdict::Accepted => counters.accepted += 1,
+        Verdict::RejectedRate => counters.rejected_rate += 1,
+        Verdict::RejectedControllers => counters.rejected_controllers += 1,
+        Verdict::Reaped => counters.reaped += 1,
+    };
+    count(STATS.lock().unwrap().entry(source.to_string()).or_default());
+    count(SHARES.lock().unwrap().entry(nqn.to_string()).or_default());
+}
+
+/// poller looking for new controllers, it runs on the thread of the
+/// subsystems
+pub(crate) extern "C" fn guard_poll(_arg: *mut c_void) -> i32 {
+    GUARD.with(|g| g.borrow_mut().poll());
+    0
+}
+
+/// the connection counters of the target, in total, per source address and
+/// per subsystem
+pub fn connection_stats() -> ConnectionStats {
+    let stats = STATS.lock().unwrap();
+    let mut total = ConnectionStats::default();
+
+    for (address, s) in stats.iter() {
+        total.accepted += s.accepted;
+        total.rejected_rate += s.rejected_rate;
+        total.rejected_controllers += s.rejected_controllers;
+        total.reaped += s.reaped;
+        total.sources.push(SourceStats {
+            address: address.clone(),
+            accepted: s.accepted,
+            rejected: s.rejected_rate + s.rejected_controllers,
+            reaped: s.reaped,
+        });
+    }
+
+    total.shares = SHARES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(nqn, s)| ShareStats {
+            nqn: nqn.clone(),
+            accepted: s.accepted,
+            rejected: s.rejected_rate + s.rejected_controllers,
+            reaped: s.reaped,
+        })
+        .collect();
+
+    total.sources.sort_by(|a, b| a.address.cmp(&b.address));
+    total.shares.sort_by(|a, b| a.nqn.cmp(&b.nqn));
+    total
+}
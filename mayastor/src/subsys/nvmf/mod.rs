@@ -3,8 +3,9 @@
 //! but also, if desired a nexus device. A target makes use of
 //! several transports, what transports that exactly is -- is flexible.
 //!
-//! In our case we currently only deal with TCP. We create two transports
-//! one for the frontend (nexus) and one for the backend (replica)
+//! In our case we deal with TCP and, if enabled, RDMA. Each transport listens
+//! on two ports, one for the frontend (nexus) and one for the backend
+//! (replica)
 //!
 //! As connections come on, we randomly schedule them across cores by putting
 //! the qpair in a poll group that is allocated during reactor start.
@@ -13,6 +14,7 @@ use std::cell::RefCell;
 use nix::errno::Errno;
 use snafu::Snafu;
 
+pub use guard::{connection_stats, ConnectionStats, ShareStats, SourceStats};
 use poll_groups::PollGroup;
 use spdk_sys::{
     spdk_subsystem,
@@ -21,6 +23,7 @@ use spdk_sys::{
 };
 pub use subsystem::{NvmfSubsystem, SubType};
 pub use target::Target;
+pub use transport::NvmfTransport;
 
 use crate::{
     jsonrpc::{Code, RpcErrorCode},
@@ -28,6 +31,7 @@ use crate::{
 };
 
 mod admin_cmd;
+mod guard;
 mod poll_groups;
 mod subsystem;
 mod target;
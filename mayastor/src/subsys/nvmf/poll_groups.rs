@@ -4,7 +4,7 @@ use spdk_sys::{
     spdk_nvmf_tgt,
 };
 
-use crate::core::Mthread;
+use crate::core::{Cores, Mthread};
 
 #[derive(Clone, Debug)]
 struct Pg(*mut spdk_nvmf_poll_group);
@@ -13,13 +13,17 @@ struct Pg(*mut spdk_nvmf_poll_group);
 #[derive(Clone, Debug)]
 pub(crate) struct PollGroup {
     pub thread: Mthread,
+    /// core the poll group runs on
+    pub core: u32,
     group: Pg,
 }
 
 impl PollGroup {
+    /// create a poll group, on the thread which is to poll it
     pub fn new(tgt: *mut spdk_nvmf_tgt, mt: Mthread) -> Self {
         Self {
             thread: mt,
+            core: Cores::current(),
             group: Pg(unsafe { spdk_nvmf_poll_group_create(tgt) }),
         }
     }
@@ -13,9 +13,12 @@ use serde::export::{Formatter, TryFrom};
 
 use spdk_sys::{
     spdk_bdev_nvme_opts,
+    spdk_nvme_ana_state,
+    spdk_nvmf_ctrlr,
     spdk_nvmf_ns_get_bdev,
     spdk_nvmf_ns_opts,
     spdk_nvmf_subsystem,
+    spdk_nvmf_subsystem_add_host,
     spdk_nvmf_subsystem_add_listener,
     spdk_nvmf_subsystem_add_ns,
     spdk_nvmf_subsystem_create,
@@ -30,6 +33,8 @@ use spdk_sys::{
     spdk_nvmf_subsystem_pause,
     spdk_nvmf_subsystem_resume,
     spdk_nvmf_subsystem_set_allow_any_host,
+    spdk_nvmf_subsystem_set_ana_reporting,
+    spdk_nvmf_subsystem_set_ana_state,
     spdk_nvmf_subsystem_set_mn,
     spdk_nvmf_subsystem_set_sn,
     spdk_nvmf_subsystem_start,
@@ -43,7 +48,12 @@ use crate::{
     core::{Bdev, Reactors},
     ffihelper::{cb_arg, AsStr, FfiResult, IntoCString},
     subsys::{
-        nvmf::{transport::TransportID, Error, NVMF_TGT},
+        nvmf::{
+            transport::{NvmfTransport, TransportID},
+            Error,
+            NVMF_PGS,
+            NVMF_TGT,
+        },
         Config,
     },
 };
@@ -120,6 +130,7 @@ impl TryFrom<Bdev> for NvmfSubsystem {
     fn try_from(bdev: Bdev) -> Result<Self, Self::Error> {
         let ss = NvmfSubsystem::new(bdev.name().as_str())?;
         ss.allow_any(true);
+        ss.ana_reporting(true)?;
         if let Err(e) = ss.add_namespace(&bdev) {
             ss.destroy();
             return Err(e);
@@ -228,6 +239,83 @@ impl NvmfSubsystem {
         };
     }
 
+    /// allow the host with the given NQN to connect to the subsystem, which
+    /// only matters if not any host is allowed to
+    pub fn add_host(&self, host_nqn: &str) -> Result<(), Error> {
+        let nqn = host_nqn.into_cstring();
+        unsafe { spdk_nvmf_subsystem_add_host(self.0.as_ptr(), nqn.as_ptr()) }
+            .to_result(|e| Error::Subsystem {
+                source: Errno::from_i32(e),
+                nqn: self.get_nqn(),
+                msg: format!("failed to allow host {}", host_nqn),
+            })
+    }
+
+    /// Report the ANA state of the listeners to the hosts, which lets them
+    /// fail over to another path before their IO times out. It can only be
+    /// changed while the subsystem is inactive.
+    pub fn ana_reporting(&self, enable: bool) -> Result<(), Error> {
+        unsafe {
+            spdk_nvmf_subsystem_set_ana_reporting(self.0.as_ptr(), enable)
+        }
+        .to_result(|e| Error::Subsystem {
+            source: Errno::from_i32(e),
+            nqn: self.get_nqn(),
+            msg: "failed to set ANA reporting".to_string(),
+        })
+    }
+
+    /// Set the ANA state of all listeners of the subsystem. The hosts
+    /// connected are told of the change by an asynchronous event.
+    pub async fn set_ana_state(
+        &self,
+        ana_state: spdk_nvme_ana_state,
+    ) -> Result<(), Error> {
+        extern "C" fn ana_cb(arg: *mut c_void, status: i32) {
+            let s = unsafe { Box::from_raw(arg as *mut oneshot::Sender<i32>) };
+            s.send(status).unwrap();
+        }
+
+        for trid in self.listeners_to_vec().unwrap_or_default() {
+            let (s, r) = oneshot::channel::<i32>();
+            unsafe {
+                spdk_nvmf_subsystem_set_ana_state(
+                    self.0.as_ptr(),
+                    trid.as_ptr(),
+                    ana_state,
+                    Some(ana_cb),
+                    cb_arg(s),
+                );
+            }
+
+            r.await.expect("ana state callback gone").to_result(|e| {
+                Error::Subsystem {
+                    source: Errno::from_i32(e),
+                    nqn: self.get_nqn(),
+                    msg: format!("failed to set ANA state of {}", trid),
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// the ANA state of the first listener, none if there is none or ANA
+    /// reporting is not enabled
+    pub fn ana_state(&self) -> Option<spdk_nvme_ana_state> {
+        unsafe {
+            if !self.0.as_ref().ana_reporting {
+                return None;
+            }
+            let listener =
+                spdk_nvmf_subsystem_get_first_listener(self.0.as_ptr());
+            if listener.is_null() {
+                None
+            } else {
+                Some((*listener).ana_state)
+            }
+        }
+    }
+
     // we currently allow all listeners to the subsystem
     async fn add_listener(&self) -> Result<(), Error> {
         extern "C" fn listen_cb(arg: *mut c_void, status: i32) {
@@ -237,26 +325,31 @@ impl NvmfSubsystem {
 
         let cfg = Config::get();
 
-        // dont yet enable both ports, IOW just add one transportID now
+        // dont yet enable both ports, IOW just add the replica port of every
+        // transport now
+        for kind in NvmfTransport::enabled() {
+            let trid_replica =
+                TransportID::new(kind, cfg.nexus_opts.nvmf_replica_port);
 
-        let trid_replica = TransportID::new(cfg.nexus_opts.nvmf_replica_port);
+            let (s, r) = oneshot::channel::<i32>();
+            unsafe {
+                spdk_nvmf_subsystem_add_listener(
+                    self.0.as_ptr(),
+                    trid_replica.as_ptr(),
+                    Some(listen_cb),
+                    cb_arg(s),
+                );
+            }
 
-        let (s, r) = oneshot::channel::<i32>();
-        unsafe {
-            spdk_nvmf_subsystem_add_listener(
-                self.0.as_ptr(),
-                trid_replica.as_ptr(),
-                Some(listen_cb),
-                cb_arg(s),
-            );
+            r.await.expect("listen a callback gone").to_result(|e| {
+                Error::Transport {
+                    source: Errno::from_i32(e),
+                    msg: format!("Failed to add {} listener", kind),
+                }
+            })?;
         }
 
-        r.await.expect("listen a callback gone").to_result(|e| {
-            Error::Transport {
-                source: Errno::from_i32(e),
-                msg: "Failed to add listener".to_string(),
-            }
-        })
+        Ok(())
     }
 
     /// start the subsystem previously created -- note that we destroy it on
@@ -485,8 +578,7 @@ impl NvmfSubsystem {
     /// lookup a subsystem by its UUID
     pub fn nqn_lookup(uuid: &str) -> Option<NvmfSubsystem> {
         let nqn = gen_nqn(uuid);
-        NvmfSubsystem::first()
-            .unwrap()
+        NvmfSubsystem::first()?
             .into_iter()
             .find(|s| s.get_nqn() == nqn)
     }
@@ -506,6 +598,59 @@ impl NvmfSubsystem {
         Some(Bdev::from(b))
     }
 
+    /// the controllers connected to this subsystem, oldest first. Must be
+    /// called on the thread of the subsystem, which owns the list.
+    pub(crate) fn controllers(&self) -> Vec<*mut spdk_nvmf_ctrlr> {
+        let mut list = Vec::new();
+        unsafe {
+            let mut ctrlr = self.0.as_ref().ctrlrs.tqh_first;
+            while !ctrlr.is_null() {
+                list.push(ctrlr);
+                ctrlr = (*ctrlr).link.tqe_next;
+            }
+        }
+        list
+    }
+
+    /// the queue pairs of this subsystem per core of the poll group polling
+    /// them, ordered by core. Must be called on the master core, which owns
+    /// the list of poll groups.
+    pub fn qpairs_per_core(&self) -> Vec<(u32, u32)> {
+        let mut cores = NVMF_PGS.with(|pgs| {
+            pgs.borrow()
+                .iter()
+                .map(|pg| {
+                    let mut count = 0;
+                    unsafe {
+                        let mut qpair = (*pg.group_ptr()).qpairs.tqh_first;
+                        while !qpair.is_null() {
+                            let ctrlr = (*qpair).ctrlr;
+                            if !ctrlr.is_null()
+                                && (*ctrlr).subsys == self.0.as_ptr()
+                            {
+                                count += 1;
+                            }
+                            qpair = (*qpair).link.tqe_next;
+                        }
+                    }
+                    (pg.core, count)
+                })
+                .filter(|(_, count)| *count > 0)
+                .collect::<Vec<_>>()
+        });
+        cores.sort_unstable();
+        cores
+    }
+
+    /// the addresses of the transports this subsystem listens on
+    pub fn listeners(&self) -> Vec<String> {
+        self.listeners_to_vec()
+            .unwrap_or_default()
+            .iter()
+            .map(|t| t.to_string())
+            .collect()
+    }
+
     fn listeners_to_vec(&self) -> Option<Vec<TransportID>> {
         unsafe {
             let mut listener =
@@ -552,14 +697,25 @@ impl NvmfSubsystem {
         if let Some(v) = self.listeners_to_vec() {
             let nqn = self.get_nqn();
             Some(
-                v.iter()
-                    .map(|t| format!("{}/{}", t, nqn))
-                    .collect::<Vec<_>>(),
+                v.iter().map(|t| t.uri(&nqn)).collect::<Vec<_>>(),
             )
         } else {
             None
         }
     }
+
+    /// return the URI the subsystem is shared at, which is the one of the
+    /// configured share transport if it listens on it
+    pub fn share_uri(&self) -> Option<String> {
+        let ids = self.listeners_to_vec()?;
+        let kind = Config::get().nvmf_tcp_tgt_conf.share_transport;
+        let nqn = self.get_nqn();
+        ids.iter()
+            .rev()
+            .find(|t| t.transport() == kind)
+            .or_else(|| ids.last())
+            .map(|t| t.uri(&nqn))
+    }
 }
 
 fn gen_nqn(id: &str) -> String {
@@ -7,7 +7,6 @@ use std::{
 use nix::errno::Errno;
 
 use spdk_sys::{
-    spdk_env_get_core_count,
     spdk_nvmf_poll_group_destroy,
     spdk_nvmf_subsystem_create,
     spdk_nvmf_subsystem_set_mn,
@@ -32,10 +31,11 @@ use crate::{
     ffihelper::{AsStr, FfiResult},
     subsys::{
         nvmf::{
+            guard,
             poll_groups::PollGroup,
             subsystem::NvmfSubsystem,
             transport,
-            transport::{get_ipv4_address, TransportID},
+            transport::{get_ipv4_address, NvmfTransport, TransportID},
             Error,
             NVMF_PGS,
         },
@@ -55,6 +55,8 @@ pub struct Target {
     pub(crate) tgt: NonNull<spdk_nvmf_tgt>,
     /// poller used to accept new connections on
     acceptor_poller: NonNull<spdk_poller>,
+    /// poller enforcing the connection limits on new controllers
+    guard_poller: NonNull<spdk_poller>,
     /// the number of poll groups created for this target
     poll_group_count: u16,
     /// The current state of the target
@@ -105,6 +107,7 @@ impl Target {
         Self {
             tgt: NonNull::dangling(),
             acceptor_poller: NonNull::dangling(),
+            guard_poller: NonNull::dangling(),
             poll_group_count: 0,
             next_state: TargetState::Init,
         }
@@ -181,10 +184,17 @@ impl Target {
         };
     }
 
-    /// add the transport to the target
+    /// add the enabled transports to the target
     fn add_transport(&self) {
         Reactors::master().send_future(async {
-            let result = transport::add_tcp_transport().await;
+            let mut result = Ok(());
+            for kind in NvmfTransport::enabled() {
+                result = transport::add_transport(kind).await;
+                if let Err(e) = &result {
+                    error!("{}", e);
+                    break;
+                }
+            }
             NVMF_TGT.with(|t| {
                 if result.is_err() {
                     t.borrow_mut().next_state = TargetState::Invalid;
@@ -206,19 +216,35 @@ impl Target {
         })
         .unwrap();
 
+        self.guard_poller = NonNull::new(unsafe {
+            spdk_poller_register_named(
+                Some(guard::guard_poll),
+                std::ptr::null_mut(),
+                guard::GUARD_POLL_PERIOD_US,
+                "mayastor_nvmf_guard_poller\0" as *const _ as *mut _,
+            )
+        })
+        .unwrap();
+
         self.next_state();
     }
 
-    /// init the poll groups per core
+    /// init the poll groups per core, the management core is skipped when it
+    /// is dedicated
     fn init_poll_groups(&self) {
-        Reactors::iter().for_each(|r| {
-            if let Some(t) = Mthread::new(
-                format!("mayastor_nvmf_tcp_pg_core_{}", r.core()),
-                r.core(),
-            ) {
-                r.send_future(Self::create_poll_group(self.tgt.as_ptr(), t));
-            }
-        });
+        Reactors::iter()
+            .filter(|r| Cores::is_io_core(r.core()))
+            .for_each(|r| {
+                if let Some(t) = Mthread::new(
+                    format!("mayastor_nvmf_tcp_pg_core_{}", r.core()),
+                    r.core(),
+                ) {
+                    r.send_future(Self::create_poll_group(
+                        self.tgt.as_ptr(),
+                        t,
+                    ));
+                }
+            });
     }
 
     /// init the poll groups implementation
@@ -231,9 +257,7 @@ impl Target {
                     let mut tgt = tgt.borrow_mut();
                     NVMF_PGS.with(|p| p.borrow_mut().push(pg));
                     tgt.poll_group_count += 1;
-                    if tgt.poll_group_count
-                        == unsafe { spdk_env_get_core_count() as u16 }
-                    {
+                    if tgt.poll_group_count == Cores::io_count() as u16 {
                         Reactors::master().send_future(async {
                             NVMF_TGT.with(|tgt| {
                                 tgt.borrow_mut().next_state();
@@ -256,33 +280,38 @@ impl Target {
     /// port
     fn listen(&mut self) -> Result<()> {
         let cfg = Config::get();
-        let trid_nexus = TransportID::new(cfg.nexus_opts.nvmf_nexus_port);
-        let rc = unsafe {
-            spdk_nvmf_tgt_listen(self.tgt.as_ptr(), trid_nexus.as_ptr())
-        };
-
-        if rc != 0 {
-            return Err(Error::CreateTarget {
-                msg: "failed to back target".into(),
-            });
-        }
+        for kind in NvmfTransport::enabled() {
+            let trid_nexus =
+                TransportID::new(kind, cfg.nexus_opts.nvmf_nexus_port);
+            let rc = unsafe {
+                spdk_nvmf_tgt_listen(self.tgt.as_ptr(), trid_nexus.as_ptr())
+            };
+
+            if rc != 0 {
+                return Err(Error::CreateTarget {
+                    msg: format!("failed to back target over {}", kind),
+                });
+            }
 
-        let trid_replica = TransportID::new(cfg.nexus_opts.nvmf_replica_port);
-        let rc = unsafe {
-            spdk_nvmf_tgt_listen(self.tgt.as_ptr(), trid_replica.as_ptr())
-        };
+            let trid_replica =
+                TransportID::new(kind, cfg.nexus_opts.nvmf_replica_port);
+            let rc = unsafe {
+                spdk_nvmf_tgt_listen(self.tgt.as_ptr(), trid_replica.as_ptr())
+            };
 
-        if rc != 0 {
-            return Err(Error::CreateTarget {
-                msg: "failed to front target".into(),
-            });
+            if rc != 0 {
+                return Err(Error::CreateTarget {
+                    msg: format!("failed to front target over {}", kind),
+                });
+            }
+            info!(
+                "nvmf target listening over {} on {}:({},{})",
+                kind,
+                get_ipv4_address().unwrap(),
+                trid_nexus.trsvcid.as_str(),
+                trid_replica.trsvcid.as_str(),
+            );
         }
-        info!(
-            "nvmf target listening on {}:({},{})",
-            get_ipv4_address().unwrap(),
-            trid_nexus.trsvcid.as_str(),
-            trid_replica.trsvcid.as_str(),
-        );
         self.next_state();
         Ok(())
     }
@@ -380,24 +409,37 @@ impl Target {
     fn shutdown(&mut self) {
         extern "C" fn destroy_cb(_arg: *mut c_void, _status: i32) {
             info!("NVMe-oF target shutdown completed");
+            // the target is destroyed asynchronously, so it is no longer
+            // borrowed here. It is reset such that it can be initialized
+            // again when the environment is.
+            NVMF_TGT.with(|t| *t.borrow_mut() = Target::new());
+            NVMF_PGS.with(|p| p.borrow_mut().clear());
             unsafe {
                 spdk_subsystem_fini_next();
             }
         }
 
         unsafe { spdk_poller_unregister(&mut self.acceptor_poller.as_ptr()) };
+        unsafe { spdk_poller_unregister(&mut self.guard_poller.as_ptr()) };
 
         let cfg = Config::get();
-        let trid_nexus = TransportID::new(cfg.nexus_opts.nvmf_nexus_port);
-        let trid_replica = TransportID::new(cfg.nexus_opts.nvmf_replica_port);
+        for kind in NvmfTransport::enabled() {
+            let trid_nexus =
+                TransportID::new(kind, cfg.nexus_opts.nvmf_nexus_port);
+            let trid_replica =
+                TransportID::new(kind, cfg.nexus_opts.nvmf_replica_port);
 
-        unsafe {
-            spdk_nvmf_tgt_stop_listen(self.tgt.as_ptr(), trid_replica.as_ptr())
-        };
+            unsafe {
+                spdk_nvmf_tgt_stop_listen(
+                    self.tgt.as_ptr(),
+                    trid_replica.as_ptr(),
+                )
+            };
 
-        unsafe {
-            spdk_nvmf_tgt_stop_listen(self.tgt.as_ptr(), trid_nexus.as_ptr())
-        };
+            unsafe {
+                spdk_nvmf_tgt_stop_listen(self.tgt.as_ptr(), trid_nexus.as_ptr())
+            };
+        }
 
         unsafe {
             spdk_nvmf_tgt_destroy(
@@ -5,17 +5,20 @@ use std::{
     net::Ipv4Addr,
     ops::{Deref, DerefMut},
     ptr::copy_nonoverlapping,
+    str::FromStr,
 };
 
 use futures::channel::oneshot;
 use nix::errno::Errno;
 use once_cell::sync::Lazy;
-use serde::export::Formatter;
+use serde::{export::Formatter, Deserialize, Serialize};
 
 use spdk_sys::{
     spdk_nvme_transport_id,
+    spdk_nvme_transport_type,
     spdk_nvmf_tgt_add_transport,
     spdk_nvmf_transport_create,
+    SPDK_NVME_TRANSPORT_RDMA,
     SPDK_NVME_TRANSPORT_TCP,
     SPDK_NVMF_ADRFAM_IPV4,
     SPDK_NVMF_TRSVCID_MAX_LEN,
@@ -39,16 +42,84 @@ use crate::{
 static TCP_TRANSPORT: Lazy<CString> =
     Lazy::new(|| CString::new("TCP").unwrap());
 
-pub async fn add_tcp_transport() -> Result<(), Error> {
+static RDMA_TRANSPORT: Lazy<CString> =
+    Lazy::new(|| CString::new("RDMA").unwrap());
+
+/// transport the target listens on and shares the subsystems over
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NvmfTransport {
+    Tcp,
+    /// RoCE or iWARP, whichever the RDMA devices of the node support
+    Rdma,
+}
+
+impl Default for NvmfTransport {
+    fn default() -> Self {
+        NvmfTransport::Tcp
+    }
+}
+
+impl Display for NvmfTransport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NvmfTransport::Tcp => write!(f, "tcp"),
+            NvmfTransport::Rdma => write!(f, "rdma"),
+        }
+    }
+}
+
+impl FromStr for NvmfTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tcp" => Ok(NvmfTransport::Tcp),
+            "rdma" => Ok(NvmfTransport::Rdma),
+            _ => Err(format!("invalid nvmf transport {}", s)),
+        }
+    }
+}
+
+impl NvmfTransport {
+    /// the transports the target has been configured with, TCP first
+    pub fn enabled() -> Vec<NvmfTransport> {
+        let mut transports = vec![NvmfTransport::Tcp];
+        if Config::get().nvmf_tcp_tgt_conf.rdma {
+            transports.push(NvmfTransport::Rdma);
+        }
+        transports
+    }
+
+    fn name(self) -> &'static CString {
+        match self {
+            NvmfTransport::Tcp => &TCP_TRANSPORT,
+            NvmfTransport::Rdma => &RDMA_TRANSPORT,
+        }
+    }
+
+    fn trtype(self) -> spdk_nvme_transport_type {
+        match self {
+            NvmfTransport::Tcp => SPDK_NVME_TRANSPORT_TCP,
+            NvmfTransport::Rdma => SPDK_NVME_TRANSPORT_RDMA,
+        }
+    }
+}
+
+/// create the transport and add it to the target
+pub async fn add_transport(kind: NvmfTransport) -> Result<(), Error> {
     let cfg = Config::get();
-    let mut opts = cfg.nvmf_tcp_tgt_conf.opts.clone().into();
-    let transport = unsafe {
-        spdk_nvmf_transport_create(TCP_TRANSPORT.as_ptr(), &mut opts)
-    };
+    let mut opts = match kind {
+        NvmfTransport::Tcp => cfg.nvmf_tcp_tgt_conf.opts,
+        NvmfTransport::Rdma => cfg.nvmf_tcp_tgt_conf.rdma_opts,
+    }
+    .into();
+    let transport =
+        unsafe { spdk_nvmf_transport_create(kind.name().as_ptr(), &mut opts) };
 
     transport.to_result(|_| Error::Transport {
         source: Errno::UnknownErrno,
-        msg: "failed to create transport".into(),
+        msg: format!("failed to create {} transport", kind),
     })?;
 
     let (s, r) = oneshot::channel::<ErrnoResult<()>>();
@@ -65,7 +136,7 @@ pub async fn add_tcp_transport() -> Result<(), Error> {
 
     let _result = r.await.unwrap();
 
-    debug!("Added TCP nvmf transport");
+    debug!("Added {} nvmf transport", kind);
     Ok(())
 }
 
@@ -85,11 +156,11 @@ impl DerefMut for TransportID {
 }
 
 impl TransportID {
-    pub fn new(port: u16) -> Self {
+    pub fn new(kind: NvmfTransport, port: u16) -> Self {
         let address = get_ipv4_address().unwrap();
 
         let mut trid: spdk_nvme_transport_id = Default::default();
-        trid.trtype = SPDK_NVME_TRANSPORT_TCP;
+        trid.trtype = kind.trtype();
         trid.adrfam = SPDK_NVMF_ADRFAM_IPV4;
 
         let c_addr = address.into_cstring();
@@ -100,9 +171,9 @@ impl TransportID {
 
         unsafe {
             copy_nonoverlapping(
-                TCP_TRANSPORT.as_ptr(),
+                kind.name().as_ptr(),
                 &mut trid.trstring[0],
-                trid.trstring.len(),
+                kind.name().as_bytes().len(),
             );
             copy_nonoverlapping(
                 c_addr.as_ptr(),
@@ -121,6 +192,24 @@ impl TransportID {
     pub fn as_ptr(&self) -> *mut spdk_nvme_transport_id {
         &self.0 as *const _ as *mut spdk_nvme_transport_id
     }
+
+    /// the transport of the ID
+    pub fn transport(&self) -> NvmfTransport {
+        if self.0.trtype == SPDK_NVME_TRANSPORT_RDMA {
+            NvmfTransport::Rdma
+        } else {
+            NvmfTransport::Tcp
+        }
+    }
+
+    /// the URI of the subsystem with the given NQN at this ID, the transport
+    /// is given as a parameter unless it is TCP
+    pub fn uri(&self, nqn: &str) -> String {
+        match self.transport() {
+            NvmfTransport::Tcp => format!("{}/{}", self, nqn),
+            kind => format!("{}/{}?trtype={}", self, nqn, kind),
+        }
+    }
 }
 
 impl Display for TransportID {
@@ -0,0 +1,450 @@
+//! Read-only check of the metadata mayastor keeps on disk and in its config,
+//! for debugging a node of which the nexuses or pools misbehave. The disk
+//! labels of the children of the nexuses, the labels and lvol metadata of
+//! the pools and the config are checked against each other, and what does
+//! not add up is reported as findings, each with what can be done about it.
+//!
+//! Nothing is written, in particular the labels which would be repaired when
+//! a nexus is opened are left as they are, so that the check can be run on
+//! a node which is in use.
+use rpc::mayastor::{
+    FindingSeverity,
+    MetadataFinding,
+    VerifyMetadataReply,
+    VerifyMetadataRequest,
+};
+use snafu::Snafu;
+
+use crate::{
+    bdev::nexus::{
+        instances,
+        nexus_bdev::{nexus_lookup, Nexus},
+        nexus_label::{GptGuid, NexusLabelStatus},
+    },
+    grpc::name_to_uuid,
+    lvs::{label, Lvs},
+    subsys::Config,
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Nexus {} not found", name))]
+    NexusNotFound { name: String },
+    #[snafu(display("Pool {} not found", name))]
+    PoolNotFound { name: String },
+}
+
+impl From<Error> for tonic::Status {
+    fn from(e: Error) -> Self {
+        Self::not_found(e.to_string())
+    }
+}
+
+/// the findings of a check, and the number of objects checked
+#[derive(Default)]
+struct Findings {
+    checked: u32,
+    findings: Vec<MetadataFinding>,
+}
+
+impl Findings {
+    fn add(
+        &mut self,
+        severity: FindingSeverity,
+        object: &str,
+        message: String,
+        action: &str,
+    ) {
+        self.findings.push(MetadataFinding {
+            severity: severity as i32,
+            object: object.to_string(),
+            message,
+            action: action.to_string(),
+        });
+    }
+
+    /// the reply with the errors first, then the warnings
+    fn into_reply(mut self) -> VerifyMetadataReply {
+        self.findings.sort_by(|a, b| {
+            b.severity.cmp(&a.severity).then(a.object.cmp(&b.object))
+        });
+        VerifyMetadataReply {
+            checked: self.checked,
+            findings: self.findings,
+        }
+    }
+}
+
+/// Check the metadata of the given nexus and pool, or of all nexuses and
+/// pools and the config if neither is given.
+pub async fn verify_metadata(
+    args: VerifyMetadataRequest,
+) -> Result<VerifyMetadataReply, Error> {
+    let mut findings = Findings::default();
+
+    if !args.nexus.is_empty() {
+        let nexus = nexus_lookup(&args.nexus)
+            .or_else(|| nexus_lookup(&format!("nexus-{}", args.nexus)))
+            .ok_or_else(|| Error::NexusNotFound {
+                name: args.nexus.clone(),
+            })?;
+        verify_nexus(nexus, &mut findings).await;
+    }
+    if !args.pool.is_empty() {
+        let lvs =
+            Lvs::lookup(&args.pool).ok_or_else(|| Error::PoolNotFound {
+                name: args.pool.clone(),
+            })?;
+        verify_pool(&lvs, &mut findings).await;
+    }
+
+    if args.nexus.is_empty() && args.pool.is_empty() {
+        for nexus in instances().iter() {
+            verify_nexus(nexus, &mut findings).await;
+        }
+        for lvs in Lvs::iter() {
+            verify_pool(&lvs, &mut findings).await;
+        }
+        verify_config(&mut findings);
+    }
+
+    Ok(findings.into_reply())
+}
+
+/// Check the GPT and v2 labels of the children of the nexus against each
+/// other and against the nexus.
+async fn verify_nexus(nexus: &Nexus, findings: &mut Findings) {
+    let nexus_object = format!("nexus/{}", name_to_uuid(&nexus.name));
+    findings.checked += 1;
+
+    let mut guids: Vec<(String, GptGuid)> = Vec::new();
+    for child in nexus.children.iter() {
+        let object = format!("{}/child/{}", nexus_object, child.name);
+        if !child.can_rw() {
+            findings.add(
+                FindingSeverity::FindingWarning,
+                &object,
+                format!(
+                    "the child is {}, its labels cannot be read",
+                    child.status().to_string()
+                ),
+                "remove the child from the nexus, or add it again to have it \
+                 rebuilt",
+            );
+            continue;
+        }
+        findings.checked += 1;
+
+        let label = match child.probe_label().await {
+            Ok(label) => label,
+            Err(e) => {
+                findings.add(
+                    FindingSeverity::FindingError,
+                    &object,
+                    format!("the GPT label cannot be read: {}", e),
+                    "remove the child from the nexus and add it again to have \
+                     it labelled and rebuilt",
+                );
+                continue;
+            }
+        };
+        match label.status {
+            NexusLabelStatus::Both => {}
+            NexusLabelStatus::Primary | NexusLabelStatus::Secondary => {
+                findings.add(
+                    FindingSeverity::FindingWarning,
+                    &object,
+                    format!(
+                        "only the {} GPT header is valid",
+                        if label.status == NexusLabelStatus::Primary {
+                            "primary"
+                        } else {
+                            "backup"
+                        }
+                    ),
+                    "the other header is restored when the nexus is opened \
+                     again",
+                );
+            }
+            NexusLabelStatus::Neither => {
+                findings.add(
+                    FindingSeverity::FindingError,
+                    &object,
+                    "neither GPT header is valid".to_string(),
+                    "remove the child from the nexus and add it again to have \
+                     it labelled and rebuilt",
+                );
+                continue;
+            }
+        }
+        if label.offset() != nexus.data_ent_offset {
+            findings.add(
+                FindingSeverity::FindingError,
+                &object,
+                format!(
+                    "the data partition starts at block {} instead of {}",
+                    label.offset(),
+                    nexus.data_ent_offset
+                ),
+                "remove the child from the nexus and add it again to have it \
+                 labelled and rebuilt",
+            );
+        }
+        guids.push((child.name.clone(), label.primary.guid));
+
+        match child.probe_label_v2(&label).await {
+            Err(e) => findings.add(
+                FindingSeverity::FindingError,
+                &object,
+                format!("the v2 label cannot be read: {}", e),
+                "remove the child from the nexus and add it again to have it \
+                 labelled and rebuilt",
+            ),
+            Ok(None) => findings.add(
+                FindingSeverity::FindingInfo,
+                &object,
+                "the child has no v2 label".to_string(),
+                "none, the label is upgraded when the nexus is opened again",
+            ),
+            Ok(Some(record)) => {
+                let current = match nexus.label_v2.as_ref() {
+                    Some(current) => current,
+                    None => continue,
+                };
+                if record.generation < current.generation {
+                    findings.add(
+                        FindingSeverity::FindingWarning,
+                        &object,
+                        format!(
+                            "the v2 label is of generation {} while the \
+                             nexus is at generation {}",
+                            record.generation, current.generation
+                        ),
+                        "rebuild the child if it is not being rebuilt",
+                    );
+                }
+                if !record.children.contains(&child.name) {
+                    findings.add(
+                        FindingSeverity::FindingWarning,
+                        &object,
+                        "the child is not listed among the children of its \
+                         v2 label"
+                            .to_string(),
+                        "rebuild the child if it is not being rebuilt",
+                    );
+                }
+            }
+        }
+    }
+
+    // the GUIDs which differ from that of the first child
+    if let Some((first, guid)) = guids.first() {
+        for (name, other) in guids.iter().skip(1).filter(|(_, g)| g != guid) {
+            findings.add(
+                FindingSeverity::FindingError,
+                &format!("{}/child/{}", nexus_object, name),
+                format!(
+                    "the GPT disk GUID {} differs from {} of child {}, a new \
+                     label is written to all children when the nexus is \
+                     opened again",
+                    other, guid, first
+                ),
+                "remove the children which do not belong to the nexus before \
+                 it is opened again",
+            );
+        }
+    }
+}
+
+/// Check the label and the lvols of the pool.
+async fn verify_pool(lvs: &Lvs, findings: &mut Findings) {
+    let object = format!("pool/{}", lvs.name());
+    findings.checked += 1;
+
+    match label::read(lvs.0.as_ptr()).await {
+        Err(e) => findings.add(
+            FindingSeverity::FindingError,
+            &object,
+            format!("the pool label cannot be read: {}", e),
+            "export the pool and import it again, which labels it afresh",
+        ),
+        Ok(None) => findings.add(
+            FindingSeverity::FindingWarning,
+            &object,
+            "the pool has no label".to_string(),
+            "export the pool and import it again to have it labelled",
+        ),
+        Ok(Some(label)) => {
+            if label.uuid != lvs.uuid() {
+                findings.add(
+                    FindingSeverity::FindingError,
+                    &object,
+                    format!(
+                        "the label is of pool {} instead of {}",
+                        label.uuid,
+                        lvs.uuid()
+                    ),
+                    "check whether the disk has been copied from another pool",
+                );
+            }
+            if let Some(owner) = label.foreign_owner() {
+                findings.add(
+                    FindingSeverity::FindingError,
+                    &object,
+                    format!("the pool belongs to {}", owner),
+                    "export the pool unless it has been moved on purpose",
+                );
+            }
+        }
+    }
+
+    if let Some(reason) = lvs.read_only_reason() {
+        findings.add(
+            FindingSeverity::FindingWarning,
+            &object,
+            format!("the pool is read-only: {}", reason),
+            "check the disk of the pool, then export and import the pool",
+        );
+    }
+    if lvs.used() > lvs.capacity() {
+        findings.add(
+            FindingSeverity::FindingError,
+            &object,
+            format!(
+                "{} bytes are used of a capacity of {}",
+                lvs.used(),
+                lvs.capacity()
+            ),
+            "the blobstore metadata is inconsistent, copy the replicas off \
+             the pool",
+        );
+    }
+
+    let mut allocated = 0;
+    for lvol in lvs.lvols().into_iter().flatten() {
+        findings.checked += 1;
+        allocated +=
+            lvol.allocated_clusters().len() as u64 * lvol.cluster_size();
+        if let Err(e) = lvol.metadata() {
+            findings.add(
+                FindingSeverity::FindingWarning,
+                &format!("{}/replica/{}", object, lvol.name()),
+                format!("the metadata cannot be read: {}", e),
+                "set the metadata of the replica again",
+            );
+        }
+    }
+    if allocated > lvs.used() {
+        findings.add(
+            FindingSeverity::FindingError,
+            &object,
+            format!(
+                "the replicas have {} bytes allocated while the pool has {} \
+                 bytes in use",
+                allocated,
+                lvs.used()
+            ),
+            "the blobstore metadata is inconsistent, copy the replicas off \
+             the pool",
+        );
+    }
+}
+
+/// Check the pools and nexuses which are loaded against the config the node
+/// was started with. What has been created since is only reported, what
+/// has gone missing may have failed to load.
+fn verify_config(findings: &mut Findings) {
+    let config = Config::get();
+    let object = "config";
+    findings.checked += 1;
+
+    let pools = config.pools.as_deref().unwrap_or_default();
+    for pool in pools {
+        let lvs = match Lvs::lookup(&pool.name) {
+            Some(lvs) => lvs,
+            None => {
+                findings.add(
+                    FindingSeverity::FindingWarning,
+                    object,
+                    format!("pool {} is not loaded", pool.name),
+                    "check the disks of the pool, unless it has been \
+                     destroyed since",
+                );
+                continue;
+            }
+        };
+        if !pool.uuid.is_empty() && pool.uuid != lvs.uuid() {
+            findings.add(
+                FindingSeverity::FindingError,
+                object,
+                format!(
+                    "pool {} is of uuid {} instead of {}",
+                    pool.name,
+                    lvs.uuid(),
+                    pool.uuid
+                ),
+                "check whether another disk has been given the name of the \
+                 pool",
+            );
+        }
+        for replica in pool.replicas.iter() {
+            let found = lvs.lvols().into_iter().flatten().any(|l| {
+                l.name() == replica.name
+                    || (!replica.uuid.is_empty() && l.uuid() == replica.uuid)
+            });
+            if !found {
+                findings.add(
+                    FindingSeverity::FindingWarning,
+                    object,
+                    format!(
+                        "replica {} of pool {} does not exist",
+                        replica.name, pool.name
+                    ),
+                    "check the pool, unless the replica has been destroyed \
+                     since",
+                );
+            }
+        }
+    }
+    for lvs in Lvs::iter().filter(|l| pools.iter().all(|p| p.name != l.name()))
+    {
+        findings.add(
+            FindingSeverity::FindingInfo,
+            object,
+            format!("pool {} is not in the config", lvs.name()),
+            "none if the pool has been created or imported since",
+        );
+    }
+
+    for bdev in config.nexus_bdevs.as_deref().unwrap_or_default() {
+        let nexus = match nexus_lookup(&bdev.name) {
+            Some(nexus) => nexus,
+            None => {
+                findings.add(
+                    FindingSeverity::FindingWarning,
+                    object,
+                    format!("nexus {} is not running", bdev.name),
+                    "check the children of the nexus, unless it has been \
+                     destroyed since",
+                );
+                continue;
+            }
+        };
+        let children = nexus
+            .children
+            .iter()
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+        if children != bdev.children {
+            findings.add(
+                FindingSeverity::FindingInfo,
+                object,
+                format!(
+                    "nexus {} has children {:?} instead of {:?}",
+                    bdev.name, children, bdev.children
+                ),
+                "none if children have been added or removed since",
+            );
+        }
+    }
+}
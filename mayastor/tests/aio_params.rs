@@ -0,0 +1,81 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static DISKNAME1: &str = "/tmp/aio_params1.img";
+static DISKNAME2: &str = "/tmp/aio_params2.img";
+
+#[test]
+fn aio_params() {
+    common::delete_file(&[DISKNAME1.into(), DISKNAME2.into()]);
+    common::truncate_file(DISKNAME1, 64 * 1024);
+    common::truncate_file(DISKNAME2, 64 * 1024);
+
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            // a block size which is not a power of two is refused
+            let uri = format!("aio://{}?blk_size=1000", DISKNAME1);
+            assert!(bdev_create(&uri).await.is_err());
+            assert!(Bdev::lookup_by_name(DISKNAME1).is_none());
+
+            // emulate a 4Kn device
+            let uri = format!("aio://{}?blk_size=4096", DISKNAME1);
+            bdev_create(&uri).await.unwrap();
+            let bdev = Bdev::lookup_by_name(DISKNAME1).unwrap();
+            assert_eq!(bdev.block_len(), 4096);
+            assert_eq!(bdev.num_blocks(), 64 * 1024 * 1024 / 4096);
+
+            let h = Bdev::open_by_name(DISKNAME1, true)
+                .unwrap()
+                .into_handle()
+                .unwrap();
+            let mut buf = h.dma_malloc(4096).unwrap();
+            buf.fill(0xab);
+            h.write_at(4096, &buf).await.unwrap();
+            drop(h);
+            bdev_destroy(&uri).await.unwrap();
+
+            // the same file is opened read-only, it can be read but not
+            // written to
+            let uri = format!("uring://{}?readonly=true", DISKNAME1);
+            bdev_create(&uri).await.unwrap();
+            let h = Bdev::open_by_name(DISKNAME1, true)
+                .unwrap()
+                .into_handle()
+                .unwrap();
+            let mut buf = h.dma_malloc(4096).unwrap();
+            h.read_at(4096, &mut buf).await.unwrap();
+            assert!(buf.as_slice().iter().all(|b| *b == 0xab));
+            assert!(h.write_at(0, &buf).await.is_err());
+            drop(h);
+            bdev_destroy(&uri).await.unwrap();
+
+            // a writable bdev next to it is not affected
+            let uri = format!("aio://{}", DISKNAME2);
+            bdev_create(&uri).await.unwrap();
+            let h = Bdev::open_by_name(DISKNAME2, true)
+                .unwrap()
+                .into_handle()
+                .unwrap();
+            let buf = h.dma_malloc(4096).unwrap();
+            h.write_at(0, &buf).await.unwrap();
+            drop(h);
+            bdev_destroy(&uri).await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+
+    common::delete_file(&[DISKNAME1.into(), DISKNAME2.into()]);
+}
@@ -206,6 +206,18 @@ impl MayastorProcess {
     pub fn sig_cont(&mut self) {
         self.sig_x("CONT", Some(WaitPidFlag::WCONTINUED));
     }
+
+    /// ask the mayastor process to reload its config, the reload happens
+    /// asynchronously
+    pub fn sig_hup(&self) {
+        if self.child == 0 {
+            return;
+        }
+        Command::new("kill")
+            .args(&["-s", "HUP", &format!("{}", self.child)])
+            .output()
+            .unwrap();
+    }
 }
 
 /// ensure we umount the huge pages during shutdown
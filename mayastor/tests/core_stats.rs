@@ -0,0 +1,49 @@
+use mayastor::core::{
+    mayastor_env_stop,
+    Cores,
+    MayastorCliArgs,
+    MayastorEnvironment,
+    Reactor,
+    Reactors,
+};
+
+pub mod common;
+
+#[test]
+fn parse_core_list() {
+    assert_eq!(
+        Cores::parse_list("2,3,8-11").unwrap(),
+        vec![2, 3, 8, 9, 10, 11]
+    );
+    assert_eq!(Cores::parse_list("5, 1-2,2").unwrap(), vec![1, 2, 5]);
+    assert!(Cores::parse_list("").is_err());
+    assert!(Cores::parse_list("3-1").is_err());
+    assert!(Cores::parse_list("a,1").is_err());
+}
+
+#[test]
+fn core_stats() {
+    common::mayastor_test_init();
+    let mut args = MayastorCliArgs::default();
+    args.core_list = Some("0-1".into());
+
+    MayastorEnvironment::new(args)
+        .start(|| {
+            Reactor::block_on(async {
+                stats().await;
+            });
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+}
+
+async fn stats() {
+    assert_eq!(Reactors::iter().count(), 2);
+
+    let stats = Reactors::stats().await;
+    assert_eq!(stats.iter().map(|s| s.core).collect::<Vec<_>>(), vec![0, 1]);
+    for s in stats {
+        assert!(s.threads > 0);
+        assert!(s.busy_tsc + s.idle_tsc > 0);
+    }
+}
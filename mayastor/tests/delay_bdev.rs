@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+
+use mayastor::{
+    bdev::delay::{self, Delays, IoDelay},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        DmaBuf,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static BASE: &str = "malloc:///malloc0?blk_size=512&size_mb=64";
+static DELAY: &str = "delay:///malloc0?read_us=20000&read_jitter_us=10000";
+static NAME: &str = "delay_malloc0";
+
+#[test]
+fn delay_bdev() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            bdev_create(BASE).await.unwrap();
+            assert_eq!(bdev_create(DELAY).await.unwrap(), NAME);
+
+            // the base bdev has to exist
+            assert!(bdev_create("delay:///malloc1").await.is_err());
+            // and the latencies have to be numbers
+            assert!(bdev_create("delay:///malloc0?read_us=x").await.is_err());
+
+            assert_eq!(
+                delay::get(NAME).unwrap(),
+                Delays {
+                    read: IoDelay {
+                        latency: Duration::from_millis(20),
+                        jitter: Duration::from_millis(10),
+                    },
+                    write: IoDelay::default(),
+                }
+            );
+            assert!(delay::get("malloc0").is_none());
+            assert!(!delay::set("malloc0", Delays::default()));
+        });
+
+        let h = Bdev::open_by_name(NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = DmaBuf::new(4096, 9).unwrap();
+        buf.fill(0xff);
+
+        Reactor::block_on(async move {
+            for i in 0 .. 4 {
+                let start = Instant::now();
+                h.read_at(i * 4096, &mut buf).await.unwrap();
+                assert!(start.elapsed() >= Duration::from_millis(20));
+            }
+
+            // the latencies apply to the IOs submitted from now on
+            assert!(delay::set(
+                NAME,
+                Delays {
+                    read: IoDelay::default(),
+                    write: IoDelay {
+                        latency: Duration::from_millis(50),
+                        jitter: Duration::default(),
+                    },
+                }
+            ));
+
+            let start = Instant::now();
+            h.write_at(0, &buf).await.unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(50));
+
+            let start = Instant::now();
+            h.read_at(0, &mut buf).await.unwrap();
+            assert!(start.elapsed() < Duration::from_millis(50));
+        });
+
+        Reactor::block_on(async {
+            bdev_destroy(DELAY).await.unwrap();
+            assert!(Bdev::lookup_by_name(NAME).is_none());
+            assert!(delay::get(NAME).is_none());
+            assert!(Bdev::lookup_by_name("malloc0").is_some());
+            bdev_destroy(BASE).await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
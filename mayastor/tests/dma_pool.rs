@@ -0,0 +1,63 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        DmaPool,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::bdev_create,
+};
+
+pub mod common;
+
+static BDEVNAME: &str = "malloc:///malloc0?size_mb=16";
+
+#[test]
+fn dma_pool() {
+    common::mayastor_test_init();
+    MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                pool().await;
+            });
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+}
+
+async fn pool() {
+    let name = bdev_create(BDEVNAME).await.unwrap();
+    let h = BdevHandle::open(&name, true, false).unwrap();
+
+    // the buffers of a common size are taken from the pool and returned
+    // to it, zeroed again for the next user
+    let mut buf = h.dma_malloc(4096).unwrap();
+    let cached = DmaPool::cached();
+    let hits = DmaPool::hits();
+    buf.fill(0xa5);
+    h.write_at(0, &buf).await.unwrap();
+    drop(buf);
+    assert_eq!(DmaPool::cached(), cached + 1);
+
+    let mut buf = h.dma_malloc(4096).unwrap();
+    assert_eq!(DmaPool::hits(), hits + 1);
+    assert!(buf.as_slice().iter().all(|&b| b == 0));
+    h.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|&b| b == 0xa5));
+    drop(buf);
+
+    // a smaller buffer is served by the smallest class it fits in
+    let buf = h.dma_malloc(512).unwrap();
+    assert_eq!(buf.len(), 512);
+    assert_eq!(DmaPool::hits(), hits + 2);
+    drop(buf);
+
+    // a buffer larger than any class is allocated as it is
+    let cached = DmaPool::cached();
+    let buf = h.dma_malloc(4 * 1024 * 1024).unwrap();
+    drop(buf);
+    assert_eq!(DmaPool::cached(), cached);
+    assert_eq!(DmaPool::hits(), hits + 2);
+}
@@ -0,0 +1,63 @@
+use mayastor::{
+    core::{Bdev, MayastorCliArgs, MayastorEnvironment, Reactor, Share},
+    nexus_uri::bdev_create,
+    subsys::Config,
+};
+
+pub mod common;
+
+static CONFIG: &str = "/tmp/env_restart.yaml";
+
+/// every round is initialized with a config of its own
+fn init(round: u16) {
+    let mut config = Config::default();
+    config.nexus_opts.nvmf_replica_port = 8430 + round;
+    config.write(CONFIG).unwrap();
+
+    MayastorEnvironment::new(MayastorCliArgs {
+        reactor_mask: "0x3".to_string(),
+        node_name: Some(format!("node-{}", round)),
+        mayastor_config: Some(CONFIG.to_string()),
+        ..Default::default()
+    })
+    .init();
+}
+
+#[test]
+fn env_restart() {
+    common::mayastor_test_init();
+
+    for round in 0 .. 3 {
+        init(round);
+
+        assert_eq!(
+            Config::get().nexus_opts.nvmf_replica_port,
+            8430 + round,
+            "{}",
+            round
+        );
+        assert_eq!(
+            MayastorEnvironment::global_node_name(),
+            format!("node-{}", round)
+        );
+
+        Reactor::block_on(async move {
+            // the bdevs of the previous round are gone with it
+            assert!(Bdev::lookup_by_name("malloc0").is_none());
+
+            bdev_create("malloc:///malloc0?blk_size=512&size_mb=64")
+                .await
+                .unwrap();
+
+            let bdev = Bdev::lookup_by_name("malloc0").unwrap();
+            assert_eq!(bdev.size_in_bytes(), 64 * 1024 * 1024, "{}", round);
+
+            // the nvmf target is up again, listening on the port of the
+            // config of this round, and the share is torn down with it
+            let uri = bdev.share_nvmf().await.unwrap();
+            assert!(uri.contains(&format!(":{}/", 8430 + round)), "{}", uri);
+        });
+
+        assert_eq!(MayastorEnvironment::shutdown(0), 0);
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+use mayastor::{
+    bdev::fault::{self, Fault, FaultIo},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        DmaBuf,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static BASE: &str = "malloc:///malloc0?blk_size=512&size_mb=64";
+static ERROR: &str = "error:///malloc0";
+static NAME: &str = "EE_malloc0";
+
+#[test]
+fn error_bdev() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            bdev_create(BASE).await.unwrap();
+            assert_eq!(bdev_create(ERROR).await.unwrap(), NAME);
+
+            // the base bdev has to exist
+            assert!(bdev_create("error:///malloc1").await.is_err());
+
+            assert!(Bdev::lookup_by_name(NAME).is_some());
+            assert_eq!(fault::list(NAME).unwrap(), Vec::new());
+            assert!(fault::list("malloc0").is_none());
+            assert!(!fault::inject("malloc0", Fault::default()));
+        });
+
+        let h = Bdev::open_by_name(NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = DmaBuf::new(4096, 9).unwrap();
+        buf.fill(0xff);
+
+        Reactor::block_on(async move {
+            // fail every second read of the first 8 blocks, twice
+            assert!(fault::inject(
+                NAME,
+                Fault {
+                    io: FaultIo::Read,
+                    num_blocks: 8,
+                    frequency: 2,
+                    count: 2,
+                    ..Default::default()
+                }
+            ));
+
+            h.write_at(0, &buf).await.unwrap();
+            h.read_at(4096, &mut buf).await.unwrap();
+
+            assert!(h.read_at(0, &mut buf).await.is_ok());
+            assert!(h.read_at(0, &mut buf).await.is_err());
+            assert!(h.read_at(0, &mut buf).await.is_ok());
+            assert!(h.read_at(0, &mut buf).await.is_err());
+            for _ in 0 .. 4 {
+                assert!(h.read_at(0, &mut buf).await.is_ok());
+            }
+
+            let faults = fault::list(NAME).unwrap();
+            assert_eq!(faults.len(), 1);
+            assert_eq!(faults[0].matched, 8);
+            assert_eq!(faults[0].injected, 2);
+
+            assert!(fault::clear(NAME));
+            assert!(fault::list(NAME).unwrap().is_empty());
+
+            // delayed writes complete once the delay has expired
+            assert!(fault::inject(
+                NAME,
+                Fault {
+                    io: FaultIo::Write,
+                    delay: Some(Duration::from_millis(100)),
+                    ..Default::default()
+                }
+            ));
+
+            let start = Instant::now();
+            h.write_at(0, &buf).await.unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            h.read_at(0, &mut buf).await.unwrap();
+
+            assert!(fault::clear(NAME));
+        });
+
+        Reactor::block_on(async {
+            bdev_destroy(ERROR).await.unwrap();
+            assert!(Bdev::lookup_by_name(NAME).is_none());
+            assert!(fault::list(NAME).is_none());
+            assert!(Bdev::lookup_by_name("malloc0").is_some());
+            bdev_destroy(BASE).await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
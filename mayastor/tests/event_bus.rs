@@ -0,0 +1,95 @@
+use futures::StreamExt;
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{
+        mayastor_env_stop,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    pool::{create_pool, Pool},
+    subsys::{subscribe_events, Config, Event, EventMessage},
+};
+use rpc::mayastor::CreatePoolRequest;
+use serde_json::Value;
+
+pub mod common;
+
+static POOL: &str = "pool_event_bus";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static NEXUS: &str = "nexus-8d3f1a2b-6c4e-4f7a-9b1d-2e3f4a5b6c7d";
+static CHILD: &str = "malloc:///malloc1?size_mb=64";
+
+#[test]
+fn event_message() {
+    let message = EventMessage::new(Event::ChildFaulted {
+        nexus: "nexus-3c9e1f5a".into(),
+        uuid: "3c9e1f5a".into(),
+        child: "bdev:///malloc0".into(),
+        reason: "io errors".into(),
+    });
+    assert!(message.timestamp > 0);
+
+    // the event is named alongside its fields, so the message stays flat
+    let json: Value = serde_json::to_value(&message).unwrap();
+    assert_eq!(json["event"], "child_faulted");
+    assert_eq!(json["node"], message.node.as_str());
+    assert_eq!(json["nexus"], "nexus-3c9e1f5a");
+    assert_eq!(json["reason"], "io errors");
+
+    let parsed: EventMessage = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed, message);
+}
+
+#[test]
+fn event_bus_without_nats() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            let opts = &Config::get().event_bus;
+            assert!(opts.enable);
+            assert_eq!(opts.subject, "mayastor.events");
+
+            // the events are dropped as there is no message bus to publish
+            // them on, which must not fail the operation
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+
+            // the events are dispatched within mayastor all the same
+            let mut events = subscribe_events();
+            nexus_create(NEXUS, 16 * 1024 * 1024, None, &[CHILD.into()])
+                .await
+                .unwrap();
+            nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+
+            let created = events.next().await.unwrap();
+            assert_eq!(created.event.nexus(), Some(NEXUS));
+            assert!(matches!(created.event, Event::NexusCreated { .. }));
+
+            // the children may report their removal before the nexus is gone
+            let destroyed = Event::NexusDestroyed {
+                nexus: NEXUS.into(),
+                uuid: "8d3f1a2b-6c4e-4f7a-9b1d-2e3f4a5b6c7d".into(),
+            };
+            loop {
+                let message = events.next().await.unwrap();
+                assert_eq!(message.event.nexus(), Some(NEXUS));
+                if message.event == destroyed {
+                    break;
+                }
+            }
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,36 @@
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::getpid,
+};
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{shutdown, MayastorCliArgs, MayastorEnvironment, Reactor},
+};
+
+pub mod common;
+
+static NEXUS: &str = "nexus_shutdown";
+static CHILD: &str = "malloc:///malloc0?blk_size=512&size_mb=64";
+
+#[test]
+fn graceful_shutdown() {
+    common::mayastor_test_init();
+    let rc = MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                nexus_create(NEXUS, 32 * 1024 * 1024, None, &[CHILD.into()])
+                    .await
+                    .unwrap();
+                assert!(nexus_lookup(NEXUS).is_some());
+                assert!(!shutdown::is_shutting_down());
+            });
+
+            // the reactors are stopped after the nexus has been drained
+            kill(getpid(), Signal::SIGTERM).unwrap();
+        })
+        .unwrap();
+
+    assert_eq!(rc, 0);
+    assert!(shutdown::is_shutting_down());
+}
@@ -0,0 +1,70 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use mayastor::grpc::queue::{self, Resource};
+
+fn nexus(uuid: &str) -> Resource {
+    Resource::Nexus(uuid.into())
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn grpc_queue() {
+    let mut rt = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    rt.block_on(async {
+        let first = queue::turn(nexus("1")).await.unwrap();
+
+        // the calls on other objects go ahead
+        let other = queue::turn_within(nexus("2"), ms(10)).await.unwrap();
+        let pool = queue::turn_within(Resource::Pool("1".into()), ms(10))
+            .await
+            .unwrap();
+        drop(other);
+        drop(pool);
+
+        // a call on the same object waits, until it gives up
+        let e = queue::turn_within(nexus("1"), ms(10)).await.unwrap_err();
+        assert_eq!(e.code(), tonic::Code::Unavailable);
+
+        // the calls queued on the object get their turn in order once the
+        // call having it is done, passing over the one which gave up
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let waiting = (1 ..= 2)
+            .map(|i| {
+                let order = Arc::clone(&order);
+                tokio::spawn(async move {
+                    let turn = queue::turn(nexus("1")).await.unwrap();
+                    order.lock().unwrap().push(i);
+                    tokio::time::delay_for(ms(10)).await;
+                    drop(turn);
+                })
+            })
+            .collect::<Vec<_>>();
+        tokio::time::delay_for(ms(10)).await;
+        assert!(order.lock().unwrap().is_empty());
+
+        drop(first);
+        for call in waiting {
+            call.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![1, 2]);
+
+        // a call which goes away once it has been given the turn passes it on
+        let first = queue::turn(nexus("3")).await.unwrap();
+        let mut gone = Box::pin(queue::turn(nexus("3")));
+        assert!(futures::poll!(gone.as_mut()).is_pending());
+        drop(first);
+        drop(gone);
+        queue::turn_within(nexus("3"), ms(10)).await.unwrap();
+    });
+}
@@ -0,0 +1,81 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        CoreError,
+        IoDescriptor,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::bdev_create,
+};
+
+pub mod common;
+
+static BDEVNAME: &str = "malloc:///malloc0?size_mb=16";
+
+#[test]
+fn io_batch() {
+    common::mayastor_test_init();
+    MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                batch().await;
+            });
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+}
+
+async fn batch() {
+    let name = bdev_create(BDEVNAME).await.unwrap();
+    let h = BdevHandle::open(&name, true, false).unwrap();
+
+    let bufs = (0 .. 32u8)
+        .map(|i| {
+            let mut buf = h.dma_malloc(4096).unwrap();
+            buf.fill(i);
+            buf
+        })
+        .collect::<Vec<_>>();
+    let results = h
+        .submit_batch(
+            bufs.iter()
+                .enumerate()
+                .map(|(i, buf)| IoDescriptor::Write {
+                    offset: i as u64 * 4096,
+                    buf,
+                })
+                .collect(),
+        )
+        .await;
+    assert_eq!(results.len(), 32);
+    assert!(results.into_iter().all(|r| r.unwrap() == 4096));
+
+    // the reads come back in the order of the batch, a read past the end
+    // fails without failing the others
+    let mut bufs = (0 .. 33)
+        .map(|_| h.dma_malloc(4096).unwrap())
+        .collect::<Vec<_>>();
+    let size = h.get_bdev().size_in_bytes();
+    let results = h
+        .submit_batch(
+            bufs.iter_mut()
+                .enumerate()
+                .map(|(i, buf)| IoDescriptor::Read {
+                    offset: if i == 32 { size } else { i as u64 * 4096 },
+                    buf,
+                })
+                .collect(),
+        )
+        .await;
+    for (i, result) in results.into_iter().enumerate() {
+        if i == 32 {
+            assert!(matches!(result, Err(CoreError::ReadDispatch { .. })));
+        } else {
+            assert_eq!(result.unwrap(), 4096);
+            assert!(bufs[i].as_slice().iter().all(|&b| b == i as u8));
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use futures::future::join_all;
+
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        IoCtxPool,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::bdev_create,
+};
+
+pub mod common;
+
+static BDEVNAME: &str = "malloc:///malloc0?size_mb=64";
+
+#[test]
+fn io_ctx_pool() {
+    common::mayastor_test_init();
+    MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                pool().await;
+            });
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+}
+
+async fn pool() {
+    let name = bdev_create(BDEVNAME).await.unwrap();
+    let h = BdevHandle::open(&name, true, false).unwrap();
+    let allocated = IoCtxPool::allocated();
+    assert!(allocated > 0);
+
+    // the contexts are reused by the IOs one after the other
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(0xa5);
+    for i in 0 .. 1000 {
+        h.write_at(i * 4096, &buf).await.unwrap();
+    }
+    assert_eq!(IoCtxPool::in_use(), 0);
+    assert_eq!(IoCtxPool::allocated(), allocated);
+
+    // more IOs in flight than there are contexts grow the pool
+    let bufs = (0 .. allocated * 2)
+        .map(|_| {
+            let mut buf = h.dma_malloc(512).unwrap();
+            buf.fill(0x5a);
+            buf
+        })
+        .collect::<Vec<_>>();
+    let writes = bufs
+        .iter()
+        .enumerate()
+        .map(|(i, buf)| h.write_at(i as u64 * 512, buf));
+    for rc in join_all(writes).await {
+        assert_eq!(rc.unwrap(), 512);
+    }
+    assert_eq!(IoCtxPool::in_use(), 0);
+    assert!(IoCtxPool::allocated() >= allocated);
+
+    let mut buf = h.dma_malloc(512).unwrap();
+    h.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|&b| b == 0x5a));
+
+    // a failed submission hands its context back
+    let mut buf = h.dma_malloc(512).unwrap();
+    let offset = h.get_bdev().size_in_bytes();
+    assert!(h.read_at(offset, &mut buf).await.is_err());
+    assert_eq!(IoCtxPool::in_use(), 0);
+}
@@ -0,0 +1,71 @@
+use std::{collections::HashSet, sync::Arc};
+
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        Cores,
+        IoSpread,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::bdev_create,
+};
+
+pub mod common;
+
+static BDEVNAME: &str = "malloc:///spread?size_mb=64";
+
+#[test]
+fn io_spread() {
+    common::mayastor_test_init();
+    let mut args = MayastorCliArgs::default();
+    args.reactor_mask = "0x3".into();
+
+    MayastorEnvironment::new(args)
+        .start(|| {
+            Reactor::block_on(async {
+                spread().await;
+            });
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+}
+
+async fn spread() {
+    let name = bdev_create(BDEVNAME).await.unwrap();
+    let desc = Arc::new(Bdev::open_by_name(&name, true).unwrap());
+    let spread = IoSpread::new(vec![desc]);
+    assert_eq!(IoSpread::cores(), 2);
+
+    // every future writes its own block with a handle of its core
+    let copies = (0 .. 8u64)
+        .map(|n| {
+            spread.spawn(move |handles| async move {
+                let h = &handles[0];
+                let mut buf = h.dma_malloc(512).unwrap();
+                buf.fill(n as u8 + 1);
+                h.write_at(n * 512, &buf).await.unwrap();
+                Cores::current()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut cores = HashSet::new();
+    for copy in copies {
+        cores.insert(copy.await.unwrap().unwrap());
+    }
+    assert_eq!(cores.len(), 2);
+
+    // the writes of all the cores have landed
+    let h = Bdev::open_by_name(&name, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(8 * 512).unwrap();
+    h.read_at(0, &mut buf).await.unwrap();
+    for n in 0 .. 8 {
+        assert_eq!(buf.as_slice()[n * 512], n as u8 + 1);
+    }
+}
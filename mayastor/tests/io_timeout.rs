@@ -0,0 +1,101 @@
+#![cfg(feature = "mock-children")]
+
+use std::time::{Duration, Instant};
+
+use mayastor::{
+    bdev::mock::{self, Behavior},
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        CoreError,
+        IoCtxPool,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static MOCK: &str = "mock:///mock_timeout?size_mb=16";
+
+#[test]
+fn io_timeout() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs {
+        no_huge: true,
+        mem_size: 256,
+        ..Default::default()
+    });
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            timeout().await;
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+async fn timeout() {
+    let name = bdev_create(MOCK).await.unwrap();
+    let mut h = BdevHandle::open(&name, true, false).unwrap();
+    h.set_timeout(Some(Duration::from_millis(100)));
+
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(0x5a);
+    h.write_at(0, &buf).await.unwrap();
+
+    // a stalled read is aborted rather than awaited, it is the only IO in
+    // flight so it has the first context of the pool
+    mock::set_behavior(
+        &name,
+        Behavior {
+            delay: Some(Duration::from_secs(10)),
+            ..Default::default()
+        },
+    );
+    let in_use = IoCtxPool::in_use();
+    let start = Instant::now();
+    match h.read_at(0, &mut buf).await {
+        Err(CoreError::IoTimeout {
+            offset,
+            len,
+            timeout,
+        }) => {
+            assert_eq!(offset, 0);
+            assert_eq!(len, 4096);
+            assert_eq!(timeout, Duration::from_millis(100));
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(IoCtxPool::in_use(), in_use);
+
+    // the IO itself is aborted, the bdev is not reset
+    let stats = mock::stats(&name).unwrap();
+    assert_eq!(stats.aborts, 1);
+    assert_eq!(stats.resets, 0);
+
+    // and the handle is usable once the bdev recovers
+    mock::set_behavior(&name, Behavior::default());
+    buf.fill(0);
+    h.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|&b| b == 0x5a));
+
+    // without a timeout a slow IO is awaited
+    h.set_timeout(None);
+    mock::set_behavior(
+        &name,
+        Behavior {
+            delay: Some(Duration::from_millis(200)),
+            ..Default::default()
+        },
+    );
+    h.read_at(0, &mut buf).await.unwrap();
+
+    drop(h);
+    bdev_destroy(MOCK).await.unwrap();
+}
@@ -0,0 +1,107 @@
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, HostAccess},
+    core::{
+        mayastor_env_stop,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    iscsi_portal::Portal,
+    pool::{create_pool, Pool},
+    replica::{Replica, ShareType},
+};
+use rpc::mayastor::{CreatePoolRequest, ShareProtocolNexus};
+
+pub mod common;
+
+static POOL: &str = "pool_iscsi_portal";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static UUID: &str = "4f2d6c8e-1a3b-4c5d-9e7f-0a1b2c3d4e5f";
+static NEXUS: &str = "nexus_iscsi_portal";
+static CHILD: &str = "malloc:///malloc1?size_mb=64";
+
+#[test]
+fn iscsi_portal() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+            let replica = Replica::create(UUID, POOL, 8 * 1024 * 1024, false)
+                .await
+                .unwrap();
+
+            // the portal is reported in the share URI
+            let portal = Portal {
+                address: "127.0.0.1".into(),
+                port: 3270,
+                ..Default::default()
+            };
+            replica
+                .share_on_portal(ShareType::Iscsi, &portal)
+                .await
+                .unwrap();
+            assert!(replica.get_share_uri().contains("127.0.0.1:3270"));
+            replica.unshare().await.unwrap();
+
+            // the address of the loopback interface on the default port is
+            // the default portal
+            let portal = Portal {
+                interface: "lo".into(),
+                ..Default::default()
+            };
+            replica
+                .share_on_portal(ShareType::Iscsi, &portal)
+                .await
+                .unwrap();
+            assert!(replica.get_share_uri().contains("127.0.0.1:3262"));
+            replica.unshare().await.unwrap();
+
+            // an unknown interface fails the share
+            let portal = Portal {
+                interface: "nosuchif0".into(),
+                ..Default::default()
+            };
+            assert!(replica
+                .share_on_portal(ShareType::Iscsi, &portal)
+                .await
+                .is_err());
+            assert_eq!(replica.get_share_type(), None);
+
+            // a nexus is shared on its portal as well
+            nexus_create(NEXUS, 16 * 1024 * 1024, None, &[CHILD.into()])
+                .await
+                .unwrap();
+            let portal = Portal {
+                port: 3271,
+                ..Default::default()
+            };
+            let uri = nexus_lookup(NEXUS)
+                .unwrap()
+                .share_to_hosts(
+                    ShareProtocolNexus::NexusIscsi,
+                    None,
+                    false,
+                    HostAccess::default(),
+                    &portal,
+                )
+                .await
+                .unwrap();
+            assert!(uri.contains(":3271/"));
+
+            nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,71 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        DmaBuf,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static BDEV: &str = "malloc:///malloc0?blk_size=512&size_mb=64";
+
+#[test]
+fn latency_histogram() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            bdev_create(BDEV).await.unwrap();
+
+            let bdev = Bdev::lookup_by_name("malloc0").unwrap();
+            assert!(!bdev.histogram_enabled());
+            assert!(bdev.latency_histogram().await.is_err());
+
+            bdev.enable_histogram(true).await.unwrap();
+            assert!(bdev.histogram_enabled());
+            assert!(bdev.latency_histogram().await.unwrap().is_empty());
+        });
+
+        let h = Bdev::open_by_name("malloc0", true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = DmaBuf::new(4096, 9).unwrap();
+        buf.fill(0xff);
+
+        Reactor::block_on(async move {
+            for i in 0 .. 10 {
+                h.write_at(i * 4096, &buf).await.unwrap();
+                h.read_at(i * 4096, &mut buf).await.unwrap();
+            }
+        });
+
+        Reactor::block_on(async {
+            let bdev = Bdev::lookup_by_name("malloc0").unwrap();
+            let buckets = bdev.latency_histogram().await.unwrap();
+
+            // reads and writes are counted together
+            assert_eq!(buckets.iter().map(|b| b.count).sum::<u64>(), 20);
+            for b in &buckets {
+                assert!(b.count > 0);
+                assert!(b.start_ns <= b.end_ns);
+            }
+            for w in buckets.windows(2) {
+                assert!(w[0].end_ns <= w[1].start_ns);
+            }
+
+            bdev.enable_histogram(false).await.unwrap();
+            assert!(!bdev.histogram_enabled());
+
+            bdev_destroy(BDEV).await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
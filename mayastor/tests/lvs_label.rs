@@ -0,0 +1,101 @@
+use mayastor::{
+    bdev::fault::{self, Fault, FaultIo},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    lvs::{Error, Lvs},
+    nexus_uri::bdev_create,
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static BASE: &str = "malloc:///malloc0?blk_size=512&size_mb=64";
+static DISK: &str = "error:///malloc0";
+static NAME: &str = "EE_malloc0";
+static POOL: &str = "lpool";
+
+fn request() -> CreatePoolRequest {
+    CreatePoolRequest {
+        name: POOL.into(),
+        disks: vec![DISK.into()],
+        block_size: 0,
+        io_if: 0,
+        adopt: false,
+        idempotency_key: String::new(),
+    }
+}
+
+/// export the pool, which destroys the error bdev but leaves the malloc bdev
+/// and so the pool on it alone, and create the error bdev again
+async fn export() {
+    Lvs::lookup(POOL).unwrap().export().await.unwrap();
+    bdev_create(DISK).await.unwrap();
+}
+
+/// number of reads of importing the pool
+async fn import_reads() -> u64 {
+    // a fault which never hits, to count the reads
+    assert!(fault::inject(
+        NAME,
+        Fault {
+            io: FaultIo::Read,
+            frequency: u32::MAX,
+            ..Default::default()
+        }
+    ));
+    Lvs::import(POOL, NAME).await.unwrap();
+    let reads = fault::list(NAME).unwrap()[0].matched;
+    export().await;
+    reads
+}
+
+#[test]
+fn lvs_label() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            bdev_create(BASE).await.unwrap();
+            let pool = Lvs::create_or_import(request()).await.unwrap();
+            pool.create_lvol("replica", 4 * 1024 * 1024, false)
+                .await
+                .unwrap();
+            export().await;
+
+            // the label is read last, so failing the last read of the import
+            // fails the read of the label
+            let reads = import_reads().await;
+            assert!(fault::inject(
+                NAME,
+                Fault {
+                    io: FaultIo::Read,
+                    frequency: reads as u32,
+                    count: 1,
+                    ..Default::default()
+                }
+            ));
+
+            // the pool is neither exported nor created anew
+            let e = Lvs::create_or_import(request()).await.unwrap_err();
+            assert!(
+                matches!(
+                    e,
+                    Error::Label {
+                        ..
+                    }
+                ),
+                "{}",
+                e
+            );
+            assert_eq!(fault::list(NAME).unwrap()[0].injected, 1);
+            assert!(Lvs::lookup(POOL).is_some());
+
+            // the label is checked again and the replica is still there
+            let pool = Lvs::create_or_import(request()).await.unwrap();
+            assert!(pool.lvols().unwrap().any(|l| l.name() == "replica"));
+
+            pool.destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
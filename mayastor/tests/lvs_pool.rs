@@ -10,7 +10,7 @@ use mayastor::{
         Reactor,
         Share,
     },
-    lvs::{Lvs, PropName, PropValue},
+    lvs::{FlushPolicy, Lvs, PropName, PropValue},
     nexus_uri::bdev_create,
     subsys::NvmfSubsystem,
 };
@@ -48,6 +48,8 @@ fn lvs_pool_test() {
                         disks: vec!["aio:///tmp/disk1.img".into()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await
                     .unwrap();
@@ -62,6 +64,8 @@ fn lvs_pool_test() {
                             disks: vec!["aio:///tmp/disk1.img".into()],
                             block_size: 0,
                             io_if: 0,
+                            adopt: false,
+                            idempotency_key: String::new(),
                         })
                         .await
                         .is_ok(),
@@ -175,6 +179,8 @@ fn lvs_pool_test() {
                         disks: vec!["malloc:///malloc0?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await
                     .unwrap();
@@ -208,6 +214,8 @@ fn lvs_pool_test() {
                         disks: vec!["aio:///tmp/disk1.img".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await
                     .unwrap();
@@ -277,6 +285,18 @@ fn lvs_pool_test() {
                         PropValue::Shared(false)
                     );
 
+                    // without a policy on disk flushes are forwarded
+                    assert_eq!(lvol.flush_policy(), FlushPolicy::Forward);
+
+                    lvol.set(PropValue::FlushPolicy(FlushPolicy::Batch))
+                        .await
+                        .unwrap();
+                    assert_eq!(
+                        lvol.get(PropName::FlushPolicy).await.unwrap(),
+                        PropValue::FlushPolicy(FlushPolicy::Batch)
+                    );
+                    assert_eq!(lvol.flush_policy(), FlushPolicy::Batch);
+
                     lvol.destroy().await.unwrap();
                 });
 
@@ -338,6 +358,8 @@ fn lvs_pool_test() {
                         disks: vec!["aio:///tmp/disk1.img".into()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await
                     .unwrap();
@@ -1,8 +1,11 @@
 use mayastor::{
     core::{
         mayastor_env_stop,
+        tracker,
         Bdev,
         DmaBuf,
+        HandleInfo,
+        HandleKind,
         MayastorCliArgs,
         MayastorEnvironment,
         Reactor,
@@ -12,6 +15,13 @@ use mayastor::{
 
 pub mod common;
 
+/// the handles open on the bdev, which are listed by the reactors
+fn handles(bdev: &str) -> Vec<HandleInfo> {
+    let bdev = bdev.to_string();
+    Reactor::block_on(async move { tracker::handles(Some(&bdev)).await })
+        .unwrap()
+}
+
 #[test]
 fn malloc_bdev() {
     common::mayastor_test_init();
@@ -43,9 +53,17 @@ fn malloc_bdev() {
             m1.get_bdev().uuid_as_string()
         );
 
+        // the descriptors are accounted to this file
+        let open = handles("malloc0");
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].kind, HandleKind::Descriptor);
+        assert!(open[0].owner.starts_with(file!()));
+
         let h0 = m0.into_handle().unwrap();
         let h1 = m1.into_handle().unwrap();
 
+        assert_eq!(handles("malloc1").len(), 2);
+
         let mut buf = DmaBuf::new(4096, 9).unwrap();
         buf.fill(3);
 
@@ -71,6 +89,10 @@ fn malloc_bdev() {
             }
         });
 
+        // all handles are dropped once the IO is done
+        assert!(handles("malloc0").is_empty());
+        assert!(handles("malloc1").is_empty());
+
         Reactor::block_on(async {
             bdev_destroy("malloc:///malloc0?blk_size=512&size_mb=100")
                 .await
@@ -0,0 +1,40 @@
+use mayastor::core::{
+    mayastor_env_stop,
+    Cores,
+    IoSpread,
+    MayastorCliArgs,
+    MayastorEnvironment,
+    Mthread,
+    Reactor,
+    Reactors,
+};
+
+pub mod common;
+
+#[test]
+fn mgmt_thread() {
+    common::mayastor_test_init();
+    let mut args = MayastorCliArgs::default();
+    args.core_list = Some("0-1".into());
+    args.dedicated_mgmt_core = true;
+
+    MayastorEnvironment::new(args)
+        .start(|| {
+            Reactor::block_on(async {
+                assert!(Cores::is_mgmt_dedicated());
+
+                // the management thread is a thread of its own next to the
+                // init thread
+                let mgmt = Mthread::get_mgmt();
+                assert_ne!(mgmt, Mthread::get_init());
+                assert_eq!(mgmt.name(), "mgmt_thread");
+
+                // both run on the first core, which carries no IO
+                let stats = Reactors::stats().await;
+                assert!(stats[0].threads >= 2);
+                assert_eq!(IoSpread::cores(), 1);
+            });
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+}
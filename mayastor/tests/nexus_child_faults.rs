@@ -0,0 +1,151 @@
+#![cfg(feature = "mock-children")]
+
+use std::time::Duration;
+
+use crossbeam::channel::unbounded;
+
+use mayastor::{
+    bdev::{
+        mock::{self, Behavior},
+        nexus_create,
+        nexus_lookup,
+        ChildFaultPolicy,
+        ChildIoStats,
+    },
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    rebuild::RebuildState,
+};
+
+pub mod common;
+
+static MOCK0: &str = "mock:///fault0?size_mb=64";
+static MOCK1: &str = "mock:///fault1?size_mb=64";
+static SPARE: &str = "mock:///fault2?size_mb=64";
+
+static NEXUS: &str = "child_faults_nexus";
+
+#[test]
+fn nexus_child_faults() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs {
+        no_huge: true,
+        mem_size: 256,
+        ..Default::default()
+    });
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            nexus_create(
+                NEXUS,
+                32 * 1024 * 1024,
+                None,
+                &[MOCK0.to_string(), MOCK1.to_string()],
+            )
+            .await
+            .unwrap();
+
+            // a generous policy, such that the child is not faulted yet
+            nexus_lookup(NEXUS).unwrap().set_child_fault_policy(
+                Some(ChildFaultPolicy {
+                    max_errors: 100,
+                    window: Duration::from_secs(60),
+                }),
+                Vec::new(),
+            );
+
+            mock::set_behavior(
+                "fault0",
+                Behavior {
+                    fail_reads: true,
+                    ..Default::default()
+                },
+            );
+            // the reads are spread over both children, those of the first
+            // one fail
+            let mut failed = 0;
+            for _ in 0 .. 4 {
+                if !read_nexus().await {
+                    failed += 1;
+                }
+            }
+            assert_eq!(failed, 2);
+        });
+        reactor_run_millis(10);
+
+        let nexus = nexus_lookup(NEXUS).unwrap();
+        let stats = child_stats(MOCK0).unwrap();
+        assert_eq!(stats.errors, 2);
+        assert_eq!(stats.retries, 0);
+        assert_eq!(stats.timeouts, 0);
+        // the reads which succeeded on the other child are counted there
+        let stats1 = child_stats(MOCK1).unwrap();
+        assert_eq!(stats1.errors, 0);
+        assert_eq!(stats1.num_read_ops, 2);
+        assert_eq!(nexus.children.len(), 2);
+
+        // past the errors of the policy the child is replaced by the spare
+        Reactor::block_on(async {
+            nexus_lookup(NEXUS).unwrap().set_child_fault_policy(
+                Some(ChildFaultPolicy {
+                    max_errors: 2,
+                    window: Duration::from_secs(60),
+                }),
+                vec![SPARE.to_string()],
+            );
+            for _ in 0 .. 4 {
+                read_nexus().await;
+            }
+        });
+        reactor_run_millis(100);
+
+        assert!(child_stats(MOCK0).is_none());
+        assert!(child_stats(SPARE).is_some());
+        assert!(nexus.spares().is_empty());
+        common::wait_for_rebuild(
+            SPARE.to_string(),
+            RebuildState::Completed,
+            Duration::from_secs(20),
+        )
+        .unwrap();
+
+        Reactor::block_on(async {
+            nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+/// the IO stats of the child of the nexus with the URI
+fn child_stats(uri: &str) -> Option<ChildIoStats> {
+    nexus_lookup(NEXUS)
+        .unwrap()
+        .children
+        .iter()
+        .find(|c| c.to_grpc().uri == uri)
+        .map(|c| c.io_stats())
+}
+
+async fn read_nexus() -> bool {
+    let h = Bdev::open_by_name(NEXUS, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    h.read_at(0, &mut buf).await.is_ok()
+}
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
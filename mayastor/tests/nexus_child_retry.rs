@@ -0,0 +1,183 @@
+#![cfg(feature = "mock-children")]
+
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::unbounded;
+
+use mayastor::{
+    bdev::{
+        mock::{self, Behavior},
+        nexus_create,
+        nexus_lookup,
+        ChildFaultPolicy,
+        ChildIoStats,
+        NexusStatus,
+    },
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+};
+
+pub mod common;
+
+static MOCK0: &str = "mock:///retry0?size_mb=64";
+static MOCK1: &str = "mock:///retry1?size_mb=64";
+
+static NEXUS: &str = "child_retry_nexus";
+
+#[test]
+fn nexus_child_retry() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs {
+        no_huge: true,
+        mem_size: 256,
+        ..Default::default()
+    });
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            nexus_create(
+                NEXUS,
+                32 * 1024 * 1024,
+                None,
+                &[MOCK0.to_string(), MOCK1.to_string()],
+            )
+            .await
+            .unwrap();
+
+            // a single error held against a child would fault it
+            nexus_lookup(NEXUS).unwrap().set_child_fault_policy(
+                Some(ChildFaultPolicy {
+                    max_errors: 1,
+                    window: Duration::from_secs(60),
+                }),
+                Vec::new(),
+            );
+
+            // the write is resubmitted to the child until its path is back,
+            // after 10, 20 and 40ms with the default retry options
+            mock::set_behavior(
+                "retry0",
+                Behavior {
+                    path_down_ios: 3,
+                    ..Default::default()
+                },
+            );
+            let start = Instant::now();
+            assert!(write_nexus(0x5a).await);
+            assert!(start.elapsed() >= Duration::from_millis(70));
+        });
+        reactor_run_millis(10);
+
+        let nexus = nexus_lookup(NEXUS).unwrap();
+        let stats0 = child_stats(MOCK0).unwrap();
+        assert_eq!((stats0.errors, stats0.timeouts, stats0.retries), (3, 0, 3));
+        // the write is counted once for each child, the retries aside
+        let stats1 = child_stats(MOCK1).unwrap();
+        assert_eq!((stats1.errors, stats1.retries), (0, 0));
+        assert_eq!(stats0.num_write_ops, stats1.num_write_ops);
+        assert_eq!(stats0.bytes_written, stats1.bytes_written);
+        assert!(stats0.bytes_written > 0);
+        assert_eq!(nexus.children.len(), 2);
+        assert_eq!(nexus.status(), NexusStatus::Online);
+
+        Reactor::block_on(async {
+            // both children have the data, reads alternate between them
+            assert_eq!(read_nexus().await, Some(0x5a));
+            assert_eq!(read_nexus().await, Some(0x5a));
+        });
+        let reads = |s: ChildIoStats| s.num_read_ops;
+        assert_eq!(reads(child_stats(MOCK0).unwrap()) - reads(stats0), 1);
+        assert_eq!(reads(child_stats(MOCK1).unwrap()) - reads(stats1), 1);
+
+        Reactor::block_on(async {
+            // the write fails once the retries are exhausted
+            mock::set_behavior(
+                "retry0",
+                Behavior {
+                    path_down_ios: 10,
+                    ..Default::default()
+                },
+            );
+            assert!(!write_nexus(0x6b).await);
+            mock::set_behavior("retry0", Behavior::default());
+        });
+        reactor_run_millis(10);
+
+        let stats = child_stats(MOCK0).unwrap();
+        assert_eq!(stats.errors, 7);
+        assert_eq!(stats.retries, 6);
+
+        Reactor::block_on(async {
+            // the retries of a write are counted per child, such that it
+            // survives the paths of both children going down at once
+            let path_down = Behavior {
+                path_down_ios: 2,
+                ..Default::default()
+            };
+            mock::set_behavior("retry0", path_down.clone());
+            mock::set_behavior("retry1", path_down);
+            assert!(write_nexus(0x7c).await);
+        });
+        reactor_run_millis(10);
+
+        assert_eq!(child_stats(MOCK0).unwrap().retries, 8);
+        assert_eq!(child_stats(MOCK1).unwrap().retries, 2);
+
+        Reactor::block_on(async {
+            nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+/// the IO stats of the child of the nexus with the URI
+fn child_stats(uri: &str) -> Option<ChildIoStats> {
+    nexus_lookup(NEXUS)
+        .unwrap()
+        .children
+        .iter()
+        .find(|c| c.to_grpc().uri == uri)
+        .map(|c| c.io_stats())
+}
+
+async fn write_nexus(pattern: u8) -> bool {
+    let h = Bdev::open_by_name(NEXUS, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(pattern);
+    h.write_at(0, &buf).await.is_ok()
+}
+
+/// read the first block of the nexus, returns the value of its bytes if they
+/// are all the same
+async fn read_nexus() -> Option<u8> {
+    let h = Bdev::open_by_name(NEXUS, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    h.read_at(0, &mut buf).await.unwrap();
+    let first = buf.as_slice()[0];
+    if buf.as_slice().iter().all(|&b| b == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
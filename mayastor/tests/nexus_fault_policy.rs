@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, FaultPolicy, NexusStatus},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        BdevHandle,
+        DmaBuf,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+        Reactors,
+    },
+};
+
+pub mod common;
+
+static NEXUS: &str = "nexus_fault_policy";
+static CHILD: &str = "malloc:///malloc0?blk_size=512&size_mb=64";
+
+#[test]
+fn nexus_fault_policy() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            nexus_create(NEXUS, 32 * 1024 * 1024, None, &[CHILD.to_string()])
+                .await
+                .unwrap();
+        });
+
+        let h = Bdev::open_by_name(NEXUS, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+
+        Reactor::block_on(fail(&h));
+        Reactor::block_on(freeze_expire(&h));
+        Reactor::block_on(freeze_resume(h));
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+/// without any healthy child IO fails right away by default
+async fn fail(h: &BdevHandle) {
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    assert_eq!(nexus.fault_policy(), FaultPolicy::Fail);
+
+    nexus.offline_child(CHILD).await.unwrap();
+    assert_eq!(nexus.status(), NexusStatus::Faulted);
+
+    let mut buf = DmaBuf::new(4096, 9).unwrap();
+    assert!(h.read_at(0, &mut buf).await.is_err());
+
+    // the child was the last healthy one, it needs no rebuild
+    nexus.online_child(CHILD).await.unwrap();
+    assert_eq!(nexus.status(), NexusStatus::Online);
+    h.read_at(0, &mut buf).await.unwrap();
+}
+
+/// frozen IO fails once its timeout has expired
+async fn freeze_expire(h: &BdevHandle) {
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    let timeout = Duration::from_millis(500);
+    nexus.set_fault_policy(FaultPolicy::Freeze(timeout));
+
+    nexus.offline_child(CHILD).await.unwrap();
+
+    let mut buf = DmaBuf::new(4096, 9).unwrap();
+    let started = Instant::now();
+    assert!(h.read_at(0, &mut buf).await.is_err());
+    assert!(started.elapsed() >= timeout);
+
+    nexus.online_child(CHILD).await.unwrap();
+    assert_eq!(nexus.status(), NexusStatus::Online);
+}
+
+/// frozen IO resumes when the child comes back online
+async fn freeze_resume(h: BdevHandle) {
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    nexus.set_fault_policy(FaultPolicy::Freeze(Duration::from_secs(30)));
+
+    nexus.offline_child(CHILD).await.unwrap();
+
+    let mut buf = DmaBuf::new(4096, 9).unwrap();
+    buf.fill(0xa5);
+    let write = Reactors::current().spawn_local(async move {
+        let rc = h.write_at(0, &buf).await;
+        (h, rc)
+    });
+
+    // wait for the write to be frozen
+    while nexus.io_stats().await.unwrap().queue_depth == 0 {}
+
+    nexus.online_child(CHILD).await.unwrap();
+    let (h, rc) = write.await.unwrap();
+    assert_eq!(rc.unwrap(), 4096);
+
+    let mut buf = DmaBuf::new(4096, 9).unwrap();
+    h.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|b| *b == 0xa5));
+
+    drop(h);
+    nexus.destroy().await.unwrap();
+}
@@ -0,0 +1,96 @@
+use std::{fs, thread, time::Duration};
+
+use rpc::mayastor::ShareProtocolNexus;
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    subsys::Config,
+};
+
+pub mod common;
+
+static NEXUS: &str = "hooks_nexus";
+static CONFIG: &str = "/tmp/nexus_hooks.yaml";
+static LOG: &str = "/tmp/nexus_hooks.log";
+
+fn generate_config() {
+    let hook = format!(
+        "echo \"$MAYASTOR_EVENT $MAYASTOR_NEXUS $MAYASTOR_CHILD $MAYASTOR_REASON $MAYASTOR_DEVICE_URI\" >> {}",
+        LOG
+    );
+    let mut config = Config::default();
+    config.event_hooks.child_faulted = Some(hook.clone());
+    config.event_hooks.nexus_published = Some(hook);
+    config.write(CONFIG).unwrap();
+}
+
+/// wait for the hooks to have logged the given number of events
+fn logged(events: usize) -> Vec<String> {
+    for _ in 0 .. 50 {
+        let log = fs::read_to_string(LOG).unwrap_or_default();
+        let lines = log.lines().map(String::from).collect::<Vec<_>>();
+        if lines.len() >= events {
+            return lines;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("the hooks have not run");
+}
+
+#[test]
+fn nexus_hooks() {
+    generate_config();
+    let _ = fs::remove_file(LOG);
+
+    common::mayastor_test_init();
+    let mut args = MayastorCliArgs::default();
+    args.mayastor_config = Some(CONFIG.to_string());
+    let ms = MayastorEnvironment::new(args);
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            hooks().await;
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+async fn hooks() {
+    nexus_create(
+        NEXUS,
+        32 * 1024 * 1024,
+        None,
+        &[
+            "malloc:///malloc0?size_mb=64".into(),
+            "malloc:///malloc1?size_mb=64".into(),
+        ],
+    )
+    .await
+    .unwrap();
+
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    let uri = nexus
+        .share(ShareProtocolNexus::NexusIscsi, None)
+        .await
+        .unwrap();
+    let lines = logged(1);
+    assert!(lines[0].starts_with(&format!("nexus_published {}", NEXUS)));
+    assert!(lines[0].ends_with(&uri));
+
+    nexus
+        .fault_child("malloc:///malloc1?size_mb=64")
+        .await
+        .unwrap();
+    let lines = logged(2);
+    assert_eq!(
+        lines[1].trim(),
+        format!(
+            "child_faulted {} malloc:///malloc1?size_mb=64 io errors",
+            NEXUS
+        )
+    );
+
+    nexus.destroy().await.unwrap();
+}
@@ -0,0 +1,138 @@
+use std::{
+    fs::OpenOptions,
+    io::{Read, Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    rebuild::{RebuildJob, RebuildState},
+    subsys::Config,
+};
+
+pub mod common;
+
+static NEXUS: &str = "intent_log_nexus";
+static UUID: &str = "3ec4b4f6-3a45-4aae-8c1b-5dc3d2a3b8a1";
+static CONFIG: &str = "/tmp/intent_log.yaml";
+
+static DISK1: &str = "/tmp/intent_log1.img";
+static DISK2: &str = "/tmp/intent_log2.img";
+static DISK3: &str = "/tmp/intent_log3.img";
+static CHILD1: &str = "aio:///tmp/intent_log1.img?blk_size=512";
+static CHILD2: &str = "aio:///tmp/intent_log2.img?blk_size=512";
+static CHILD3: &str = "aio:///tmp/intent_log3.img?blk_size=512";
+
+/// blocks of 512 bytes per region of 1MiB
+const REGION_BLOCKS: u64 = 2048;
+
+fn generate_config() {
+    let mut config = Config::default();
+    config.nexus_opts.iscsi_enable = false;
+    config.nexus_opts.nvmf_enable = false;
+    config.nexus_opts.intent_log.enable = true;
+    // the regions are not cleared while the test runs
+    config.nexus_opts.intent_log.clean_interval_ms = 3_600_000;
+    config.write(CONFIG).unwrap();
+}
+
+fn file_fill(path: &str, offset: u64, len: usize, val: u8) {
+    let mut file = OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(&vec![val; len]).unwrap();
+    file.sync_all().unwrap();
+}
+
+fn file_check(path: &str, offset: u64, len: usize, val: u8) -> bool {
+    let mut file = OpenOptions::new().read(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    let mut buf = vec![0u8; len];
+    file.read_exact(&mut buf).unwrap();
+    buf.iter().all(|b| *b == val)
+}
+
+async fn create_nexus(children: &[&str]) {
+    let children = children.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    nexus_create(NEXUS, 32 * 1024 * 1024, Some(UUID), &children)
+        .await
+        .unwrap();
+}
+
+async fn nexus_fill(blk: u64, val: u8) {
+    let h = BdevHandle::open(NEXUS, true, false).unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(val);
+    h.write_at(blk * 512, &buf).await.unwrap();
+}
+
+#[test]
+fn nexus_intent_log() {
+    generate_config();
+    for disk in &[DISK1, DISK2, DISK3] {
+        common::delete_file(&[disk.to_string()]);
+        common::truncate_file(disk, 64 * 1024);
+    }
+
+    test_init!(CONFIG);
+
+    // write to the first region, then leave it marked by destroying the
+    // nexus while a child is being rebuilt
+    let data_offset = Reactor::block_on(async {
+        create_nexus(&[CHILD1, CHILD2]).await;
+        nexus_fill(0, 0x5a).await;
+
+        let nexus = nexus_lookup(NEXUS).unwrap();
+        nexus.add_child(CHILD3, false).await.unwrap();
+        nexus.pause_rebuild(CHILD3).await.unwrap();
+
+        let offset = nexus.data_ent_offset * 512;
+        nexus.destroy().await.unwrap();
+        offset
+    })
+    .unwrap();
+
+    // the second child misses the write of the first region, and has data
+    // of its own in a region which has not been written
+    file_fill(DISK2, data_offset, 4096, 0x11);
+    file_fill(DISK2, data_offset + 5 * REGION_BLOCKS * 512, 4096, 0x77);
+
+    // only the marked region is resynced once the nexus is created again
+    Reactor::block_on(async {
+        create_nexus(&[CHILD1, CHILD2]).await;
+    });
+    common::wait_for_rebuild(
+        CHILD2.to_string(),
+        RebuildState::Completed,
+        Duration::from_secs(20),
+    )
+    .unwrap();
+
+    assert!(file_check(DISK2, data_offset, 4096, 0x5a));
+    assert!(file_check(
+        DISK2,
+        data_offset + 5 * REGION_BLOCKS * 512,
+        4096,
+        0x77
+    ));
+
+    // a clean shutdown leaves no region marked
+    Reactor::block_on(async {
+        nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+        create_nexus(&[CHILD1, CHILD2]).await;
+
+        assert!(RebuildJob::lookup(CHILD2).is_err());
+        nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+    });
+
+    for disk in &[DISK1, DISK2, DISK3] {
+        common::delete_file(&[disk.to_string()]);
+    }
+    mayastor_env_stop(0);
+}
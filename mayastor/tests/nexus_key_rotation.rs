@@ -0,0 +1,62 @@
+use rpc::mayastor::ShareProtocolNexus;
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+};
+
+pub mod common;
+
+static NEXUS: &str = "key_rotation_nexus";
+static KEY: &str = "0123456789abcdef";
+
+#[test]
+fn nexus_key_rotation() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            preconditions().await;
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+/// the key of a nexus can only be rotated if it is published encrypted
+async fn preconditions() {
+    nexus_create(
+        NEXUS,
+        32 * 1024 * 1024,
+        None,
+        &[
+            "malloc:///malloc0?size_mb=64".into(),
+            "malloc:///malloc1?size_mb=64".into(),
+        ],
+    )
+    .await
+    .unwrap();
+
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    let e = nexus.start_key_rotation(KEY).await.unwrap_err();
+    assert!(e.to_string().contains("not published encrypted"), "{}", e);
+
+    nexus
+        .share(ShareProtocolNexus::NexusIscsi, None)
+        .await
+        .unwrap();
+    let e = nexus.start_key_rotation(KEY).await.unwrap_err();
+    assert!(e.to_string().contains("not published encrypted"), "{}", e);
+
+    // the key is checked before anything else
+    let e = nexus.start_key_rotation("short").await.unwrap_err();
+    assert_eq!(e.to_string(), "Invalid encryption key");
+
+    // there is nothing to stop
+    assert!(nexus.stop_key_rotation().is_err());
+    assert_eq!(nexus.get_key_rotation_progress().state, "none");
+
+    nexus.unshare_nexus().await.unwrap();
+    nexus.destroy().await.unwrap();
+}
@@ -0,0 +1,129 @@
+use std::{
+    fs::OpenOptions,
+    io::{Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    rebuild::{RebuildJob, RebuildState},
+};
+
+pub mod common;
+
+static NEXUS: &str = "label_v2_nexus";
+static UUID: &str = "7a1c4e2b-9f3d-4b6a-8e5c-2d1f0a9b8c7e";
+
+static DISK1: &str = "/tmp/label_v2_1.img";
+static DISK2: &str = "/tmp/label_v2_2.img";
+static CHILD1: &str = "aio:///tmp/label_v2_1.img?blk_size=512";
+static CHILD2: &str = "aio:///tmp/label_v2_2.img?blk_size=512";
+
+/// blocks of 512 bytes of the intent log and of a slot of the v2 label
+const LOG_BLOCKS: u64 = 128;
+const SLOT_BLOCKS: u64 = 8;
+
+async fn create_nexus(children: &[&str]) {
+    let children = children.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    nexus_create(NEXUS, 32 * 1024 * 1024, Some(UUID), &children)
+        .await
+        .unwrap();
+}
+
+/// the generation and the children of the v2 label of the child
+async fn label_v2(uri: &str) -> Option<(u64, Vec<String>)> {
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    let child = nexus
+        .children
+        .iter()
+        .find(|c| c.to_grpc().uri == uri)
+        .unwrap();
+    let label = child.probe_label().await.unwrap();
+    child
+        .probe_label_v2(&label)
+        .await
+        .unwrap()
+        .map(|r| (r.generation, r.children))
+}
+
+/// zero the slot of the v2 label on the disk
+fn zero_slot(path: &str, data_start: u64, slot: u64) {
+    let lba = data_start - LOG_BLOCKS - 2 * SLOT_BLOCKS + slot * SLOT_BLOCKS;
+    let mut file = OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(lba * 512)).unwrap();
+    file.write_all(&[0; SLOT_BLOCKS as usize * 512]).unwrap();
+    file.sync_all().unwrap();
+}
+
+#[test]
+fn nexus_label_v2() {
+    for disk in &[DISK1, DISK2] {
+        common::delete_file(&[disk.to_string()]);
+        common::truncate_file(disk, 64 * 1024);
+    }
+
+    test_init!();
+
+    let both = vec![CHILD1.to_string(), CHILD2.to_string()];
+
+    // the children list each other once the nexus is created, and the
+    // second one is no longer listed once it is removed
+    let data_start = Reactor::block_on(async {
+        create_nexus(&[CHILD1, CHILD2]).await;
+        assert_eq!(label_v2(CHILD1).await, Some((1, both.clone())));
+        assert_eq!(label_v2(CHILD2).await, Some((1, both.clone())));
+
+        let nexus = nexus_lookup(NEXUS).unwrap();
+        nexus.remove_child(CHILD2).await.unwrap();
+        assert_eq!(label_v2(CHILD1).await, Some((2, vec![CHILD1.to_string()])));
+
+        let data_start = nexus.data_ent_offset;
+        nexus.destroy().await.unwrap();
+        data_start
+    })
+    .unwrap();
+
+    // the removed child is rebuilt when it is part of the nexus again
+    Reactor::block_on(async {
+        create_nexus(&[CHILD1, CHILD2]).await;
+    });
+    common::wait_for_rebuild(
+        CHILD2.to_string(),
+        RebuildState::Completed,
+        Duration::from_secs(20),
+    )
+    .unwrap();
+    Reactor::block_on(async {
+        let nexus = nexus_lookup(NEXUS).unwrap();
+        assert!(nexus.children[1].io_stats().rebuild_bytes_written > 0);
+        assert_eq!(label_v2(CHILD1).await, Some((3, both.clone())));
+        nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+    });
+
+    // a torn record leaves the one of the previous generation in place
+    zero_slot(DISK1, data_start, 1);
+    Reactor::block_on(async {
+        create_nexus(&[CHILD1]).await;
+        assert_eq!(label_v2(CHILD1).await, Some((2, vec![CHILD1.to_string()])));
+        nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+    });
+
+    // children labelled without a v2 label are upgraded to it
+    for disk in &[DISK1, DISK2] {
+        zero_slot(disk, data_start, 0);
+        zero_slot(disk, data_start, 1);
+    }
+    Reactor::block_on(async {
+        create_nexus(&[CHILD1, CHILD2]).await;
+        assert!(RebuildJob::lookup(CHILD2).is_err());
+        assert_eq!(label_v2(CHILD1).await, Some((1, both.clone())));
+        assert_eq!(label_v2(CHILD2).await, Some((1, both.clone())));
+        nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+    });
+
+    for disk in &[DISK1, DISK2] {
+        common::delete_file(&[disk.to_string()]);
+    }
+    mayastor_env_stop(0);
+}
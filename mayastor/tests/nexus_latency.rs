@@ -0,0 +1,142 @@
+#![cfg(feature = "mock-children")]
+
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::unbounded;
+
+use mayastor::{
+    bdev::{
+        mock::{self, Behavior},
+        nexus_create,
+        nexus_lookup,
+        ChildStatus,
+        NexusStatus,
+    },
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    subsys::Config,
+};
+
+pub mod common;
+
+static MOCK0: &str = "mock:///latency0?size_mb=64";
+static MOCK1: &str = "mock:///latency1?size_mb=64";
+
+static NEXUS: &str = "latency_nexus";
+static CONFIG: &str = "/tmp/nexus_latency.yaml";
+
+fn generate_config() {
+    let mut config = Config::default();
+    config.nexus_opts.iscsi_enable = false;
+    config.nexus_opts.nvmf_enable = false;
+    config.nexus_opts.child_latency.threshold_ms = 50;
+    config.nexus_opts.child_latency.max_slow_writes = 2;
+    config.nexus_opts.child_latency.catch_up_delay_ms = 500;
+    config.write(CONFIG).unwrap();
+}
+
+#[test]
+fn nexus_latency() {
+    generate_config();
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs {
+        no_huge: true,
+        mem_size: 256,
+        mayastor_config: Some(CONFIG.to_string()),
+        ..Default::default()
+    });
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            nexus_create(
+                NEXUS,
+                32 * 1024 * 1024,
+                None,
+                &[MOCK0.to_string(), MOCK1.to_string()],
+            )
+            .await
+            .unwrap();
+        });
+
+        // a single slow write is tolerated
+        mock::set_behavior(
+            "latency0",
+            Behavior {
+                delay: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        );
+        Reactor::block_on(async {
+            write_nexus().await;
+        });
+        reactor_run_millis(10);
+        assert_eq!(child_status(MOCK0), ChildStatus::Online);
+
+        // the next one has the slow child degraded
+        Reactor::block_on(async {
+            write_nexus().await;
+        });
+        reactor_run_millis(10);
+        assert_eq!(child_status(MOCK0), ChildStatus::Degraded);
+        assert_eq!(child_status(MOCK1), ChildStatus::Online);
+        assert_eq!(
+            nexus_lookup(NEXUS).unwrap().status(),
+            NexusStatus::Degraded
+        );
+
+        // the writes are no longer held up by the degraded child
+        let start = Instant::now();
+        Reactor::block_on(async {
+            write_nexus().await;
+        });
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // the child is caught up once the delay has passed
+        mock::set_behavior("latency0", Behavior::default());
+        reactor_run_millis(2000);
+        assert_eq!(child_status(MOCK0), ChildStatus::Online);
+        assert_eq!(nexus_lookup(NEXUS).unwrap().status(), NexusStatus::Online);
+
+        Reactor::block_on(async {
+            nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+
+    common::delete_file(&[CONFIG.to_string()]);
+}
+
+fn child_status(uri: &str) -> ChildStatus {
+    nexus_lookup(NEXUS)
+        .unwrap()
+        .children
+        .iter()
+        .find(|c| c.to_grpc().uri == uri)
+        .map(|c| c.status())
+        .unwrap()
+}
+
+async fn write_nexus() {
+    let h = Bdev::open_by_name(NEXUS, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(0x5a);
+    h.write_at(0, &buf).await.unwrap();
+}
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
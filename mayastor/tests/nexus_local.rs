@@ -0,0 +1,85 @@
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, ChildStatus},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    pool::{create_pool, Pool},
+    replica::{Replica, ShareType},
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static POOL: &str = "pool_nexus_local";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static NEXUS: &str = "nexus_local";
+static UUID: &str = "5d3c7b1e-8a2f-4e6d-9c0b-1f2e3d4c5b6a";
+
+#[test]
+fn nexus_local() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+
+            let replica = Replica::create(UUID, POOL, 32 * 1024 * 1024, false)
+                .await
+                .unwrap();
+            replica.share(ShareType::Nvmf).await.unwrap();
+            let uri = replica.get_share_uri();
+
+            // the replica is used as the child without connecting to
+            // ourselves over nvmf
+            nexus_create(NEXUS, 16 * 1024 * 1024, None, &[uri.clone()])
+                .await
+                .unwrap();
+            let nexus = nexus_lookup(NEXUS).unwrap();
+            let child = nexus.get_child_by_name(&uri).unwrap();
+            assert_eq!(child.status(), ChildStatus::Online);
+            assert_eq!(child.local_bdev(), Some(UUID.to_string()));
+            assert_eq!(child.to_grpc().uri, uri);
+            assert_eq!(child.to_grpc().local_bdev, UUID);
+            assert!(Bdev::bdev_first()
+                .unwrap()
+                .into_iter()
+                .all(|b| b.driver() != "nvme"));
+
+            let h = Bdev::open_by_name(NEXUS, true)
+                .unwrap()
+                .into_handle()
+                .unwrap();
+            let mut buf = h.dma_malloc(4096).unwrap();
+            buf.fill(0x5a);
+            h.write_at(0, &buf).await.unwrap();
+            let mut read = h.dma_malloc(4096).unwrap();
+            h.read_at(0, &mut read).await.unwrap();
+            assert_eq!(read.as_slice(), buf.as_slice());
+            drop(h);
+
+            // the replica stays shared and outlives the nexus
+            nexus.destroy().await.unwrap();
+            let replica = Replica::lookup(UUID).unwrap();
+            assert_eq!(replica.get_share_type(), Some(ShareType::Nvmf));
+            assert!(Bdev::lookup_by_name(UUID).unwrap().is_claimed());
+
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
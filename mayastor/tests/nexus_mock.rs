@@ -0,0 +1,159 @@
+#![cfg(feature = "mock-children")]
+
+use std::time::{Duration, Instant};
+
+use mayastor::{
+    bdev::{
+        mock::{self, Behavior},
+        nexus_create,
+        nexus_lookup,
+        NexusStatus,
+    },
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static MOCK0: &str = "mock:///mock0?size_mb=64";
+static MOCK1: &str = "mock:///mock1?size_mb=64";
+static MOCK2: &str = "mock:///mock2?size_mb=64";
+
+static NEXUS: &str = "mock_nexus";
+
+#[test]
+fn nexus_mock() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs {
+        no_huge: true,
+        mem_size: 256,
+        ..Default::default()
+    });
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            mock_behaviors().await;
+            nexus_io().await;
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+fn open(name: &str) -> BdevHandle {
+    Bdev::open_by_name(name, true)
+        .unwrap()
+        .into_handle()
+        .unwrap()
+}
+
+/// the behaviours of a single mock bdev
+async fn mock_behaviors() {
+    bdev_create(MOCK2).await.unwrap();
+
+    let h = open("mock2");
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(1);
+    h.write_at(0, &buf).await.unwrap();
+
+    let mut read = h.dma_malloc(4096).unwrap();
+    h.read_at(0, &mut read).await.unwrap();
+    assert!(read.as_slice().iter().all(|b| *b == 1));
+
+    assert!(mock::set_behavior(
+        "mock2",
+        Behavior {
+            fail_reads: true,
+            ..Default::default()
+        }
+    ));
+    assert!(h.read_at(0, &mut read).await.is_err());
+
+    // a torn write only stores the first 4 of its 8 blocks
+    mock::set_behavior(
+        "mock2",
+        Behavior {
+            torn_writes: true,
+            ..Default::default()
+        },
+    );
+    buf.fill(2);
+    assert!(h.write_at(0, &buf).await.is_err());
+
+    mock::set_behavior("mock2", Behavior::default());
+    h.read_at(0, &mut read).await.unwrap();
+    assert!(read.as_slice()[.. 2048].iter().all(|b| *b == 2));
+    assert!(read.as_slice()[2048 ..].iter().all(|b| *b == 1));
+
+    let stats = mock::stats("mock2").unwrap();
+    assert_eq!(stats.writes, 2);
+    assert_eq!(stats.reads, 3);
+    assert_eq!(stats.failed, 2);
+
+    drop(h);
+    bdev_destroy(MOCK2).await.unwrap();
+    assert!(mock::stats("mock2").is_none());
+}
+
+/// a nexus over mock children
+async fn nexus_io() {
+    nexus_create(
+        NEXUS,
+        32 * 1024 * 1024,
+        None,
+        &[MOCK0.to_string(), MOCK1.to_string()],
+    )
+    .await
+    .unwrap();
+
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    assert_eq!(nexus.status(), NexusStatus::Online);
+
+    let h = open(NEXUS);
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(0xa5);
+
+    let before = mock::stats("mock1").unwrap();
+    h.write_at(0, &buf).await.unwrap();
+    assert_eq!(mock::stats("mock0").unwrap().failed, 0);
+    assert_eq!(mock::stats("mock1").unwrap().writes, before.writes + 1);
+
+    // the nexus write completes once the slowest child completes
+    mock::set_behavior(
+        "mock0",
+        Behavior {
+            delay: Some(Duration::from_millis(50)),
+            ..Default::default()
+        },
+    );
+    let start = Instant::now();
+    h.write_at(0, &buf).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // a write fails when one of the children fails it
+    mock::set_behavior("mock0", Behavior::default());
+    mock::set_behavior(
+        "mock1",
+        Behavior {
+            fail_writes: true,
+            ..Default::default()
+        },
+    );
+    assert!(h.write_at(0, &buf).await.is_err());
+
+    mock::set_behavior("mock1", Behavior::default());
+    let mut read = h.dma_malloc(4096).unwrap();
+    h.read_at(0, &mut read).await.unwrap();
+    assert!(read.as_slice().iter().all(|b| *b == 0xa5));
+
+    drop(h);
+    nexus.destroy().await.unwrap();
+    assert!(Bdev::lookup_by_name("mock0").is_none());
+}
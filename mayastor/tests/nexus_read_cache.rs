@@ -0,0 +1,121 @@
+use common::ms_exec::MayastorProcess;
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    subsys,
+    subsys::Config,
+};
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/rcache_disk.img";
+static BDEVNAME: &str = "aio:///tmp/rcache_disk.img?blk_size=512";
+static UUID: &str = "22222222-76b6-4fcf-864d-1027d4038756";
+
+static NEXUS: &str = "rcache_nexus";
+static LOCAL_NEXUS: &str = "rcache_local_nexus";
+static CACHE: &str = "malloc:///rcache?size_mb=8";
+
+fn generate_config() {
+    let mut config = Config::default();
+
+    config.base_bdevs = Some(vec![subsys::BaseBdev {
+        uri: format!("{}&uuid={}", BDEVNAME, UUID),
+    }]);
+    config.implicit_share_base = true;
+    config.nexus_opts.iscsi_enable = false;
+    config.nexus_opts.nvmf_replica_port = 8450;
+    config.nexus_opts.nvmf_nexus_port = 8460;
+    config.write("/tmp/rcache_child.yaml").unwrap();
+}
+
+#[test]
+fn nexus_read_cache() {
+    generate_config();
+    common::truncate_file(DISKNAME, 64 * 1024);
+
+    let args = vec![
+        "-s".to_string(),
+        "128".to_string(),
+        "-y".to_string(),
+        "/tmp/rcache_child.yaml".to_string(),
+    ];
+    let _ms = MayastorProcess::new(Box::from(args)).unwrap();
+
+    test_init!();
+
+    Reactor::block_on(async {
+        local_children().await;
+        read_cache().await;
+    });
+    mayastor_env_stop(0);
+}
+
+/// reads of local children are not cached
+async fn local_children() {
+    nexus_create(
+        LOCAL_NEXUS,
+        32 * 1024 * 1024,
+        None,
+        &["malloc:///rcache_malloc?size_mb=64".into()],
+    )
+    .await
+    .unwrap();
+
+    let nexus = nexus_lookup(LOCAL_NEXUS).unwrap();
+    assert!(nexus.set_read_cache(CACHE).await.is_err());
+    assert_eq!(nexus.read_cache_uri(), None);
+    nexus.destroy().await.unwrap();
+}
+
+async fn hits() -> u64 {
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    nexus.io_stats().await.unwrap().read_cache_hits
+}
+
+async fn read_cache() {
+    let child =
+        format!("nvmf://127.0.0.1:8450/nqn.2019-05.io.openebs:{}", UUID);
+    nexus_create(NEXUS, 32 * 1024 * 1024, None, &[child])
+        .await
+        .unwrap();
+
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    nexus.set_read_cache(CACHE).await.unwrap();
+    assert_eq!(nexus.read_cache_uri(), Some(CACHE.to_string()));
+
+    let h = BdevHandle::open(NEXUS, true, false).unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(0xaa);
+    h.write_at(0, &buf).await.unwrap();
+
+    // the first read misses and copies the line to the cache in the
+    // background, the following ones are served by the cache
+    for _ in 0 .. 100 {
+        buf.fill(0);
+        h.read_at(0, &mut buf).await.unwrap();
+        assert_eq!(buf.as_slice()[0], 0xaa);
+        if hits().await > 0 {
+            break;
+        }
+    }
+    assert!(hits().await > 0);
+
+    // a write drops the line from the cache
+    let before = hits().await;
+    buf.fill(0xbb);
+    h.write_at(0, &buf).await.unwrap();
+    buf.fill(0);
+    h.read_at(0, &mut buf).await.unwrap();
+    assert_eq!(buf.as_slice()[0], 0xbb);
+    assert_eq!(hits().await, before);
+    drop(h);
+
+    nexus.destroy().await.unwrap();
+}
@@ -0,0 +1,145 @@
+#![cfg(feature = "mock-children")]
+
+use std::time::Duration;
+
+use mayastor::{
+    bdev::{
+        mock::{self, Behavior},
+        nexus_create,
+        nexus_lookup,
+        ChildFaultPolicy,
+        NexusStatus,
+    },
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    subsys::Config,
+};
+
+pub mod common;
+
+static MOCK0: &str = "mock:///failover0?size_mb=64";
+static MOCK1: &str = "mock:///failover1?size_mb=64";
+static MOCK2: &str = "mock:///failover2?size_mb=64";
+
+static NEXUS: &str = "read_failover_nexus";
+static CONFIG: &str = "/tmp/nexus_read_failover.yaml";
+
+fn generate_config() {
+    let mut config = Config::default();
+    config.nexus_opts.iscsi_enable = false;
+    config.nexus_opts.nvmf_enable = false;
+    config.nexus_opts.child_io_retry.read_failover = true;
+    config.err_store_opts.enable_err_store = false;
+    config.write(CONFIG).unwrap();
+}
+
+/// the number of reads submitted to each of the mock bdevs
+fn reads() -> Vec<u64> {
+    ["failover0", "failover1", "failover2"]
+        .iter()
+        .map(|name| mock::stats(name).unwrap().reads)
+        .collect()
+}
+
+#[test]
+fn nexus_read_failover() {
+    generate_config();
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs {
+        no_huge: true,
+        mem_size: 256,
+        mayastor_config: Some(CONFIG.to_string()),
+        ..Default::default()
+    });
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            nexus_create(
+                NEXUS,
+                32 * 1024 * 1024,
+                None,
+                &[MOCK0.to_string(), MOCK1.to_string(), MOCK2.to_string()],
+            )
+            .await
+            .unwrap();
+
+            // the children are not faulted for the failed reads
+            nexus_lookup(NEXUS).unwrap().set_child_fault_policy(
+                Some(ChildFaultPolicy {
+                    max_errors: 1000,
+                    window: Duration::from_secs(60),
+                }),
+                Vec::new(),
+            );
+            assert!(write_nexus(0x5a).await);
+
+            let failing = Behavior {
+                fail_reads: true,
+                ..Default::default()
+            };
+            mock::set_behavior("failover0", failing.clone());
+            mock::set_behavior("failover1", failing.clone());
+
+            // whichever child a read starts on, it ends up on the healthy
+            // one, without being tried on a failing child twice
+            for _ in 0 .. 6 {
+                let before = reads();
+                assert_eq!(read_nexus().await, Some(0x5a));
+                let after = reads();
+                assert_eq!(after[2] - before[2], 1);
+                assert!(after[0] - before[0] <= 1);
+                assert!(after[1] - before[1] <= 1);
+            }
+
+            // the read fails once it has been tried on all of them
+            mock::set_behavior("failover2", failing);
+            let before = reads();
+            assert_eq!(read_nexus().await, None);
+            for (after, before) in reads().iter().zip(before) {
+                assert_eq!(after - before, 1);
+            }
+
+            for name in &["failover0", "failover1", "failover2"] {
+                mock::set_behavior(name, Behavior::default());
+            }
+            let nexus = nexus_lookup(NEXUS).unwrap();
+            assert_eq!(nexus.children.len(), 3);
+            assert_eq!(nexus.status(), NexusStatus::Online);
+            nexus.destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+async fn write_nexus(pattern: u8) -> bool {
+    let h = Bdev::open_by_name(NEXUS, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(pattern);
+    h.write_at(0, &buf).await.is_ok()
+}
+
+/// read the first block of the nexus, returns the value of its bytes if the
+/// read succeeded and they are all the same
+async fn read_nexus() -> Option<u8> {
+    let h = Bdev::open_by_name(NEXUS, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    h.read_at(0, &mut buf).await.ok()?;
+    let first = buf.as_slice()[0];
+    if buf.as_slice().iter().all(|&b| b == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,86 @@
+use rpc::mayastor::ShareProtocolNexus;
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+};
+
+pub mod common;
+
+static NEXUS: &str = "ro_nexus";
+
+#[test]
+fn nexus_read_only() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            read_only().await;
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+fn open() -> BdevHandle {
+    Bdev::open_by_name(NEXUS, true)
+        .unwrap()
+        .into_handle()
+        .unwrap()
+}
+
+async fn read_only() {
+    nexus_create(
+        NEXUS,
+        32 * 1024 * 1024,
+        None,
+        &[
+            "malloc:///malloc0?size_mb=64".into(),
+            "malloc:///malloc1?size_mb=64".into(),
+        ],
+    )
+    .await
+    .unwrap();
+
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    let uri = nexus
+        .share_read_only(ShareProtocolNexus::NexusIscsi, None)
+        .await
+        .unwrap();
+
+    // sharing it again read-only is idempotent, read-write is refused
+    assert_eq!(
+        nexus
+            .share_read_only(ShareProtocolNexus::NexusIscsi, None)
+            .await
+            .unwrap(),
+        uri
+    );
+    assert!(nexus
+        .share(ShareProtocolNexus::NexusIscsi, None)
+        .await
+        .is_err());
+
+    let h = open();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(0xff);
+    assert!(h.write_at(0, &buf).await.is_err());
+    h.read_at(0, &mut buf).await.unwrap();
+    drop(h);
+
+    // once unshared the nexus is writable again
+    nexus.unshare_nexus().await.unwrap();
+    let h = open();
+    h.write_at(0, &buf).await.unwrap();
+    drop(h);
+
+    nexus.destroy().await.unwrap();
+}
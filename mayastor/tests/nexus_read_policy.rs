@@ -0,0 +1,134 @@
+#![cfg(feature = "mock-children")]
+
+use std::time::Duration;
+
+use crossbeam::channel::unbounded;
+
+use mayastor::{
+    bdev::{
+        mock::{self, Behavior},
+        nexus_create,
+        nexus_lookup,
+        ReadPolicy,
+    },
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+        Reactors,
+    },
+};
+use rpc::mayastor as rpc;
+
+pub mod common;
+
+static MOCK0: &str = "mock:///policy0?size_mb=64";
+static MOCK1: &str = "mock:///policy1?size_mb=64";
+
+static NEXUS: &str = "read_policy_nexus";
+
+#[test]
+fn nexus_read_policy() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs {
+        no_huge: true,
+        mem_size: 256,
+        ..Default::default()
+    });
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            nexus_create(
+                NEXUS,
+                32 * 1024 * 1024,
+                None,
+                &[MOCK0.to_string(), MOCK1.to_string()],
+            )
+            .await
+            .unwrap();
+        });
+
+        let nexus = nexus_lookup(NEXUS).unwrap();
+        assert_eq!(nexus.read_policy(), ReadPolicy::RoundRobin);
+
+        // the reads alternate between the children
+        let before = child_reads();
+        Reactor::block_on(async {
+            for _ in 0 .. 4 {
+                read_nexus().await;
+            }
+        });
+        assert_eq!(reads_since(before), (2, 2));
+
+        // with a read in flight on the slow child, the reads go to the other
+        nexus.set_read_policy(ReadPolicy::QueueDepth);
+        assert_eq!(
+            nexus.to_grpc().read_policy,
+            rpc::ReadPolicy::QueueDepth as i32
+        );
+        mock::set_behavior(
+            "policy0",
+            Behavior {
+                delay: Some(Duration::from_millis(200)),
+                ..Default::default()
+            },
+        );
+        let before = child_reads();
+        Reactors::current().spawn_local(read_nexus());
+        Reactors::current().spawn_local(read_nexus());
+        reactor_run_millis(20);
+        Reactor::block_on(async {
+            for _ in 0 .. 4 {
+                read_nexus().await;
+            }
+        });
+        reactor_run_millis(300);
+        assert_eq!(reads_since(before), (1, 5));
+        mock::set_behavior("policy0", Behavior::default());
+
+        Reactor::block_on(async {
+            nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+/// the reads of the nexus served by each child
+fn child_reads() -> (u64, u64) {
+    let reads = |uri: &str| {
+        nexus_lookup(NEXUS)
+            .unwrap()
+            .children
+            .iter()
+            .find(|c| c.to_grpc().uri == uri)
+            .map(|c| c.io_stats().num_read_ops)
+            .unwrap()
+    };
+    (reads(MOCK0), reads(MOCK1))
+}
+
+fn reads_since(before: (u64, u64)) -> (u64, u64) {
+    let now = child_reads();
+    (now.0 - before.0, now.1 - before.1)
+}
+
+async fn read_nexus() {
+    let h = Bdev::open_by_name(NEXUS, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    h.read_at(0, &mut buf).await.unwrap();
+}
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
@@ -127,6 +127,44 @@ fn rebuild_test_add() {
     test_fini();
 }
 
+#[test]
+// test the choice of the child to rebuild from
+fn rebuild_test_source() {
+    test_ini("rebuild_test_source");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 2, true).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+
+        // the source has to be a healthy child
+        nexus
+            .add_child_from(&get_dev(2), true, Some(&get_dev(3)))
+            .await
+            .expect_err("source is not a child");
+        assert!(nexus.get_child_by_name(&get_dev(2)).is_err());
+
+        // the source is kept until the rebuild is started
+        nexus
+            .add_child_from(&get_dev(2), true, Some(&get_dev(1)))
+            .await
+            .unwrap();
+        nexus.start_rebuild(&get_dev(2)).await.unwrap();
+        assert_eq!(RebuildJob::lookup(&get_dev(2)).unwrap().source, get_dev(1));
+        nexus_test_child(2).await;
+
+        // a child is not rebuilt from itself
+        nexus.add_child(&get_dev(3), true).await.unwrap();
+        nexus
+            .start_rebuild_from(&get_dev(3), &get_dev(3))
+            .await
+            .expect_err("child is its own source");
+
+        nexus_lookup(nexus_name()).unwrap().destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
 #[test]
 fn rebuild_progress() {
     test_ini_large_nexus("rebuild_progress");
@@ -0,0 +1,65 @@
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, ChildStatus, NexusStatus},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+};
+
+pub mod common;
+
+static NEXUS: &str = "nexus_replace_child";
+static CHILD1: &str = "malloc:///malloc1?blk_size=512&size_mb=64";
+static CHILD2: &str = "malloc:///malloc2?blk_size=512&size_mb=64";
+static CHILD3: &str = "malloc:///malloc3?blk_size=512&size_mb=64";
+static CHILD4: &str = "malloc:///malloc4?blk_size=512&size_mb=64";
+
+fn child_status(uri: &str) -> Option<ChildStatus> {
+    nexus_lookup(NEXUS)
+        .unwrap()
+        .get_child_by_name(uri)
+        .ok()
+        .map(|c| c.status())
+}
+
+#[test]
+fn nexus_replace_child() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            nexus_create(
+                NEXUS,
+                32 * 1024 * 1024,
+                None,
+                &[CHILD1.to_string(), CHILD2.to_string()],
+            )
+            .await
+            .unwrap();
+            let nexus = nexus_lookup(NEXUS).unwrap();
+
+            // another child is online, so the new child is rebuilt
+            nexus.fault_child(CHILD1).await.unwrap();
+            nexus.replace_child(CHILD1, CHILD3).await.unwrap();
+            assert!(child_status(CHILD1).is_none());
+            assert!(child_status(CHILD3).is_some());
+            assert_eq!(nexus.children.len(), 2);
+        });
+
+        Reactor::block_on(async {
+            let nexus = nexus_lookup(NEXUS).unwrap();
+
+            // no other child is online, so the new child is up to date
+            nexus.offline_child(CHILD3).await.unwrap();
+            nexus.fault_child(CHILD2).await.unwrap();
+            nexus.replace_child(CHILD2, CHILD4).await.unwrap();
+            assert_eq!(child_status(CHILD4), Some(ChildStatus::Online));
+            assert_eq!(nexus.status(), NexusStatus::Degraded);
+
+            // a child which is not there can not be replaced
+            assert!(nexus.replace_child(CHILD1, CHILD2).await.is_err());
+
+            nexus.destroy().await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,152 @@
+use mayastor::{
+    bdev::nexus_create,
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+        Share,
+    },
+    nexus_uri::{bdev_create, bdev_get_name},
+};
+use spdk_sys::spdk_nvme_cmd;
+
+pub mod common;
+
+static NEXUS: &str = "nexus_reservations";
+static NEXUS_LOCAL: &str = "nexus_reservations_local";
+
+static MALLOC1: &str = "malloc:///malloc1?size_mb=64";
+static MALLOC2: &str = "malloc:///malloc2?size_mb=64";
+static MALLOC3: &str = "malloc:///malloc3?size_mb=64";
+
+const RESERVATION_REGISTER: u8 = 0x0d;
+const RESERVATION_REPORT: u8 = 0x0e;
+const RESERVATION_ACQUIRE: u8 = 0x11;
+const RESERVATION_RELEASE: u8 = 0x15;
+const READ: u8 = 0x02;
+
+const KEY: u64 = 0xfeed_beef;
+/// write exclusive reservation type
+const RTYPE: u32 = 1;
+
+fn command(opc: u8, cdw10: u32) -> spdk_nvme_cmd {
+    let mut cmd = spdk_nvme_cmd::default();
+    cmd.set_opc(opc.into());
+    cmd.__bindgen_anon_1.cdw10 = cdw10;
+    cmd
+}
+
+/// reservation type and number of registered controllers of the report
+async fn report(h: &BdevHandle) -> (u8, u16) {
+    let mut buf = h.dma_malloc(4096).unwrap();
+    h.nvme_io_passthru(
+        &command(RESERVATION_REPORT, 4096 / 4 - 1),
+        Some(&mut buf),
+    )
+    .await
+    .unwrap();
+    let data = buf.as_slice();
+    (data[4], u16::from_le_bytes([data[5], data[6]]))
+}
+
+/// register, acquire and release a reservation through the nexus
+async fn reservations(children: Vec<String>) {
+    let h = BdevHandle::open(NEXUS, true, false).unwrap();
+
+    // register the key, as the new key following the current one
+    let mut buf = h.dma_malloc(16).unwrap();
+    buf.as_mut_slice()[8 .. 16].copy_from_slice(&KEY.to_le_bytes());
+    h.nvme_io_passthru(&command(RESERVATION_REGISTER, 0), Some(&mut buf))
+        .await
+        .unwrap();
+    assert_eq!(report(&h).await, (0, 1));
+
+    // acquire and release the reservation with the current key
+    let mut buf = h.dma_malloc(16).unwrap();
+    buf.as_mut_slice()[.. 8].copy_from_slice(&KEY.to_le_bytes());
+    h.nvme_io_passthru(
+        &command(RESERVATION_ACQUIRE, RTYPE << 8),
+        Some(&mut buf),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report(&h).await, (RTYPE as u8, 1));
+
+    let mut buf = h.dma_malloc(8).unwrap();
+    buf.as_mut_slice().copy_from_slice(&KEY.to_le_bytes());
+    h.nvme_io_passthru(
+        &command(RESERVATION_RELEASE, RTYPE << 8),
+        Some(&mut buf),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report(&h).await, (0, 1));
+
+    // the registration is mirrored to each of the children
+    for uri in &children {
+        let name = bdev_get_name(uri).unwrap();
+        let ch = BdevHandle::open(&name, false, false).unwrap();
+        assert_eq!(report(&ch).await, (0, 1));
+    }
+
+    // other NVMe IO commands are not passed through
+    let mut buf = h.dma_malloc(512).unwrap();
+    assert!(h
+        .nvme_io_passthru(&command(READ, 0), Some(&mut buf))
+        .await
+        .is_err());
+}
+
+#[test]
+fn nexus_reservations() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        let children = Reactor::block_on(async {
+            // replicas shared over nvmf, as on other nodes
+            let mut children = Vec::new();
+            for uri in &[MALLOC1, MALLOC2] {
+                let name = bdev_create(uri).await.unwrap();
+                let bdev = Bdev::lookup_by_name(&name).unwrap();
+                children.push(bdev.share_nvmf().await.unwrap());
+            }
+            nexus_create(NEXUS, 32 * 1024 * 1024, None, &children)
+                .await
+                .unwrap();
+            children
+        })
+        .unwrap();
+
+        Reactor::block_on(reservations(children));
+
+        // local children do not support reservations
+        Reactor::block_on(async {
+            nexus_create(
+                NEXUS_LOCAL,
+                32 * 1024 * 1024,
+                None,
+                &[MALLOC3.to_string()],
+            )
+            .await
+            .unwrap();
+            let bdev = Bdev::lookup_by_name(NEXUS_LOCAL).unwrap();
+            assert!(!bdev.io_type_supported(7));
+
+            let h = BdevHandle::open(NEXUS_LOCAL, true, false).unwrap();
+            let mut buf = h.dma_malloc(4096).unwrap();
+            assert!(h
+                .nvme_io_passthru(
+                    &command(RESERVATION_REPORT, 4096 / 4 - 1),
+                    Some(&mut buf)
+                )
+                .await
+                .is_err());
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
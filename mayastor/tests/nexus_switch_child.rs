@@ -0,0 +1,115 @@
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, ChildStatus},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    pool::{create_pool, Pool},
+    replica::{Replica, ShareType},
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static POOL: &str = "pool_nexus_switch_child";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static NEXUS: &str = "nexus_switch_child";
+static UUID: &str = "7a1e5c3d-2b4f-4a6e-8d0c-9f1e2d3c4b5a";
+static OTHER: &str = "8b2f6d4e-3c5a-4b7f-9e1d-0a2f3e4d5c6b";
+
+async fn write_pattern(pattern: u8) {
+    let h = Bdev::open_by_name(NEXUS, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(pattern);
+    h.write_at(0, &buf).await.unwrap();
+}
+
+async fn check_pattern(pattern: u8) {
+    let h = Bdev::open_by_name(NEXUS, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    h.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|b| *b == pattern));
+}
+
+#[test]
+fn nexus_switch_child() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+
+            let replica = Replica::create(UUID, POOL, 16 * 1024 * 1024, false)
+                .await
+                .unwrap();
+            Replica::create(OTHER, POOL, 16 * 1024 * 1024, false)
+                .await
+                .unwrap();
+            replica.share(ShareType::Nvmf).await.unwrap();
+            let nvmf_uri = replica.get_share_uri();
+
+            nexus_create(NEXUS, 8 * 1024 * 1024, None, &[nvmf_uri.clone()])
+                .await
+                .unwrap();
+            write_pattern(0x5a).await;
+
+            // the child follows the replica as it is unshared
+            let (previous, nexuses) = replica.switch_share(None).await.unwrap();
+            assert_eq!(previous, nvmf_uri);
+            assert_eq!(nexuses, vec![NEXUS.to_string()]);
+            assert_eq!(replica.get_share_type(), None);
+
+            let local_uri = replica.get_share_uri();
+            let nexus = nexus_lookup(NEXUS).unwrap();
+            assert_eq!(nexus.children.len(), 1);
+            let child = nexus.get_child_by_name(&local_uri).unwrap();
+            assert_eq!(child.status(), ChildStatus::Online);
+            check_pattern(0x5a).await;
+            write_pattern(0xa5).await;
+
+            // and as it is shared again, without being rebuilt
+            let (previous, nexuses) =
+                replica.switch_share(Some(ShareType::Nvmf)).await.unwrap();
+            assert_eq!(previous, local_uri);
+            assert_eq!(nexuses, vec![NEXUS.to_string()]);
+            assert_eq!(replica.get_share_type(), Some(ShareType::Nvmf));
+
+            let nvmf_uri = replica.get_share_uri();
+            let nexus = nexus_lookup(NEXUS).unwrap();
+            let child = nexus.get_child_by_name(&nvmf_uri).unwrap();
+            assert_eq!(child.status(), ChildStatus::Online);
+            check_pattern(0xa5).await;
+
+            // a child is not switched to another replica
+            assert!(nexus
+                .switch_child(&nvmf_uri, &format!("bdev:///{}", OTHER))
+                .await
+                .is_err());
+            assert!(nexus.get_child_by_name(&nvmf_uri).is_ok());
+
+            nexus.destroy().await.unwrap();
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
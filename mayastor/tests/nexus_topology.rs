@@ -0,0 +1,74 @@
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, node_count},
+    core::{
+        mayastor_env_stop,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    pool::{create_pool, Pool},
+    replica::{Replica, ShareType},
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static POOL: &str = "pool_nexus_topology";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static NEXUS: &str = "nexus_topology";
+static UUID: &str = "3c9e1f5a-7b2d-4e8f-a6c4-2d1e0f9a8b7c";
+static CHILD: &str = "malloc:///malloc1?size_mb=64";
+
+#[test]
+fn nexus_topology() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+
+            let replica = Replica::create(UUID, POOL, 32 * 1024 * 1024, false)
+                .await
+                .unwrap();
+            replica.share(ShareType::Nvmf).await.unwrap();
+            let uri = replica.get_share_uri();
+
+            nexus_create(
+                NEXUS,
+                16 * 1024 * 1024,
+                None,
+                &[uri.clone(), CHILD.into()],
+            )
+            .await
+            .unwrap();
+
+            // the replica shared by this node is used directly, so neither
+            // child leaves the node
+            let children = nexus_lookup(NEXUS).unwrap().topology().await;
+            assert_eq!(children.len(), 2);
+            for child in &children {
+                assert!(child.local);
+                assert_eq!(child.transport, "local");
+                assert!(child.address.is_empty());
+                assert!(child.controller.is_none());
+                assert!(child.latency_us.is_some());
+            }
+            assert_eq!(children[0].uri, uri);
+            assert_eq!(node_count(&children), 1);
+
+            nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,84 @@
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+};
+
+pub mod common;
+
+static NEXUS: &str = "cached_nexus";
+
+#[test]
+fn nexus_write_cache() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+
+    ms.start(|| {
+        Reactor::block_on(async {
+            write_cache().await;
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
+
+/// the first byte of the nexus data on the given child
+async fn child_byte(name: &str, offset: u64) -> u8 {
+    let h = BdevHandle::open(name, false, false).unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    h.read_at(offset, &mut buf).await.unwrap();
+    buf.as_slice()[0]
+}
+
+async fn write_cache() {
+    nexus_create(
+        NEXUS,
+        32 * 1024 * 1024,
+        None,
+        &[
+            "malloc:///malloc0?size_mb=64".into(),
+            "malloc:///malloc1?size_mb=64".into(),
+        ],
+    )
+    .await
+    .unwrap();
+
+    let nexus = nexus_lookup(NEXUS).unwrap();
+    nexus.set_write_cache(1024 * 1024).await.unwrap();
+    assert_eq!(nexus.write_cache_size(), 1024 * 1024);
+
+    let h = BdevHandle::open(NEXUS, true, false).unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    let offset = nexus.data_ent_offset * h.get_bdev().block_len() as u64;
+
+    // the write is held in the cache until the nexus is flushed
+    buf.fill(0xaa);
+    h.write_at(0, &buf).await.unwrap();
+    assert_eq!(child_byte("malloc0", offset).await, 0);
+    h.flush().await.unwrap();
+    assert_eq!(child_byte("malloc0", offset).await, 0xaa);
+    assert_eq!(child_byte("malloc1", offset).await, 0xaa);
+
+    // a read of cached data writes it back first
+    buf.fill(0xbb);
+    h.write_at(0, &buf).await.unwrap();
+    buf.fill(0);
+    h.read_at(0, &mut buf).await.unwrap();
+    assert_eq!(buf.as_slice()[0], 0xbb);
+    assert_eq!(child_byte("malloc1", offset).await, 0xbb);
+
+    // disabling the cache writes back what is left
+    buf.fill(0xcc);
+    h.write_at(0, &buf).await.unwrap();
+    nexus.set_write_cache(0).await.unwrap();
+    assert_eq!(nexus.write_cache_size(), 0);
+    assert_eq!(child_byte("malloc0", offset).await, 0xcc);
+    drop(h);
+
+    nexus.destroy().await.unwrap();
+}
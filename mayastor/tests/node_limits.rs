@@ -0,0 +1,120 @@
+use rpc::mayastor::{CreatePoolRequest, ShareProtocolNexus};
+use tonic::{Code, Status};
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    pool::{create_pool, Pool},
+    replica::{Replica, ShareType},
+    subsys::Config,
+};
+
+pub mod common;
+
+static CONFIG: &str = "/tmp/node_limits.yaml";
+static POOL: &str = "pool_node_limits";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static UUID1: &str = "3f2c9a61-7d4b-4e8a-b1c5-9e6d2f0a8b11";
+static UUID2: &str = "3f2c9a61-7d4b-4e8a-b1c5-9e6d2f0a8b12";
+static UUID3: &str = "3f2c9a61-7d4b-4e8a-b1c5-9e6d2f0a8b13";
+
+const MB: u64 = 1024 * 1024;
+
+fn generate_config() {
+    let mut config = Config::default();
+    config.node_limits.max_nexuses = 1;
+    config.node_limits.max_replicas = 2;
+    config.node_limits.max_shares = 2;
+    config.node_limits.max_capacity = 24 * MB;
+    config.write(CONFIG).unwrap();
+}
+
+#[test]
+fn node_limits() {
+    generate_config();
+    common::mayastor_test_init();
+    let mut args = MayastorCliArgs::default();
+    args.mayastor_config = Some(CONFIG.to_string());
+
+    MayastorEnvironment::new(args)
+        .start(|| {
+            Reactor::block_on(async {
+                limits().await;
+            });
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+}
+
+async fn limits() {
+    create_pool(CreatePoolRequest {
+        name: POOL.into(),
+        disks: vec![DISK.into()],
+        block_size: 0,
+        io_if: 0,
+        adopt: false,
+        idempotency_key: String::new(),
+    })
+    .await
+    .unwrap();
+
+    // the capacity runs out before the number of replicas does
+    let replica = Replica::create(UUID1, POOL, 16 * MB, false).await.unwrap();
+    let e = Replica::create(UUID2, POOL, 16 * MB, false)
+        .await
+        .err()
+        .unwrap();
+    let status = Status::from(e);
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert!(status.message().contains("max_capacity"));
+
+    Replica::create(UUID2, POOL, 8 * MB, false).await.unwrap();
+    let e = Replica::create(UUID3, POOL, 4 * MB, false)
+        .await
+        .err()
+        .unwrap();
+    let status = Status::from(e);
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert!(status.message().contains("max_replicas"));
+
+    nexus_create(
+        "nexus_limits0",
+        8 * MB,
+        None,
+        &["malloc:///malloc1?size_mb=16".into()],
+    )
+    .await
+    .unwrap();
+    let e = nexus_create(
+        "nexus_limits1",
+        8 * MB,
+        None,
+        &["malloc:///malloc2?size_mb=16".into()],
+    )
+    .await
+    .err()
+    .unwrap();
+    let status = Status::from(e);
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert!(status.message().contains("max_nexuses"));
+
+    // a replica and the nexus use up the shares
+    replica.share(ShareType::Nvmf).await.unwrap();
+    let nexus = nexus_lookup("nexus_limits0").unwrap();
+    nexus
+        .share(ShareProtocolNexus::NexusIscsi, None)
+        .await
+        .unwrap();
+    let e = Replica::lookup(UUID2)
+        .unwrap()
+        .share(ShareType::Nvmf)
+        .await
+        .err()
+        .unwrap();
+    let status = Status::from(e);
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert!(status.message().contains("max_shares"));
+
+    nexus.destroy().await.unwrap();
+    Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+}
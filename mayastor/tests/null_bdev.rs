@@ -0,0 +1,87 @@
+use mayastor::{
+    bdev::pattern,
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        DmaBuf,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static NULL0: &str = "null:///null0?size_mb=64&blk_size=4096";
+static NULL1: &str = "null:///null1?num_blocks=1024&verify=true";
+
+#[test]
+fn null_bdev() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            assert!(bdev_create("null:///null2").await.is_err());
+            assert!(bdev_create("null:///null2?size_mb=1&blk_size=100")
+                .await
+                .is_err());
+            assert!(bdev_create("null:///null2?size_mb=1&num_blocks=1")
+                .await
+                .is_err());
+
+            assert_eq!(bdev_create(NULL0).await.unwrap(), "null0");
+            assert_eq!(bdev_create(NULL1).await.unwrap(), "null1");
+
+            let bdev = Bdev::lookup_by_name("null0").unwrap();
+            assert_eq!(bdev.size_in_bytes(), 64 * 1024 * 1024);
+            assert_eq!(bdev.block_len(), 4096);
+            assert!(pattern::mismatches("null0").is_none());
+
+            let bdev = Bdev::lookup_by_name("null1").unwrap();
+            assert_eq!(bdev.num_blocks(), 1024);
+            assert_eq!(bdev.block_len(), 512);
+            assert_eq!(pattern::mismatches("null1"), Some(0));
+        });
+
+        let h = Bdev::open_by_name("null1", true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = DmaBuf::new(4096, 9).unwrap();
+
+        Reactor::block_on(async move {
+            // the blocks read are stamped with the pattern of their LBA
+            buf.fill(0);
+            h.read_at(8 * 512, &mut buf).await.unwrap();
+            assert_eq!(pattern::verify(buf.as_slice(), 8, 512), None);
+            assert_eq!(pattern::verify(buf.as_slice(), 0, 512), Some(0));
+
+            // and can be written back as they are
+            h.write_at(8 * 512, &buf).await.unwrap();
+
+            pattern::stamp(buf.as_mut_slice(), 16, 512);
+            h.write_at(16 * 512, &buf).await.unwrap();
+
+            // but not to another LBA
+            assert!(h.write_at(24 * 512, &buf).await.is_err());
+
+            // nor damaged
+            pattern::stamp(buf.as_mut_slice(), 16, 512);
+            buf.as_mut_slice()[1000] ^= 0xff;
+            assert!(h.write_at(16 * 512, &buf).await.is_err());
+
+            assert_eq!(pattern::mismatches("null1"), Some(2));
+        });
+
+        Reactor::block_on(async {
+            bdev_destroy(NULL0).await.unwrap();
+            bdev_destroy(NULL1).await.unwrap();
+            assert!(pattern::mismatches("null1").is_none());
+            assert!(Bdev::lookup_by_name("null1").is_none());
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,40 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        CoreError,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static BDEV: &str = "malloc:///malloc0?blk_size=512&size_mb=64";
+
+#[test]
+fn nvme_telemetry_unsupported() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            bdev_create(BDEV).await.unwrap();
+
+            // only local NVMe devices have a telemetry log
+            let bdev = Bdev::lookup_by_name("malloc0").unwrap();
+            match bdev.nvme_telemetry_log(3).await {
+                Err(CoreError::NvmeTelemetryUnsupported {
+                    name,
+                }) => assert_eq!(name, "malloc0"),
+                r => panic!("unexpected result {:?}", r.map(|l| l.len())),
+            }
+
+            bdev_destroy(BDEV).await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,98 @@
+use common::ms_exec::MayastorProcess;
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, NexusBdevError},
+    subsys,
+    subsys::Config,
+};
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/hostname_disk.img";
+static BDEVNAME: &str = "aio:///tmp/hostname_disk.img?blk_size=512";
+static UUID: &str = "33333333-76b6-4fcf-864d-1027d4038756";
+
+static NEXUS: &str = "hostname_nexus";
+
+fn generate_config() {
+    let mut config = Config::default();
+
+    config.base_bdevs = Some(vec![subsys::BaseBdev {
+        uri: format!("{}&uuid={}", BDEVNAME, UUID),
+    }]);
+    config.implicit_share_base = true;
+    config.nexus_opts.iscsi_enable = false;
+    config.nexus_opts.nvmf_replica_port = 8470;
+    config.nexus_opts.nvmf_nexus_port = 8480;
+    config.write("/tmp/hostname_child.yaml").unwrap();
+}
+
+#[test]
+fn nvmf_hostname() {
+    generate_config();
+    common::truncate_file(DISKNAME, 64 * 1024);
+
+    let args = vec![
+        "-s".to_string(),
+        "128".to_string(),
+        "-y".to_string(),
+        "/tmp/hostname_child.yaml".to_string(),
+    ];
+    let _ms = MayastorProcess::new(Box::from(args)).unwrap();
+
+    test_init!();
+
+    Reactor::block_on(async {
+        unknown_host().await;
+        hostname_child().await;
+    });
+    mayastor_env_stop(0);
+
+    common::delete_file(&[
+        DISKNAME.to_string(),
+        "/tmp/hostname_child.yaml".to_string(),
+    ]);
+}
+
+/// a host name which does not resolve fails the create
+async fn unknown_host() {
+    let uri = format!(
+        "nvmf://no-such-host.invalid:8470/nqn.2019-05.io.openebs:{}",
+        UUID
+    );
+    match bdev_create(&uri).await {
+        Err(NexusBdevError::ResolveHost {
+            host, ..
+        }) => assert_eq!(host, "no-such-host.invalid"),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+/// a child addressed by the name of its host
+async fn hostname_child() {
+    let child =
+        format!("nvmf://localhost:8470/nqn.2019-05.io.openebs:{}", UUID);
+    nexus_create(NEXUS, 32 * 1024 * 1024, None, &[child])
+        .await
+        .unwrap();
+
+    let nexus = nexus_lookup(NEXUS).unwrap();
+
+    let h = BdevHandle::open(NEXUS, true, false).unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(0xaa);
+    h.write_at(0, &buf).await.unwrap();
+    buf.fill(0);
+    h.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|&b| b == 0xaa));
+    drop(h);
+
+    nexus.destroy().await.unwrap();
+}
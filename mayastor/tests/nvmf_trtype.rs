@@ -0,0 +1,74 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, NexusBdevError},
+    pool::{create_pool, Pool},
+    replica::{Replica, ShareType},
+    subsys::{Config, NvmfTransport},
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static POOL: &str = "pool_nvmf_trtype";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static UUID: &str = "0e6c8f2a-3b4d-4c5e-8f9a-1b2c3d4e5f60";
+
+#[test]
+fn nvmf_trtype() {
+    assert_eq!("RDMA".parse::<NvmfTransport>(), Ok(NvmfTransport::Rdma));
+    assert!("fc".parse::<NvmfTransport>().is_err());
+
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            // an unknown transport is refused before connecting
+            let uri = format!(
+                "nvmf://127.0.0.1:8420/nqn.2019-05.io.openebs:{}?trtype=fc",
+                UUID
+            );
+            match bdev_create(&uri).await {
+                Err(NexusBdevError::UriInvalid {
+                    ..
+                }) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+
+            // without RDMA the replica is shared over TCP only, and the URI
+            // does not carry the transport
+            assert_eq!(NvmfTransport::enabled(), vec![NvmfTransport::Tcp]);
+            assert_eq!(
+                Config::get().nvmf_tcp_tgt_conf.share_transport,
+                NvmfTransport::Tcp
+            );
+
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+
+            let replica = Replica::create(UUID, POOL, 8 * 1024 * 1024, false)
+                .await
+                .unwrap();
+            replica.share(ShareType::Nvmf).await.unwrap();
+            let uri = replica.get_share_uri();
+            assert!(uri.starts_with("nvmf://"));
+            assert!(!uri.contains("trtype"));
+
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
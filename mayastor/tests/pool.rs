@@ -34,6 +34,8 @@ fn create_pool_legacy() {
                         disks: vec![DISKNAME1.to_string()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await
                     .unwrap();
@@ -46,6 +48,8 @@ fn create_pool_legacy() {
                         disks: vec!["malloc:///malloc0?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await
                     .unwrap();
@@ -60,6 +64,8 @@ fn create_pool_legacy() {
                         disks: vec!["malloc:///malloc0?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await;
 
@@ -74,6 +80,8 @@ fn create_pool_legacy() {
                         disks: vec!["malloc:///malloc1?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await;
                     assert_eq!(pool.is_err(), true)
@@ -83,6 +91,9 @@ fn create_pool_legacy() {
                 Reactor::block_on(async {
                     let pool = Pool::lookup("uri").unwrap();
                     assert_eq!(pool.get_name(), "uri");
+                    // the pool is found by its uuid as well
+                    let by_uuid = Pool::lookup(&pool.get_uuid()).unwrap();
+                    assert_eq!(by_uuid.get_name(), "uri");
                     let bdev = pool.get_base_bdev();
                     assert_eq!(bdev.name(), "malloc0");
                     assert_eq!(
@@ -113,6 +124,8 @@ fn create_pool_legacy() {
                         disks: vec!["malloc:///malloc0?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await
                     .unwrap();
@@ -122,6 +135,8 @@ fn create_pool_legacy() {
                         disks: vec![DISKNAME1.to_string()],
                         block_size: 0,
                         io_if: 0,
+                        adopt: false,
+                        idempotency_key: String::new(),
                     })
                     .await
                     .unwrap();
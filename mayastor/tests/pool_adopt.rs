@@ -0,0 +1,80 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+        Uuid,
+    },
+    lvs::Lvs,
+    pool::{create_pool, Pool},
+    replica::ReplicaIter,
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/disk_adopt.img";
+static DISKURI: &str = "aio:///tmp/disk_adopt.img";
+
+fn request(name: &str, adopt: bool) -> CreatePoolRequest {
+    CreatePoolRequest {
+        name: name.into(),
+        disks: vec![DISKURI.into()],
+        block_size: 0,
+        io_if: 0,
+        adopt,
+        idempotency_key: String::new(),
+    }
+}
+
+#[test]
+fn pool_adopt() {
+    common::delete_file(&[DISKNAME.into()]);
+    common::truncate_file(DISKNAME, 64 * 1024);
+    common::mayastor_test_init();
+
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        // a store with a name and an lvol as created with the SPDK RPCs
+        Reactor::block_on(async {
+            let lvs = Lvs::create_or_import(request("foreign", false))
+                .await
+                .unwrap();
+            lvs.create_lvol("lvol0", 8 * 1024 * 1024, true)
+                .await
+                .unwrap();
+            lvs.export().await.unwrap();
+        });
+
+        Reactor::block_on(async {
+            // the disk is in use by another pool
+            assert!(create_pool(request("adopted", false)).await.is_err());
+            assert!(Pool::lookup("adopted").is_none());
+
+            create_pool(request("adopted", true)).await.unwrap();
+            assert!(Pool::lookup("adopted").is_some());
+            assert!(Pool::lookup("foreign").is_none());
+
+            let replica = ReplicaIter::new()
+                .find(|r| r.get_pool_name() == "adopted")
+                .unwrap();
+            assert!(Uuid::parse_str(replica.get_uuid()).is_ok());
+
+            // the lvol can still be found by its former name
+            let bdev = Bdev::lookup_by_name("lvol0").unwrap();
+            assert_eq!(bdev.name(), replica.get_uuid());
+
+            // adopting the pool again is a no-op
+            create_pool(request("adopted", true)).await.unwrap();
+
+            Pool::lookup("adopted").unwrap().destroy().await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+
+    common::delete_file(&[DISKNAME.into()]);
+}
@@ -0,0 +1,87 @@
+use std::{fs, os::unix::fs::symlink};
+
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    lvs::Lvs,
+    pool::{create_pool, Pool},
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static POOL: &str = "pool_devlink";
+static DISKNAME1: &str = "/tmp/pool_devlink1.img";
+static DISKNAME2: &str = "/tmp/pool_devlink2.img";
+static LINK_DIR: &str = "/dev/disk/by-id";
+static LINK: &str = "/dev/disk/by-id/mayastor-test-pool-devlink";
+static URI: &str = "devlink://by-id/mayastor-test-pool-devlink";
+
+fn request(disk: &str) -> CreatePoolRequest {
+    CreatePoolRequest {
+        name: POOL.into(),
+        disks: vec![disk.into()],
+        block_size: 0,
+        io_if: 0,
+        adopt: false,
+        idempotency_key: String::new(),
+    }
+}
+
+/// point the link to the disk, as udev would after it has been renamed
+fn link_to(disk: &str) {
+    let _ = fs::remove_file(LINK);
+    symlink(disk, LINK).unwrap();
+}
+
+#[test]
+fn pool_devlink() {
+    common::delete_file(&[DISKNAME1.into(), DISKNAME2.into()]);
+    common::truncate_file(DISKNAME1, 64 * 1024);
+    fs::create_dir_all(LINK_DIR).unwrap();
+    link_to(DISKNAME1);
+
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            // a partition which does not exist is reported as such
+            let err = create_pool(request(
+                "partuuid://d3a1c2b4-5e6f-4a7b-8c9d-0e1f2a3b4c5d",
+            ))
+            .await
+            .unwrap_err();
+            assert!(err.to_string().contains("by-partuuid"));
+
+            // the pool is created on the device the link points to and is
+            // reported by the link
+            let pool = create_pool(request(URI)).await.unwrap();
+            assert_eq!(pool.disks, vec![URI.to_string()]);
+            assert!(Bdev::lookup_by_name(DISKNAME1).is_some());
+
+            Lvs::lookup(POOL).unwrap().export().await.unwrap();
+            assert!(Bdev::lookup_by_name(DISKNAME1).is_none());
+
+            // the device is known by another name now, the pool is imported
+            // from it by the same URI
+            fs::rename(DISKNAME1, DISKNAME2).unwrap();
+            link_to(DISKNAME2);
+
+            let pool = create_pool(request(URI)).await.unwrap();
+            assert_eq!(pool.disks, vec![URI.to_string()]);
+            assert!(Bdev::lookup_by_name(DISKNAME2).is_some());
+
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+
+    let _ = fs::remove_file(LINK);
+    common::delete_file(&[DISKNAME1.into(), DISKNAME2.into()]);
+}
@@ -0,0 +1,80 @@
+use mayastor::{
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    lvs::{DiskHealth, Lvs},
+    pool::create_pool,
+    subsys::PoolHealthOpts,
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static POOL: &str = "pool_health";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+
+/// a SMART / health log page with the given spare and media errors
+fn health_log(available_spare: u8, media_errors: u64) -> Vec<u8> {
+    let mut page = vec![0u8; 512];
+    page[1 .. 3].copy_from_slice(&310u16.to_le_bytes());
+    page[3] = available_spare;
+    page[4] = 10;
+    page[5] = 42;
+    page[128 .. 136].copy_from_slice(&1000u64.to_le_bytes());
+    page[144 .. 152].copy_from_slice(&3u64.to_le_bytes());
+    page[160 .. 168].copy_from_slice(&media_errors.to_le_bytes());
+    page
+}
+
+#[test]
+fn nvme_health_log() {
+    let health = DiskHealth::from_nvme_log(&health_log(90, 5));
+    assert_eq!(health.source, "nvme");
+    assert_eq!(health.temperature, 310);
+    assert_eq!(health.available_spare, Some(90));
+    assert_eq!(health.available_spare_threshold, Some(10));
+    assert_eq!(health.percentage_used, Some(42));
+    assert_eq!(health.power_on_hours, 1000);
+    assert_eq!(health.unsafe_shutdowns, 3);
+    assert_eq!(health.media_errors, 5);
+
+    // no threshold is set by default
+    assert_eq!(health.fault_reason(&PoolHealthOpts::default()), None);
+
+    let opts = PoolHealthOpts {
+        max_media_errors: 10,
+        min_available_spare: 20,
+    };
+    assert_eq!(health.fault_reason(&opts), None);
+    assert!(DiskHealth::from_nvme_log(&health_log(90, 11))
+        .fault_reason(&opts)
+        .is_some());
+    assert!(DiskHealth::from_nvme_log(&health_log(19, 0))
+        .fault_reason(&opts)
+        .is_some());
+}
+
+#[test]
+fn pool_health() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+
+            // a malloc disk does not report its health
+            let lvs = Lvs::lookup(POOL).unwrap();
+            assert_eq!(lvs.disk_health().await, None);
+            lvs.destroy().await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,83 @@
+use common::ms_exec::MayastorProcess;
+use mayastor::{
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    pool::{create_pool, Pool, PoolsIter},
+    subsys,
+    subsys::Config,
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static DISKNAME1: &str = "/tmp/pool_label1.img";
+static DISKNAME2: &str = "/tmp/pool_label2.img";
+static CONFIG: &str = "/tmp/pool_label.yaml";
+
+fn generate_config() {
+    let mut config = Config::default();
+    config.nexus_opts.iscsi_enable = false;
+    config.pools = Some(vec![subsys::Pool {
+        name: "foreign".into(),
+        uuid: Default::default(),
+        disks: vec![DISKNAME1.into()],
+        blk_size: 512,
+        io_if: 1,
+        replicas: Vec::new(),
+    }]);
+    config.write(CONFIG).unwrap();
+}
+
+fn request(name: &str, disk: &str) -> CreatePoolRequest {
+    CreatePoolRequest {
+        name: name.into(),
+        disks: vec![disk.into()],
+        block_size: 0,
+        io_if: 1,
+        adopt: false,
+        idempotency_key: String::new(),
+    }
+}
+
+#[test]
+fn pool_label() {
+    generate_config();
+    common::delete_file(&[DISKNAME1.into(), DISKNAME2.into()]);
+    common::truncate_file(DISKNAME1, 64 * 1024);
+    common::truncate_file(DISKNAME2, 64 * 1024);
+
+    // another node creates a pool on the first disk
+    let args = vec![
+        "-s".to_string(),
+        "128".to_string(),
+        "-N".to_string(),
+        "other-node".to_string(),
+        "-y".to_string(),
+        CONFIG.to_string(),
+    ];
+    let mut ms = MayastorProcess::new(Box::from(args)).unwrap();
+    ms.sig_term();
+
+    test_init!();
+
+    Reactor::block_on(async {
+        // the pool of the other node is neither imported nor overwritten
+        let err = create_pool(request("foreign", DISKNAME1))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("node other-node"));
+        assert!(PoolsIter::new().next().is_none());
+
+        // a pool created by this node carries its label
+        let pool = create_pool(request("local", DISKNAME2)).await.unwrap();
+        let label = pool.label.unwrap();
+        assert_eq!(label.node, "mayastor-node");
+        assert_eq!(label.cluster_id, "");
+        assert!(!label.uuid.is_empty());
+        assert!(label.created_at > 0);
+
+        Pool::lookup("local").unwrap().destroy().await.unwrap();
+    });
+
+    common::delete_file(&[DISKNAME1.into(), DISKNAME2.into()]);
+    mayastor_env_stop(0);
+}
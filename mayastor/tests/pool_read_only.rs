@@ -0,0 +1,124 @@
+use crossbeam::channel::unbounded;
+use std::time::Duration;
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, ChildStatus, NexusStatus},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    lvs::Lvs,
+    pool::{create_pool, Pool},
+    replica::{Replica, ShareType},
+};
+use rpc::mayastor::{CreatePoolRequest, PoolState};
+
+pub mod common;
+
+static POOL: &str = "pool_read_only";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static LOCAL: &str = "malloc:///malloc1?size_mb=64";
+static NEXUS: &str = "nexus_pool_read_only";
+static UUID: &str = "c9a3f6d1-2b8e-4c5a-9f7e-1d3b5a7c9e01";
+static UUID2: &str = "c9a3f6d1-2b8e-4c5a-9f7e-1d3b5a7c9e02";
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
+
+async fn write_nexus() -> bool {
+    let h = Bdev::open_by_name(NEXUS, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = h.dma_malloc(4096).unwrap();
+    buf.fill(0xff);
+    h.write_at(0, &buf).await.is_ok()
+}
+
+#[test]
+fn pool_read_only() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        let replica_uri = Reactor::block_on(async {
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+
+            // the replica is shared over nvmf, as for a nexus on another node
+            let replica = Replica::create(UUID, POOL, 32 * 1024 * 1024, false)
+                .await
+                .unwrap();
+            replica.share(ShareType::Nvmf).await.unwrap();
+            let uri = replica.get_share_uri();
+
+            nexus_create(
+                NEXUS,
+                16 * 1024 * 1024,
+                None,
+                &[uri.clone(), LOCAL.to_string()],
+            )
+            .await
+            .unwrap();
+            assert!(write_nexus().await);
+
+            Lvs::lookup(POOL)
+                .unwrap()
+                .set_read_only("disk is worn out")
+                .await;
+
+            // the write to the replica is refused
+            assert!(!write_nexus().await);
+            uri
+        })
+        .unwrap();
+
+        // the child is faulted from the master core
+        reactor_run_millis(100);
+
+        Reactor::block_on(async {
+            let nexus = nexus_lookup(NEXUS).unwrap();
+            let child = nexus.get_child_by_name(&replica_uri).unwrap();
+            assert_eq!(child.status(), ChildStatus::Faulted);
+            assert!(child.is_read_only());
+            assert!(child.to_grpc().read_only);
+            assert_eq!(nexus.status(), NexusStatus::Degraded);
+
+            // the remaining child takes the writes
+            assert!(write_nexus().await);
+
+            // the pool and its replicas report being read-only
+            let pool: rpc::mayastor::Pool = Pool::lookup(POOL).unwrap().into();
+            assert_eq!(pool.state, PoolState::PoolReadOnly as i32);
+            assert_eq!(pool.state_reason, "disk is worn out");
+            assert!(Replica::lookup(UUID).unwrap().is_read_only());
+
+            // no replicas are created on it
+            assert!(Replica::create(UUID2, POOL, 4 * 1024 * 1024, false)
+                .await
+                .is_err());
+
+            nexus.destroy().await.unwrap();
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,57 @@
+use mayastor::core::preflight::{pci_address, Preflight, PreflightMode};
+
+#[test]
+fn preflight_mode() {
+    assert_eq!("off".parse::<PreflightMode>(), Ok(PreflightMode::Off));
+    assert_eq!("warn".parse::<PreflightMode>(), Ok(PreflightMode::Warn));
+    assert_eq!(
+        "enforce".parse::<PreflightMode>(),
+        Ok(PreflightMode::Enforce)
+    );
+    assert!("strict".parse::<PreflightMode>().is_err());
+    assert_eq!(PreflightMode::Enforce.to_string(), "enforce");
+}
+
+#[test]
+fn preflight_pci_address() {
+    assert_eq!(
+        pci_address("pcie:///0000:00:04.0"),
+        Some("0000:00:04.0".to_string())
+    );
+    assert_eq!(pci_address("aio:///dev/sda?blk_size=4096"), None);
+}
+
+#[test]
+fn preflight_pci_devices() {
+    // a device which does not exist is reported as fatal
+    let findings = Preflight {
+        no_huge: true,
+        pci_devices: vec!["ffff:ff:1f.7".into()],
+        ..Default::default()
+    }
+    .run();
+
+    assert!(findings
+        .iter()
+        .any(|f| f.fatal && f.message.contains("ffff:ff:1f.7")));
+
+    // as is any device when PCIe is disabled
+    let findings = Preflight {
+        no_huge: true,
+        no_pci: true,
+        pci_devices: vec!["0000:00:04.0".into()],
+        ..Default::default()
+    }
+    .run();
+
+    assert!(findings.iter().any(|f| f.fatal && f.check == "pcie"));
+
+    // without hugepages or PCIe devices nothing fatal can be found
+    let findings = Preflight {
+        no_huge: true,
+        ..Default::default()
+    }
+    .run();
+
+    assert!(findings.iter().all(|f| !f.fatal));
+}
@@ -0,0 +1,60 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+    probe::probe_replica_path,
+};
+use rpc::mayastor::ProbeReplicaPathRequest;
+
+pub mod common;
+
+static MALLOC: &str = "malloc:///probe0?size_mb=8";
+
+fn request(queue_depths: Vec<u32>, io_size: u32) -> ProbeReplicaPathRequest {
+    ProbeReplicaPathRequest {
+        uri: MALLOC.into(),
+        duration_ms: 100,
+        queue_depths,
+        io_size,
+    }
+}
+
+#[test]
+fn probe_replica() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            let reply =
+                probe_replica_path(request(vec![1, 4], 0)).await.unwrap();
+            assert_eq!(reply.io_size, 64 * 1024);
+            assert_eq!(reply.profiles.len(), 2);
+            for (profile, depth) in reply.profiles.iter().zip(&[1, 4]) {
+                assert_eq!(profile.queue_depth, *depth);
+                assert!(profile.reads > 0);
+                assert!(profile.bytes_per_sec > 0);
+                assert!(profile.max_latency_us >= profile.avg_latency_us);
+            }
+
+            // the bdev created for the probe is gone again
+            assert!(Bdev::lookup_by_name("probe0").is_none());
+
+            // the bdev of a replica in use is left alone
+            bdev_create(MALLOC).await.unwrap();
+            probe_replica_path(request(vec![1], 4096)).await.unwrap();
+            assert!(Bdev::lookup_by_name("probe0").is_some());
+
+            assert!(probe_replica_path(request(vec![0], 0)).await.is_err());
+            assert!(probe_replica_path(request(vec![1], 1000)).await.is_err());
+
+            bdev_destroy(MALLOC).await.unwrap();
+        });
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use mayastor::{
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    lvs::Lvs,
+    nexus_uri::bdev_create,
+    pool::create_pool,
+    replica::Replica,
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/metadata.img";
+static POOL: &str = "pool_metadata";
+static UUID: &str = "5b2f4c1e-7d3a-4e6b-8c9f-0a1b2c3d4e5f";
+
+fn metadata(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn replica_metadata() {
+    common::delete_file(&[DISKNAME.into()]);
+    common::truncate_file(DISKNAME, 64 * 1024);
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![format!("aio://{}", DISKNAME)],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+            let replica = Replica::create(UUID, POOL, 8 * 1024 * 1024, true)
+                .await
+                .unwrap();
+            assert!(replica.get_metadata().unwrap().is_empty());
+
+            let set = metadata(&[
+                ("volume", "e1c2a3b4-0000-4000-8000-000000000001"),
+                ("generation", "7"),
+            ]);
+            assert_eq!(replica.set_metadata(set.clone()).await.unwrap(), set);
+
+            // an empty value removes the key, the others are kept
+            let current = replica
+                .set_metadata(metadata(&[("generation", "")]))
+                .await
+                .unwrap();
+            assert_eq!(current.len(), 1);
+            assert!(current.contains_key("volume"));
+
+            // invalid keys and too large values are refused
+            assert!(replica
+                .set_metadata(metadata(&[("no spaces", "x")]))
+                .await
+                .is_err());
+            let large = "x".repeat(1025);
+            assert!(replica
+                .set_metadata(metadata(&[("large", &large)]))
+                .await
+                .is_err());
+        });
+
+        // the metadata is persisted in the pool
+        Reactor::block_on(async {
+            Lvs::lookup(POOL).unwrap().export().await.unwrap();
+            bdev_create(&format!("aio://{}", DISKNAME)).await.unwrap();
+            Lvs::import(POOL, &format!("aio://{}", DISKNAME))
+                .await
+                .unwrap();
+
+            let replica = Replica::lookup(UUID).unwrap();
+            assert_eq!(
+                replica.get_metadata().unwrap(),
+                metadata(&[("volume", "e1c2a3b4-0000-4000-8000-000000000001")])
+            );
+            Lvs::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+
+    common::delete_file(&[DISKNAME.into()]);
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use crossbeam::channel::unbounded;
+
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    pool::{create_pool, Pool},
+    replica::{Replica, ShareType},
+    subsys::Config,
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static POOL: &str = "pool_replica_reshare";
+static DISK: &str = "malloc:///malloc0?size_mb=64";
+static UUID: &str = "3c9d2e1f-5a4b-4c6d-8e7f-1a2b3c4d5e6f";
+
+/// the old share of the replica as recorded in the config
+fn retiring() -> Option<ShareType> {
+    let config = Config::get().refresh().unwrap();
+    config.pools.unwrap()[0]
+        .replicas
+        .iter()
+        .find(|r| r.name == UUID)
+        .unwrap()
+        .retiring
+        .as_ref()
+        .map(|r| r.share)
+}
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
+
+#[test]
+fn replica_reshare() {
+    common::mayastor_test_init();
+    let ms = MayastorEnvironment::new(MayastorCliArgs::default());
+    ms.start(|| {
+        Reactor::block_on(async {
+            create_pool(CreatePoolRequest {
+                name: POOL.into(),
+                disks: vec![DISK.into()],
+                block_size: 0,
+                io_if: 0,
+                adopt: false,
+                idempotency_key: String::new(),
+            })
+            .await
+            .unwrap();
+
+            let replica = Replica::create(UUID, POOL, 16 * 1024 * 1024, false)
+                .await
+                .unwrap();
+            replica.share(ShareType::Nvmf).await.unwrap();
+            let nvmf_uri = replica.get_share_uri();
+
+            // the grace timer is armed from within the reactor
+            let previous = replica
+                .reshare(ShareType::Iscsi, Duration::from_millis(500))
+                .await
+                .unwrap();
+            assert_eq!(previous, Some(nvmf_uri));
+            assert_eq!(replica.get_share_type(), Some(ShareType::Iscsi));
+            assert_eq!(retiring(), Some(ShareType::Nvmf));
+
+            // one reshare at a time
+            assert!(replica
+                .reshare(ShareType::Nvmf, Duration::from_secs(1))
+                .await
+                .is_err());
+        });
+
+        reactor_run_millis(1000);
+
+        Reactor::block_on(async {
+            let replica = Replica::lookup(UUID).unwrap();
+            assert_eq!(retiring(), None);
+            assert_eq!(replica.get_share_type(), Some(ShareType::Iscsi));
+
+            // the old share is gone, so it can be reshared again
+            replica
+                .reshare(ShareType::Nvmf, Duration::from_millis(100))
+                .await
+                .unwrap();
+            replica.unshare().await.unwrap();
+            assert_eq!(retiring(), None);
+            assert_eq!(replica.get_share_type(), None);
+
+            Pool::lookup(POOL).unwrap().destroy().await.unwrap();
+        });
+
+        mayastor_env_stop(0);
+    })
+    .unwrap();
+}
@@ -2,7 +2,7 @@ use std::{io, io::Write, process::Command, thread, time};
 
 use common::{bdev_io, ms_exec::MayastorProcess};
 use mayastor::{
-    bdev::nexus_create,
+    bdev::{nexus_create, nexus_lookup},
     core::{
         mayastor_env_stop,
         BdevHandle,
@@ -35,6 +35,7 @@ fn generate_config() {
     config.nexus_opts.nvmf_nexus_port = 8440;
     let pool = subsys::Pool {
         name: "pool0".to_string(),
+        uuid: Default::default(),
         disks: vec![DISKNAME1.to_string()],
         blk_size: 512,
         io_if: 1, // AIO
@@ -98,25 +99,62 @@ fn replica_snapshot() {
 
     test_init!();
 
-    Reactor::block_on(async {
+    let snapshot = Reactor::block_on(async {
         create_nexus().await;
         bdev_io::write_some(NXNAME).await.unwrap();
-        custom_nvme_admin(0xc1)
+        custom_nvme_admin(0xc2)
             .await
             .expect_err("unexpectedly succeeded invalid nvme admin command");
         bdev_io::read_some(NXNAME).await.unwrap();
-        create_snapshot().await.unwrap();
+        let snapshot = create_snapshot().await.unwrap();
         // Check that IO to the replica still works after creating a snapshot
-        // Checking the snapshot itself is tbd
         bdev_io::read_some(NXNAME).await.unwrap();
         bdev_io::write_some(NXNAME).await.unwrap();
         bdev_io::read_some(NXNAME).await.unwrap();
-    });
+        // Overwrite the data and roll the nexus back to the snapshot
+        overwrite().await;
+        nexus_lookup(NXNAME)
+            .unwrap()
+            .restore_snapshot(snapshot)
+            .await
+            .unwrap();
+        bdev_io::read_some(NXNAME).await.unwrap();
+        nexus_lookup(NXNAME)
+            .unwrap()
+            .restore_snapshot(snapshot + 1)
+            .await
+            .expect_err("unexpectedly restored a missing snapshot");
+        snapshot
+    })
+    .unwrap();
+    list_snapshots(snapshot);
     mayastor_env_stop(0);
 
     common::delete_file(&[DISKNAME1.to_string()]);
 }
 
+fn list_snapshots(snapshot: u64) {
+    let msc = "../target/debug/mayastor-client";
+    let output = Command::new(msc)
+        .args(&["-p", "10125", "-o", "json", "snapshot", "list"])
+        .output()
+        .expect("could not exec mayastor-client");
+    assert!(output.status.success());
+
+    // the snapshot of the nexus is named after the replica and its time
+    let reply: serde_json::Value =
+        serde_json::from_slice(&output.stdout).unwrap();
+    let snapshots = reply["snapshots"].as_array().unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0]["pool"], "pool0");
+    assert_eq!(snapshots[0]["replica"], UUID1);
+    assert_eq!(snapshots[0]["snapshot"], snapshot);
+    assert_eq!(
+        snapshots[0]["name"],
+        format!("{}-snap-{}", UUID1, snapshot)
+    );
+}
+
 async fn create_nexus() {
     let ch = vec![
         "nvmf://127.0.0.1:8430/nqn.2019-05.io.openebs:".to_string()
@@ -128,12 +166,23 @@ async fn create_nexus() {
         .unwrap();
 }
 
-async fn create_snapshot() -> Result<(), CoreError> {
+async fn create_snapshot() -> Result<u64, CoreError> {
     let h = BdevHandle::open(NXNAME, true, false).unwrap();
-    h.create_snapshot()
+    let snapshot = h
+        .create_snapshot()
         .await
         .expect("failed to create snapshot");
-    Ok(())
+    Ok(snapshot)
+}
+
+async fn overwrite() {
+    let h = BdevHandle::open(NXNAME, true, false).unwrap();
+    let mut buf = h.dma_malloc(1024).unwrap();
+    buf.fill(0x55);
+    h.write_at(0, &buf).await.unwrap();
+    buf.fill(0);
+    h.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|&b| b == 0x55));
 }
 
 async fn custom_nvme_admin(opc: u8) -> Result<(), CoreError> {
@@ -0,0 +1,87 @@
+use std::{
+    fs::OpenOptions,
+    io::{Seek, SeekFrom, Write},
+};
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    verify::verify_metadata,
+};
+use rpc::mayastor::{FindingSeverity, VerifyMetadataRequest};
+
+pub mod common;
+
+static NEXUS: &str = "nexus-3c6e2a1f-8d4b-4f7e-9a2c-5b1d0e8f7a6c";
+static UUID: &str = "3c6e2a1f-8d4b-4f7e-9a2c-5b1d0e8f7a6c";
+
+static DISK1: &str = "/tmp/verify_1.img";
+static DISK2: &str = "/tmp/verify_2.img";
+static CHILD1: &str = "aio:///tmp/verify_1.img?blk_size=512";
+static CHILD2: &str = "aio:///tmp/verify_2.img?blk_size=512";
+
+/// zero the block of the disk
+fn zero_block(path: &str, lba: u64) {
+    let mut file = OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(lba * 512)).unwrap();
+    file.write_all(&[0; 512]).unwrap();
+    file.sync_all().unwrap();
+}
+
+fn request(nexus: &str) -> VerifyMetadataRequest {
+    VerifyMetadataRequest {
+        nexus: nexus.into(),
+        pool: String::new(),
+    }
+}
+
+#[test]
+fn verify_nexus_metadata() {
+    for disk in &[DISK1, DISK2] {
+        common::delete_file(&[disk.to_string()]);
+        common::truncate_file(disk, 64 * 1024);
+    }
+
+    test_init!();
+
+    Reactor::block_on(async {
+        nexus_create(
+            NEXUS,
+            32 * 1024 * 1024,
+            Some(UUID),
+            &[CHILD1.to_string(), CHILD2.to_string()],
+        )
+        .await
+        .unwrap();
+
+        // a freshly labelled nexus is fine
+        let reply = verify_metadata(request(UUID)).await.unwrap();
+        assert_eq!(reply.checked, 3);
+        assert!(reply.findings.is_empty());
+    });
+
+    // a broken primary GPT header is reported, and left as it is
+    zero_block(DISK2, 1);
+    Reactor::block_on(async {
+        let reply = verify_metadata(request(UUID)).await.unwrap();
+        assert_eq!(reply.findings.len(), 1);
+        let finding = &reply.findings[0];
+        assert_eq!(finding.severity, FindingSeverity::FindingWarning as i32);
+        assert_eq!(finding.object, format!("nexus/{}/child/{}", UUID, CHILD2));
+        assert!(finding.message.contains("backup"));
+
+        let reply = verify_metadata(request(UUID)).await.unwrap();
+        assert_eq!(reply.findings.len(), 1);
+
+        // a nexus which does not exist is an error
+        let uuid = "3c6e2a1f-0000-0000-0000-000000000000";
+        assert!(verify_metadata(request(uuid)).await.is_err());
+
+        nexus_lookup(NEXUS).unwrap().destroy().await.unwrap();
+    });
+
+    for disk in &[DISK1, DISK2] {
+        common::delete_file(&[disk.to_string()]);
+    }
+    mayastor_env_stop(0);
+}
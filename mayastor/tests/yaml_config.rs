@@ -121,6 +121,7 @@ fn yaml_pool_tests() {
     // does not exist, so we expect that it gets created -- and not imported.
     let pool = subsys::Pool {
         name: "tpool".to_string(),
+        uuid: Default::default(),
         disks: vec!["/tmp/disk1.img".into()],
         blk_size: 512,
         io_if: 1,
@@ -264,3 +265,134 @@ fn yaml_multi_maya() {
         "/tmp/second.yaml".into(),
     ])
 }
+
+#[test]
+// Change the options which can be changed while running in the config file
+// and reload it with SIGHUP. The exported config contains the new values,
+// which it only can if they have been applied.
+fn yaml_reload() {
+    let mut cfg = Config::default();
+    cfg.source = Some("/tmp/reload.yaml".into());
+    cfg.nexus_opts.nvmf_enable = false;
+    cfg.write("/tmp/reload.yaml").unwrap();
+
+    let args = vec![
+        "-s".to_string(),
+        "128".into(),
+        "-y".into(),
+        "/tmp/reload.yaml".to_string(),
+    ];
+
+    run_test(Box::from(args), |ms| {
+        let mut cfg = Config::read("/tmp/reload.yaml").unwrap();
+        cfg.log_level = Some("debug".into());
+        cfg.nexus_opts.qos_defaults.rw_ios_per_sec = 10_000;
+        cfg.nvmf_tcp_tgt_conf.max_connects_per_sec = 5;
+        cfg.nvmf_tcp_tgt_conf.keep_alive_timeout_ms = 15_000;
+        // requires a restart, and is not applied
+        cfg.nexus_opts.iscsi_nexus_port = 3263;
+
+        // the export overwrites the file, if it ran before the reload the
+        // file is written again
+        let reloaded = common::retry(10, Duration::from_millis(500), || {
+            cfg.write("/tmp/reload.yaml").unwrap();
+            ms.sig_hup();
+            std::thread::sleep(Duration::from_millis(100));
+
+            ms.rpc_call("mayastor_config_export", serde_json::json!(null))
+                .unwrap();
+            let exported = Config::read("/tmp/reload.yaml").unwrap();
+            if exported.nvmf_tcp_tgt_conf.max_connects_per_sec == 5 {
+                Ok(exported)
+            } else {
+                Err(())
+            }
+        });
+
+        assert_eq!(reloaded.log_level, Some("debug".into()));
+        assert_eq!(reloaded.nexus_opts.qos_defaults.rw_ios_per_sec, 10_000);
+        assert_eq!(reloaded.nvmf_tcp_tgt_conf.keep_alive_timeout_ms, 15_000);
+        assert_eq!(
+            reloaded.nexus_opts.iscsi_nexus_port,
+            subsys::NexusOpts::default().iscsi_nexus_port
+        );
+    });
+
+    common::delete_file(&["/tmp/reload.yaml".into()]);
+}
+
+#[test]
+// The replies of the calls made with an idempotency key are kept across a
+// restart, so they are exported again along with those of the new calls.
+fn yaml_operations() {
+    let mut cfg = Config::default();
+    cfg.source = Some("/tmp/operations.yaml".into());
+    cfg.operations = vec![subsys::Operation {
+        method: "CreateReplica".into(),
+        key: "6c1a3f5e-create-replica".into(),
+        reply: serde_json::json!({"uuid": "6c1a3f5e", "pool": "pool0"}),
+    }];
+    cfg.write("/tmp/operations.yaml").unwrap();
+
+    let args = vec![
+        "-s".to_string(),
+        "128".into(),
+        "-y".into(),
+        "/tmp/operations.yaml".to_string(),
+    ];
+
+    run_test(Box::from(args), |ms| {
+        ms.rpc_call("mayastor_config_export", serde_json::json!(null))
+            .unwrap();
+        let exported = Config::read("/tmp/operations.yaml").unwrap();
+        assert_eq!(exported.operations.len(), 1);
+        assert_eq!(exported.operations[0].method, "CreateReplica");
+        assert_eq!(exported.operations[0].key, "6c1a3f5e-create-replica");
+        assert_eq!(exported.operations[0].reply["pool"], "pool0");
+    });
+
+    common::delete_file(&["/tmp/operations.yaml".into()]);
+}
+
+#[test]
+// A config file written before the layout was versioned is migrated when it
+// is read, and written back with the current version. A config file of a
+// newer version is refused.
+fn yaml_versions() {
+    std::fs::write("/tmp/versions.yaml", "nexus_opts:\n  nvmf_enable: false\n")
+        .unwrap();
+    let cfg = Config::read("/tmp/versions.yaml").unwrap();
+    assert_eq!(cfg.version, subsys::CONFIG_VERSION);
+    assert_eq!(cfg.nexus_opts.nvmf_enable, false);
+
+    // the file is replaced as a whole, no temporary file is left behind
+    cfg.write("/tmp/versions.yaml").unwrap();
+    assert!(metadata("/tmp/versions.yaml.tmp").is_err());
+    let written = Config::read("/tmp/versions.yaml").unwrap();
+    assert_eq!(written, cfg);
+
+    let newer = format!("version: {}\n", subsys::CONFIG_VERSION + 1);
+    std::fs::write("/tmp/versions.yaml", newer).unwrap();
+    assert!(Config::read("/tmp/versions.yaml").is_err());
+
+    common::delete_file(&["/tmp/versions.yaml".into()]);
+}
+
+#[test]
+// The config file is one of the stores of the config, nothing is stored in
+// it until it is saved.
+fn yaml_file_store() {
+    use subsys::ConfigStore;
+
+    common::delete_file(&["/tmp/store.yaml".into()]);
+    let store = subsys::FileStore::new("/tmp/store.yaml");
+    assert!(store.load().unwrap().is_none());
+
+    let yaml = serde_yaml::to_string(&Config::default()).unwrap();
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(store.save(yaml.clone().into_bytes())).unwrap();
+    assert_eq!(store.load().unwrap(), Some(yaml.into_bytes()));
+    assert!(metadata("/tmp/store.yaml.tmp").is_err());
+
+    common::delete_file(&["/tmp/store.yaml".into()]);
+}
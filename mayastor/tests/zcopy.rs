@@ -0,0 +1,56 @@
+use mayastor::{
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    nexus_uri::bdev_create,
+};
+
+pub mod common;
+
+static BDEV1: &str = "malloc:///malloc0?size_mb=16";
+static BDEV2: &str = "malloc:///malloc1?size_mb=16";
+
+#[test]
+fn zcopy_read() {
+    common::mayastor_test_init();
+    MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                read().await;
+            });
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+}
+
+async fn read() {
+    let src = bdev_create(BDEV1).await.unwrap();
+    let dst = bdev_create(BDEV2).await.unwrap();
+    let src = BdevHandle::open(&src, true, false).unwrap();
+    let dst = BdevHandle::open(&dst, true, false).unwrap();
+
+    let mut buf = src.dma_malloc(8192).unwrap();
+    buf.fill(0xa5);
+    src.write_at(4096, &buf).await.unwrap();
+
+    // the data is the same whether it is lent by the bdev or copied
+    let zbuf = src.read_zcopy_at(4096, 8192).await.unwrap();
+    assert_eq!(zbuf.is_zero_copy(), src.supports_zcopy());
+    assert_eq!(zbuf.len(), 8192);
+    assert!(zbuf.slices().flatten().all(|&b| b == 0xa5));
+
+    // and can be written out as it is
+    assert_eq!(dst.write_zcopy_at(0, &zbuf).await.unwrap(), 8192);
+    drop(zbuf);
+    let mut buf = dst.dma_malloc(8192).unwrap();
+    dst.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|&b| b == 0xa5));
+
+    // the offset and length must be whole blocks
+    assert!(src.read_zcopy_at(100, 512).await.is_err());
+    assert!(src.read_zcopy_at(0, 100).await.is_err());
+}
@@ -3,12 +3,14 @@
 # builder in nixpkgs is questionable which is why we postpone this step.
 
 { stdenv
+, btrfs-progs
 , busybox
 , dockerTools
 , e2fsprogs
 , git
 , lib
 , moac
+, quota
 , writeScriptBin
 , xfsprogs
 , mayastor
@@ -18,7 +20,7 @@
 let
   versionDrv = import ../../lib/version.nix { inherit lib stdenv git; };
   version = builtins.readFile "${versionDrv}";
-  env = stdenv.lib.makeBinPath [ busybox xfsprogs e2fsprogs ];
+  env = stdenv.lib.makeBinPath [ busybox xfsprogs e2fsprogs btrfs-progs quota ];
 
   # common props for all mayastor images
   mayastorImageProps = {
@@ -52,6 +52,7 @@ stdenv.mkDerivation rec {
     ncurses
     numactl
     openssl
+    rdma-core
   ] ++ stdenv.lib.optionals enableDebug [ cunit lcov ];
 
   # add this once we merged this new option from upstream. The tests are going
@@ -68,6 +69,7 @@ stdenv.mkDerivation rec {
     "--without-vhost"
     "--with-iscsi-initiator"
     "--with-crypto"
+    "--with-rdma"
   ] ++ stdenv.lib.optionals (enableDebug) [ "--enable-debug" ];
 
 
@@ -90,7 +92,7 @@ stdenv.mkDerivation rec {
     #find . -type f -name 'librte_vhost.a' -delete
 
     $CC -shared -o libspdk.so \
-    -lc  -laio -liscsi -lnuma -ldl -lrt -luuid -lpthread -lcrypto \
+    -lc  -laio -liscsi -lnuma -ldl -lrt -luuid -lpthread -lcrypto -libverbs -lrdmacm \
     -Wl,--whole-archive \
     $(find build/lib -type f -name 'libspdk_*.a*' -o -name 'librte_*.a*') \
     $(find dpdk/build/lib -type f -name 'librte_*.a*') \
@@ -103,7 +105,7 @@ stdenv.mkDerivation rec {
  #   #find . -type f -name 'librte_vhost.a' -delete
 
  #   $CC -shared -o libspdk.so \
- #   -lc  -laio -liscsi -lnuma -ldl -lrt -luuid -lpthread -lcrypto \
+ #   -lc  -laio -liscsi -lnuma -ldl -lrt -luuid -lpthread -lcrypto -libverbs -lrdmacm \
  #   -Wl,--whole-archive \
  #   $(find build/lib -type f -name 'libspdk_*.a*' -o -name 'librte_*.a*') \
  #   $(find dpdk/build/lib -type f -name 'librte_*.a*') \
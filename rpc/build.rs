@@ -4,7 +4,7 @@ fn main() {
     tonic_build::configure()
         .build_server(true)
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
-        .compile(&["proto/mayastor.proto"], &["proto"])
+        .compile(&["proto/mayastor.proto", "proto/health.proto"], &["proto"])
         .unwrap_or_else(|e| {
             panic!("mayastor protobuf compilation failed: {}", e)
         });
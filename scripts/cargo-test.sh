@@ -3,4 +3,5 @@ set -euxo pipefail
 export PATH=$PATH:${HOME}/.cargo/bin
 ( cd jsonrpc && cargo test )
 ( cd mayastor && cargo test -- --test-threads=1 )
+( cd mayastor && cargo test --features mock-children --test nexus_mock )
 ( cd nvmeadm && cargo test )
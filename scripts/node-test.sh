@@ -8,3 +8,4 @@ cargo build --all
 ( cd mayastor-test && ./node_modules/mocha/bin/mocha test_csi.js )
 ( cd mayastor-test && ./node_modules/mocha/bin/mocha test_rebuild.js )
 ( cd mayastor-test && ./node_modules/mocha/bin/mocha test_nats.js )
+( cd mayastor-test && ./node_modules/mocha/bin/mocha test_grpc_endpoints.js )
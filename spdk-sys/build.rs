@@ -74,6 +74,8 @@ fn main() {
         .whitelist_function("^spdk.*")
         .whitelist_function("create_malloc_disk")
         .whitelist_function("delete_malloc_disk")
+        .whitelist_function("create_delay_disk")
+        .whitelist_function("delete_delay_disk")
         .whitelist_function("^bdev.*")
         .whitelist_function("^nbd_.*")
         .whitelist_function("^vbdev_.*")
@@ -112,6 +114,8 @@ fn main() {
     println!("cargo:rustc-link-lib=uuid");
     println!("cargo:rustc-link-lib=numa");
     println!("cargo:rustc-link-lib=crypto");
+    println!("cargo:rustc-link-lib=ibverbs");
+    println!("cargo:rustc-link-lib=rdmacm");
 
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=wrapper.h");
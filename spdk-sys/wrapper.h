@@ -1,11 +1,14 @@
 #include <bdev/aio/bdev_aio.h>
 #include <bdev/crypto/vbdev_crypto.h>
+#include <bdev/delay/vbdev_delay.h>
 #include <bdev/error/vbdev_error.h>
 #include <bdev/iscsi/bdev_iscsi.h>
 #include <bdev/lvol/vbdev_lvol.h>
 #include <bdev/nvme/bdev_nvme.h>
 #include <bdev/malloc/bdev_malloc.h>
+#include <bdev/null/bdev_null.h>
 #include <bdev/uring/bdev_uring.h>
+#include <blob/blobstore.h>
 #include <iscsi/init_grp.h>
 #include <iscsi/iscsi.h>
 #include <iscsi/portal_grp.h>